@@ -7,6 +7,7 @@ use criterion::{black_box, Criterion, BenchmarkId, Throughput};
 use crdt_testdata::{load_testing_data, TestData};
 use diamond_types::list::{ListCRDT, ListOpLog};
 use diamond_types::list::encoding::*;
+use diamond_types::simulator::{NetworkModel, SimConfig, Simulator};
 use crate::utils::*;
 
 fn testing_data(name: &str) -> TestData {
@@ -67,6 +68,18 @@ fn local_benchmarks(c: &mut Criterion) {
             })
         });
 
+        group.bench_function(BenchmarkId::new("import_linear_trace", name), |b| {
+            b.iter(|| {
+                let mut oplog = ListOpLog::new();
+                let agent = oplog.get_or_create_agent_id("seph");
+                let patches = test_data.txns.iter()
+                    .flat_map(|txn| txn.patches.iter())
+                    .map(|p| (p.0, p.1, p.2.as_str()));
+                oplog.import_linear_trace(agent, patches);
+                black_box(oplog.len());
+            })
+        });
+
         group.finish();
     }
 }
@@ -143,6 +156,25 @@ fn encoding_nodecc_benchmarks(c: &mut Criterion) {
     }
 }
 
+fn simulator_benchmarks(c: &mut Criterion) {
+    let mut group = c.benchmark_group("simulator");
+    for (name, network) in [
+        ("reliable", NetworkModel::default()),
+        ("lossy", NetworkModel { loss: 0.02, min_latency: 1, max_latency: 20, reconnect_delay: 10 }),
+    ] {
+        let config = SimConfig { num_peers: 5, network, ..Default::default() };
+        group.bench_function(BenchmarkId::new("sync", name), |b| {
+            b.iter(|| {
+                let mut sim = Simulator::new(123, config);
+                sim.run(500, 0.2);
+                sim.settle();
+                black_box(sim.stats().bytes_sent);
+            })
+        });
+    }
+    group.finish();
+}
+
 // criterion_group!(benches,
 //     local_benchmarks,
 //     encoding_nodecc_benchmarks,
@@ -158,5 +190,6 @@ fn main() {
 
     local_benchmarks(&mut c);
     encoding_nodecc_benchmarks(&mut c);
+    simulator_benchmarks(&mut c);
     c.final_summary();
 }
\ No newline at end of file
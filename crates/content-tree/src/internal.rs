@@ -1,5 +1,5 @@
 use super::*;
-use std::mem::{self, MaybeUninit};
+use core::mem::{self, MaybeUninit};
 
 impl<E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> NodeInternal<E, I, IE, LE> {
     pub fn new_with_parent(parent: ParentPtr<E, I, IE, LE>) -> Pin<Box<Self>> {
@@ -72,7 +72,7 @@ impl<E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> Node
         // Doing this with a memcpy seems better but this is buggy, and I'm not sure why.
         // let old_len = self.count_children();
         // unsafe {
-        //     std::ptr::copy(&self.data[idx], &mut self.data[idx + 1], old_len - idx);
+        //     core::ptr::copy(&self.data[idx], &mut self.data[idx + 1], old_len - idx);
         // }
         // self.data[idx] = buffer;
         for i in idx..IE {
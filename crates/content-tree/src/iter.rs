@@ -1,5 +1,6 @@
 
-use crate::{NodeLeaf, ContentTraits, TreeMetrics, Cursor, ContentTreeRaw};
+use core::ops::Range;
+use crate::{NodeLeaf, ContentTraits, TreeMetrics, Cursor, ContentTreeRaw, FindOffset};
 use rle::{Searchable, MergeIter, merge_items};
 
 /// Iterator for all the items inside the entries. Unlike entry iteration we use the offset here.
@@ -32,6 +33,36 @@ impl<'a, E: ContentTraits + Searchable, I: TreeMetrics<E>, const IE: usize, cons
     }
 }
 
+/// Iterator for the entries in a range of the tree. Entries at the edges of the range are trimmed
+/// to fit. Made with [`ContentTreeRaw::iter_range_at_offset`].
+#[derive(Debug)]
+pub struct RangeIter<'a, E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> {
+    cursor: Cursor<'a, E, I, IE, LE>,
+    remaining: usize,
+}
+
+impl<'a, E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> Iterator for RangeIter<'a, E, I, IE, LE> {
+    type Item = E;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 { return None; }
+
+        let mut entry = self.cursor.inner.try_get_raw_entry()?;
+        if self.cursor.inner.offset > 0 {
+            entry.truncate_keeping_right(self.cursor.inner.offset);
+        }
+        if entry.len() > self.remaining {
+            entry.truncate(self.remaining);
+        }
+        self.remaining -= entry.len();
+
+        if !self.cursor.inner.next_entry() {
+            self.remaining = 0;
+        }
+        Some(entry)
+    }
+}
+
 /// Iterator for whole nodes in the tree. This lets you iterate through chunks of items efficiently.
 #[derive(Debug)]
 pub struct NodeIter<'a, E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize>(Option<&'a NodeLeaf<E, I, IE, LE>>);
@@ -74,9 +105,24 @@ impl<E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> Cont
     }
 }
 
+impl<E: ContentTraits, I: FindOffset<E>, const IE: usize, const LE: usize> ContentTreeRaw<E, I, IE, LE> {
+    /// Iterate through the entries in the named range of offset positions. Entries which straddle
+    /// the edges of the range are trimmed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range extends past the end of the tree.
+    pub fn iter_range_at_offset(&self, range: Range<usize>) -> RangeIter<'_, E, I, IE, LE> {
+        assert!(range.start <= range.end && range.end <= self.offset_len(), "Range is out of bounds");
+        let mut cursor = self.cursor_at_offset_pos(range.start, false);
+        if !range.is_empty() { cursor.inner.roll_to_next_entry(); }
+        RangeIter { cursor, remaining: range.len() }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::ContentTree;
+    use crate::{ContentTree, ContentTreeRaw, DEFAULT_IE, DEFAULT_LE, RawPositionMetricsUsize};
     use crate::testrange::TestRange;
 
     #[test]
@@ -93,4 +139,31 @@ mod test {
         assert_eq!(first.num_entries, 1);
         assert!(iter.next().is_none());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn mutate_and_iter_range() {
+        let mut tree = ContentTreeRaw::<TestRange, RawPositionMetricsUsize, DEFAULT_IE, DEFAULT_LE>::new();
+        tree.push(TestRange { id: 0, len: 10, is_activated: true });
+        tree.push(TestRange { id: 100, len: 10, is_activated: true });
+
+        tree.mutate_range(5..15, |e| e.is_activated = false);
+        tree.check();
+        assert_eq!(tree.raw_iter().collect::<Vec<_>>(), vec![
+            TestRange { id: 0, len: 5, is_activated: true },
+            TestRange { id: 5, len: 5, is_activated: false },
+            TestRange { id: 100, len: 5, is_activated: false },
+            TestRange { id: 105, len: 5, is_activated: true },
+        ]);
+
+        assert_eq!(tree.iter_range_at_offset(3..12).collect::<Vec<_>>(), vec![
+            TestRange { id: 3, len: 2, is_activated: true },
+            TestRange { id: 5, len: 5, is_activated: false },
+            TestRange { id: 100, len: 2, is_activated: false },
+        ]);
+        // Ranges can start and end on entry boundaries.
+        assert_eq!(tree.iter_range_at_offset(10..15).collect::<Vec<_>>(), vec![
+            TestRange { id: 100, len: 5, is_activated: false },
+        ]);
+        assert_eq!(tree.iter_range_at_offset(20..20).count(), 0);
+    }
+}
@@ -1,5 +1,5 @@
-use std::mem::take;
-use std::ptr::NonNull;
+use core::mem::take;
+use core::ptr::NonNull;
 
 use rle::Searchable;
 
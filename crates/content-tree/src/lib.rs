@@ -2,16 +2,20 @@
 // be extended to inline a rope, but I haven't done that here.
 
 #![allow(clippy::missing_safety_doc)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 
-// use std::cell::Cell;
-use std::fmt::Debug;
-use std::marker;
-use std::marker::PhantomPinned;
-use std::pin::Pin;
-use std::ptr::NonNull;
+extern crate alloc;
+
+// use core::cell::Cell;
+use core::fmt::Debug;
+use core::marker;
+use core::marker::PhantomPinned;
+use core::pin::Pin;
+use core::ptr::NonNull;
+use alloc::boxed::Box;
 
 pub use metrics::*;
-pub use root::DeleteResult;
+pub use root::{DeleteResult, TreeInvariantError, TreeStats};
 
 // Types re-exported from rle for convenience
 pub use rle::{SplitAndJoinSpan, RleRun, ReverseSpan, Single};
@@ -285,10 +289,10 @@ impl<E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> Node
     fn ptr_eq(&self, ptr: NodePtr<E, I, IE, LE>) -> bool {
         match (self, ptr) {
             (Node::Internal(n), NodePtr::Internal(ptr)) => {
-                std::ptr::eq(n.as_ref().get_ref(), ptr.as_ptr())
+                core::ptr::eq(n.as_ref().get_ref(), ptr.as_ptr())
             },
             (Node::Leaf(n), NodePtr::Leaf(ptr)) => {
-                std::ptr::eq(n.as_ref().get_ref(), ptr.as_ptr())
+                core::ptr::eq(n.as_ref().get_ref(), ptr.as_ptr())
             },
             _ => panic!("Pointer type does not match")
         }
@@ -346,4 +350,24 @@ mod test {
         // let item_size = size_of::<Pin<Box<NodeInternal<OrderSpan, RawPositionIndex>>>>();
         // assert_eq!(node_size, item_size);
     }
+
+    #[test]
+    fn stats_and_validate() {
+        let mut tree = ContentTreeRaw::<TestRange, RawPositionMetricsU32, DEFAULT_IE, DEFAULT_LE>::new();
+        assert_eq!(tree.stats().num_entries, 0);
+        for i in 0..100 {
+            tree.push(TestRange { id: i * 10, len: 5, is_activated: true });
+        }
+
+        let stats = tree.stats();
+        assert_eq!(stats.num_entries, 100);
+        assert!(stats.depth >= 1);
+        assert!(stats.num_leaf_nodes >= 100 / DEFAULT_LE);
+        assert!(stats.leaf_fill_factor() > 0.0 && stats.leaf_fill_factor() <= 1.0);
+        assert!(stats.memory_usage > 100 * size_of::<TestRange>());
+        assert_eq!(tree.validate(), Ok(()));
+
+        unsafe { tree.as_mut().get_unchecked_mut().count += 1; }
+        assert_eq!(tree.validate(), Err(TreeInvariantError::WrongCount));
+    }
 }
@@ -1,5 +1,5 @@
-use std::fmt::Debug;
-use std::ops::{AddAssign, SubAssign};
+use core::fmt::Debug;
+use core::ops::{AddAssign, SubAssign};
 
 use crate::ContentTraits;
 
@@ -1,7 +1,7 @@
-use std::{mem, ptr};
-use std::hint::unreachable_unchecked;
-use std::pin::Pin;
-use std::ptr::NonNull;
+use core::{mem, ptr};
+use core::hint::unreachable_unchecked;
+use core::pin::Pin;
+use core::ptr::NonNull;
 
 use smallvec::SmallVec;
 
@@ -1038,7 +1038,7 @@ impl<E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> Node
             }
 
             // This pointer has been moved. We need to set its entry to None without dropping it.
-            std::mem::forget(self.children[num_children - 1].take());
+            core::mem::forget(self.children[num_children - 1].take());
 
             removed
         }
@@ -1255,6 +1255,46 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn bulk_load_and_clear() {
+        // Alternating entries can't be merged.
+        let entries: Vec<TestRange> = (0..500).map(|i| TestRange {
+            id: i * 10,
+            len: 10,
+            is_activated: i % 2 == 0,
+        }).collect();
+
+        let mut notified = 0;
+        let mut tree = ContentTreeRaw::<TestRange, ContentMetrics, DEFAULT_IE, DEFAULT_LE>::from_sorted_iter_notify(entries.iter().copied(), |e, leaf| {
+            notified += 1;
+            assert!(unsafe { leaf.as_ref() }.as_slice().contains(&e));
+        });
+        tree.check();
+        assert_eq!(notified, 500);
+        assert_eq!(tree.raw_iter().collect::<Vec<TestRange>>(), entries);
+        assert_eq!(tree.count_nodes().1, 500usize.div_ceil(DEFAULT_LE));
+
+        // The tree can be edited normally after being built.
+        tree.insert_at_content(5, TestRange { id: 10000, len: 3, is_activated: true });
+        tree.check();
+
+        tree.clear();
+        tree.check();
+        assert_eq!(tree.count_entries(), 0);
+        assert_eq!(tree.count_nodes(), (0, 1));
+        tree.push(TestRange { id: 0, len: 5, is_activated: true });
+        tree.check();
+        assert_eq!(tree.content_len(), 5);
+
+        // Adjacent entries are merged.
+        let tree = ContentTreeRaw::<TestRange, ContentMetrics, DEFAULT_IE, DEFAULT_LE>::from_sorted_iter([
+            TestRange { id: 0, len: 5, is_activated: true },
+            TestRange { id: 5, len: 5, is_activated: true },
+        ]);
+        tree.check();
+        assert_eq!(tree.count_entries(), 1);
+    }
+
     #[test]
     fn delete_collapses() {
         let mut tree = ContentTreeRaw::<TestRange, ContentMetrics, DEFAULT_IE, DEFAULT_LE>::new();
@@ -1,14 +1,87 @@
 #![allow(clippy::needless_lifetimes)] // Clippy doesn't understand the need for some lifetimes below
 
-use std::mem::size_of;
+use core::fmt::{Display, Formatter};
+use core::mem::size_of;
 
+#[cfg(feature = "std")]
 use humansize::{file_size_opts, FileSize};
+use alloc::vec::Vec;
 use smallvec::SmallVec;
-use rle::{Searchable, merge_items};
+use rle::Searchable;
+#[cfg(feature = "std")]
+use rle::merge_items;
 use super::*;
 
 pub type DeleteResult<E> = SmallVec<[E; 8]>;
 
+/// A problem found by [`ContentTreeRaw::validate`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TreeInvariantError {
+    /// A node's parent pointer doesn't point to its parent.
+    WrongParent,
+    /// A leaf contains an entry with a length of 0.
+    EmptyEntry,
+    /// A leaf other than the root has no entries.
+    EmptyLeaf,
+    /// An internal node has no children.
+    EmptyInternalNode,
+    /// A leaf's next pointer doesn't point to the following leaf.
+    WrongNextPointer,
+    /// An internal node has a gap in its list of children.
+    ChildrenNotPacked,
+    /// An internal node has a mix of leaf and internal children.
+    MixedChildTypes,
+    /// The metrics stored in an internal node don't match the size of the child.
+    WrongChildMetrics,
+    /// The tree's count doesn't match the size of its contents.
+    WrongCount,
+}
+
+impl Display for TreeInvariantError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TreeInvariantError {}
+
+/// Statistics about the shape of a content tree, from [`ContentTreeRaw::stats`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TreeStats {
+    pub num_entries: usize,
+    pub num_internal_nodes: usize,
+    pub num_leaf_nodes: usize,
+    /// The maximum number of entries in each leaf.
+    pub leaf_capacity: usize,
+    /// The number of internal nodes between the root and each leaf.
+    pub depth: usize,
+    /// The total memory used by the tree, in bytes.
+    pub memory_usage: usize,
+}
+
+impl TreeStats {
+    /// The fraction of the space in the tree's leaves which is used, from 0 to 1.
+    pub fn leaf_fill_factor(&self) -> f32 {
+        self.num_entries as f32 / (self.num_leaf_nodes * self.leaf_capacity) as f32
+    }
+}
+
+/// Split len items into the fewest chunks of at most max_len items, with the chunk sizes as even
+/// as possible.
+fn even_chunk_lens(len: usize, max_len: usize) -> impl Iterator<Item = usize> {
+    let num_chunks = len.div_ceil(max_len);
+    (0..num_chunks).map(move |i| len / num_chunks + usize::from(i < len % num_chunks))
+}
+
+fn even_chunks<E>(items: &[E], max_len: usize) -> impl Iterator<Item = &[E]> {
+    let mut start = 0;
+    even_chunk_lens(items.len(), max_len).map(move |len| {
+        start += len;
+        &items[start - len..start]
+    })
+}
+
 impl<E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> ContentTreeRaw<E, I, IE, LE> {
     pub fn new() -> Pin<Box<Self>> {
         let mut tree = Box::pin(Self {
@@ -25,6 +98,122 @@ impl<E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> Cont
         tree
     }
 
+    /// Build a tree from a list of entries, in order. Unlike calling push() repeatedly, this
+    /// builds the tree bottom-up, so the resulting tree is balanced and its nodes are (nearly) full.
+    pub fn from_sorted_iter<Iter: IntoIterator<Item = E>>(iter: Iter) -> Pin<Box<Self>> {
+        Self::from_sorted_iter_notify(iter, null_notify)
+    }
+
+    /// Same as [`from_sorted_iter`](Self::from_sorted_iter), but calls notify with the leaf
+    /// containing each entry once the tree has been built.
+    pub fn from_sorted_iter_notify<Iter, F>(iter: Iter, mut notify: F) -> Pin<Box<Self>>
+        where Iter: IntoIterator<Item = E>, F: FnMut(E, NonNull<NodeLeaf<E, I, IE, LE>>)
+    {
+        let mut entries: Vec<E> = Vec::new();
+        for e in iter {
+            if let Some(last) = entries.last_mut() {
+                if last.can_append(&e) {
+                    last.append(e);
+                    continue;
+                }
+            }
+            entries.push(e);
+        }
+
+        let mut tree = Self::new();
+        if entries.is_empty() { return tree; }
+
+        // Leaves are filled as evenly as possible. Each leaf is boxed before its next pointer is
+        // set, so the pointers stay valid as the leaves are moved into the tree.
+        let mut leaves: Vec<Pin<Box<NodeLeaf<E, I, IE, LE>>>> = Vec::new();
+        let mut counts: Vec<I::Value> = Vec::new();
+        for chunk in even_chunks(&entries, LE) {
+            let mut leaf = NodeLeaf::new_with_parent(ParentPtr::Root(NonNull::dangling()), None);
+            leaf.data[..chunk.len()].copy_from_slice(chunk);
+            leaf.num_entries = chunk.len() as u8;
+
+            let mut count = I::Value::default();
+            for e in chunk { I::increment_offset(&mut count, e); }
+            counts.push(count);
+            leaves.push(Box::pin(leaf));
+        }
+
+        for i in 1..leaves.len() {
+            let next = unsafe { ref_to_nonnull(leaves[i].as_ref().get_ref()) };
+            unsafe { leaves[i - 1].as_mut().get_unchecked_mut().next = Some(next); }
+        }
+        for leaf in leaves.iter() {
+            let ptr = unsafe { ref_to_nonnull(leaf.as_ref().get_ref()) };
+            for e in leaf.as_slice() { notify(*e, ptr); }
+        }
+
+        let mut level: Vec<(I::Value, Node<E, I, IE, LE>)> = counts.into_iter()
+            .zip(leaves.into_iter().map(Node::Leaf))
+            .collect();
+
+        // Then build each layer of internal nodes on top of the layer below.
+        while level.len() > 1 {
+            let mut next_level = Vec::new();
+            let mut children = level.into_iter();
+            for len in even_chunk_lens(children.len(), IE) {
+                let mut node = NodeInternal::new_with_parent(ParentPtr::Root(NonNull::dangling()));
+                let parent = unsafe { node.to_parent_ptr() };
+                let mut count = I::Value::default();
+                for idx in 0..len {
+                    let (c, mut child) = children.next().unwrap();
+                    child.set_parent(parent);
+                    count += c;
+                    node.as_mut().set_entry(idx, c, Some(child));
+                }
+                next_level.push((count, Node::Internal(node)));
+            }
+            level = next_level;
+        }
+
+        let (count, root) = level.pop().unwrap();
+        unsafe {
+            let parent_ref = tree.as_ref().get_ref().to_parent_ptr();
+            let t = tree.as_mut().get_unchecked_mut();
+            t.root = root;
+            t.root.set_parent(parent_ref);
+            t.count = count;
+        }
+        tree
+    }
+
+    /// Remove all entries from the tree. The tree's first leaf is kept and reused as the new
+    /// (empty) root, so clearing a tree with few entries doesn't allocate.
+    ///
+    /// Any pointers to leaves (eg from notify) are invalidated.
+    pub fn clear(self: &mut Pin<Box<Self>>) {
+        fn first_leaf<E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize>(node: Node<E, I, IE, LE>) -> Pin<Box<NodeLeaf<E, I, IE, LE>>> {
+            match node {
+                Node::Leaf(leaf) => leaf,
+                Node::Internal(mut n) => {
+                    // The rest of the internal node is dropped here.
+                    let child = unsafe { n.as_mut().get_unchecked_mut() }.children[0].take().unwrap();
+                    first_leaf(child)
+                }
+            }
+        }
+
+        unsafe {
+            let parent_ref = self.as_ref().get_ref().to_parent_ptr();
+            let t = self.as_mut().get_unchecked_mut();
+            let old_root = core::mem::replace(&mut t.root, Node::Leaf(Box::pin(NodeLeaf::new(None))));
+            let mut leaf = first_leaf(old_root);
+
+            let l = leaf.as_mut().get_unchecked_mut();
+            l.parent = parent_ref;
+            l.num_entries = 0;
+            l.data = [E::default(); LE];
+            l.next = None;
+
+            t.root = Node::Leaf(leaf);
+            t.count = I::Value::default();
+        }
+    }
+
     fn root_ref_mut(self: Pin<&mut Self>) -> &mut Node<E, I, IE, LE> {
         unsafe {
             &mut self.get_unchecked_mut().root
@@ -172,9 +361,9 @@ impl<E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> Cont
     }
 
     // Returns size.
-    fn check_leaf(leaf: &NodeLeaf<E, I, IE, LE>, expected_parent: ParentPtr<E, I, IE, LE>) -> I::Value {
-        assert_eq!(leaf.parent, expected_parent);
-        
+    fn check_leaf(leaf: &NodeLeaf<E, I, IE, LE>, expected_parent: ParentPtr<E, I, IE, LE>) -> Result<I::Value, TreeInvariantError> {
+        if leaf.parent != expected_parent { return Err(TreeInvariantError::WrongParent); }
+
         // let mut count: usize = 0;
         let mut count = I::Value::default();
 
@@ -182,28 +371,28 @@ impl<E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> Cont
             // assert!(e.is_valid());
 
             // Make sure there's no data after an invalid entry
-            assert_ne!(e.len(), 0, "Invalid leaf - 0 length");
+            if e.len() == 0 { return Err(TreeInvariantError::EmptyEntry); }
             // count += e.content_len() as usize;
             I::increment_offset(&mut count, e);
         }
 
         // An empty leaf is only valid if we're the root element.
         if let ParentPtr::Internal(_) = leaf.parent {
-            assert_ne!(leaf.num_entries, 0, "Non-root leaf is empty");
+            if leaf.num_entries == 0 { return Err(TreeInvariantError::EmptyLeaf); }
         }
 
         // Check the next pointer makes sense.
         // Note we're using adjacent_leaf_by_traversal, which forces the full traversal.
         let next = leaf.adjacent_leaf_by_traversal(true);
-        assert_eq!(next, leaf.next);
+        if next != leaf.next { return Err(TreeInvariantError::WrongNextPointer); }
 
-        count
+        Ok(count)
     }
-    
+
     // Returns size.
-    fn check_internal(node: &NodeInternal<E, I, IE, LE>, expected_parent: ParentPtr<E, I, IE, LE>) -> I::Value {
-        assert_eq!(node.parent, expected_parent);
-        
+    fn check_internal(node: &NodeInternal<E, I, IE, LE>, expected_parent: ParentPtr<E, I, IE, LE>) -> Result<I::Value, TreeInvariantError> {
+        if node.parent != expected_parent { return Err(TreeInvariantError::WrongParent); }
+
         // let mut count_total: usize = 0;
         let mut count_total = I::Value::default();
         let mut done = false;
@@ -217,7 +406,7 @@ impl<E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> Cont
 
             if let Some(child) = child {
                 // Make sure there's no data after an invalid entry
-                assert!(!done);
+                if done { return Err(TreeInvariantError::ChildrenNotPacked); }
 
                 let child_ref = child;
 
@@ -227,41 +416,73 @@ impl<E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> Cont
                 };
                 // Make sure all children have the same type.
                 if child_type.is_none() { child_type = Some(actual_type) }
-                else { assert_eq!(child_type, Some(actual_type)); }
+                else if child_type != Some(actual_type) { return Err(TreeInvariantError::MixedChildTypes); }
 
                 // Recurse
                 let count_actual = match child_ref {
-                    Node::Leaf(ref n) => { Self::check_leaf(n.as_ref().get_ref(), self_parent) },
-                    Node::Internal(ref n) => { Self::check_internal(n.as_ref().get_ref(), self_parent) },
+                    Node::Leaf(ref n) => { Self::check_leaf(n.as_ref().get_ref(), self_parent)? },
+                    Node::Internal(ref n) => { Self::check_internal(n.as_ref().get_ref(), self_parent)? },
                 };
 
                 // Make sure all the individual counts match.
-                // if *child_count_expected as usize != count_actual {
-                //     eprintln!("xxx {:#?}", node);
-                // }
-                assert_eq!(child_count_expected, count_actual, "Child node count does not match");
+                if child_count_expected != count_actual { return Err(TreeInvariantError::WrongChildMetrics); }
                 count_total += count_actual;
             } else {
                 done = true;
             }
         }
 
-        count_total
+        if child_type.is_none() { return Err(TreeInvariantError::EmptyInternalNode); }
+
+        Ok(count_total)
     }
 
-    pub fn check(&self) {
+    /// Check the structure of the tree, returning the first problem found. Unlike
+    /// [`check`](Self::check) this doesn't panic, so it can be used to monitor the health of trees
+    /// in release builds.
+    ///
+    /// This walks the whole tree, so it takes O(n) time.
+    pub fn validate(&self) -> Result<(), TreeInvariantError> {
         // Check the parent of each node is its correct parent
         // Check the size of each node is correct up and down the tree
-        // println!("check tree {:#?}", self);
         let root = &self.root;
         let expected_parent = ParentPtr::Root(unsafe { ref_to_nonnull(self) });
         let expected_size = match root {
-            Node::Internal(n) => { Self::check_internal(n, expected_parent) },
-            Node::Leaf(n) => { Self::check_leaf(n, expected_parent) },
+            Node::Internal(n) => { Self::check_internal(n, expected_parent)? },
+            Node::Leaf(n) => { Self::check_leaf(n, expected_parent)? },
         };
-        assert_eq!(self.count, expected_size, "tree.count is incorrect");
+        if self.count != expected_size { return Err(TreeInvariantError::WrongCount); }
+        Ok(())
+    }
+
+    /// Check the structure of the tree.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tree is invalid. See [`validate`](Self::validate).
+    pub fn check(&self) {
+        // println!("check tree {:#?}", self);
+        if let Err(err) = self.validate() {
+            panic!("Invalid content tree: {}", err);
+        }
+    }
+
+    /// Get statistics about the shape and size of the tree.
+    ///
+    /// This walks the whole tree, so it takes O(n) time.
+    pub fn stats(&self) -> TreeStats {
+        let (num_internal_nodes, num_leaf_nodes) = self.count_nodes();
+        TreeStats {
+            num_entries: self.count_entries(),
+            num_internal_nodes,
+            num_leaf_nodes,
+            leaf_capacity: LE,
+            depth: self.get_depth(),
+            memory_usage: self.count_total_memory(),
+        }
     }
 
+    #[cfg(feature = "std")]
     fn print_node_tree(node: &Node<E, I, IE, LE>, depth: usize) {
         for _ in 0..depth { eprint!("  "); }
         match node {
@@ -286,12 +507,14 @@ impl<E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> Cont
         }
     }
 
+    #[cfg(feature = "std")]
     #[allow(unused)]
     pub fn print_ptr_tree(&self) {
         eprintln!("Tree count {:?} ptr {:?}", self.count, self as *const _);
         Self::print_node_tree(&self.root, 1);
     }
 
+    #[cfg(feature = "std")]
     #[allow(unused)]
     pub fn print_stats(&self, name: &str, detailed: bool) {
         // We'll get the distribution of entry sizes
@@ -350,11 +573,11 @@ impl<E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> Cont
 
         if detailed {
             println!("Entry distribution {:?}", size_counts);
-            println!("Internal node size {}", std::mem::size_of::<NodeInternal<E, I, IE, LE>>());
+            println!("Internal node size {}", core::mem::size_of::<NodeInternal<E, I, IE, LE>>());
             println!("Node entry size {} alignment {}",
-                     std::mem::size_of::<Option<Node<E, I, IE, LE>>>(),
-                     std::mem::align_of::<Option<Node<E, I, IE, LE>>>());
-            println!("Leaf size {}", std::mem::size_of::<NodeLeaf<E, I, IE, LE>>());
+                     core::mem::size_of::<Option<Node<E, I, IE, LE>>>(),
+                     core::mem::align_of::<Option<Node<E, I, IE, LE>>>());
+            println!("Leaf size {}", core::mem::size_of::<NodeLeaf<E, I, IE, LE>>());
         }
     }
 
@@ -403,7 +626,7 @@ impl<E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> Cont
                 }
             }
             Node::Leaf(_) => {
-                *size += std::mem::size_of::<NodeLeaf<E, I, IE, LE>>();
+                *size += core::mem::size_of::<NodeLeaf<E, I, IE, LE>>();
             }
         }
     }
@@ -1,8 +1,8 @@
 #![allow(clippy::needless_lifetimes)] // Clippy doesn't understand the need for some lifetimes below
 
-use std::cmp::Ordering;
-use std::marker::PhantomData;
-use std::ops::{Deref, AddAssign, DerefMut};
+use core::cmp::Ordering;
+use core::marker::PhantomData;
+use core::ops::{Deref, AddAssign, DerefMut, Range};
 use rle::Searchable;
 
 use super::*;
@@ -62,14 +62,14 @@ impl<'a, E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize>
 
     #[inline(always)]
     fn deref(&self) -> &Self::Target {
-        unsafe { std::mem::transmute(self) }
+        unsafe { core::mem::transmute(self) }
     }
 }
 
 impl<'a, E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> DerefMut for MutCursor<'a, E, I, IE, LE> {
     #[inline(always)]
     fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { std::mem::transmute(self) }
+        unsafe { core::mem::transmute(self) }
     }
 }
 
@@ -186,6 +186,19 @@ impl<'a, E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize>
             ContentTreeRaw::unsafe_mutate_single_entry_notify(map_fn, self, replace_max, notify)
         }
     }
+
+    /// Mutate the next replace_len items from this cursor in-place, splitting entries at the edges
+    /// of the range as needed. Each entry in the range is passed to map_fn.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range extends past the end of the tree.
+    pub fn mutate_entries_notify<MapFn, N>(&mut self, replace_len: usize, notify: N, map_fn: MapFn)
+    where N: FnMut(E, NonNull<NodeLeaf<E, I, IE, LE>>), MapFn: Fn(&mut E) {
+        unsafe {
+            ContentTreeRaw::unsafe_mutate_entries_notify(map_fn, &mut self.inner, replace_len, notify)
+        }
+    }
 }
 
 impl<E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> ContentTreeRaw<E, I, IE, LE> {
@@ -235,6 +248,28 @@ impl<E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> Cont
     }
 }
 
+impl<E: ContentTraits, I: FindOffset<E>, const IE: usize, const LE: usize> ContentTreeRaw<E, I, IE, LE> {
+    /// Mutate the entries in the named range of offset positions in-place. Entries which straddle
+    /// the edges of the range are split, so map_fn only sees items inside the range. This is a
+    /// safe alternative to manipulating the tree through an UnsafeCursor.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range extends past the end of the tree.
+    pub fn mutate_range_notify<MapFn, N>(self: &mut Pin<Box<Self>>, range: Range<usize>, notify: N, map_fn: MapFn)
+    where N: FnMut(E, NonNull<NodeLeaf<E, I, IE, LE>>), MapFn: Fn(&mut E) {
+        assert!(range.start <= range.end && range.end <= self.offset_len(), "Range is out of bounds");
+        if range.is_empty() { return; }
+        self.mut_cursor_at_offset_pos(range.start, false)
+            .mutate_entries_notify(range.len(), notify, map_fn);
+    }
+
+    pub fn mutate_range<MapFn>(self: &mut Pin<Box<Self>>, range: Range<usize>, map_fn: MapFn)
+    where MapFn: Fn(&mut E) {
+        self.mutate_range_notify(range, null_notify, map_fn);
+    }
+}
+
 impl<R, E: ContentTraits + ContentLength, I: FindContent<E>, const IE: usize, const LE: usize> SafeCursor<R, E, I, IE, LE> {
     pub fn count_content_pos(&self) -> usize {
         unsafe { self.inner.unsafe_count_content_pos() }
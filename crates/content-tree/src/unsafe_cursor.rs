@@ -1,10 +1,10 @@
-use std::cmp::Ordering;
-use std::hint::unreachable_unchecked;
+use core::cmp::Ordering;
+use core::hint::unreachable_unchecked;
 
 use rle::Searchable;
 
 use super::*;
-use std::ops::AddAssign;
+use core::ops::AddAssign;
 
 // TODO: All these methods should be unsafe and have safe wrappers in safe_cursor.
 impl<E: ContentTraits, I: TreeMetrics<E>, const IE: usize, const LE: usize> UnsafeCursor<E, I, IE, LE> {
@@ -18,9 +18,9 @@ use similar::{ChangeTag, TextDiff};
 use similar::utils::TextDiffRemapper;
 use diamond_types::causalgraph::agent_assignment::remote_ids::RemoteVersionOwned;
 use diamond_types::list::{gen_oplog, ListBranch, ListOpLog};
-use diamond_types::list::encoding::{ENCODE_FULL, EncodeOptions};
+use diamond_types::list::encoding::{Compression, ENCODE_FULL, EncodeOptions};
 use crate::dot::{generate_svg_with_dot};
-use crate::export::{check_trace_invariants, export_full_to_json, export_trace_to_json, export_transformed};
+use crate::export::{check_trace_invariants, export_full_to_json};
 use crate::git::extract_from_git;
 
 #[derive(Parser, Debug)]
@@ -101,6 +101,14 @@ enum Commands {
         history: bool,
     },
 
+    /// Print a summary of a diamond types file: its agents, format features and version.
+    ///
+    /// This works even on files which this version of diamond types can't load.
+    Info {
+        /// Diamond types file to read
+        dt_filename: OsString,
+    },
+
     /// Get (print) the current version of a DT file
     Version {
         /// Diamond types file to read
@@ -183,6 +191,29 @@ enum Commands {
         quiet: bool,
     },
 
+    /// Merge the changes from several diamond types files (or patches) together, and save the
+    /// result to a new file.
+    ///
+    /// Patches are merged in the order they're named, so each patch must come after the files
+    /// containing the changes it depends on.
+    Merge {
+        /// Files to merge
+        #[arg(required = true, num_args = 2..)]
+        dt_filenames: Vec<OsString>,
+
+        /// Save the merged file here
+        #[arg(short, long)]
+        output: OsString,
+
+        /// Force overwrite the file which exists with the same name.
+        #[arg(short, long)]
+        force: bool,
+
+        /// Suppress all output to stdout
+        #[arg(short, long)]
+        quiet: bool,
+    },
+
     /// Export a diamond types file to raw JSON. This outputs the raw data stored in a diamond types
     /// file in a simplified JSON format.
     Export {
@@ -445,6 +476,32 @@ fn main() -> Result<(), anyhow::Error> {
             }
         }
 
+        Commands::Info { dt_filename } => {
+            let data = fs::read(&dt_filename)?;
+            let info = ListOpLog::file_info(&data)?;
+
+            if let Some(doc_id) = info.doc_id.as_ref() {
+                println!("Document ID: {doc_id}");
+            }
+            println!("Size: {} bytes", data.len());
+            println!("Agents: {}", info.agent_names.join(", "));
+            println!("Tie break: {:?}", info.tie_break);
+            println!("Features: {:?} (required: {:?})", info.features, info.required_features);
+            if !info.start_version.is_empty() {
+                println!("Patch from version: {}", serde_json::to_string(&info.start_version).unwrap());
+            }
+
+            if !info.is_supported() {
+                println!("File uses features which this version of diamond types does not support");
+                return Ok(());
+            }
+
+            let oplog = ListOpLog::load_from(&data)?;
+            println!("Operations: {}", oplog.len());
+            println!("Version: {}", serde_json::to_string(&oplog.remote_frontier()).unwrap());
+            println!("Content length: {} chars", oplog.checkout_tip().len());
+        }
+
         Commands::Version { oplog } => {
             let version = serde_json::to_string(&oplog.remote_frontier()).unwrap();
             println!("{version}");
@@ -530,7 +587,11 @@ fn main() -> Result<(), anyhow::Error> {
                 experimentally_store_end_branch_content: false,
                 store_inserted_content: !no_inserted_content,
                 store_deleted_content: !no_deleted_content,
-                compress_content: !uncompressed,
+                compression: if uncompressed { Compression::None } else { Compression::LZ4 },
+                filter: None,
+                store_chunk_checksums: false,
+                segment_history: false,
+                dedup_content: false,
                 verbose: false
             }, from_version.as_ref());
 
@@ -556,6 +617,22 @@ fn main() -> Result<(), anyhow::Error> {
             }
         }
 
+        Commands::Merge { dt_filenames, output, force, quiet } => {
+            let mut oplog = ListOpLog::new();
+            for filename in &dt_filenames {
+                let data = fs::read(filename)?;
+                oplog.decode_and_add(&data)?;
+            }
+
+            let data = oplog.encode(ENCODE_FULL);
+            maybe_overwrite(&output, &data, force)?;
+
+            if !quiet {
+                println!("Merged version {}", serde_json::to_string(&oplog.remote_frontier()).unwrap());
+                println!("Written {} bytes to {}", data.len(), output.to_str().unwrap_or("(invalid)"));
+            }
+        }
+
         Commands::Export { dt_filename, output, pretty } => {
             let data = fs::read(&dt_filename)?;
             let oplog = ListOpLog::load_from(&data)?;
@@ -588,7 +665,7 @@ fn main() -> Result<(), anyhow::Error> {
                 ");
             }
 
-            let result = export_trace_to_json(&oplog);
+            let result = oplog.export_trace();
             write_serde_data(output, pretty, &result)?;
         }
 
@@ -607,7 +684,7 @@ fn main() -> Result<(), anyhow::Error> {
             let data = fs::read(&dt_filename)?;
             let oplog = ListOpLog::load_from(&data)?;
 
-            let result = export_transformed(&oplog, timestamp);
+            let result = oplog.export_sequential_trace(&timestamp);
             write_serde_data(output, pretty, &result)?;
         }
 
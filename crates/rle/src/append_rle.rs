@@ -1,5 +1,6 @@
 #[cfg(feature = "smallvec")]
 use smallvec::SmallVec;
+use alloc::vec::Vec;
 
 use crate::MergableSpan;
 
@@ -1,9 +1,13 @@
-use std::fmt::Debug;
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+use core::fmt::Debug;
 
 pub use append_rle::AppendRle;
 pub use splitable_span::*;
 pub use merge_iter::*;
-use std::ops::Range;
+use core::ops::Range;
 
 mod splitable_span;
 mod merge_iter;
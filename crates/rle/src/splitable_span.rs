@@ -1,4 +1,4 @@
-use std::ops::{Deref, DerefMut, Range};
+use core::ops::{Deref, DerefMut, Range};
 
 pub trait HasLength {
     /// The number of child items in the entry. This is indexed with the size used in truncate.
@@ -442,11 +442,11 @@ impl MergableSpan for Range<u32> {
 ///
 /// Use this to test splitablespan implementations in tests.
 // #[cfg(test)]
-pub fn test_splitable_methods_valid<E: SplitAndJoinSpan + std::fmt::Debug + Clone + Eq>(entry: E) {
+pub fn test_splitable_methods_valid<E: SplitAndJoinSpan + core::fmt::Debug + Clone + Eq>(entry: E) {
     test_splitable_methods_valid_ctx(entry, &());
 }
 
-pub fn test_splitable_methods_valid_ctx<E: SplitAndJoinSpanCtx + std::fmt::Debug + Clone + Eq>(entry: E, ctx: &E::Ctx) {
+pub fn test_splitable_methods_valid_ctx<E: SplitAndJoinSpanCtx + core::fmt::Debug + Clone + Eq>(entry: E, ctx: &E::Ctx) {
     assert!(entry.len() >= 2, "Call this with a larger entry");
     // dbg!(&entry);
 
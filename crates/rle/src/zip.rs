@@ -1,5 +1,5 @@
-use std::cmp::Ordering;
-use std::mem::take;
+use core::cmp::Ordering;
+use core::mem::take;
 use crate::{HasLength, SplitableSpan};
 
 // Also used by intersect.
@@ -14,7 +14,7 @@ use diamond_types::list::*;
 use trace_alloc::*;
 #[cfg(feature = "memusage")]
 use humansize::{DECIMAL, format_size};
-use diamond_types::list::encoding::EncodeOptions;
+use diamond_types::list::encoding::{Compression, EncodeOptions};
 
 pub fn apply_edits_direct(doc: &mut ListCRDT, txns: &Vec<TestTxn>) {
     let id = doc.get_or_create_agent_id("jeremy");
@@ -76,7 +76,8 @@ fn print_stats_for_testdata(name: &str) {
         experimentally_store_end_branch_content: false,
         store_inserted_content: true,
         store_deleted_content: false,
-        compress_content: true,
+        compression: Compression::LZ4,
+        filter: None,
         verbose: true
     });
     println!("Regular file size {} bytes", data.len());
@@ -109,7 +110,8 @@ fn print_stats_for_file(name: &str) {
         experimentally_store_end_branch_content: true,
         store_inserted_content: false,
         store_deleted_content: false,
-        compress_content: true,
+        compression: Compression::LZ4,
+        filter: None,
         verbose: true
     });
     println!("Smol size {}", data_smol.len());
@@ -1,7 +1,7 @@
 #![allow(unused)]
 
 use std::env;
-use diamond_types::list::{ListOpLog, encoding::EncodeOptions};
+use diamond_types::list::{ListOpLog, encoding::{Compression, EncodeOptions}};
 use rle::zip::rle_zip;
 
 fn print_stats_for_file(name: &str) {
@@ -66,7 +66,8 @@ fn print_stats_for_file(name: &str) {
         experimentally_store_end_branch_content: false,
         store_inserted_content: true,
         store_deleted_content: true,
-        compress_content: true,
+        compression: Compression::LZ4,
+        filter: None,
         verbose: true,
     });
 }
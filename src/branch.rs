@@ -1,4 +1,5 @@
-use std::collections::{btree_map, BTreeMap, BTreeSet};
+use alloc::collections::{btree_map, BTreeMap, BTreeSet};
+use alloc::vec::Vec;
 use smallvec::SmallVec;
 use crate::{CRDTKind, DTRange, Branch, OpLog, LV, LVKey, RegisterInfo, RegisterState, RegisterValue, ROOT_CRDT_ID, Primitive};
 use smartstring::alias::String as SmartString;
@@ -60,7 +61,7 @@ impl OpLog {
     }
 
 
-    fn checkout_map_key_nc(&self, crdt: LVKey, key: &str) -> Option<RegisterValue> {
+    pub(crate) fn checkout_map_key_nc(&self, crdt: LVKey, key: &str) -> Option<RegisterValue> {
         // Just checkout this path item.
         let info = self.map_keys.get(&(crdt, key.into()))?;
         Some(self.value_for_register_nc(info))
@@ -97,40 +98,55 @@ impl OpLog {
         // I'm going with option 2, but that might not be the best option.
 
         let mut maps_to_copy = vec![ROOT_CRDT_ID];
+        let mut lists_to_copy = vec![];
         let mut result = Branch {
             frontier: self.cg.version.clone(),
             maps: Default::default(),
             texts: Default::default(),
+            lists: Default::default(),
         };
 
-        while let Some(crdt) = maps_to_copy.pop() {
-            let mut this_map = BTreeMap::new();
-            for ((this_id, key), info) in btree_range_for_crdt(&self.map_keys, crdt) {
-                debug_assert_eq!(*this_id, crdt);
-                let state = self.get_state_for_register(info);
-
-                state.each_value(|rv| {
-                    // Recursively copy value and conflicting values.
-                    match rv {
-                        RegisterValue::Primitive(_) => {}
-                        RegisterValue::OwnedCRDT(CRDTKind::Map, child_map) => {
-                            // I could use recursion here but this avoids stack-smashing attacks.
-                            maps_to_copy.push(*child_map);
-                        }
-                        RegisterValue::OwnedCRDT(CRDTKind::Register, _) => { todo!() }
-                        RegisterValue::OwnedCRDT(CRDTKind::Collection, _) => { todo!() }
-                        RegisterValue::OwnedCRDT(CRDTKind::Text, text_crdt) => {
-                            // Eventually (rich) text items might contain more embedded CRDTs. But for
-                            // now this is fine.
-                            let rope = self.checkout_text(*text_crdt);
-                            result.texts.insert(*text_crdt, rope);
-                        }
-                    }
-                });
-
-                this_map.insert(key.clone(), state);
+        let copy_value = |result: &mut Branch, rv: &RegisterValue, maps_to_copy: &mut Vec<LVKey>, lists_to_copy: &mut Vec<LVKey>| {
+            // Recursively copy value and conflicting values.
+            match rv {
+                RegisterValue::Primitive(_) => {}
+                RegisterValue::OwnedCRDT(CRDTKind::Map, child_map) => {
+                    // I could use recursion here but this avoids stack-smashing attacks.
+                    maps_to_copy.push(*child_map);
+                }
+                RegisterValue::OwnedCRDT(CRDTKind::Register, _) => { todo!() }
+                RegisterValue::OwnedCRDT(CRDTKind::Collection, _) => { todo!() }
+                RegisterValue::OwnedCRDT(CRDTKind::Text, text_crdt) => {
+                    // Eventually (rich) text items might contain more embedded CRDTs. But for
+                    // now this is fine.
+                    let rope = self.checkout_text(*text_crdt);
+                    result.texts.insert(*text_crdt, rope);
+                }
+                RegisterValue::OwnedCRDT(CRDTKind::List, child_list) => {
+                    lists_to_copy.push(*child_list);
+                }
             }
-            result.maps.insert(crdt, this_map);
+        };
+
+        loop {
+            if let Some(crdt) = maps_to_copy.pop() {
+                let mut this_map = BTreeMap::new();
+                for ((this_id, key), info) in btree_range_for_crdt(&self.map_keys, crdt) {
+                    debug_assert_eq!(*this_id, crdt);
+                    let state = self.get_state_for_register(info);
+
+                    state.each_value(|rv| copy_value(&mut result, rv, &mut maps_to_copy, &mut lists_to_copy));
+
+                    this_map.insert(key.clone(), state);
+                }
+                result.maps.insert(crdt, this_map);
+            } else if let Some(crdt) = lists_to_copy.pop() {
+                let items = self.checkout_list(crdt);
+                for rv in items.iter() {
+                    copy_value(&mut result, rv, &mut maps_to_copy, &mut lists_to_copy);
+                }
+                result.lists.insert(crdt, items);
+            } else { break; }
         }
 
         result
@@ -149,19 +165,20 @@ impl Branch {
             frontier: Default::default(),
             maps: BTreeMap::from([(ROOT_CRDT_ID, Default::default())]),
             texts: Default::default(),
+            lists: Default::default(),
         }
     }
 
-    fn recursive_delete_reg_state(&mut self, state: RegisterState) {
-        fn delete_value(b: &mut Branch, val: RegisterValue) {
-            if let RegisterValue::OwnedCRDT(kind, key) = val {
-                b.recursive_delete(kind, key);
-            }
+    fn delete_value(&mut self, val: RegisterValue) {
+        if let RegisterValue::OwnedCRDT(kind, key) = val {
+            self.recursive_delete(kind, key);
         }
+    }
 
-        delete_value(self, state.value);
+    fn recursive_delete_reg_state(&mut self, state: RegisterState) {
+        self.delete_value(state.value);
         for rv in state.conflicts_with {
-            delete_value(self, rv);
+            self.delete_value(rv);
         }
     }
 
@@ -177,6 +194,12 @@ impl Branch {
             CRDTKind::Text => {
                 self.texts.remove(&crdt); // Easy peasy!
             }
+            CRDTKind::List => {
+                let Some(items) = self.lists.remove(&crdt) else { return; };
+                for rv in items {
+                    self.delete_value(rv);
+                }
+            }
             _ => { todo!() }
         }
     }
@@ -185,6 +208,7 @@ impl Branch {
     pub fn merge_changes_to_tip(&mut self, oplog: &OpLog) -> SmallVec<[DTRange; 4]> {
         // Well, for now nothing can be deleted yet. So that makes things easier.
         let diff_rev = oplog.cg.diff_since_rev(self.frontier.as_ref());
+        let mut lists_changed = false;
 
         for range in diff_rev.iter().rev() {
             // for (_, text_crdt) in self.text_index.range(*range) {
@@ -217,6 +241,14 @@ impl Branch {
             for (_v, text_crdt) in oplog.text_index.range(*range) {
                 if oplog.deleted_crdts.contains(text_crdt) { continue; }
 
+                if oplog.lists.contains(text_crdt) {
+                    // Lists are just checked out again. The oplog doesn't track which list elements
+                    // have been deleted, so the CRDTs inside removed elements are cleaned up below.
+                    self.lists.insert(*text_crdt, oplog.checkout_list(*text_crdt));
+                    lists_changed = true;
+                    continue;
+                }
+
                 let textinfo = oplog.texts.get(text_crdt).unwrap();
                 let text_content = self.texts.entry(*text_crdt).or_default();
 
@@ -224,10 +256,43 @@ impl Branch {
             }
         }
 
+        if lists_changed { self.remove_unreachable(); }
+
         self.frontier = oplog.cg.version.clone();
         diff_rev
     }
 
+    /// Remove any CRDTs which aren't reachable from the root map.
+    fn remove_unreachable(&mut self) {
+        let mut maps = BTreeSet::new();
+        let mut texts = BTreeSet::new();
+        let mut lists = BTreeSet::new();
+        let mut queue = vec![RegisterValue::OwnedCRDT(CRDTKind::Map, ROOT_CRDT_ID)];
+
+        while let Some(value) = queue.pop() {
+            match value {
+                RegisterValue::OwnedCRDT(CRDTKind::Map, crdt) => {
+                    maps.insert(crdt);
+                    let Some(map) = self.maps.get(&crdt) else { continue; };
+                    for state in map.values() {
+                        state.each_value(|v| queue.push(v.clone()));
+                    }
+                }
+                RegisterValue::OwnedCRDT(CRDTKind::Text, crdt) => { texts.insert(crdt); }
+                RegisterValue::OwnedCRDT(CRDTKind::List, crdt) => {
+                    lists.insert(crdt);
+                    let Some(items) = self.lists.get(&crdt) else { continue; };
+                    queue.extend(items.iter().cloned());
+                }
+                _ => {}
+            }
+        }
+
+        self.maps.retain(|crdt, _| maps.contains(crdt));
+        self.texts.retain(|crdt, _| texts.contains(crdt));
+        self.lists.retain(|crdt, _| lists.contains(crdt));
+    }
+
     pub fn crdt_at_path(&self, path: &[&str]) -> (CRDTKind, LVKey) {
         let mut kind = CRDTKind::Map;
         let mut key = ROOT_CRDT_ID;
@@ -292,24 +357,37 @@ impl Branch {
             .copied()
             .collect();
 
+        let mut owned_list_crdts = BTreeSet::new();
+        let root_list_crdts: BTreeSet<_> = self.lists.keys()
+            .copied()
+            .collect();
+
+        let mut add_owned = |v: &RegisterValue| {
+            if let RegisterValue::OwnedCRDT(kind, key) = v {
+                match kind {
+                    CRDTKind::Map => &mut owned_map_crdts,
+                    CRDTKind::Text => &mut owned_text_crdts,
+                    CRDTKind::List => &mut owned_list_crdts,
+                    _ => { unimplemented!() }
+                }.insert(*key);
+            }
+        };
+
         for (map_crdt, state) in &self.maps {
             root_map_crdts.insert(*map_crdt);
 
             for reg_state in state.values() {
-                reg_state.each_value(|v| {
-                    if let RegisterValue::OwnedCRDT(kind, key) = v {
-                        match kind {
-                            CRDTKind::Map => &mut owned_map_crdts,
-                            CRDTKind::Text => &mut owned_text_crdts,
-                            _ => { unimplemented!() }
-                        }.insert(*key);
-                    }
-                });
+                reg_state.each_value(&mut add_owned);
             }
         }
 
+        for items in self.lists.values() {
+            items.iter().for_each(&mut add_owned);
+        }
+
         assert_eq!(owned_map_crdts, root_map_crdts);
         assert_eq!(owned_text_crdts, root_text_crdts);
+        assert_eq!(owned_list_crdts, root_list_crdts);
     }
 }
 
@@ -404,4 +482,31 @@ mod tests {
 
         assert_eq!(branch_expected, branch_incremental);
     }
+
+    #[test]
+    fn lists() {
+        let mut oplog = OpLog::new();
+        let seph = oplog.cg.get_or_create_agent_id("seph");
+
+        let mut branch_incremental = Branch::new();
+        let list = oplog.local_map_set(seph, ROOT_CRDT_ID, "list", CreateValue::NewCRDT(CRDTKind::List));
+        let items = oplog.local_list_insert(seph, list, 0, &[
+            CreateValue::Primitive(Primitive::I64(1)),
+            CreateValue::NewCRDT(CRDTKind::Map),
+            CreateValue::NewCRDT(CRDTKind::Text),
+        ]);
+        branch_incremental.merge_changes_to_tip(&oplog);
+        oplog.local_map_set(seph, items.start + 1, "child", CreateValue::NewCRDT(CRDTKind::Text));
+        oplog.local_text_op(seph, items.start + 2, TextOperation::new_insert(0, "hi"));
+        branch_incremental.merge_changes_to_tip(&oplog);
+
+        // Deleting the map element removes it (and its children) from the branch.
+        oplog.local_list_delete(seph, list, 1..2);
+        branch_incremental.merge_changes_to_tip(&oplog);
+        oplog.dbg_check(true);
+
+        let branch_expected = check_oplog_checkouts_match(&oplog);
+        assert_eq!(branch_expected, branch_incremental);
+        assert_eq!(branch_expected.lists[&list].len(), 2);
+    }
 }
\ No newline at end of file
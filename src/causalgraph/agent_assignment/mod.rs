@@ -1,9 +1,15 @@
-use std::cmp::Ordering;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
 use smartstring::alias::String as SmartString;
-use rle::HasLength;
+use rle::{HasLength, Searchable};
 use crate::causalgraph::agent_span::{AgentSpan, AgentVersion};
-use crate::{AgentId, DTRange, LV};
-use crate::rle::{KVPair, RleVec};
+use crate::{AgentId, DTError, DTRange, LV};
+use crate::rle::{KVPair, RleMap, RleVec};
+
+/// Maps from an agent's sequence numbers to local versions. Most agents' sequence numbers map to a
+/// handful of runs, but this can get large and sparse when lots of concurrent edits are merged.
+pub(crate) type AgentSeqMap = RleMap<KVPair<DTRange>>;
 
 pub mod remote_ids;
 
@@ -23,7 +29,7 @@ pub(crate) struct ClientData {
     /// might be ordered as (0, 2, 1). This will only happen when changes are concurrent. The order
     /// of time spans must always obey the partial order of changes. But it will not necessarily
     /// agree with the order amongst time spans.
-    pub(crate) lv_for_seq: RleVec<KVPair<DTRange>>,
+    pub(crate) lv_for_seq: AgentSeqMap,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -42,6 +48,9 @@ pub struct AgentAssignment {
     /// This is used to map external CRDT locations -> Order numbers.
     pub(crate) client_data: Vec<ClientData>,
 
+    /// Maps from agent names to their index in client_data. Documents can have thousands of
+    /// agents, so scanning client_data for a name is too slow.
+    pub(super) agent_ids: BTreeMap<SmartString, AgentId>,
 }
 
 
@@ -84,26 +93,47 @@ impl AgentAssignment {
     pub fn new() -> Self { Self::default() }
 
     pub fn get_agent_id(&self, name: &str) -> Option<AgentId> {
-        self.client_data.iter()
-            .position(|client_data| client_data.name == name)
-            .map(|id| id as AgentId)
+        self.agent_ids.get(name).copied()
     }
 
+    /// Get the ID of the named agent, creating it if needed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name is "ROOT", or if it's too long. Use
+    /// [`try_get_or_create_agent_id`](AgentAssignment::try_get_or_create_agent_id) for agent names
+    /// which come from remote peers.
     pub fn get_or_create_agent_id(&mut self, name: &str) -> AgentId {
-        // TODO: -> Result or something so this can be handled.
         if name == "ROOT" { panic!("Agent ID 'ROOT' is reserved"); }
-
         assert!(name.len() < MAX_AGENT_NAME_LENGTH, "Agent name cannot exceed {MAX_AGENT_NAME_LENGTH} UTF8 bytes");
+        self.try_get_or_create_agent_id(name).unwrap()
+    }
 
-        if let Some(id) = self.get_agent_id(name) {
+    /// Get the ID of the named agent, creating it if needed. Returns an error if the name is
+    /// reserved or too long.
+    pub fn try_get_or_create_agent_id(&mut self, name: &str) -> Result<AgentId, DTError> {
+        if name == "ROOT" || name.len() >= MAX_AGENT_NAME_LENGTH {
+            return Err(DTError::InvalidAgentName);
+        }
+
+        Ok(if let Some(id) = self.get_agent_id(name) {
             id
         } else {
             // Create a new id.
+            let id = self.client_data.len() as AgentId;
             self.client_data.push(ClientData {
                 name: SmartString::from(name),
-                lv_for_seq: RleVec::new()
+                lv_for_seq: AgentSeqMap::new()
             });
-            (self.client_data.len() - 1) as AgentId
+            self.agent_ids.insert(SmartString::from(name), id);
+            id
+        })
+    }
+
+    /// Forget about all agents with IDs >= num_agents. Used to roll back after a failed merge.
+    pub(crate) fn truncate_agents(&mut self, num_agents: usize) {
+        for c in self.client_data.drain(num_agents..) {
+            self.agent_ids.remove(&c.name);
         }
     }
 
@@ -112,6 +142,15 @@ impl AgentAssignment {
         self.client_data[agent as usize].name.as_str()
     }
 
+    /// Iterates over all the agents in the document. The iterator returns triples of
+    /// (agent id, name, number of operations by that agent).
+    pub fn iter_agents(&self) -> impl Iterator<Item = (AgentId, &str, usize)> + '_ {
+        self.client_data.iter().enumerate().map(|(id, c)| {
+            let num_ops = c.lv_for_seq.iter().map(|KVPair(_, lvs)| lvs.len()).sum();
+            (id as AgentId, c.name.as_str(), num_ops)
+        })
+    }
+
     /// Iterates over the local version mappings for the specified agent. The iterator returns
     /// triples of (seq_start, lv_start, length).
     ///
@@ -132,14 +171,26 @@ impl AgentAssignment {
     }
 
     pub fn local_to_agent_version(&self, version: LV) -> AgentVersion {
+        self.local_to_agent_version_hinted(version, &mut 0)
+    }
+
+    /// Same as [`local_to_agent_version`](Self::local_to_agent_version), but faster when called
+    /// with (mostly) sequential versions. The hint should start at 0, and be reused between calls.
+    pub fn local_to_agent_version_hinted(&self, version: LV, hint: &mut usize) -> AgentVersion {
         debug_assert_ne!(version, usize::MAX);
-        self.client_with_localtime.get(version)
+        let (loc, offset) = self.client_with_localtime.find_packed_with_offset_hinted(version, hint);
+        loc.at_offset(offset)
     }
 
     pub(crate) fn local_span_to_agent_span(&self, version: DTRange) -> AgentSpan {
+        self.local_span_to_agent_span_hinted(version, &mut 0)
+    }
+
+    /// Same as [`local_span_to_agent_span`](Self::local_span_to_agent_span), using a search hint.
+    pub(crate) fn local_span_to_agent_span_hinted(&self, version: DTRange, hint: &mut usize) -> AgentSpan {
         debug_assert_ne!(version.start, usize::MAX);
 
-        let (loc, offset) = self.client_with_localtime.find_packed_with_offset(version.start);
+        let (loc, offset) = self.client_with_localtime.find_packed_with_offset_hinted(version.start, hint);
         let start = loc.1.seq_range.start + offset;
         let end = usize::min(loc.1.seq_range.end, start + version.len());
         AgentSpan {
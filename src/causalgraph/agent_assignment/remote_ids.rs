@@ -1,15 +1,18 @@
 /// This file contains utilities to convert remote IDs to local version and back.
 
 
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
 use smartstring::alias::String as SmartString;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 use rle::{HasLength, MergableSpan, SplitableSpanHelpers};
 use crate::dtrange::DTRange;
-use crate::{Frontier, LV};
+use crate::{AgentId, Frontier, LV};
 use crate::causalgraph::agent_assignment::AgentAssignment;
 use crate::causalgraph::agent_span::{AgentVersion, AgentSpan};
+use crate::rle::KVPair;
 
 /// Remote IDs are IDs you can pass to a remote peer.
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -107,6 +110,20 @@ pub enum VersionConversionError {
     SeqInFuture,
 }
 
+/// The changes needed to convert some remote versions which are missing from the document. Each
+/// entry names a range of sequence numbers from one agent.
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct MissingOps(pub Vec<RemoteVersionSpanOwned>);
+
+/// A range of an agent's sequence numbers which isn't known to the document. Returned by
+/// [`AgentAssignment::translate_batch`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct MissingRange<'a> {
+    pub name: &'a str,
+    pub seq_range: DTRange,
+}
+
 impl AgentAssignment {
     pub fn try_remote_to_local_version(&self, rv: RemoteVersion) -> Result<LV, VersionConversionError> {
         let agent = self.get_agent_id(rv.0)
@@ -117,6 +134,87 @@ impl AgentAssignment {
             .ok_or(VersionConversionError::SeqInFuture)
     }
 
+    /// Find the changes which are needed for a set of remote versions, but which are missing from
+    /// the document. The result is sorted by agent name.
+    ///
+    /// An agent's changes are sequential, so the version (agent, seq) needs all of that agent's
+    /// changes up to seq. Any dependencies on other agents can't be known until those changes
+    /// arrive.
+    pub fn missing_remote_versions(&self, versions: &[RemoteVersion]) -> MissingOps {
+        let mut needed: BTreeMap<&str, usize> = BTreeMap::new();
+        for RemoteVersion(name, seq) in versions {
+            let end = needed.entry(name).or_default();
+            *end = (*end).max(seq + 1);
+        }
+
+        let mut result = vec![];
+        for (name, end) in needed {
+            let mut next_seq = 0;
+            if let Some(agent) = self.get_agent_id(name) {
+                for KVPair(seq, lvs) in self.client_data[agent as usize].lv_for_seq.iter() {
+                    if *seq >= end { break; }
+                    if *seq > next_seq {
+                        result.push(RemoteVersionSpanOwned(name.into(), (next_seq..*seq).into()));
+                    }
+                    next_seq = seq + lvs.len();
+                }
+            }
+            if next_seq < end {
+                result.push(RemoteVersionSpanOwned(name.into(), (next_seq..end).into()));
+            }
+        }
+        MissingOps(result)
+    }
+
+    /// Convert a batch of remote version spans to local versions. This is faster than converting
+    /// each span separately when merging patches with lots of spans from the same agents.
+    ///
+    /// The results are returned in order. A span can map to several local version ranges (or be
+    /// partly missing), so each span can produce more than one result. The lengths of the results
+    /// for each span always add up to the length of the span.
+    pub fn translate_batch<'a>(&self, spans: &[RemoteVersionSpan<'a>]) -> Vec<Result<DTRange, MissingRange<'a>>> {
+        let mut result = Vec::with_capacity(spans.len());
+        let mut cache: Option<(&str, Option<AgentId>)> = None;
+
+        for &RemoteVersionSpan(name, seq_range) in spans {
+            let agent = match cache {
+                Some((cached_name, agent)) if cached_name == name => agent,
+                _ => {
+                    let agent = self.get_agent_id(name);
+                    cache = Some((name, agent));
+                    agent
+                }
+            };
+
+            let Some(agent) = agent else {
+                if !seq_range.is_empty() {
+                    result.push(Err(MissingRange { name, seq_range }));
+                }
+                continue;
+            };
+
+            let lv_for_seq = &self.client_data[agent as usize].lv_for_seq;
+            let mut seq = seq_range.start;
+            while seq < seq_range.end {
+                match lv_for_seq.find_sparse(seq) {
+                    (Ok(KVPair(_, lvs)), offset) => {
+                        let len = usize::min(lvs.len() - offset, seq_range.end - seq);
+                        let start = lvs.start + offset;
+                        result.push(Ok((start..start + len).into()));
+                        seq += len;
+                    }
+                    (Err(missing), _) => {
+                        let end = usize::min(missing.end, seq_range.end);
+                        result.push(Err(MissingRange { name, seq_range: (seq..end).into() }));
+                        seq = end;
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
     /// This panics if the ID isn't known to the document.
     pub fn remote_to_local_version(&self, RemoteVersion(name, seq): RemoteVersion) -> LV {
         let agent = self.get_agent_id(name).unwrap();
@@ -158,11 +256,50 @@ impl AgentAssignment {
         self.agent_span_to_remote(agent_span)
     }
 
+    /// Variant of try_remote_to_local_version which remembers the last agent name it looked up.
+    /// Looking up agents by name is O(n) in the number of agents, and batches of remote versions
+    /// usually name the same agent many times in a row.
+    fn try_remote_to_local_version_cached<'a>(&self, rv: RemoteVersion<'a>, cache: &mut Option<(&'a str, AgentId)>) -> Result<LV, VersionConversionError> {
+        let agent = match cache {
+            Some((name, agent)) if *name == rv.0 => *agent,
+            _ => {
+                let agent = self.get_agent_id(rv.0)
+                    .ok_or(VersionConversionError::UnknownAgent)?;
+                *cache = Some((rv.0, agent));
+                agent
+            }
+        };
+
+        self.client_data[agent as usize]
+            .try_seq_to_lv(rv.1)
+            .ok_or(VersionConversionError::SeqInFuture)
+    }
+
+    /// Convert a batch of remote versions into local versions. The returned list is in the same
+    /// order as the input.
+    pub fn try_remote_to_local_versions<'a, B: 'a, I>(&self, ids_iter: I) -> Result<Vec<LV>, VersionConversionError>
+        where RemoteVersion<'a>: From<B>, I: Iterator<Item=B> + 'a
+    {
+        let mut cache = None;
+        ids_iter
+            .map(|rv| self.try_remote_to_local_version_cached(rv.into(), &mut cache))
+            .collect()
+    }
+
+    /// Convert a batch of local versions into remote versions. The returned list is in the same
+    /// order as the input.
+    pub fn local_to_remote_versions(&self, versions: &[LV]) -> Vec<RemoteVersion<'_>> {
+        versions.iter()
+            .map(|lv| self.local_to_remote_version(*lv))
+            .collect()
+    }
+
     pub fn try_remote_to_local_frontier<'a, B: 'a, I>(&self, ids_iter: I) -> Result<Frontier, VersionConversionError>
         where RemoteVersion<'a>: From<B>, I: Iterator<Item=B> + 'a
     {
+        let mut cache = None;
         let frontier: Frontier = ids_iter
-            .map(|rv| self.try_remote_to_local_version(rv.into()))
+            .map(|rv| self.try_remote_to_local_version_cached(rv.into(), &mut cache))
             .collect::<Result<Frontier, VersionConversionError>>()?;
 
         Ok(frontier)
@@ -213,7 +350,7 @@ impl AgentAssignment {
 
 #[cfg(test)]
 mod test {
-    use crate::causalgraph::agent_assignment::remote_ids::{RemoteVersion, RemoteVersionOwned};
+    use crate::causalgraph::agent_assignment::remote_ids::{MissingRange, RemoteVersion, RemoteVersionOwned, RemoteVersionSpan, VersionConversionError};
     use crate::CausalGraph;
 
     #[test]
@@ -246,6 +383,54 @@ mod test {
         // ]);
     }
 
+    #[test]
+    fn batch_conversion() {
+        let mut cg = CausalGraph::new();
+        cg.get_or_create_agent_id("seph");
+        cg.get_or_create_agent_id("mike");
+        cg.assign_local_op_with_parents(&[], 0, 2);
+        cg.assign_local_op_with_parents(&[], 1, 4);
+
+        let aa = &cg.agent_assignment;
+        let rvs = aa.local_to_remote_versions(&[5, 0, 1, 3]);
+        assert_eq!(&rvs, &[
+            RemoteVersion("mike", 3),
+            RemoteVersion("seph", 0),
+            RemoteVersion("seph", 1),
+            RemoteVersion("mike", 1),
+        ]);
+        assert_eq!(aa.try_remote_to_local_versions(rvs.iter().copied()), Ok(vec![5, 0, 1, 3]));
+
+        assert_eq!(aa.try_remote_to_local_versions([RemoteVersion("seph", 2)].into_iter()),
+                   Err(VersionConversionError::SeqInFuture));
+        assert_eq!(aa.try_remote_to_local_versions([RemoteVersion("fred", 0)].into_iter()),
+                   Err(VersionConversionError::UnknownAgent));
+    }
+
+    #[test]
+    fn translate_batch() {
+        let mut cg = CausalGraph::new();
+        let seph = cg.get_or_create_agent_id("seph");
+        let mike = cg.get_or_create_agent_id("mike");
+        cg.assign_local_op_with_parents(&[], seph, 2);
+        cg.assign_local_op_with_parents(&[], mike, 4);
+        cg.assign_local_op_with_parents(&[1], seph, 3);
+
+        let aa = &cg.agent_assignment;
+        assert_eq!(aa.get_agent_id("mike"), Some(mike));
+        assert_eq!(aa.translate_batch(&[
+            RemoteVersionSpan("seph", (1..4).into()),
+            RemoteVersionSpan("mike", (2..6).into()),
+            RemoteVersionSpan("fred", (0..2).into()),
+        ]), vec![
+            Ok((1..2).into()),
+            Ok((6..8).into()),
+            Ok((4..6).into()),
+            Err(MissingRange { name: "mike", seq_range: (4..6).into() }),
+            Err(MissingRange { name: "fred", seq_range: (0..2).into() }),
+        ]);
+    }
+
     #[test]
     fn remote_versions_can_be_empty() {
         let cg = CausalGraph::new();
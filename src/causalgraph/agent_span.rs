@@ -1,6 +1,6 @@
 // TODO: Consider moving me into agent_assignment/.
 
-use std::ops::Range;
+use core::ops::Range;
 use content_tree::ContentLength;
 use rle::{HasLength, MergableSpan, Searchable, SplitableSpan, SplitableSpanHelpers};
 use crate::AgentId;
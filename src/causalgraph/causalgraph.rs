@@ -1,9 +1,9 @@
 use smallvec::SmallVec;
 use rle::{HasLength, MergableSpan, SplitableSpan};
 use rle::zip::rle_zip;
-use crate::{AgentId, CausalGraph, LV};
+use crate::{AgentId, CausalGraph, DTError, DTRange, LV};
 use crate::causalgraph::*;
-use crate::causalgraph::agent_assignment::remote_ids::{RemoteFrontier, RemoteFrontierOwned};
+use crate::causalgraph::agent_assignment::remote_ids::{RemoteFrontier, RemoteFrontierOwned, RemoteVersion};
 use crate::causalgraph::entry::CGEntry;
 use crate::causalgraph::graph::GraphEntrySimple;
 use crate::causalgraph::agent_span::AgentSpan;
@@ -20,6 +20,10 @@ impl CausalGraph {
         self.agent_assignment.get_or_create_agent_id(name)
     }
 
+    pub fn try_get_or_create_agent_id(&mut self, name: &str) -> Result<AgentId, DTError> {
+        self.agent_assignment.try_get_or_create_agent_id(name)
+    }
+
     pub fn num_agents(&self) -> usize {
         self.agent_assignment.client_data.len()
     }
@@ -145,59 +149,87 @@ impl CausalGraph {
         // 3. There's some overlap. The overlap must be at the start of the entry, because all of
         //    each item's parents must be known.
 
-        match client_data.lv_for_seq.find_index(span.seq_range.last()) {
-            Ok(_idx) => {
-                // If we know the last ID, the entire entry is known. Case 1 - discard and return.
-                (time_start..time_start).into()
-            }
-            Err(idx) => {
-                // idx is the index where the item could be inserted to maintain order.
-                if idx >= 1 { // if idx == 0, there's no overlap anyway.
-                    let prev_entry = &mut client_data.lv_for_seq.0[idx - 1];
-                    let previous_end = prev_entry.end();
-
-                    if previous_end >= span.seq_range.start {
-                        // In this case we need to trim the incoming edit and insert it. But we
-                        // already have the previous edit. We need to extend it.
-                        let actual_len = span.seq_range.end - previous_end;
-                        let time_span: DTRange = (time_start..time_start + actual_len).into();
-                        let new_entry = KVPair(previous_end, time_span);
-
-                        self.agent_assignment.client_with_localtime.push(KVPair(time_start, AgentSpan {
-                            agent: span.agent,
-                            seq_range: (prev_entry.end()..span.seq_range.end).into()
-                        }));
-
-                        if previous_end > span.seq_range.start {
-                            // Case 3 - there's some overlap.
-                            let parents = &[prev_entry.1.last()];
-                            self.version.advance_by_known_run(parents, time_span);
-                            self.graph.push(parents, time_span);
-                        } else {
-                            // I don't like the duplication here but ... ehhh.
-                            self.version.advance_by_known_run(parents, time_span);
-                            self.graph.push(parents, time_span);
-                        }
-
-                        if prev_entry.can_append(&new_entry) {
-                            prev_entry.append(new_entry);
-                        } else {
-                            client_data.lv_for_seq.0.insert(idx, new_entry);
-                        }
-
-                        return time_span;
-                    }
+        if client_data.lv_for_seq.contains_needle(span.seq_range.last()) {
+            // If we know the last ID, the entire entry is known. Case 1 - discard and return.
+            return (time_start..time_start).into();
+        }
+
+        // The last entry before the new span's last ID. If there isn't one, there's no overlap.
+        let prev = client_data.lv_for_seq.entry_at_or_before(span.seq_range.last())
+            .map(|prev_entry| (prev_entry.end(), prev_entry.1.last()));
+
+        if let Some((previous_end, prev_last)) = prev {
+            if previous_end >= span.seq_range.start {
+                // In this case we need to trim the incoming edit and insert it. But we
+                // already have the previous edit. We need to extend it.
+                let actual_len = span.seq_range.end - previous_end;
+                let time_span: DTRange = (time_start..time_start + actual_len).into();
+
+                self.agent_assignment.client_with_localtime.push(KVPair(time_start, AgentSpan {
+                    agent: span.agent,
+                    seq_range: (previous_end..span.seq_range.end).into()
+                }));
+
+                if previous_end > span.seq_range.start {
+                    // Case 3 - there's some overlap.
+                    let parents = &[prev_last];
+                    self.version.advance_by_known_run(parents, time_span);
+                    self.graph.push(parents, time_span);
+                } else {
+                    // I don't like the duplication here but ... ehhh.
+                    self.version.advance_by_known_run(parents, time_span);
+                    self.graph.push(parents, time_span);
                 }
 
-                // We know it can't combine with the previous element.
-                let time_span = (time_start..time_start + span.len()).into();
-                client_data.lv_for_seq.0.insert(idx, KVPair(span.seq_range.start, time_span));
-                self.agent_assignment.client_with_localtime.push(KVPair(time_start, span));
-                self.graph.push(parents, time_span);
-                self.version.advance_by_known_run(parents, time_span);
-                time_span
+                // This is merged into the previous entry if possible.
+                client_data.lv_for_seq.insert(KVPair(previous_end, time_span));
+                return time_span;
+            }
+        }
+
+        // We know it can't combine with the previous element.
+        let time_span = (time_start..time_start + span.len()).into();
+        client_data.lv_for_seq.insert(KVPair(span.seq_range.start, time_span));
+        self.agent_assignment.client_with_localtime.push(KVPair(time_start, span));
+        self.graph.push(parents, time_span);
+        self.version.advance_by_known_run(parents, time_span);
+        time_span
+    }
+
+    /// Variant of [`merge_and_assign`](CausalGraph::merge_and_assign) for spans from untrusted
+    /// peers, which names the agent and parents using remote IDs. The span is checked before
+    /// anything is added to the causal graph, and an error is returned if it's invalid.
+    ///
+    /// Spans can be delivered more than once. Returns the local versions assigned to the part of
+    /// the span which was new. The new part is always at the end of `seq_range`, and the range is
+    /// empty if the whole span was already known.
+    pub fn push_remote_span(&mut self, agent: &str, seq_range: DTRange, parents: &[RemoteVersion]) -> Result<DTRange, DTError> {
+        if seq_range.is_empty() { return Err(DTError::InvalidOperation); }
+        if parents.iter().any(|p| p.0 == agent && p.1 >= seq_range.start && p.1 < seq_range.end) {
+            // The span can't depend on itself.
+            return Err(DTError::InvalidOperation);
+        }
+
+        let parents = self.agent_assignment.try_remote_to_local_frontier(parents.iter().copied())?;
+        let parents = self.graph.find_dominators(parents.as_ref());
+
+        if let Some(agent) = self.agent_assignment.get_agent_id(agent) {
+            // Any sequence numbers we already know must be at the start of the span.
+            let lv_for_seq = &self.agent_assignment.client_data[agent as usize].lv_for_seq;
+            let mut known_end = seq_range.start;
+            while known_end < seq_range.end {
+                let Some(e) = lv_for_seq.find(known_end) else { break; };
+                known_end = e.end();
+            }
+            if known_end < seq_range.end {
+                if let (Err(gap), _) = lv_for_seq.find_sparse(known_end) {
+                    if gap.end < seq_range.end { return Err(DTError::SeqOverlap); }
+                }
             }
         }
+
+        let agent = self.agent_assignment.try_get_or_create_agent_id(agent)?;
+        Ok(self.merge_and_assign(parents.as_ref(), AgentSpan { agent, seq_range }))
     }
 
     /// Iterate through history entries
@@ -252,11 +284,22 @@ impl CausalGraph {
         self.agent_assignment.local_to_remote_frontier_owned(self.version.as_ref())
     }
 
-    #[allow(unused)]
     pub fn iter(&self) -> impl Iterator<Item=CGEntry> + '_ {
         self.iter_range((0..self.len()).into())
     }
 
+    /// Iterate through the entire causal graph, yielding `(versions, parents, agent span)` for each
+    /// run of operations. Each run has a single agent and sequential sequence numbers, and every
+    /// operation after the first in a run has the previous operation as its only parent.
+    ///
+    /// Entries are yielded in local version order. Parents are always assigned lower local versions
+    /// than their children, so this is a topological order - and it's stable for a given causal
+    /// graph. Replaying the entries in order (eg with
+    /// [`merge_and_assign`](CausalGraph::merge_and_assign)) rebuilds the same graph.
+    pub fn iter_full(&self) -> impl Iterator<Item=(DTRange, Frontier, AgentSpan)> + '_ {
+        self.iter().map(|e| (e.time_span(), e.parents, e.span))
+    }
+
     pub fn diff_since(&self, frontier: &[LV]) -> SmallVec<[DTRange; 4]> {
         let mut result = self.diff_since_rev(frontier);
         result.reverse();
@@ -272,7 +315,9 @@ impl CausalGraph {
 
 #[cfg(test)]
 mod tests {
-    use crate::{CausalGraph, DTRange};
+    use crate::{CausalGraph, DTError, DTRange, Frontier};
+    use crate::causalgraph::agent_assignment::remote_ids::RemoteVersion;
+    use crate::causalgraph::agent_span::AgentSpan;
 
     #[test]
     fn merge_and_assign_updates_version() {
@@ -289,4 +334,62 @@ mod tests {
         cg.merge_and_assign(&[4], (agent, 5..15).into());
         cg.dbg_check(true);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn iter_full() {
+        let mut cg = CausalGraph::new();
+        let seph = cg.get_or_create_agent_id("seph");
+        let mike = cg.get_or_create_agent_id("mike");
+        cg.merge_and_assign(&[], (seph, 0..5).into());
+        cg.merge_and_assign(&[2], (mike, 0..3).into());
+        cg.merge_and_assign(&[4], (seph, 5..7).into());
+        cg.merge_and_assign(&[7, 9], (seph, 7..8).into());
+
+        let entries = cg.iter_full().collect::<Vec<_>>();
+        assert_eq!(entries, vec![
+            ((0..5).into(), Frontier::root(), AgentSpan { agent: seph, seq_range: (0..5).into() }),
+            ((5..8).into(), Frontier::new_1(2), AgentSpan { agent: mike, seq_range: (0..3).into() }),
+            ((8..10).into(), Frontier::new_1(4), AgentSpan { agent: seph, seq_range: (5..7).into() }),
+            ((10..11).into(), Frontier::from_sorted(&[7, 9]), AgentSpan { agent: seph, seq_range: (7..8).into() }),
+        ]);
+
+        // Replaying the entries rebuilds the same graph.
+        let mut cg2 = CausalGraph::new();
+        cg2.get_or_create_agent_id("seph");
+        cg2.get_or_create_agent_id("mike");
+        for (_, parents, span) in entries {
+            cg2.merge_and_assign(parents.as_ref(), span);
+        }
+        assert_eq!(cg2, cg);
+    }
+
+    #[test]
+    fn push_remote_span() {
+        let mut cg = CausalGraph::new();
+        assert_eq!(cg.push_remote_span("seph", (0..5).into(), &[]), Ok((0..5).into()));
+        assert_eq!(cg.push_remote_span("mike", (0..3).into(), &[RemoteVersion("seph", 2)]), Ok((5..8).into()));
+
+        // Spans can be delivered again. Only the new part is added.
+        assert_eq!(cg.push_remote_span("seph", (0..5).into(), &[]), Ok((8..8).into()));
+        assert_eq!(cg.push_remote_span("seph", (3..7).into(), &[RemoteVersion("seph", 2)]), Ok((8..10).into()));
+        assert_eq!(cg.version, Frontier::from_sorted(&[7, 9]));
+        cg.dbg_check(true);
+
+        // But a span can't skip over seqs we don't know yet.
+        let before = cg.clone();
+        assert_eq!(cg.push_remote_span("mike", (2..10).into(), &[]), Ok((10..17).into()));
+        assert_eq!(cg.push_remote_span("mike", (20..25).into(), &[]), Ok((17..22).into()));
+        let before2 = cg.clone();
+        assert_eq!(cg.push_remote_span("mike", (15..25).into(), &[]), Err(DTError::SeqOverlap));
+        assert_eq!(cg, before2);
+
+        // Invalid parents are rejected without changing the graph.
+        let mut cg = before;
+        let before = cg.clone();
+        assert!(matches!(cg.push_remote_span("kath", (0..2).into(), &[RemoteVersion("seph", 100)]),
+            Err(DTError::VersionConversion(_))));
+        assert_eq!(cg.push_remote_span("seph", (7..9).into(), &[RemoteVersion("seph", 7)]), Err(DTError::InvalidOperation));
+        assert_eq!(cg, before);
+        assert_eq!(cg.agent_assignment.get_agent_id("kath"), None);
+    }
+}
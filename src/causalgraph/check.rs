@@ -1,4 +1,4 @@
-use crate::CausalGraph;
+use crate::{AgentId, CausalGraph};
 use crate::causalgraph::agent_assignment::AgentAssignment;
 
 impl AgentAssignment {
@@ -7,6 +7,12 @@ impl AgentAssignment {
         // The client_with_localtime should match with the corresponding items in client_data
         self.client_with_localtime.check_packed();
 
+        // The name index should match client_data.
+        assert_eq!(self.agent_ids.len(), self.client_data.len());
+        for (agent, client) in self.client_data.iter().enumerate() {
+            assert_eq!(self.agent_ids.get(&client.name), Some(&(agent as AgentId)));
+        }
+
         for pair in self.client_with_localtime.iter() {
             let expected_range = pair.range();
 
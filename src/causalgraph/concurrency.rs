@@ -0,0 +1,126 @@
+//! Reports of which changes happened concurrently.
+//!
+//! When a user comes back online, an application might want to show them which edits were made
+//! while they were away. [`CausalGraph::concurrency_report`] compares two versions (eg the user's
+//! version and the version of the document they're merging) and lists the operations made in each
+//! version's history since the two diverged, along with the agent which made them.
+
+use alloc::vec::Vec;
+use rle::HasLength;
+use crate::{AgentId, CausalGraph, DTRange, Frontier, LV};
+use crate::rle::KVPair;
+
+pub use crate::causalgraph::graph::tools::DiffFlag;
+
+/// A run of operations by a single agent in a [`ConcurrencyReport`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ConcurrencySpan {
+    /// Whether the operations are only in a's history, only in b's history, or in both.
+    pub flag: DiffFlag,
+    /// The local versions of the operations.
+    pub span: DTRange,
+    /// The agent which made the operations. Use
+    /// [`get_agent_name`](crate::causalgraph::agent_assignment::AgentAssignment::get_agent_name) to
+    /// find its name.
+    pub agent: AgentId,
+    /// The agent's sequence numbers for the operations.
+    pub seq_range: DTRange,
+}
+
+/// The operations made since two versions diverged. Returned by
+/// [`CausalGraph::concurrency_report`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ConcurrencyReport {
+    /// The version the spans are counted from. Every operation in the history of either version
+    /// is either in this version's history, or in one of the spans.
+    pub common_ancestor: Frontier,
+    /// The operations since the common ancestor, in ascending order. Operations in both histories
+    /// are [`Shared`](DiffFlag::Shared) - they're included when the two versions only have a
+    /// common ancestor further back.
+    pub spans: Vec<ConcurrencySpan>,
+}
+
+impl ConcurrencyReport {
+    /// Iterate through the spans with the specified flag.
+    pub fn iter_flag(&self, flag: DiffFlag) -> impl Iterator<Item = &ConcurrencySpan> + '_ {
+        self.spans.iter().filter(move |s| s.flag == flag)
+    }
+
+    /// True if each version contains operations which the other doesn't.
+    pub fn is_concurrent(&self) -> bool {
+        self.iter_flag(DiffFlag::OnlyA).next().is_some()
+            && self.iter_flag(DiffFlag::OnlyB).next().is_some()
+    }
+}
+
+impl CausalGraph {
+    /// Compare the versions `a` and `b`, and find the operations made in each version's history
+    /// since they diverged. See the [module documentation](self) for details.
+    pub fn concurrency_report(&self, a: &[LV], b: &[LV]) -> ConcurrencyReport {
+        let mut found: Vec<(DTRange, DiffFlag)> = Vec::new();
+        let common_ancestor = self.graph.find_conflicting(a, b, |span, flag| {
+            if !span.is_empty() { found.push((span, flag)); }
+        });
+        found.sort_unstable_by_key(|(span, _)| span.start);
+
+        let mut spans: Vec<ConcurrencySpan> = Vec::new();
+        for (span, flag) in found {
+            for KVPair(start, agent_span) in self.agent_assignment.client_with_localtime.iter_range(span) {
+                let len = agent_span.len();
+                if let Some(last) = spans.last_mut() {
+                    if last.flag == flag && last.agent == agent_span.agent
+                        && last.span.end == start && last.seq_range.end == agent_span.seq_range.start {
+                        last.span.end += len;
+                        last.seq_range.end += len;
+                        continue;
+                    }
+                }
+                spans.push(ConcurrencySpan {
+                    flag,
+                    span: (start..start + len).into(),
+                    agent: agent_span.agent,
+                    seq_range: agent_span.seq_range,
+                });
+            }
+        }
+
+        ConcurrencyReport { common_ancestor, spans }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+    use crate::causalgraph::concurrency::{ConcurrencySpan, DiffFlag};
+    use crate::Frontier;
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn edits_while_offline() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let v = oplog.add_insert(seph, 0, "hello");
+
+        // Mike goes offline, and both keep editing.
+        let m = oplog.add_insert_at(mike, &[v], 5, " there");
+        let s = oplog.add_insert_at(seph, &[v], 5, "!");
+        let s = oplog.add_insert_at(seph, &[s], 0, "> ");
+
+        let report = oplog.cg.concurrency_report(&[m], &[s]);
+        assert!(report.is_concurrent());
+        assert_eq!(report.common_ancestor, Frontier::new_1(v));
+        assert_eq!(report.spans, vec![
+            ConcurrencySpan { flag: DiffFlag::OnlyA, span: (5..11).into(), agent: mike, seq_range: (0..6).into() },
+            ConcurrencySpan { flag: DiffFlag::OnlyB, span: (11..14).into(), agent: seph, seq_range: (5..8).into() },
+        ]);
+        assert_eq!(oplog.cg.agent_assignment.get_agent_name(report.spans[0].agent), "mike");
+
+        // Once mike has merged seph's changes, later changes aren't concurrent.
+        let t = oplog.add_insert(seph, 0, "#");
+        let report = oplog.cg.concurrency_report(&[m, s], &[t]);
+        assert!(!report.is_concurrent());
+        assert_eq!(report.common_ancestor.as_ref(), &[m, s]);
+        assert_eq!(report.iter_flag(DiffFlag::OnlyB).map(|s| s.span).collect::<Vec<_>>(), vec![(14..15).into()]);
+    }
+}
@@ -7,6 +7,7 @@
 // This implementation of Eq is mostly designed to help fuzz testing. It is not optimized for
 // performance.
 
+use alloc::vec::Vec;
 use rle::{HasLength, SplitableSpan};
 use rle::zip::rle_zip;
 use crate::{AgentId, CausalGraph, Frontier, LV};
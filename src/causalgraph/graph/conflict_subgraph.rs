@@ -9,10 +9,11 @@
 ///
 /// and it allows callers to add extra fields on each returned item.
 
-use std::cmp::{Ordering, Reverse};
+use alloc::vec::Vec;
+use core::cmp::{Ordering, Reverse};
 use smallvec::{SmallVec, smallvec};
-use std::collections::BinaryHeap;
-use std::fmt::Debug;
+use alloc::collections::BinaryHeap;
+use core::fmt::Debug;
 use rle::{AppendRle, ReverseSpan};
 use crate::causalgraph::graph::Graph;
 use crate::causalgraph::graph::tools::DiffFlag;
@@ -7,7 +7,7 @@ mod check;
 mod subgraph;
 mod simple;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "testkit"))]
 pub mod random_graphs;
 pub(crate) mod conflict_subgraph;
 
@@ -8,7 +8,10 @@ use crate::causalgraph::graph::Graph;
 use crate::{AgentId, CausalGraph, DTRange, Frontier};
 use crate::list_fuzzer_tools::choose_2;
 
-pub(crate) fn with_random_cgs<F: FnMut((usize, usize), &CausalGraph, &[Frontier])>(seed: u64, iterations: (usize, usize), mut f: F) {
+/// Generate random causal graphs with 3 agents. For each of `iterations.0` seeds (starting at
+/// `seed`), the graph is grown `iterations.1` times. After each step, `f` is called with
+/// `(seed index, step)`, the graph and the current frontier of each agent.
+pub fn with_random_cgs<F: FnMut((usize, usize), &CausalGraph, &[Frontier])>(seed: u64, iterations: (usize, usize), mut f: F) {
     for outer in 0..iterations.0 {
         let seed_here = seed + outer as u64;
         let mut rng = SmallRng::seed_from_u64(seed_here);
@@ -1,6 +1,6 @@
 //! TODO: This code is not currently used. Use it or remove it!
 
-use std::collections::BinaryHeap;
+use alloc::collections::BinaryHeap;
 use smallvec::smallvec;
 use crate::*;
 use crate::causalgraph::graph::tools::DiffFlag;
@@ -1,4 +1,4 @@
-use std::collections::BinaryHeap;
+use alloc::collections::BinaryHeap;
 use crate::causalgraph::graph::{Graph, GraphEntrySimple};
 use crate::{DTRange, Frontier, LV};
 use crate::rle::{RleKeyedAndSplitable, RleVec};
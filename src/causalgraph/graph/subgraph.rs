@@ -1,4 +1,5 @@
-use std::collections::BinaryHeap;
+use alloc::vec::Vec;
+use alloc::collections::BinaryHeap;
 use smallvec::{SmallVec, smallvec};
 use rle::{MergeableIterator, MergeIter};
 use crate::causalgraph::graph::{Graph, GraphEntryInternal};
@@ -1,8 +1,8 @@
 //! This file contains tools to manage the document as a time dag. Specifically, tools to tell us
 //! about branches, find diffs and move between branches.
 
-use std::cmp::Ordering;
-use std::collections::BinaryHeap;
+use core::cmp::Ordering;
+use alloc::collections::BinaryHeap;
 use smallvec::{smallvec, SmallVec};
 use rle::{AppendRle, SplitableSpan};
 
@@ -17,9 +17,10 @@ use serde::Serialize;
 
 // Diff function needs to tag each entry in the queue based on whether its part of a's history or
 // b's history or both, and do so without changing the sort order for the heap.
+/// Which of two versions' histories a span of operations is in.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 #[cfg_attr(feature = "serde", derive(Serialize))]
-pub(crate) enum DiffFlag { OnlyA, OnlyB, Shared }
+pub enum DiffFlag { OnlyA, OnlyB, Shared }
 
 impl Graph {
     fn shadow_of(&self, time: LV) -> LV {
@@ -196,6 +197,31 @@ impl Graph {
         result
     }
 
+    /// Find the frontier of the versions in a's history which remain after removing the
+    /// (ascending) spans in `remove`. The removed spans must contain all of their descendants in
+    /// a's history - as they do when they come from a [`diff`](Graph::diff).
+    pub(crate) fn frontier_without(&self, a: &[LV], remove: &[DTRange]) -> Frontier {
+        let removed = |v: &LV| remove.iter().any(|r| r.contains(*v));
+
+        let mut candidates: SmallVec<[LV; 4]> = a.iter().copied()
+            .filter(|v| !removed(v))
+            .collect();
+        for span in remove {
+            for e in self.iter_range(*span) {
+                candidates.extend(e.parents.iter().copied().filter(|v| !removed(v)));
+            }
+        }
+
+        self.find_dominators(&candidates)
+    }
+
+    /// Find the greatest common ancestor of versions a and b. This is the version containing
+    /// every operation which is in the history of both a and b.
+    pub fn common_ancestor(&self, a: &[LV], b: &[LV]) -> Frontier {
+        let (only_a, _) = self.diff(a, b);
+        self.frontier_without(a, &only_a)
+    }
+
     /// Returns (spans only in a, spans only in b). Spans are in reverse (descending) order.
     ///
     /// Also find which operation is the greatest common ancestor.
@@ -0,0 +1,217 @@
+//! Content-addressed version hashes.
+//!
+//! Local versions (LVs) are only meaningful inside a single oplog, and remote versions need the
+//! agent table to be shared before they can be interpreted. Version hashes are a transport
+//! independent alternative, similar to git's commit IDs. The hash of each version is computed from:
+//!
+//! - The (sorted) hashes of its parents
+//! - The agent name and sequence number which created it
+//! - (Optionally) some bytes describing the operation itself
+//!
+//! Because parent hashes are sorted before being hashed, two peers which store the same operations
+//! in a different local order will still compute identical hashes.
+
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
+use sha2::{Digest, Sha256};
+use smallvec::SmallVec;
+use rle::HasLength;
+use crate::{CausalGraph, LV};
+use crate::rle::KVPair;
+
+/// A SHA-256 hash naming a single version.
+pub type VersionHash = [u8; 32];
+
+/// A computed set of version hashes for every version in a causal graph, along with a reverse
+/// index from hash to local version.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct VersionHashes {
+    /// The hash of each local version, indexed by LV.
+    hashes: Vec<VersionHash>,
+    lookup: BTreeMap<VersionHash, LV>,
+}
+
+fn hash_frontier_hashes(hashes: &mut SmallVec<[VersionHash; 2]>, hasher: &mut Sha256) {
+    // Frontiers are sorted by local version, which isn't stable across peers.
+    hashes.sort_unstable();
+    hasher.update((hashes.len() as u64).to_le_bytes());
+    for h in hashes.iter() {
+        hasher.update(h);
+    }
+}
+
+impl VersionHashes {
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Get the hash of the specified local version, or None if the version is out of range.
+    pub fn hash_of(&self, lv: LV) -> Option<VersionHash> {
+        self.hashes.get(lv).copied()
+    }
+
+    /// Look up the local version with the specified hash.
+    pub fn lv_of(&self, hash: &VersionHash) -> Option<LV> {
+        self.lookup.get(hash).copied()
+    }
+
+    /// Get a single hash naming the entire frontier. The root frontier (`[]`) has a valid hash
+    /// too.
+    ///
+    /// Panics if any version in the frontier is out of range.
+    pub fn frontier_hash(&self, frontier: &[LV]) -> VersionHash {
+        let mut hashes: SmallVec<[VersionHash; 2]> = frontier.iter()
+            .map(|v| self.hashes[*v])
+            .collect();
+
+        let mut hasher = Sha256::new();
+        hasher.update(b"frontier");
+        hash_frontier_hashes(&mut hashes, &mut hasher);
+        hasher.finalize().into()
+    }
+
+    /// Convert a list of version hashes back into local versions. Returns None if any of the
+    /// hashes are unknown.
+    pub fn try_hashes_to_frontier(&self, hashes: &[VersionHash]) -> Option<crate::Frontier> {
+        hashes.iter()
+            .map(|h| self.lv_of(h))
+            .collect()
+    }
+}
+
+impl CausalGraph {
+    /// Compute version hashes for every version in the causal graph. `op_bytes` is called once for
+    /// each version (in LV order) and can append extra bytes describing the operation at that
+    /// version into the passed buffer.
+    pub(crate) fn version_hashes_with<F: FnMut(LV, &mut Vec<u8>)>(&self, mut op_bytes: F) -> VersionHashes {
+        let len = self.len();
+        let mut result = VersionHashes {
+            hashes: Vec::with_capacity(len),
+            lookup: BTreeMap::new(),
+        };
+
+        let mut buf = Vec::new();
+        for KVPair(start, span) in self.agent_assignment.client_with_localtime.iter() {
+            let name = self.agent_assignment.get_agent_name(span.agent);
+
+            for offset in 0..span.len() {
+                let lv = *start + offset;
+                let seq = span.seq_range.start + offset;
+
+                let mut parent_hashes: SmallVec<[VersionHash; 2]> = self.graph.with_parents(lv, |parents| {
+                    parents.iter().map(|p| result.hashes[*p]).collect()
+                });
+
+                let mut hasher = Sha256::new();
+                hash_frontier_hashes(&mut parent_hashes, &mut hasher);
+                hasher.update((name.len() as u64).to_le_bytes());
+                hasher.update(name.as_bytes());
+                hasher.update((seq as u64).to_le_bytes());
+
+                buf.clear();
+                op_bytes(lv, &mut buf);
+                hasher.update((buf.len() as u64).to_le_bytes());
+                hasher.update(&buf);
+
+                let hash: VersionHash = hasher.finalize().into();
+                result.hashes.push(hash);
+                result.lookup.insert(hash, lv);
+            }
+        }
+
+        result
+    }
+
+    /// Compute version hashes for every version in the causal graph.
+    ///
+    /// The causal graph doesn't store operation contents, so these hashes only cover the graph
+    /// structure and version IDs. Use [`ListOpLog::version_hashes`](crate::list::ListOpLog::version_hashes)
+    /// to also hash each operation.
+    pub fn version_hashes(&self) -> VersionHashes {
+        self.version_hashes_with(|_, _| {})
+    }
+
+    /// Get the content-addressed hash of a single local version.
+    ///
+    /// This needs to hash all the versions in history up to lv. If you're looking up more than one
+    /// hash, call [`version_hashes`](CausalGraph::version_hashes) once instead.
+    pub fn hash_of(&self, lv: LV) -> VersionHash {
+        self.version_hashes().hash_of(lv).unwrap()
+    }
+
+    /// Get the content-addressed hash of the passed frontier.
+    pub fn frontier_hash(&self, frontier: &[LV]) -> VersionHash {
+        self.version_hashes().frontier_hash(frontier)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::CausalGraph;
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn hashes_ignore_local_order() {
+        // Two concurrent changes, added in a different order on each peer.
+        let mut cg1 = CausalGraph::new();
+        let a = cg1.get_or_create_agent_id("a");
+        let b = cg1.get_or_create_agent_id("b");
+        cg1.assign_local_op_with_parents(&[], a, 2);
+        cg1.assign_local_op_with_parents(&[], b, 3);
+        cg1.assign_local_op_with_parents(&[1, 4], a, 1);
+
+        let mut cg2 = CausalGraph::new();
+        let b = cg2.get_or_create_agent_id("b");
+        let a = cg2.get_or_create_agent_id("a");
+        cg2.assign_local_op_with_parents(&[], b, 3);
+        cg2.assign_local_op_with_parents(&[], a, 2);
+        cg2.assign_local_op_with_parents(&[2, 4], a, 1);
+
+        let h1 = cg1.version_hashes();
+        let h2 = cg2.version_hashes();
+        assert_eq!(h1.hash_of(0), h2.hash_of(3));
+        assert_eq!(h1.hash_of(4), h2.hash_of(2));
+        assert_eq!(h1.hash_of(5), h2.hash_of(5));
+        assert_eq!(h1.frontier_hash(&[1, 4]), h2.frontier_hash(&[2, 4]));
+        assert_ne!(h1.frontier_hash(&[1]), h1.frontier_hash(&[1, 4]));
+
+        for lv in 0..cg1.len() {
+            assert_eq!(h1.lv_of(&cg1.hash_of(lv)), Some(lv));
+        }
+        assert_eq!(h1.try_hashes_to_frontier(&[cg1.hash_of(4)]).unwrap().as_ref(), &[4]);
+    }
+
+    #[test]
+    fn op_content_changes_hash() {
+        let mut oplog1 = ListOpLog::new();
+        let seph = oplog1.get_or_create_agent_id("seph");
+        oplog1.add_insert(seph, 0, "hi there");
+        oplog1.add_delete_without_content(seph, 2..5);
+
+        let mut oplog2 = ListOpLog::new();
+        let seph = oplog2.get_or_create_agent_id("seph");
+        oplog2.add_insert(seph, 0, "hi");
+        oplog2.add_insert(seph, 2, " there");
+        oplog2.add_delete_without_content(seph, 2..5);
+
+        // RLE boundaries don't affect the result.
+        assert_eq!(oplog1.version_hashes(), oplog2.version_hashes());
+
+        let mut oplog3 = ListOpLog::new();
+        let seph = oplog3.get_or_create_agent_id("seph");
+        oplog3.add_insert(seph, 0, "hi thar");
+        oplog3.add_delete_without_content(seph, 2..6);
+
+        let h1 = oplog1.version_hashes();
+        let h3 = oplog3.version_hashes();
+        assert_eq!(h1.hash_of(0), h3.hash_of(0));
+        assert_ne!(h1.hash_of(5), h3.hash_of(5));
+
+        // But the graph-only hashes don't know about the content.
+        assert_eq!(oplog1.cg.hash_of(5), oplog3.cg.hash_of(5));
+    }
+}
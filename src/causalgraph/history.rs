@@ -0,0 +1,111 @@
+//! A read-only view of the causal graph, for tools which need to reason about the history of a
+//! document without reaching into the graph's internals.
+//!
+//! ```
+//! use diamond_types::list::ListOpLog;
+//! let mut oplog = ListOpLog::new();
+//! let seph = oplog.get_or_create_agent_id("seph");
+//! let mike = oplog.get_or_create_agent_id("mike");
+//! let a = oplog.add_insert(seph, 0, "aaa"); // 0..3
+//! let b = oplog.add_insert_at(mike, &[a], 3, "bbb"); // 3..6, after a
+//! let c = oplog.add_insert_at(seph, &[a], 0, "c"); // 6, concurrent with b
+//!
+//! let history = oplog.cg.history();
+//! assert!(history.is_ancestor(a, b));
+//! assert!(!history.is_ancestor(b, c));
+//! assert_eq!(history.common_ancestor(&[&[b], &[c]]).as_ref(), &[a]);
+//! assert_eq!(history.iter_parents(c).collect::<Vec<_>>(), vec![a]);
+//! ```
+
+use smallvec::SmallVec;
+use crate::{CausalGraph, DTRange, Frontier, LV};
+use crate::causalgraph::graph::Graph;
+
+/// A read-only view of the causal graph. Create one with [`CausalGraph::history`].
+#[derive(Debug, Clone, Copy)]
+pub struct History<'a>(&'a Graph);
+
+impl CausalGraph {
+    /// Get a read-only view of the causal graph, for querying relationships between versions.
+    pub fn history(&self) -> History<'_> {
+        History(&self.graph)
+    }
+}
+
+impl<'a> History<'a> {
+    /// Returns true if the operation at `a` is in the history of `b`. Every version is considered
+    /// to be its own ancestor.
+    pub fn is_ancestor(&self, a: LV, b: LV) -> bool {
+        self.0.frontier_contains_version(&[b], a)
+    }
+
+    /// Returns true if the operation at `v` is in the history of the named frontier.
+    pub fn frontier_contains(&self, frontier: &[LV], v: LV) -> bool {
+        self.0.frontier_contains_version(frontier, v)
+    }
+
+    /// Find the greatest common ancestor of all the passed frontiers. That is, the version
+    /// containing exactly the operations which are in the history of every frontier.
+    ///
+    /// Returns the root version if no frontiers are passed.
+    pub fn common_ancestor(&self, frontiers: &[&[LV]]) -> Frontier {
+        let Some((first, rest)) = frontiers.split_first() else {
+            return Frontier::root();
+        };
+
+        rest.iter().fold(Frontier::from(*first), |acc, f| {
+            self.0.common_ancestor(acc.as_ref(), f)
+        })
+    }
+
+    /// Returns (spans only in a, spans only in b). Spans are in ascending order.
+    pub fn diff(&self, a: &[LV], b: &[LV]) -> (SmallVec<[DTRange; 4]>, SmallVec<[DTRange; 4]>) {
+        self.0.diff(a, b)
+    }
+
+    /// Find the smallest frontier which contains all the passed versions.
+    pub fn dominators(&self, versions: &[LV]) -> Frontier {
+        self.0.find_dominators(versions)
+    }
+
+    /// Iterate through the direct parents of the operation at `v`, in ascending order.
+    pub fn iter_parents(&self, v: LV) -> impl Iterator<Item = LV> {
+        self.0.parents_at_version(v).into_iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::causalgraph::graph::{Graph, GraphEntrySimple};
+    use crate::causalgraph::history::History;
+    use crate::Frontier;
+
+    #[test]
+    fn common_ancestor() {
+        // 0 and 1 are concurrent. 2 and 3 both merge them, and 4 follows 2.
+        let graph = Graph::from_simple_items(&[
+            GraphEntrySimple { span: (0..1).into(), parents: Frontier::root() },
+            GraphEntrySimple { span: (1..2).into(), parents: Frontier::root() },
+            GraphEntrySimple { span: (2..3).into(), parents: Frontier::from_sorted(&[0, 1]) },
+            GraphEntrySimple { span: (3..4).into(), parents: Frontier::from_sorted(&[0, 1]) },
+            GraphEntrySimple { span: (4..5).into(), parents: Frontier::from_sorted(&[2]) },
+        ]);
+        let history = History(&graph);
+
+        assert!(history.is_ancestor(0, 4));
+        assert!(history.is_ancestor(4, 4));
+        assert!(!history.is_ancestor(3, 4));
+        assert!(history.frontier_contains(&[3, 4], 1));
+
+        assert_eq!(history.common_ancestor(&[&[4], &[3]]), Frontier::from_sorted(&[0, 1]));
+        assert_eq!(history.common_ancestor(&[&[4], &[2]]), Frontier::new_1(2));
+        assert_eq!(history.common_ancestor(&[&[4], &[3], &[1]]), Frontier::new_1(1));
+        assert_eq!(history.common_ancestor(&[&[0], &[1]]), Frontier::root());
+        assert_eq!(history.common_ancestor(&[&[3, 4]]), Frontier::from_sorted(&[3, 4]));
+        assert_eq!(history.common_ancestor(&[]), Frontier::root());
+
+        assert_eq!(history.dominators(&[0, 1, 2]), Frontier::new_1(2));
+        assert_eq!(history.iter_parents(3).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(history.iter_parents(0).count(), 0);
+    }
+}
@@ -3,6 +3,7 @@
 use crate::{DTRange, Frontier, KVPair, Graph};
 use crate::causalgraph::agent_assignment::AgentAssignment;
 
+#[cfg(feature = "std")]
 pub(crate) mod storage;
 mod causalgraph;
 mod check;
@@ -10,6 +11,8 @@ pub mod graph;
 mod eq;
 pub mod entry;
 pub mod summary;
+pub mod history;
+pub mod concurrency;
 pub mod agent_span;
 pub mod agent_assignment;
 
@@ -17,6 +20,8 @@ pub mod agent_assignment;
 mod enc_fuzzer;
 #[cfg(feature = "dot_export")]
 pub mod dot;
+#[cfg(feature = "version_hashes")]
+pub mod hash;
 
 #[derive(Clone, Debug, Default)]
 pub struct CausalGraph {
@@ -1,3 +1,4 @@
+use alloc::vec::Vec;
 use smallvec::{SmallVec, smallvec};
 use smartstring::alias::String as SmartString;
 use crate::{CausalGraph, DTRange, Frontier, LV};
@@ -7,6 +8,10 @@ use rle::{HasLength, MergeableIterator, SplitableSpanHelpers};
 use serde::{Serialize, Deserialize};
 use crate::causalgraph::agent_assignment::AgentAssignment;
 use crate::rle::RleSpanHelpers;
+use crate::encoding::bufparser::BufParser;
+use crate::encoding::parseerror::ParseError;
+use crate::encoding::tools::push_str;
+use crate::encoding::varint::push_usize;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -31,7 +36,8 @@ pub struct VersionSummaryFlat(Vec<(SmartString, usize)>);
 // Serialize as {name1: [[start, end], [start, end], ..], name2: ...}.
 #[cfg(feature = "serde")]
 mod serde_encoding {
-    use std::fmt::Formatter;
+    use alloc::vec::Vec;
+    use core::fmt::Formatter;
     use serde::ser::SerializeMap;
     use serde::{Deserialize, Deserializer, Serialize, Serializer};
     use serde::de::{MapAccess, Visitor};
@@ -55,7 +61,7 @@ mod serde_encoding {
     impl<'de> Visitor<'de> for VSVisitor {
         type Value = VersionSummary;
 
-        fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        fn expecting(&self, formatter: &mut Formatter) -> core::fmt::Result {
             formatter.write_str("A version summary map")
         }
 
@@ -93,7 +99,7 @@ mod serde_encoding {
     impl<'de> Visitor<'de> for VSVisitorFlat {
         type Value = VersionSummaryFlat;
 
-        fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        fn expecting(&self, formatter: &mut Formatter) -> core::fmt::Result {
             formatter.write_str("A flat version summary")
         }
 
@@ -115,6 +121,40 @@ mod serde_encoding {
 }
 
 
+impl VersionSummary {
+    /// Write the summary in a compact binary form. Read it back with
+    /// [`decode`](VersionSummary::decode).
+    pub(crate) fn encode_into(&self, into: &mut Vec<u8>) {
+        push_usize(into, self.0.len());
+        for e in &self.0 {
+            push_str(into, &e.name);
+            push_usize(into, e.seq_ranges.len());
+            for r in &e.seq_ranges {
+                push_usize(into, r.start);
+                push_usize(into, r.len());
+            }
+        }
+    }
+
+    pub(crate) fn decode(reader: &mut BufParser) -> Result<Self, ParseError> {
+        let num_entries = reader.next_usize()?;
+        let mut entries = Vec::new();
+        for _ in 0..num_entries {
+            let name = reader.next_str()?.into();
+            let num_ranges = reader.next_usize()?;
+            let mut seq_ranges = SmallVec::new();
+            for _ in 0..num_ranges {
+                let start = reader.next_usize()?;
+                let len = reader.next_usize()?;
+                let end = start.checked_add(len).ok_or(ParseError::InvalidLength)?;
+                seq_ranges.push((start..end).into());
+            }
+            entries.push(VSEntry { name, seq_ranges });
+        }
+        Ok(VersionSummary(entries))
+    }
+}
+
 impl AgentAssignment {
     pub fn summarize_versions(&self) -> VersionSummary {
         VersionSummary(self.client_data.iter().filter_map(|c| {
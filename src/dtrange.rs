@@ -1,9 +1,9 @@
-use std::cmp::Ordering;
-use std::collections::Bound;
-use std::fmt::{Debug, DebugStruct, Formatter};
+use core::cmp::Ordering;
+use core::ops::Bound;
+use core::fmt::{Debug, DebugStruct, Formatter};
 use rle::{HasLength, HasRleKey, MergableSpan, Searchable, SplitableSpanHelpers};
 
-use std::ops::{Range, RangeBounds};
+use core::ops::{Range, RangeBounds};
 use crate::LV;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
@@ -207,7 +207,7 @@ pub(crate) fn is_underwater(time: LV) -> bool {
 // struct RootTime;
 
 // impl Debug for RootTime {
-//     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+//     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
 //         f.write_str("ROOT")
 //     }
 // }
@@ -225,13 +225,13 @@ pub(crate) fn debug_time(fmt: &mut DebugStruct, name: &str, val: LV) {
 struct Underwater(usize);
 
 impl Debug for Underwater {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         f.write_fmt(format_args!("Underwater({})", self.0))
     }
 }
 
 impl Debug for DTRange {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         if self.is_empty() {
             write!(f, "(EMPTY)")?;
         } else {
@@ -1,4 +1,4 @@
-use std::mem::size_of;
+use core::mem::size_of;
 use crate::encoding::parseerror::ParseError;
 use crate::encoding::varint::*;
 
@@ -119,8 +119,8 @@ impl<'a> BufParser<'a> {
         if len > self.0.len() { return Err(ParseError::InvalidLength); }
 
         let bytes = self.next_n_bytes(len)?;
-        // std::str::from_utf8(bytes).map_err(InvalidUTF8)
-        std::str::from_utf8(bytes).map_err(|_| ParseError::InvalidUTF8)
+        // core::str::from_utf8(bytes).map_err(InvalidUTF8)
+        core::str::from_utf8(bytes).map_err(|_| ParseError::InvalidUTF8)
     }
 
     // /// Read the next string thats encoded in this content chunk
@@ -134,6 +134,6 @@ impl<'a> BufParser<'a> {
     //     // if len > self.0.len() {
     //     //     return Err(InvalidLength);
     //     // }
-    //     std::str::from_utf8(self.0).map_err(|_| ParseError::InvalidUTF8)
+    //     core::str::from_utf8(self.0).map_err(|_| ParseError::InvalidUTF8)
     // }
 }
\ No newline at end of file
@@ -1,3 +1,4 @@
+use alloc::vec::Vec;
 use rle::HasLength;
 use crate::{AgentId, CausalGraph, DTRange, KVPair, Frontier, LV};
 use crate::causalgraph::agent_assignment::{AgentAssignment};
@@ -1,3 +1,4 @@
+use alloc::vec::Vec;
 use rle::HasLength;
 use crate::{AgentId, DTRange, KVPair, RleVec, LV};
 use crate::causalgraph::agent_assignment::ClientData;
@@ -1,5 +1,5 @@
-use std::marker::PhantomData;
-use std::mem::replace;
+use core::marker::PhantomData;
+use core::mem::replace;
 use rle::MergableSpan;
 use num_enum::TryFromPrimitive;
 
@@ -112,6 +112,8 @@ impl<S: MergableSpan, F: FnMut(S, &mut ())> Merger<S, F, ()> {
 
 impl<S: MergableSpan, F: FnMut(S, &mut Ctx), Ctx> Drop for Merger<S, F, Ctx> {
     fn drop(&mut self) {
+        // Without std we can't tell if we're already unwinding, so the check is skipped.
+        #[cfg(feature = "std")]
         if self.last.is_some() && !std::thread::panicking() {
             panic!("Merger dropped with unprocessed data");
         }
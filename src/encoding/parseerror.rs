@@ -1,6 +1,7 @@
-use std::error::Error;
-use std::fmt::{Display, Formatter};
+use core::error::Error;
+use core::fmt::{Display, Formatter};
 use crate::causalgraph::agent_assignment::remote_ids::VersionConversionError;
+use crate::list::encoding::FormatFeatures;
 
 
 // #[derive(Debug)]
@@ -17,11 +18,16 @@ pub enum ParseError {
     InvalidMagic,
     UnsupportedProtocolVersion,
     DocIdMismatch,
+    /// The data orders concurrent inserts using a different [`TieBreak`](crate::list::tie_break::TieBreak)
+    /// strategy than the oplog it was merged into.
+    TieBreakMismatch,
     BaseVersionUnknown,
     UnknownChunk,
     LZ4DecoderNeeded,
     LZ4DecompressionError, // I'd wrap it but lz4_flex errors don't implement any traits
     // LZ4DecompressionError(lz4_flex::block::DecompressError),
+    ZstdDecoderNeeded,
+    ZstdDecompressionError,
     CompressedDataMissing,
     InvalidChunkHeader,
     MissingChunk(u32),
@@ -49,10 +55,23 @@ pub enum ParseError {
     /// I'd like to explicitly support this case, and allow the oplog to contain a somewhat- sparse
     /// set of data, and load more as needed.
     DataMissing,
+
+    /// The file needs format features this version of diamond types doesn't support. The
+    /// unsupported features are listed.
+    UnsupportedFeatures(FormatFeatures),
+
+    /// The data's content is encrypted and the oplog's isn't, or the other way around. See
+    /// `list::encryption` (behind the `encryption` feature).
+    EncryptionMismatch,
+
+    /// Encrypted content is missing its authentication tag, or doesn't match it. The content was
+    /// changed (or the wrong key was used). See `list::encryption` (behind the `encryption`
+    /// feature).
+    InvalidContentTag,
 }
 
 impl Display for ParseError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "ParseError {:?}", self)
     }
 }
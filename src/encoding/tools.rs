@@ -1,3 +1,4 @@
+use alloc::vec::Vec;
 use crate::encoding::ChunkType;
 use bumpalo::collections::vec::Vec as BumpVec;
 use crate::encoding::varint::{push_u32, push_u64, push_usize, try_push_u32, try_push_u64, try_push_usize};
@@ -108,10 +109,13 @@ pub(crate) fn push_chunk<V: TryExtendFromSlice>(into: &mut V, chunk_type: ChunkT
     Ok(())
 }
 
+/// The checksum algorithm used in encoded files. This is crc32c.
+pub(crate) const CHECKSUM: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+
 pub fn calc_checksum(data: &[u8]) -> u32 {
     // This is crc32c. Using the crc library because the resulting binary size is much smaller.
     // let checksum = crc32c::crc32c(&result);
-    crc::Crc::<u32>::new(&crc::CRC_32_ISCSI).checksum(data)
+    CHECKSUM.checksum(data)
 }
 
 /// A DTSerializable object knows how to turn itself into a byte array.
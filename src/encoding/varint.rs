@@ -15,7 +15,7 @@
 //!
 //! ... And so on.
 
-use std::mem::size_of;
+use core::mem::size_of;
 use crate::encoding::parseerror::ParseError;
 use crate::encoding::tools::{ExtendFromSlice, TryExtendFromSlice};
 
@@ -230,7 +230,7 @@ fn decode_prefix_varint_u32_unroll(buf: &[u8]) -> Result<(u32, usize), ParseErro
         Ok((val, 3))
     } else if b0 <= 0b1110_1111 {
         if buf.len() < 4 { return Err(ParseError::UnexpectedEOF); }
-        let n = unsafe { std::ptr::read_unaligned(&buf[0] as *const u8 as *const u32) };
+        let n = unsafe { core::ptr::read_unaligned(&buf[0] as *const u8 as *const u32) };
         let val = u32::from_be(n) - (0b1110_0000 << 24) + ENC_3_U32;
         Ok((val, 4))
     } else {
@@ -273,7 +273,7 @@ pub fn decode_prefix_varint_u64_unroll(buf: &[u8]) -> Result<(u64, usize), Parse
         Ok((val, 3))
     } else if b0 <= 0b1110_1111 {
         if buf.len() < 4 { return Err(ParseError::UnexpectedEOF); }
-        let n = unsafe { std::ptr::read_unaligned(&buf[0] as *const u8 as *const u32) };
+        let n = unsafe { core::ptr::read_unaligned(&buf[0] as *const u8 as *const u32) };
         let val = u32::from_be(n) as u64 - (0b1110_0000 << 24)
             + ENC_3_U64;
         Ok((val, 4))
@@ -313,14 +313,14 @@ pub fn decode_prefix_varint_u64_unroll(buf: &[u8]) -> Result<(u64, usize), Parse
         Ok((val, 7))
     } else if b0 == 0b1111_1110 {
         if buf.len() < 8 { return Err(ParseError::UnexpectedEOF); }
-        let n = unsafe { std::ptr::read_unaligned(&buf[0] as *const u8 as *const u64) };
+        let n = unsafe { core::ptr::read_unaligned(&buf[0] as *const u8 as *const u64) };
 
         let val = u64::from_be(n) - (0b1111_1110 << 56)
             + ENC_7_U64;
         Ok((val, 8))
     } else {
         if buf.len() < 9 { return Err(ParseError::UnexpectedEOF); }
-        let n = unsafe { std::ptr::read_unaligned(&buf[1] as *const u8 as *const u64) };
+        let n = unsafe { core::ptr::read_unaligned(&buf[1] as *const u8 as *const u64) };
 
         let val = u64::from_be(n) + ENC_8_U64;
         Ok((val, 9))
@@ -346,7 +346,7 @@ pub(crate) fn decode_prefix_varint_u64_unroll_flat(buf: &U64VarintArr) -> (u64,
             + ENC_2_U64;
         (val, 3)
     } else if b0 <= 0b1110_1111 {
-        let n = unsafe { std::ptr::read_unaligned(&buf[0] as *const u8 as *const u32) };
+        let n = unsafe { core::ptr::read_unaligned(&buf[0] as *const u8 as *const u32) };
         let val = u32::from_be(n) as u64 - (0b1110_0000 << 24)
             + ENC_3_U64;
         (val, 4)
@@ -354,7 +354,7 @@ pub(crate) fn decode_prefix_varint_u64_unroll_flat(buf: &U64VarintArr) -> (u64,
 
         // Here we're really parsing a u64 big endian value. The optimizer is clever enough to
         // figure that out and optimize this code with a read + byteswap.
-        let n = unsafe { std::ptr::read_unaligned(&buf[1] as *const u8 as *const u32) };
+        let n = unsafe { core::ptr::read_unaligned(&buf[1] as *const u8 as *const u32) };
 
         let val: u64 = ((b0 as u64 & 0b0000_0111) << 32)
             + u32::from_be(n) as u64
@@ -385,13 +385,13 @@ pub(crate) fn decode_prefix_varint_u64_unroll_flat(buf: &U64VarintArr) -> (u64,
             + ENC_6_U64;
         (val, 7)
     } else if b0 == 0b1111_1110 {
-        let n = unsafe { std::ptr::read_unaligned(&buf[0] as *const u8 as *const u64) };
+        let n = unsafe { core::ptr::read_unaligned(&buf[0] as *const u8 as *const u64) };
 
         let val = u64::from_be(n) - (0b1111_1110 << 56)
             + ENC_7_U64;
         (val, 8)
     } else {
-        let n = unsafe { std::ptr::read_unaligned(&buf[1] as *const u8 as *const u64) };
+        let n = unsafe { core::ptr::read_unaligned(&buf[1] as *const u8 as *const u64) };
 
         let val = u64::from_be(n) + ENC_8_U64;
         (val, 9)
@@ -0,0 +1,73 @@
+use core::error::Error;
+use core::fmt::{Display, Formatter};
+use crate::causalgraph::agent_assignment::remote_ids::VersionConversionError;
+use crate::encoding::parseerror::ParseError;
+use crate::list::encoding::PolicyViolation;
+
+/// Errors returned when ingesting changes from another peer.
+///
+/// Data from the network shouldn't be trusted. Methods which accept remote changes return these
+/// errors instead of panicking, so a malformed or malicious patch can't bring down the process
+/// which receives it.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[non_exhaustive]
+pub enum DTError {
+    /// The agent name is reserved ("ROOT"), or it's too long. See
+    /// [`MAX_AGENT_NAME_LENGTH`](crate::causalgraph::agent_assignment::MAX_AGENT_NAME_LENGTH).
+    InvalidAgentName,
+    /// The agent ID hasn't been assigned in this document.
+    UnknownAgent,
+    /// The change names a version (eg, as a parent) which isn't in the causal graph.
+    UnknownVersion,
+    /// The operation's content doesn't match its length, or the operation is empty.
+    InvalidOperation,
+    /// A remote version couldn't be converted to a local version.
+    VersionConversion(VersionConversionError),
+    /// Binary or JSON data couldn't be parsed.
+    Parse(ParseError),
+    /// The operation would need more resources than its configured limits allow. See
+    /// [`MergeLimits`](crate::list::MergeLimits).
+    ResourceExhausted,
+    /// A patch's range is backwards, or extends past the end of the document. `index` is the
+    /// position of the first invalid patch in the list.
+    PatchOutOfBounds { index: usize },
+    /// The path doesn't name a value in the document, or it names the wrong kind of value. See
+    /// [`JsonDoc`](crate::json::JsonDoc).
+    InvalidPath,
+    /// The row doesn't exist (or has been deleted). See
+    /// [`RecordSet`](crate::record_set::RecordSet).
+    UnknownRow,
+    /// The column isn't in the record set's schema, or it has a different type.
+    InvalidColumn,
+    /// The agent has already been linked to a different agent, or the link would make a cycle. See
+    /// [`agent_links`](crate::list::agent_links).
+    InvalidAgentLink,
+    /// Some of a span's sequence numbers are already known, but they aren't at the start of the
+    /// span. Each agent's changes must be delivered in order.
+    SeqOverlap,
+    /// An operation inserts past the end of the document, or deletes content which doesn't exist
+    /// at the operation's parents.
+    OpOutOfBounds,
+    /// The data was rejected by a [`MergePolicy`](crate::list::encoding::MergePolicy).
+    Rejected(PolicyViolation),
+}
+
+impl Display for DTError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "DTError {:?}", self)
+    }
+}
+
+impl Error for DTError {}
+
+impl From<ParseError> for DTError {
+    fn from(err: ParseError) -> Self {
+        DTError::Parse(err)
+    }
+}
+
+impl From<VersionConversionError> for DTError {
+    fn from(err: VersionConversionError) -> Self {
+        DTError::VersionConversion(err)
+    }
+}
@@ -1,7 +1,7 @@
-use std::borrow::Borrow;
-use std::cmp::Ordering;
-use std::fmt::Debug;
-use std::ops::{Index, IndexMut};
+use alloc::borrow::Borrow;
+use core::cmp::Ordering;
+use core::fmt::Debug;
+use core::ops::{Index, IndexMut};
 use smallvec::{Array, SmallVec, smallvec};
 use crate::causalgraph::graph::Graph;
 use crate::dtrange::DTRange;
@@ -18,7 +18,7 @@ use crate::causalgraph::graph::tools::DiffFlag;
 ///
 /// A frontier must always remain sorted (in numerical order). Note: This is not checked when
 /// deserializing via serde!
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
 pub struct Frontier(pub SmallVec<[LV; 2]>);
 
@@ -170,7 +170,7 @@ impl Frontier {
         self.0.is_empty()
     }
 
-    pub fn iter(&self) -> std::slice::Iter<usize> {
+    pub fn iter(&self) -> core::slice::Iter<usize> {
         self.0.iter()
     }
 
@@ -474,11 +474,11 @@ pub fn clone_smallvec<T, const LEN: usize>(v: &SmallVec<[T; LEN]>) -> SmallVec<[
     //         // We only need to copy v.len() items, because LEN is small (2, usually) its actually
     //         // faster & less code to just copy the bytes in all cases rather than branch.
     //         // let mut arr: MaybeUninit<[T; LEN]> = MaybeUninit::uninit();
-    //         // std::ptr::copy_nonoverlapping(v.as_ptr(), arr.as_mut_ptr().cast(), LEN);
+    //         // core::ptr::copy_nonoverlapping(v.as_ptr(), arr.as_mut_ptr().cast(), LEN);
     //         // SmallVec::from_buf_and_len_unchecked(arr, v.len())
     //
     //         let mut result: MaybeUninit<SmallVec<[T; LEN]>> = MaybeUninit::uninit();
-    //         std::ptr::copy_nonoverlapping(v, result.as_mut_ptr(), 1);
+    //         core::ptr::copy_nonoverlapping(v, result.as_mut_ptr(), 1);
     //         result.assume_init()
     //     }
     // }
@@ -0,0 +1,224 @@
+//! Importing a file's editing history from a git repository.
+//!
+//! [`git_import`] walks the commits which change a file, and replays the changes between each
+//! version of the file as diamond types operations. Each commit author becomes an agent, and the
+//! parents of each change match the git commit graph, so concurrent git branches become
+//! concurrent edits in the oplog. Git only stores snapshots, so the operations are found by diffing
+//! the file's content before and after each commit.
+//!
+//! This is how the `git-makefile` and `git-makefile-clean` benchmark traces were made.
+
+use std::collections::HashMap;
+use std::path::Path;
+use git2::{Commit, ObjectType, Oid, Repository};
+use similar::{ChangeTag, TextDiff};
+use similar::utils::TextDiffRemapper;
+use smallvec::SmallVec;
+use crate::{AgentId, Frontier};
+use crate::list::{ListBranch, ListOpLog};
+
+/// The result of [`git_import_from`].
+#[derive(Debug, Clone)]
+pub struct GitImport {
+    pub oplog: ListOpLog,
+
+    /// The version of the document after each commit which changed the file, in the order the
+    /// commits were imported.
+    pub commit_versions: Vec<(Oid, Frontier)>,
+}
+
+/// The state of the file after a commit which has been imported.
+struct Imported {
+    branch: ListBranch,
+    /// The file's blob at this commit, if known. Used to skip diffing when the file hasn't changed.
+    blob: Option<Oid>,
+    /// The number of child commits which haven't been imported yet.
+    remaining_children: usize,
+}
+
+/// Some commits (like 13e652800d1644dfedcd0d59ac95ef0beb7f3165 in the linux repository) name the
+/// same parent twice. This lists each parent once.
+fn uniq_parents(commit: &Commit) -> SmallVec<[Oid; 2]> {
+    let mut parents = SmallVec::new();
+    for id in commit.parent_ids() {
+        if !parents.contains(&id) { parents.push(id); }
+    }
+    parents
+}
+
+/// Take the branch at a parent commit. The branch is only cloned if other children still need it.
+fn take_parent(imported: &mut HashMap<Oid, Imported>, id: Oid) -> (ListBranch, Option<Oid>) {
+    let entry = imported.get_mut(&id).unwrap();
+    entry.remaining_children -= 1;
+    if entry.remaining_children == 0 {
+        let entry = imported.remove(&id).unwrap();
+        (entry.branch, entry.blob)
+    } else {
+        (entry.branch.clone(), entry.blob)
+    }
+}
+
+/// Diamond types only allows agent names up to 50 bytes long. Long author names are trimmed to 30
+/// bytes to be on the safe side.
+fn agent_name(author: &str) -> &str {
+    let mut end = author.len().min(30);
+    while !author.is_char_boundary(end) { end -= 1; }
+    &author[..end]
+}
+
+/// Apply the changes needed to turn the branch's content (`old`) into `new`.
+fn apply_diff(oplog: &mut ListOpLog, branch: &mut ListBranch, agent: AgentId, old: &str, new: &str) {
+    let diff = TextDiff::from_chars(old, new);
+    let remapper = TextDiffRemapper::from_text_diff(&diff, old, new);
+
+    let mut pos = 0;
+    for (tag, s) in diff.ops().iter().flat_map(|op| remapper.iter_slices(op)) {
+        let len = s.chars().count();
+        match tag {
+            ChangeTag::Equal => pos += len,
+            ChangeTag::Delete => { branch.delete(oplog, agent, pos..pos + len); }
+            ChangeTag::Insert => {
+                branch.insert(oplog, agent, pos, s);
+                pos += len;
+            }
+        }
+    }
+}
+
+/// Import the history of `file_path` from the checked out branch (HEAD) of the git repository at
+/// `repo_path`. `file_path` is relative to the root of the repository.
+pub fn git_import(repo_path: &Path, file_path: &Path) -> Result<ListOpLog, git2::Error> {
+    let repo = Repository::open(repo_path)?;
+    let head = repo.head()?.peel_to_commit()?.id();
+    Ok(git_import_from(&repo, file_path, head, |_, _| {})?.oplog)
+}
+
+/// Import the history of `file_path` in every commit up to (and including) `head`.
+///
+/// `progress` is called after each commit is imported, with the number of commits imported so far
+/// and the total number of commits.
+pub fn git_import_from<F: FnMut(usize, usize)>(repo: &Repository, file_path: &Path, head: Oid, mut progress: F) -> Result<GitImport, git2::Error> {
+    // First scan the commit graph, since commits need to be imported parents first.
+    let mut parents = HashMap::<Oid, SmallVec<[Oid; 2]>>::new();
+    let mut children = HashMap::<Oid, SmallVec<[Oid; 2]>>::new();
+    let mut ready = Vec::new();
+    let mut scan = vec![head];
+    while let Some(id) = scan.pop() {
+        if parents.contains_key(&id) { continue; }
+
+        let commit_parents = uniq_parents(&repo.find_commit(id)?);
+        if commit_parents.is_empty() { ready.push(id); }
+        for &p in &commit_parents {
+            children.entry(p).or_default().push(id);
+            scan.push(p);
+        }
+        parents.insert(id, commit_parents);
+    }
+
+    let mut oplog = ListOpLog::new();
+    let mut commit_versions = Vec::new();
+    let mut imported = HashMap::<Oid, Imported>::new();
+    let mut num_imported = 0;
+
+    while let Some(id) = ready.pop() {
+        let (mut branch, mut blob) = match parents[&id].split_first() {
+            None => (ListBranch::new(), None),
+            Some((&first, rest)) => {
+                let (mut branch, mut blob) = take_parent(&mut imported, first);
+                for &p in rest {
+                    let (other, other_blob) = take_parent(&mut imported, p);
+                    if oplog.cg.graph.frontier_contains_frontier(other.local_frontier_ref(), branch.local_frontier_ref()) {
+                        branch = other;
+                        blob = other_blob;
+                    } else if !oplog.cg.graph.frontier_contains_frontier(branch.local_frontier_ref(), other.local_frontier_ref()) {
+                        // The parents are concurrent.
+                        branch.merge(&oplog, other.local_frontier_ref());
+                        blob = None;
+                    }
+                }
+                (branch, blob)
+            }
+        };
+
+        let commit = repo.find_commit(id)?;
+        // If the file doesn't exist in this commit, the document is left as it was.
+        if let Ok(entry) = commit.tree()?.get_path(file_path) {
+            if entry.kind() == Some(ObjectType::Blob) && blob != Some(entry.id()) {
+                blob = Some(entry.id());
+                let object = entry.to_object(repo)?;
+                let new = String::from_utf8_lossy(object.as_blob().unwrap().content());
+                let old = branch.content().to_string();
+
+                if old != new {
+                    let author = commit.author();
+                    let agent = oplog.get_or_create_agent_id(agent_name(author.name().unwrap_or("unknown")));
+                    apply_diff(&mut oplog, &mut branch, agent, &old, &new);
+                    commit_versions.push((id, branch.local_frontier()));
+                }
+            }
+        }
+
+        let commit_children = children.remove(&id).unwrap_or_default();
+        imported.insert(id, Imported { branch, blob, remaining_children: commit_children.len() });
+        for c in commit_children {
+            if parents[&c].iter().all(|p| imported.contains_key(p)) {
+                ready.push(c);
+            }
+        }
+
+        num_imported += 1;
+        progress(num_imported, parents.len());
+    }
+
+    Ok(GitImport { oplog, commit_versions })
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use git2::{Oid, Repository, Signature};
+    use crate::git::{git_import, git_import_from};
+
+    fn commit(repo: &Repository, author: &str, content: &str, parents: &[Oid]) -> Oid {
+        let mut tree = repo.treebuilder(None).unwrap();
+        tree.insert("file.txt", repo.blob(content.as_bytes()).unwrap(), 0o100644).unwrap();
+        let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+        let sig = Signature::now(author, "x@example.com").unwrap();
+        let parents: Vec<_> = parents.iter().map(|p| repo.find_commit(*p).unwrap()).collect();
+        let parents: Vec<_> = parents.iter().collect();
+        repo.commit(None, &sig, &sig, "msg", &tree, &parents).unwrap()
+    }
+
+    #[test]
+    fn import_branches() {
+        let dir = std::env::temp_dir().join(format!("dt-git-import-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let repo = Repository::init(&dir).unwrap();
+
+        let a = commit(&repo, "seph", "hello world\n", &[]);
+        let b = commit(&repo, "seph", "hello cruel world\n", &[a]);
+        let c = commit(&repo, "mike", "hello world!\n", &[a]);
+        let d = commit(&repo, "mike", "hello cruel world!\n", &[b, c]);
+        repo.reference("HEAD", d, true, "").unwrap();
+
+        let mut calls = 0;
+        let result = git_import_from(&repo, Path::new("file.txt"), d, |done, total| {
+            calls += 1;
+            assert_eq!(total, 4);
+            assert_eq!(done, calls);
+        }).unwrap();
+        assert_eq!(calls, 4);
+
+        let oplog = result.oplog;
+        assert_eq!(oplog.checkout_tip().content().to_string(), "hello cruel world!\n");
+        assert_eq!(oplog.cg.agent_assignment.client_data.len(), 2);
+        // The merge commit didn't change anything, so it isn't in the list.
+        assert_eq!(result.commit_versions.len(), 3);
+        let (_, b_version) = result.commit_versions.iter().find(|(id, _)| *id == b).unwrap();
+        assert_eq!(oplog.checkout(b_version.as_ref()).content().to_string(), "hello cruel world\n");
+
+        let oplog2 = git_import(&dir, Path::new("file.txt")).unwrap();
+        assert_eq!(oplog2.checkout_tip().content().to_string(), "hello cruel world!\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,344 @@
+//! A JSON-style document CRDT, composed from the map, list and text CRDTs in [`OpLog`].
+//!
+//! A [`JsonDoc`] is a tree of objects, arrays, strings, numbers, booleans and nulls:
+//!
+//! - Objects are map CRDTs. Each key holds a register, so if a key is set concurrently on two
+//!   peers, the same value wins everywhere.
+//! - Arrays are list CRDTs. Elements are inserted and deleted like characters in a text document,
+//!   and concurrent inserts are all kept.
+//! - Strings are text CRDTs, so concurrent edits inside a string are merged rather than
+//!   overwriting each other.
+//! - Numbers (i64), booleans and null are stored directly in the containing object or array.
+//!
+//! Every CRDT in the document shares a single [`CausalGraph`](crate::CausalGraph), and changes to
+//! any part of the document are exchanged together using [`JsonDoc::ops_since`] and
+//! [`JsonDoc::merge_ops`].
+//!
+//! Values are named by a path of object keys and array indexes, starting from the root object:
+//!
+//! ```
+//! use diamond_types::json::{JsonDoc, JsonValue, PathItem::*};
+//!
+//! let mut doc = JsonDoc::new();
+//! let seph = doc.get_or_create_agent_id("seph");
+//! doc.set(seph, &[Key("todos")], JsonValue::Array(vec![])).unwrap();
+//! doc.insert(seph, &[Key("todos"), Index(0)], JsonValue::String("buy milk".into())).unwrap();
+//! doc.text_at(&[Key("todos"), Index(0)]).unwrap().insert(seph, 0, "please ");
+//! assert_eq!(doc.get(&[Key("todos"), Index(0)]), Ok(JsonValue::String("please buy milk".into())));
+//! ```
+//!
+//! Looking up an array index checks out the array, so paths through arrays take time proportional
+//! to the array's history.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::ops::Range;
+use smartstring::alias::String as SmartString;
+use crate::{AgentId, CRDTKind, CreateValue, DTError, DTRange, DTValue, LV, LVKey, OpLog, ParseError, Primitive, RegisterValue, ROOT_CRDT_ID, SerializedOps};
+use crate::list::operation::TextOperation;
+
+/// One step in a path through a [`JsonDoc`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PathItem<'a> {
+    /// A key in an object.
+    Key(&'a str),
+    /// An index in an array.
+    Index(usize),
+}
+
+impl<'a> From<&'a str> for PathItem<'a> {
+    fn from(key: &'a str) -> Self {
+        PathItem::Key(key)
+    }
+}
+
+impl From<usize> for PathItem<'_> {
+    fn from(index: usize) -> Self {
+        PathItem::Index(index)
+    }
+}
+
+/// A JSON value. This is used both to set values in a [`JsonDoc`], and to read them back.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(BTreeMap<SmartString, JsonValue>),
+}
+
+impl JsonValue {
+    /// The value stored in the containing map or list. Strings, arrays and objects get a new CRDT.
+    fn to_create_value(&self) -> CreateValue {
+        match self {
+            JsonValue::Null => CreateValue::Primitive(Primitive::Nil),
+            JsonValue::Bool(b) => CreateValue::Primitive(Primitive::Bool(*b)),
+            JsonValue::Number(n) => CreateValue::Primitive(Primitive::I64(*n)),
+            JsonValue::String(_) => CreateValue::NewCRDT(CRDTKind::Text),
+            JsonValue::Array(_) => CreateValue::NewCRDT(CRDTKind::List),
+            JsonValue::Object(_) => CreateValue::NewCRDT(CRDTKind::Map),
+        }
+    }
+}
+
+impl From<DTValue> for JsonValue {
+    fn from(value: DTValue) -> Self {
+        match value {
+            DTValue::Primitive(Primitive::Bool(b)) => JsonValue::Bool(b),
+            DTValue::Primitive(Primitive::I64(n)) => JsonValue::Number(n),
+            DTValue::Primitive(Primitive::Str(s)) => JsonValue::String(s.to_string()),
+            DTValue::Primitive(Primitive::Nil | Primitive::InvalidUninitialized) => JsonValue::Null,
+            DTValue::Map(map) => JsonValue::Object(map.into_iter()
+                .map(|(key, value)| (key, (*value).into()))
+                .collect()),
+            DTValue::Text(s) => JsonValue::String(s),
+            DTValue::List(items) => JsonValue::Array(items.into_iter()
+                .map(|value| (*value).into())
+                .collect()),
+        }
+    }
+}
+
+/// A JSON document. See the [module documentation](self) for details.
+#[derive(Debug, Clone, Default)]
+pub struct JsonDoc {
+    oplog: OpLog,
+}
+
+/// A text CRDT in an [`OpLog`], which can be edited in place. Returned by [`JsonDoc::text_at`] and
+/// [`RecordSet::text_at`](crate::record_set::RecordSet::text_at).
+#[derive(Debug)]
+pub struct TextRef<'a> {
+    oplog: &'a mut OpLog,
+    crdt: LVKey,
+}
+
+impl<'a> TextRef<'a> {
+    pub(crate) fn new(oplog: &'a mut OpLog, crdt: LVKey) -> Self {
+        Self { oplog, crdt }
+    }
+
+    /// Insert content at pos (in unicode characters). pos must be within the string.
+    pub fn insert(&mut self, agent: AgentId, pos: usize, content: &str) -> DTRange {
+        self.oplog.local_text_op(agent, self.crdt, TextOperation::new_insert(pos, content))
+    }
+
+    /// Delete the characters in range. The range must be within the string.
+    pub fn delete(&mut self, agent: AgentId, range: Range<usize>) -> DTRange {
+        self.oplog.local_text_op(agent, self.crdt, TextOperation::new_delete(range))
+    }
+
+    /// The current content of the string.
+    pub fn content(&self) -> String {
+        self.oplog.checkout_text(self.crdt).to_string()
+    }
+}
+
+impl JsonDoc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The underlying oplog, which stores all of the document's CRDTs.
+    pub fn oplog(&self) -> &OpLog { &self.oplog }
+
+    pub fn get_or_create_agent_id(&mut self, name: &str) -> AgentId {
+        self.oplog.cg.get_or_create_agent_id(name)
+    }
+
+    pub fn local_frontier_ref(&self) -> &[LV] { self.oplog.cg.version.as_ref() }
+
+    /// Get all the changes since the named version, to send to another peer.
+    pub fn ops_since(&self, since_frontier: &[LV]) -> SerializedOps<'_> {
+        self.oplog.ops_since(since_frontier)
+    }
+
+    /// Add changes from another peer. Returns the range of new local versions.
+    pub fn merge_ops(&mut self, changes: SerializedOps) -> Result<DTRange, ParseError> {
+        self.oplog.merge_ops(changes)
+    }
+
+    /// The whole document, as a JSON object.
+    pub fn to_json(&self) -> JsonValue {
+        DTValue::Map(self.oplog.checkout()).into()
+    }
+
+    fn resolve(&self, path: &[PathItem]) -> Result<RegisterValue, DTError> {
+        let mut value = RegisterValue::OwnedCRDT(CRDTKind::Map, ROOT_CRDT_ID);
+        for item in path {
+            value = match (value, item) {
+                (RegisterValue::OwnedCRDT(CRDTKind::Map, crdt), PathItem::Key(key)) => {
+                    let info = self.oplog.map_keys.get(&(crdt, (*key).into()))
+                        .ok_or(DTError::InvalidPath)?;
+                    self.oplog.resolve_mv(info)
+                }
+                (RegisterValue::OwnedCRDT(CRDTKind::List, crdt), PathItem::Index(i)) => {
+                    self.oplog.checkout_list(crdt).into_iter().nth(*i)
+                        .ok_or(DTError::InvalidPath)?
+                }
+                _ => { return Err(DTError::InvalidPath); }
+            };
+        }
+        Ok(value)
+    }
+
+    /// Find the container at the path, and the key or index inside it named by the last item.
+    fn resolve_parent<'p>(&self, path: &'p [PathItem<'p>]) -> Result<(CRDTKind, LVKey, PathItem<'p>), DTError> {
+        let (last, parent) = path.split_last().ok_or(DTError::InvalidPath)?;
+        match self.resolve(parent)? {
+            RegisterValue::OwnedCRDT(kind, crdt) => Ok((kind, crdt, *last)),
+            RegisterValue::Primitive(_) => Err(DTError::InvalidPath),
+        }
+    }
+
+    /// Get the value at path.
+    pub fn get(&self, path: &[PathItem]) -> Result<JsonValue, DTError> {
+        let value = self.resolve(path)?;
+        Ok(self.oplog.checkout_value(value).into())
+    }
+
+    /// Get the string at path, for editing.
+    pub fn text_at(&mut self, path: &[PathItem]) -> Result<TextRef<'_>, DTError> {
+        match self.resolve(path)? {
+            RegisterValue::OwnedCRDT(CRDTKind::Text, crdt) => Ok(TextRef::new(&mut self.oplog, crdt)),
+            _ => Err(DTError::InvalidPath),
+        }
+    }
+
+    /// Fill a newly created CRDT with the contents of value.
+    fn fill(&mut self, agent: AgentId, crdt: LVKey, value: JsonValue) {
+        match value {
+            JsonValue::Object(map) => {
+                for (key, value) in map {
+                    let v = self.oplog.local_map_set(agent, crdt, &key, value.to_create_value());
+                    self.fill(agent, v, value);
+                }
+            }
+            JsonValue::Array(items) => self.insert_items(agent, crdt, 0, items),
+            JsonValue::String(s) => {
+                if !s.is_empty() {
+                    self.oplog.local_text_op(agent, crdt, TextOperation::new_insert(0, &s));
+                }
+            }
+            JsonValue::Null | JsonValue::Bool(_) | JsonValue::Number(_) => {}
+        }
+    }
+
+    fn insert_items(&mut self, agent: AgentId, crdt: LVKey, pos: usize, items: Vec<JsonValue>) {
+        if items.is_empty() { return; }
+        let values: Vec<CreateValue> = items.iter().map(JsonValue::to_create_value).collect();
+        let v_range = self.oplog.local_list_insert(agent, crdt, pos, &values);
+        for (v, value) in v_range.iter().zip(items) {
+            self.fill(agent, v, value);
+        }
+    }
+
+    /// Set the value at path. The path must name a key in an object (which is added if it doesn't
+    /// exist yet), or an existing element in an array (which is replaced).
+    ///
+    /// Strings, arrays and objects in value are stored as new CRDTs. If a key is set concurrently
+    /// to two different objects, one object wins, and edits made inside the other object are lost.
+    pub fn set(&mut self, agent: AgentId, path: &[PathItem], value: JsonValue) -> Result<(), DTError> {
+        match self.resolve_parent(path)? {
+            (CRDTKind::Map, crdt, PathItem::Key(key)) => {
+                let v = self.oplog.local_map_set(agent, crdt, key, value.to_create_value());
+                self.fill(agent, v, value);
+            }
+            (CRDTKind::List, crdt, PathItem::Index(i)) => {
+                if i >= self.oplog.checkout_list(crdt).len() { return Err(DTError::InvalidPath); }
+                self.oplog.local_list_delete(agent, crdt, i..i + 1);
+                self.insert_items(agent, crdt, i, vec![value]);
+            }
+            _ => { return Err(DTError::InvalidPath); }
+        }
+        Ok(())
+    }
+
+    /// Insert a value into an array. The last item in path is the index of the new element, which
+    /// can be at most the length of the array.
+    pub fn insert(&mut self, agent: AgentId, path: &[PathItem], value: JsonValue) -> Result<(), DTError> {
+        match self.resolve_parent(path)? {
+            (CRDTKind::List, crdt, PathItem::Index(i)) if i <= self.oplog.checkout_list(crdt).len() => {
+                self.insert_items(agent, crdt, i, vec![value]);
+                Ok(())
+            }
+            _ => Err(DTError::InvalidPath),
+        }
+    }
+
+    /// Delete an element from an array. Object keys can't be removed (yet). Set them to null
+    /// instead.
+    pub fn delete(&mut self, agent: AgentId, path: &[PathItem]) -> Result<(), DTError> {
+        match self.resolve_parent(path)? {
+            (CRDTKind::List, crdt, PathItem::Index(i)) if i < self.oplog.checkout_list(crdt).len() => {
+                self.oplog.local_list_delete(agent, crdt, i..i + 1);
+                Ok(())
+            }
+            _ => Err(DTError::InvalidPath),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::collections::BTreeMap;
+    use crate::DTError;
+    use crate::json::{JsonDoc, JsonValue};
+    use crate::json::PathItem::*;
+
+    fn string(s: &str) -> JsonValue { JsonValue::String(s.into()) }
+
+    #[test]
+    fn json_doc() {
+        let mut doc = JsonDoc::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.set(seph, &[Key("title")], string("Shopping")).unwrap();
+        doc.set(seph, &[Key("items")], JsonValue::Array(vec![
+            JsonValue::Object(BTreeMap::from([
+                ("name".into(), string("milk")),
+                ("qty".into(), JsonValue::Number(2)),
+            ])),
+            JsonValue::Null,
+        ])).unwrap();
+        doc.set(seph, &[Key("items"), Index(1)], JsonValue::Bool(true)).unwrap();
+        doc.set(seph, &[Key("items"), Index(0), Key("qty")], JsonValue::Number(3)).unwrap();
+        doc.text_at(&[Key("items"), Index(0), Key("name")]).unwrap().insert(seph, 4, "shake");
+        doc.oplog.dbg_check(true);
+
+        assert_eq!(doc.get(&[Key("items"), Index(0), Key("name")]), Ok(string("milkshake")));
+        assert_eq!(doc.get(&[Key("items"), Index(1)]), Ok(JsonValue::Bool(true)));
+        assert_eq!(doc.get(&[Key("items"), Index(2)]), Err(DTError::InvalidPath));
+        assert_eq!(doc.get(&[Key("title"), Key("x")]), Err(DTError::InvalidPath));
+        assert!(doc.text_at(&[Key("items")]).is_err());
+        assert_eq!(doc.insert(seph, &[Key("items"), Index(5)], JsonValue::Null), Err(DTError::InvalidPath));
+
+        // Concurrent edits on another peer.
+        let mut other = JsonDoc::new();
+        other.merge_ops(doc.ops_since(&[])).unwrap();
+        assert_eq!(other.to_json(), doc.to_json());
+        let mike = other.get_or_create_agent_id("mike");
+        let since = doc.local_frontier_ref().to_vec();
+        other.text_at(&[Key("title")]).unwrap().insert(mike, 8, " list");
+        other.insert(mike, &[Key("items"), Index(0)], string("eggs")).unwrap();
+        other.delete(mike, &[Key("items"), Index(2)]).unwrap();
+
+        doc.text_at(&[Key("title")]).unwrap().insert(seph, 0, "My ");
+        doc.insert(seph, &[Key("items"), Index(2)], JsonValue::Number(10)).unwrap();
+
+        let other_since = other.oplog.cg.version.clone();
+        doc.merge_ops(other.ops_since(&since)).unwrap();
+        other.merge_ops(doc.ops_since(other_since.as_ref())).unwrap();
+        doc.oplog.dbg_check(true);
+        other.oplog.dbg_check(true);
+
+        assert_eq!(doc.get(&[Key("title")]), Ok(string("My Shopping list")));
+        let JsonValue::Array(items) = doc.get(&[Key("items")]).unwrap() else { panic!() };
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0], string("eggs"));
+        assert_eq!(items[2], JsonValue::Number(10));
+        assert_eq!(other.to_json(), doc.to_json());
+    }
+}
@@ -181,23 +181,31 @@
 
 #![allow(clippy::module_inception)]
 #![allow(unused_imports, dead_code)] // During dev. TODO: Take me out!
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
 extern crate core;
+#[macro_use]
+extern crate alloc;
 
-use std::collections::{BTreeMap, BTreeSet};
-use std::fmt::{Debug, Formatter};
+use alloc::{boxed::Box, string::String, vec::Vec};
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt::{Debug, Formatter};
 use jumprope::{JumpRope, JumpRopeBuf};
 use smallvec::SmallVec;
 use smartstring::alias::String as SmartString;
 pub use crate::causalgraph::CausalGraph;
 pub use crate::dtrange::DTRange;
 use causalgraph::graph::Graph;
+#[cfg(feature = "std")]
 use crate::causalgraph::storage::CGStorage;
 use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
 use crate::rle::{KVPair, RleVec};
+#[cfg(feature = "std")]
 use crate::wal::WriteAheadLog;
 pub use ::rle::HasLength;
 pub use frontier::Frontier;
+pub use error::DTError;
+pub use encoding::parseerror::ParseError;
 use crate::causalgraph::agent_span::AgentVersion;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
@@ -206,6 +214,10 @@ use crate::textinfo::TextInfo;
 
 // use crate::list::internal_op::OperationInternal as TextOpInternal;
 
+#[cfg(not(any(feature = "std", test)))]
+#[macro_use]
+mod no_std_macros;
+
 pub mod list;
 mod rle;
 mod dtrange;
@@ -214,7 +226,9 @@ mod rev_range;
 pub mod frontier;
 mod check;
 mod encoding;
+mod error;
 pub mod causalgraph;
+#[cfg(feature = "std")]
 mod wal;
 
 #[cfg(feature = "serde")]
@@ -223,7 +237,7 @@ pub(crate) mod serde_helpers;
 // TODO: Make me private!
 pub mod listmerge;
 
-#[cfg(any(test, feature = "gen_test_data"))]
+#[cfg(any(test, feature = "gen_test_data", feature = "testkit"))]
 mod list_fuzzer_tools;
 #[cfg(test)]
 mod fuzzer;
@@ -233,7 +247,15 @@ mod oplog;
 #[cfg(feature = "storage")]
 mod storage;
 mod simple_checkout;
+pub mod json;
+pub mod record_set;
 mod listmerge2;
+#[cfg(feature = "testkit")]
+pub mod testkit;
+#[cfg(any(test, feature = "testkit"))]
+pub mod simulator;
+#[cfg(feature = "git")]
+pub mod git;
 
 pub type AgentId = u32;
 
@@ -260,7 +282,7 @@ pub enum Primitive {
 }
 
 impl Debug for Primitive {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match self {
             Primitive::Nil => f.debug_struct("Nil").finish(),
             Primitive::Bool(val) => val.fmt(f),
@@ -281,6 +303,7 @@ pub enum CRDTKind {
     Register,
     Collection, // SQL table / mongo collection
     Text,
+    List, // Ordered list of values (like a JS array)
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -394,9 +417,15 @@ pub struct OpLog {
 
     /// (CRDT ID, key) -> MVRegister.
     map_keys: BTreeMap<(LVKey, SmartString), RegisterInfo>,
-    /// CRDT ID -> Text CRDT.
+    /// CRDT ID -> Text CRDT. Lists store their positional operations here too, with a placeholder
+    /// character for each element.
     texts: BTreeMap<LVKey, TextInfo>,
 
+    /// The CRDTs in texts which are lists.
+    lists: BTreeSet<LVKey>,
+    /// The value of each list element, keyed by the version of the insert which created it.
+    list_values: BTreeMap<LV, CreateValue>,
+
     // These are always inserted at the end, but items in the middle are removed. There's probably
     // a better data structure to accomplish this.
     map_index: BTreeMap<LV, (LVKey, SmartString)>,
@@ -422,6 +451,7 @@ pub struct Branch {
     // registers: BTreeMap<LVKey, SmallVec<[LV; 2]>>, // TODO.
     maps: BTreeMap<LVKey, BTreeMap<SmartString, RegisterState>>, // any objects.
     pub texts: BTreeMap<LVKey, JumpRopeBuf>,
+    pub lists: BTreeMap<LVKey, Vec<RegisterValue>>,
 }
 
 /// The register stores the specified value, but if conflicts_with is not empty, it has some
@@ -442,6 +472,10 @@ pub struct SerializedOps<'a> {
     map_ops: Vec<(RemoteVersion<'a>, RemoteVersion<'a>, &'a str, CreateValue)>,
     text_ops: Vec<(RemoteVersion<'a>, RemoteVersion<'a>, ListOpMetrics)>,
     text_context: ListOperationCtx,
+
+    // The version of each new list element, and its value.
+    #[cfg_attr(feature = "serde", serde(borrow, default))]
+    list_values: Vec<(RemoteVersion<'a>, CreateValue)>,
 }
 
 /// This is used for checkouts. This is a value tree.
@@ -454,4 +488,5 @@ pub enum DTValue {
     Map(BTreeMap<SmartString, Box<DTValue>>),
     // Collection(BTreeMap<LV, Box<DTValue>>),
     Text(String),
+    List(Vec<Box<DTValue>>),
 }
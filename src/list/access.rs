@@ -0,0 +1,200 @@
+//! Access control for protected regions of a document.
+//!
+//! A [`ProtectedRegion`] is a range of the document, named by anchors so it moves along with its
+//! content as the document is edited. An [`AccessPolicy`] decides whether each operation which
+//! touches a protected region is allowed. This can be used for locked sections in shared
+//! documents, where only some agents may edit the locked text.
+//!
+//! Peers can't stop each other making changes, so a vetoed operation can't be removed from the
+//! oplog. Instead it's recorded as a rejected [suggestion](crate::list::suggestions). Rejected
+//! operations are tombstoned in [`ListOpLog::checkout_mainline`]: a rejected insert is hidden, and
+//! the text removed by a rejected delete is restored. Operations which depend on them are kept.
+//!
+//! Each operation is checked against the regions as they were at the operation's parent version,
+//! so the decision doesn't depend on the order peers receive changes in. As long as every peer
+//! uses the same regions and a deterministic policy, they all reject the same operations and
+//! converge. The rejections are stored when the oplog is encoded, so peers which don't enforce the
+//! policy still see them.
+
+use alloc::vec::Vec;
+use rle::{AppendRle, HasLength};
+use crate::{DTRange, Frontier, LV};
+use crate::encoding::parseerror::ParseError;
+use crate::list::ListOpLog;
+use crate::list::anchor::Anchor;
+use crate::list::operation::{ListOpKind, TextOperation};
+use crate::list::suggestions::{SuggestionEntry, SuggestionState};
+use crate::rle::KVPair;
+
+/// A protected range of the document. The region starts at the `start` anchor and ends at the
+/// `end` anchor. Usually `start` is [`Anchor::before`] the region's first character and `end` is
+/// [`Anchor::after`] its last character, so content typed at either edge is outside the region.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct ProtectedRegion {
+    pub start: Anchor,
+    pub end: Anchor,
+}
+
+/// Whether an operation touching a protected region is allowed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum AccessDecision {
+    Allow,
+    Reject,
+}
+
+/// Decides whether operations which touch protected regions are allowed.
+///
+/// The policy must be deterministic - it should only depend on its arguments. Otherwise peers
+/// can make different decisions about the same operation, and they won't converge.
+pub trait AccessPolicy {
+    /// Check an operation made by the named agent which touches a protected region.
+    fn check(&self, agent: &str, op: &TextOperation, region: &ProtectedRegion) -> AccessDecision;
+}
+
+impl<F: Fn(&str, &TextOperation, &ProtectedRegion) -> AccessDecision> AccessPolicy for F {
+    fn check(&self, agent: &str, op: &TextOperation, region: &ProtectedRegion) -> AccessDecision {
+        self(agent, op, region)
+    }
+}
+
+/// True if the operation touches the range `start..end` of the document. Inserts right at the edge
+/// of the range are outside it.
+fn touches(op: &TextOperation, start: usize, end: usize) -> bool {
+    match op.kind {
+        ListOpKind::Ins => start < op.start() && op.start() < end,
+        ListOpKind::Del => op.start() < end && op.end() > start,
+    }
+}
+
+impl ListOpLog {
+    /// Check all the operations which aren't in the `since` version against an access policy.
+    /// Operations touching protected regions which the policy rejects are recorded as rejected
+    /// suggestions. See the [module documentation](crate::list::access) for details.
+    ///
+    /// Returns the local versions of the rejected operations.
+    ///
+    /// Protected regions are found by resolving their anchors at each operation's parent version.
+    /// This is slow for large histories, so this is best used on small sets of new changes.
+    pub fn enforce_access_policy<P: AccessPolicy>(&mut self, since: &[LV], regions: &[ProtectedRegion], policy: &P) -> Vec<DTRange> {
+        let mut rejected: Vec<DTRange> = Vec::new();
+        if regions.is_empty() { return rejected; }
+
+        for range in self.cg.diff_since(since) {
+            for entry in self.cg.iter_range(range) {
+                let agent = self.cg.agent_assignment.get_agent_name(entry.span.agent);
+                let mut parents = entry.parents;
+                let entry_range: DTRange = (entry.start..entry.start + entry.span.len()).into();
+
+                for (KVPair(start, metrics), content) in self.iter_range_simple(entry_range) {
+                    let op: TextOperation = (metrics, content).into();
+                    let reject = regions.iter().any(|region| {
+                        let Some(region_start) = self.resolve_anchor(parents.as_ref(), region.start) else { return false; };
+                        let Some(region_end) = self.resolve_anchor(parents.as_ref(), region.end) else { return false; };
+                        touches(&op, region_start, region_end)
+                            && policy.check(agent, &op, region) == AccessDecision::Reject
+                    });
+                    if reject { rejected.push_rle((start..start + op.len()).into()); }
+                    parents = Frontier::new_1(start + op.len() - 1);
+                }
+            }
+        }
+
+        for span in rejected.iter() {
+            // A rejected span can cover operations from several agents.
+            let mut span = *span;
+            while !span.is_empty() {
+                let agent_span = self.cg.agent_assignment.local_span_to_agent_span(span);
+                self.suggestions.merge_entry((agent_span.agent, agent_span.seq_range.start), SuggestionEntry {
+                    seq_end: agent_span.seq_range.end,
+                    state: SuggestionState::Rejected,
+                    changed: 0,
+                });
+                span.start += agent_span.seq_range.len();
+            }
+        }
+        rejected
+    }
+
+    /// Add all operations from a binary chunk into this document (like
+    /// [`decode_and_add`](ListOpLog::decode_and_add)), then check the new operations against an
+    /// access policy. See [`enforce_access_policy`](ListOpLog::enforce_access_policy).
+    ///
+    /// Returns the local versions of the rejected operations.
+    pub fn decode_and_add_with_access_policy<P: AccessPolicy>(&mut self, data: &[u8], regions: &[ProtectedRegion], policy: &P) -> Result<Vec<DTRange>, ParseError> {
+        let since = self.local_frontier();
+        self.decode_and_add(data)?;
+        Ok(self.enforce_access_policy(since.as_ref(), regions, policy))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::access::{AccessDecision, ProtectedRegion};
+    use crate::list::anchor::Anchor;
+    use crate::list::encoding::EncodeOptions;
+    use crate::list::ListOpLog;
+    use crate::list::operation::TextOperation;
+    use crate::list::suggestions::SuggestionState;
+
+    fn only_seph(agent: &str, _op: &TextOperation, _region: &ProtectedRegion) -> AccessDecision {
+        if agent == "seph" { AccessDecision::Allow } else { AccessDecision::Reject }
+    }
+
+    #[test]
+    fn locked_section() {
+        let mut base = ListOpLog::new();
+        let seph = base.get_or_create_agent_id("seph");
+        base.add_insert(seph, 0, "intro LOCKED outro");
+        // The region covers "LOCKED".
+        let regions = [ProtectedRegion { start: Anchor::before(6), end: Anchor::after(11) }];
+
+        let mut remote = base.clone();
+        let mike = remote.get_or_create_agent_id("mike");
+        remote.add_insert(mike, 0, "> ");
+        let first_part = remote.encode(EncodeOptions::default());
+        remote.add_delete_without_content(mike, 10..12);
+        // Typing at the edge of the region is allowed. But this insert depends on the delete.
+        remote.add_insert(mike, 8, "!");
+        remote.add_insert(seph, 8, "(");
+        let data = remote.encode(EncodeOptions::default());
+
+        let mut a = base.clone();
+        let rejected = a.decode_and_add_with_access_policy(&data, &regions, &only_seph).unwrap();
+        assert_eq!(rejected, vec![(20..22).into()]);
+        // The rejected delete is undone, but the changes which depend on it are kept.
+        assert_eq!(a.checkout_mainline(), "> intro (!LOCKED outro");
+
+        // Peers checking the same changes make the same decision, even if they received them in
+        // several parts.
+        let mut b = base.clone();
+        b.decode_and_add(&first_part).unwrap();
+        assert_eq!(b.enforce_access_policy(&[17], &regions, &only_seph), vec![]);
+        b.decode_and_add(&data).unwrap();
+        assert_eq!(b.enforce_access_policy(&[19], &regions, &only_seph), rejected);
+        assert_eq!(b.checkout_mainline(), a.checkout_mainline());
+
+        // The rejections are stored in the encoding, for peers which don't check the policy.
+        let c = ListOpLog::load_from(&a.encode(EncodeOptions::default())).unwrap();
+        assert_eq!(c.checkout_mainline(), a.checkout_mainline());
+    }
+
+    #[test]
+    fn reject_several_agents() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "intro LOCKED outro");
+        let regions = [ProtectedRegion { start: Anchor::before(6), end: Anchor::after(11) }];
+
+        // The rejected operations are next to each other, but they were made by different agents.
+        let mike = oplog.get_or_create_agent_id("mike");
+        let kaarina = oplog.get_or_create_agent_id("kaarina");
+        oplog.add_delete_at(mike, &[17], 6..8);
+        oplog.add_delete_at(kaarina, &[17], 8..10);
+        let rejected = oplog.enforce_access_policy(&[17], &regions, &only_seph);
+        assert_eq!(rejected, vec![(18..22).into()]);
+        for v in 18..22 {
+            assert_eq!(oplog.suggestion_state(v), Some(SuggestionState::Rejected));
+        }
+        assert_eq!(oplog.checkout_mainline(), "intro LOCKED outro");
+    }
+}
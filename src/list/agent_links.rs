@@ -0,0 +1,131 @@
+//! Linking agents which belong to the same principal (user).
+//!
+//! Privacy-conscious clients often use a fresh agent ID for each session. An agent can be linked
+//! to another agent with [`ListOpLog::link_agent`] to record that both belong to the same
+//! principal. This lets apps group the agents together (eg in blame views or statistics). Links
+//! don't change how operations are ordered - the CRDT still uses each agent's own name.
+//!
+//! Links are stored in the oplog and encoded along with the agent table. Links aren't signed, so
+//! they should only be trusted as much as the peers which sent them.
+//!
+//! Each agent can only be linked once. If an agent is concurrently linked to different agents, the
+//! link to the agent with the lowest name wins. Concurrent links can also form a cycle. In that
+//! case, the agent in the cycle with the lowest name is the principal.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use crate::{AgentId, DTError};
+use crate::list::ListOpLog;
+
+/// All the links between agents in an oplog. Each agent maps to the agent it's linked to.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AgentLinks {
+    pub(crate) links: BTreeMap<AgentId, AgentId>,
+}
+
+impl AgentLinks {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+}
+
+impl ListOpLog {
+    /// Record that `agent` belongs to the same principal as `principal`. See the
+    /// [module documentation](self) for details.
+    ///
+    /// Returns [`DTError::InvalidAgentLink`] if the agent has already been linked to a different
+    /// agent, or if the link would make a cycle.
+    pub fn link_agent(&mut self, agent: AgentId, principal: AgentId) -> Result<(), DTError> {
+        let num_agents = self.cg.agent_assignment.client_data.len();
+        if agent as usize >= num_agents || principal as usize >= num_agents {
+            return Err(DTError::UnknownAgent);
+        }
+
+        match self.agent_links.links.get(&agent) {
+            Some(&existing) if existing == principal => Ok(()),
+            Some(_) => Err(DTError::InvalidAgentLink),
+            None if self.principal_of(principal) == agent => Err(DTError::InvalidAgentLink),
+            None => {
+                self.agent_links.links.insert(agent, principal);
+                Ok(())
+            }
+        }
+    }
+
+    /// Merge in a link from another peer.
+    pub(crate) fn merge_agent_link(&mut self, agent: AgentId, target: AgentId) {
+        let existing = self.agent_links.links.entry(agent).or_insert(target);
+        if self.cg.agent_assignment.get_agent_name(target) < self.cg.agent_assignment.get_agent_name(*existing) {
+            *existing = target;
+        }
+    }
+
+    /// Get the principal for an agent. This follows the agent's links. Agents which haven't been
+    /// linked are their own principal.
+    pub fn principal_of(&self, agent: AgentId) -> AgentId {
+        let mut path = Vec::new();
+        let mut agent = agent;
+        while let Some(&next) = self.agent_links.links.get(&agent) {
+            path.push(agent);
+            if let Some(i) = path.iter().position(|&a| a == next) {
+                // The links form a cycle. The agent with the lowest name is the principal.
+                return path[i..].iter().copied()
+                    .min_by_key(|&a| self.get_agent_name(a))
+                    .unwrap();
+            }
+            agent = next;
+        }
+        agent
+    }
+
+    /// List all the agents which have the same principal as `agent`, including `agent`.
+    pub fn linked_agents(&self, agent: AgentId) -> Vec<AgentId> {
+        let principal = self.principal_of(agent);
+        (0..self.cg.agent_assignment.client_data.len() as AgentId)
+            .filter(|&a| self.principal_of(a) == principal)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec;
+    use crate::DTError;
+    use crate::list::encoding::ENCODE_FULL;
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn link_agents() {
+        let mut a = ListOpLog::new();
+        let seph = a.get_or_create_agent_id("seph");
+        let s1 = a.get_or_create_agent_id("session1");
+        let s2 = a.get_or_create_agent_id("session2");
+        a.add_insert(s1, 0, "hi");
+        a.link_agent(s1, seph).unwrap();
+        a.link_agent(s2, s1).unwrap();
+        assert_eq!(a.principal_of(s2), seph);
+        assert_eq!(a.linked_agents(seph), vec![seph, s1, s2]);
+
+        assert_eq!(a.link_agent(s1, s2), Err(DTError::InvalidAgentLink));
+        assert_eq!(a.link_agent(seph, s2), Err(DTError::InvalidAgentLink));
+
+        // Links are encoded with the agent table.
+        let mut b = ListOpLog::load_from(&a.encode(ENCODE_FULL)).unwrap();
+        assert_eq!(b, a);
+        assert_eq!(b.principal_of(b.get_agent_id("session2").unwrap()), b.get_agent_id("seph").unwrap());
+
+        // Concurrent links which make a cycle pick the same principal on every peer.
+        let mike = a.get_or_create_agent_id("mike");
+        let m1 = a.get_or_create_agent_id("m1");
+        a.link_agent(mike, m1).unwrap();
+        let m1 = b.get_or_create_agent_id("m1");
+        let mike = b.get_or_create_agent_id("mike");
+        b.link_agent(m1, mike).unwrap();
+        let a_data = a.encode(ENCODE_FULL);
+        a.decode_and_add(&b.encode(ENCODE_FULL)).unwrap();
+        b.decode_and_add(&a_data).unwrap();
+        assert_eq!(b, a);
+        assert_eq!(b.principal_of(mike), m1);
+        assert_eq!(b.linked_agents(mike), vec![m1, mike]);
+    }
+}
@@ -0,0 +1,117 @@
+//! Metadata about agents, like display names, colors or device IDs.
+//!
+//! Each agent can have a set of string key / value pairs. The metadata is stored in the oplog and
+//! encoded along with the agent table, so it stays in sync with the agents which made changes.
+//!
+//! Conflicting values are resolved by last-writer-wins. Each value has a counter which is
+//! incremented each time the value is set. When two peers concurrently set a key to different
+//! values with the same counter, the greater value wins, so every peer ends up with the same value.
+
+use alloc::collections::BTreeMap;
+use smartstring::alias::String as SmartString;
+use crate::AgentId;
+use crate::list::ListOpLog;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct AgentMetaValue {
+    /// Incremented each time the value is set. Used to find the most recent value.
+    pub(crate) clock: usize,
+    pub(crate) value: SmartString,
+}
+
+impl AgentMetaValue {
+    fn wins_over(&self, other: &Self) -> bool {
+        (self.clock, &self.value) > (other.clock, &other.value)
+    }
+}
+
+/// All the agent metadata in an oplog, keyed by (agent, key).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AgentMeta {
+    pub(crate) entries: BTreeMap<(AgentId, SmartString), AgentMetaValue>,
+}
+
+impl AgentMeta {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Merge in a value from another peer. The value is kept if it wins over the existing value.
+    pub(crate) fn merge_entry(&mut self, agent: AgentId, key: &str, value: AgentMetaValue) {
+        match self.entries.get_mut(&(agent, SmartString::from(key))) {
+            Some(existing) => {
+                if value.wins_over(existing) { *existing = value; }
+            }
+            None => { self.entries.insert((agent, key.into()), value); }
+        }
+    }
+}
+
+impl ListOpLog {
+    /// Set a metadata value for the named agent. See the [module documentation](self) for
+    /// details.
+    pub fn set_agent_meta(&mut self, agent: AgentId, key: &str, value: &str) {
+        assert!((agent as usize) < self.cg.agent_assignment.client_data.len(), "Unknown agent");
+        let clock = self.agent_meta.entries.get(&(agent, SmartString::from(key)))
+            .map_or(0, |e| e.clock + 1);
+        self.agent_meta.entries.insert((agent, key.into()), AgentMetaValue {
+            clock,
+            value: value.into(),
+        });
+    }
+
+    /// Get a metadata value for the named agent. Returns None if the value hasn't been set.
+    pub fn get_agent_meta(&self, agent: AgentId, key: &str) -> Option<&str> {
+        self.agent_meta.entries.get(&(agent, SmartString::from(key)))
+            .map(|e| e.value.as_str())
+    }
+
+    /// Iterate over all the metadata for an agent, as (key, value) pairs in key order.
+    pub fn iter_agent_meta(&self, agent: AgentId) -> impl Iterator<Item = (&str, &str)> + '_ {
+        self.agent_meta.entries.range((agent, SmartString::new())..)
+            .take_while(move |((a, _), _)| *a == agent)
+            .map(|((_, key), e)| (key.as_str(), e.value.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use crate::list::encoding::ENCODE_FULL;
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn agent_meta() {
+        let mut a = ListOpLog::new();
+        let seph = a.get_or_create_agent_id("seph");
+        a.add_insert(seph, 0, "hi");
+        a.set_agent_meta(seph, "name", "Seph");
+        a.set_agent_meta(seph, "color", "red");
+
+        // Metadata is encoded with the agent table.
+        let mut b = ListOpLog::load_from(&a.encode(ENCODE_FULL)).unwrap();
+        assert_eq!(b, a);
+        assert_eq!(b.iter_agent_meta(seph).collect::<Vec<_>>(), vec![("color", "red"), ("name", "Seph")]);
+
+        // Later values win.
+        b.set_agent_meta(seph, "color", "blue");
+        a.decode_and_add(&b.encode(ENCODE_FULL)).unwrap();
+        assert_eq!(a.get_agent_meta(seph, "color"), Some("blue"));
+
+        // Concurrent values resolve the same way on both peers.
+        a.set_agent_meta(seph, "name", "Seph G");
+        b.set_agent_meta(seph, "name", "Joseph");
+        let a_data = a.encode(ENCODE_FULL);
+        a.decode_and_add(&b.encode(ENCODE_FULL)).unwrap();
+        b.decode_and_add(&a_data).unwrap();
+        assert_eq!(a.get_agent_meta(seph, "name"), Some("Seph G"));
+        assert_eq!(b, a);
+
+        // Agents with metadata don't need to have made any changes.
+        let mike = a.get_or_create_agent_id("mike");
+        a.set_agent_meta(mike, "device", "phone");
+        let c = ListOpLog::load_from(&a.encode(ENCODE_FULL)).unwrap();
+        assert_eq!(c.get_agent_meta(c.get_agent_id("mike").unwrap(), "device"), Some("phone"));
+    }
+}
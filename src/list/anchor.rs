@@ -0,0 +1,210 @@
+//! Anchors name a position in a document relative to an inserted character, rather than as an
+//! offset. As the document is edited and merged, the anchor's position moves along with the
+//! character. This is useful for comment threads, decorations and bookmarks.
+
+use alloc::vec::Vec;
+use rle::HasLength;
+use crate::LV;
+use crate::list::{ListBranch, ListCRDT, ListOpLog};
+use crate::list::operation::{ListOpKind, TextOperation};
+use crate::rle::KVPair;
+
+/// Which side of the referenced character an anchor sticks to.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum AnchorBias {
+    /// The position immediately before the character.
+    Before,
+    /// The position immediately after the character.
+    After,
+}
+
+/// A position in a document, named relative to the character inserted at some LV.
+///
+/// If the character is later deleted, the anchor resolves to the position where the character
+/// would have been.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Anchor {
+    pub lv: LV,
+    pub bias: AnchorBias,
+}
+
+impl Anchor {
+    /// An anchor at the position immediately before the character inserted at lv.
+    pub fn before(lv: LV) -> Self {
+        Self { lv, bias: AnchorBias::Before }
+    }
+
+    /// An anchor at the position immediately after the character inserted at lv.
+    pub fn after(lv: LV) -> Self {
+        Self { lv, bias: AnchorBias::After }
+    }
+
+    /// Transform a resolved position of this anchor by an operation.
+    fn transform(&self, pos: usize, op: &TextOperation) -> usize {
+        // Inserts right at the anchor end up after a Before anchor's character, and before an
+        // After anchor's character.
+        transform_pos(pos, op, self.bias == AnchorBias::Before)
+    }
+}
+
+/// Transform a position by an operation. `move_on_insert` controls whether content inserted
+/// right at the position ends up before it (true) or after it (false).
+pub(crate) fn transform_pos(pos: usize, op: &TextOperation, move_on_insert: bool) -> usize {
+    let start = op.start();
+    match op.kind {
+        ListOpKind::Ins => {
+            if pos > start || (move_on_insert && pos == start) { pos + op.len() } else { pos }
+        }
+        ListOpKind::Del => {
+            if pos >= op.end() { pos - op.len() }
+            else if pos > start { start }
+            else { pos }
+        }
+    }
+}
+
+impl ListOpLog {
+    /// Find the position of an anchor in the document at the specified version. See
+    /// [`ListBranch::resolve_anchor`].
+    pub fn resolve_anchor(&self, version: &[LV], anchor: Anchor) -> Option<usize> {
+        self.resolve_anchor_with(version, anchor, anchor.bias == AnchorBias::Before)
+    }
+
+    /// Variant of [`resolve_anchor`](ListOpLog::resolve_anchor) where the caller chooses what
+    /// happens when content is inserted right at the anchor. The anchor's bias only picks which
+    /// side of its character the position starts on.
+    pub(crate) fn resolve_anchor_with(&self, version: &[LV], anchor: Anchor, move_on_insert: bool) -> Option<usize> {
+        let lv = anchor.lv;
+        if lv >= self.len() || !self.cg.graph.frontier_contains_version(version, lv) {
+            return None;
+        }
+
+        // Find where the character was when it was inserted...
+        let (KVPair(_, op), _) = self.iter_range_simple((lv..lv + 1).into()).next()?;
+        if op.kind != ListOpKind::Ins { return None; }
+        let mut pos = match anchor.bias {
+            AnchorBias::Before => op.start(),
+            AnchorBias::After => op.start() + 1,
+        };
+
+        // ... Then move it through all the operations since.
+        for (_, op) in self.iter_xf_operations_from(&[lv], version) {
+            if let Some(op) = op {
+                pos = transform_pos(pos, &op, move_on_insert);
+            }
+        }
+        Some(pos)
+    }
+}
+
+impl ListBranch {
+    /// Find the current position of an anchor in this branch.
+    ///
+    /// Returns None if the anchor's character hasn't been merged into the branch, or if the
+    /// anchor doesn't reference an inserted character.
+    pub fn resolve_anchor(&self, oplog: &ListOpLog, anchor: Anchor) -> Option<usize> {
+        oplog.resolve_anchor(self.version.as_ref(), anchor)
+    }
+
+    /// Find the current positions of a set of anchors. See
+    /// [`resolve_anchor`](ListBranch::resolve_anchor).
+    pub fn resolve_anchors(&self, oplog: &ListOpLog, anchors: &[Anchor]) -> Vec<Option<usize>> {
+        anchors.iter().map(|anchor| self.resolve_anchor(oplog, *anchor)).collect()
+    }
+
+    /// Merge changes into the branch (like [`merge`](ListBranch::merge)), and update the resolved
+    /// positions of a set of anchors at the same time.
+    ///
+    /// Each anchor is paired with its position in the branch before the merge (or None if the
+    /// anchor couldn't be resolved). Known positions are moved through the merged changes, which
+    /// is much faster than resolving each anchor again. Anchors which couldn't be resolved before
+    /// are resolved after the merge.
+    pub fn merge_with_anchors(&mut self, oplog: &ListOpLog, merge_frontier: &[LV], anchors: &mut [(Anchor, Option<usize>)]) {
+        let ops = self.merge_and_collect(oplog, merge_frontier);
+
+        for (anchor, pos) in anchors.iter_mut() {
+            *pos = match *pos {
+                Some(pos) => Some(ops.iter().fold(pos, |pos, op| anchor.transform(pos, op))),
+                None => self.resolve_anchor(oplog, *anchor),
+            };
+        }
+    }
+}
+
+impl ListCRDT {
+    /// Find the current position of an anchor in the document. See
+    /// [`ListBranch::resolve_anchor`].
+    pub fn resolve_anchor(&self, anchor: Anchor) -> Option<usize> {
+        self.branch.resolve_anchor(&self.oplog, anchor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::anchor::Anchor;
+    use crate::list::{ListCRDT, ListOpLog};
+
+    #[test]
+    fn anchors_track_content() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "hello world");
+        let w = 6;
+        let before = Anchor::before(w);
+        let after = Anchor::after(w);
+        assert_eq!(doc.resolve_anchor(before), Some(6));
+        assert_eq!(doc.resolve_anchor(after), Some(7));
+
+        // Inserting at the anchor keeps it next to its character.
+        doc.insert(seph, 6, "big ");
+        assert_eq!(doc.resolve_anchor(before), Some(10));
+        doc.insert(seph, 11, "!");
+        assert_eq!(doc.resolve_anchor(after), Some(11));
+        assert_eq!(doc.branch.content().to_string(), "hello big w!orld");
+
+        // Deleting the character collapses the anchors to where it was.
+        doc.delete(seph, 0..11);
+        assert_eq!(doc.resolve_anchor(before), Some(0));
+        assert_eq!(doc.resolve_anchor(after), Some(0));
+
+        // Anchors which don't refer to inserted characters can't be resolved.
+        assert_eq!(doc.resolve_anchor(Anchor::before(1000)), None);
+        assert_eq!(doc.resolve_anchor(Anchor::before(doc.oplog.len() - 1)), None);
+    }
+
+    #[test]
+    fn anchors_survive_merges() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "abc");
+        let b = oplog.add_insert_at(seph, &[a], 0, "xyz");
+        let c = oplog.add_insert_at(mike, &[a], 1, "123");
+        let c = oplog.add_delete_at(mike, &[c], 0..1);
+        // A reverse-order insert.
+        let mut d = c;
+        for _ in 0..3 { d = oplog.add_insert_at(mike, &[d], 3, "Q"); }
+
+        let anchors = [
+            Anchor::before(1), Anchor::after(1), Anchor::after(a),
+            Anchor::before(0), Anchor::after(c - 1), Anchor::before(d), Anchor::after(d - 2),
+        ];
+
+        let mut branch = oplog.checkout(&[b]);
+        let mut positions: Vec<_> = anchors.iter()
+            .map(|anchor| (*anchor, branch.resolve_anchor(&oplog, *anchor)))
+            .collect();
+        assert_eq!(positions[4].1, None);
+
+        branch.merge_with_anchors(&oplog, &[d], &mut positions);
+        let expected = branch.resolve_anchors(&oplog, &anchors);
+        assert_eq!(positions.iter().map(|(_, pos)| *pos).collect::<Vec<_>>(), expected);
+
+        // Check a few by hand. The document is "xyz123QQQbc"
+        assert_eq!(branch.content().to_string(), "xyz123QQQbc");
+        assert_eq!(expected[0], Some(9));
+        assert_eq!(expected[3], Some(3));
+        assert_eq!(expected[5], Some(6));
+        assert_eq!(expected[6], Some(9));
+    }
+}
@@ -0,0 +1,217 @@
+//! Fast paths for append-only documents, like chat logs and event feeds.
+//!
+//! In an append-only document, every insert is at the end of the document (at the version it was
+//! made), and nothing is ever deleted. Use [`ListCRDT::append`] to make changes like this.
+//!
+//! Concurrent appends are still ordered the same way as any other concurrent inserts. But the
+//! general merge algorithm's range tree isn't needed to figure out where they go, because
+//! each append's left origin is the last item in the document at the version it was made. So
+//! [`ListOpLog::checkout_append_only`] replays the history using a plain list of runs instead.
+//! Concurrent runs almost always land at the end of the document, so this is very fast. The result
+//! is identical to a normal checkout.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use rle::HasLength;
+use crate::{AgentId, DTRange, LV};
+use crate::list::{ListBranch, ListCRDT, ListOpLog};
+use crate::list::operation::ListOpKind;
+use crate::rle::KVPair;
+
+/// A run of items in the document, inserted by a single operation. Each item's left origin is
+/// the item before it in the run.
+#[derive(Debug, Clone, Copy)]
+struct AppendRun {
+    span: DTRange,
+    /// The left origin of the first item in the run. None if it was inserted at the start of the
+    /// document.
+    origin_left: Option<LV>,
+}
+
+impl ListCRDT {
+    /// Append content to the end of the document. Documents which are only ever edited with
+    /// this method can be checked out using [`ListOpLog::checkout_append_only`].
+    pub fn append(&mut self, agent: AgentId, content: &str) -> LV {
+        let pos = self.len();
+        self.insert(agent, pos, content)
+    }
+}
+
+impl ListOpLog {
+    /// Call visit(run, parents) for each insert, in local version order. Returns false if any
+    /// operation isn't an append.
+    fn visit_appends<F: FnMut(DTRange, &[LV])>(&self, mut visit: F) -> bool {
+        let graph = &self.cg.graph;
+
+        // Nothing is ever deleted, so the length of the document at each version is the number of
+        // operations in its history. This stores the length at each graph entry's parents.
+        let mut base_len: Vec<usize> = Vec::with_capacity(graph.entries.num_entries());
+        let len_at_lv = |base_len: &[usize], v: LV| -> usize {
+            let idx = graph.entries.find_index(v).unwrap();
+            base_len[idx] + v - graph.entries.0[idx].span.start + 1
+        };
+
+        for entry in graph.entries.iter() {
+            let len = match entry.parents.as_ref() {
+                [] => 0,
+                [first, ..] => {
+                    let (extra, _) = graph.diff(entry.parents.as_ref(), &[*first]);
+                    len_at_lv(&base_len, *first) + extra.iter().map(|span| span.len()).sum::<usize>()
+                }
+            };
+            base_len.push(len);
+
+            for (KVPair(lv, op), _) in self.iter_range_simple(entry.span) {
+                let expected_pos = len + lv - entry.span.start;
+                if op.kind != ListOpKind::Ins || op.start() != expected_pos || (!op.loc.fwd && op.len() > 1) {
+                    return false;
+                }
+
+                let span: DTRange = (lv..lv + op.len()).into();
+                if lv == entry.span.start {
+                    visit(span, entry.parents.as_ref());
+                } else {
+                    visit(span, &[lv - 1]);
+                }
+            }
+        }
+        true
+    }
+
+    /// Returns true if every operation in the oplog inserts content at the end of the document.
+    pub fn is_append_only(&self) -> bool {
+        self.visit_appends(|_, _| {})
+    }
+
+    /// Check out the current version of an append-only document, without using the general
+    /// merge algorithm. See the [`append_log`](crate::list::append_log) module for details.
+    ///
+    /// Returns None if the document isn't append-only.
+    pub fn checkout_append_only(&self) -> Option<ListBranch> {
+        let aa = &self.cg.agent_assignment;
+        let mut runs: Vec<AppendRun> = vec![];
+        let find_run = |runs: &[AppendRun], v: LV| -> usize {
+            runs.iter().rposition(|run| run.span.contains(v)).unwrap()
+        };
+
+        let is_append_only = self.visit_appends(|span, parents| {
+            // The left origin is the item in parents which is last in the document.
+            let found = runs.iter().enumerate().rev().find_map(|(i, run)| {
+                parents.iter().find(|p| run.span.contains(**p)).map(|p| (i, *p))
+            });
+            let (mut idx, origin_left) = match found {
+                Some((i, p)) => {
+                    if p + 1 < runs[i].span.end {
+                        let mut rest = runs[i];
+                        rest.span.start = p + 1;
+                        rest.origin_left = Some(p);
+                        runs[i].span.end = p + 1;
+                        runs.insert(i + 1, rest);
+                    }
+                    (i + 1, Some(p))
+                }
+                None => (0, None),
+            };
+
+            // Skip past concurrent runs which belong before this one. This matches the ordering
+            // used by the merge tracker (YjsMod), where every append has the same right origin.
+            let scan_start = idx;
+            while idx < runs.len() {
+                let other = runs[idx];
+                let cmp = match (other.origin_left, origin_left) {
+                    (a, b) if a == b => Ordering::Equal,
+                    (None, _) => Ordering::Less,
+                    (Some(_), None) => Ordering::Greater,
+                    (Some(other_left), Some(_)) => {
+                        // The other run's origin is either something we've skipped past, or it's
+                        // before our origin.
+                        let i = find_run(&runs, other_left);
+                        if i >= scan_start { Ordering::Greater } else { Ordering::Less }
+                    }
+                };
+                match cmp {
+                    Ordering::Less => break,
+                    Ordering::Equal if self.tie_break.cmp(aa, span.start, other.span.start) == Ordering::Less => break,
+                    _ => idx += 1,
+                }
+            }
+
+            let new_run = AppendRun { span, origin_left };
+            match idx.checked_sub(1).map(|i| &mut runs[i]) {
+                Some(prev) if prev.span.end == span.start && origin_left == Some(span.start - 1) => {
+                    prev.span.end = span.end;
+                }
+                _ => runs.insert(idx, new_run),
+            }
+        });
+        if !is_append_only { return None; }
+
+        let mut content = String::new();
+        for run in runs.iter() {
+            for (_, c) in self.iter_range_simple(run.span) {
+                content.push_str(c.expect("Inserted content is not stored in the oplog"));
+            }
+        }
+
+        Some(ListBranch::new_with_content(self.cg.version.clone(), content.as_str().into()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::prelude::*;
+    use crate::list::{ListCRDT, ListOpLog};
+
+    #[test]
+    fn append_only() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        let mike = doc.get_or_create_agent_id("mike");
+        doc.append(seph, "hi\n");
+        let a = doc.append(mike, "hello\n");
+        doc.append(seph, "how are you?\n");
+        doc.oplog.add_insert_at(mike, &[a], 9, "good\n");
+
+        assert!(doc.oplog.is_append_only());
+        let branch = doc.oplog.checkout_append_only().unwrap();
+        assert_eq!(branch, doc.oplog.checkout_tip());
+
+        doc.delete(seph, 0..1);
+        assert!(!doc.oplog.is_append_only());
+        assert!(doc.oplog.checkout_append_only().is_none());
+
+        let mut oplog = ListOpLog::new();
+        oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(0, 0, "abc");
+        oplog.add_insert(0, 1, "x");
+        assert!(!oplog.is_append_only());
+    }
+
+    #[test]
+    fn append_only_fuzz() {
+        for seed in 0..100 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let mut oplog = ListOpLog::new();
+            let agents = ["a", "b", "c"].map(|name| oplog.get_or_create_agent_id(name));
+            let mut branches = [oplog.checkout_tip(), oplog.checkout_tip(), oplog.checkout_tip()];
+
+            for _ in 0..30 {
+                let i = rng.gen_range(0..branches.len());
+                let len = rng.gen_range(1..4);
+                let content: String = (0..len).map(|_| rng.gen_range('a'..='z')).collect();
+                let pos = branches[i].len();
+                branches[i].insert(&mut oplog, agents[i], pos, &content);
+
+                if rng.gen_bool(0.3) {
+                    let j = rng.gen_range(0..branches.len());
+                    let v = branches[j].local_frontier();
+                    branches[i].merge(&oplog, v.as_ref());
+                }
+            }
+
+            assert!(oplog.is_append_only());
+            assert_eq!(oplog.checkout_append_only().unwrap(), oplog.checkout_tip(), "seed {}", seed);
+        }
+    }
+}
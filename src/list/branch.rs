@@ -1,23 +1,107 @@
-use std::ops::Range;
-use jumprope::{JumpRope, JumpRopeBuf};
+use alloc::string::{String, ToString};
+use core::fmt::{Debug, Display, Formatter};
+use core::ops::{Deref, Range};
+use jumprope::JumpRope;
 use crate::list::{ListBranch, ListOpLog};
 use smartstring::SmartString;
 use crate::list::list::{apply_local_operations};
 use crate::list::operation::ListOpKind::*;
 use crate::list::operation::{TextOperation, ListOpKind};
 use crate::dtrange::DTRange;
-use crate::{AgentId, Frontier, LV};
+use crate::{AgentId, DTError, Frontier, LV};
+use smallvec::SmallVec;
+use crate::unicount::count_chars;
 use crate::causalgraph::agent_assignment::remote_ids::RemoteFrontier;
 
+/// The content of a [`ListBranch`]. This is a read-only view of the branch's rope, and it
+/// dereferences to a [`JumpRope`].
+///
+/// Branches used to store their content in a [`JumpRopeBuf`](jumprope::JumpRopeBuf), and this type
+/// has the same read methods (`borrow`, `len_chars`, comparisons, etc) so code written against it
+/// keeps working. The content isn't buffered anymore, since a `JumpRopeBuf` flushes its pending
+/// edits when it's read through a shared reference, which isn't safe across threads. Edits are
+/// applied straight to the rope instead.
+#[derive(Clone, Eq, PartialEq)]
+pub struct BranchContent(JumpRope);
+
+impl BranchContent {
+    /// The branch's rope. This is the same as dereferencing the content, and is here for code
+    /// which called [`JumpRopeBuf::borrow`](jumprope::JumpRopeBuf::borrow).
+    #[allow(clippy::should_implement_trait)]
+    pub fn borrow(&self) -> &JumpRope { &self.0 }
+
+    /// Take the branch's rope.
+    pub fn into_inner(self) -> JumpRope { self.0 }
+}
+
+impl Deref for BranchContent {
+    type Target = JumpRope;
+
+    fn deref(&self) -> &JumpRope { &self.0 }
+}
+
+impl From<JumpRope> for BranchContent {
+    fn from(rope: JumpRope) -> Self { Self(rope) }
+}
+
+impl From<BranchContent> for JumpRope {
+    fn from(content: BranchContent) -> Self { content.0 }
+}
+
+impl Display for BranchContent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result { Display::fmt(&self.0, f) }
+}
+
+impl Debug for BranchContent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result { Debug::fmt(&self.0, f) }
+}
+
+impl PartialEq<JumpRope> for BranchContent {
+    fn eq(&self, other: &JumpRope) -> bool { self.0 == *other }
+}
+
+impl PartialEq<BranchContent> for JumpRope {
+    fn eq(&self, other: &BranchContent) -> bool { *self == other.0 }
+}
+
+impl PartialEq<str> for BranchContent {
+    fn eq(&self, other: &str) -> bool { self.0 == *other }
+}
+
+impl PartialEq<&str> for BranchContent {
+    fn eq(&self, other: &&str) -> bool { self.0 == **other }
+}
+
+impl PartialEq<String> for BranchContent {
+    fn eq(&self, other: &String) -> bool { self.0 == *other }
+}
+
+impl PartialEq<BranchContent> for str {
+    fn eq(&self, other: &BranchContent) -> bool { other.0 == *self }
+}
+
+impl PartialEq<BranchContent> for &str {
+    fn eq(&self, other: &BranchContent) -> bool { other.0 == **self }
+}
+
+impl PartialEq<BranchContent> for String {
+    fn eq(&self, other: &BranchContent) -> bool { other.0 == *self }
+}
+
 impl ListBranch {
     /// Create a new (empty) branch at the start of history. The branch will be an empty list.
     pub fn new() -> Self {
         Self {
             version: Frontier::root(),
-            content: JumpRopeBuf::new(),
+            content: BranchContent(JumpRope::new()),
+            snapshot_base: None,
         }
     }
 
+    pub(crate) fn new_with_content(version: Frontier, content: JumpRope) -> Self {
+        Self { version, content: BranchContent(content), snapshot_base: None }
+    }
+
     /// Create a new branch as a checkout from the specified oplog, at the specified local time.
     /// This method equivalent to calling [`oplog.checkout(version)`](OpLog::checkout).
     pub fn new_at_local_version(oplog: &ListOpLog, version: &[LV]) -> Self {
@@ -48,7 +132,7 @@ impl ListBranch {
     /// Return the current document contents. Note there is no mutable variant of this method
     /// because mutating the document's content directly would violate the constraint that all
     /// changes must bump the document's version.
-    pub fn content(&self) -> &JumpRopeBuf { &self.content }
+    pub fn content(&self) -> &BranchContent { &self.content }
 
     /// Returns the document's content length.
     ///
@@ -62,15 +146,31 @@ impl ListBranch {
         self.content.is_empty()
     }
 
+    /// Insert into the branch's content. This method does not update the version.
+    pub(crate) fn insert_content(&mut self, pos: usize, content: &str) {
+        self.content.0.insert(pos, content);
+        if let Some(base) = self.snapshot_base.as_mut() {
+            if !base.record(TextOperation::new_insert(pos, content)) { self.snapshot_base = None; }
+        }
+    }
+
+    /// Remove from the branch's content. This method does not update the version.
+    pub(crate) fn remove_content(&mut self, range: Range<usize>) {
+        if let Some(base) = self.snapshot_base.as_mut() {
+            if !base.record(TextOperation::new_delete(range.clone())) { self.snapshot_base = None; }
+        }
+        self.content.0.remove(range);
+    }
+
     /// Apply a single operation. This method does not update the version.
     fn apply_internal(&mut self, kind: ListOpKind, pos: DTRange, content: Option<&str>) {
         match kind {
             Ins => {
-                self.content.insert(pos.start, content.unwrap());
+                self.insert_content(pos.start, content.unwrap());
             }
 
             Del => {
-                self.content.remove(pos.into());
+                self.remove_content(pos.into());
             }
         }
     }
@@ -95,7 +195,7 @@ impl ListBranch {
     pub fn make_delete_op(&self, loc: Range<usize>) -> TextOperation {
         assert!(loc.end <= self.content.len_chars());
         let mut s = SmartString::new();
-        s.extend(self.content.borrow().slice_chars(loc.clone()));
+        s.extend(self.content.slice_chars(loc.clone()));
         TextOperation::new_delete_with_content_range(loc, s)
     }
 
@@ -103,6 +203,37 @@ impl ListBranch {
         apply_local_operations(oplog, self, agent, ops)
     }
 
+    /// Apply a batch of patches to the branch, and record them in the oplog. Each patch replaces
+    /// a range of the document with new content. Like operations, each patch's range is relative
+    /// to the document after the previous patches have been applied.
+    ///
+    /// All the patches are validated before anything is changed, so either every patch is applied
+    /// or none of them are. The new operations are contiguous, so they're stored as a single
+    /// entry in the causal graph. This is useful for editors applying IME composition updates.
+    ///
+    /// Returns the new version of the branch, or [`DTError::PatchOutOfBounds`] naming the first
+    /// patch with an invalid range.
+    pub fn apply_local_patches(&mut self, oplog: &mut ListOpLog, agent: AgentId, patches: &[(Range<usize>, &str)]) -> Result<Frontier, DTError> {
+        let mut len = self.len();
+        for (index, (range, content)) in patches.iter().enumerate() {
+            if range.start > range.end || range.end > len {
+                return Err(DTError::PatchOutOfBounds { index });
+            }
+            len = len - range.len() + count_chars(content);
+        }
+
+        for (range, content) in patches {
+            let mut ops: SmallVec<[TextOperation; 2]> = SmallVec::new();
+            if !range.is_empty() { ops.push(self.make_delete_op(range.clone())); }
+            if !content.is_empty() { ops.push(TextOperation::new_insert(range.start, content)); }
+            if !ops.is_empty() {
+                apply_local_operations(oplog, self, agent, &ops);
+            }
+        }
+
+        Ok(self.local_frontier())
+    }
+
     pub fn insert(&mut self, oplog: &mut ListOpLog, agent: AgentId, pos: usize, ins_content: &str) -> LV {
         // The internal_do_insert / do_delete methods require that the branch is at the same version
         // as the oplog.
@@ -122,16 +253,15 @@ impl ListBranch {
 
     #[cfg(feature = "wchar_conversion")]
     pub fn insert_at_wchar(&mut self, oplog: &mut ListOpLog, agent: AgentId, wchar_pos: usize, ins_content: &str) -> LV {
-        let char_pos = self.content.borrow().wchars_to_chars(wchar_pos);
+        let char_pos = self.content.wchars_to_chars(wchar_pos);
         self.insert(oplog, agent, char_pos, ins_content)
     }
 
     #[cfg(feature = "wchar_conversion")]
     pub fn delete_at_wchar(&mut self, oplog: &mut ListOpLog, agent: AgentId, del_span_wchar: Range<usize>) -> LV {
-        let c = self.content.borrow();
+        let c = &self.content;
         let start_pos = c.wchars_to_chars(del_span_wchar.start);
         let end_pos = c.wchars_to_chars(del_span_wchar.end);
-        drop(c);
         apply_local_operations(oplog, self, agent, &[self.make_delete_op(start_pos .. end_pos)])
     }
 
@@ -191,4 +321,48 @@ mod test {
 
         oplog.dbg_check(true);
     }
+
+    #[test]
+    fn content_reads_like_a_buffered_rope() {
+        // Code written when branches stored a JumpRopeBuf should still work.
+        let mut oplog = ListOpLog::new();
+        oplog.get_or_create_agent_id("seph");
+        let mut branch = oplog.checkout(&[]);
+        branch.insert(&mut oplog, 0, 0, "hi there");
+
+        let content = branch.content();
+        assert_eq!(content.borrow().len_chars(), 8);
+        assert_eq!(content.len_chars(), 8);
+        assert_eq!(content.borrow().slice_chars(0..2).collect::<String>(), "hi");
+        assert_eq!(*content, "hi there");
+        assert_eq!(content.to_string(), "hi there");
+        assert_eq!(JumpRope::from(content.clone()), *content.borrow());
+    }
+
+    #[test]
+    fn apply_local_patches() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mut branch = oplog.checkout_tip();
+        branch.insert(&mut oplog, seph, 0, "hello world");
+        let entries = oplog.cg.graph.entries.num_entries();
+
+        let v = branch.apply_local_patches(&mut oplog, seph, &[(0..5, "hi"), (2..2, " there,"), (15..15, "!")]).unwrap();
+        assert_eq!(branch.content, "hi there, world!");
+        assert_eq!(v, oplog.cg.version);
+        assert_eq!(oplog.cg.graph.entries.num_entries(), entries);
+        assert_eq!(oplog.checkout_tip().content, "hi there, world!");
+        oplog.dbg_check(true);
+
+        // Invalid patches are rejected, and nothing is applied.
+        let len = oplog.len();
+        #[allow(clippy::reversed_empty_ranges)]
+        let backwards = 3..2;
+        assert_eq!(branch.apply_local_patches(&mut oplog, seph, &[(0..1, "H"), (backwards, "")]),
+                   Err(DTError::PatchOutOfBounds { index: 1 }));
+        assert_eq!(branch.apply_local_patches(&mut oplog, seph, &[(0..16, ""), (0..1, "")]),
+                   Err(DTError::PatchOutOfBounds { index: 1 }));
+        assert_eq!(oplog.len(), len);
+        assert_eq!(branch.content, "hi there, world!");
+    }
 }
\ No newline at end of file
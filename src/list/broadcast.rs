@@ -0,0 +1,95 @@
+//! Tools for relay servers, which need to forward new changes to many subscribers.
+//!
+//! Each subscriber has a document at some version. When new operations arrive, every subscriber
+//! needs those operations transformed relative to their own version. Usually most subscribers are
+//! up to date, so they all share the same version. [`ListOpLog::prepare_broadcast`] only
+//! transforms the new operations once for each distinct subscriber version.
+//!
+//! Each distinct version still costs a full transform (a separate walk of the operations which
+//! are concurrent with that version). Subscribers which are far behind, or which are all at
+//! different versions, cost about the same as merging for each of them separately.
+
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
+use smallvec::SmallVec;
+use crate::{DTRange, Frontier, LV};
+use crate::list::ListOpLog;
+use crate::list::encoding::EncodeFilter;
+use crate::list::operation::TextOperation;
+
+/// The changes a subscriber needs to apply to their document to bring it up to date.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct Patch {
+    /// The version of the subscriber's document after the patch has been applied.
+    pub version: Frontier,
+    /// Transformed operations, which should be applied to the document in order.
+    pub ops: Vec<TextOperation>,
+}
+
+impl ListOpLog {
+    /// Prepare the patches needed to send the operations in `new_range` to a set of subscribers,
+    /// each of which has a document at the corresponding frontier. Returns one patch per
+    /// subscriber.
+    ///
+    /// Patches also include any operations in the history of `new_range` which the subscriber
+    /// doesn't have yet. Subscribers which already have all the new operations get an empty patch.
+    ///
+    /// This runs one transform for each distinct subscriber frontier, so the cost grows with the
+    /// number of different versions, not the number of subscribers.
+    pub fn prepare_broadcast(&self, new_range: DTRange, subscriber_frontiers: &[Frontier]) -> Vec<Patch> {
+        let new_version = self.filter_version(EncodeFilter::Spans(&[new_range]));
+        let mut patches: BTreeMap<&[LV], Patch> = BTreeMap::new();
+
+        subscriber_frontiers.iter().map(|from| {
+            patches.entry(from.as_ref()).or_insert_with(|| {
+                let versions: SmallVec<[LV; 4]> = from.iter().chain(new_version.iter()).copied().collect();
+                let merging = self.cg.graph.find_dominators(&versions);
+                if merging == *from {
+                    return Patch { version: merging, ops: vec![] };
+                }
+
+                let ops = self.iter_xf_operations_from(from.as_ref(), merging.as_ref())
+                    .filter_map(|(_, op)| op)
+                    .collect();
+                Patch { version: merging, ops }
+            }).clone()
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Frontier;
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn broadcast_matches_merge() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "hello");
+        let b = oplog.add_insert_at(mike, &[a], 0, "yo ");
+        let c = oplog.add_insert_at(seph, &[a], 5, " world");
+        // New operation merging b and c.
+        let start = oplog.len();
+        oplog.add_delete_at(seph, &[b, c], 0..3);
+        let new_range = (start..oplog.len()).into();
+
+        let subscribers = [
+            Frontier::new_1(a), Frontier::from_sorted(&[b, c]), Frontier::new_1(b),
+            Frontier::new_1(a), Frontier::root(), oplog.cg.version.clone(),
+        ];
+        let patches = oplog.prepare_broadcast(new_range, &subscribers);
+        assert_eq!(patches.len(), subscribers.len());
+        assert_eq!(patches[0], patches[3]);
+        assert!(patches[5].ops.is_empty());
+
+        for (from, patch) in subscribers.iter().zip(patches.iter()) {
+            let mut branch = oplog.checkout(from.as_ref());
+            branch.apply(&patch.ops);
+            let expected = oplog.checkout(patch.version.as_ref());
+            assert_eq!(branch.content().to_string(), expected.content().to_string());
+            assert!(oplog.cg.graph.frontier_contains_version(patch.version.as_ref(), start));
+        }
+    }
+}
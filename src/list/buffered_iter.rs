@@ -1,4 +1,4 @@
-use std::ops::Deref;
+use core::ops::Deref;
 
 /// This is a simple iterator wrapper which has a buffer, and allows an item to be "put back" on
 /// the iterator.
@@ -0,0 +1,192 @@
+//! An opt-in cache of document snapshots, to make repeated checkouts of nearby versions fast.
+//!
+//! Checking out a version normally replays every operation from the start of history. Thats fine
+//! for one-off checkouts, but its very slow for things like history scrubbing UIs, which check out
+//! lots of versions which are all close to one another. When the cache is enabled,
+//! [`ListOpLog::checkout_cached`] starts from the closest cached snapshot which is an ancestor of
+//! the requested version, and only merges the difference.
+//!
+//! Oplogs are append-only, so cached snapshots stay valid as new operations are added. The
+//! exception is [`ListOpLog::redact`], which rewrites the content of existing operations. Redacting
+//! discards every cached snapshot (the cache stays enabled, and refills on later checkouts).
+
+use alloc::vec::Vec;
+use jumprope::JumpRope;
+use rle::HasLength;
+use crate::{Frontier, LV};
+use crate::list::{ListBranch, ListOpLog};
+
+#[derive(Debug, Clone)]
+struct CachedCheckout {
+    version: Frontier,
+    content: JumpRope,
+    last_used: u64,
+}
+
+/// A size-bounded LRU cache of checked out document states.
+#[derive(Debug, Clone)]
+pub(crate) struct CheckoutCache {
+    max_entries: usize,
+    entries: Vec<CachedCheckout>,
+    /// Incremented on each access. Used to find the least recently used entry.
+    clock: u64,
+}
+
+impl CheckoutCache {
+    fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            entries: Vec::with_capacity(max_entries),
+            clock: 0,
+        }
+    }
+
+    fn evict_lru(&mut self) {
+        let (idx, _) = self.entries.iter().enumerate()
+            .min_by_key(|(_, e)| e.last_used)
+            .unwrap();
+        self.entries.swap_remove(idx);
+    }
+
+    fn insert(&mut self, version: Frontier, content: JumpRope) {
+        self.clock += 1;
+        if let Some(e) = self.entries.iter_mut().find(|e| e.version == version) {
+            e.last_used = self.clock;
+            return;
+        }
+
+        if self.entries.len() >= self.max_entries {
+            self.evict_lru();
+        }
+
+        self.entries.push(CachedCheckout {
+            version,
+            content,
+            last_used: self.clock,
+        });
+    }
+}
+
+impl ListOpLog {
+    /// Enable the checkout cache, storing up to `max_entries` document snapshots. If the cache is
+    /// already enabled, this resizes it (evicting snapshots as needed).
+    ///
+    /// Each snapshot stores a full copy of the document, so keep max_entries small.
+    pub fn enable_checkout_cache(&mut self, max_entries: usize) {
+        assert!(max_entries > 0);
+
+        let cache = self.checkout_cache.get_or_insert_with(|| CheckoutCache::new(max_entries));
+        cache.max_entries = max_entries;
+        while cache.entries.len() > max_entries {
+            cache.evict_lru();
+        }
+    }
+
+    /// Discard any cached snapshots, leaving the cache enabled.
+    pub(crate) fn clear_checkout_cache(&mut self) {
+        if let Some(cache) = self.checkout_cache.as_mut() {
+            cache.entries.clear();
+        }
+    }
+
+    /// Disable the checkout cache and discard any cached snapshots.
+    pub fn disable_checkout_cache(&mut self) {
+        self.checkout_cache = None;
+    }
+
+    /// The number of document snapshots currently in the checkout cache.
+    pub fn checkout_cache_len(&self) -> usize {
+        self.checkout_cache.as_ref().map_or(0, |c| c.entries.len())
+    }
+
+    /// Check out the document at the specified version, using (and filling) the checkout cache.
+    /// This returns the same result as [`checkout`](ListOpLog::checkout), but when the cache is
+    /// enabled and contains a nearby ancestor of the requested version, its much faster.
+    ///
+    /// If the cache is not enabled, this is equivalent to calling `checkout()`.
+    pub fn checkout_cached(&mut self, local_version: &[LV]) -> ListBranch {
+        let Some(cache) = self.checkout_cache.as_ref() else {
+            return self.checkout(local_version);
+        };
+
+        // Find the cached snapshot which needs the fewest operations merged to reach the requested
+        // version. We can only merge forwards in time, so only ancestors are considered. The start
+        // of history is an (empty) ancestor of everything.
+        let graph = &self.cg.graph;
+        let mut best: Option<usize> = None;
+        let mut best_cost = graph.diff(&[], local_version).1.iter()
+            .map(|r| r.len())
+            .sum::<usize>();
+
+        for (i, e) in cache.entries.iter().enumerate() {
+            if best_cost == 0 { break; }
+            if !graph.frontier_contains_frontier(local_version, e.version.as_ref()) { continue; }
+
+            let cost = graph.diff(e.version.as_ref(), local_version).1.iter()
+                .map(|r| r.len())
+                .sum::<usize>();
+            if cost < best_cost {
+                best = Some(i);
+                best_cost = cost;
+            }
+        }
+
+        let mut branch = match best {
+            Some(i) => {
+                let e = &cache.entries[i];
+                ListBranch::new_with_content(e.version.clone(), e.content.clone())
+            }
+            None => ListBranch::new(),
+        };
+        branch.merge(self, local_version);
+
+        let cache = self.checkout_cache.as_mut().unwrap();
+        if let Some(i) = best {
+            // Mark the snapshot we started from as used, so its not evicted by the new entry.
+            cache.clock += 1;
+            cache.entries[i].last_used = cache.clock;
+        }
+        cache.insert(branch.version.clone(), branch.content.borrow().clone());
+
+        branch
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn cached_checkouts_match() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let mut versions = vec![];
+        for i in 0..10 {
+            versions.push(oplog.add_insert(seph, i, "a"));
+        }
+        let fork = versions[4];
+        versions.push(oplog.add_insert_at(mike, &[fork], 0, "mike"));
+        versions.push(oplog.add_delete_at(mike, &[fork], 1..3));
+
+        // Without the cache, checkout_cached() just calls checkout().
+        assert_eq!(oplog.checkout_cached(&[versions[3]]), oplog.checkout(&[versions[3]]));
+        assert_eq!(oplog.checkout_cache_len(), 0);
+
+        oplog.enable_checkout_cache(3);
+        // Scrub back and forth through history.
+        for &v in versions.iter().chain(versions.iter().rev()) {
+            let expect = oplog.checkout(&[v]);
+            assert_eq!(oplog.checkout_cached(&[v]), expect);
+            assert!(oplog.checkout_cache_len() <= 3);
+        }
+
+        let tip = oplog.cg.version.clone();
+        assert_eq!(oplog.checkout_cached(tip.as_ref()), oplog.checkout_tip());
+
+        oplog.enable_checkout_cache(1);
+        assert_eq!(oplog.checkout_cache_len(), 1);
+        oplog.disable_checkout_cache();
+        assert_eq!(oplog.checkout_cache_len(), 0);
+    }
+}
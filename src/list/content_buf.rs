@@ -0,0 +1,379 @@
+//! Storage for the inserted and deleted content in an oplog.
+//!
+//! Usually content is stored in a `Vec<u8>`. But an oplog can also be loaded with its content left
+//! in a [`ContentStore`] (see [`ListOpLog::load_with_content_store`]). Content is then only read
+//! from the store when it's needed - for example when checking out the document, or transforming
+//! operations with their content. Most operations in a long history are never looked at again, so
+//! their content never needs to be in memory.
+//!
+//! Memory mapped files implement `ContentStore` (see [`ListOpLog::open_mmap`]). Other stores can
+//! fetch content from disk or over the network on demand.
+//!
+//! Content is copied into memory the first time anything is appended which isn't the next part of
+//! the store.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{Debug, Formatter};
+use core::ops::{Deref, Range};
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use rle::HasLength;
+use crate::dtrange::DTRange;
+use crate::list::ListOpLog;
+use crate::encoding::parseerror::ParseError;
+
+/// A source of bytes which an oplog's content can be read from, without holding it all in
+/// memory. See [`ListOpLog::load_with_content_store`].
+///
+/// Reads return borrowed bytes. Stores which fetch content on demand need to keep what they've
+/// fetched (eg in a chunked cache) for as long as the store lives.
+///
+/// The store must return the same bytes the oplog was loaded from. Content read from a store is
+/// checked before it's used as a string, and the oplog panics if a read returns the wrong number
+/// of bytes, or bytes which aren't valid UTF-8.
+pub trait ContentStore: Send + Sync {
+    /// The total number of bytes in the store.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Read a range of bytes. The range is always within `0..self.len()`.
+    fn read(&self, range: Range<usize>) -> &[u8];
+}
+
+impl ContentStore for Vec<u8> {
+    fn len(&self) -> usize { self.as_slice().len() }
+    fn read(&self, range: Range<usize>) -> &[u8] { &self[range] }
+}
+
+#[cfg(feature = "mmap")]
+impl ContentStore for Mmap {
+    fn len(&self) -> usize { self.deref().len() }
+    fn read(&self, range: Range<usize>) -> &[u8] { &self[range] }
+}
+
+#[derive(Clone)]
+pub(crate) enum ContentBuf {
+    Owned(Vec<u8>),
+
+    /// Content referenced from a byte range in a content store.
+    Stored {
+        store: Arc<dyn ContentStore>,
+        range: DTRange,
+
+        /// While the oplog is being loaded, the address and length of the bytes being decoded.
+        /// These have the same content as the store, so content which is a slice of them is
+        /// referenced from the store instead of being copied.
+        source: Option<(usize, usize)>,
+    },
+}
+
+impl Default for ContentBuf {
+    fn default() -> Self {
+        Self::Owned(Vec::new())
+    }
+}
+
+impl From<Vec<u8>> for ContentBuf {
+    fn from(v: Vec<u8>) -> Self {
+        Self::Owned(v)
+    }
+}
+
+impl Deref for ContentBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            ContentBuf::Owned(v) => v,
+            ContentBuf::Stored { store, range, .. } => store.read(range.start..range.end),
+        }
+    }
+}
+
+impl PartialEq for ContentBuf {
+    fn eq(&self, other: &Self) -> bool {
+        self.deref() == other.deref()
+    }
+}
+
+impl Eq for ContentBuf {}
+
+impl Debug for ContentBuf {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        self.deref().fmt(f)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for ContentBuf {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Serialized the same way as the Vec<u8> this replaced.
+        serializer.collect_seq(self.iter())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for ContentBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(Self::Owned)
+    }
+}
+
+impl ContentBuf {
+    fn new_stored(store: Arc<dyn ContentStore>, source: &[u8]) -> Self {
+        ContentBuf::Stored {
+            store,
+            range: (0..0).into(),
+            source: Some((source.as_ptr() as usize, source.len())),
+        }
+    }
+
+    /// The length of the content in bytes. Unlike `deref().len()`, this doesn't read from the
+    /// content store.
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            ContentBuf::Owned(v) => v.len(),
+            ContentBuf::Stored { range, .. } => range.len(),
+        }
+    }
+
+    pub(crate) fn is_owned(&self) -> bool {
+        matches!(self, ContentBuf::Owned(_))
+    }
+
+    /// Read a range of the content. Only the requested range is read from the content store.
+    pub(crate) fn slice(&self, r: DTRange) -> &[u8] {
+        match self {
+            ContentBuf::Owned(v) => &v[r.start..r.end],
+            ContentBuf::Stored { store, range, .. } => {
+                assert!(r.end <= range.len());
+                let bytes = store.read(range.start + r.start..range.start + r.end);
+                assert_eq!(bytes.len(), r.len(), "Content store returned the wrong number of bytes");
+                bytes
+            }
+        }
+    }
+
+    /// Get a mutable reference to the content, copying it out of the content store if needed.
+    fn to_mut(&mut self) -> &mut Vec<u8> {
+        if let ContentBuf::Stored { .. } = self {
+            *self = ContentBuf::Owned(self.deref().to_vec());
+        }
+
+        match self {
+            ContentBuf::Owned(v) => v,
+            ContentBuf::Stored { .. } => unreachable!(),
+        }
+    }
+
+    pub(crate) fn extend_from_slice(&mut self, bytes: &[u8]) {
+        if let ContentBuf::Stored { range, source: Some((start, len)), .. } = self {
+            // If the bytes are the next bytes in the source, we can just extend the range. When
+            // nothing has been referenced yet, the range can start anywhere.
+            let offset = (bytes.as_ptr() as usize).wrapping_sub(*start);
+            if (range.is_empty() || offset == range.end)
+                && offset.checked_add(bytes.len()).is_some_and(|end| end <= *len)
+            {
+                if range.is_empty() { range.start = offset; }
+                range.end = offset + bytes.len();
+                return;
+            }
+        }
+
+        self.to_mut().extend_from_slice(bytes);
+    }
+
+    pub(crate) fn truncate(&mut self, len: usize) {
+        match self {
+            ContentBuf::Owned(v) => v.truncate(len),
+            ContentBuf::Stored { range, .. } => {
+                range.end = range.end.min(range.start + len);
+            }
+        }
+    }
+
+    /// Stop referencing the bytes being decoded. Called once loading is done, since the memory
+    /// may be reused afterwards.
+    fn finish_loading(&mut self) {
+        if let ContentBuf::Stored { source, .. } = self {
+            *source = None;
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_stored(&self) -> bool {
+        matches!(self, ContentBuf::Stored { .. })
+    }
+}
+
+impl ListOpLog {
+    /// Load an oplog, leaving its inserted and deleted content in a [`ContentStore`]. Content is
+    /// only read from the store when it's needed. See the [`ContentStore`] docs for details.
+    ///
+    /// The store must contain exactly the same bytes as `data` - for example, a store which reads
+    /// from the file `data` was loaded from. `data` isn't needed once this function returns.
+    ///
+    /// The content can only be referenced from the store if it was stored uncompressed (ie, the
+    /// file was encoded with [`Compression::None`](crate::list::encoding::Compression::None)).
+    /// Compressed content is copied into memory. Content is also copied into memory the first
+    /// time new operations are added to the oplog.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the store isn't the same length as `data`.
+    pub fn load_with_content_store(data: &[u8], store: Arc<dyn ContentStore>) -> Result<Self, ParseError> {
+        assert_eq!(data.len(), store.len(), "Content store doesn't match the data being loaded");
+
+        let mut oplog = Self::new();
+        oplog.operation_ctx.ins_content = ContentBuf::new_stored(store.clone(), data);
+        oplog.operation_ctx.del_content = ContentBuf::new_stored(store, data);
+        let result = oplog.decode_and_add(data);
+        oplog.operation_ctx.ins_content.finish_loading();
+        oplog.operation_ctx.del_content.finish_loading();
+        result?;
+        Ok(oplog)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl ListOpLog {
+    /// Open an oplog from a file, without copying the inserted and deleted content into memory.
+    /// Content is instead read from the file as needed through a read-only memory map. This
+    /// makes loading large documents use much less memory.
+    ///
+    /// This is [`load_with_content_store`](ListOpLog::load_with_content_store), with the memory
+    /// map as the content store.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated (by this process or any other) while the oplog
+    /// or anything cloned from it is alive. The oplog borrows content straight out of the memory
+    /// map, so changing the file changes memory Rust assumes is immutable. See
+    /// [`Mmap::map`](memmap2::Mmap::map).
+    pub unsafe fn open_mmap<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: The caller promises the file isn't modified while the map is in use.
+        let map = Arc::new(unsafe { Mmap::map(&file)? });
+
+        Self::load_with_content_store(&map, map.clone())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::ops::Range;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::list::encoding::{Compression, ENCODE_FULL, EncodeOptions};
+    use crate::list::{ContentStore, ListOpLog};
+
+    /// A content store which counts how many bytes have been read from it.
+    struct CountingStore {
+        data: Vec<u8>,
+        bytes_read: AtomicUsize,
+    }
+
+    impl ContentStore for CountingStore {
+        fn len(&self) -> usize { self.data.len() }
+
+        fn read(&self, range: Range<usize>) -> &[u8] {
+            self.bytes_read.fetch_add(range.len(), Ordering::Relaxed);
+            &self.data[range]
+        }
+    }
+
+    #[test]
+    fn content_is_read_on_demand() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let a = oplog.add_insert(seph, 0, "hello");
+        oplog.add_insert(seph, 5, &"x".repeat(10000));
+        oplog.add_delete_without_content(seph, 0..5000);
+
+        let data = oplog.encode(EncodeOptions {
+            compression: Compression::None,
+            ..ENCODE_FULL
+        });
+        let store = Arc::new(CountingStore { data: data.clone(), bytes_read: AtomicUsize::new(0) });
+        let result = ListOpLog::load_with_content_store(&data, store.clone()).unwrap();
+        drop(data);
+        assert!(result.operation_ctx.ins_content.is_stored());
+        assert_eq!(store.bytes_read.load(Ordering::Relaxed), 0);
+
+        // Only the content which is checked out gets read.
+        assert_eq!(result.checkout(&[a]).content().to_string(), "hello");
+        assert_eq!(store.bytes_read.load(Ordering::Relaxed), 5);
+        assert_eq!(result.checkout_tip(), oplog.checkout_tip());
+    }
+
+    #[test]
+    #[should_panic = "invalid UTF-8"]
+    fn store_content_is_checked() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "hello");
+        let data = oplog.encode(EncodeOptions {
+            compression: Compression::None,
+            ..ENCODE_FULL
+        });
+
+        // The store doesn't match the data which was loaded.
+        let mut store = data.clone();
+        let pos = store.windows(5).position(|w| w == b"hello").unwrap();
+        store[pos] = 0xff;
+        let result = ListOpLog::load_with_content_store(&data, Arc::new(store)).unwrap();
+        result.checkout_tip();
+    }
+
+    #[cfg(feature = "mmap")]
+    fn write_oplog(name: &str, oplog: &ListOpLog, compression: Compression) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("dt-{}-{name}.dt", std::process::id()));
+        let bytes = oplog.encode(EncodeOptions {
+            store_deleted_content: true,
+            compression,
+            ..ENCODE_FULL
+        });
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    #[cfg(feature = "mmap")]
+    fn open_mmap() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "hello world");
+        oplog.add_insert_at(mike, &[a], 5, " there");
+        oplog.add_delete_at(seph, &[a], 0..6);
+
+        let path = write_oplog("uncompressed", &oplog, Compression::None);
+        // SAFETY: Nothing else writes to the file.
+        let mut result = unsafe { ListOpLog::open_mmap(&path) }.unwrap();
+        assert!(result.operation_ctx.ins_content.is_stored());
+        assert!(result.operation_ctx.del_content.is_stored());
+        assert_eq!(result, oplog);
+
+        // Appending copies the content into memory.
+        oplog.add_insert(seph, 0, "yo ");
+        result.add_insert(seph, 0, "yo ");
+        assert!(!result.operation_ctx.ins_content.is_stored());
+        assert_eq!(result, oplog);
+        assert_eq!(result.checkout_tip(), oplog.checkout_tip());
+        std::fs::remove_file(&path).unwrap();
+
+        // Compressed files are loaded normally.
+        if cfg!(feature = "lz4") {
+            let path = write_oplog("lz4", &oplog, Compression::LZ4);
+            let result = unsafe { ListOpLog::open_mmap(&path) }.unwrap();
+            assert!(!result.operation_ctx.ins_content.is_stored());
+            assert_eq!(result, oplog);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! Interning of repeated inserted content.
+//!
+//! Documents often contain the same text inserted many times - from copy & paste, undo / redo, or
+//! templated edits. When a long enough string is inserted which is already stored somewhere in the
+//! content buffer, the operation references the existing copy instead of storing it again. This
+//! works because operations only name their content by byte range.
+//!
+//! Short strings aren't interned. Typing one character at a time would otherwise reference
+//! scattered copies of common letters, and stop adjacent operations from being run-length merged.
+//!
+//! The same index is used when encoding (see
+//! [`EncodeOptions::dedup_content`](crate::list::encoding::EncodeOptions::dedup_content)) to
+//! replace repeated content in the file with references to its first copy.
+
+use alloc::collections::BTreeMap;
+use smallvec::SmallVec;
+use crate::dtrange::DTRange;
+
+/// Strings shorter than this (in bytes) are never interned.
+pub(crate) const MIN_INTERNED_LEN: usize = 16;
+
+/// FNV-1a. The index only needs a cheap hash to find candidates - matches are always checked by
+/// comparing the content itself.
+fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// An index from the hash of each interned string to where it's stored in a content buffer.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ContentIndex(BTreeMap<u64, SmallVec<[DTRange; 1]>>);
+
+impl ContentIndex {
+    /// Find where `needle` is stored in `content`, or add it to the index at the range `append_at`
+    /// (where the caller is about to store it). Returns the existing range if one was found.
+    pub(crate) fn find_or_insert(&mut self, content: &[u8], needle: &[u8], append_at: usize) -> Option<DTRange> {
+        debug_assert!(needle.len() >= MIN_INTERNED_LEN);
+        let ranges = self.0.entry(content_hash(needle)).or_default();
+        if let Some(r) = ranges.iter().find(|r| &content[r.start..r.end] == needle) {
+            return Some(*r);
+        }
+        ranges.push((append_at..append_at + needle.len()).into());
+        None
+    }
+
+    /// Forget any strings stored past `len` bytes, after the content buffer is truncated.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.0.retain(|_, ranges| {
+            ranges.retain(|r| r.end <= len);
+            !ranges.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::content_intern::MIN_INTERNED_LEN;
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn repeated_inserts_are_stored_once() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let pasted = "x".repeat(MIN_INTERNED_LEN);
+        oplog.add_insert(seph, 0, &pasted);
+        oplog.add_insert(seph, 0, "ab");
+        oplog.add_insert(seph, 0, "ab");
+        oplog.add_insert(seph, 1, &pasted);
+        assert_eq!(oplog.operation_ctx.ins_content.len(), MIN_INTERNED_LEN + 4);
+
+        let (_, content) = oplog.iter_range_simple((20..36).into()).next().unwrap();
+        assert_eq!(content, Some(pasted.as_str()));
+        assert_eq!(oplog.checkout_tip().content().to_string(), format!("a{pasted}bab{pasted}"));
+    }
+}
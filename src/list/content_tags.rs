@@ -0,0 +1,90 @@
+//! Authentication tags for encrypted content.
+//!
+//! An oplog holding encrypted content (see `list::encryption`, behind the `encryption` feature)
+//! can't check the content it stores, since it doesn't have the key. Instead it keeps the tags
+//! which peers with the key send along with their changes, and passes them on when it's encoded.
+//! Peers with the key check the tags before they decrypt anything.
+//!
+//! Each agent's operations are split into blocks of [`TAG_BLOCK`] sequence numbers. A tag covers
+//! the first `len` operations of a block. Only the tags for the longest tagged prefix of each block
+//! are kept.
+
+use alloc::collections::btree_map::Entry;
+use alloc::collections::{BTreeMap, BTreeSet};
+use rle::HasLength;
+use smallvec::{smallvec, SmallVec};
+use crate::{AgentId, DTRange};
+use crate::list::ListOpLog;
+
+/// The number of operations from an agent covered by each tag.
+pub(crate) const TAG_BLOCK: usize = 64;
+
+pub(crate) const TAG_LEN: usize = 16;
+
+pub(crate) type ContentTag = [u8; TAG_LEN];
+
+/// An agent, and the index of a block of its operations.
+pub(crate) type TagBlock = (AgentId, usize);
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct ContentTags {
+    /// Map from (agent, block) to the length of the block's tagged prefix, and the tags for it.
+    /// There's usually one tag. Redacting content changes the tag of its block, so a block can
+    /// have a tag from before and after the redaction.
+    pub(crate) entries: BTreeMap<TagBlock, (usize, SmallVec<[ContentTag; 1]>)>,
+}
+
+impl ContentTags {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub(crate) fn merge_entry(&mut self, block: TagBlock, len: usize, tag: ContentTag) {
+        match self.entries.entry(block) {
+            Entry::Vacant(e) => { e.insert((len, smallvec![tag])); }
+            Entry::Occupied(mut e) => {
+                let (old_len, tags) = e.get_mut();
+                if len > *old_len {
+                    *old_len = len;
+                    tags.clear();
+                }
+                if len == *old_len && !tags.contains(&tag) { tags.push(tag); }
+            }
+        }
+    }
+}
+
+impl ListOpLog {
+    /// The (agent, block) pairs whose tags are sent along with the operations in `ranges`. This is
+    /// every block containing one of the operations, and every block containing redacted
+    /// operations - since redacting content changes its tag.
+    pub(crate) fn content_tag_blocks(&self, ranges: &[DTRange]) -> BTreeSet<TagBlock> {
+        let mut blocks = BTreeSet::new();
+        for mut range in ranges.iter().chain(self.redactions.iter()).copied() {
+            while !range.is_empty() {
+                let span = self.cg.agent_assignment.local_span_to_agent_span(range);
+                let seqs = span.seq_range;
+                for block in seqs.start / TAG_BLOCK..=(seqs.end - 1) / TAG_BLOCK {
+                    blocks.insert((span.agent, block));
+                }
+                range.start += seqs.len();
+            }
+        }
+        blocks
+    }
+
+    /// The number of operations in an agent's block which this oplog contains, counting from the
+    /// start of the block.
+    pub(crate) fn content_tag_block_len(&self, agent: AgentId, block: usize) -> usize {
+        let client_data = &self.cg.agent_assignment.client_data[agent as usize];
+        let start = block * TAG_BLOCK;
+        let mut seq = start;
+        while seq < start + TAG_BLOCK {
+            match client_data.try_seq_to_lv_span((seq..start + TAG_BLOCK).into()) {
+                Some(lvs) => seq += lvs.len(),
+                None => break,
+            }
+        }
+        seq - start
+    }
+}
@@ -0,0 +1,111 @@
+//! Checking whether two oplogs converge.
+//!
+//! Two peers which have seen the same operations should always end up with the same document. When
+//! changes are imported from another system, or synced by a custom transport, bugs can break this.
+//! For example, an importer might give two different operations the same ID.
+//! [`ListOpLog::converges_with`] merges two oplogs both ways and checks that the results match, to
+//! help test that kind of code.
+
+use alloc::string::{String, ToString};
+use rle::HasLength;
+use crate::{DTRange, Frontier, LV};
+use crate::causalgraph::agent_assignment::remote_ids::RemoteVersionOwned;
+use crate::list::{ListBranch, ListOpLog};
+
+/// Where two oplogs diverge. Returned by [`ListOpLog::converges_with`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Divergence {
+    /// The first operation after which the merged documents differ. This is None if every version
+    /// matches, but the tips don't - eg because the oplogs have different suggestions.
+    pub version: Option<RemoteVersionOwned>,
+    /// This oplog's document (merged with the other oplog) at that version.
+    pub ours: String,
+    /// The other oplog's document (merged with this oplog) at that version.
+    pub theirs: String,
+}
+
+/// Map a frontier of one oplog to another oplog which contains the same operations.
+fn map_frontier(from: &ListOpLog, to: &ListOpLog, frontier: &[LV]) -> Frontier {
+    Frontier::from_unsorted_iter(frontier.iter().map(|&v| {
+        let rv = from.cg.agent_assignment.local_to_remote_version(v);
+        to.cg.agent_assignment.remote_to_local_version(rv)
+    }))
+}
+
+impl ListOpLog {
+    /// Check that this oplog and `other` converge. Each oplog is merged with the other one, and the
+    /// [mainline content](ListOpLog::checkout_mainline) of the merged documents is compared.
+    ///
+    /// If they differ, the operations are replayed in order to find the first operation after
+    /// which the merged documents differ. This is slow, so this method is meant for tests and
+    /// debugging.
+    pub fn converges_with(&self, other: &ListOpLog) -> Result<(), Divergence> {
+        let mut a = self.clone();
+        a.add_missing_operations_from(other);
+        let mut b = other.clone();
+        b.add_missing_operations_from(self);
+
+        let a_tip = a.checkout_mainline();
+        let b_tip = b.checkout_mainline();
+        if a_tip == b_tip { return Ok(()); }
+
+        // Replay both documents in the order of a's operations, one entry at a time. Both branches
+        // contain the same set of operations at each step.
+        let mut a_branch = ListBranch::new();
+        let mut b_branch = ListBranch::new();
+        let mut prefix = Frontier::root();
+        for entry in a.cg.iter() {
+            let range: DTRange = (entry.start..entry.start + entry.span.len()).into();
+            let (a_prev, b_prev, prev) = (a_branch.clone(), b_branch.clone(), prefix.clone());
+            prefix.advance(&a.cg.graph, range);
+            a_branch.merge(&a, prefix.as_ref());
+            b_branch.merge(&b, map_frontier(&a, &b, prefix.as_ref()).as_ref());
+            if a_branch.content() == b_branch.content() { continue; }
+
+            // Find the operation in the entry where they diverge.
+            (a_branch, b_branch, prefix) = (a_prev, b_prev, prev);
+            for v in range.iter() {
+                prefix.advance(&a.cg.graph, (v..v + 1).into());
+                a_branch.merge(&a, prefix.as_ref());
+                b_branch.merge(&b, map_frontier(&a, &b, prefix.as_ref()).as_ref());
+                if a_branch.content() != b_branch.content() {
+                    return Err(Divergence {
+                        version: Some(a.cg.agent_assignment.local_to_remote_version(v).to_owned()),
+                        ours: a_branch.content().to_string(),
+                        theirs: b_branch.content().to_string(),
+                    });
+                }
+            }
+        }
+
+        Err(Divergence { version: None, ours: a_tip, theirs: b_tip })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::causalgraph::agent_assignment::remote_ids::RemoteVersionOwned;
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn detect_conflicting_import() {
+        let mut base = ListOpLog::new();
+        let seph = base.get_or_create_agent_id("seph");
+        base.add_insert(seph, 0, "hello");
+
+        let mut a = base.clone();
+        let mike = a.get_or_create_agent_id("mike");
+        a.add_insert(mike, 5, " world");
+        assert_eq!(a.converges_with(&base), Ok(()));
+
+        // A buggy importer reuses the IDs (seph, 5..7) for different operations.
+        let mut b = base.clone();
+        b.add_insert(seph, 5, "!!");
+        let mut c = base.clone();
+        c.add_insert(seph, 5, "??");
+        let divergence = b.converges_with(&c).unwrap_err();
+        assert_eq!(divergence.version, Some(RemoteVersionOwned("seph".into(), 5)));
+        assert_eq!(divergence.ours, "hello!");
+        assert_eq!(divergence.theirs, "hello?");
+    }
+}
@@ -0,0 +1,225 @@
+//! Export a document's history as sequence CRDT items, rather than as positional operations.
+//!
+//! Internally, diamond-types stores operations as positional edits (insert at 5, delete 3..6). To
+//! merge them, it replays the operations through a CRDT built from items in the style of Yjs /
+//! YATA. [`ListOpLog::iter_crdt_items`] exposes the items from that CRDT, so other CRDT
+//! implementations (and researchers) can consume diamond-types histories at the item level.
+//!
+//! # Format
+//!
+//! Each [`CRDTItem`] is a run of characters which were inserted together, left to right. Items are
+//! yielded in document order, including deleted items, so reading the content of each item which
+//! hasn't been deleted reproduces the document.
+//!
+//! Characters are named by their local version ([`LV`]) in the oplog. Use
+//! [`AgentAssignment::local_to_remote_version_span`] to convert them to (agent, seq) pairs, which
+//! are equivalent to Yjs's (client, clock) IDs.
+//!
+//! - The first character in an item has an origin left and origin right of
+//!   `item.origin_left` and `item.origin_right`.
+//! - Every other character has an origin left of the character before it in the item
+//!   (`id - 1`), and the same origin right as the rest of the item.
+//!
+//! [`ListOpLog::from_crdt_items`] goes the other way, and builds an oplog from a list of items.
+//!
+//! [`AgentAssignment::local_to_remote_version_span`]: crate::causalgraph::agent_assignment::AgentAssignment::local_to_remote_version_span
+
+use alloc::vec::Vec;
+use rle::HasLength;
+use smartstring::alias::String as SmartString;
+use crate::{DTError, DTRange, LV};
+use crate::dtrange::is_underwater;
+use crate::list::ListOpLog;
+use crate::listmerge::merge::TransformedOpsIter2;
+use crate::unicount::count_chars;
+
+/// A run of characters in the sequence CRDT. See the [module documentation](self) for details.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CRDTItem {
+    /// The local versions of the inserted characters.
+    pub id: DTRange,
+
+    /// The character immediately to the left of the item when it was inserted, or None if the
+    /// item was inserted at the start of the document.
+    pub origin_left: Option<LV>,
+
+    /// The first character to the right of the item when it was inserted, or None if the item was
+    /// inserted at the end of the document.
+    pub origin_right: Option<LV>,
+
+    /// Whether the item has been deleted, as of the current version of the oplog.
+    pub deleted: bool,
+
+    /// The inserted content, or None if the oplog doesn't store the item's content.
+    pub content: Option<SmartString>,
+}
+
+impl ListOpLog {
+    /// Iterate through the items in the document's sequence CRDT, in document order. See the
+    /// [`crdt_items`](crate::list::crdt_items) module for a description of the format.
+    ///
+    /// This replays the whole history, so it takes about as long as checking out the document.
+    pub fn iter_crdt_items(&self) -> impl Iterator<Item=CRDTItem> + '_ {
+        let mut iter = TransformedOpsIter2::new_without_ff(&self.cg.graph, &self.cg.agent_assignment,
+                                                           &self.operation_ctx, &self.operations, self.tie_break,
+                                                           &[], self.cg.version.as_ref());
+        for _ in &mut iter {}
+
+        let spans: Vec<_> = iter.tracker_items().collect();
+        spans.into_iter().map(|span| {
+            let mut content = Some(SmartString::new());
+            for (_, c) in self.iter_range_simple(span.id) {
+                match (&mut content, c) {
+                    (Some(content), Some(c)) => content.push_str(c),
+                    _ => { content = None; }
+                }
+            }
+
+            CRDTItem {
+                id: span.id,
+                origin_left: Some(span.origin_left).filter(|v| *v != usize::MAX),
+                origin_right: Some(span.origin_right).filter(|v| !is_underwater(*v)),
+                // Every delete is in the oplog's current version, so any item which was ever
+                // deleted is deleted now.
+                deleted: span.ever_deleted,
+                content,
+            }
+        })
+    }
+
+    /// Build an oplog from a list of CRDT items, in the format produced by
+    /// [`iter_crdt_items`](ListOpLog::iter_crdt_items). Items must be in document order, and every
+    /// item needs its content.
+    ///
+    /// Items don't contain positional operations, so they're synthesized. Each item is inserted in
+    /// order of its ID, at the position which puts it in the right place in the document. Then the
+    /// deleted items are removed. All the operations are attributed to `agent_name`, and the
+    /// resulting history is linear. (The new oplog has the same content, but it doesn't preserve
+    /// concurrency in the original history, and its local versions won't match the item IDs.)
+    ///
+    /// Returns [`DTError::InvalidOperation`] if items overlap, if an item's origin isn't part of an
+    /// item inserted before it, or if an item's content is missing or the wrong length.
+    pub fn from_crdt_items(agent_name: &str, items: &[CRDTItem]) -> Result<Self, DTError> {
+        let mut oplog = Self::new();
+        let agent = oplog.try_get_or_create_agent_id(agent_name)?;
+
+        // Indexes into items, sorted by ID.
+        let mut by_id: Vec<usize> = (0..items.len()).collect();
+        by_id.sort_unstable_by_key(|i| items[*i].id.start);
+
+        for (n, &i) in by_id.iter().enumerate() {
+            let item = &items[i];
+            let content_len = item.content.as_ref().map(|c| count_chars(c));
+            if item.id.is_empty() || content_len != Some(item.id.len()) {
+                return Err(DTError::InvalidOperation);
+            }
+            if n > 0 && items[by_id[n - 1]].id.end > item.id.start {
+                return Err(DTError::InvalidOperation);
+            }
+
+            for origin in [item.origin_left, item.origin_right].into_iter().flatten() {
+                let idx = by_id.partition_point(|j| items[*j].id.end <= origin);
+                if idx >= n || !items[by_id[idx]].id.contains(origin) {
+                    return Err(DTError::InvalidOperation);
+                }
+            }
+        }
+
+        // The lengths of the items inserted so far, in document order. This is a fenwick tree, so
+        // we can quickly count the characters before each new item.
+        let mut lengths = vec![0; items.len() + 1];
+        for &i in by_id.iter() {
+            let item = &items[i];
+            let mut pos = 0;
+            let mut j = i;
+            while j > 0 { pos += lengths[j]; j &= j - 1; }
+
+            oplog.add_insert(agent, pos, item.content.as_ref().unwrap());
+
+            let mut j = i + 1;
+            while j < lengths.len() { lengths[j] += item.id.len(); j += j & j.wrapping_neg(); }
+        }
+
+        // Deletes are applied back to front, so the positions of the remaining items don't move.
+        let mut pos: usize = items.iter().map(|item| item.id.len()).sum();
+        for item in items.iter().rev() {
+            pos -= item.id.len();
+            if item.deleted {
+                oplog.add_delete_without_content(agent, pos..pos + item.id.len());
+            }
+        }
+
+        Ok(oplog)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::DTError;
+    use crate::list::crdt_items::CRDTItem;
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn crdt_items() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "abc");
+        oplog.add_insert_at(seph, &[a], 1, "xx");
+        let c = oplog.add_insert_at(mike, &[a], 3, "Z");
+        oplog.add_delete_at(mike, &[c], 1..2);
+
+        let items: Vec<_> = oplog.iter_crdt_items().collect();
+        assert_eq!(items, vec![
+            CRDTItem { id: (0..1).into(), origin_left: None, origin_right: None, deleted: false, content: Some("a".into()) },
+            CRDTItem { id: (3..5).into(), origin_left: Some(0), origin_right: Some(1), deleted: false, content: Some("xx".into()) },
+            CRDTItem { id: (1..2).into(), origin_left: Some(0), origin_right: None, deleted: true, content: Some("b".into()) },
+            CRDTItem { id: (2..3).into(), origin_left: Some(1), origin_right: None, deleted: false, content: Some("c".into()) },
+            CRDTItem { id: (5..6).into(), origin_left: Some(2), origin_right: None, deleted: false, content: Some("Z".into()) },
+        ]);
+
+        // Reading the items which haven't been deleted gives the document.
+        let content: String = items.iter()
+            .filter(|item| !item.deleted)
+            .map(|item| item.content.as_ref().unwrap().as_str())
+            .collect();
+        assert_eq!(content, oplog.checkout_tip().content().to_string());
+    }
+
+    #[test]
+    fn from_crdt_items() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "hello world");
+        let b = oplog.add_insert_at(seph, &[a], 5, " there");
+        let c = oplog.add_delete_at(mike, &[a], 0..6);
+        let c = oplog.add_insert_at(mike, &[c], 0, "yo ");
+        for _ in 0..3 { oplog.add_insert_at(mike, &[c], 0, "<"); }
+        oplog.add_delete_at(seph, &[b], 8..10);
+
+        let items: Vec<_> = oplog.iter_crdt_items().collect();
+        let imported = ListOpLog::from_crdt_items("importer", &items).unwrap();
+        assert_eq!(imported.checkout_tip().content(), oplog.checkout_tip().content());
+
+        // The imported oplog has the same items, in the same order.
+        let imported_items: Vec<_> = imported.iter_crdt_items().collect();
+        let chars = |items: &[CRDTItem]| -> Vec<(char, bool)> {
+            items.iter().flat_map(|item| {
+                item.content.as_ref().unwrap().chars().map(|c| (c, item.deleted)).collect::<Vec<_>>()
+            }).collect()
+        };
+        assert_eq!(chars(&imported_items), chars(&items));
+
+        // Invalid items are rejected.
+        let mut bad = items.clone();
+        bad[0].content = None;
+        assert_eq!(ListOpLog::from_crdt_items("importer", &bad).unwrap_err(), DTError::InvalidOperation);
+        let mut bad = items.clone();
+        bad[0].origin_left = Some(1000);
+        assert_eq!(ListOpLog::from_crdt_items("importer", &bad).unwrap_err(), DTError::InvalidOperation);
+        let mut bad = items;
+        bad[1].id = bad[0].id;
+        assert_eq!(ListOpLog::from_crdt_items("importer", &bad).unwrap_err(), DTError::InvalidOperation);
+    }
+}
@@ -0,0 +1,290 @@
+//! Buffering rapid local edits before they're added to the oplog.
+//!
+//! When a user types, each keystroke is usually added to the oplog as its own operation. Typing
+//! and backspacing produces lots of tiny operations, which bloat the document's history. An
+//! [`EditBuffer`] collects local edits and only adds them to the oplog when it's flushed. Edits
+//! which cancel out (like typing a character then deleting it) are dropped entirely, and the rest
+//! are coalesced into as few operations as possible.
+//!
+//! Buffered edits aren't visible in the branch until they're flushed. The buffer must be flushed
+//! before merging remote changes into the branch, since the buffered positions are relative to
+//! the branch's current content.
+//!
+//! Like [presence](crate::list::presence), the buffer doesn't read the clock. Timestamps are
+//! supplied by the caller and only compared with each other.
+//!
+//! ```
+//! use diamond_types::list::ListCRDT;
+//! use diamond_types::list::edit_buffer::EditBuffer;
+//!
+//! let mut doc = ListCRDT::new();
+//! let seph = doc.get_or_create_agent_id("seph");
+//! let mut buf = EditBuffer::new(seph, 1000, 100);
+//! buf.insert(0, "helo", 0);
+//! buf.delete(2..4, 10);
+//! buf.insert(2, "llo", 20);
+//! assert!(!buf.should_flush(500));
+//! assert!(buf.should_flush(1000));
+//!
+//! buf.flush(&mut doc.oplog, &mut doc.branch);
+//! assert_eq!(doc.branch.content().to_string(), "hello");
+//! assert_eq!(doc.oplog.iter().count(), 1);
+//! ```
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+use smartstring::SmartString;
+use crate::{AgentId, LV};
+use crate::list::{ListBranch, ListOpLog};
+use crate::list::operation::TextOperation;
+use crate::unicount::{chars_to_bytes, count_chars};
+
+/// A piece of the buffered edits, relative to the branch's content.
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Piece {
+    /// Characters from the branch which haven't been changed.
+    Keep(usize),
+    /// Characters from the branch which have been deleted.
+    Del(usize),
+    /// New content.
+    Ins(String),
+}
+
+impl Piece {
+    /// The number of characters this piece contributes to the edited document.
+    fn visible_len(&self) -> usize {
+        match self {
+            Piece::Keep(n) => *n,
+            Piece::Del(_) => 0,
+            Piece::Ins(s) => count_chars(s),
+        }
+    }
+
+    /// Split the piece at a visible offset, returning the second half.
+    fn split(&mut self, at: usize) -> Piece {
+        match self {
+            Piece::Keep(n) => {
+                let rest = *n - at;
+                *n = at;
+                Piece::Keep(rest)
+            }
+            Piece::Del(_) => unreachable!(),
+            Piece::Ins(s) => Piece::Ins(s.split_off(chars_to_bytes(s, at))),
+        }
+    }
+}
+
+/// A buffer of local edits for a single agent. See the [module documentation](self) for details.
+#[derive(Debug, Clone)]
+pub struct EditBuffer {
+    agent: AgentId,
+
+    /// The buffered edits, from the start of the document. Any content after the last piece is
+    /// unchanged.
+    pieces: Vec<Piece>,
+
+    /// When the first buffered edit was made.
+    first_edit: Option<u64>,
+    /// The number of characters inserted or deleted since the buffer was last flushed.
+    pending_chars: usize,
+
+    max_delay: u64,
+    max_pending_chars: usize,
+}
+
+impl EditBuffer {
+    /// Create a new buffer for edits made by `agent`. [`should_flush`](EditBuffer::should_flush)
+    /// returns true once edits have been buffered for `max_delay`, or once more than
+    /// `max_pending_chars` characters have been inserted or deleted.
+    pub fn new(agent: AgentId, max_delay: u64, max_pending_chars: usize) -> Self {
+        Self {
+            agent,
+            pieces: Vec::new(),
+            first_edit: None,
+            pending_chars: 0,
+            max_delay,
+            max_pending_chars,
+        }
+    }
+
+    /// Returns true if there are no buffered edits.
+    pub fn is_empty(&self) -> bool {
+        self.first_edit.is_none()
+    }
+
+    /// Returns true if the buffer should be flushed.
+    pub fn should_flush(&self, now: u64) -> bool {
+        match self.first_edit {
+            Some(first_edit) => now.saturating_sub(first_edit) >= self.max_delay
+                || self.pending_chars > self.max_pending_chars,
+            None => false,
+        }
+    }
+
+    /// Make sure a piece starts at the visible position `pos`. Returns the index of that piece.
+    fn split_at(&mut self, pos: usize) -> usize {
+        let mut cur = 0;
+        for i in 0..self.pieces.len() {
+            if cur == pos { return i; }
+            let len = self.pieces[i].visible_len();
+            if pos < cur + len {
+                let rest = self.pieces[i].split(pos - cur);
+                self.pieces.insert(i + 1, rest);
+                return i + 1;
+            }
+            cur += len;
+        }
+
+        if cur < pos { self.pieces.push(Piece::Keep(pos - cur)); }
+        self.pieces.len()
+    }
+
+    fn touch(&mut self, len: usize, now: u64) {
+        self.first_edit.get_or_insert(now);
+        self.pending_chars += len;
+    }
+
+    /// Buffer an insert at `pos`. Positions are relative to the document with all the buffered
+    /// edits applied.
+    pub fn insert(&mut self, pos: usize, content: &str, now: u64) {
+        if content.is_empty() { return; }
+        self.touch(count_chars(content), now);
+
+        let i = self.split_at(pos);
+        if let Some(Piece::Ins(s)) = i.checked_sub(1).and_then(|i| self.pieces.get_mut(i)) {
+            s.push_str(content);
+        } else {
+            self.pieces.insert(i, Piece::Ins(content.into()));
+        }
+    }
+
+    /// Buffer a delete of `range`. Positions are relative to the document with all the buffered
+    /// edits applied.
+    pub fn delete(&mut self, range: Range<usize>, now: u64) {
+        if range.is_empty() { return; }
+        self.touch(range.len(), now);
+
+        let start = self.split_at(range.start);
+        let end = self.split_at(range.end);
+        for piece in &mut self.pieces[start..end] {
+            match piece {
+                Piece::Keep(n) => { *piece = Piece::Del(*n); }
+                // Deleting buffered content just removes it.
+                Piece::Ins(s) => { s.clear(); }
+                Piece::Del(_) => {}
+            }
+        }
+        self.normalize();
+    }
+
+    /// Remove empty pieces and merge neighbouring pieces of the same kind.
+    fn normalize(&mut self) {
+        let mut result: Vec<Piece> = Vec::with_capacity(self.pieces.len());
+        for piece in self.pieces.drain(..) {
+            match (result.last_mut(), piece) {
+                (_, Piece::Keep(0) | Piece::Del(0)) => {}
+                (_, Piece::Ins(s)) if s.is_empty() => {}
+                (Some(Piece::Keep(a)), Piece::Keep(b)) => { *a += b; }
+                (Some(Piece::Del(a)), Piece::Del(b)) => { *a += b; }
+                (Some(Piece::Ins(_)), Piece::Del(b)) => {
+                    // Keep deletes before inserts at the same position, so they can be merged.
+                    let ins = result.pop().unwrap();
+                    match result.last_mut() {
+                        Some(Piece::Del(a)) => { *a += b; }
+                        _ => { result.push(Piece::Del(b)); }
+                    }
+                    result.push(ins);
+                }
+                (Some(Piece::Ins(a)), Piece::Ins(b)) => { a.push_str(&b); }
+                (_, piece) => { result.push(piece); }
+            }
+        }
+        self.pieces = result;
+    }
+
+    /// Add all the buffered edits to the oplog and the branch. Returns the last local version
+    /// added, or None if nothing was buffered.
+    ///
+    /// The branch must be at the same version as when the first edit was buffered. Panics if the
+    /// edits go past the end of the branch's content.
+    pub fn flush(&mut self, oplog: &mut ListOpLog, branch: &mut ListBranch) -> Option<LV> {
+        let ops = self.take_operations(branch);
+        if ops.is_empty() { None }
+        else { Some(branch.apply_local_operations(oplog, self.agent, &ops)) }
+    }
+
+    /// Clear the buffer, returning the buffered edits as operations against `branch`. The content
+    /// of deletes is read from the branch.
+    pub(crate) fn take_operations(&mut self, branch: &ListBranch) -> Vec<TextOperation> {
+        self.first_edit = None;
+        self.pending_chars = 0;
+        let pieces = core::mem::take(&mut self.pieces);
+
+        let mut ops = Vec::new();
+        // Positions in the edited document and in the branch.
+        let mut pos = 0;
+        let mut base_pos = 0;
+        for piece in pieces {
+            match piece {
+                Piece::Keep(n) => {
+                    pos += n;
+                    base_pos += n;
+                }
+                Piece::Del(n) => {
+                    assert!(base_pos + n <= branch.len(), "Edits are past the end of the document");
+                    let mut content = SmartString::new();
+                    content.extend(branch.content.slice_chars(base_pos..base_pos + n));
+                    ops.push(TextOperation::new_delete_with_content_range(pos..pos + n, content));
+                    base_pos += n;
+                }
+                Piece::Ins(s) => {
+                    let len = count_chars(&s);
+                    ops.push(TextOperation::new_insert(pos, &s));
+                    pos += len;
+                }
+            }
+        }
+        assert!(base_pos <= branch.len(), "Edits are past the end of the document");
+        ops
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::string::ToString;
+    use crate::list::ListCRDT;
+    use crate::list::edit_buffer::EditBuffer;
+
+    #[test]
+    fn coalesce_typing() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "abcdef");
+
+        // Type and backspace, one character at a time.
+        let mut buf = EditBuffer::new(seph, 100, 1000);
+        for (i, c) in "hello wrold".chars().enumerate() {
+            buf.insert(3 + i, &c.to_string(), i as u64);
+        }
+        for i in 0..4 { buf.delete(13 - i..14 - i, 20); }
+        buf.insert(10, "orld", 30);
+        buf.delete(1..2, 40);
+        buf.delete(0..2, 50);
+        assert!(!buf.should_flush(99));
+        assert!(buf.should_flush(100));
+
+        let before = doc.oplog.len();
+        buf.flush(&mut doc.oplog, &mut doc.branch);
+        assert_eq!(doc.branch.content().to_string(), "hello worlddef");
+        assert!(buf.is_empty());
+        // One delete and one insert.
+        assert_eq!(doc.oplog.len() - before, 3 + 11);
+        assert_eq!(doc.oplog.iter().count(), 3);
+        assert_eq!(doc.oplog.cg.graph.entries.num_entries(), 1);
+        doc.oplog.dbg_check(true);
+
+        // Nothing to flush.
+        assert_eq!(buf.flush(&mut doc.oplog, &mut doc.branch), None);
+    }
+}
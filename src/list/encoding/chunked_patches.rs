@@ -0,0 +1,150 @@
+//! Splitting patches to fit within message size limits.
+//!
+//! Transports like WebRTC data channels (and many websocket servers) limit the size of each
+//! message. [`ListOpLog::encode_ops_since_chunked`] splits the operations since some version into
+//! a series of patches, each of which is a complete encoded file. Each patch only depends on the
+//! starting version and the patches before it, so they can be merged in order as they arrive.
+
+use alloc::vec::Vec;
+use smallvec::SmallVec;
+use rle::HasLength;
+use crate::{DTRange, Frontier, LV};
+use crate::list::encoding::{ENCODE_PATCH, EncodeFilter, EncodeOptions};
+use crate::list::ListOpLog;
+
+/// An iterator over patches, each smaller than a size limit. Returned by
+/// [`ListOpLog::encode_ops_since_chunked`].
+#[derive(Debug, Clone)]
+pub struct ChunkedPatches<'a> {
+    oplog: &'a ListOpLog,
+    /// The version the next patch is encoded from.
+    from: Frontier,
+    /// Spans which haven't been encoded yet, in descending order so they can be popped.
+    remaining: Vec<DTRange>,
+    max_bytes: usize,
+    /// The number of operations to try putting in the next patch.
+    batch_size: usize,
+}
+
+impl ListOpLog {
+    /// Encode the operations since `frontier` as a series of patches, each at most `max_bytes`
+    /// long. The patches must be merged in order.
+    ///
+    /// Patches are split between operations. If a single operation doesn't fit within
+    /// `max_bytes`, it's sent in a patch on its own, which will be larger than the limit.
+    pub fn encode_ops_since_chunked(&self, frontier: &[LV], max_bytes: usize) -> ChunkedPatches<'_> {
+        let (mut remaining, _) = self.cg.graph.diff(self.cg.version.as_ref(), frontier);
+        remaining.reverse();
+        let batch_size = remaining.iter().map(|span| span.len()).sum();
+
+        ChunkedPatches {
+            oplog: self,
+            from: self.cg.graph.find_dominators(frontier),
+            remaining: remaining.into_vec(),
+            max_bytes,
+            batch_size,
+        }
+    }
+}
+
+impl<'a> ChunkedPatches<'a> {
+    /// The first n operations which haven't been encoded yet.
+    fn take_batch(&self, mut n: usize) -> SmallVec<[DTRange; 4]> {
+        let mut batch = SmallVec::new();
+        for span in self.remaining.iter().rev() {
+            if n == 0 { break; }
+            let len = span.len().min(n);
+            batch.push((span.start..span.start + len).into());
+            n -= len;
+        }
+        batch
+    }
+
+    /// Mark the first n operations as encoded.
+    fn consume(&mut self, mut n: usize) {
+        while n > 0 {
+            let span = self.remaining.last_mut().unwrap();
+            if span.len() <= n {
+                n -= span.len();
+                self.remaining.pop();
+            } else {
+                span.start += n;
+                n = 0;
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for ChunkedPatches<'a> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let total: usize = self.remaining.iter().map(|span| span.len()).sum();
+        if total == 0 { return None; }
+
+        // Spans are in ascending order, so the history of each batch is either in the batch or
+        // already encoded.
+        let mut n = self.batch_size.clamp(1, total);
+        loop {
+            let batch = self.take_batch(n);
+            let filter = EncodeFilter::Spans(&batch);
+            let patch = self.oplog.encode_from(EncodeOptions {
+                filter: Some(filter),
+                ..ENCODE_PATCH
+            }, self.from.as_ref());
+
+            if patch.len() <= self.max_bytes || n == 1 {
+                let versions: SmallVec<[LV; 4]> = self.from.iter()
+                    .chain(self.oplog.filter_version(filter).iter())
+                    .copied()
+                    .collect();
+                self.from = self.oplog.cg.graph.find_dominators(&versions);
+                self.consume(n);
+                // The next patch will probably be a similar size. Start a bit bigger in case it
+                // compresses better.
+                self.batch_size = n + n / 2 + 1;
+                return Some(patch);
+            }
+
+            // Guess how many operations will fit based on the size of this patch.
+            n = (n * self.max_bytes / patch.len()).clamp(1, n - 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn chunked_patches() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let base = oplog.add_insert(seph, 0, "base ");
+        oplog.add_insert_at(seph, &[base], 0, &(0..200).map(|i| (i * 7919 % 1000).to_string()).collect::<String>());
+        let mut v = base;
+        for i in 0..50 {
+            v = oplog.add_insert_at(mike, &[v], 5, if i % 2 == 0 { "x" } else { "yz" });
+        }
+        let v = oplog.local_frontier();
+        oplog.add_delete_at(seph, v.as_ref(), 0..10);
+
+        let patches: Vec<_> = oplog.encode_ops_since_chunked(&[base], 200).collect();
+        assert!(patches.len() > 1);
+        assert!(patches.iter().all(|p| p.len() <= 200));
+
+        let mut dest = ListOpLog::new();
+        let seph = dest.get_or_create_agent_id("seph");
+        dest.add_insert(seph, 0, "base ");
+        for patch in &patches {
+            dest.decode_and_add(patch).unwrap();
+        }
+        assert_eq!(dest, oplog);
+
+        // Nothing to send.
+        assert_eq!(oplog.encode_ops_since_chunked(oplog.cg.version.as_ref(), 200).count(), 0);
+    }
+}
@@ -1,3 +1,4 @@
+use alloc::vec::Vec;
 use smallvec::{smallvec, SmallVec};
 use crate::list::encoding::*;
 use crate::list::{ListOpLog, switch};
@@ -5,7 +6,7 @@ use crate::frontier::*;
 use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
 use crate::list::operation::ListOpKind::{Del, Ins};
 use crate::rev_range::RangeRev;
-use crate::{AgentId, Frontier, LV};
+use crate::{AgentId, DTError, Frontier, LV};
 use crate::unicount::*;
 use rle::*;
 use crate::list::buffered_iter::Buffered;
@@ -14,11 +15,17 @@ use crate::causalgraph::graph::GraphEntrySimple;
 use crate::list::operation::ListOpKind;
 use crate::dtrange::{DTRange, UNDERWATER_START};
 use crate::list::encoding::decode_tools::{BufReader, ChunkReader};
-use crate::causalgraph::agent_span::AgentSpan;
+use crate::causalgraph::agent_span::{AgentSpan, AgentVersion};
 use crate::rle::{KVPair, RleKeyedAndSplitable, RleSpanHelpers, RleVec};
 use crate::encoding::parseerror::ParseError;
 use crate::encoding::tools::calc_checksum;
 use crate::list::encoding::leb::num_decode_zigzag_isize_old;
+use crate::list::tie_break::TieBreak;
+use crate::list::anchor::{Anchor, AnchorBias};
+use crate::list::marks::{MarkEntry, MarkExpand, MarkId, MarkRange};
+use crate::list::suggestions::{SuggestionEntry, SuggestionState};
+use crate::list::agent_meta::AgentMetaValue;
+use crate::list::content_tags::{ContentTag, TagBlock, TAG_BLOCK, TAG_LEN};
 
 // If this is set to false, the compiler can optimize out the verbose printing code. This makes the
 // compiled output slightly smaller.
@@ -26,6 +33,16 @@ const ALLOW_VERBOSE: bool = false;
 // const ALLOW_VERBOSE: bool = true;
 
 impl<'a> BufReader<'a> {
+    /// Check the content of a ChunkChecksums chunk against the checksum at its end.
+    pub(super) fn check_chunk_checksums(self) -> Result<(), ParseError> {
+        let (content, expected_crc) = self.0.split_at_checked(self.0.len().wrapping_sub(4))
+            .ok_or(ParseError::UnexpectedEOF)?;
+        if calc_checksum(content) != BufReader(expected_crc).next_u32_le()? {
+            return Err(ParseError::ChecksumFailed);
+        }
+        Ok(())
+    }
+
     fn read_next_agent_assignment(&mut self, map: &mut [(AgentId, usize)]) -> Result<Option<AgentSpan>, ParseError> {
         // Agent assignments are almost always (but not always) linear. They can have gaps, and
         // they can be reordered if the same agent ID is used to contribute to multiple branches.
@@ -56,9 +73,8 @@ impl<'a> BufReader<'a> {
         let entry = &mut map[inner_agent];
         let agent = entry.0;
 
-        // TODO: Error if this overflows.
-        let start = (entry.1 as isize + jump) as usize;
-        let end = start + len;
+        let start = entry.1.checked_add_signed(jump).ok_or(ParseError::InvalidLength)?;
+        let end = start.checked_add(len).ok_or(ParseError::InvalidLength)?;
         entry.1 = end;
 
         Ok(Some(AgentSpan {
@@ -67,6 +83,165 @@ impl<'a> BufReader<'a> {
         }))
     }
 
+    fn read_mapped_agent(&mut self, agent_map: &[(AgentId, usize)]) -> Result<AgentId, ParseError> {
+        let mapped_agent = self.next_usize()?;
+        agent_map.get(mapped_agent.wrapping_sub(1))
+            .map(|(agent, _)| *agent)
+            .ok_or(ParseError::GenericInvalidData)
+    }
+
+    fn read_remote_lv(&mut self, oplog: &ListOpLog, agent_map: &[(AgentId, usize)]) -> Result<LV, ParseError> {
+        let agent = self.read_mapped_agent(agent_map)?;
+        let seq = self.next_usize()?;
+        oplog.try_crdt_id_to_time((agent, seq))
+            .ok_or(ParseError::BaseVersionUnknown)
+    }
+
+    fn read_frontier(&mut self, oplog: &ListOpLog, agent_map: &[(AgentId, usize)]) -> Result<Frontier, ParseError> {
+        let len = self.next_usize()?;
+        let mut result = smallvec![];
+        for _ in 0..len {
+            result.push(self.read_remote_lv(oplog, agent_map)?);
+        }
+        sort_frontier(&mut result);
+        Ok(Frontier(result))
+    }
+
+    /// Read the marks chunk. The marks are merged into the oplog once the whole file has been read.
+    fn read_marks(mut self, oplog: &ListOpLog, agent_map: &[(AgentId, usize)]) -> Result<Vec<(MarkId, MarkEntry)>, ParseError> {
+        let mut result = Vec::new();
+        while !self.is_empty() {
+            let id = MarkId {
+                agent: self.read_mapped_agent(agent_map)?,
+                seq: self.next_usize()?,
+            };
+
+            let flags = self.next_usize()?;
+            let expand = MarkExpand::try_from(flags & 0b11).map_err(|_| ParseError::GenericInvalidData)?;
+            if flags >> 4 != 0 { return Err(ParseError::GenericInvalidData); }
+            let bias = |after: bool| if after { AnchorBias::After } else { AnchorBias::Before };
+            let start_anchor = Anchor { lv: self.read_remote_lv(oplog, agent_map)?, bias: bias(flags & 0b100 != 0) };
+            let end_anchor = Anchor { lv: self.read_remote_lv(oplog, agent_map)?, bias: bias(flags & 0b1000 != 0) };
+            let payload_len = self.next_usize()?;
+            let payload = self.next_n_bytes(payload_len)?.to_vec();
+
+            let created_at = self.read_frontier(oplog, agent_map)?;
+            let num_deletes = self.next_usize()?;
+            let mut deleted_at = Vec::new();
+            for _ in 0..num_deletes {
+                deleted_at.push(self.read_frontier(oplog, agent_map)?);
+            }
+
+            result.push((id, MarkEntry {
+                range: MarkRange { start_anchor, end_anchor, expand, payload },
+                created_at,
+                deleted_at,
+                changed: 0,
+            }));
+        }
+        Ok(result)
+    }
+
+    /// Read the agent metadata chunk.
+    fn read_agent_meta(mut self, agent_map: &[(AgentId, usize)]) -> Result<Vec<(AgentId, &'a str, AgentMetaValue)>, ParseError> {
+        let mut result = Vec::new();
+        while !self.is_empty() {
+            let agent = self.read_mapped_agent(agent_map)?;
+            let key = self.next_str()?;
+            let value = self.next_str()?.into();
+            let clock = self.next_usize()?;
+            result.push((agent, key, AgentMetaValue { clock, value }));
+        }
+        Ok(result)
+    }
+
+    /// Read the agent links chunk. Returns a list of (agent, linked agent) pairs.
+    fn read_agent_links(mut self, agent_map: &[(AgentId, usize)]) -> Result<Vec<(AgentId, AgentId)>, ParseError> {
+        let mut result = Vec::new();
+        while !self.is_empty() {
+            let agent = self.read_mapped_agent(agent_map)?;
+            let target = self.read_mapped_agent(agent_map)?;
+            if agent == target { return Err(ParseError::GenericInvalidData); }
+            result.push((agent, target));
+        }
+        Ok(result)
+    }
+
+    /// Read the suggestions chunk. The suggestions are merged into the oplog once the whole file
+    /// has been read.
+    fn read_suggestions(mut self, oplog: &ListOpLog, agent_map: &[(AgentId, usize)]) -> Result<Vec<(AgentVersion, SuggestionEntry)>, ParseError> {
+        let mut result = Vec::new();
+        while !self.is_empty() {
+            let agent = self.read_mapped_agent(agent_map)?;
+            let seq_start = self.next_usize()?;
+            let len = self.next_usize()?;
+            let state = SuggestionState::try_from(self.next_usize()?)
+                .map_err(|_| ParseError::GenericInvalidData)?;
+
+            // We should have all the operations in the suggestion. The agent's sequence numbers
+            // might not all be known, even if later ones are.
+            let seq_end = seq_start.checked_add(len).filter(|_| len > 0)
+                .ok_or(ParseError::InvalidLength)?;
+            let client_data = &oplog.cg.agent_assignment.client_data[agent as usize];
+            let mut seq = seq_start;
+            while seq < seq_end {
+                seq += client_data.try_seq_to_lv_span((seq..seq_end).into())
+                    .ok_or(ParseError::BaseVersionUnknown)?
+                    .len();
+            }
+
+            result.push(((agent, seq_start), SuggestionEntry { seq_end, state, changed: 0 }));
+        }
+        Ok(result)
+    }
+
+    /// Read the redactions chunk. Returns the redacted ranges of local versions.
+    fn read_redactions(mut self, oplog: &ListOpLog, agent_map: &[(AgentId, usize)]) -> Result<Vec<DTRange>, ParseError> {
+        let mut result = Vec::new();
+        while !self.is_empty() {
+            let agent = self.read_mapped_agent(agent_map)?;
+            let mut seq_start = self.next_usize()?;
+            let len = self.next_usize()?;
+            let seq_end = seq_start.checked_add(len).filter(|_| len > 0)
+                .ok_or(ParseError::InvalidLength)?;
+
+            // The operations may not be contiguous locally.
+            while seq_start < seq_end {
+                let range = oplog.cg.agent_assignment.client_data[agent as usize]
+                    .try_seq_to_lv_span((seq_start..seq_end).into())
+                    .ok_or(ParseError::BaseVersionUnknown)?;
+                result.push(range);
+                seq_start += range.len();
+            }
+        }
+        Ok(result)
+    }
+
+    /// Read the tags of encrypted content. Returns ((agent, block), len, tag) for each tag.
+    fn read_content_tags(mut self, oplog: &ListOpLog, agent_map: &[(AgentId, usize)]) -> Result<Vec<(TagBlock, usize, ContentTag)>, ParseError> {
+        let mut result = Vec::new();
+        while !self.is_empty() {
+            let agent = self.read_mapped_agent(agent_map)?;
+            let block = self.next_usize()?;
+            let len = self.next_usize()?;
+            let count = self.next_usize()?;
+            if len == 0 || len > TAG_BLOCK || count == 0 { return Err(ParseError::InvalidLength); }
+
+            // The tagged operations must all be known.
+            let seq_end = block.checked_mul(TAG_BLOCK).and_then(|start| start.checked_add(len))
+                .ok_or(ParseError::InvalidLength)?;
+            if oplog.cg.agent_assignment.client_data[agent as usize].try_seq_to_lv(seq_end - 1).is_none() {
+                return Err(ParseError::BaseVersionUnknown);
+            }
+
+            for _ in 0..count {
+                let tag = self.next_n_bytes(TAG_LEN)?.try_into().unwrap();
+                result.push(((agent, block), len, tag));
+            }
+        }
+        Ok(result)
+    }
+
     fn read_version(mut self, oplog: &ListOpLog, agent_map: &[(AgentId, usize)]) -> Result<Frontier, ParseError> {
         let mut result = smallvec![];
         // All frontiers contain at least one item.
@@ -76,7 +251,7 @@ impl<'a> BufReader<'a> {
             let seq = self.next_usize()?; // Bleh. Skip me when root!
             if mapped_agent == 0 { break; } // Root.
 
-            let agent = agent_map[mapped_agent - 1].0;
+            let agent = agent_map.get(mapped_agent - 1).ok_or(ParseError::InvalidLength)?.0;
 
             let time = oplog.try_crdt_id_to_time((agent, seq))
                 .ok_or(ParseError::BaseVersionUnknown)?;
@@ -104,7 +279,7 @@ impl<'a> BufReader<'a> {
                     // The parents list is empty (ie, our parent is ROOT).
                     break;
                 } else {
-                    let agent = agent_map[n - 1].0;
+                    let agent = agent_map.get(n - 1).ok_or(ParseError::InvalidLength)?.0;
                     let seq = self.next_usize()?;
                     // dbg!((agent, seq));
                     if let Some(c) = oplog.cg.agent_assignment.client_data.get(agent as usize) {
@@ -118,7 +293,8 @@ impl<'a> BufReader<'a> {
             } else {
                 // Local parents (parents inside this chunk of data) are stored using their
                 // local time offset.
-                next_time - n
+                if n == 0 { return Err(ParseError::InvalidLength); }
+                next_time.checked_sub(n).ok_or(ParseError::BaseVersionUnknown)?
             };
 
             parents.push(parent);
@@ -190,16 +366,41 @@ impl<'a> ChunkReader<'a> {
             let bytes = compressed.ok_or(ParseError::CompressedDataMissing)?
                 .next_n_bytes(len)?;
 
-            std::str::from_utf8(bytes).map_err(|_| ParseError::InvalidUTF8)
+            core::str::from_utf8(bytes).map_err(|_| ParseError::InvalidUTF8)
         }
     }
 
-    fn read_fileinfo(&mut self, oplog: &mut ListOpLog) -> Result<FileInfoData, ParseError> {
+    fn read_fileinfo(&mut self, oplog: &mut ListOpLog) -> Result<FileInfoData<'a>, DTError> {
         let mut fileinfo = self.expect_chunk(ListChunkType::FileInfo)?.chunks();
 
         let doc_id = fileinfo.read_chunk_if_eq(ListChunkType::DocId)?;
         let mut agent_names_chunk = fileinfo.expect_chunk(ListChunkType::AgentNames)?;
         let userdata = fileinfo.read_chunk_if_eq(ListChunkType::UserData)?;
+        let tie_break = match fileinfo.read_chunk_if_eq(ListChunkType::TieBreak)? {
+            Some(mut chunk) => {
+                let tie_break = TieBreak::try_from(chunk.next_u32()?)
+                    .map_err(|_| ParseError::GenericInvalidData)?;
+                chunk.expect_empty()?;
+                tie_break
+            }
+            None => TieBreak::default(),
+        };
+        let agent_meta_chunk = fileinfo.read_chunk_if_eq(ListChunkType::AgentMeta)?;
+        let agent_links_chunk = fileinfo.read_chunk_if_eq(ListChunkType::AgentLinks)?;
+
+        // Files from older versions don't list their features. We'll find out if they use
+        // anything we don't support as they're read.
+        let features = match fileinfo.read_chunk_if_eq(ListChunkType::Features)? {
+            Some(chunk) => {
+                let (features, required) = chunk.read_features()?;
+                let unsupported = required.difference(FormatFeatures::SUPPORTED);
+                if !unsupported.is_empty() {
+                    return Err(ParseError::UnsupportedFeatures(unsupported).into());
+                }
+                features
+            }
+            None => FormatFeatures::NONE,
+        };
 
         let doc_id = if let Some(doc_id) = doc_id {
             Some(doc_id.into_content_str()?)
@@ -214,25 +415,73 @@ impl<'a> ChunkReader<'a> {
         let mut agent_map = Vec::new();
         while !agent_names_chunk.0.is_empty() {
             let name = agent_names_chunk.next_str()?;
-            let id = oplog.get_or_create_agent_id(name);
+            let id = oplog.try_get_or_create_agent_id(name)?;
             agent_map.push((id, 0));
         }
 
+        // The metadata is only merged once the rest of the file has been read, so it isn't
+        // modified if the file can't be loaded.
+        let agent_meta = match agent_meta_chunk {
+            Some(chunk) => chunk.read_agent_meta(&agent_map)?,
+            None => Vec::new(),
+        };
+        let agent_links = match agent_links_chunk {
+            Some(chunk) => chunk.read_agent_links(&agent_map)?,
+            None => Vec::new(),
+        };
+
         Ok(FileInfoData {
             userdata,
             doc_id,
+            tie_break,
             agent_map,
+            agent_meta,
+            agent_links,
+            features,
         })
     }
 }
 
 
+#[cfg(feature = "lz4")]
+fn decompress_lz4(mut chunk: BufReader) -> Result<Vec<u8>, ParseError> {
+    let uncompressed_len = chunk.next_usize()?;
+
+    // The rest of the bytes contain lz4 compressed data.
+    lz4_flex::decompress(chunk.0, uncompressed_len)
+        .map_err(|_e| ParseError::LZ4DecompressionError)
+}
+
+#[cfg(not(feature = "lz4"))]
+fn decompress_lz4(_chunk: BufReader) -> Result<Vec<u8>, ParseError> {
+    Err(ParseError::LZ4DecoderNeeded)
+}
+
+#[cfg(feature = "zstd")]
+fn decompress_zstd(mut chunk: BufReader) -> Result<Vec<u8>, ParseError> {
+    let uncompressed_len = chunk.next_usize()?;
+
+    let data = zstd::bulk::decompress(chunk.0, uncompressed_len)
+        .map_err(|_e| ParseError::ZstdDecompressionError)?;
+    if data.len() != uncompressed_len { return Err(ParseError::ZstdDecompressionError); }
+    Ok(data)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress_zstd(_chunk: BufReader) -> Result<Vec<u8>, ParseError> {
+    Err(ParseError::ZstdDecoderNeeded)
+}
+
 // Returning a tuple was getting too unwieldy.
 #[derive(Debug)]
 struct FileInfoData<'a> {
     userdata: Option<BufReader<'a>>,
     doc_id: Option<&'a str>,
+    tie_break: TieBreak,
     agent_map: Vec<(AgentId, usize)>,
+    agent_meta: Vec<(AgentId, &'a str, AgentMetaValue)>,
+    agent_links: Vec<(AgentId, AgentId)>,
+    features: FormatFeatures,
 }
 
 
@@ -348,7 +597,21 @@ impl<'a> Iterator for ReadPatchesIter<'a> {
 #[derive(Debug)]
 struct ReadPatchContentIter<'a> {
     run_chunk: BufReader<'a>,
+    /// All the content stored in the chunk. Repeated content may have been removed.
     content: &'a str,
+    /// The byte offset in content of the next content to read.
+    pos: usize,
+
+    /// Where repeated content was removed from content. See ContentChunk in encode_oplog.
+    repeats: Option<BufReader<'a>>,
+    last_repeat_pos: usize,
+    /// The next repeat (as the position in content, and the repeated content), read ahead.
+    next_repeat: Option<(usize, &'a str)>,
+    /// The rest of the repeated content currently being read.
+    repeating: &'a str,
+
+    /// The remaining length of a run of known content, when the run spans a repeat.
+    pending: Option<usize>,
 }
 
 #[derive(Debug, Clone)]
@@ -392,23 +655,79 @@ impl<'a> ReadPatchContentIter<'a> {
         let content = chunk.expect_content_str(compressed)?;
 
         let run_chunk = chunk.expect_chunk(ContentIsKnown)?;
+        let repeats = chunk.read_chunk_if_eq(ContentRepeats)?;
+
+        let mut iter = Self {
+            run_chunk,
+            content,
+            pos: 0,
+            repeats,
+            last_repeat_pos: 0,
+            next_repeat: None,
+            repeating: "",
+            pending: None,
+        };
+        iter.read_next_repeat()?;
+        Ok((tag, iter))
+    }
 
-        Ok((tag, Self { run_chunk, content }))
+    fn read_next_repeat(&mut self) -> Result<(), ParseError> {
+        self.next_repeat = match self.repeats.as_mut() {
+            Some(r) if !r.is_empty() => {
+                let pos = self.last_repeat_pos.checked_add(r.next_usize()?)
+                    .ok_or(ParseError::InvalidContent)?;
+                let start = pos.checked_sub(r.next_usize()?).ok_or(ParseError::InvalidContent)?;
+                let len = r.next_usize()?;
+                // The repeated content must come before the place it's repeated.
+                if start.saturating_add(len) > pos || !self.content.is_char_boundary(pos) {
+                    return Err(ParseError::InvalidContent);
+                }
+                let repeated = self.content.get(start..start + len).ok_or(ParseError::InvalidContent)?;
+                self.last_repeat_pos = pos;
+                Some((pos, repeated))
+            }
+            _ => None,
+        };
+        Ok(())
     }
 
     fn next_internal(&mut self) -> Result<ContentItem<'a>, ParseError> {
-        let n = self.run_chunk.next_usize()?;
-        let (len, known) = strip_bit_usize(n);
-        let content = if known {
-            let content = consume_chars(&mut self.content, len);
-            if count_chars(content) != len { // Having a duplicate strlen here is gross.
-                // We couldn't pull as many chars as requested from self.content.
-                return Err(ParseError::UnexpectedEOF);
+        let (len, known) = match self.pending.take() {
+            Some(len) => (len, true),
+            None => strip_bit_usize(self.run_chunk.next_usize()?),
+        };
+        if !known { return Ok(ContentItem { len, content: None }); }
+
+        if self.repeating.is_empty() {
+            if let Some((pos, repeated)) = self.next_repeat {
+                if pos == self.pos {
+                    self.repeating = repeated;
+                    self.read_next_repeat()?;
+                }
             }
-            Some(content)
-        } else { None };
+        }
+
+        let content = if !self.repeating.is_empty() {
+            consume_chars(&mut self.repeating, len)
+        } else {
+            // Stop at the next repeat.
+            let end = self.next_repeat.map_or(self.content.len(), |(pos, _)| pos);
+            let mut available = &self.content[self.pos..end];
+            let content = consume_chars(&mut available, len);
+            self.pos += content.len();
+            content
+        };
+
+        let content_len = count_chars(content);
+        if content_len == 0 {
+            // We couldn't pull as many chars as requested from self.content.
+            return Err(ParseError::UnexpectedEOF);
+        }
+        if content_len < len {
+            self.pending = Some(len - content_len);
+        }
 
-        Ok(ContentItem { len, content })
+        Ok(ContentItem { len: content_len, content: Some(content) })
     }
 }
 
@@ -416,10 +735,13 @@ impl<'a> Iterator for ReadPatchContentIter<'a> {
     type Item = Result<ContentItem<'a>, ParseError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match (self.run_chunk.is_empty(), self.content.is_empty()) {
-            (false, _) => Some(self.next_internal()),
-            (true, true) => None,
-            (true, false) => Some(Err(ParseError::UnexpectedEOF)),
+        let more_runs = self.pending.is_some() || !self.run_chunk.is_empty();
+        let more_content = self.pos < self.content.len() || !self.repeating.is_empty()
+            || self.next_repeat.is_some();
+        match (more_runs, more_content) {
+            (true, _) => Some(self.next_internal()),
+            (false, false) => None,
+            (false, true) => Some(Err(ParseError::UnexpectedEOF)),
         }
     }
 }
@@ -443,16 +765,82 @@ impl Default for DecodeOptions {
     }
 }
 
+/// Limits on the changes accepted by [`ListOpLog::merge_bytes_validated`]. Servers which merge
+/// patches from untrusted clients can use this to reject data they don't expect.
+///
+/// The default policy accepts everything.
+#[derive(Debug, Clone, Default)]
+pub struct MergePolicy {
+    /// Reject operations from agents the oplog hasn't seen before.
+    pub reject_unknown_agents: bool,
+
+    /// Reject operations unless each agent's sequence numbers carry on directly from the
+    /// operations we already have from that agent.
+    pub reject_out_of_order_seqs: bool,
+
+    /// The maximum number of new operations (inserted or deleted characters) in a patch.
+    pub max_new_ops: Option<usize>,
+}
+
+/// The reason a patch was rejected by a [`MergePolicy`].
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum PolicyViolation {
+    /// The patch contains operations from an agent the oplog hasn't seen before.
+    UnknownAgent,
+    /// The patch contains operations which skip or reuse some of an agent's sequence numbers.
+    OutOfOrderSeq { expected: usize, actual: usize },
+    /// The patch contains more new operations than the policy allows.
+    TooManyOps,
+}
+
+impl MergePolicy {
+    /// Check a span of new operations before it's added to the oplog.
+    fn check_new_span(&self, oplog: &ListOpLog, span: AgentSpan, num_known_agents: usize, num_new_ops: usize) -> Result<(), DTError> {
+        if self.reject_unknown_agents && span.agent as usize >= num_known_agents {
+            return Err(DTError::Rejected(PolicyViolation::UnknownAgent));
+        }
+
+        if self.reject_out_of_order_seqs {
+            let expected = oplog.cg.agent_assignment.client_data[span.agent as usize].get_next_seq();
+            if span.seq_range.start != expected {
+                return Err(DTError::Rejected(PolicyViolation::OutOfOrderSeq {
+                    expected, actual: span.seq_range.start
+                }));
+            }
+        }
+
+        if let Some(max_new_ops) = self.max_new_ops {
+            if num_new_ops + span.len() > max_new_ops {
+                return Err(DTError::Rejected(PolicyViolation::TooManyOps));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The decoding methods which return a [`ParseError`] report the (rare) errors which aren't about
+/// the format of the data as [`ParseError::GenericInvalidData`].
+fn to_parse_error(err: DTError) -> ParseError {
+    match err {
+        DTError::Parse(err) => err,
+        _ => ParseError::GenericInvalidData,
+    }
+}
+
+/// A check run on the operations a file adds, before the rest of the file (its marks, redactions,
+/// tags, etc) is merged. It's passed the range of new operations, and the content tags and
+/// redactions in the file. If it fails, the new operations are removed again.
+pub(crate) type DecodeCheck<'a> = dyn FnMut(&mut ListOpLog, DTRange, &[(TagBlock, usize, ContentTag)], &[DTRange]) -> Result<(), ParseError> + 'a;
+
 impl ListOpLog {
     pub fn load_from(data: &[u8]) -> Result<Self, ParseError> {
-        let mut oplog = Self::new();
-        oplog.decode_internal(data, DecodeOptions::default())?;
-        Ok(oplog)
+        Self::load_from_opts(data, DecodeOptions::default())
     }
 
     pub fn load_from_opts(data: &[u8], opts: DecodeOptions) -> Result<Self, ParseError> {
         let mut oplog = Self::new();
-        oplog.decode_internal(data, opts)?;
+        oplog.decode_internal(data, opts, None, None).map_err(to_parse_error)?;
         Ok(oplog)
     }
 
@@ -474,6 +862,28 @@ impl ListOpLog {
     /// This method takes an options object, which for now doesn't do much. Most users should just
     /// call [`OpLog::decode_and_add`](OpLog::decode_and_add)
     pub fn decode_and_add_opts(&mut self, data: &[u8], opts: DecodeOptions) -> Result<Frontier, ParseError> {
+        self.decode_and_add_internal(data, opts, None, None).map_err(to_parse_error)
+    }
+
+    /// Like [`decode_and_add`](Self::decode_and_add), but the new operations are passed through
+    /// `check` before anything else in the file is merged.
+    #[cfg(feature = "encryption")]
+    pub(crate) fn decode_and_add_checked(&mut self, data: &[u8], check: &mut DecodeCheck) -> Result<Frontier, ParseError> {
+        self.decode_and_add_internal(data, DecodeOptions::default(), None, Some(check)).map_err(to_parse_error)
+    }
+
+    /// Add all operations from a binary chunk into this document, after checking them against a
+    /// policy. This is intended for servers which merge patches from untrusted clients.
+    ///
+    /// Invalid data never panics. If the data is malformed, names parents we don't have, contains
+    /// operations outside the bounds of the document, or breaks the policy, an error is returned
+    /// and the oplog is left unchanged. Policy failures
+    /// are reported as [`DTError::Rejected`], and malformed data as [`DTError::Parse`].
+    pub fn merge_bytes_validated(&mut self, patch: &[u8], policy: &MergePolicy) -> Result<Frontier, DTError> {
+        self.decode_and_add_internal(patch, DecodeOptions::default(), Some(policy), None)
+    }
+
+    fn decode_and_add_internal(&mut self, data: &[u8], opts: DecodeOptions, policy: Option<&MergePolicy>, check: Option<&mut DecodeCheck>) -> Result<Frontier, DTError> {
         // In order to merge data safely, when an error happens we need to unwind all the merged
         // operations before returning. Otherwise self is in an invalid state.
         //
@@ -485,18 +895,20 @@ impl ListOpLog {
 
         // We could regenerate the frontier, but this is much lazier.
         let doc_id = self.doc_id.clone();
+        let tie_break = self.tie_break;
         let old_frontier = self.cg.version.clone();
         let num_known_agents = self.cg.agent_assignment.client_data.len();
         let ins_content_length = self.operation_ctx.ins_content.len();
         let del_content_length = self.operation_ctx.del_content.len();
 
-        let result = self.decode_internal(data, opts);
+        let result = self.decode_internal(data, opts, policy, check);
 
         if result.is_err() {
             // Unwind changes back to len.
             // This would be nicer with an RleVec iterator, but the iter implementation doesn't
             // support iterating backwards.
             self.doc_id = doc_id;
+            self.tie_break = tie_break;
 
             while let Some(last) = self.cg.agent_assignment.client_with_localtime.0.last_mut() {
                 debug_assert!(len <= last.end());
@@ -518,6 +930,7 @@ impl ListOpLog {
             let num_operations = self.operations.end();
             if num_operations > len {
                 self.operations.remove_ctx((len..num_operations).into(), &self.operation_ctx);
+                self.len_prefix.truncate(self.operations.num_entries());
             }
 
             // Trim history
@@ -571,10 +984,9 @@ impl ListOpLog {
             }
 
             // Remove excess agents
-            self.cg.agent_assignment.client_data.truncate(num_known_agents);
+            self.cg.agent_assignment.truncate_agents(num_known_agents);
 
-            self.operation_ctx.ins_content.truncate(ins_content_length);
-            self.operation_ctx.del_content.truncate(del_content_length);
+            self.operation_ctx.truncate(ins_content_length, del_content_length);
 
             self.cg.version = old_frontier;
         }
@@ -587,7 +999,7 @@ impl ListOpLog {
     /// NOTE: This code is quite new.
     /// TODO: Currently if this method returns an error, the local state is undefined & invalid.
     /// Until this is fixed, the signature of the method will stay kinda weird to prevent misuse.
-    fn decode_internal(&mut self, data: &[u8], opts: DecodeOptions) -> Result<Frontier, ParseError> {
+    fn decode_internal(&mut self, data: &[u8], opts: DecodeOptions, policy: Option<&MergePolicy>, check: Option<&mut DecodeCheck>) -> Result<Frontier, DTError> {
         // Written to be symmetric with encode functions.
         let mut reader = BufReader(data);
 
@@ -599,7 +1011,7 @@ impl ListOpLog {
         reader.read_magic()?;
         let protocol_version = reader.next_usize()?;
         if protocol_version != PROTOCOL_VERSION {
-            return Err(ParseError::UnsupportedProtocolVersion);
+            return Err(ParseError::UnsupportedProtocolVersion.into());
         }
 
         // The rest of the file is made of chunks!
@@ -608,47 +1020,53 @@ impl ListOpLog {
         // *** Compressed data ***
         // If there is a compressed chunk, it can contain data for other fields, all mushed
         // together.
-        let mut compressed_chunk;
-
-        #[cfg(not(feature = "lz4"))] {
-            compressed_chunk = None;
-            if reader.read_chunk_if_eq(ListChunkType::CompressedFieldsLZ4)?.is_some() {
-                return Err(ParseError::LZ4DecoderNeeded);
-            }
-        }
-
-        let _compressed_chunk_raw: Option<Vec<u8>>; // Pulled out so its lifetime escapes the block.
-        #[cfg(feature = "lz4")] {
-            _compressed_chunk_raw = if let Some(mut c) = reader.read_chunk_if_eq(ListChunkType::CompressedFieldsLZ4)? {
-                let uncompressed_len = c.next_usize()?;
+        let compressed_chunk_raw = if let Some(c) = reader.read_chunk_if_eq(ListChunkType::CompressedFieldsLZ4)? {
+            Some(decompress_lz4(c)?)
+        } else if let Some(c) = reader.read_chunk_if_eq(ListChunkType::CompressedFieldsZstd)? {
+            Some(decompress_zstd(c)?)
+        } else { None };
 
-                // The rest of the bytes contain lz4 compressed data.
-                let data = lz4_flex::decompress(c.0, uncompressed_len)
-                    .map_err(|_e| ParseError::LZ4DecompressionError)?;
-                Some(data)
-            } else { None };
+        // To consume from compressed_chunk_raw, we'll make a slice that we can iterate through.
+        let mut compressed_chunk = compressed_chunk_raw.as_ref().map(|b| BufReader(b));
 
-            // To consume from compressed_chunk_raw, we'll make a slice that we can iterate through.
-            compressed_chunk = _compressed_chunk_raw.as_ref().map(|b| BufReader(b));
-        }
+        // Agents the file adds are created when the fileinfo chunk is read.
+        let num_known_agents = self.cg.agent_assignment.client_data.len();
 
         // *** FileInfo ***
         // fileinfo has DocID, UserData and AgentNames.
         // The agent_map is a map from agent_id in the file to agent_id in self.
         let FileInfoData {
-            userdata: _userdata, doc_id, mut agent_map,
+            userdata: _userdata, doc_id, tie_break, mut agent_map, agent_meta, agent_links, features,
         } = reader.read_fileinfo(self)?;
 
+        // Data using a different tie break strategy can only be merged into an empty oplog. The
+        // oplog then adopts the file's strategy.
+        if tie_break != self.tie_break {
+            if !self.is_empty() { return Err(ParseError::TieBreakMismatch.into()); }
+            self.tie_break = tie_break;
+        }
+
+        // Likewise, encrypted and plain content can't be mixed.
+        let encrypted = features.contains(FormatFeatures::ENCRYPTED_CONTENT);
+        if encrypted != self.content_encrypted {
+            if !self.is_empty() { return Err(ParseError::EncryptionMismatch.into()); }
+            self.content_encrypted = encrypted;
+        }
+
         // If we already have a doc_id, make sure they match before merging.
         if let Some(file_doc_id) = doc_id {
             if let Some(local_doc_id) = self.doc_id.as_ref() {
                 if file_doc_id != local_doc_id && !self.is_empty() {
-                    return Err(ParseError::DocIdMismatch);
+                    return Err(ParseError::DocIdMismatch.into());
                 }
             }
             self.doc_id = Some(file_doc_id.into());
         }
 
+        // Patch files name their base version by hash. That's checked by merge_patch_file before
+        // the file is decoded.
+        reader.read_chunk_if_eq(ListChunkType::BaseVersion)?;
+
         // *** StartBranch ***
         let mut start_branch = reader.expect_chunk(ListChunkType::StartBranch)?.chunks();
 
@@ -671,37 +1089,12 @@ impl ListOpLog {
         // dbg!(patches_overlap);
 
         // *** Patches ***
+        // Large files written with chunk checksums split the history into several Patches chunks.
+        // Each chunk holds the next run of operations in file order, so the state below carries on
+        // from one chunk to the next.
+        let segmented = features.contains(FormatFeatures::SEGMENTED_HISTORY);
+        let new_ops_start = self.len();
         let file_frontier = {
-            // This chunk contains the actual set of edits to the document.
-            let mut patch_chunk = reader.expect_chunk(ListChunkType::Patches)?
-                .chunks();
-
-            let mut ins_content = None;
-            let mut del_content = None;
-
-            while let Some(chunk) = patch_chunk.read_chunk_if_eq(ListChunkType::PatchContent)? {
-                let (tag, content_chunk) = ReadPatchContentIter::new(chunk, compressed_chunk.as_mut())?;
-                // let iter = content_chunk.take_max();
-                let iter = content_chunk.buffered();
-                match tag {
-                    Ins => { ins_content = Some(iter); }
-                    Del => { del_content = Some(iter); }
-                }
-            }
-
-            // So note that the file we're loading from may contain changes we already have locally.
-            // We (may) need to filter out operations from the patch stream, which we read from
-            // below. To do that without extra need to read both the agent assignments and patches together.
-            let mut agent_assignment_chunk = patch_chunk.expect_chunk(ListChunkType::OpVersions)?;
-            let pos_patches_chunk = patch_chunk.expect_chunk(ListChunkType::OpTypeAndPosition)?;
-            let mut history_chunk = patch_chunk.expect_chunk(ListChunkType::OpParents)?;
-
-            // We need an insert ctx in some situations, though it'll never be accessed.
-            let dummy_ctx = ListOperationCtx::new();
-
-            let mut patches_iter = ReadPatchesIter::new(pos_patches_chunk)
-                .buffered();
-
             let first_new_time = self.len();
             let mut next_patch_time = first_new_time;
 
@@ -712,6 +1105,11 @@ impl ListOpLog {
             let mut next_assignment_time = first_new_time;
             let new_op_start = if patches_overlap { UNDERWATER_START } else { first_new_time };
             let mut next_file_time = new_op_start;
+            // The file time of the next history entry. This trails next_file_time, since each
+            // segment's history is read after its agent assignments.
+            let mut next_history_file_time = new_op_start;
+            let mut next_history_time = first_new_time;
+            let mut file_frontier = start_version;
 
             // Mapping from "file order" (numbered from 0) to the resulting local order. Using a
             // smallvec here because it'll almost always just be a single entry, and that prevents
@@ -727,208 +1125,275 @@ impl ListOpLog {
             // let mut version_map: SmallVec<[KVPair<TimeSpan>; 1]> = SmallVec::new();
             let mut version_map = RleVec::new();
 
-            // Take and merge the next exactly n patches
-            let mut parse_next_patches = |oplog: &mut ListOpLog, mut n: usize, keep: bool| -> Result<(), ParseError> {
-                while n > 0 {
-                    let mut max_len = n;
-
-                    if let Some(op) = patches_iter.next() {
-                        let mut op = op?;
-                        // dbg!((n, &op));
-                        max_len = max_len.min(op.len());
-
-                        // Trim down the operation to size.
-                        let content_here = if let Some(iter) = switch(op.kind, &mut ins_content, &mut del_content) {
-                            // There's probably a way to compact with Option helpers magic but ??
-                            if let Some(content) = iter.next() {
-                                let mut content = content?;
-                                max_len = max_len.min(content.len);
-                                // Put the rest (if any) back into the iterator.
-                                if let Some(r) = content.trim(max_len) {
-                                    iter.push_back(Ok(r));
+            // This chunk contains the actual set of edits to the document.
+            let mut next_chunk = Some(reader.expect_chunk(ListChunkType::Patches)?);
+            while let Some(chunk) = next_chunk {
+                let mut patch_chunk = chunk.chunks();
+
+                // The content of each segment is compressed separately.
+                let segment_compressed_raw = if !segmented {
+                    None
+                } else if let Some(c) = patch_chunk.read_chunk_if_eq(ListChunkType::CompressedFieldsLZ4)? {
+                    Some(decompress_lz4(c)?)
+                } else if let Some(c) = patch_chunk.read_chunk_if_eq(ListChunkType::CompressedFieldsZstd)? {
+                    Some(decompress_zstd(c)?)
+                } else { None };
+                let mut compressed = match segment_compressed_raw.as_ref() {
+                    Some(raw) => Some(BufReader(raw)),
+                    None => compressed_chunk.take(),
+                };
+
+                let mut ins_content = None;
+                let mut del_content = None;
+
+                while let Some(chunk) = patch_chunk.read_chunk_if_eq(ListChunkType::PatchContent)? {
+                    let (tag, content_chunk) = ReadPatchContentIter::new(chunk, compressed.as_mut())?;
+                    // let iter = content_chunk.take_max();
+                    let iter = content_chunk.buffered();
+                    match tag {
+                        Ins => { ins_content = Some(iter); }
+                        Del => { del_content = Some(iter); }
+                    }
+                }
+
+                // So note that the file we're loading from may contain changes we already have locally.
+                // We (may) need to filter out operations from the patch stream, which we read from
+                // below. To do that without extra need to read both the agent assignments and patches together.
+                let mut agent_assignment_chunk = patch_chunk.expect_chunk(ListChunkType::OpVersions)?;
+                let pos_patches_chunk = patch_chunk.expect_chunk(ListChunkType::OpTypeAndPosition)?;
+                let mut history_chunk = patch_chunk.expect_chunk(ListChunkType::OpParents)?;
+
+                // We need an insert ctx in some situations, though it'll never be accessed.
+                let dummy_ctx = ListOperationCtx::new();
+
+                let mut patches_iter = ReadPatchesIter::new(pos_patches_chunk)
+                    .buffered();
+
+                // Take and merge the next exactly n patches
+                let mut parse_next_patches = |oplog: &mut ListOpLog, mut n: usize, keep: bool| -> Result<(), ParseError> {
+                    while n > 0 {
+                        let mut max_len = n;
+
+                        if let Some(op) = patches_iter.next() {
+                            let mut op = op?;
+                            // dbg!((n, &op));
+                            max_len = max_len.min(op.len());
+
+                            // Trim down the operation to size.
+                            let content_here = if let Some(iter) = switch(op.kind, &mut ins_content, &mut del_content) {
+                                // There's probably a way to compact with Option helpers magic but ??
+                                if let Some(content) = iter.next() {
+                                    let mut content = content?;
+                                    max_len = max_len.min(content.len);
+                                    // Put the rest (if any) back into the iterator.
+                                    if let Some(r) = content.trim(max_len) {
+                                        iter.push_back(Ok(r));
+                                    }
+                                    content.content
+                                } else {
+                                    return Err(ParseError::InvalidLength);
                                 }
-                                content.content
-                            } else {
-                                return Err(ParseError::InvalidLength);
-                            }
-                        } else { None };
+                            } else { None };
 
-                        assert!(max_len > 0);
-                        n -= max_len;
+                            if max_len == 0 { return Err(ParseError::InvalidLength); }
+                            n -= max_len;
 
-                        let remainder = op.trim_ctx(max_len, &dummy_ctx);
+                            let remainder = op.trim_ctx(max_len, &dummy_ctx);
 
-                        // dbg!(keep, (next_patch_time, &op, content_here));
+                            // dbg!(keep, (next_patch_time, &op, content_here));
 
-                        // self.operations.push(KVPair(next_time, op));
-                        if keep {
-                            oplog.push_op_internal(next_patch_time, op.loc, op.kind, content_here);
-                            next_patch_time += max_len;
-                        }
+                            // self.operations.push(KVPair(next_time, op));
+                            if keep {
+                                oplog.push_op_internal(next_patch_time, op.loc, op.kind, content_here);
+                                next_patch_time += max_len;
+                            }
 
-                        if let Some(r) = remainder {
-                            patches_iter.push_back(Ok(r));
+                            if let Some(r) = remainder {
+                                patches_iter.push_back(Ok(r));
+                            }
+                        } else {
+                            return Err(ParseError::InvalidLength);
                         }
-                    } else {
-                        return Err(ParseError::InvalidLength);
                     }
-                }
 
-                Ok(())
-            };
+                    Ok(())
+                };
 
-            while let Some(mut crdt_span) = agent_assignment_chunk.read_next_agent_assignment(&mut agent_map)? {
-                // let mut crdt_span = crdt_span; // TODO: Remove me. Blerp clion.
-                // dbg!(crdt_span);
-                if crdt_span.agent as usize >= self.cg.agent_assignment.client_data.len() {
-                    return Err(ParseError::InvalidLength);
-                }
+                while let Some(mut crdt_span) = agent_assignment_chunk.read_next_agent_assignment(&mut agent_map)? {
+                    // let mut crdt_span = crdt_span; // TODO: Remove me. Blerp clion.
+                    // dbg!(crdt_span);
+                    if crdt_span.agent as usize >= self.cg.agent_assignment.client_data.len() {
+                        return Err(ParseError::InvalidLength.into());
+                    }
 
-                if patches_overlap {
-                    // Sooo, if the current document overlaps with the data we're loading, we need
-                    // to filter out all the operations we already have from the stream.
-                    while !crdt_span.seq_range.is_empty() {
-                        // dbg!(&crdt_span);
-                        let client = &self.cg.agent_assignment.client_data[crdt_span.agent as usize];
-                        let (span, offset) = client.lv_for_seq.find_sparse(crdt_span.seq_range.start);
-                        // dbg!((crdt_span.seq_range, span, offset));
-                        let (span_end, overlap_start) = match span {
-                            // Skip the entry.
-                            Ok(entry) => (entry.end(), Some(entry.1.start + offset)),
-                            // Consume the entry
-                            Err(empty_span) => (empty_span.end, None),
-                        };
-
-                        let end = crdt_span.seq_range.end.min(span_end);
-                        let consume_here = crdt_span.seq_range.truncate_keeping_right_from(end);
-                        let len = consume_here.len();
-
-                        let keep = if let Some(overlap_start) = overlap_start {
-                            let overlap = (overlap_start .. overlap_start + len).into();
-                            // There's overlap. We'll filter out this item.
-                            version_map.push_rle(KVPair(next_file_time, overlap));
-                            // println!("push overlap {:?}", KVPair(next_file_time, overlap));
-                            false
-                        } else {
-                            self.assign_time_to_crdt_span(next_assignment_time, AgentSpan {
-                                agent: crdt_span.agent,
-                                seq_range: consume_here,
-                            });
-
-                            // println!("push to end {:?}", KVPair(
-                            //     next_file_time,
-                            //     TimeSpan::from(next_assignment_time..next_assignment_time + len),
-                            // ));
-                            version_map.push_rle(KVPair(
-                                next_file_time,
-                                (next_assignment_time..next_assignment_time + len).into(),
-                            ));
-                            next_assignment_time += len;
-                            true
-                        };
+                    if patches_overlap {
+                        // Sooo, if the current document overlaps with the data we're loading, we need
+                        // to filter out all the operations we already have from the stream.
+                        while !crdt_span.seq_range.is_empty() {
+                            // dbg!(&crdt_span);
+                            let client = &self.cg.agent_assignment.client_data[crdt_span.agent as usize];
+                            let (span, offset) = client.lv_for_seq.find_sparse(crdt_span.seq_range.start);
+                            // dbg!((crdt_span.seq_range, span, offset));
+                            let (span_end, overlap_start) = match span {
+                                // Skip the entry.
+                                Ok(entry) => (entry.end(), Some(entry.1.start + offset)),
+                                // Consume the entry
+                                Err(empty_span) => (empty_span.end, None),
+                            };
+
+                            let end = crdt_span.seq_range.end.min(span_end);
+                            let consume_here = crdt_span.seq_range.truncate_keeping_right_from(end);
+                            let len = consume_here.len();
+
+                            let keep = if let Some(overlap_start) = overlap_start {
+                                let overlap = (overlap_start .. overlap_start + len).into();
+                                // There's overlap. We'll filter out this item.
+                                version_map.push_rle(KVPair(next_file_time, overlap));
+                                // println!("push overlap {:?}", KVPair(next_file_time, overlap));
+                                false
+                            } else {
+                                let span = AgentSpan {
+                                    agent: crdt_span.agent,
+                                    seq_range: consume_here,
+                                };
+                                if let Some(policy) = policy {
+                                    policy.check_new_span(self, span, num_known_agents, next_assignment_time - first_new_time)?;
+                                }
+                                self.assign_time_to_crdt_span(next_assignment_time, span);
+
+                                // println!("push to end {:?}", KVPair(
+                                //     next_file_time,
+                                //     TimeSpan::from(next_assignment_time..next_assignment_time + len),
+                                // ));
+                                version_map.push_rle(KVPair(
+                                    next_file_time,
+                                    (next_assignment_time..next_assignment_time + len).into(),
+                                ));
+                                next_assignment_time += len;
+                                true
+                            };
+                            next_file_time += len;
+
+                            // dbg!(&file_to_local_version_map);
+
+                            parse_next_patches(self, len, keep)?;
+
+                            // And deal with history.
+                            // parse_next_history(&mut self, &file_to_self_agent_map, &version_map, len, keep)?;
+                        }
+                        // dbg!(span);
+                    } else {
+                        // Optimization - don't bother with the filtering code above if loaded changes
+                        // follow local changes. Most calls to this function load into an empty
+                        // document, and this is the case.
+                        if let Some(policy) = policy {
+                            policy.check_new_span(self, crdt_span, num_known_agents, next_assignment_time - first_new_time)?;
+                        }
+                        self.assign_time_to_crdt_span(next_assignment_time, crdt_span);
+                        let len = crdt_span.len();
+                        let timespan = (next_assignment_time..next_assignment_time+len).into();
+                        // file_to_local_version_map.push_rle((next_assignment_time..next_assignment_time + len).into());
+                        version_map.push_rle(KVPair(next_file_time, timespan));
+                        parse_next_patches(self, len, true)?;
+                        // parse_next_history(&mut self, &file_to_self_agent_map, &version_map, len, true)?;
+
+                        next_assignment_time += len;
                         next_file_time += len;
+                    }
+                }
 
-                        // dbg!(&file_to_local_version_map);
-
-                        parse_next_patches(self, len, keep)?;
+                let file_end = next_file_time;
 
-                        // And deal with history.
-                        // parse_next_history(&mut self, &file_to_self_agent_map, &version_map, len, keep)?;
+                while !history_chunk.is_empty() {
+                    let mut entry = history_chunk.next_history_entry(self, next_history_file_time, &agent_map)?;
+                    if entry.span.end > file_end { return Err(ParseError::InvalidLength.into()); }
+                    // Parents must name operations we already have, or earlier operations in the file.
+                    if entry.parents.iter().any(|&p| p >= first_new_time && p < new_op_start) {
+                        return Err(ParseError::BaseVersionUnknown.into());
                     }
-                    // dbg!(span);
-                } else {
-                    // Optimization - don't bother with the filtering code above if loaded changes
-                    // follow local changes. Most calls to this function load into an empty
-                    // document, and this is the case.
-                    self.assign_time_to_crdt_span(next_assignment_time, crdt_span);
-                    let len = crdt_span.len();
-                    let timespan = (next_assignment_time..next_assignment_time+len).into();
-                    // file_to_local_version_map.push_rle((next_assignment_time..next_assignment_time + len).into());
-                    version_map.push_rle(KVPair(next_file_time, timespan));
-                    parse_next_patches(self, len, true)?;
-                    // parse_next_history(&mut self, &file_to_self_agent_map, &version_map, len, true)?;
-
-                    next_assignment_time += len;
-                    next_file_time += len;
-                }
-            }
+                    // So at this point the entry has underwater entry spans, and parents are underwater
+                    // when they're local to the file (and non-underwater when they refer to our items).
+                    // This makes the entry safe to truncate(), but we need to map it before we can use
+                    // it.
+
+                    next_history_file_time += entry.len();
+                    // dbg!(&entry);
+
+                    // If patches don't overlap, this code can be simplified to this:
+                    //     self.insert_history(&entry.parents, entry.span);
+                    //     self.advance_frontier(&entry.parents, entry.span);
+                    //     next_history_time += entry.len();
+                    // But benchmarks show it doesn't make any real difference in practice, so I'm not
+                    // going to sweat it.
+
+                    loop {
+                        let (mut mapped, remainder)
+                            = history_entry_map_and_truncate(entry, &version_map);
+                        // dbg!(&mapped);
+                        mapped.parents.debug_check_sorted();
+                        assert!(mapped.span.start <= next_history_time);
+
+                        // We'll update merge parents even if nothing is merged.
+                        // dbg!((&file_frontier, &mapped));
+                        file_frontier.advance_by_known_run(mapped.parents.as_ref(), mapped.span);
+                        // dbg!(&file_frontier);
+
+                        if mapped.span.end > next_history_time {
+                            // We'll merge items from mapped.
+
+                            // This is needed because the overlapping & new items aren't strictly
+                            // separated in version_map. Its kinda ugly though - I'd like a better way
+                            // to deal with this case.
+                            if mapped.span.start < next_history_time {
+                                mapped.truncate_keeping_right(next_history_time - mapped.span.start);
+                            }
 
-            next_file_time = new_op_start;
-            // dbg!(&version_map);
-            let mut next_history_time = first_new_time;
+                            self.cg.graph.push(mapped.parents.as_ref(), mapped.span);
+                            self.cg.version.advance_by_known_run(mapped.parents.as_ref(), mapped.span);
 
-            let mut file_frontier = start_version;
+                            next_history_time += mapped.len();
+                        } // else we already have these entries. Filter them out.
 
-            while !history_chunk.is_empty() {
-                let mut entry = history_chunk.next_history_entry(self, next_file_time, &agent_map)?;
-                // So at this point the entry has underwater entry spans, and parents are underwater
-                // when they're local to the file (and non-underwater when they refer to our items).
-                // This makes the entry safe to truncate(), but we need to map it before we can use
-                // it.
-
-                next_file_time += entry.len();
-                // dbg!(&entry);
-
-                // If patches don't overlap, this code can be simplified to this:
-                //     self.insert_history(&entry.parents, entry.span);
-                //     self.advance_frontier(&entry.parents, entry.span);
-                //     next_history_time += entry.len();
-                // But benchmarks show it doesn't make any real difference in practice, so I'm not
-                // going to sweat it.
-
-                loop {
-                    let (mut mapped, remainder)
-                        = history_entry_map_and_truncate(entry, &version_map);
-                    // dbg!(&mapped);
-                    mapped.parents.debug_check_sorted();
-                    assert!(mapped.span.start <= next_history_time);
-
-                    // We'll update merge parents even if nothing is merged.
-                    // dbg!((&file_frontier, &mapped));
-                    file_frontier.advance_by_known_run(mapped.parents.as_ref(), mapped.span);
-                    // dbg!(&file_frontier);
-
-                    if mapped.span.end > next_history_time {
-                        // We'll merge items from mapped.
-
-                        // This is needed because the overlapping & new items aren't strictly
-                        // separated in version_map. Its kinda ugly though - I'd like a better way
-                        // to deal with this case.
-                        if mapped.span.start < next_history_time {
-                            mapped.truncate_keeping_right(next_history_time - mapped.span.start);
+                        if let Some(remainder) = remainder {
+                            entry = remainder;
+                        } else {
+                            break;
                         }
+                    }
+                }
 
-                        self.cg.graph.push(mapped.parents.as_ref(), mapped.span);
-                        self.cg.version.advance_by_known_run(mapped.parents.as_ref(), mapped.span);
+                // We'll count the lengths in each section to make sure they all match up with each other.
+                if next_patch_time != next_assignment_time { return Err(ParseError::InvalidLength.into()); }
+                if next_patch_time != next_history_time { return Err(ParseError::InvalidLength.into()); }
 
-                        next_history_time += mapped.len();
-                    } // else we already have these entries. Filter them out.
+                // dbg!(&patch_chunk);
+                patch_chunk.expect_empty()?;
+                history_chunk.expect_empty()?;
 
-                    if let Some(remainder) = remainder {
-                        entry = remainder;
-                    } else {
-                        break;
+                if let Some(mut iter) = ins_content {
+                    if iter.next().is_some() {
+                        return Err(ParseError::InvalidContent.into());
                     }
                 }
-            }
-
-            // We'll count the lengths in each section to make sure they all match up with each other.
-            if next_patch_time != next_assignment_time { return Err(ParseError::InvalidLength); }
-            if next_patch_time != next_history_time { return Err(ParseError::InvalidLength); }
-
-            // dbg!(&patch_chunk);
-            patch_chunk.expect_empty()?;
-            history_chunk.expect_empty()?;
 
-            if let Some(mut iter) = ins_content {
-                if iter.next().is_some() {
-                    return Err(ParseError::InvalidContent);
+                if let Some(mut iter) = del_content {
+                    if iter.next().is_some() {
+                        return Err(ParseError::InvalidContent.into());
+                    }
                 }
+
+                next_chunk = if segmented {
+                    reader.read_chunk_if_eq(ListChunkType::Patches)?
+                } else { None };
             }
 
-            if let Some(mut iter) = del_content {
-                if iter.next().is_some() {
-                    return Err(ParseError::InvalidContent);
+            // Untrusted data also needs each operation's position checked. Otherwise a position past
+            // the end of the document would panic when the document is checked out.
+            if policy.is_some() {
+                for entry in self.cg.graph.iter_range((first_new_time..self.len()).into()) {
+                    self.check_op_bounds(entry.parents.as_ref(), || {
+                        self.iter_metrics_range(entry.span).map(|KVPair(_, op)| (op.kind, op.loc.span))
+                    })?;
                 }
             }
 
@@ -936,7 +1401,29 @@ impl ListOpLog {
             file_frontier
         }; // End of patches
 
+        // *** Marks ***
+        let marks = match reader.read_chunk_if_eq(ListChunkType::Marks)? {
+            Some(chunk) => chunk.read_marks(self, &agent_map)?,
+            None => Vec::new(),
+        };
+        let suggestions = match reader.read_chunk_if_eq(ListChunkType::Suggestions)? {
+            Some(chunk) => chunk.read_suggestions(self, &agent_map)?,
+            None => Vec::new(),
+        };
+        // Redactions rewrite content we already had, which can't be undone. So they're applied
+        // once everything else has been checked.
+        let redactions = match reader.read_chunk_if_eq(ListChunkType::Redactions)? {
+            Some(chunk) => chunk.read_redactions(self, &agent_map)?,
+            None => Vec::new(),
+        };
+        let content_tags = match reader.read_chunk_if_eq(ListChunkType::ContentTags)? {
+            Some(chunk) => chunk.read_content_tags(self, &agent_map)?,
+            None => Vec::new(),
+        };
+
         // TODO: Move checksum check to the start, so if it fails we don't modify the document.
+        // Unknown chunks before the checksum are still covered by it.
+        reader.skip_unknown_chunks()?;
         let reader_len = reader.0.len();
         if let Some(mut crc_reader) = reader.read_chunk_if_eq(ListChunkType::Crc)? {
             // So this is a bit dirty. The bytes which have been checksummed is everything up to
@@ -949,11 +1436,48 @@ impl ListOpLog {
 
                 // TODO: Add flag to ignore invalid checksum.
                 if calc_checksum(checksummed_data) != expected_crc {
-                    return Err(ParseError::ChecksumFailed);
+                    return Err(ParseError::ChecksumFailed.into());
                 }
             }
         }
 
+        // The per-chunk checksums are only used when repairing files. But they're still checked,
+        // so damage to them is noticed.
+        if let Some(checksums) = reader.read_chunk_if_eq(ListChunkType::ChunkChecksums)? {
+            if !opts.ignore_crc {
+                checksums.check_chunk_checksums()?;
+            }
+        }
+
+        if let Some(check) = check {
+            check(self, (new_ops_start..self.len()).into(), &content_tags, &redactions)?;
+        }
+
+        for (agent, key, value) in agent_meta {
+            self.agent_meta.merge_entry(agent, key, value);
+        }
+        for (agent, target) in agent_links {
+            self.merge_agent_link(agent, target);
+        }
+        if features.contains(FormatFeatures::DELETED_CONTENT_PURGED) {
+            self.retention.purged = true;
+        }
+        for (id, entry) in marks {
+            self.marks.merge_entry(id, entry);
+        }
+        for (start, entry) in suggestions {
+            self.suggestions.merge_entry(start, entry);
+        }
+        for range in redactions {
+            self.redact(range);
+        }
+        // Only oplogs which store encrypted content need the tags.
+        if self.content_encrypted {
+            for (block, len, tag) in content_tags {
+                self.content_tags.merge_entry(block, len, tag);
+            }
+        }
+
         // self.frontier = end_frontier_chunk.read_full_frontier(&self)?;
 
         Ok(file_frontier)
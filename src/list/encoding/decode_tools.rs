@@ -1,4 +1,4 @@
-use std::mem::size_of;
+use core::mem::size_of;
 use crate::encoding::parseerror::ParseError;
 use crate::list::encoding::leb::num_decode_zigzag_isize_old;
 use crate::list::encoding::{DataType, ListChunkType, MAGIC_BYTES};
@@ -125,8 +125,8 @@ impl<'a> BufReader<'a> {
         if len > self.0.len() { return Err(ParseError::InvalidLength); }
 
         let bytes = self.next_n_bytes(len)?;
-        // std::str::from_utf8(bytes).map_err(InvalidUTF8)
-        std::str::from_utf8(bytes).map_err(|_| ParseError::InvalidUTF8)
+        // core::str::from_utf8(bytes).map_err(InvalidUTF8)
+        core::str::from_utf8(bytes).map_err(|_| ParseError::InvalidUTF8)
     }
 
     /// Read the next string thats encoded in this content chunk
@@ -140,7 +140,7 @@ impl<'a> BufReader<'a> {
         // if len > self.0.len() {
         //     return Err(InvalidLength);
         // }
-        std::str::from_utf8(self.0).map_err(|_| ParseError::InvalidUTF8)
+        core::str::from_utf8(self.0).map_err(|_| ParseError::InvalidUTF8)
     }
 
     pub fn dbg_print_chunk_tree_internal(mut self) -> Result<(), ParseError> {
@@ -233,9 +233,25 @@ impl<'a> ChunkReader<'a> {
         }
     }
 
+    /// Skip over any chunks with types we don't know about. Newer versions of diamond types can
+    /// add optional chunks anywhere in the file.
+    pub(super) fn skip_unknown_chunks(&mut self) -> Result<(), ParseError> {
+        while let Some(chunk_type) = self.0.peek_u32()? {
+            if ListChunkType::try_from(chunk_type).is_ok() { break; }
+            let mut next = self.clone();
+            match next.next_chunk_raw() {
+                Err(ParseError::UnknownChunk) => { *self = next; }
+                Err(e) => { return Err(e); }
+                Ok(_) => unreachable!(),
+            }
+        }
+        Ok(())
+    }
+
     /// Read a chunk with the named type. Returns None if the next chunk isn't the specified type,
-    /// or we hit EOF.
+    /// or we hit EOF. Unknown chunks before it are skipped.
     pub(super) fn read_chunk_if_eq(&mut self, expect_chunk_type: ListChunkType) -> Result<Option<BufReader<'a>>, ParseError> {
+        self.skip_unknown_chunks()?;
         if let Some(actual_chunk_type) = self.0.peek_u32()? {
             if actual_chunk_type != (expect_chunk_type as u32) {
                 // Chunk doesn't match requested type.
@@ -1,22 +1,33 @@
+use alloc::{string::String, vec::Vec};
 use jumprope::JumpRope;
-use rle::{HasLength, RleRun};
+use rle::{HasLength, RleRun, SplitableSpanCtx};
 use crate::list::encoding::*;
 use crate::causalgraph::graph::GraphEntrySimple;
 use crate::list::operation::ListOpKind::{Del, Ins};
 use crate::list::{ListBranch, ListOpLog, switch};
 use crate::rle::{KVPair, RleVec};
-use crate::{AgentId, LV};
+use crate::{AgentId, Frontier, LV};
+use smallvec::SmallVec;
 use crate::frontier::local_frontier_is_root;
 use crate::list::op_metrics::ListOpMetrics;
 use crate::list::operation::ListOpKind;
+use crate::list::tie_break::TieBreak;
+use crate::list::content_intern::{ContentIndex, MIN_INTERNED_LEN};
+use crate::list::content_tags::TAG_BLOCK;
+use crate::list::anchor::AnchorBias;
 use crate::dtrange::DTRange;
-use crate::encoding::tools::calc_checksum;
-use crate::list::encoding::encode_tools::{Merger, push_leb_chunk, push_leb_str, push_leb_u32, push_leb_usize, push_u32_le, write_leb_bit_run};
+use crate::encoding::tools::{calc_checksum, CHECKSUM};
+use crate::list::encoding::encode_tools::{Merger, push_leb_chunk, push_leb_chunk_header, push_leb_str, push_leb_u32, push_leb_u64, push_leb_usize, push_u32_le, write_leb_bit_run};
 use crate::list::encoding::leb::{encode_leb_u32, encode_leb_usize, num_encode_zigzag_isize_old};
 use crate::listmerge::plan::M1PlanAction;
+use crate::causalgraph::agent_assignment::remote_ids::{RemoteVersion, VersionConversionError};
+use crate::list::encoding::txn_trace::TxnWalkItem;
 
 const ALLOW_VERBOSE: bool = false;
 
+/// The maximum number of operations in each Patches chunk, when the file has chunk checksums.
+pub(crate) const HISTORY_SEGMENT_LEN: usize = 1 << 14;
+
 /// Write an operation to the passed writer.
 fn write_op(dest: &mut Vec<u8>, op: &ListOpMetrics, cursor: &mut usize) {
     // Note I'm relying on the operation log itself to be iter_merged, which simplifies things here
@@ -90,6 +101,46 @@ fn write_op(dest: &mut Vec<u8>, op: &ListOpMetrics, cursor: &mut usize) {
     dest.extend_from_slice(&buf[..pos]);
 }
 
+/// The compression algorithm used for content stored in encoded files.
+///
+/// Compression algorithms are behind feature flags. If the requested algorithm isn't compiled in,
+/// encoding falls back to LZ4 (if enabled) or stores content uncompressed. Files are always
+/// decompressed transparently on load, so long as the algorithm used is compiled in.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Compression {
+    None,
+    /// LZ4 is very fast, but compresses text relatively poorly. Needs the `lz4` feature.
+    LZ4,
+    /// Zstd compression at the specified level (1-22, or negative levels for faster compression).
+    /// This is slower than LZ4 but produces much smaller files. Needs the `zstd` feature.
+    Zstd(i32),
+}
+
+impl Compression {
+    /// The compression algorithm which will actually be used, given the enabled features.
+    fn effective(self) -> Self {
+        match self {
+            Compression::Zstd(_) if !cfg!(feature = "zstd") => Compression::LZ4.effective(),
+            Compression::LZ4 if !cfg!(feature = "lz4") => Compression::None,
+            c => c,
+        }
+    }
+}
+
+/// Restricts which operations are written when encoding an oplog.
+///
+/// Operations can't be stored without the operations they depend on. So the encoded file contains
+/// the selected operations, plus everything in their causal history (other than what's already in
+/// the version being encoded from).
+#[derive(Debug, Clone, Copy)]
+pub enum EncodeFilter<'a> {
+    /// Only encode the named spans of local versions.
+    Spans(&'a [DTRange]),
+    /// Only encode operations created by the named agents. Agents which aren't in the oplog are
+    /// ignored.
+    Agents(&'a [AgentId]),
+}
+
 // TODO: Make a builder API for this
 #[derive(Debug, Clone)]
 pub struct EncodeOptions<'a> {
@@ -105,18 +156,59 @@ pub struct EncodeOptions<'a> {
     pub store_inserted_content: bool,
     pub store_deleted_content: bool,
 
-    pub compress_content: bool,
+    /// How to compress inserted and deleted content in the file.
+    pub compression: Compression,
+
+    /// Only encode some of the operations in the oplog. See [`EncodeFilter`].
+    pub filter: Option<EncodeFilter<'a>>,
+
+    /// Store a checksum for each chunk in the file, as well as for the file as a whole. This makes
+    /// files a few bytes bigger, but lets
+    /// [`load_from_with_repair`](crate::list::ListOpLog::load_from_with_repair) find which part of
+    /// a corrupt file is damaged.
+    pub store_chunk_checksums: bool,
+
+    /// Split large histories into several Patches chunks of up to 16384 operations each. Along
+    /// with [`store_chunk_checksums`](EncodeOptions::store_chunk_checksums), this lets
+    /// [`load_from_with_repair`](crate::list::ListOpLog::load_from_with_repair) recover the
+    /// operations before a damaged chunk. Files with more than 16384 operations written this way
+    /// can't be read by versions of diamond types from before this option was added.
+    pub segment_history: bool,
+
+    /// Store repeated inserted and deleted content (like pasted text) once, and replace later
+    /// copies with references to the first. This can make files much smaller, but files written
+    /// this way can't be read by versions of diamond types from before this option was added.
+    /// Only strings of at least 16 bytes are deduplicated.
+    pub dedup_content: bool,
 
     pub verbose: bool,
 }
 
+/// Local clocks which are bumped whenever the oplog's marks or suggestions change. Adding a mark or
+/// accepting a suggestion doesn't change the oplog's version, so these are used to find changes
+/// which haven't been saved or sent yet. They aren't encoded.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub(crate) struct ChangeClocks {
+    pub(crate) marks: usize,
+    pub(crate) suggestions: usize,
+}
+
+impl ChangeClocks {
+    /// Encoding changes since these clocks writes no marks or suggestions.
+    pub(crate) const MAX: ChangeClocks = ChangeClocks { marks: usize::MAX, suggestions: usize::MAX };
+}
+
 pub const ENCODE_PATCH: EncodeOptions = EncodeOptions {
     user_data: None,
     store_start_branch_content: false,
     experimentally_store_end_branch_content: false,
     store_inserted_content: true,
     store_deleted_content: false,
-    compress_content: true,
+    compression: Compression::LZ4,
+    filter: None,
+    store_chunk_checksums: false,
+    segment_history: false,
+    dedup_content: false,
     verbose: false
 };
 
@@ -126,7 +218,11 @@ pub const ENCODE_FULL: EncodeOptions = EncodeOptions {
     experimentally_store_end_branch_content: false,
     store_inserted_content: true,
     store_deleted_content: false, // ?? Not sure about this one!
-    compress_content: true,
+    compression: Compression::LZ4,
+    filter: None,
+    store_chunk_checksums: true,
+    segment_history: false,
+    dedup_content: false,
     verbose: false
 };
 
@@ -263,6 +359,142 @@ fn write_local_version(dest: &mut Vec<u8>, version: &[LV], map: &mut AgentMappin
     // buf.clear();
 }
 
+fn write_remote_lv(dest: &mut Vec<u8>, lv: LV, map: &mut AgentMapping, oplog: &ListOpLog) {
+    let (agent, seq) = oplog.lv_to_agent_version(lv);
+    push_leb_usize(dest, map.map(oplog, agent) as usize);
+    push_leb_usize(dest, seq);
+}
+
+fn write_frontier(dest: &mut Vec<u8>, version: &[LV], map: &mut AgentMapping, oplog: &ListOpLog) {
+    push_leb_usize(dest, version.len());
+    for lv in version {
+        write_remote_lv(dest, *lv, map, oplog);
+    }
+}
+
+/// Write out all the marks which only reference operations in to_version. Returns None if there
+/// aren't any.
+fn write_marks(oplog: &ListOpLog, to_version: &[LV], since: usize, map: &mut AgentMapping) -> Option<Vec<u8>> {
+    let graph = &oplog.cg.graph;
+    let mut buf = Vec::new();
+
+    for (id, entry) in oplog.marks.entries.iter() {
+        if entry.changed <= since { continue; }
+        // The anchors are always contained in created_at.
+        if !graph.frontier_contains_frontier(to_version, entry.created_at.as_ref()) { continue; }
+
+        push_leb_usize(&mut buf, map.map(oplog, id.agent) as usize);
+        push_leb_usize(&mut buf, id.seq);
+
+        let range = &entry.range;
+        let flags = range.expand as usize
+            | ((range.start_anchor.bias == AnchorBias::After) as usize) << 2
+            | ((range.end_anchor.bias == AnchorBias::After) as usize) << 3;
+        push_leb_usize(&mut buf, flags);
+        write_remote_lv(&mut buf, range.start_anchor.lv, map, oplog);
+        write_remote_lv(&mut buf, range.end_anchor.lv, map, oplog);
+        push_leb_usize(&mut buf, range.payload.len());
+        buf.extend_from_slice(&range.payload);
+
+        write_frontier(&mut buf, entry.created_at.as_ref(), map, oplog);
+        let deleted_at: SmallVec<[&Frontier; 1]> = entry.deleted_at.iter()
+            .filter(|v| graph.frontier_contains_frontier(to_version, v.as_ref()))
+            .collect();
+        push_leb_usize(&mut buf, deleted_at.len());
+        for v in deleted_at {
+            write_frontier(&mut buf, v.as_ref(), map, oplog);
+        }
+    }
+
+    if buf.is_empty() { None } else { Some(buf) }
+}
+
+/// Write out the state of all suggestions whose operations are included in to_version.
+fn write_suggestions(oplog: &ListOpLog, to_version: &[LV], since: usize, map: &mut AgentMapping) -> Option<Vec<u8>> {
+    let mut buf = Vec::new();
+    for (&(agent, seq_start), entry) in oplog.suggestions.entries.iter() {
+        if entry.changed <= since { continue; }
+        // An agent's changes are always ordered, so the suggestion is included if its last
+        // change is.
+        let last = oplog.cg.agent_assignment.client_data[agent as usize].seq_to_lv(entry.seq_end - 1);
+        if !oplog.cg.graph.frontier_contains_version(to_version, last) { continue; }
+
+        push_leb_usize(&mut buf, map.map(oplog, agent) as usize);
+        push_leb_usize(&mut buf, seq_start);
+        push_leb_usize(&mut buf, entry.seq_end - seq_start);
+        push_leb_usize(&mut buf, entry.state as usize);
+    }
+
+    if buf.is_empty() { None } else { Some(buf) }
+}
+
+/// Write out the redacted ranges of operations which are included in to_version.
+fn write_redactions(oplog: &ListOpLog, to_version: &[LV], map: &mut AgentMapping) -> Option<Vec<u8>> {
+    let mut buf = Vec::new();
+    for range in oplog.redactions.iter() {
+        let mut range = *range;
+        while !range.is_empty() {
+            let span = oplog.cg.agent_assignment.local_span_to_agent_span(range);
+            let len = span.seq_range.len();
+            if oplog.cg.graph.frontier_contains_version(to_version, range.start + len - 1) {
+                push_leb_usize(&mut buf, map.map(oplog, span.agent) as usize);
+                push_leb_usize(&mut buf, span.seq_range.start);
+                push_leb_usize(&mut buf, len);
+            }
+            range.start += len;
+        }
+    }
+
+    if buf.is_empty() { None } else { Some(buf) }
+}
+
+/// Write out the tags of encrypted content for the blocks of the operations in `ranges`, which
+/// are included in to_version.
+fn write_content_tags(oplog: &ListOpLog, ranges: &[DTRange], to_version: &[LV], map: &mut AgentMapping) -> Option<Vec<u8>> {
+    let mut buf = Vec::new();
+    for (agent, block) in oplog.content_tag_blocks(ranges) {
+        let Some((len, tags)) = oplog.content_tags.entries.get(&(agent, block)) else { continue; };
+        let last = oplog.cg.agent_assignment.client_data[agent as usize].seq_to_lv(block * TAG_BLOCK + len - 1);
+        if !oplog.cg.graph.frontier_contains_version(to_version, last) { continue; }
+
+        push_leb_usize(&mut buf, map.map(oplog, agent) as usize);
+        push_leb_usize(&mut buf, block);
+        push_leb_usize(&mut buf, *len);
+        push_leb_usize(&mut buf, tags.len());
+        for tag in tags.iter() {
+            buf.extend_from_slice(tag);
+        }
+    }
+
+    if buf.is_empty() { None } else { Some(buf) }
+}
+
+/// Write out all the agent metadata. This is always written in full, since it's small.
+fn write_agent_meta(oplog: &ListOpLog, map: &mut AgentMapping) -> Option<Vec<u8>> {
+    if oplog.agent_meta.is_empty() { return None; }
+
+    let mut buf = Vec::new();
+    for ((agent, key), entry) in oplog.agent_meta.entries.iter() {
+        push_leb_usize(&mut buf, map.map(oplog, *agent) as usize);
+        push_leb_str(&mut buf, key);
+        push_leb_str(&mut buf, &entry.value);
+        push_leb_usize(&mut buf, entry.clock);
+    }
+    Some(buf)
+}
+
+/// Write out all the links between agents.
+fn write_agent_links(oplog: &ListOpLog, map: &mut AgentMapping) -> Option<Vec<u8>> {
+    if oplog.agent_links.is_empty() { return None; }
+
+    let mut buf = Vec::new();
+    for (&agent, &target) in oplog.agent_links.links.iter() {
+        push_leb_usize(&mut buf, map.map(oplog, agent) as usize);
+        push_leb_usize(&mut buf, map.map(oplog, target) as usize);
+    }
+    Some(buf)
+}
+
 fn write_content<'a, I: Iterator<Item = &'a [u8]>>(dest: &mut Vec<u8>, kind: DataType, len: usize, iter: I, compressed: Option<&mut Vec<u8>>) {
     // There's two ways of storing content: compressed or not compressed.
     //
@@ -281,7 +513,7 @@ fn write_content<'a, I: Iterator<Item = &'a [u8]>>(dest: &mut Vec<u8>, kind: Dat
     const MIN_COMPRESSED_LEN: usize = 20;
 
     let (b, chunk_type) = match (compressed, len >= MIN_COMPRESSED_LEN) {
-        #[cfg(feature = "lz4")]
+        #[cfg(any(feature = "lz4", feature = "zstd"))]
         (Some(b), true) => {
             // Store the compressed length in the origin chunk.
             push_leb_usize(&mut buf, len);
@@ -302,7 +534,7 @@ fn write_content<'a, I: Iterator<Item = &'a [u8]>>(dest: &mut Vec<u8>, kind: Dat
 }
 
 fn write_content_str(dest: &mut Vec<u8>, s: &str, compressed: Option<&mut Vec<u8>>) {
-    write_content(dest, DataType::PlainText, s.len(), std::iter::once(s.as_bytes()), compressed);
+    write_content(dest, DataType::PlainText, s.len(), core::iter::once(s.as_bytes()), compressed);
 }
 
 fn write_content_rope(dest: &mut Vec<u8>, rope: &JumpRope, compressed: Option<&mut Vec<u8>>) {
@@ -320,7 +552,7 @@ fn write_chunk_str(dest: &mut Vec<u8>, s: &str, chunk_type: ListChunkType) {
 
 /// Returns compressed chunk size
 #[cfg(feature = "lz4")]
-fn write_compressed_chunk(dest: &mut Vec<u8>, data: &[u8]) -> usize {
+fn write_compressed_chunk_lz4(dest: &mut Vec<u8>, data: &[u8]) -> usize {
     // dbg!(&compress_bytes);
     let max_compressed_size = lz4_flex::block::get_maximum_output_size(data.len());
 
@@ -345,9 +577,101 @@ fn write_compressed_chunk(dest: &mut Vec<u8>, data: &[u8]) -> usize {
     pos
 }
 
-/// Simple helper struct for content (ins / del) chunks. These have two parts:
+/// Returns compressed chunk size
+#[cfg(feature = "zstd")]
+fn write_compressed_chunk_zstd(dest: &mut Vec<u8>, data: &[u8], level: i32) -> usize {
+    // Same layout as the LZ4 chunk: the uncompressed length, then the compressed data.
+    let mut compressed = Vec::new();
+    push_leb_usize(&mut compressed, data.len());
+
+    // The only errors here are from invalid parameters (like an out of range level), which are
+    // clamped by zstd anyway. Memory errors would panic elsewhere regardless.
+    compressed.extend_from_slice(&zstd::bulk::compress(data, level).unwrap());
+    push_leb_chunk(dest, ListChunkType::CompressedFieldsZstd, &compressed);
+
+    compressed.len()
+}
+
+/// Write a compressed chunk using the specified compression. Returns compressed chunk size.
+#[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables, clippy::ptr_arg))]
+fn write_compressed_chunk(dest: &mut Vec<u8>, compression: Compression, data: &[u8]) -> usize {
+    match compression {
+        #[cfg(feature = "lz4")]
+        Compression::LZ4 => write_compressed_chunk_lz4(dest, data),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd(level) => write_compressed_chunk_zstd(dest, data, level),
+        _ => unreachable!(),
+    }
+}
+
+/// The encoded file, as a list of byte strings to be written in order.
+#[derive(Debug, Default)]
+pub(super) struct EncodedPieces {
+    pieces: Vec<Vec<u8>>,
+
+    /// If set, the checksum of each top level chunk is stored in chunk_checksums.
+    checksum_chunks: bool,
+    /// The index of the first piece in the current chunk.
+    chunk_start: usize,
+    chunk_checksums: Vec<u32>,
+}
+
+impl EncodedPieces {
+    fn new(checksum_chunks: bool) -> Self {
+        Self { checksum_chunks, ..Default::default() }
+    }
+
+    fn push(&mut self, piece: Vec<u8>) {
+        self.pieces.push(piece);
+    }
+
+    /// Mark the end of a top level chunk. Pieces pushed since the last call make up the chunk.
+    fn end_chunk(&mut self) {
+        if self.checksum_chunks {
+            self.chunk_checksums.push(checksum_pieces(&self.pieces[self.chunk_start..]));
+        }
+        self.chunk_start = self.pieces.len();
+    }
+
+    fn push_chunk(&mut self, chunk_type: ListChunkType, data: Vec<u8>) {
+        let mut header = Vec::new();
+        push_leb_chunk_header(&mut header, chunk_type, data.len());
+        self.push(header);
+        self.push(data);
+        self.end_chunk();
+    }
+
+    fn len(&self) -> usize {
+        self.pieces.iter().map(|piece| piece.len()).sum()
+    }
+
+    /// The checksum of everything written so far.
+    fn checksum(&self) -> u32 {
+        checksum_pieces(&self.pieces)
+    }
+
+    pub(super) fn concat(self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.len());
+        for piece in self.pieces {
+            result.extend_from_slice(&piece);
+        }
+        result
+    }
+}
+
+fn checksum_pieces(pieces: &[Vec<u8>]) -> u32 {
+    let mut digest = CHECKSUM.digest();
+    for piece in pieces {
+        digest.update(piece);
+    }
+    digest.finalize()
+}
+
+/// Simple helper struct for content (ins / del) chunks. These have up to three parts:
 /// - A RLE bit vector describing which elements of the specified type have known lengths
 /// - The data itself
+/// - If content is deduplicated, a list of places where repeated content was removed from the
+///   data. Each entry is (position in the data, distance back to the first copy, length), in bytes.
 ///
 /// Its gross that I need to pass a generic parameter here, since it'll always be write_bit_run.
 /// I wish there were a cleaner way to write this.
@@ -355,23 +679,55 @@ struct ContentChunk<F: FnMut(RleRun<bool>, &mut Vec<u8>)> {
     kind: ListOpKind,
     known_out: Vec<u8>,
     bit_writer: Merger<RleRun<bool>, F, Vec<u8>>,
-    content: String
+    content: String,
+
+    /// Only used when content is deduplicated.
+    dedup: Option<ContentIndex>,
+    repeats_out: Vec<u8>,
+    last_repeat_pos: usize,
+}
+
+/// The child chunks of a single Patches chunk, before the content has been baked.
+struct HistorySegment<F: FnMut(RleRun<bool>, &mut Vec<u8>)> {
+    inserted_content: Option<ContentChunk<F>>,
+    deleted_content: Option<ContentChunk<F>>,
+    agent_assignment_chunk: Vec<u8>,
+    ops_chunk: Vec<u8>,
+    txns_chunk: Vec<u8>,
 }
 
 // impl<F: FnMut(S, &mut Vec<u8>)> ContentChunk<F> {
 impl<F: FnMut(RleRun<bool>, &mut Vec<u8>)> ContentChunk<F> {
-    fn new(f: F, kind: ListOpKind) -> Self {
+    fn new(f: F, kind: ListOpKind, dedup: bool) -> Self {
         Self {
             kind,
             known_out: Vec::new(),
             bit_writer: Merger::new(f),
             content: String::new(),
+            dedup: dedup.then(ContentIndex::default),
+            repeats_out: Vec::new(),
+            last_repeat_pos: 0,
         }
     }
 
     fn push(&mut self, content: Option<&str>, len: usize) {
         let known = if let Some(content) = content {
-            self.content.push_str(content);
+            let pos = self.content.len();
+            let repeat = match self.dedup.as_mut() {
+                Some(index) if content.len() >= MIN_INTERNED_LEN => {
+                    index.find_or_insert(self.content.as_bytes(), content.as_bytes(), pos)
+                }
+                _ => None,
+            };
+
+            if let Some(first) = repeat {
+                push_leb_usize(&mut self.repeats_out, pos - self.last_repeat_pos);
+                push_leb_usize(&mut self.repeats_out, pos - first.start);
+                push_leb_usize(&mut self.repeats_out, first.len());
+                self.last_repeat_pos = pos;
+            } else {
+                self.content.push_str(content);
+            }
             true
         } else {
             false
@@ -394,20 +750,110 @@ impl<F: FnMut(RleRun<bool>, &mut Vec<u8>)> ContentChunk<F> {
             write_content_str(&mut buf, &self.content, compressed_out);
 
             push_leb_chunk(&mut buf, ListChunkType::ContentIsKnown, &self.known_out);
+            if !self.repeats_out.is_empty() {
+                push_leb_chunk(&mut buf, ListChunkType::ContentRepeats, &self.repeats_out);
+            }
             Some(buf)
         }
     }
 }
 
 impl ListOpLog {
+    /// Find the version containing all the operations selected by the filter, and their history.
+    pub(crate) fn filter_version(&self, filter: EncodeFilter) -> Frontier {
+        let mut versions: SmallVec<[LV; 4]> = SmallVec::new();
+        let mut push_span = |span: DTRange| {
+            // The last version in a span doesn't necessarily contain the rest of the span, since
+            // spans can contain concurrent changes. But the end of each graph entry does.
+            for e in self.cg.graph.iter_range(span) {
+                versions.push(e.span.last());
+            }
+        };
+
+        match filter {
+            EncodeFilter::Spans(spans) => {
+                for span in spans {
+                    if !span.is_empty() { push_span(*span); }
+                }
+            }
+            EncodeFilter::Agents(agents) => {
+                for &agent in agents {
+                    // Agents this oplog doesn't know about have no operations to encode.
+                    let Some(client_data) = self.cg.agent_assignment.client_data.get(agent as usize) else { continue; };
+                    for KVPair(_, span) in client_data.lv_for_seq.iter() {
+                        push_span(*span);
+                    }
+                }
+            }
+        }
+
+        self.cg.graph.find_dominators(&versions)
+    }
+
     /// Encode the data stored in the OpLog into a (custom) compact binary form suitable for saving
     /// to disk, or sending over the network.
     pub fn encode_from(&self, opts: EncodeOptions, from_version: &[LV]) -> Vec<u8> {
+        self.encode_pieces(opts, from_version, None, ChangeClocks::default()).concat()
+    }
+
+    /// Variant of [`encode_from`](ListOpLog::encode_from) which only writes the marks and
+    /// suggestions which changed after the oplog's change clocks passed `since`. Marks and
+    /// suggestion states don't change the oplog's version, so this is used to save or send them
+    /// on their own.
+    pub(crate) fn encode_from_changes_since(&self, opts: EncodeOptions, from_version: &[LV], since: ChangeClocks) -> Vec<u8> {
+        self.encode_pieces(opts, from_version, None, since).concat()
+    }
+
+    /// The current values of the oplog's change clocks.
+    pub(crate) fn change_clocks(&self) -> ChangeClocks {
+        ChangeClocks { marks: self.marks.clock, suggestions: self.suggestions.clock }
+    }
+
+    /// Variant of [`encode`](ListOpLog::encode) which writes the file to `w` as its written,
+    /// instead of returning it. This avoids making a second full copy of the file in memory.
+    ///
+    /// `w` is written to in lots of small pieces, so it should usually be buffered.
+    #[cfg(feature = "std")]
+    pub fn encode_to<W: std::io::Write>(&self, w: W, opts: EncodeOptions) -> std::io::Result<()> {
+        self.encode_from_to(w, opts, &[])
+    }
+
+    /// Variant of [`encode_from`](ListOpLog::encode_from) which writes the file to `w`. See
+    /// [`encode_to`](ListOpLog::encode_to).
+    #[cfg(feature = "std")]
+    pub fn encode_from_to<W: std::io::Write>(&self, mut w: W, opts: EncodeOptions, from_version: &[LV]) -> std::io::Result<()> {
+        for piece in self.encode_pieces(opts, from_version, None, ChangeClocks::default()).pieces {
+            w.write_all(&piece)?;
+        }
+        Ok(())
+    }
+
+    /// Async version of [`encode_to`](ListOpLog::encode_to). The file is assembled before anything
+    /// is written, so this doesn't yield while encoding.
+    #[cfg(feature = "async")]
+    pub async fn encode_to_async<W: futures_util::AsyncWrite + Unpin>(&self, mut w: W, opts: EncodeOptions<'_>) -> std::io::Result<()> {
+        use futures_util::AsyncWriteExt;
+        let pieces = self.encode_pieces(opts, &[], None, ChangeClocks::default());
+        for piece in pieces.pieces {
+            w.write_all(&piece).await?;
+        }
+        w.flush().await
+    }
+
+    /// Encode the oplog. If base_version is passed, it's written as a BaseVersion chunk. Only marks
+    /// and suggestions which changed after the change clocks passed `since` are written.
+    pub(super) fn encode_pieces(&self, opts: EncodeOptions, from_version: &[LV], base_version: Option<Vec<u8>>, since: ChangeClocks) -> EncodedPieces {
         // if !frontier_is_root(from_frontier) {
         //     unimplemented!("Encoding from a non-root frontier is not implemented");
         // }
         let verbose = ALLOW_VERBOSE && opts.verbose;
 
+        // The version containing everything we're going to write.
+        let to_version = match opts.filter {
+            Some(filter) => self.filter_version(filter),
+            None => self.cg.version.clone(),
+        };
+
         // Before anything else, we'll scan the oplog and assemble all the data in memory that we
         // need to write.
 
@@ -438,34 +884,16 @@ impl ListOpLog {
         // - Interleaved it would compress much less well with snappy / lz4.
 
         // Only used when compression is enabled.
-        let mut compress_bytes = if opts.compress_content && cfg!(feature = "lz4") {
+        let compression = opts.compression.effective();
+        let mut compress_bytes = if compression != Compression::None {
             Some(Vec::new())
         } else { None };
 
-        let mut inserted_content = if opts.store_inserted_content {
-            Some(ContentChunk::new(write_leb_bit_run, Ins))
-        } else { None };
-        let mut deleted_content = if opts.store_deleted_content {
-            Some(ContentChunk::new(write_leb_bit_run, Del))
-        } else { None };
-
         // Map from old agent ID -> new agent ID in the file.
         //
         // (Agent ID 0 is reserved for ROOT, to make special parents slightly simpler.)
         let mut agent_mapping = AgentMapping::new(self);
 
-        // let mut agent_assignment_chunk = SpanWriter::new(push_run_u32);
-        let mut agent_assignment_chunk = Vec::new();
-        let mut agent_assignment_writer = Merger::new(|run: AgentAssignmentRun, _| {
-            write_assignment_run(&mut agent_assignment_chunk, run);
-        });
-
-        let mut ops_chunk = Vec::new();
-        let mut last_cursor_pos: usize = 0;
-        let mut ops_writer = Merger::new(|op, _| {
-            write_op(&mut ops_chunk, &op, &mut last_cursor_pos);
-        });
-
         // Parents are always smaller than the item itself (txn.span.start). So we can build a txn
         // map while we go showing how the incoming txns and outgoing txns connect together.
         //
@@ -473,134 +901,200 @@ impl ListOpLog {
         // output.
         let mut txn_map = RleVec::<KVPair<DTRange>>::new();
         let mut next_output_time = 0;
-        let mut txns_chunk = Vec::new();
-        let mut txns_writer = Merger::new(|txn: GraphEntrySimple, agent_mapping: &mut AgentMapping| {
-            // println!("Upstream {}-{}", txn.span.start, txn.span.end);
-            // First add this entry to the txn map.
-            let len = txn.span.len();
-            let output_range = (next_output_time .. next_output_time + len).into();
-            // txn_map.push(KVPair(txn.span.start, output_range));
-            txn_map.insert(KVPair(txn.span.start, output_range));
-            next_output_time = output_range.end;
-
-            push_leb_usize(&mut txns_chunk, len);
-
-            // Then the parents.
-            if txn.parents.is_root() {
-                // Parenting off the root is special-cased, because its rare in practice (well,
-                // usually exactly 1 item will have the parents as root). We'll write a single dummy
-                // value with foreign 0 here, because we (unfortunately) need to mark the list is
-                // empty.
-
-                // let n = 0, has_more = false, is_foreign = true. -> val = 1.
-                push_leb_usize(&mut txns_chunk, 1);
-            } else {
-                let mut iter = txn.parents.iter().peekable();
-                while let Some(&p) = iter.next() {
-                    // let p = p; // intellij bug
-                    let has_more = iter.peek().is_some();
-
-                    let mut write_parent_diff = |mut n: usize, is_foreign: bool| {
-                        n = mix_bit_usize(n, has_more);
-                        n = mix_bit_usize(n, is_foreign);
-                        push_leb_usize(&mut txns_chunk, n);
-                    };
-
-                    // Parents are either local or foreign. Local changes are changes we've written
-                    // (already) to the file. And foreign changes are changes that point outside the
-                    // local part of the DAG we're sending.
-                    //
-                    // Most parents will be local.
-                    if let Some((map, offset)) = txn_map.find_with_offset(p) {
-                        // Local change!
-                        // TODO: There's a sort of bug here. Local parents should (probably?) be sorted
-                        // in the file, but this mapping doesn't guarantee that. Currently I'm
-                        // re-sorting after reading - which is necessary for external parents anyway.
-                        // But allowing unsorted local parents is vaguely upsetting.
-                        let mapped_parent = map.1.start + offset;
-
-                        write_parent_diff(output_range.start - mapped_parent, false);
-                    } else {
-                        // Foreign change
-                        // println!("Region does not contain parent for {}", p);
-
-                        let (local_agent, seq) = self.lv_to_agent_version(p);
-                        let mapped_agent = agent_mapping.map(self, local_agent);
-                        debug_assert!(mapped_agent >= 1);
-
-                        // There are probably more compact ways to do this, but the txn data set is
-                        // usually quite small anyway, even in large histories. And most parents objects
-                        // will be in the set anyway. So I'm not too concerned about a few extra bytes
-                        // here.
+
+        // With segment_history set, the operations are split into segments, each stored in its
+        // own Patches chunk. If a segment is damaged, the segments before it can still be loaded.
+        // Walks are split where they cross a segment boundary.
+        let segment_len = if opts.segment_history { HISTORY_SEGMENT_LEN } else { usize::MAX };
+        let mut segments: Vec<Vec<TxnWalkItem>> = vec![Vec::new()];
+        let mut len_here = 0;
+        for mut walk in self.cg.graph.optimized_txns_between(from_version, to_version.as_ref()) {
+            while len_here + walk.consume.len() > segment_len {
+                let split = walk.consume.start + (segment_len - len_here);
+                if split > walk.consume.start {
+                    segments.last_mut().unwrap().push(TxnWalkItem {
+                        parents: walk.parents,
+                        consume: (walk.consume.start..split).into(),
+                    });
+                    walk.parents = Frontier::new_1(split - 1);
+                    walk.consume.start = split;
+                }
+                segments.push(Vec::new());
+                len_here = 0;
+            }
+            len_here += walk.consume.len();
+            segments.last_mut().unwrap().push(walk);
+        }
+
+        let mut history_segments = Vec::with_capacity(segments.len());
+        for walks in segments {
+            let mut inserted_content = if opts.store_inserted_content {
+                Some(ContentChunk::new(write_leb_bit_run, Ins, opts.dedup_content))
+            } else { None };
+            let mut deleted_content = if opts.store_deleted_content {
+                Some(ContentChunk::new(write_leb_bit_run, Del, opts.dedup_content))
+            } else { None };
+
+            // let mut agent_assignment_chunk = SpanWriter::new(push_run_u32);
+            let mut agent_assignment_chunk = Vec::new();
+            let mut agent_assignment_writer = Merger::new(|run: AgentAssignmentRun, _| {
+                write_assignment_run(&mut agent_assignment_chunk, run);
+            });
+
+            let mut ops_chunk = Vec::new();
+            let mut last_cursor_pos: usize = 0;
+            let max_op_len = self.max_op_len;
+            let mut ops_writer = Merger::new(|mut op: ListOpMetrics, _| {
+                // Split long operations to match the oplog's max_op_len. Content positions aren't
+                // valid on merged operations (see below), but they aren't needed to split or write them.
+                op.content_pos = None;
+                if let Some(max) = max_op_len {
+                    while op.len() > max {
+                        let rest = op.truncate_ctx(max, &self.operation_ctx);
+                        write_op(&mut ops_chunk, &op, &mut last_cursor_pos);
+                        op = rest;
+                    }
+                }
+                write_op(&mut ops_chunk, &op, &mut last_cursor_pos);
+            });
+
+            let mut txns_chunk = Vec::new();
+            let mut txns_writer = Merger::new(|txn: GraphEntrySimple, agent_mapping: &mut AgentMapping| {
+                // println!("Upstream {}-{}", txn.span.start, txn.span.end);
+                // First add this entry to the txn map.
+                let len = txn.span.len();
+                let output_range = (next_output_time .. next_output_time + len).into();
+                // txn_map.push(KVPair(txn.span.start, output_range));
+                txn_map.insert(KVPair(txn.span.start, output_range));
+                next_output_time = output_range.end;
+
+                push_leb_usize(&mut txns_chunk, len);
+
+                // Then the parents.
+                if txn.parents.is_root() {
+                    // Parenting off the root is special-cased, because its rare in practice (well,
+                    // usually exactly 1 item will have the parents as root). We'll write a single dummy
+                    // value with foreign 0 here, because we (unfortunately) need to mark the list is
+                    // empty.
+
+                    // let n = 0, has_more = false, is_foreign = true. -> val = 1.
+                    push_leb_usize(&mut txns_chunk, 1);
+                } else {
+                    let mut iter = txn.parents.iter().peekable();
+                    while let Some(&p) = iter.next() {
+                        // let p = p; // intellij bug
+                        let has_more = iter.peek().is_some();
+
+                        let mut write_parent_diff = |mut n: usize, is_foreign: bool| {
+                            n = mix_bit_usize(n, has_more);
+                            n = mix_bit_usize(n, is_foreign);
+                            push_leb_usize(&mut txns_chunk, n);
+                        };
+
+                        // Parents are either local or foreign. Local changes are changes we've written
+                        // (already) to the file. And foreign changes are changes that point outside the
+                        // local part of the DAG we're sending.
                         //
-                        // I'm adding 1 to the mapped agent to make room for ROOT. This is quite dirty!
-                        write_parent_diff(mapped_agent as usize, true);
-                        push_leb_usize(&mut txns_chunk, seq);
+                        // Most parents will be local.
+                        if let Some((map, offset)) = txn_map.find_with_offset(p) {
+                            // Local change!
+                            // TODO: There's a sort of bug here. Local parents should (probably?) be sorted
+                            // in the file, but this mapping doesn't guarantee that. Currently I'm
+                            // re-sorting after reading - which is necessary for external parents anyway.
+                            // But allowing unsorted local parents is vaguely upsetting.
+                            let mapped_parent = map.1.start + offset;
+
+                            write_parent_diff(output_range.start - mapped_parent, false);
+                        } else {
+                            // Foreign change
+                            // println!("Region does not contain parent for {}", p);
+
+                            let (local_agent, seq) = self.lv_to_agent_version(p);
+                            let mapped_agent = agent_mapping.map(self, local_agent);
+                            debug_assert!(mapped_agent >= 1);
+
+                            // There are probably more compact ways to do this, but the txn data set is
+                            // usually quite small anyway, even in large histories. And most parents objects
+                            // will be in the set anyway. So I'm not too concerned about a few extra bytes
+                            // here.
+                            //
+                            // I'm adding 1 to the mapped agent to make room for ROOT. This is quite dirty!
+                            write_parent_diff(mapped_agent as usize, true);
+                            push_leb_usize(&mut txns_chunk, seq);
+                        }
                     }
                 }
-            }
-        });
-
-        // If we just iterate in the current order, this code would be way simpler :p
-        // let iter = self.cg.history.optimized_txns_between(from_frontier, &self.frontier);
-        // for walk in self.cg.parents.iter() {
-        for walk in self.cg.graph.optimized_txns_between(from_version, self.cg.version.as_ref()) {
-            // We only care about walk.consume and parents.
-
-            // We need to update *lots* of stuff in here!!
-
-            // 1. Agent names and agent assignment
-            for KVPair(_, span) in self.cg.agent_assignment.client_with_localtime.iter_range_ctx(walk.consume, &()) {
-                // Mark the agent as in-use (if we haven't already)
-                let mapped_agent = agent_mapping.map(self, span.agent);
-
-                // dbg!(&span);
-
-                // agent_assignment is a list of (agent, len) pairs.
-                // dbg!(span);
-                agent_assignment_writer.push(AgentAssignmentRun {
-                    agent: mapped_agent,
-                    delta: agent_mapping.seq_delta(span.agent, span.seq_range),
-                    len: span.len()
-                });
-            }
+            });
+
+            // If we just iterate in the current order, this code would be way simpler :p
+            // let iter = self.cg.history.optimized_txns_between(from_frontier, &self.frontier);
+            // for walk in self.cg.parents.iter() {
+            for walk in walks {
+                // We only care about walk.consume and parents.
+
+                // We need to update *lots* of stuff in here!!
+
+                // 1. Agent names and agent assignment
+                for KVPair(_, span) in self.cg.agent_assignment.client_with_localtime.iter_range_ctx(walk.consume, &()) {
+                    // Mark the agent as in-use (if we haven't already)
+                    let mapped_agent = agent_mapping.map(self, span.agent);
+
+                    // dbg!(&span);
+
+                    // agent_assignment is a list of (agent, len) pairs.
+                    // dbg!(span);
+                    agent_assignment_writer.push(AgentAssignmentRun {
+                        agent: mapped_agent,
+                        delta: agent_mapping.seq_delta(span.agent, span.seq_range),
+                        len: span.len()
+                    });
+                }
 
-            // 2. Operations!
-            for (op, content) in self.iter_range_simple(walk.consume) {
-                let op = op.1;
+                // 2. Operations!
+                for (op, content) in self.iter_range_simple(walk.consume) {
+                    let op = op.1;
 
-                // DANGER!! Its super important we pull out the content here rather than in
-                // ops_writer somehow. The reason is that the content_pos field on the merged
-                // OperationInternal objects will be invalid! Total foot gun there :p
+                    // DANGER!! Its super important we pull out the content here rather than in
+                    // ops_writer somehow. The reason is that the content_pos field on the merged
+                    // OperationInternal objects will be invalid! Total foot gun there :p
 
-                if op.kind == Ins && opts.store_inserted_content {
-                    // For now at least, we can't skip inserted content for inserts.
-                    // TODO: Reconsider this at some point.
-                    assert!(content.is_some());
-                }
+                    if op.kind == Ins && opts.store_inserted_content {
+                        // For now at least, we can't skip inserted content for inserts.
+                        // TODO: Reconsider this at some point.
+                        assert!(content.is_some());
+                    }
+
+                    let content_chunk = switch(op.kind,
+                                               &mut inserted_content,
+                                               &mut deleted_content
+                    );
+                    if let Some(content_chunk) = content_chunk {
+                        content_chunk.push(content, op.len());
+                    }
 
-                let content_chunk = switch(op.kind,
-                                           &mut inserted_content,
-                                           &mut deleted_content
-                );
-                if let Some(content_chunk) = content_chunk {
-                    content_chunk.push(content, op.len());
+                    ops_writer.push(op);
                 }
 
-                ops_writer.push(op);
+                // 3. Parents!
+                txns_writer.push2(GraphEntrySimple {
+                    span: walk.consume,
+                    parents: walk.parents
+                }, &mut agent_mapping);
             }
 
-            // 3. Parents!
-            txns_writer.push2(GraphEntrySimple {
-                span: walk.consume,
-                parents: walk.parents
-            }, &mut agent_mapping);
+            agent_assignment_writer.flush();
+            ops_writer.flush();
+            txns_writer.flush2(&mut agent_mapping);
+
+            history_segments.push(HistorySegment {
+                inserted_content,
+                deleted_content,
+                agent_assignment_chunk,
+                ops_chunk,
+                txns_chunk,
+            });
         }
 
-        agent_assignment_writer.flush();
-        ops_writer.flush();
-        txns_writer.flush2(&mut agent_mapping);
-
         // This nominally needs to happen before we write out agent_mapping.
         // TODO: Support partial data sets. (from_frontier)
         let mut start_branch = Vec::new();
@@ -613,21 +1107,39 @@ impl ListOpLog {
             if opts.store_start_branch_content {
                 let branch_here = ListBranch::new_at_local_version(self, from_version);
                 // dbg!(&branch_here);
-                write_content_rope(&mut start_branch, &branch_here.content.borrow(), compress_bytes.as_mut());
+                write_content_rope(&mut start_branch, &branch_here.content, compress_bytes.as_mut());
             }
         }
 
         let end_branch = if opts.experimentally_store_end_branch_content {
             let mut end_branch = Vec::new();
-            write_local_version(&mut end_branch, self.cg.version.as_ref(), &mut agent_mapping, self);
+            write_local_version(&mut end_branch, to_version.as_ref(), &mut agent_mapping, self);
 
-            let branch_here = ListBranch::new_at_tip(self);
-            write_content_rope(&mut end_branch, &branch_here.content.borrow(), compress_bytes.as_mut());
+            let branch_here = ListBranch::new_at_local_version(self, to_version.as_ref());
+            write_content_rope(&mut end_branch, &branch_here.content, compress_bytes.as_mut());
 
             Some(end_branch)
         } else { None };
         // dbg!(&start_branch);
 
+        // This needs to happen before the agent mapping is written out, since marks can name agents
+        // which haven't made any changes.
+        let marks = if self.marks.is_empty() { None } else {
+            write_marks(self, to_version.as_ref(), since.marks, &mut agent_mapping)
+        };
+        let suggestions = if self.suggestions.is_empty() { None } else {
+            write_suggestions(self, to_version.as_ref(), since.suggestions, &mut agent_mapping)
+        };
+        let redactions = if self.redactions.is_empty() { None } else {
+            write_redactions(self, to_version.as_ref(), &mut agent_mapping)
+        };
+        let content_tags = if self.content_tags.is_empty() { None } else {
+            let (_, ranges) = self.cg.graph.diff(from_version, to_version.as_ref());
+            write_content_tags(self, &ranges, to_version.as_ref(), &mut agent_mapping)
+        };
+        let agent_meta = write_agent_meta(self, &mut agent_mapping);
+        let agent_links = write_agent_links(self, &mut agent_mapping);
+
         // self.write_xf_since(from_version);
 
         // TODO: The fileinfo chunk should specify encoding version and information
@@ -649,41 +1161,120 @@ impl ListOpLog {
             push_leb_chunk(&mut fileinfo_buf, ListChunkType::UserData, data);
         }
 
+        // Tie break strategy
+        if self.tie_break() != TieBreak::default() {
+            let mut buf = Vec::new();
+            push_leb_u32(&mut buf, self.tie_break() as u32);
+            push_leb_chunk(&mut fileinfo_buf, ListChunkType::TieBreak, &buf);
+        }
+
+        // Agent metadata
+        if let Some(buf) = agent_meta.as_ref() {
+            push_leb_chunk(&mut fileinfo_buf, ListChunkType::AgentMeta, buf);
+        }
+        if let Some(buf) = agent_links.as_ref() {
+            push_leb_chunk(&mut fileinfo_buf, ListChunkType::AgentLinks, buf);
+        }
+
+        let content_repeats = history_segments.iter()
+            .flat_map(|segment| [&segment.inserted_content, &segment.deleted_content])
+            .any(|c| c.as_ref().is_some_and(|c| !c.repeats_out.is_empty()));
+
         // Bake inserted & deleted content. I need to do this here because the CompressedFields
         // chunk goes first in the file, so if we compress anything, it needs to be filled up.
-        let inserted_content = inserted_content.and_then(|inserted_content| {
-            if verbose {
-                println!("Inserted text length {}", inserted_content.content.len());
-            }
+        //
+        // When the history is segmented, each segment's content is compressed into a
+        // CompressedFields chunk at the start of its own Patches chunk instead. That way a damaged
+        // segment doesn't stop the segments before it from being read.
+        let segmented = history_segments.len() > 1;
+        let mut any_compressed = false;
+        let history_segments: Vec<_> = history_segments.into_iter().map(|mut segment| {
+            let mut segment_compress_bytes = if segmented && compress_bytes.is_some() {
+                Some(Vec::new())
+            } else { None };
+            let mut compress_into = if segmented {
+                segment_compress_bytes.as_mut()
+            } else { compress_bytes.as_mut() };
+
+            let inserted_content = segment.inserted_content.take().and_then(|inserted_content| {
+                if verbose {
+                    println!("Inserted text length {}", inserted_content.content.len());
+                }
 
-            inserted_content.flush(compress_bytes.as_mut())
-        });
-        let deleted_content = deleted_content.and_then(|deleted_content| {
-            if verbose {
-                println!("Deleted text length {}", deleted_content.content.len());
-            }
+                inserted_content.flush(compress_into.as_deref_mut())
+            });
+            let deleted_content = segment.deleted_content.take().and_then(|deleted_content| {
+                if verbose {
+                    println!("Deleted text length {}", deleted_content.content.len());
+                }
 
-            deleted_content.flush(compress_bytes.as_mut())
-        });
+                deleted_content.flush(compress_into)
+            });
 
+            let segment_compressed = segment_compress_bytes.filter(|b| !b.is_empty()).map(|bytes| {
+                let mut chunk = Vec::new();
+                let compressed_len = write_compressed_chunk(&mut chunk, compression, &bytes);
+                if verbose {
+                    println!("Compressed {} bytes in the segment to {}", bytes.len(), compressed_len);
+                }
+                chunk
+            });
+            any_compressed |= segment_compressed.is_some();
+            (segment_compressed, segment, inserted_content, deleted_content)
+        }).collect();
+        any_compressed |= compress_bytes.as_ref().is_some_and(|b| !b.is_empty());
+
+        // The features chunk goes last in the fileinfo chunk, since we only know if any content
+        // was compressed once it has all been written.
+        let mut features = FormatFeatures::NONE;
+        if any_compressed {
+            features |= match compression {
+                Compression::Zstd(_) => FormatFeatures::ZSTD,
+                _ => FormatFeatures::LZ4,
+            };
+        }
+        if marks.is_some() { features |= FormatFeatures::MARKS; }
+        if suggestions.is_some() { features |= FormatFeatures::SUGGESTIONS; }
+        if redactions.is_some() { features |= FormatFeatures::REDACTIONS; }
+        if agent_meta.is_some() { features |= FormatFeatures::AGENT_META; }
+        if agent_links.is_some() { features |= FormatFeatures::AGENT_LINKS; }
+        if opts.store_chunk_checksums { features |= FormatFeatures::CHUNK_CHECKSUMS; }
+        if content_repeats { features |= FormatFeatures::CONTENT_REPEATS; }
+        if segmented { features |= FormatFeatures::SEGMENTED_HISTORY; }
+        if self.retention.purged { features |= FormatFeatures::DELETED_CONTENT_PURGED; }
+        if self.content_encrypted { features |= FormatFeatures::ENCRYPTED_CONTENT; }
+        if !features.is_empty() {
+            let mut buf = Vec::new();
+            push_leb_u64(&mut buf, features.bits());
+            push_leb_u64(&mut buf, features.intersection(FormatFeatures::ALWAYS_REQUIRED).bits());
+            push_leb_chunk(&mut fileinfo_buf, ListChunkType::Features, &buf);
+        }
 
-        // *** Actually start writing to Result!! YAAAAYYY ***
-        let mut result = Vec::new();
+        // *** Actually start writing the output!! YAAAAYYY ***
+        // The output is assembled as a list of pieces, which are either written out one by one or
+        // concatenated. This way the file never needs to be copied into a single buffer.
+        let mut out = EncodedPieces::new(opts.store_chunk_checksums);
         // The file starts with MAGIC_BYTES
-        result.extend_from_slice(&MAGIC_BYTES);
-        push_leb_usize(&mut result, PROTOCOL_VERSION);
+        let mut header = MAGIC_BYTES.to_vec();
+        push_leb_usize(&mut header, PROTOCOL_VERSION);
+        out.push(header);
+        // The header isn't part of any chunk.
+        out.chunk_start = out.pieces.len();
 
         // We'll write a series of chunks. Each chunk has a chunk header (chunk type, length).
         // The first chunk is CompressedFields, in case we need compressed content later.
 
-        #[cfg(not(feature = "lz4"))] {
+        #[cfg(not(any(feature = "lz4", feature = "zstd")))] {
             debug_assert!(compress_bytes.is_none());
         }
 
-        #[cfg(feature = "lz4")] {
+        #[cfg(any(feature = "lz4", feature = "zstd"))] {
             if let Some(compress_bytes) = compress_bytes {
                 if !compress_bytes.is_empty() {
-                    let compressed_len = write_compressed_chunk(&mut result, &compress_bytes);
+                    let mut chunk = Vec::new();
+                    let compressed_len = write_compressed_chunk(&mut chunk, compression, &compress_bytes);
+                    out.push(chunk);
+                    out.end_chunk();
                     if verbose {
                         println!("Compressed {} bytes in the file to {}", compress_bytes.len(), compressed_len);
                     }
@@ -691,61 +1282,120 @@ impl ListOpLog {
             }
         }
 
-        let mut write_chunk = |c: ListChunkType, data: &mut Vec<u8>| {
+        let write_chunk = |out: &mut EncodedPieces, c: ListChunkType, data: Vec<u8>| {
             if verbose {
                 println!("{:?} length {}", c, data.len());
             }
             // dbg!(&data);
-            push_leb_chunk(&mut result, c, data.as_slice());
-            data.clear();
+            out.push_chunk(c, data);
         };
 
-        write_chunk(ListChunkType::FileInfo, &mut fileinfo_buf);
+        write_chunk(&mut out, ListChunkType::FileInfo, fileinfo_buf);
+
+        if let Some(bytes) = base_version {
+            write_chunk(&mut out, ListChunkType::BaseVersion, bytes);
+        }
 
         // *** Start Branch - which was filled in above. ***
-        write_chunk(ListChunkType::StartBranch, &mut start_branch);
+        write_chunk(&mut out, ListChunkType::StartBranch, start_branch);
 
-        if let Some(mut bytes) = end_branch {
-            write_chunk(ListChunkType::ExperimentalEndBranch, &mut bytes);
+        if let Some(bytes) = end_branch {
+            write_chunk(&mut out, ListChunkType::ExperimentalEndBranch, bytes);
         }
 
         // *** Patches ***
-        // I'll just assemble it in buf. There's a lot of sloppy use of vec<u8>'s in here.
-        let mut patches_buf = fileinfo_buf;
+        // The patches chunk is made of child chunks. Rather than copying them all into one buffer,
+        // the chunk header is written with the total length, followed by each child chunk. Each
+        // segment of the history gets its own Patches chunk.
+        for (segment_compressed, segment, inserted_content, deleted_content) in history_segments {
+            let mut patches: SmallVec<[(ListChunkType, Vec<u8>); 5]> = SmallVec::new();
+            if let Some(bytes) = inserted_content {
+                patches.push((ListChunkType::PatchContent, bytes));
+            }
+            if let Some(bytes) = deleted_content {
+                patches.push((ListChunkType::PatchContent, bytes));
+            }
 
-        if let Some(bytes) = inserted_content {
-            push_leb_chunk(&mut patches_buf, ListChunkType::PatchContent, &bytes);
-        }
-        if let Some(bytes) = deleted_content {
-            push_leb_chunk(&mut patches_buf, ListChunkType::PatchContent, &bytes);
+            patches.push((ListChunkType::OpVersions, segment.agent_assignment_chunk));
+            patches.push((ListChunkType::OpTypeAndPosition, segment.ops_chunk));
+            patches.push((ListChunkType::OpParents, segment.txns_chunk));
+
+            // The segment's compressed chunk already has its header.
+            let mut patches: SmallVec<[(Vec<u8>, Vec<u8>); 6]> = patches.into_iter().map(|(c, data)| {
+                let mut header = Vec::new();
+                push_leb_chunk_header(&mut header, c, data.len());
+                (header, data)
+            }).collect();
+            if let Some(chunk) = segment_compressed {
+                patches.insert(0, (Vec::new(), chunk));
+            }
+            let patches_len = patches.iter().map(|(header, data)| header.len() + data.len()).sum();
+            if verbose {
+                println!("{:?} length {}", ListChunkType::Patches, patches_len);
+            }
+            let mut header = Vec::new();
+            push_leb_chunk_header(&mut header, ListChunkType::Patches, patches_len);
+            out.push(header);
+            for (header, data) in patches {
+                if !header.is_empty() { out.push(header); }
+                out.push(data);
+            }
+            out.end_chunk();
         }
 
-        push_leb_chunk(&mut patches_buf, ListChunkType::OpVersions, &agent_assignment_chunk);
-        push_leb_chunk(&mut patches_buf, ListChunkType::OpTypeAndPosition, &ops_chunk);
-        push_leb_chunk(&mut patches_buf, ListChunkType::OpParents, &txns_chunk);
-
-        write_chunk(ListChunkType::Patches, &mut patches_buf);
+        if let Some(bytes) = marks {
+            write_chunk(&mut out, ListChunkType::Marks, bytes);
+        }
+        if let Some(bytes) = suggestions {
+            write_chunk(&mut out, ListChunkType::Suggestions, bytes);
+        }
+        if let Some(bytes) = redactions {
+            write_chunk(&mut out, ListChunkType::Redactions, bytes);
+        }
+        if let Some(bytes) = content_tags {
+            write_chunk(&mut out, ListChunkType::ContentTags, bytes);
+        }
 
         // TODO (later): Final branch content.
 
         // println!("checksum {checksum}");
-        let checksum = calc_checksum(&result);
-        push_u32_le(&mut patches_buf, checksum);
-        push_leb_chunk(&mut result, ListChunkType::Crc, &patches_buf);
-        // write_chunk(Chunk::CRC, &mut buf);
-        // push_u32(&mut result, checksum);
+        let mut checksum = Vec::new();
+        push_u32_le(&mut checksum, out.checksum());
+        let chunk_checksums = core::mem::take(&mut out.chunk_checksums);
+        out.push_chunk(ListChunkType::Crc, checksum);
+
+        // The chunk checksums go after the file's checksum, so older versions of diamond types
+        // ignore them. They cover each chunk before the Crc chunk, in order. The chunk ends with
+        // a checksum of its own content.
+        if opts.store_chunk_checksums {
+            let mut buf = Vec::new();
+            push_leb_usize(&mut buf, chunk_checksums.len());
+            for c in chunk_checksums {
+                push_u32_le(&mut buf, c);
+            }
+            let own_checksum = calc_checksum(&buf);
+            push_u32_le(&mut buf, own_checksum);
+            out.push_chunk(ListChunkType::ChunkChecksums, buf);
+        }
 
         if verbose {
-            println!("== Total length {}", result.len());
+            println!("== Total length {}", out.len());
         }
 
-        result
+        out
     }
 
     pub fn encode(&self, opts: EncodeOptions) -> Vec<u8> {
         self.encode_from(opts, &[])
     }
 
+    /// Variant of [`encode_from`](ListOpLog::encode_from) which names the starting version using
+    /// remote IDs. This is useful when a remote peer tells us which version it already has.
+    pub fn encode_from_remote(&self, opts: EncodeOptions, from_version: &[RemoteVersion]) -> Result<Vec<u8>, VersionConversionError> {
+        let from_version = self.cg.agent_assignment.try_remote_to_local_frontier(from_version.iter().copied())?;
+        Ok(self.encode_from(opts, from_version.as_ref()))
+    }
+
     /// Encode the data stored in the OpLog into a (custom) compact binary form suitable for saving
     /// to disk, or sending over the network.
     pub fn encode_simple(&self, _opts: EncodeOptions) -> Vec<u8> {
@@ -955,4 +1605,25 @@ mod tests {
         // let hex_str = data.iter().map(|x| format!("{:02X} ({})", x, std::char::from_u32(*x as u32).unwrap())).collect::<Vec<_>>();
         // dbg!(hex_str);
     }
+
+    #[test]
+    fn encode_to_writer() {
+        let mut doc = ListCRDT::new();
+        doc.get_or_create_agent_id("seph");
+        doc.insert(0, 0, "hi there");
+        doc.delete(0, 0..3);
+
+        let mut streamed = Vec::new();
+        doc.oplog.encode_to(&mut streamed, EncodeOptions::default()).unwrap();
+        assert_eq!(streamed, doc.oplog.encode(EncodeOptions::default()));
+        assert_eq!(ListOpLog::load_from(&streamed).unwrap().checkout_tip().content().to_string(), "there");
+
+        #[cfg(feature = "async")] {
+            use futures_util::FutureExt;
+            let mut streamed_async = Vec::new();
+            doc.oplog.encode_to_async(&mut streamed_async, EncodeOptions::default())
+                .now_or_never().unwrap().unwrap();
+            assert_eq!(streamed_async, streamed);
+        }
+    }
 }
\ No newline at end of file
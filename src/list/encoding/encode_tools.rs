@@ -1,6 +1,7 @@
-use std::mem::{replace, size_of};
+use alloc::vec::Vec;
+use core::mem::{replace, size_of};
 use rle::{MergableSpan, RleRun};
-use std::marker::PhantomData;
+use core::marker::PhantomData;
 use crate::list::encoding::ListChunkType;
 use crate::encoding::varint::mix_bit_usize;
 
@@ -42,7 +43,7 @@ pub(super) fn push_u32_le(into: &mut Vec<u8>, val: u32) {
     into.extend_from_slice(&bytes);
 }
 
-fn push_leb_chunk_header(into: &mut Vec<u8>, chunk_type: ListChunkType, len: usize) {
+pub(super) fn push_leb_chunk_header(into: &mut Vec<u8>, chunk_type: ListChunkType, len: usize) {
     push_leb_u32(into, chunk_type as u32);
     push_leb_usize(into, len);
 }
@@ -103,6 +104,8 @@ impl<S: MergableSpan, F: FnMut(S, &mut ())> Merger<S, F, ()> {
 
 impl<S: MergableSpan, F: FnMut(S, &mut Ctx), Ctx> Drop for Merger<S, F, Ctx> {
     fn drop(&mut self) {
+        // Without std we can't tell if we're already unwinding, so the check is skipped.
+        #[cfg(feature = "std")]
         if self.last.is_some() && !std::thread::panicking() {
             panic!("Merger dropped with unprocessed data");
         }
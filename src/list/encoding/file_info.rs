@@ -0,0 +1,268 @@
+//! Reading a file's header without loading it.
+//!
+//! Files list the format features they use in their FileInfo chunk. Some features (like
+//! compression) are *required* - a reader which doesn't understand them can't load the file.
+//! Others (like marks) can be skipped by older readers, which just load the rest of the file.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::ops::{BitOr, BitOrAssign};
+use crate::causalgraph::agent_assignment::remote_ids::{RemoteFrontierOwned, RemoteVersionOwned};
+use crate::encoding::parseerror::ParseError;
+use crate::encoding::varint::strip_bit_usize;
+use crate::list::encoding::{ListChunkType, PROTOCOL_VERSION};
+use crate::list::encoding::decode_tools::BufReader;
+use crate::list::ListOpLog;
+use crate::list::tie_break::TieBreak;
+
+/// A set of format features used by an encoded file.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub struct FormatFeatures(u64);
+
+impl FormatFeatures {
+    pub const NONE: Self = Self(0);
+    /// Content is compressed using LZ4.
+    pub const LZ4: Self = Self(1 << 0);
+    /// Content is compressed using zstd.
+    pub const ZSTD: Self = Self(1 << 1);
+    /// The file contains marks (range annotations).
+    pub const MARKS: Self = Self(1 << 2);
+    /// The file records which operations are suggestions.
+    pub const SUGGESTIONS: Self = Self(1 << 3);
+    /// The file stores a checksum for each chunk.
+    pub const CHUNK_CHECKSUMS: Self = Self(1 << 4);
+    /// Reserved for signed operations. Not written by this version of diamond types.
+    pub const SIGNATURES: Self = Self(1 << 5);
+    /// Reserved for non-text CRDT types. Not written by this version of diamond types.
+    pub const NON_TEXT: Self = Self(1 << 6);
+    /// The file contains metadata about agents.
+    pub const AGENT_META: Self = Self(1 << 7);
+    /// The file contains links between agents.
+    pub const AGENT_LINKS: Self = Self(1 << 8);
+    /// Repeated content is stored once, with references to it. Readers must understand this to
+    /// read the content. See
+    /// [`EncodeOptions::dedup_content`](crate::list::encoding::EncodeOptions::dedup_content).
+    pub const CONTENT_REPEATS: Self = Self(1 << 9);
+    /// Some deleted content was purged from the oplog before it was encoded. See
+    /// [`ListOpLog::purge_deleted_content`].
+    pub const DELETED_CONTENT_PURGED: Self = Self(1 << 10);
+    /// The file records which operations have been redacted. See
+    /// [`ListOpLog::redact`].
+    pub const REDACTIONS: Self = Self(1 << 11);
+    /// The content of the file's operations is encrypted. Readers which don't understand this
+    /// would show the encrypted content as text.
+    pub const ENCRYPTED_CONTENT: Self = Self(1 << 12);
+    /// The operations are split across several Patches chunks. Readers which don't understand
+    /// this would only load the first chunk. See
+    /// [`EncodeOptions::segment_history`](crate::list::encoding::EncodeOptions::segment_history).
+    pub const SEGMENTED_HISTORY: Self = Self(1 << 13);
+
+    /// The features this version of diamond types knows how to read.
+    pub const SUPPORTED: Self = Self(Self::LZ4.0 | Self::ZSTD.0 | Self::MARKS.0
+        | Self::SUGGESTIONS.0 | Self::CHUNK_CHECKSUMS.0 | Self::AGENT_META.0
+        | Self::AGENT_LINKS.0 | Self::CONTENT_REPEATS.0 | Self::DELETED_CONTENT_PURGED.0
+        | Self::REDACTIONS.0 | Self::ENCRYPTED_CONTENT.0 | Self::SEGMENTED_HISTORY.0);
+
+    /// Features which readers must understand to load a file. Files which use other features
+    /// can still be loaded by readers which don't understand them.
+    pub(crate) const ALWAYS_REQUIRED: Self = Self(Self::LZ4.0 | Self::ZSTD.0 | Self::CONTENT_REPEATS.0
+        | Self::ENCRYPTED_CONTENT.0 | Self::SEGMENTED_HISTORY.0);
+
+    pub const fn from_bits(bits: u64) -> Self { Self(bits) }
+
+    pub const fn bits(self) -> u64 { self.0 }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn intersection(self, other: Self) -> Self { Self(self.0 & other.0) }
+
+    /// The features in self which aren't in other.
+    pub const fn difference(self, other: Self) -> Self { Self(self.0 & !other.0) }
+
+    pub const fn is_empty(self) -> bool { self.0 == 0 }
+}
+
+impl BitOr for FormatFeatures {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self { Self(self.0 | rhs.0) }
+}
+
+impl BitOrAssign for FormatFeatures {
+    fn bitor_assign(&mut self, rhs: Self) { self.0 |= rhs.0; }
+}
+
+/// Information from the header of an encoded file. See [`ListOpLog::file_info`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FileInfo {
+    pub doc_id: Option<String>,
+    /// The names of the agents which made changes in the file.
+    pub agent_names: Vec<String>,
+    pub user_data: Option<Vec<u8>>,
+    pub tie_break: TieBreak,
+    /// The format features used by the file.
+    pub features: FormatFeatures,
+    /// The features a reader must understand to load the file.
+    pub required_features: FormatFeatures,
+    /// The version the file's operations were encoded from. The file can only be merged into an
+    /// oplog which has this version. Empty if the file starts from the beginning of history.
+    pub start_version: RemoteFrontierOwned,
+}
+
+impl FileInfo {
+    /// True if this version of diamond types can load the file.
+    pub fn is_supported(&self) -> bool {
+        FormatFeatures::SUPPORTED.contains(self.required_features)
+    }
+}
+
+impl<'a> BufReader<'a> {
+    /// Read the content of a Features chunk. Returns (features, required features).
+    pub(super) fn read_features(mut self) -> Result<(FormatFeatures, FormatFeatures), ParseError> {
+        let features = FormatFeatures(self.next_u64()?);
+        let required = FormatFeatures(self.next_u64()?);
+        // Later versions might add more fields.
+        Ok((features, required))
+    }
+
+    /// Read the content of a Version chunk, naming each agent by its name in the file.
+    fn read_remote_version(mut self, agent_names: &[String]) -> Result<RemoteFrontierOwned, ParseError> {
+        let mut result = RemoteFrontierOwned::new();
+        loop {
+            let (mapped_agent, has_more) = strip_bit_usize(self.next_usize()?);
+            let seq = self.next_usize()?;
+            if mapped_agent == 0 { break; } // Root.
+
+            let name = agent_names.get(mapped_agent - 1).ok_or(ParseError::InvalidLength)?;
+            result.push(RemoteVersionOwned(name.as_str().into(), seq));
+            if !has_more { break; }
+        }
+        self.expect_empty()?;
+        Ok(result)
+    }
+}
+
+/// Figure out which features an older file uses from the chunks it contains.
+fn infer_features(data: &[u8]) -> Result<FormatFeatures, ParseError> {
+    let mut reader = BufReader(data);
+    reader.read_magic()?;
+    reader.next_usize()?;
+
+    let mut features = FormatFeatures::NONE;
+    for chunk in reader.chunks() {
+        features |= match chunk?.0 {
+            ListChunkType::CompressedFieldsLZ4 => FormatFeatures::LZ4,
+            ListChunkType::CompressedFieldsZstd => FormatFeatures::ZSTD,
+            ListChunkType::Marks => FormatFeatures::MARKS,
+            ListChunkType::Suggestions => FormatFeatures::SUGGESTIONS,
+            ListChunkType::Redactions => FormatFeatures::REDACTIONS,
+            ListChunkType::ChunkChecksums => FormatFeatures::CHUNK_CHECKSUMS,
+            _ => FormatFeatures::NONE,
+        };
+    }
+    Ok(features)
+}
+
+impl ListOpLog {
+    /// Read the header of an encoded file, without loading the file. This is much faster than
+    /// loading the file, and works even if the file uses features this version of diamond types
+    /// can't read.
+    pub fn file_info(data: &[u8]) -> Result<FileInfo, ParseError> {
+        let mut reader = BufReader(data);
+        reader.read_magic()?;
+        if reader.next_usize()? != PROTOCOL_VERSION {
+            return Err(ParseError::UnsupportedProtocolVersion);
+        }
+
+        let mut reader = reader.chunks();
+        reader.read_chunk_if_eq(ListChunkType::CompressedFieldsLZ4)?;
+        reader.read_chunk_if_eq(ListChunkType::CompressedFieldsZstd)?;
+        let mut fileinfo = reader.expect_chunk(ListChunkType::FileInfo)?.chunks();
+
+        let doc_id = match fileinfo.read_chunk_if_eq(ListChunkType::DocId)? {
+            Some(chunk) => Some(chunk.into_content_str()?.to_string()),
+            None => None,
+        };
+        let mut agent_names_chunk = fileinfo.expect_chunk(ListChunkType::AgentNames)?;
+        let mut agent_names = Vec::new();
+        while !agent_names_chunk.is_empty() {
+            agent_names.push(agent_names_chunk.next_str()?.to_string());
+        }
+        let user_data = fileinfo.read_chunk_if_eq(ListChunkType::UserData)?.map(|c| c.0.to_vec());
+        let tie_break = match fileinfo.read_chunk_if_eq(ListChunkType::TieBreak)? {
+            Some(mut chunk) => TieBreak::try_from(chunk.next_u32()?)
+                .map_err(|_| ParseError::GenericInvalidData)?,
+            None => TieBreak::default(),
+        };
+        fileinfo.read_chunk_if_eq(ListChunkType::AgentMeta)?;
+        fileinfo.read_chunk_if_eq(ListChunkType::AgentLinks)?;
+
+        let (features, required_features) = match fileinfo.read_chunk_if_eq(ListChunkType::Features)? {
+            Some(chunk) => chunk.read_features()?,
+            None => {
+                let features = infer_features(data)?;
+                (features, features.intersection(FormatFeatures::ALWAYS_REQUIRED))
+            }
+        };
+
+        reader.read_chunk_if_eq(ListChunkType::BaseVersion)?;
+        let start_version = match reader.expect_chunk(ListChunkType::StartBranch)?.chunks()
+            .read_chunk_if_eq(ListChunkType::Version)? {
+            Some(chunk) => chunk.read_remote_version(&agent_names)?,
+            None => RemoteFrontierOwned::new(),
+        };
+
+        Ok(FileInfo { doc_id, agent_names, user_data, tie_break, features, required_features, start_version })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::string::ToString;
+    use alloc::vec;
+    use crate::causalgraph::agent_assignment::remote_ids::RemoteVersionOwned;
+    use crate::encoding::parseerror::ParseError;
+    use crate::list::encoding::{ENCODE_FULL, EncodeOptions, FormatFeatures, ListChunkType};
+    use crate::list::encoding::decode_oplog::DecodeOptions;
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn read_file_info() {
+        let mut oplog = ListOpLog::new();
+        oplog.doc_id = Some("doc".into());
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, &"hi there ".repeat(100));
+
+        let data = oplog.encode(EncodeOptions { user_data: Some(b"hello"), ..ENCODE_FULL });
+        let info = ListOpLog::file_info(&data).unwrap();
+        assert_eq!(info.doc_id.as_deref(), Some("doc"));
+        assert_eq!(info.agent_names, vec!["seph".to_string()]);
+        assert_eq!(info.user_data.as_deref(), Some(&b"hello"[..]));
+        assert_eq!(info.features, FormatFeatures::LZ4 | FormatFeatures::CHUNK_CHECKSUMS);
+        assert_eq!(info.required_features, FormatFeatures::LZ4);
+        assert!(info.is_supported());
+        assert!(info.start_version.is_empty());
+
+        let mut next = oplog.clone();
+        next.add_insert(seph, 0, "yo");
+        let patch = ListOpLog::file_info(&next.encode_from(ENCODE_FULL, oplog.local_frontier().as_ref())).unwrap();
+        assert_eq!(patch.start_version.as_slice(), &[RemoteVersionOwned("seph".into(), 899)]);
+
+        // Newer files can add unknown chunks, which are skipped.
+        let ignore_crc = || DecodeOptions { ignore_crc: true, ..Default::default() };
+        let mut newer = data.clone();
+        newer.splice(9..9, [90, 2, 1, 2]);
+        assert_eq!(ListOpLog::load_from_opts(&newer, ignore_crc()).unwrap(), oplog);
+
+        // But files which need features we don't support can't be loaded.
+        let features_chunk = [ListChunkType::Features as u8, 2, info.features.bits() as u8, 1];
+        let pos = data.windows(4).position(|w| w == features_chunk).unwrap();
+        let mut newer = data.clone();
+        newer[pos + 3] = FormatFeatures::SIGNATURES.bits() as u8;
+        assert_eq!(ListOpLog::load_from_opts(&newer, ignore_crc()).unwrap_err(),
+                   ParseError::UnsupportedFeatures(FormatFeatures::SIGNATURES));
+        assert!(!ListOpLog::file_info(&newer).unwrap().is_supported());
+    }
+}
@@ -1,6 +1,6 @@
 use rand::prelude::*;
 use crate::list::{ListCRDT, ListOpLog};
-use crate::list::encoding::EncodeOptions;
+use crate::list::encoding::{Compression, EncodeOptions};
 use crate::list::old_fuzzer_tools::old_make_random_change;
 use crate::list_fuzzer_tools::{choose_2, make_random_change};
 use crate::listmerge::simple_oplog::{SimpleBranch, SimpleOpLog};
@@ -28,7 +28,11 @@ fn fuzz_encode_decode_once(seed: u64) {
             experimentally_store_end_branch_content: false,
             store_inserted_content: true,
             store_deleted_content: true,
-            compress_content: true,
+            compression: Compression::LZ4,
+            filter: None,
+            store_chunk_checksums: false,
+            segment_history: false,
+            dedup_content: false,
             verbose: false
         });
 
@@ -95,7 +99,11 @@ fn fuzz_encode_decode_multi(seed: u64, verbose: bool) {
             experimentally_store_end_branch_content: false,
             store_inserted_content: true,
             store_deleted_content: true,
-            compress_content: true,
+            compression: Compression::LZ4,
+            filter: None,
+            store_chunk_checksums: false,
+            segment_history: false,
+            dedup_content: false,
             verbose: false
         };
         let a_data = a.oplog.encode(encode_opts.clone());
@@ -1,4 +1,4 @@
-use std::mem::size_of;
+use core::mem::size_of;
 use crate::encoding::parseerror::ParseError;
 
 /// We're using protobuf's encoding system for variable sized integers. Most numbers we store here
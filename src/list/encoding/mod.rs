@@ -4,6 +4,11 @@
 
 mod encode_oplog;
 mod decode_oplog;
+mod repair;
+mod file_info;
+mod chunked_patches;
+#[cfg(feature = "version_hashes")]
+mod patch_file;
 
 #[cfg(test)]
 mod tests;
@@ -18,7 +23,12 @@ mod txn_trace;
 use rle::MergableSpan;
 use crate::encoding::varint::*;
 use num_enum::TryFromPrimitive;
-pub use encode_oplog::{ENCODE_FULL, ENCODE_PATCH, EncodeOptions};
+pub use encode_oplog::{Compression, ENCODE_FULL, ENCODE_PATCH, EncodeFilter, EncodeOptions};
+pub(crate) use encode_oplog::ChangeClocks;
+pub use decode_oplog::{MergePolicy, PolicyViolation};
+pub use repair::{LostData, RepairReport};
+pub use file_info::{FileInfo, FormatFeatures};
+pub use chunked_patches::ChunkedPatches;
 
 const MAGIC_BYTES: [u8; 8] = *b"DMNDTYPS";
 
@@ -30,12 +40,26 @@ const PROTOCOL_VERSION: usize = 0;
 enum ListChunkType {
     /// Packed bytes storing any data compressed in later parts of the file.
     CompressedFieldsLZ4 = 5,
+    /// Like CompressedFieldsLZ4, but compressed using zstd.
+    CompressedFieldsZstd = 6,
 
     /// FileInfo contains optional UserData and AgentNames.
     FileInfo = 1,
     DocId = 2,
     AgentNames = 3,
     UserData = 4,
+    /// The strategy used to order concurrent inserts. Omitted when the default strategy is used.
+    TieBreak = 7,
+    /// The format features used by the file. See [`FormatFeatures`].
+    Features = 8,
+    /// Metadata about the agents named in AgentNames. See [`crate::list::agent_meta`].
+    AgentMeta = 15,
+    /// Links between agents named in AgentNames. See [`crate::list::agent_links`].
+    AgentLinks = 16,
+
+    /// The hashes of each version in the start version, in patch files written by
+    /// `encode_patch_since`.
+    BaseVersion = 9,
 
     /// The StartBranch chunk describes the state of the document before included patches have been
     /// applied.
@@ -54,10 +78,25 @@ enum ListChunkType {
     PatchContent = 24,
     /// ContentKnown is a RLE expressing which ranges of patches have known content
     ContentIsKnown = 25,
+    /// Where repeated content was removed from a PatchContent chunk's content. Only written when
+    /// content is deduplicated.
+    ContentRepeats = 26,
 
     TransformedPositions = 27, // Currently unused
 
+    /// Range annotations. See [`crate::list::marks`].
+    Marks = 30,
+    /// Which operations are suggestions. See [`crate::list::suggestions`].
+    Suggestions = 31,
+    /// Which operations have been redacted. See [`crate::list::redaction`].
+    Redactions = 32,
+    /// Authentication tags for encrypted content. See `list::content_tags`.
+    ContentTags = 33,
+
     Crc = 100,
+    /// A checksum for each chunk before the Crc chunk. See
+    /// [`EncodeOptions::store_chunk_checksums`].
+    ChunkChecksums = 101,
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone, TryFromPrimitive)]
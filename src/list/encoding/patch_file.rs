@@ -0,0 +1,115 @@
+//! Patch files, for incremental backups.
+//!
+//! A patch file is a normal encoded file containing the changes since some base version. The file
+//! also names the base version by its [version hashes](crate::causalgraph::hash), so it can only
+//! be merged into an oplog which has exactly the same history up to that point.
+
+use alloc::vec::Vec;
+use crate::causalgraph::hash::VersionHash;
+use crate::encoding::parseerror::ParseError;
+use crate::Frontier;
+use crate::list::encoding::{ChangeClocks, EncodeOptions, ListChunkType, PROTOCOL_VERSION};
+use crate::list::encoding::decode_tools::BufReader;
+use crate::list::encoding::encode_tools::push_leb_usize;
+use crate::list::ListOpLog;
+use crate::LV;
+
+/// Read the version hashes from the BaseVersion chunk of a patch file.
+fn read_base_hashes(data: &[u8]) -> Result<Vec<VersionHash>, ParseError> {
+    let mut reader = BufReader(data);
+    reader.read_magic()?;
+    if reader.next_usize()? != PROTOCOL_VERSION {
+        return Err(ParseError::UnsupportedProtocolVersion);
+    }
+
+    let mut reader = reader.chunks();
+    reader.read_chunk_if_eq(ListChunkType::CompressedFieldsLZ4)?;
+    reader.read_chunk_if_eq(ListChunkType::CompressedFieldsZstd)?;
+    reader.expect_chunk(ListChunkType::FileInfo)?;
+    let mut chunk = reader.read_chunk_if_eq(ListChunkType::BaseVersion)?
+        .ok_or(ParseError::MissingChunk(ListChunkType::BaseVersion as u32))?;
+
+    let count = chunk.next_usize()?;
+    if count > chunk.len() / 32 { return Err(ParseError::InvalidLength); }
+    let hashes = (0..count)
+        .map(|_| chunk.next_n_bytes(32).map(|h| h.try_into().unwrap()))
+        .collect::<Result<Vec<VersionHash>, _>>()?;
+    chunk.expect_empty()?;
+    Ok(hashes)
+}
+
+impl ListOpLog {
+    /// Encode the changes made since `frontier` as a patch file. This is like
+    /// [`encode_from`](ListOpLog::encode_from), but the file also names `frontier` by hash. Merge
+    /// it using [`merge_patch_file`](ListOpLog::merge_patch_file).
+    ///
+    /// This is useful for incremental backups. Store a full copy of the oplog once, then store
+    /// patch files since the previous backup.
+    pub fn encode_patch_since(&self, frontier: &[LV], opts: EncodeOptions) -> Vec<u8> {
+        let frontier = self.cg.graph.find_dominators(frontier);
+        let hashes = self.version_hashes();
+        let mut base_hashes: Vec<VersionHash> = frontier.iter()
+            .map(|lv| hashes.hash_of(*lv).unwrap())
+            .collect();
+        base_hashes.sort_unstable();
+
+        let mut base_version = Vec::with_capacity(1 + base_hashes.len() * 32);
+        push_leb_usize(&mut base_version, base_hashes.len());
+        for h in base_hashes {
+            base_version.extend_from_slice(&h);
+        }
+
+        self.encode_pieces(opts, frontier.as_ref(), Some(base_version), ChangeClocks::default()).concat()
+    }
+
+    /// Merge a patch file written by [`encode_patch_since`](ListOpLog::encode_patch_since).
+    ///
+    /// Returns [`ParseError::BaseVersionUnknown`] if this oplog doesn't contain the patch's base
+    /// version, with identical history. In that case the oplog is left unchanged.
+    pub fn merge_patch_file(&mut self, data: &[u8]) -> Result<Frontier, ParseError> {
+        let base_hashes = read_base_hashes(data)?;
+        if self.version_hashes().try_hashes_to_frontier(&base_hashes).is_none() {
+            return Err(ParseError::BaseVersionUnknown);
+        }
+
+        self.decode_and_add(data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::encoding::parseerror::ParseError;
+    use crate::list::encoding::ENCODE_FULL;
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn incremental_backups() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "hi there");
+        let full_backup = oplog.encode(ENCODE_FULL);
+        let backup_version = oplog.local_frontier();
+
+        oplog.add_delete_without_content(seph, 0..3);
+        oplog.add_insert(seph, 0, "oh ");
+        let patch = oplog.encode_patch_since(backup_version.as_ref(), ENCODE_FULL);
+
+        let mut restored = ListOpLog::load_from(&full_backup).unwrap();
+        restored.merge_patch_file(&patch).unwrap();
+        assert_eq!(restored, oplog);
+
+        // The patch can't be merged into a document which doesn't have its base version.
+        let mut empty = ListOpLog::new();
+        assert_eq!(empty.merge_patch_file(&patch), Err(ParseError::BaseVersionUnknown));
+        assert!(empty.is_empty());
+
+        // Or into one which has a different history with the same version IDs.
+        let mut other = ListOpLog::new();
+        let seph = other.get_or_create_agent_id("seph");
+        other.add_insert(seph, 0, "yo there");
+        assert_eq!(other.merge_patch_file(&patch), Err(ParseError::BaseVersionUnknown));
+
+        // Normal files aren't patch files.
+        assert!(matches!(oplog.merge_patch_file(&full_backup), Err(ParseError::MissingChunk(_))));
+    }
+}
@@ -0,0 +1,354 @@
+//! Loading damaged files.
+//!
+//! A single flipped bit in a large file makes [`ListOpLog::load_from`] fail the whole load. Files
+//! are made of a series of chunks, and some of them (like the marks) aren't needed to load the
+//! document's history. [`ListOpLog::load_from_with_repair`] finds the damaged chunks using the
+//! per-chunk checksums (see [`EncodeOptions::store_chunk_checksums`](super::EncodeOptions)) and
+//! loads what it can, reporting anything which was lost.
+//!
+//! Large files written with [`EncodeOptions::segment_history`](super::EncodeOptions) split the
+//! document's operations across several chunks, of up to 16384 operations each. If one of them is
+//! damaged, the history is cut back to the operations before it. Since operations are stored after everything they depend on, what's
+//! left is a valid history. If the first of these chunks is damaged, the file can't be repaired.
+//!
+//! Files without per-chunk checksums can only be checked as a whole. When they're damaged, there's
+//! no way to tell which part of the file is wrong, so they're only loaded by
+//! [`ListOpLog::load_from_with_repair_unverified`].
+
+use alloc::vec::Vec;
+use crate::encoding::parseerror::ParseError;
+use crate::encoding::tools::calc_checksum;
+use crate::list::encoding::{ListChunkType, MAGIC_BYTES, PROTOCOL_VERSION};
+use crate::list::encoding::decode_tools::BufReader;
+use crate::list::encoding::encode_tools::push_leb_usize;
+use crate::list::ListOpLog;
+
+/// Data which was lost while repairing a file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum LostData {
+    /// The marks (range annotations) in the file.
+    Marks,
+    /// The record of which operations are suggestions.
+    Suggestions,
+    /// The record of which operations have been redacted. The redacted content itself is never
+    /// stored in the file.
+    Redactions,
+    /// The authentication tags of encrypted content. Peers with the key can't decrypt content
+    /// without its tags.
+    ContentTags,
+    /// Operations from a damaged part of the history, and every operation stored after it.
+    History,
+}
+
+/// What [`ListOpLog::load_from_with_repair`] had to do to load a file.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RepairReport {
+    /// Parts of the file which were damaged, and skipped.
+    pub lost: Vec<LostData>,
+
+    /// The number of bytes at the end of the file which couldn't be read. This is usually because
+    /// the file was only partly written.
+    pub truncated_bytes: usize,
+
+    /// Set if the file's checksum didn't match, but the damaged part of the file couldn't be found.
+    /// This happens with files saved without per-chunk checksums. The loaded data might be wrong.
+    /// Only [`ListOpLog::load_from_with_repair_unverified`] loads files like this.
+    pub unverified: bool,
+}
+
+impl RepairReport {
+    /// True if the file loaded without any problems.
+    pub fn is_clean(&self) -> bool {
+        self.lost.is_empty() && self.truncated_bytes == 0 && !self.unverified
+    }
+}
+
+/// A top level chunk in a file.
+#[derive(Debug, Clone, Copy)]
+struct RawChunk<'a> {
+    chunk_type: u32,
+    /// The whole chunk, including its header.
+    bytes: &'a [u8],
+}
+
+impl<'a> RawChunk<'a> {
+    fn is(&self, chunk_type: ListChunkType) -> bool {
+        self.chunk_type == chunk_type as u32
+    }
+
+    fn content(&self) -> Result<BufReader<'a>, ParseError> {
+        let mut reader = BufReader(self.bytes);
+        reader.next_u32()?;
+        reader.next_usize()?;
+        Ok(reader)
+    }
+}
+
+/// Split a file into its top level chunks. Returns the chunks which could be read, and the number
+/// of bytes at the end of the file which couldn't be.
+fn split_chunks(data: &[u8]) -> Result<(Vec<RawChunk<'_>>, usize), ParseError> {
+    let mut reader = BufReader(data);
+    reader.read_magic()?;
+    if reader.next_usize()? != PROTOCOL_VERSION {
+        return Err(ParseError::UnsupportedProtocolVersion);
+    }
+
+    let mut chunks = Vec::new();
+    while !reader.is_empty() {
+        let start = data.len() - reader.len();
+        let mut r = reader.clone();
+        let Ok(chunk_type) = r.next_u32() else { break; };
+        let Ok(len) = r.next_usize() else { break; };
+        if len > r.len() { break; }
+        r.consume(len);
+
+        let end = data.len() - r.len();
+        chunks.push(RawChunk { chunk_type, bytes: &data[start..end] });
+        reader = r;
+    }
+
+    Ok((chunks, reader.len()))
+}
+
+/// Read the per-chunk checksums. Returns None if they're missing or damaged.
+fn read_chunk_checksums(chunks: &[RawChunk]) -> Option<Vec<u32>> {
+    let chunk = chunks.iter().find(|c| c.is(ListChunkType::ChunkChecksums))?;
+    let mut reader = chunk.content().ok()?;
+    reader.clone().check_chunk_checksums().ok()?;
+    let count = reader.next_usize().ok()?;
+    (0..count).map(|_| reader.next_u32_le()).collect::<Result<Vec<_>, _>>().ok()
+}
+
+/// Chunks which can be dropped from a file, and it will still load.
+fn optional_data(chunk: &RawChunk) -> Option<LostData> {
+    if chunk.is(ListChunkType::Marks) { Some(LostData::Marks) }
+    else if chunk.is(ListChunkType::Suggestions) { Some(LostData::Suggestions) }
+    else if chunk.is(ListChunkType::Redactions) { Some(LostData::Redactions) }
+    else if chunk.is(ListChunkType::ContentTags) { Some(LostData::ContentTags) }
+    else { None }
+}
+
+fn assemble(chunks: &[RawChunk]) -> Vec<u8> {
+    let mut data = MAGIC_BYTES.to_vec();
+    push_leb_usize(&mut data, PROTOCOL_VERSION);
+    for c in chunks {
+        data.extend_from_slice(c.bytes);
+    }
+    data
+}
+
+impl ListOpLog {
+    /// Load a file, skipping over any damaged or missing chunks which aren't needed to load the
+    /// document's history. Returns the loaded oplog, and a report of what was lost.
+    ///
+    /// Damaged chunks are found using the checksums written when
+    /// [`EncodeOptions::store_chunk_checksums`](crate::list::encoding::EncodeOptions) is set. If
+    /// the history was written in segments and part of it is damaged, the operations stored before
+    /// it are loaded. Errors are returned if the start of the document's history is damaged, or if
+    /// the file is damaged and has no per-chunk checksums.
+    pub fn load_from_with_repair(data: &[u8]) -> Result<(Self, RepairReport), ParseError> {
+        Self::repair(data, false)
+    }
+
+    /// Like [`load_from_with_repair`](ListOpLog::load_from_with_repair), but damaged files without
+    /// per-chunk checksums are loaded as well as possible, and marked as
+    /// [`unverified`](RepairReport::unverified). The loaded data might be wrong.
+    pub fn load_from_with_repair_unverified(data: &[u8]) -> Result<(Self, RepairReport), ParseError> {
+        Self::repair(data, true)
+    }
+
+    fn repair(data: &[u8], allow_unverified: bool) -> Result<(Self, RepairReport), ParseError> {
+        let err = match Self::load_from(data) {
+            Ok(oplog) => return Ok((oplog, RepairReport::default())),
+            Err(err) => err,
+        };
+
+        let (chunks, truncated_bytes) = split_chunks(data)?;
+        let mut report = RepairReport { truncated_bytes, ..Default::default() };
+
+        // The checksums cover every chunk before the Crc chunk.
+        let checksums = read_chunk_checksums(&chunks);
+        let checksummed_chunks = chunks.iter()
+            .position(|c| c.is(ListChunkType::Crc))
+            .unwrap_or(chunks.len());
+        if checksums.as_ref().is_some_and(|c| c.len() != checksummed_chunks) {
+            return Err(err);
+        }
+        report.unverified = checksums.is_none();
+        if report.unverified && !allow_unverified {
+            return Err(err);
+        }
+
+        let mut keep = Vec::with_capacity(chunks.len());
+        let mut seen_patches = false;
+        for (i, chunk) in chunks.iter().enumerate() {
+            if chunk.is(ListChunkType::Crc) || chunk.is(ListChunkType::ChunkChecksums) {
+                continue;
+            }
+
+            let is_patches = chunk.is(ListChunkType::Patches);
+            if is_patches && report.lost.contains(&LostData::History) {
+                // The rest of the history comes after the damaged segment.
+                continue;
+            }
+
+            let damaged = checksums.as_ref()
+                .is_some_and(|c| c.get(i).is_some_and(|&c| c != calc_checksum(chunk.bytes)));
+            if !damaged {
+                keep.push(*chunk);
+            } else if let Some(lost) = optional_data(chunk) {
+                report.lost.push(lost);
+            } else if is_patches && seen_patches {
+                report.lost.push(LostData::History);
+            } else {
+                return Err(err);
+            }
+            seen_patches |= is_patches;
+        }
+
+        if let Ok(oplog) = Self::load_from(&assemble(&keep)) {
+            return Ok((oplog, report));
+        }
+
+        // Without checksums we don't know which chunk is damaged. And if the history was cut
+        // short, the optional chunks can name operations which were lost. Try again without the
+        // optional chunks.
+        let (optional, required): (Vec<_>, Vec<_>) = keep.into_iter()
+            .partition(|c| optional_data(c).is_some());
+        let oplog = Self::load_from(&assemble(&required)).map_err(|_| err)?;
+        report.lost.extend(optional.iter().filter_map(optional_data));
+        Ok((oplog, report))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::{ListBranch, ListCRDT, ListOpLog};
+    use crate::list::anchor::Anchor;
+    use crate::list::encoding::{ENCODE_FULL, EncodeOptions, ListChunkType};
+    use crate::list::encoding::encode_oplog::HISTORY_SEGMENT_LEN;
+    use crate::list::encoding::repair::{LostData, RepairReport, split_chunks};
+    use crate::list::marks::{MarkExpand, MarkRange};
+
+    /// The byte range of the nth chunk of the specified type.
+    fn nth_chunk_range(data: &[u8], chunk_type: ListChunkType, n: usize) -> core::ops::Range<usize> {
+        let (chunks, _) = split_chunks(data).unwrap();
+        let c = chunks.iter().filter(|c| c.is(chunk_type)).nth(n).unwrap();
+        let start = c.bytes.as_ptr() as usize - data.as_ptr() as usize;
+        start..start + c.bytes.len()
+    }
+
+    fn chunk_range(data: &[u8], chunk_type: ListChunkType) -> core::ops::Range<usize> {
+        nth_chunk_range(data, chunk_type, 0)
+    }
+
+    #[test]
+    fn repair_damaged_files() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "hi there");
+        doc.add_mark(seph, MarkRange {
+            start_anchor: Anchor::before(0),
+            end_anchor: Anchor::after(1),
+            expand: MarkExpand::None,
+            payload: b"bold".to_vec(),
+        });
+        let mut no_marks = doc.oplog.clone();
+        no_marks.marks = Default::default();
+
+        let data = doc.oplog.encode(ENCODE_FULL);
+        assert_eq!(ListOpLog::load_from_with_repair(&data).unwrap(), (doc.oplog.clone(), RepairReport::default()));
+
+        // A bit flip in the marks loses the marks.
+        let mut damaged = data.clone();
+        damaged[chunk_range(&data, ListChunkType::Marks).end - 1] ^= 0x10;
+        assert!(ListOpLog::load_from(&damaged).is_err());
+        let (oplog, report) = ListOpLog::load_from_with_repair(&damaged).unwrap();
+        assert_eq!(oplog, no_marks);
+        assert_eq!(report.lost, vec![LostData::Marks]);
+        assert!(!report.unverified);
+
+        // A partly written file. The per-chunk checksums are at the end, so they're missing too.
+        let truncated = &data[..chunk_range(&data, ListChunkType::Crc).start + 2];
+        assert!(ListOpLog::load_from_with_repair(truncated).is_err());
+        let (oplog, report) = ListOpLog::load_from_with_repair_unverified(truncated).unwrap();
+        assert_eq!(oplog, doc.oplog);
+        assert_eq!(report.truncated_bytes, 2);
+        assert!(report.unverified);
+
+        // But the operations themselves can't be lost.
+        let mut damaged = data.clone();
+        damaged[chunk_range(&data, ListChunkType::Patches).end - 1] ^= 0x10;
+        assert!(ListOpLog::load_from_with_repair(&damaged).is_err());
+    }
+
+    #[test]
+    fn unverified_files_need_opt_in() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "hi there");
+        let data = oplog.encode(EncodeOptions {
+            store_chunk_checksums: false,
+            segment_history: false,
+            ..ENCODE_FULL
+        });
+
+        // Without per-chunk checksums, the damage could be anywhere.
+        let mut damaged = data.clone();
+        damaged[chunk_range(&data, ListChunkType::FileInfo).end - 1] ^= 0x10;
+        assert!(ListOpLog::load_from(&damaged).is_err());
+        assert!(ListOpLog::load_from_with_repair(&damaged).is_err());
+        if let Ok((_, report)) = ListOpLog::load_from_with_repair_unverified(&damaged) {
+            assert!(report.unverified);
+            assert!(!report.is_clean());
+        }
+    }
+
+    fn long_history() -> ListOpLog {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        for i in 0..2000 {
+            oplog.add_insert(seph, (i * 7) % (oplog.len() + 1), "twenty chars of text");
+        }
+        assert!(oplog.len() > 2 * HISTORY_SEGMENT_LEN);
+        oplog
+    }
+
+    #[test]
+    fn history_not_segmented_by_default() {
+        // Readers from before segmented history would only load the first segment.
+        let oplog = long_history();
+        for data in [oplog.encode(EncodeOptions::default()), oplog.encode(ENCODE_FULL)] {
+            let (chunks, _) = split_chunks(&data).unwrap();
+            assert_eq!(chunks.iter().filter(|c| c.is(ListChunkType::Patches)).count(), 1);
+            assert_eq!(ListOpLog::load_from(&data).unwrap(), oplog);
+        }
+    }
+
+    #[test]
+    fn repair_damaged_history() {
+        let oplog = long_history();
+        let data = oplog.encode(EncodeOptions {
+            segment_history: true,
+            ..ENCODE_FULL
+        });
+        assert_eq!(ListOpLog::load_from(&data).unwrap(), oplog);
+
+        // Damage to the second part of the history loses everything from there on.
+        let mut damaged = data.clone();
+        damaged[nth_chunk_range(&data, ListChunkType::Patches, 1).end - 1] ^= 0x10;
+        assert!(ListOpLog::load_from(&damaged).is_err());
+        let (loaded, report) = ListOpLog::load_from_with_repair(&damaged).unwrap();
+        assert_eq!(report.lost, vec![LostData::History]);
+        assert!(!report.unverified);
+        assert_eq!(loaded.len(), HISTORY_SEGMENT_LEN);
+        let expected = ListBranch::new_at_local_version(&oplog, &[HISTORY_SEGMENT_LEN - 1]);
+        assert_eq!(loaded.checkout_tip().content(), expected.content());
+
+        // But the start of the history can't be lost.
+        let mut damaged = data.clone();
+        damaged[chunk_range(&data, ListChunkType::Patches).end - 1] ^= 0x10;
+        assert!(ListOpLog::load_from_with_repair(&damaged).is_err());
+    }
+}
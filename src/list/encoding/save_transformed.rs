@@ -1,3 +1,4 @@
+use alloc::vec::Vec;
 use rle::{AppendRle, HasLength, RleRun};
 use crate::encoding::Merger;
 use crate::list::encoding::leb::num_encode_zigzag_isize_old;
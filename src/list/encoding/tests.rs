@@ -1,5 +1,7 @@
 use crate::encoding::parseerror::ParseError;
+use crate::DTError;
 use crate::list::{ListCRDT, ListOpLog};
+use crate::list::operation::TextOperation;
 use crate::list::encoding::decode_oplog::{dbg_print_chunks_in, DecodeOptions};
 use crate::frontier::local_frontier_eq;
 use super::*;
@@ -21,7 +23,11 @@ fn check_encode_decode_matches(oplog: &ListOpLog) {
         experimentally_store_end_branch_content: false,
         store_inserted_content: true,
         store_deleted_content: true,
-        compress_content: true,
+        compression: Compression::LZ4,
+        filter: None,
+        store_chunk_checksums: false,
+        segment_history: false,
+        dedup_content: true,
         verbose: false,
     });
 
@@ -187,7 +193,11 @@ fn check_unroll_works(dest: &ListOpLog, src: &ListOpLog) {
         experimentally_store_end_branch_content: false,
         store_inserted_content: true,
         store_deleted_content: true,
-        compress_content: true,
+        compression: Compression::LZ4,
+        filter: None,
+        store_chunk_checksums: false,
+        segment_history: false,
+        dedup_content: false,
         verbose: false
     });
 
@@ -244,7 +254,11 @@ fn save_load_save_load() {
         experimentally_store_end_branch_content: false,
         store_inserted_content: false,
         store_deleted_content: false,
-        compress_content: true,
+        compression: Compression::LZ4,
+        filter: None,
+        store_chunk_checksums: false,
+        segment_history: false,
+        dedup_content: false,
         verbose: false
     });
     dbg_print_chunks_in(&bytes);
@@ -257,7 +271,11 @@ fn save_load_save_load() {
         experimentally_store_end_branch_content: false,
         store_inserted_content: false, // Need to say false here to avoid an assert for this.
         store_deleted_content: true,
-        compress_content: true,
+        compression: Compression::LZ4,
+        filter: None,
+        store_chunk_checksums: false,
+        segment_history: false,
+        dedup_content: false,
         verbose: false
     });
     let oplog3 = ListOpLog::load_from(&bytes2).unwrap();
@@ -405,7 +423,11 @@ fn compat_simple_doc() {
         experimentally_store_end_branch_content: false,
         store_inserted_content: true,
         store_deleted_content: false,
-        compress_content: true,
+        compression: Compression::LZ4,
+        filter: None,
+        store_chunk_checksums: false,
+        segment_history: false,
+        dedup_content: false,
         verbose: false
     }));
 
@@ -422,4 +444,190 @@ fn compat_simple_doc() {
         let bytes2_compressed_full = &[68, 77, 78, 68, 84, 89, 80, 83, 0, 5, 11, 9, 144, 104, 105, 32, 116, 104, 101, 114, 101, 109, 1, 7, 3, 5, 4, 115, 101, 112, 104, 10, 0, 20, 24, 24, 8, 0, 14, 2, 4, 9, 25, 1, 19, 21, 2, 2, 13, 22, 4, 65, 79, 11, 0, 23, 2, 13, 1, 100, 4, 128, 32, 8, 191];
         assert_eq!(ListOpLog::load_from(bytes2_compressed_full).unwrap(), doc.oplog);
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn compression_options_round_trip() {
+    let mut doc = ListCRDT::new();
+    let seph = doc.get_or_create_agent_id("seph");
+    for i in 0..20 {
+        doc.insert(seph, i * 10, "The quick brown fox jumps over the lazy dog. ");
+    }
+    doc.delete(seph, 5..300);
+
+    let encode = |compression| doc.oplog.encode(EncodeOptions {
+        store_deleted_content: true,
+        compression,
+        ..ENCODE_FULL
+    });
+
+    let uncompressed = encode(Compression::None);
+    for compression in [Compression::None, Compression::LZ4, Compression::Zstd(1), Compression::Zstd(19)] {
+        let data = encode(compression);
+        // Compression algorithms which aren't compiled in fall back to another format.
+        assert!(data.len() <= uncompressed.len());
+        assert_eq!(ListOpLog::load_from(&data).unwrap(), doc.oplog);
+    }
+}
+
+#[test]
+fn dedup_content_round_trip() {
+    let mut doc = ListCRDT::new();
+    let seph = doc.get_or_create_agent_id("seph");
+    let pasted = "The quick brown fox jumps over the lazy dog. ";
+    for i in 0..10 {
+        doc.insert(seph, i, pasted);
+        // Type something in between, so the pastes aren't merged together.
+        doc.insert(seph, 0, "x");
+    }
+    doc.delete(seph, 5..100);
+
+    let encode = |compression, dedup_content| doc.oplog.encode(EncodeOptions {
+        store_deleted_content: true,
+        compression,
+        dedup_content,
+        ..ENCODE_FULL
+    });
+
+    let plain = encode(Compression::None, false);
+    let dedup = encode(Compression::None, true);
+    assert!(dedup.len() + 8 * pasted.len() < plain.len());
+    assert_eq!(ListOpLog::load_from(&dedup).unwrap(), doc.oplog);
+
+    let info = ListOpLog::file_info(&dedup).unwrap();
+    assert!(info.required_features.contains(FormatFeatures::CONTENT_REPEATS));
+    assert!(!ListOpLog::file_info(&plain).unwrap().features.contains(FormatFeatures::CONTENT_REPEATS));
+
+    let data = encode(Compression::LZ4, true);
+    assert_eq!(ListOpLog::load_from(&data).unwrap(), doc.oplog);
+}
+
+#[test]
+#[cfg(all(feature = "lz4", feature = "zstd"))]
+fn zstd_smaller_than_lz4() {
+    let doc = simple_doc();
+    let mut oplog = doc.oplog;
+    let seph = oplog.get_or_create_agent_id("seph");
+    oplog.add_insert(seph, 0, &"Lorem ipsum dolor sit amet, consectetur adipiscing elit. ".repeat(50));
+
+    let lz4 = oplog.encode(EncodeOptions { compression: Compression::LZ4, ..ENCODE_FULL });
+    let zstd = oplog.encode(EncodeOptions { compression: Compression::Zstd(19), ..ENCODE_FULL });
+    assert!(zstd.len() < lz4.len());
+    assert_eq!(ListOpLog::load_from(&zstd).unwrap(), oplog);
+}
+
+#[test]
+fn encode_with_filter() {
+    let mut oplog = ListOpLog::new();
+    let seph = oplog.get_or_create_agent_id("seph");
+    let mike = oplog.get_or_create_agent_id("mike");
+    let kaarina = oplog.get_or_create_agent_id("kaarina");
+    let base = oplog.add_insert(seph, 0, "hi there");
+    let m = oplog.add_insert_at(mike, &[base], 2, " you");
+    // Kaarina's edit is concurrent with mike's, so it shouldn't be included.
+    let k = oplog.add_delete_at(kaarina, &[base], 0..3);
+    oplog.add_insert_at(seph, &[m, k], 0, "x");
+
+    let encode = |filter: EncodeFilter<'_>| oplog.encode(EncodeOptions {
+        filter: Some(filter),
+        ..ENCODE_FULL
+    });
+
+    let result = ListOpLog::load_from(&encode(EncodeFilter::Agents(&[mike]))).unwrap();
+    assert_eq!(result.checkout_tip(), oplog.checkout(&[m]));
+    assert_eq!(result.cg.agent_assignment.get_agent_id("kaarina"), None);
+    // Unknown agents are ignored.
+    let result = ListOpLog::load_from(&encode(EncodeFilter::Agents(&[mike, 100]))).unwrap();
+    assert_eq!(result.checkout_tip(), oplog.checkout(&[m]));
+
+    // Spans are selected separately, even when they're adjacent in local versions.
+    let spans = [(m - 3..k + 1).into()];
+    let result = ListOpLog::load_from(&encode(EncodeFilter::Spans(&spans))).unwrap();
+    assert_eq!(result.checkout_tip(), oplog.checkout(&[m, k]));
+    assert_eq!(result.len(), k + 1);
+
+    // Filtered data can be merged into an oplog which already has some of it.
+    let spans = [(k..k + 1).into()];
+    let mut partial = ListOpLog::load_from(&encode(EncodeFilter::Spans(&spans))).unwrap();
+    partial.decode_and_add(&oplog.encode(ENCODE_FULL)).unwrap();
+    assert_eq!(partial.checkout_tip(), oplog.checkout_tip());
+}
+
+#[test]
+fn merge_bytes_validated() {
+    let server = simple_doc().oplog;
+    let strict = MergePolicy {
+        reject_unknown_agents: true,
+        reject_out_of_order_seqs: true,
+        max_new_ops: Some(10),
+    };
+
+    // Normal edits from a known agent are accepted.
+    let mut client = server.clone();
+    let seph = client.get_or_create_agent_id("seph");
+    let v = client.add_insert(seph, 0, "yo ");
+    let mut oplog = server.clone();
+    oplog.merge_bytes_validated(&client.encode_from(ENCODE_PATCH, server.cg.version.as_ref()), &strict).unwrap();
+    assert_eq!(oplog, client);
+
+    // Each policy violation leaves the oplog untouched.
+    let check_rejected = |patch: &[u8], expected: PolicyViolation| {
+        let mut oplog = server.clone();
+        assert_eq!(oplog.merge_bytes_validated(patch, &strict), Err(DTError::Rejected(expected)));
+        assert_eq!(oplog, server);
+        // But the data is fine without a policy.
+        oplog.decode_and_add(patch).unwrap();
+    };
+
+    let mut client = server.clone();
+    let mike = client.get_or_create_agent_id("mike");
+    client.add_insert(mike, 0, "x");
+    check_rejected(&client.encode_from(ENCODE_PATCH, server.cg.version.as_ref()), PolicyViolation::UnknownAgent);
+
+    let mut client = server.clone();
+    client.add_insert(seph, 0, "this is way too long");
+    check_rejected(&client.encode_from(ENCODE_PATCH, server.cg.version.as_ref()), PolicyViolation::TooManyOps);
+
+    // Send seph's second (concurrent) edit without the first.
+    let mut client = server.clone();
+    let base = server.cg.version.as_ref();
+    let next_seq = server.len();
+    client.add_insert_at(seph, base, 0, "a");
+    let b = client.add_insert_at(seph, base, 0, "b");
+    let patch = client.encode(EncodeOptions {
+        filter: Some(EncodeFilter::Spans(&[(b..b + 1).into()])),
+        ..ENCODE_PATCH
+    });
+    check_rejected(&patch, PolicyViolation::OutOfOrderSeq { expected: next_seq, actual: next_seq + 1 });
+
+    // Corrupt patches are rejected without panicking, and without modifying the oplog.
+    let mut client = server.clone();
+    client.add_insert_at(seph, &[v - 3], 1, "zz");
+    let patch = client.encode_from(ENCODE_PATCH, server.cg.version.as_ref());
+    for i in 0..patch.len() {
+        let mut corrupted = patch.clone();
+        corrupted[i] = !corrupted[i];
+        let mut oplog = server.clone();
+        if oplog.merge_bytes_validated(&corrupted, &MergePolicy::default()).is_err() {
+            assert_eq!(oplog, server);
+        }
+    }
+}
+
+#[test]
+fn merge_bytes_validated_checks_positions() {
+    let mut server = ListOpLog::new();
+    let seph = server.get_or_create_agent_id("seph");
+    server.add_insert(seph, 0, "hi");
+
+    for op in [TextOperation::new_insert(1000, "x"), TextOperation::new_delete(1..3)] {
+        let mut client = server.clone();
+        client.add_operations(seph, &[op]);
+        let patch = client.encode_from(ENCODE_PATCH, server.cg.version.as_ref());
+
+        let mut oplog = server.clone();
+        assert_eq!(oplog.merge_bytes_validated(&patch, &MergePolicy::default()), Err(DTError::OpOutOfBounds));
+        assert_eq!(oplog, server);
+        assert_eq!(oplog.checkout_tip().content().to_string(), "hi");
+    }
+}
@@ -26,7 +26,7 @@
 /// And what do you know! It happened. So this is now only used for optimizing the oplog order when
 /// encoding. TODO: Replace this file entirely with something simpler based off ConflictGraph.
 
-use std::mem::take;
+use core::mem::take;
 use smallvec::{SmallVec, smallvec};
 use rle::{HasLength, SplitableSpan};
 use crate::causalgraph::graph::Graph;
@@ -0,0 +1,475 @@
+//! End-to-end encryption of operation content.
+//!
+//! This lets peers sync a document through a relay server which can't read it. The content of
+//! each operation is encrypted with a [`ContentKey`] shared by the peers. Everything else - the
+//! positions, lengths, parents and agents of the operations - is left in the clear, so the relay
+//! can merge and forward changes as usual.
+//!
+//! Each character is encrypted separately, into another (random-looking) unicode character, by
+//! adding an offset taken from an XChaCha20 keystream. The keystream's nonce comes from the
+//! document's nonce and the agent which made the operation, and the operation's sequence number
+//! picks the position in the keystream. So encrypted content has the same length in characters as
+//! the original, and it can be split and merged exactly like plain content. An oplog holding
+//! encrypted content works normally, but its checkouts are ciphertext until they're decrypted.
+//!
+//! The encrypted content is authenticated with HMAC-SHA256 tags, which each cover a block of
+//! operations from an agent. The relay stores the tags and forwards them along with the content.
+//! Content which doesn't match its tag is rejected before it's decrypted, so the relay can't
+//! change the text. It can still drop operations or move them around, since their structure isn't
+//! authenticated.
+//!
+//! Peers which have the key keep a plain oplog. They send changes with
+//! [`encode_from_encrypted`](ListOpLog::encode_from_encrypted), and merge changes with
+//! [`decode_and_add_decrypting`](ListOpLog::decode_and_add_decrypting). The relay merges the
+//! encrypted files with [`decode_and_add`](ListOpLog::decode_and_add), and never needs the key.
+//! Deleted content isn't sent.
+//!
+//! [Redacted](crate::list::redaction) content isn't encrypted, since the placeholder is the same
+//! on every peer. Redacting content changes the tag of its block, so peers with the key send new
+//! tags for the redacted blocks along with their changes. If content is redacted by a peer without
+//! the key, the rest of its block can't be checked until a peer with the key has merged the
+//! redaction and sent some changes.
+
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
+use core::fmt::{Debug, Formatter};
+use chacha20::{Key, XChaCha20, XNonce};
+use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use hmac::{Hmac, Mac};
+use rle::HasLength;
+use sha2::Sha256;
+use sha2::digest::Update;
+use crate::{AgentId, DTRange, Frontier, LV};
+use crate::encoding::parseerror::ParseError;
+use crate::list::{ListBranch, ListOpLog};
+use crate::list::content_tags::{ContentTag, ContentTags, TAG_BLOCK, TAG_LEN};
+use crate::list::encoding::{EncodeOptions, FormatFeatures};
+use crate::list::operation::ListOpKind;
+use crate::list::redaction::{ranges_contain, REDACTED_CHAR};
+use crate::rle::KVPair;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The key used to encrypt the content of a document. See the [module documentation](self).
+#[derive(Clone, Eq, PartialEq)]
+pub struct ContentKey {
+    cipher: [u8; 32],
+    mac: [u8; 32],
+    /// A hash of the document's nonce.
+    doc: [u8; 32],
+}
+
+/// Hash bytes, prefixed by their length.
+fn push_with_len(h: &mut impl Update, bytes: &[u8]) {
+    h.update(&(bytes.len() as u64).to_le_bytes());
+    h.update(bytes);
+}
+
+fn derive_key(secret: &[u8; 32], label: &[u8]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(secret).unwrap();
+    push_with_len(&mut mac, label);
+    mac.finalize().into_bytes().into()
+}
+
+impl ContentKey {
+    /// Make the key for a document from a 32 byte secret and the document's nonce. The same secret
+    /// can be used for many documents, as long as each document has its own nonce - eg a random
+    /// value or a unique ID stored alongside the document. Every peer must use the same nonce for
+    /// the document.
+    pub fn new(secret: [u8; 32], doc_nonce: &[u8]) -> Self {
+        let mut doc = HmacSha256::new_from_slice(&secret).unwrap();
+        push_with_len(&mut doc, b"dt-content-doc");
+        push_with_len(&mut doc, doc_nonce);
+        Self {
+            cipher: derive_key(&secret, b"dt-content-cipher"),
+            mac: derive_key(&secret, b"dt-content-tag"),
+            doc: doc.finalize().into_bytes().into(),
+        }
+    }
+
+    /// The keystream offsets for a run of an agent's operations. Each operation uses 8 bytes of
+    /// the keystream.
+    fn offsets(&self, agent_name: &str, seqs: DTRange) -> Vec<u64> {
+        let mut nonce = HmacSha256::new_from_slice(&self.cipher).unwrap();
+        push_with_len(&mut nonce, &self.doc);
+        push_with_len(&mut nonce, agent_name.as_bytes());
+        let nonce = nonce.finalize().into_bytes();
+
+        let mut cipher = XChaCha20::new(Key::from_slice(&self.cipher), XNonce::from_slice(&nonce[..24]));
+        cipher.seek(seqs.start as u64 * 8);
+        let mut bytes = vec![0; seqs.len() * 8];
+        cipher.apply_keystream(&mut bytes);
+        bytes.chunks_exact(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()) % NUM_SCALARS)
+            .collect()
+    }
+
+    /// Start the tag for the first `len` operations in an agent's block.
+    fn start_tag(&self, agent_name: &str, block: usize, len: usize) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.mac).unwrap();
+        push_with_len(&mut mac, &self.doc);
+        push_with_len(&mut mac, agent_name.as_bytes());
+        Update::update(&mut mac, &(block as u64).to_le_bytes());
+        Update::update(&mut mac, &(len as u64).to_le_bytes());
+        mac
+    }
+}
+
+// Keys shouldn't end up in logs.
+impl Debug for ContentKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_str("ContentKey(..)")
+    }
+}
+
+/// The number of unicode scalar values. Characters are encrypted by adding an offset to their
+/// index in the list of scalar values (which skips the surrogate range).
+const NUM_SCALARS: u64 = 0x10F800;
+
+fn char_to_index(c: char) -> u64 {
+    let c = c as u64;
+    if c < 0xD800 { c } else { c - 0x800 }
+}
+
+fn index_to_char(i: u64) -> char {
+    let c = if i < 0xD800 { i } else { i + 0x800 };
+    char::from_u32(c as u32).unwrap()
+}
+
+fn crypt_char(c: char, offset: u64, decrypt: bool) -> char {
+    let i = char_to_index(c);
+    index_to_char(if decrypt { (i + NUM_SCALARS - offset) % NUM_SCALARS } else { (i + offset) % NUM_SCALARS })
+}
+
+impl ListOpLog {
+    /// True if this oplog stores encrypted content. An oplog's content is encrypted if it was
+    /// loaded from an encrypted file. Encrypted oplogs can merge changes and be encoded as usual,
+    /// but local changes shouldn't be added to them. Use [`decrypted`](ListOpLog::decrypted) to
+    /// read or edit the document.
+    pub fn is_content_encrypted(&self) -> bool {
+        self.content_encrypted
+    }
+
+    /// Encrypt (or decrypt) the inserted content of the operations in range, in place. Redacted
+    /// content is left alone.
+    fn crypt_content(&mut self, range: DTRange, key: &ContentKey, decrypt: bool) {
+        // The (first lv, agent, first seq) of each run of operations from a single agent.
+        let aa = &self.cg.agent_assignment;
+        let runs: Vec<(LV, AgentId, usize)> = aa.client_with_localtime.iter_range(range)
+            .map(|KVPair(lv, span)| (lv, span.agent, span.seq_range.start))
+            .collect();
+        let names: Vec<String> = aa.client_data.iter().map(|c| c.name.to_string()).collect();
+        let redactions = self.redactions.clone();
+
+        self.rewrite_content(range, ListOpKind::Ins, |mut lvs, content| {
+            debug_assert_eq!(lvs.len(), content.chars().count());
+            let mut chars = content.chars();
+            let mut result = String::with_capacity(content.len());
+            while !lvs.is_empty() {
+                let idx = runs.partition_point(|r| r.0 <= lvs.start) - 1;
+                let (start, agent, seq_start) = runs[idx];
+                let end = runs.get(idx + 1).map_or(lvs.end, |r| r.0.min(lvs.end));
+                let seq = seq_start + (lvs.start - start);
+                let offsets = key.offsets(&names[agent as usize], (seq..seq + end - lvs.start).into());
+                for (lv, offset) in (lvs.start..end).zip(offsets) {
+                    let c = chars.next().unwrap();
+                    result.push(if ranges_contain(&redactions, lv) { c } else { crypt_char(c, offset, decrypt) });
+                }
+                lvs.start = end;
+            }
+            Some(result)
+        });
+    }
+
+    /// The tag for the first `len` operations in an agent's block. Tags cover the encrypted
+    /// content, so content before `plain_end` (which is plain text) is encrypted first. Content in
+    /// `pending_redactions` is hashed as if it had already been redacted. Returns None if the oplog
+    /// doesn't contain all the operations.
+    fn content_tag(&self, key: &ContentKey, agent: AgentId, block: usize, len: usize, plain_end: LV, pending_redactions: &[DTRange]) -> Option<HmacSha256> {
+        let aa = &self.cg.agent_assignment;
+        let name = aa.get_agent_name(agent);
+        let mut mac = key.start_tag(name, block, len);
+
+        let start = block * TAG_BLOCK;
+        let mut seq = start;
+        while seq < start + len {
+            let lvs = aa.client_data[agent as usize].try_seq_to_lv_span((seq..start + len).into())?;
+            let offsets = if lvs.start < plain_end { key.offsets(name, (seq..seq + lvs.len()).into()) } else { vec![] };
+            for (KVPair(op_start, op), content) in self.iter_range_simple(lvs) {
+                match (op.kind, content) {
+                    (ListOpKind::Ins, Some(content)) => {
+                        for (lv, c) in (op_start..).zip(content.chars()) {
+                            let c = if pending_redactions.iter().any(|r| r.contains(lv)) {
+                                REDACTED_CHAR
+                            } else if lv < plain_end && !self.is_redacted(lv) {
+                                crypt_char(c, offsets[lv - lvs.start], false)
+                            } else { c };
+                            Update::update(&mut mac, c.encode_utf8(&mut [0; 4]).as_bytes());
+                        }
+                    }
+                    // Neither of these bytes appear in UTF-8.
+                    (ListOpKind::Ins, None) => Update::update(&mut mac, &vec![0xFE; op.len()]),
+                    (ListOpKind::Del, _) => Update::update(&mut mac, &vec![0xFF; op.len()]),
+                }
+            }
+            seq += lvs.len();
+        }
+        Some(mac)
+    }
+
+    /// Check the (encrypted) content of the operations in range against `tags`. Content before
+    /// `plain_end` is plain text. See [`content_tag`](ListOpLog::content_tag).
+    fn check_content_tags(&self, mut range: DTRange, key: &ContentKey, tags: &ContentTags, plain_end: LV, pending_redactions: &[DTRange]) -> Result<(), ParseError> {
+        // The number of operations at the start of each block which must be covered by its tag.
+        let mut needed: BTreeMap<(AgentId, usize), usize> = BTreeMap::new();
+        while !range.is_empty() {
+            let span = self.cg.agent_assignment.local_span_to_agent_span(range);
+            let seqs = span.seq_range;
+            for block in seqs.start / TAG_BLOCK..=(seqs.end - 1) / TAG_BLOCK {
+                let len = seqs.end.min((block + 1) * TAG_BLOCK) - block * TAG_BLOCK;
+                let entry = needed.entry((span.agent, block)).or_default();
+                *entry = (*entry).max(len);
+            }
+            range.start += seqs.len();
+        }
+
+        for ((agent, block), needed_len) in needed {
+            let (len, tags) = tags.entries.get(&(agent, block))
+                .filter(|(len, _)| *len >= needed_len)
+                .ok_or(ParseError::InvalidContentTag)?;
+            let mac = self.content_tag(key, agent, block, *len, plain_end, pending_redactions)
+                .ok_or(ParseError::InvalidContentTag)?;
+            if !tags.iter().any(|tag| mac.clone().verify_truncated_left(tag).is_ok()) {
+                return Err(ParseError::InvalidContentTag);
+            }
+        }
+        Ok(())
+    }
+
+    /// Encode the operations since `from_version` (like [`encode_from`](ListOpLog::encode_from)),
+    /// with their content encrypted using the key, and tags to authenticate it. Deleted content
+    /// isn't stored. If the oplog's content is already encrypted, this is the same as
+    /// `encode_from`.
+    pub fn encode_from_encrypted(&self, opts: EncodeOptions, from_version: &[LV], key: &ContentKey) -> Vec<u8> {
+        if self.content_encrypted { return self.encode_from(opts, from_version); }
+
+        let mut oplog = self.clone();
+        let ranges = self.cg.diff_since(from_version);
+        for &range in ranges.iter() {
+            oplog.crypt_content(range, key, false);
+        }
+        for (agent, block) in self.content_tag_blocks(&ranges) {
+            let len = self.content_tag_block_len(agent, block);
+            if len == 0 { continue; }
+            let mac = self.content_tag(key, agent, block, len, self.len(), &[]).unwrap();
+            let tag: ContentTag = mac.finalize().into_bytes()[..TAG_LEN].try_into().unwrap();
+            oplog.content_tags.merge_entry((agent, block), len, tag);
+        }
+        oplog.content_encrypted = true;
+        oplog.encode_from(EncodeOptions { store_deleted_content: false, ..opts }, from_version)
+    }
+
+    /// Encode the whole oplog with its content encrypted. See
+    /// [`encode_from_encrypted`](ListOpLog::encode_from_encrypted).
+    pub fn encode_encrypted(&self, opts: EncodeOptions, key: &ContentKey) -> Vec<u8> {
+        self.encode_from_encrypted(opts, &[], key)
+    }
+
+    /// Add all operations from a binary chunk of encrypted content into this document (like
+    /// [`decode_and_add`](ListOpLog::decode_and_add)). The content is checked against its tags
+    /// and decrypted using the key. If the oplog's content is already encrypted, this is the same
+    /// as `decode_and_add`.
+    ///
+    /// Nothing is added if any of the new content doesn't match its tag. In that case this
+    /// returns [`ParseError::InvalidContentTag`]. Chunks with plain content are rejected with
+    /// [`ParseError::EncryptionMismatch`], since their content can't be checked. (Otherwise a relay
+    /// could send any text it liked.) Use `decode_and_add` to merge a plain chunk from a source
+    /// you trust.
+    pub fn decode_and_add_decrypting(&mut self, data: &[u8], key: &ContentKey) -> Result<Frontier, ParseError> {
+        if self.content_encrypted { return self.decode_and_add(data); }
+        if !ListOpLog::file_info(data)?.features.contains(FormatFeatures::ENCRYPTED_CONTENT) {
+            return Err(ParseError::EncryptionMismatch);
+        }
+
+        // The new operations are checked and decrypted before the rest of the chunk is merged. If
+        // the check fails, they're removed again.
+        self.content_encrypted = true;
+        let result = self.decode_and_add_checked(data, &mut |oplog, new_ops, tags, redactions| {
+            let mut file_tags = ContentTags::default();
+            for &(block, len, tag) in tags {
+                file_tags.merge_entry(block, len, tag);
+            }
+            // New operations are always appended, so earlier content is plain text.
+            oplog.check_content_tags(new_ops, key, &file_tags, new_ops.start, redactions)?;
+            oplog.crypt_content(new_ops, key, true);
+            // This also stops the tags being merged into the oplog.
+            oplog.content_encrypted = false;
+            Ok(())
+        });
+        self.content_encrypted = false;
+        result
+    }
+
+    /// Get a copy of this oplog with its content checked against its tags, and decrypted using the
+    /// key.
+    pub fn decrypted(&self, key: &ContentKey) -> Result<ListOpLog, ParseError> {
+        let mut oplog = self.clone();
+        if oplog.content_encrypted {
+            let all: DTRange = (0..oplog.len()).into();
+            oplog.check_content_tags(all, key, &oplog.content_tags, 0, &[])?;
+            oplog.crypt_content(all, key, true);
+            oplog.content_encrypted = false;
+            oplog.content_tags = Default::default();
+        }
+        Ok(oplog)
+    }
+
+    /// Check out the document at the specified version, decrypting its content using the key. See
+    /// [`decrypted`](ListOpLog::decrypted).
+    pub fn checkout_decrypted(&self, local_version: &[LV], key: &ContentKey) -> Result<ListBranch, ParseError> {
+        Ok(self.decrypted(key)?.checkout(local_version))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::string::String;
+    use alloc::vec::Vec;
+    use crate::list::encoding::EncodeOptions;
+    use crate::list::encryption::ContentKey;
+    use crate::list::{ListCRDT, ListOpLog};
+    use crate::list::operation::ListOpKind;
+    use crate::list::redaction::REDACTED_CHAR;
+    use crate::encoding::parseerror::ParseError;
+
+    #[test]
+    fn sync_through_untrusted_relay() {
+        let key = ContentKey::new([7; 32], b"doc 1");
+        let opts = EncodeOptions { store_deleted_content: true, ..EncodeOptions::default() };
+        let mut alice = ListCRDT::new();
+        let seph = alice.get_or_create_agent_id("seph");
+        alice.insert(seph, 0, "top secret plans 🚀");
+        alice.delete(seph, 0..4);
+
+        let data = alice.oplog.encode_encrypted(opts.clone(), &key);
+        assert!(!data.windows(6).any(|w| w == b"secret"));
+
+        // The relay merges changes without the key.
+        let mut relay = ListOpLog::load_from(&data).unwrap();
+        assert!(relay.is_content_encrypted());
+        let ciphertext = relay.checkout_tip().content().to_string();
+        assert_eq!(ciphertext.chars().count(), 14);
+        assert!(!ciphertext.contains("secret"));
+
+        let mut bob = ListOpLog::new();
+        bob.decode_and_add_decrypting(&relay.encode(opts.clone()), &key).unwrap();
+        assert!(!bob.is_content_encrypted());
+        assert_eq!(bob.checkout_tip().content(), alice.branch.content());
+        // Deleted content isn't sent.
+        assert!(bob.iter().all(|op| op.kind == ListOpKind::Ins || op.content.is_none()));
+
+        // Bob edits concurrently, and sends his changes through the relay too.
+        let mike = bob.get_or_create_agent_id("mike");
+        let v = bob.local_frontier();
+        bob.add_insert(mike, 0, "our ");
+        relay.decode_and_add(&bob.encode_from_encrypted(opts.clone(), v.as_ref(), &key)).unwrap();
+        assert_eq!(relay.checkout_decrypted(relay.local_frontier_ref(), &key).unwrap().content().to_string(), "our secret plans 🚀");
+
+        alice.oplog.decode_and_add_decrypting(&relay.encode_from(opts.clone(), v.as_ref()), &key).unwrap();
+        assert_eq!(alice.oplog.checkout_tip().content(), bob.checkout_tip().content());
+
+        // Plain and encrypted content can't be mixed.
+        assert_eq!(relay.decode_and_add(&bob.encode(opts.clone())), Err(ParseError::EncryptionMismatch));
+
+        // Peers with the key don't accept plain content, since it can't be checked. Otherwise the
+        // relay could make up changes and send them in the clear.
+        let mut forged = bob.clone();
+        let v = forged.local_frontier();
+        let relay_agent = forged.get_or_create_agent_id("relay");
+        forged.add_insert(relay_agent, 0, "cancel ");
+        let len = alice.oplog.len();
+        let result = alice.oplog.decode_and_add_decrypting(&forged.encode_from(opts.clone(), v.as_ref()), &key);
+        assert_eq!(result, Err(ParseError::EncryptionMismatch));
+        assert_eq!(alice.oplog.len(), len);
+        assert_eq!(ListOpLog::new().decode_and_add_decrypting(&forged.encode(opts), &key), Err(ParseError::EncryptionMismatch));
+    }
+
+    #[test]
+    fn tampered_content_is_rejected() {
+        let key = ContentKey::new([7; 32], b"doc 1");
+        let mut alice = ListOpLog::new();
+        let seph = alice.get_or_create_agent_id("seph");
+        alice.add_insert(seph, 0, "pay mike $10");
+        let mut relay = ListOpLog::load_from(&alice.encode_encrypted(EncodeOptions::default(), &key)).unwrap();
+
+        // The same secret encrypts other documents differently.
+        let other_key = ContentKey::new([7; 32], b"doc 2");
+        let other = ListOpLog::load_from(&alice.encode_encrypted(EncodeOptions::default(), &other_key)).unwrap();
+        assert_ne!(other.checkout_tip().content(), relay.checkout_tip().content());
+        assert_eq!(relay.decrypted(&other_key).unwrap_err(), ParseError::InvalidContentTag);
+
+        // The relay swaps two characters of the ciphertext.
+        relay.rewrite_content((0..12).into(), ListOpKind::Ins, |_, content| {
+            let mut chars: Vec<char> = content.chars().collect();
+            chars.swap(10, 11);
+            Some(chars.into_iter().collect::<String>())
+        });
+        assert_eq!(relay.decrypted(&key).unwrap_err(), ParseError::InvalidContentTag);
+
+        let mut bob = ListOpLog::new();
+        let result = bob.decode_and_add_decrypting(&relay.encode(EncodeOptions::default()), &key);
+        assert_eq!(result, Err(ParseError::InvalidContentTag));
+        assert!(bob.is_empty());
+        assert!(!bob.is_content_encrypted());
+
+        // Tampered changes are removed again, leaving what was already merged.
+        let mut relay = ListOpLog::load_from(&alice.encode_encrypted(EncodeOptions::default(), &key)).unwrap();
+        bob.decode_and_add_decrypting(&relay.encode(EncodeOptions::default()), &key).unwrap();
+        let v = alice.local_frontier();
+        alice.add_insert(seph, 12, "0 today");
+        relay.decode_and_add(&alice.encode_from_encrypted(EncodeOptions::default(), v.as_ref(), &key)).unwrap();
+        relay.rewrite_content((12..19).into(), ListOpKind::Ins, |_, content| {
+            let mut chars: Vec<char> = content.chars().collect();
+            chars.swap(0, 1);
+            Some(chars.into_iter().collect::<String>())
+        });
+        let result = bob.decode_and_add_decrypting(&relay.encode_from(EncodeOptions::default(), v.as_ref()), &key);
+        assert_eq!(result, Err(ParseError::InvalidContentTag));
+        assert_eq!(bob.len(), 12);
+        assert_eq!(bob.local_frontier(), v);
+        assert_eq!(bob.checkout_tip().content().to_string(), "pay mike $10");
+        assert!(!bob.is_content_encrypted());
+    }
+
+    #[test]
+    fn redact_encrypted_content() {
+        let key = ContentKey::new([7; 32], b"doc 1");
+        let opts = EncodeOptions::default();
+        let mut alice = ListOpLog::new();
+        let seph = alice.get_or_create_agent_id("seph");
+        alice.add_insert(seph, 0, "my password is hunter2");
+        let mut relay = ListOpLog::load_from(&alice.encode_encrypted(opts.clone(), &key)).unwrap();
+        let mut bob = ListOpLog::new();
+        bob.decode_and_add_decrypting(&relay.encode(opts.clone()), &key).unwrap();
+
+        // Alice redacts the password, and sends the redaction (and a new tag) through the relay.
+        let v = alice.local_frontier();
+        alice.redact((15..22).into());
+        relay.decode_and_add(&alice.encode_from_encrypted(opts.clone(), v.as_ref(), &key)).unwrap();
+        let expected = format!("my password is {}", REDACTED_CHAR.to_string().repeat(7));
+        assert_eq!(relay.decrypted(&key).unwrap().checkout_tip().content().to_string(), expected);
+
+        bob.decode_and_add_decrypting(&relay.encode_from(opts.clone(), v.as_ref()), &key).unwrap();
+        assert_eq!(bob.checkout_tip().content().to_string(), expected);
+
+        // New peers get the placeholders, and the rest of the redacted block still checks out.
+        let v = alice.local_frontier();
+        alice.add_insert(seph, 22, "!");
+        relay.decode_and_add(&alice.encode_from_encrypted(opts.clone(), v.as_ref(), &key)).unwrap();
+        let mut carol = ListOpLog::new();
+        carol.decode_and_add_decrypting(&relay.encode(opts.clone()), &key).unwrap();
+        assert_eq!(carol.checkout_tip().content().to_string(), format!("{expected}!"));
+    }
+}
@@ -7,10 +7,12 @@
 // This implementation of Eq is mostly designed to help fuzz testing. It is not optimized for
 // performance.
 
+use alloc::vec::Vec;
 use rle::{HasLength, SplitableSpan};
 use rle::zip::rle_zip3;
 use crate::{AgentId, Frontier, LV};
 use crate::list::ListOpLog;
+use crate::list::marks::MarkId;
 use crate::frontier::sort_frontier;
 use crate::causalgraph::graph::GraphEntrySimple;
 use crate::rle::KVPair;
@@ -21,6 +23,7 @@ const VERBOSE: bool = true;
 impl PartialEq<Self> for ListOpLog {
     fn eq(&self, other: &Self) -> bool {
         if self.doc_id != other.doc_id { return false; }
+        if self.tie_break != other.tie_break { return false; }
 
         // This implementation is based on the equivalent version in the original diamond types
         // implementation.
@@ -184,6 +187,72 @@ impl PartialEq<Self> for ListOpLog {
             }
         }
 
+        // Marks are named by (agent, seq) pairs, so they're compared via agent names.
+        if self.marks.entries.len() != other.marks.entries.len() { return false; }
+        let map_frontier = |f: &Frontier| -> Option<Frontier> {
+            let mut lvs = f.iter().map(|lv| map_lv_to_other(*lv)).collect::<Option<Vec<LV>>>()?;
+            lvs.sort_unstable();
+            Some(Frontier::from_sorted(&lvs))
+        };
+        for (id, entry) in self.marks.entries.iter() {
+            let Some(other_agent) = other.get_agent_id(self.get_agent_name(id.agent)) else {
+                return false;
+            };
+            let Some(other_entry) = other.marks.entries.get(&MarkId { agent: other_agent, seq: id.seq }) else {
+                if VERBOSE { println!("Mark missing in other oplog"); }
+                return false;
+            };
+
+            let (range, other_range) = (&entry.range, &other_entry.range);
+            if range.expand != other_range.expand || range.payload != other_range.payload
+                || range.start_anchor.bias != other_range.start_anchor.bias
+                || range.end_anchor.bias != other_range.end_anchor.bias
+                || map_lv_to_other(range.start_anchor.lv) != Some(other_range.start_anchor.lv)
+                || map_lv_to_other(range.end_anchor.lv) != Some(other_range.end_anchor.lv)
+                || map_frontier(&entry.created_at).as_ref() != Some(&other_entry.created_at)
+                || entry.deleted_at.len() != other_entry.deleted_at.len()
+                || !entry.deleted_at.iter().all(|v| {
+                    map_frontier(v).is_some_and(|v| other_entry.deleted_at.contains(&v))
+                })
+            {
+                if VERBOSE { println!("Marks do not match"); }
+                return false;
+            }
+        }
+
+        // Suggestions are also named by agent.
+        if self.suggestions.entries.len() != other.suggestions.entries.len() { return false; }
+        for (&(agent, seq), entry) in self.suggestions.entries.iter() {
+            let other_entry = other.get_agent_id(self.get_agent_name(agent))
+                .and_then(|other_agent| other.suggestions.entries.get(&(other_agent, seq)));
+            // The change clock is local to each oplog.
+            if other_entry.map(|e| (e.seq_end, e.state)) != Some((entry.seq_end, entry.state)) {
+                if VERBOSE { println!("Suggestions do not match"); }
+                return false;
+            }
+        }
+
+        if self.agent_meta.entries.len() != other.agent_meta.entries.len() { return false; }
+        for ((agent, key), entry) in self.agent_meta.entries.iter() {
+            let other_entry = other.get_agent_id(self.get_agent_name(*agent))
+                .and_then(|other_agent| other.agent_meta.entries.get(&(other_agent, key.clone())));
+            if other_entry != Some(entry) {
+                if VERBOSE { println!("Agent metadata does not match"); }
+                return false;
+            }
+        }
+
+        if self.agent_links.links.len() != other.agent_links.links.len() { return false; }
+        for (&agent, &target) in self.agent_links.links.iter() {
+            let map_agent = |a: AgentId| other.get_agent_id(self.get_agent_name(a));
+            let other_target = map_agent(agent)
+                .and_then(|other_agent| other.agent_links.links.get(&other_agent).copied());
+            if other_target.is_none() || other_target != map_agent(target) {
+                if VERBOSE { println!("Agent links do not match"); }
+                return false;
+            }
+        }
+
         true
     }
 }
@@ -0,0 +1,443 @@
+//! Editing methods which address the document by extended grapheme cluster rather than by unicode
+//! character.
+//!
+//! Diamond types positions are counted in unicode characters (codepoints). But users perceive a
+//! single "character" as a grapheme cluster, which may span many codepoints - like "e\u{301}"
+//! (e + combining acute accent) or "👨‍👩‍👧" (3 emoji joined by zero width joiners). An editor which
+//! slices these clusters apart when deleting text ends up with corrupted content.
+//!
+//! The methods in this module (enabled by the `graphemes` feature) address the document using
+//! grapheme cluster indexes instead. Checking for a cluster boundary only looks at the text around
+//! the position. But converting between cluster and character positions is O(n) in the position,
+//! since clusters are counted from the start of the document.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt::{Display, Formatter};
+use core::ops::Range;
+use jumprope::JumpRope;
+use smartstring::alias::String as SmartString;
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete, UnicodeSegmentation};
+use crate::{AgentId, LV};
+use crate::list::{ListBranch, ListCRDT, ListOpLog};
+use crate::list::operation::ListOpKind;
+use crate::listmerge::merge::reverse_str;
+
+/// Returned when an edit would split an extended grapheme cluster.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct GraphemeSplitError {
+    /// The position (in characters) which is inside a grapheme cluster.
+    pub pos: usize,
+}
+
+impl Display for GraphemeSplitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Position {} is inside a grapheme cluster", self.pos)
+    }
+}
+
+impl Error for GraphemeSplitError {}
+
+/// A change to the document, expressed in grapheme clusters. Replace the `removed` clusters
+/// starting at cluster `pos` with `content`.
+///
+/// A remote change can modify an existing cluster (eg, by adding a combining mark to it). So
+/// edits replace whole clusters rather than inserting or deleting individual characters.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct GraphemeEdit {
+    pub pos: usize,
+    pub removed: usize,
+    pub content: SmartString,
+}
+
+fn char_to_byte(s: &str, char_pos: usize) -> usize {
+    assert!(char_pos <= str_indices::chars::count(s), "Position past the end of the document");
+    str_indices::chars::to_byte_idx(s, char_pos)
+}
+
+fn floor_char_boundary(s: &str, mut byte: usize) -> usize {
+    while !s.is_char_boundary(byte) { byte -= 1; }
+    byte
+}
+
+fn ceil_char_boundary(s: &str, mut byte: usize) -> usize {
+    while !s.is_char_boundary(byte) { byte += 1; }
+    byte
+}
+
+/// A window of text from the document. Cluster boundaries can depend on text a long way from
+/// them (eg in runs of flags), so queries return None if the window doesn't have enough context.
+/// The caller should try again with a bigger window.
+struct Window<'a> {
+    text: &'a str,
+    /// Whether the document continues before and after the window.
+    more_before: bool,
+    more_after: bool,
+}
+
+impl<'a> Window<'a> {
+    /// Make a cursor at a byte offset in the window. Text outside the window is stood in for by a
+    /// single byte on either side, so the cursor knows its there and asks for it when it's needed.
+    fn cursor(&self, byte: usize) -> (GraphemeCursor, usize) {
+        let base = self.more_before as usize;
+        let len = base + self.text.len() + self.more_after as usize;
+        (GraphemeCursor::new(base + byte, len, true), base)
+    }
+
+    fn is_boundary(&self, byte: usize) -> Option<bool> {
+        let (mut cursor, base) = self.cursor(byte);
+        cursor.is_boundary(self.text, base).ok()
+    }
+
+    fn next_boundary(&self, byte: usize) -> Option<usize> {
+        let (mut cursor, base) = self.cursor(byte);
+        cursor.next_boundary(self.text, base).ok()?.map(|b| b - base)
+    }
+
+    fn prev_boundary(&self, byte: usize) -> Option<usize> {
+        let (mut cursor, base) = self.cursor(byte);
+        Some(cursor.prev_boundary(self.text, base).ok()?.map_or(0, |b| b - base))
+    }
+}
+
+/// Returns true if the character position in the rope is at a cluster boundary.
+fn rope_is_boundary(rope: &JumpRope, pos: usize) -> bool {
+    let len = rope.len_chars();
+    assert!(pos <= len, "Position past the end of the document");
+    let mut context = 8;
+    loop {
+        let start = pos.saturating_sub(context);
+        // The character after the position is needed too.
+        let end = (pos + 1).min(len);
+        let text: String = rope.slice_chars(start..end).collect();
+        let window = Window { text: &text, more_before: start > 0, more_after: end < len };
+        if let Some(b) = window.is_boundary(char_to_byte(&text, pos - start)) {
+            return b;
+        }
+        context *= 2;
+    }
+}
+
+/// Iterates through the clusters in a rope, yielding the character position at the end of each
+/// one. The rope's content isn't copied.
+struct Clusters<'a, I: Iterator<Item = &'a str>> {
+    chunks: I,
+    /// The chunks read so far, and the byte offset each starts at. The cursor can ask for context
+    /// from before the current chunk.
+    seen: Vec<(usize, &'a str)>,
+    cursor: GraphemeCursor,
+    char_pos: usize,
+}
+
+fn clusters(rope: &JumpRope) -> Clusters<'_, impl Iterator<Item = &str>> {
+    let mut chunks = rope.substrings().filter(|s| !s.is_empty());
+    let seen = chunks.next().map(|c| (0, c)).into_iter().collect();
+    Clusters {
+        chunks,
+        seen,
+        cursor: GraphemeCursor::new(0, rope.len_bytes(), true),
+        char_pos: 0,
+    }
+}
+
+impl<'a, I: Iterator<Item = &'a str>> Iterator for Clusters<'a, I> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let start = self.cursor.cur_cursor();
+        loop {
+            let &(chunk_start, chunk) = self.seen.last()?;
+            match self.cursor.next_boundary(chunk, chunk_start) {
+                Ok(Some(end)) => {
+                    // Count the characters in the cluster, which can span chunks.
+                    self.char_pos += self.seen.iter().rev()
+                        .take_while(|(s, c)| s + c.len() > start)
+                        .map(|&(s, c)| str_indices::chars::count(&c[start.max(s) - s..end.min(s + c.len()) - s]))
+                        .sum::<usize>();
+                    return Some(self.char_pos);
+                }
+                Ok(None) => return None,
+                Err(GraphemeIncomplete::NextChunk) => {
+                    let next = self.chunks.next()?;
+                    self.seen.push((chunk_start + chunk.len(), next));
+                }
+                Err(GraphemeIncomplete::PreContext(end)) => {
+                    let &(s, c) = self.seen.iter().find(|(s, c)| s + c.len() == end).unwrap();
+                    self.cursor.provide_context(c, s);
+                }
+                Err(e) => panic!("Unexpected grapheme cursor error {:?}", e),
+            }
+        }
+    }
+}
+
+/// Convert a range of cluster positions into a range of character positions, in a single pass
+/// through the document.
+fn graphemes_to_chars(rope: &JumpRope, range: Range<usize>) -> Range<usize> {
+    let mut iter = clusters(rope);
+    let mut nth_boundary = |n: usize| if n == 0 { 0 } else {
+        iter.nth(n - 1).expect("Position past the end of the document")
+    };
+    let start = nth_boundary(range.start);
+    // The iterator carries on from the start of the range.
+    let end = if range.end == range.start { start } else { nth_boundary(range.end - range.start) };
+    start..end
+}
+
+fn ends_in_regional_indicator(s: &str, byte: usize) -> bool {
+    s[..byte].chars().next_back()
+        .is_some_and(|c| ('\u{1F1E6}'..='\u{1F1FF}').contains(&c))
+}
+
+/// Find the byte range of the clusters which changed when old[start..old_end] was replaced by
+/// new[start..new_end]. Returns (start, old_end, new_end), or None if the windows are too small.
+fn changed_clusters(old: &Window, new: &Window, start: usize, old_end: usize, new_end: usize) -> Option<(usize, usize, usize)> {
+    // Cluster boundaries before the change can't move. But the change might merge into the
+    // cluster before it.
+    let mut start = start;
+    if !old.is_boundary(start)? || !new.is_boundary(start)? {
+        start = old.prev_boundary(start)?;
+    }
+
+    // Boundaries after the change can move, so we scan forward until both strings agree. Pairs
+    // of regional indicators (flags) are matched up from the start of a run, so we also need the
+    // runs before the boundary to line up.
+    let (mut old_end, mut new_end) = (old_end, new_end);
+    while !(old.is_boundary(old_end)? && new.is_boundary(new_end)?
+        && ((old_end == old.text.len() && !old.more_after)
+            || ends_in_regional_indicator(old.text, old_end) == ends_in_regional_indicator(new.text, new_end)))
+    {
+        let next = old.next_boundary(old_end)?;
+        new_end += next - old_end;
+        old_end = next;
+    }
+
+    Some((start, old_end, new_end))
+}
+
+/// Find the grapheme edit made when the bytes `removed` were replaced by new[start..new_end].
+/// Only the text near the change is looked at (other than to count the clusters before it).
+fn grapheme_edit(new: &str, start: usize, removed: &str, new_end: usize) -> GraphemeEdit {
+    let mut context = 16;
+    loop {
+        let ws = floor_char_boundary(new, start.saturating_sub(context));
+        let we = ceil_char_boundary(new, (new_end + context).min(new.len()));
+        // The text around the change is the same before and after it.
+        let old_text = [&new[ws..start], removed, &new[new_end..we]].concat();
+        let old = Window { text: &old_text, more_before: ws > 0, more_after: we < new.len() };
+        let new_window = Window { text: &new[ws..we], ..old };
+
+        let (old_start, old_end) = (start - ws, start - ws + removed.len());
+        if let Some((s, old_end, new_end)) = changed_clusters(&old, &new_window, old_start, old_end, new_end - ws) {
+            return GraphemeEdit {
+                pos: new[..ws + s].graphemes(true).count(),
+                removed: old_text[s..old_end].graphemes(true).count(),
+                content: new[ws + s..ws + new_end].into(),
+            };
+        }
+        context *= 2;
+    }
+}
+
+impl ListBranch {
+    /// Returns the document's length in extended grapheme clusters.
+    pub fn len_graphemes(&self) -> usize {
+        clusters(&self.content).count()
+    }
+
+    /// Convert a position in grapheme clusters into a position in characters.
+    ///
+    /// Panics if the position is past the end of the document.
+    pub fn graphemes_to_chars(&self, pos: usize) -> usize {
+        graphemes_to_chars(&self.content, pos..pos).start
+    }
+
+    /// Convert a position in characters into a position in grapheme clusters. If the position is
+    /// inside a cluster, this returns the index of the cluster after it.
+    pub fn chars_to_graphemes(&self, pos: usize) -> usize {
+        assert!(pos <= self.content.len_chars(), "Position past the end of the document");
+        // Count the clusters which start before the position.
+        let mut start = 0;
+        clusters(&self.content).take_while(|&end| {
+            let before = start < pos;
+            start = end;
+            before
+        }).count()
+    }
+
+    /// Returns true if the character position is at the start or end of a grapheme cluster.
+    pub fn is_grapheme_boundary(&self, pos: usize) -> bool {
+        rope_is_boundary(&self.content, pos)
+    }
+
+    pub fn insert_at_grapheme(&mut self, oplog: &mut ListOpLog, agent: AgentId, pos: usize, ins_content: &str) -> LV {
+        let char_pos = self.graphemes_to_chars(pos);
+        self.insert(oplog, agent, char_pos, ins_content)
+    }
+
+    pub fn delete_at_grapheme(&mut self, oplog: &mut ListOpLog, agent: AgentId, del_span: Range<usize>) -> LV {
+        let range = graphemes_to_chars(&self.content, del_span);
+        self.delete(oplog, agent, range)
+    }
+
+    /// Delete the characters in `del_span`, which must not split any grapheme clusters.
+    ///
+    /// Returns an error (and leaves the document untouched) if either end of the range is inside
+    /// a cluster.
+    pub fn delete_whole_graphemes(&mut self, oplog: &mut ListOpLog, agent: AgentId, del_span: Range<usize>) -> Result<LV, GraphemeSplitError> {
+        for pos in [del_span.start, del_span.end] {
+            if !self.is_grapheme_boundary(pos) {
+                return Err(GraphemeSplitError { pos });
+            }
+        }
+        Ok(self.delete(oplog, agent, del_span))
+    }
+
+    /// Variant of [`merge`](ListBranch::merge) which returns the merged changes as edits to
+    /// grapheme clusters, so they can be applied to an editor which counts positions in clusters.
+    /// Edits are returned in the order they should be applied.
+    pub fn merge_graphemes(&mut self, oplog: &ListOpLog, merge_frontier: &[LV]) -> Vec<GraphemeEdit> {
+        // A copy of the document, which each change is applied to in turn.
+        let mut doc = self.content.to_string();
+
+        self.merge_and_collect(oplog, merge_frontier).into_iter().map(|op| {
+            let start = char_to_byte(&doc, op.start());
+            let (removed, new_end) = match op.kind {
+                ListOpKind::Ins => {
+                    let content = op.content_as_str().unwrap();
+                    if op.loc.fwd {
+                        doc.insert_str(start, content);
+                    } else {
+                        doc.insert_str(start, &reverse_str(content));
+                    }
+                    (String::new(), start + content.len())
+                }
+                ListOpKind::Del => {
+                    let end = char_to_byte(&doc, op.end());
+                    (doc.drain(start..end).collect(), start)
+                }
+            };
+
+            grapheme_edit(&doc, start, &removed, new_end)
+        }).collect()
+    }
+}
+
+impl ListCRDT {
+    pub fn insert_at_grapheme(&mut self, agent: AgentId, pos: usize, ins_content: &str) -> LV {
+        let char_pos = self.branch.graphemes_to_chars(pos);
+        self.insert(agent, char_pos, ins_content)
+    }
+
+    pub fn delete_at_grapheme(&mut self, agent: AgentId, range: Range<usize>) -> LV {
+        let range = graphemes_to_chars(&self.branch.content, range);
+        self.delete(agent, range)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use unicode_segmentation::UnicodeSegmentation;
+    use crate::list::graphemes::{GraphemeEdit, GraphemeSplitError};
+    use crate::list::{ListBranch, ListOpLog};
+
+    const FAMILY: &str = "👨\u{200d}👩\u{200d}👧";
+
+    #[test]
+    fn local_edits() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mut branch = ListBranch::new();
+        branch.insert(&mut oplog, seph, 0, "e\u{301}x");
+        branch.insert_at_grapheme(&mut oplog, seph, 1, FAMILY);
+        assert_eq!(branch.len(), 8);
+        assert_eq!(branch.len_graphemes(), 3);
+        assert_eq!(branch.graphemes_to_chars(2), 7);
+        assert_eq!(branch.chars_to_graphemes(7), 2);
+        assert_eq!(branch.chars_to_graphemes(3), 2);
+        assert!(!branch.is_grapheme_boundary(1));
+
+        assert_eq!(branch.delete_whole_graphemes(&mut oplog, seph, 0..1),
+                   Err(GraphemeSplitError { pos: 1 }));
+        assert_eq!(branch.delete_whole_graphemes(&mut oplog, seph, 2..4),
+                   Err(GraphemeSplitError { pos: 4 }));
+        assert_eq!(branch.content().to_string(), format!("e\u{301}{FAMILY}x"));
+
+        branch.delete_at_grapheme(&mut oplog, seph, 1..2);
+        assert_eq!(branch.content().to_string(), "e\u{301}x");
+        branch.delete_whole_graphemes(&mut oplog, seph, 0..2).unwrap();
+        assert_eq!(branch.content().to_string(), "x");
+        assert_eq!(oplog.checkout_tip(), branch);
+    }
+
+    /// Merge ops inserted at the named (character) positions into a branch containing base, and
+    /// check applying the returned edits to base's clusters gives the same result as the merge.
+    fn check_merge(base: &str, inserts: &[(usize, &str)]) -> Vec<GraphemeEdit> {
+        let mut oplog = ListOpLog::new();
+        let agent = oplog.get_or_create_agent_id("seph");
+        let v = oplog.add_insert(agent, 0, base);
+        for (i, (pos, content)) in inserts.iter().enumerate() {
+            let agent = oplog.get_or_create_agent_id(&format!("agent {i}"));
+            oplog.add_insert_at(agent, &[v], *pos, content);
+        }
+
+        let mut branch = oplog.checkout(&[v]);
+        let edits = branch.merge_graphemes(&oplog, oplog.cg.version.as_ref());
+
+        let mut clusters: Vec<String> = base.graphemes(true).map(|s| s.into()).collect();
+        for e in &edits {
+            clusters.splice(e.pos..e.pos + e.removed, e.content.graphemes(true).map(|s| s.into()));
+        }
+        let expect = branch.content().to_string();
+        assert_eq!(clusters, expect.graphemes(true).collect::<Vec<_>>());
+        assert_eq!(branch, oplog.checkout_tip());
+        edits
+    }
+
+    #[test]
+    fn merge_edits() {
+        // Concurrently adding a combining mark to a cluster modifies it.
+        let edits = check_merge("abc", &[(1, "\u{301}"), (3, "d")]);
+        assert!(edits.contains(&GraphemeEdit { pos: 0, removed: 1, content: "a\u{301}".into() }));
+
+        // Joining emoji.
+        check_merge("👨👩", &[(1, "\u{200d}"), (2, "\u{200d}👧")]);
+        check_merge(FAMILY, &[(0, "x"), (2, "y"), (5, "z")]);
+
+        // Inserting a regional indicator re-pairs every flag after it.
+        let edits = check_merge("🇦🇺🇳🇿", &[(0, "🇺")]);
+        assert_eq!(edits, vec![GraphemeEdit { pos: 0, removed: 2, content: "🇺🇦🇺🇳🇿".into() }]);
+        check_merge("🇦🇺🇳🇿x", &[(2, "🇺"), (4, "\u{301}")]);
+    }
+
+    #[test]
+    fn long_documents() {
+        // Long enough to be split across chunks of the rope, with clusters (and a run of flags)
+        // crossing the chunk boundaries.
+        let text = format!("{}x{}", "🇦🇺".repeat(150), format!("e\u{301}{FAMILY}").repeat(50));
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mut branch = ListBranch::new();
+        branch.insert(&mut oplog, seph, 0, &text);
+
+        let boundaries: Vec<usize> = text.grapheme_indices(true)
+            .map(|(b, _)| text[..b].chars().count())
+            .chain(core::iter::once(text.chars().count()))
+            .collect();
+        assert_eq!(branch.len_graphemes(), boundaries.len() - 1);
+        for (i, &c) in boundaries.iter().enumerate() {
+            assert_eq!(branch.graphemes_to_chars(i), c);
+            assert_eq!(branch.chars_to_graphemes(c), i);
+            assert!(branch.is_grapheme_boundary(c));
+        }
+        // The second character of every flag is inside a cluster.
+        assert!(!branch.is_grapheme_boundary(299));
+        assert_eq!(branch.chars_to_graphemes(299), 150);
+
+        // Inserting a regional indicator re-pairs the whole run of flags.
+        let edits = check_merge(&"🇦🇺".repeat(150), &[(0, "🇺")]);
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].removed, 150);
+    }
+}
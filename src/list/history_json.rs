@@ -0,0 +1,184 @@
+//! Export (and re-import) the full history of an oplog as JSON, for visualization tools.
+//!
+//! Unlike the graphviz output from `dot_export`, this is lossless. The exported data looks like
+//! this:
+//!
+//! ```json
+//! {
+//!   "nodes": [
+//!     {
+//!       "id": 0,
+//!       "span": [0, 11],
+//!       "agent": "seph",
+//!       "seq": 0,
+//!       "parents": [],
+//!       "ops": [{"kind": "Ins", "start": 0, "end": 11, "fwd": true, "content": "hello world"}]
+//!     },
+//!     ...
+//!   ],
+//!   "edges": [{"from": 0, "to": 1}, ...]
+//! }
+//! ```
+//!
+//! - Each node is a run of operations from a single agent, with no other operations between them
+//!   in the causal graph. `span` is the range of local versions `[start, end)` and `seq` is the
+//!   agent's sequence number for the first operation in the span.
+//! - `parents` names the local versions directly before the first operation in the node. Every
+//!   other operation in the node has the previous operation as its only parent.
+//! - `ops` are the operations themselves, at their original (untransformed) positions. Content is
+//!   included when the oplog knows it.
+//! - `edges` repeat the parent information by node ID, so tools can draw the graph without looking
+//!   up which node contains each version. An edge goes from the node containing a parent to the
+//!   node which names it.
+//!
+//! Nodes are sorted by local version.
+
+use serde::{Deserialize, Serialize};
+use smartstring::alias::String as SmartString;
+use rle::HasLength;
+use crate::{DTError, DTRange, Frontier, LV};
+use crate::encoding::parseerror::ParseError;
+use crate::list::ListOpLog;
+use crate::list::operation::TextOperation;
+use crate::rle::KVPair;
+
+/// A node in the exported history. See the [module documentation](self) for details.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct HistoryNode {
+    pub id: usize,
+    pub span: DTRange,
+    pub agent: SmartString,
+    pub seq: usize,
+    pub parents: Frontier,
+    pub ops: Vec<TextOperation>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEdge {
+    pub from: usize,
+    pub to: usize,
+}
+
+/// The full history of an oplog, in a form which is easy to consume from other tools.
+#[derive(Debug, Clone, Eq, PartialEq, Default, Serialize, Deserialize)]
+pub struct HistoryExport {
+    pub nodes: Vec<HistoryNode>,
+    pub edges: Vec<HistoryEdge>,
+}
+
+impl ListOpLog {
+    /// Export the oplog's history as a list of nodes and edges. See the
+    /// [`history_json`](crate::list::history_json) module for the schema.
+    pub fn export_history(&self) -> HistoryExport {
+        let mut nodes: Vec<HistoryNode> = vec![];
+
+        for entry in self.cg.graph.iter() {
+            let aa = &self.cg.agent_assignment;
+            for KVPair(start, agent_span) in aa.client_with_localtime.iter_range_ctx(entry.span, &()) {
+                let span: DTRange = (start..start + agent_span.len()).into();
+                let parents = if start == entry.span.start {
+                    entry.parents.clone()
+                } else {
+                    Frontier::new_1(start - 1)
+                };
+
+                nodes.push(HistoryNode {
+                    id: nodes.len(),
+                    span,
+                    agent: aa.get_agent_name(agent_span.agent).into(),
+                    seq: agent_span.seq_range.start,
+                    parents,
+                    ops: self.iter_range_simple(span)
+                        .map(|(KVPair(_, op), content)| (op, content).into())
+                        .collect(),
+                });
+            }
+        }
+
+        let node_containing = |v: LV| nodes.partition_point(|n| n.span.end <= v);
+        let edges = nodes.iter().flat_map(|n| {
+            n.parents.iter().map(move |&p| HistoryEdge {
+                from: node_containing(p),
+                to: n.id,
+            })
+        }).collect();
+
+        HistoryExport { nodes, edges }
+    }
+
+    /// Export the oplog's history as a JSON string. See the
+    /// [`history_json`](crate::list::history_json) module for the schema.
+    pub fn export_history_json(&self) -> String {
+        serde_json::to_string(&self.export_history()).unwrap()
+    }
+
+    /// Rebuild an oplog from exported history. The resulting oplog is identical to the one which
+    /// was exported.
+    ///
+    /// Returns an error if the nodes aren't in order, or if they contain invalid changes. The
+    /// history is checked the same way as changes passed to
+    /// [`try_add_operations_remote`](ListOpLog::try_add_operations_remote).
+    pub fn import_history(history: &HistoryExport) -> Result<Self, DTError> {
+        let mut oplog = Self::new();
+
+        for node in &history.nodes {
+            let len: usize = node.ops.iter().map(|op| op.len()).sum();
+            if node.span.start != oplog.len() || node.span.len() != len || len == 0 {
+                return Err(ParseError::InvalidLength.into());
+            }
+
+            let agent = oplog.try_get_or_create_agent_id(&node.agent)?;
+            // This will also fail if the same agent + seq pair is used twice.
+            let span = oplog.try_add_operations_remote(agent, node.parents.as_ref(), node.seq, &node.ops)?;
+            if span != node.span { return Err(DTError::SeqOverlap); }
+        }
+
+        Ok(oplog)
+    }
+
+    /// Rebuild an oplog from the JSON produced by
+    /// [`export_history_json`](ListOpLog::export_history_json).
+    pub fn import_history_json(json: &str) -> Result<Self, DTError> {
+        let history: HistoryExport = serde_json::from_str(json)
+            .map_err(|_| ParseError::GenericInvalidData)?;
+        Self::import_history(&history)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{DTError, ParseError};
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn history_json_round_trip() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "hello world");
+        oplog.add_insert(mike, 5, "!!");
+        let b = oplog.add_insert_at(mike, &[a], 0, "yo ");
+        let c = oplog.add_delete_at(seph, &[a], 0..6);
+        oplog.add_insert_at(seph, &[b, c], 0, "x");
+
+        let history = oplog.export_history();
+        // seph's first insert and mike's following insert are split into separate nodes.
+        assert_eq!(history.nodes[0].span, (0..11).into());
+        assert_eq!(history.nodes[1].agent, "mike");
+        assert_eq!(history.nodes[1].parents.as_ref(), &[a]);
+        assert_eq!(history.edges.iter().filter(|e| e.to == history.nodes.len() - 1).count(), 2);
+
+        let json = oplog.export_history_json();
+        let result = ListOpLog::import_history_json(&json).unwrap();
+        assert_eq!(result, oplog);
+        assert_eq!(result.checkout_tip(), oplog.checkout_tip());
+
+        assert!(ListOpLog::import_history_json("{}").is_err());
+        let mut bad = history.clone();
+        bad.nodes.swap(0, 1);
+        assert_eq!(ListOpLog::import_history(&bad), Err(DTError::Parse(ParseError::InvalidLength)));
+        let mut bad = history.clone();
+        bad.nodes[1].agent = "ROOT".into();
+        assert_eq!(ListOpLog::import_history(&bad), Err(DTError::InvalidAgentName));
+    }
+}
@@ -1,4 +1,5 @@
-use std::ops::Range;
+use core::ops::Range;
+#[cfg(feature = "std")]
 use humansize::{BINARY, format_size};
 use crate::list::{ListBranch, ListCRDT, ListOpLog};
 use crate::{AgentId, Frontier, LV};
@@ -8,6 +9,7 @@ use crate::list::operation::{ListOpKind, TextOperation};
 use crate::dtrange::DTRange;
 use crate::encoding::parseerror::ParseError;
 use crate::unicount::count_chars;
+use crate::list::tie_break::TieBreak;
 
 // For local changes to a branch, we take the checkout's frontier as the new parents list.
 fn insert_history_local(oplog: &mut ListOpLog, frontier: &mut Frontier, range: DTRange) {
@@ -57,11 +59,11 @@ pub(crate) fn apply_local_operations(oplog: &mut ListOpLog, branch: &mut ListBra
             Ins => {
                 // assert!(c.);
                 // let new_content = consume_chars(&mut content, len);
-                branch.content.insert(pos, c.content.as_ref().unwrap());
+                branch.insert_content(pos, c.content.as_ref().unwrap());
             }
 
             Del => {
-                branch.content.remove(pos..pos + len);
+                branch.remove_content(pos..pos + len);
             }
         }
 
@@ -92,7 +94,7 @@ fn internal_do_insert(oplog: &mut ListOpLog, branch: &mut ListBranch, agent: Age
 
     let len = count_chars(content);
 
-    branch.content.insert(pos, content);
+    branch.insert_content(pos, content);
 
     oplog.push_op_internal(start, (pos..pos + len).into(), ListOpKind::Ins, Some(content));
 
@@ -116,7 +118,7 @@ fn internal_do_insert(oplog: &mut ListOpLog, branch: &mut ListBranch, agent: Age
 fn internal_do_delete(oplog: &mut ListOpLog, branch: &mut ListBranch, agent: AgentId, pos: DTRange) -> LV {
     let start = oplog.len();
 
-    branch.content.remove(pos.into());
+    branch.remove_content(pos.into());
 
     oplog.push_op_internal(start, pos.into(), ListOpKind::Del, None);
 
@@ -145,7 +147,18 @@ impl ListCRDT {
     pub fn new() -> Self {
         Self {
             branch: ListBranch::new(),
-            oplog: ListOpLog::new()
+            oplog: ListOpLog::new(),
+            subscribers: Default::default(),
+        }
+    }
+
+    /// Create a new document which orders concurrent inserts using the specified strategy. See
+    /// [`TieBreak`] for details.
+    pub fn new_with_tie_break(tie_break: TieBreak) -> Self {
+        Self {
+            branch: ListBranch::new(),
+            oplog: ListOpLog::new_with_tie_break(tie_break),
+            subscribers: Default::default(),
         }
     }
 
@@ -153,13 +166,19 @@ impl ListCRDT {
         let oplog = ListOpLog::load_from(bytes)?;
         let branch = oplog.checkout_tip();
         Ok(Self {
-            branch, oplog
+            branch, oplog,
+            subscribers: Default::default(),
         })
     }
 
     pub fn merge_data_and_ff(&mut self, bytes: &[u8]) -> Result<Frontier, ParseError> {
         let v = self.oplog.decode_and_add(bytes)?;
-        self.branch.merge(&self.oplog, self.oplog.cg.version.as_ref());
+        if self.subscribers.is_empty() {
+            self.branch.merge(&self.oplog, self.oplog.cg.version.as_ref());
+        } else {
+            let ops = self.branch.merge_and_collect(&self.oplog, self.oplog.cg.version.as_ref());
+            self.subscribers.notify(&ops);
+        }
         Ok(v)
     }
 
@@ -172,17 +191,24 @@ impl ListCRDT {
     }
 
     pub fn apply_local_operations(&mut self, agent: AgentId, local_ops: &[TextOperation]) -> LV {
-        apply_local_operations(&mut self.oplog, &mut self.branch, agent, local_ops)
+        let v = apply_local_operations(&mut self.oplog, &mut self.branch, agent, local_ops);
+        self.subscribers.notify(local_ops);
+        v
     }
 
     pub fn insert(&mut self, agent: AgentId, pos: usize, ins_content: &str) -> LV {
         // self.branch.insert(&mut self.oplog, agent, pos, ins_content)
-        internal_do_insert(&mut self.oplog, &mut self.branch, agent, pos, ins_content)
+        let v = internal_do_insert(&mut self.oplog, &mut self.branch, agent, pos, ins_content);
+        if !self.subscribers.is_empty() {
+            self.subscribers.notify(&[TextOperation::new_insert(pos, ins_content)]);
+        }
+        v
     }
 
     #[cfg(feature = "wchar_conversion")]
     pub fn insert_at_wchar(&mut self, agent: AgentId, wchar_pos: usize, ins_content: &str) -> LV {
-        self.branch.insert_at_wchar(&mut self.oplog, agent, wchar_pos, ins_content)
+        let char_pos = self.branch.content.wchars_to_chars(wchar_pos);
+        self.insert(agent, char_pos, ins_content)
     }
 
     // pub fn local_delete(&mut self, agent: AgentId, pos: usize, del_span: usize) -> Time {
@@ -191,23 +217,32 @@ impl ListCRDT {
 
     pub fn delete_without_content(&mut self, agent: AgentId, loc: Range<usize>) -> LV {
         // self.branch.delete_without_content(&mut self.oplog, agent, loc)
-        internal_do_delete(&mut self.oplog, &mut self.branch, agent, loc.into())
+        let v = internal_do_delete(&mut self.oplog, &mut self.branch, agent, loc.clone().into());
+        if !self.subscribers.is_empty() {
+            self.subscribers.notify(&[TextOperation::new_delete(loc)]);
+        }
+        v
     }
 
     pub fn delete(&mut self, agent: AgentId, range: Range<usize>) -> LV {
-        self.branch.delete(&mut self.oplog, agent, range)
+        let op = self.branch.make_delete_op(range);
+        self.apply_local_operations(agent, &[op])
     }
 
     #[cfg(feature = "wchar_conversion")]
     pub fn delete_at_wchar(&mut self, agent: AgentId, wchar_range: Range<usize>) -> LV {
-        self.branch.delete_at_wchar(&mut self.oplog, agent, wchar_range)
+        let c = &self.branch.content;
+        let start_pos = c.wchars_to_chars(wchar_range.start);
+        let end_pos = c.wchars_to_chars(wchar_range.end);
+        self.delete(agent, start_pos..end_pos)
     }
 
+    #[cfg(feature = "std")]
     pub fn print_stats(&self, detailed: bool) {
         println!("Document of length {}", self.branch.len());
 
         println!("Content memory size: {}", format_size(
-            self.branch.content.borrow().mem_size(),
+            self.branch.content.mem_size(),
             BINARY
         ));
         println!("(Efficient size: {})", format_size(
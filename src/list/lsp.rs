@@ -0,0 +1,166 @@
+//! Feeding merged changes to a language server.
+//!
+//! Language servers are told about edits using the [LSP] `TextDocumentContentChangeEvent` type.
+//! Positions in LSP are named by line and UTF-16 code unit offset, while diamond types uses
+//! unicode character offsets. [`ListBranch::merge_lsp_changes`] merges changes into a branch, and
+//! returns the changes as a list of LSP change events which can be sent straight to a language
+//! server.
+//!
+//! With the `serde` feature, the types here serialize to the same JSON as the LSP types.
+//!
+//! [LSP]: https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+use jumprope::JumpRope;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use crate::LV;
+use crate::list::{ListBranch, ListOpLog};
+use crate::list::merge::apply_xf_op;
+use crate::list::text_buffer::TextBuffer;
+
+/// A position in a document, named by (zero based) line and UTF-16 offset within the line.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LspPosition {
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LspRange {
+    pub start: LspPosition,
+    pub end: LspPosition,
+}
+
+/// A single change to a document. The range is replaced by `text`.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TextDocumentContentChangeEvent {
+    pub range: LspRange,
+    pub text: String,
+}
+
+/// Find the LSP positions of a range of characters. LSP treats `\n`, `\r\n` and `\r` as line
+/// breaks.
+fn lsp_range(content: &JumpRope, range: Range<usize>) -> LspRange {
+    let mut pos = LspPosition::default();
+    let mut start = pos;
+    let mut prev_cr = false;
+    for (i, c) in content.slice_chars(0..range.end).flat_map(|s| s.chars()).enumerate() {
+        if i == range.start { start = pos; }
+        match c {
+            '\n' if prev_cr => {} // The \r already started a new line.
+            '\n' | '\r' => {
+                pos.line += 1;
+                pos.character = 0;
+            }
+            c => { pos.character += c.len_utf16() as u32; }
+        }
+        prev_cr = c == '\r';
+    }
+    if range.start == range.end { start = pos; }
+    LspRange { start, end: pos }
+}
+
+/// Applies changes to a branch's content, recording them as LSP change events.
+struct LspRecorder<'a> {
+    branch: &'a mut ListBranch,
+    events: Vec<TextDocumentContentChangeEvent>,
+}
+
+impl<'a> TextBuffer for LspRecorder<'a> {
+    fn len_chars(&self) -> usize { self.branch.content.len_chars() }
+
+    fn insert(&mut self, pos: usize, content: &str) {
+        let range = lsp_range(&self.branch.content, pos..pos);
+        self.events.push(TextDocumentContentChangeEvent { range, text: content.into() });
+        self.branch.insert_content(pos, content);
+    }
+
+    fn remove(&mut self, range: Range<usize>) {
+        let range_lsp = lsp_range(&self.branch.content, range.clone());
+        self.events.push(TextDocumentContentChangeEvent { range: range_lsp, text: String::new() });
+        self.branch.remove_content(range);
+    }
+}
+
+impl ListBranch {
+    /// Variant of [`merge`](ListBranch::merge) which returns the changes made to the branch's
+    /// content as LSP change events. The events must be applied in order, since each event's range
+    /// is relative to the document after the previous events.
+    ///
+    /// Finding the line and column of each change scans the document up to that change, so this
+    /// is slower than a normal merge for large documents.
+    pub fn merge_lsp_changes(&mut self, oplog: &ListOpLog, merge_frontier: &[LV]) -> Vec<TextDocumentContentChangeEvent> {
+        let mut iter = oplog.get_xf_operations_full(self.version.as_ref(), merge_frontier);
+        let mut recorder = LspRecorder { branch: self, events: Vec::new() };
+        for (_lv, origin_op, xf) in &mut iter {
+            apply_xf_op(&mut recorder, &oplog.operation_ctx, origin_op, xf);
+        }
+
+        let events = recorder.events;
+        self.version = iter.into_frontier();
+        events
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+    use crate::list::ListOpLog;
+    use crate::list::lsp::{LspPosition, LspRange, TextDocumentContentChangeEvent};
+
+    fn pos(line: u32, character: u32) -> LspPosition { LspPosition { line, character } }
+
+    /// Apply LSP change events to a string, the way a language server would.
+    fn apply(doc: &mut String, events: &[TextDocumentContentChangeEvent]) {
+        for e in events {
+            let offset = |p: LspPosition| {
+                let mut line_start = 0;
+                for _ in 0..p.line {
+                    line_start += doc[line_start..].find('\n').unwrap() + 1;
+                }
+                let mut utf16 = 0;
+                let col = doc[line_start..].char_indices()
+                    .find(|(_, c)| {
+                        let found = utf16 >= p.character as usize;
+                        utf16 += c.len_utf16();
+                        found
+                    })
+                    .map_or(doc.len() - line_start, |(i, _)| i);
+                line_start + col
+            };
+            let range = offset(e.range.start)..offset(e.range.end);
+            doc.replace_range(range, &e.text);
+        }
+    }
+
+    #[test]
+    fn lsp_changes() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "fn main() {\n    😀 hi\n}\n");
+        let mut branch = oplog.checkout(&[a]);
+
+        oplog.add_insert_at(mike, &[a], 21, "    bye\n");
+        oplog.add_delete_at(seph, &[a], 18..20);
+        oplog.add_insert_at(seph, &[a], 18, "yo");
+
+        let mut doc = branch.content.to_string();
+        let events = branch.merge_lsp_changes(&oplog, oplog.cg.version.as_ref());
+        assert_eq!(events.iter().map(|e| e.range).collect::<Vec<_>>(), [
+            LspRange { start: pos(1, 7), end: pos(1, 7) },
+            LspRange { start: pos(1, 9), end: pos(1, 11) },
+            LspRange { start: pos(2, 0), end: pos(2, 0) },
+        ]);
+        apply(&mut doc, &events);
+        assert_eq!(doc, branch.content.to_string());
+        assert_eq!(branch, oplog.checkout_tip());
+    }
+}
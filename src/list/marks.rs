@@ -0,0 +1,372 @@
+//! Range annotations (marks) over a document, like bold text, links or comment highlights.
+//!
+//! Each mark covers the content between two [anchors](crate::list::anchor::Anchor), so marks
+//! move with the content they annotate. Marks are stored in the oplog alongside the document's
+//! operations, and they're included when the oplog is encoded.
+//!
+//! Marks are a simple CRDT. Each mark has a unique [`MarkId`], and records the version of the
+//! document it was created at. Deleting a mark records the version it was deleted at. Merging two
+//! oplogs takes the union of their marks, so concurrent creation and deletion of marks always
+//! converges. A mark is visible at some version if it was created at (or before) that version,
+//! and not deleted.
+//!
+//! Adding or deleting a mark doesn't change the oplog's version. Instead the marks have a local
+//! clock which is bumped on every change, so [`DocFile`](crate::list::storage::DocFile) and the
+//! [sync protocol](crate::list::protocol) can find the marks they haven't saved or sent yet.
+
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
+use core::ops::Range;
+use crate::{AgentId, Frontier, LV};
+use crate::list::{ListCRDT, ListOpLog};
+use crate::list::anchor::Anchor;
+
+/// What happens when content is inserted right at the boundary of a mark.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum MarkExpand {
+    /// Content inserted at either boundary is outside the mark. This is usually right for links.
+    None = 0,
+    /// Content inserted right before the start of the mark is included in the mark.
+    Before = 1,
+    /// Content inserted right after the end of the mark is included in the mark. This is usually
+    /// right for formatting like bold text.
+    After = 2,
+    /// Content inserted at either boundary is included in the mark.
+    Both = 3,
+}
+
+impl MarkExpand {
+    fn expands_before(self) -> bool {
+        matches!(self, MarkExpand::Before | MarkExpand::Both)
+    }
+
+    fn expands_after(self) -> bool {
+        matches!(self, MarkExpand::After | MarkExpand::Both)
+    }
+}
+
+impl TryFrom<usize> for MarkExpand {
+    type Error = ();
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(MarkExpand::None),
+            1 => Ok(MarkExpand::Before),
+            2 => Ok(MarkExpand::After),
+            3 => Ok(MarkExpand::Both),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A range annotation over a document.
+///
+/// The anchors name the boundaries of the mark. Usually `start_anchor` is
+/// [`Anchor::before`] the first marked character, and `end_anchor` is [`Anchor::after`] the last
+/// marked character. Whether content inserted right at a boundary joins the mark is controlled by
+/// `expand`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct MarkRange {
+    pub start_anchor: Anchor,
+    pub end_anchor: Anchor,
+    pub expand: MarkExpand,
+    /// Application data describing the mark. Diamond types doesn't interpret this.
+    pub payload: Vec<u8>,
+}
+
+/// The unique ID of a mark. Each agent numbers the marks it creates from 0.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct MarkId {
+    pub agent: AgentId,
+    pub seq: usize,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct MarkEntry {
+    pub(crate) range: MarkRange,
+    /// The version of the document the mark was created at.
+    pub(crate) created_at: Frontier,
+    /// The versions at which the mark was deleted. Usually this has at most one entry, but a
+    /// mark can be deleted concurrently by multiple peers.
+    pub(crate) deleted_at: Vec<Frontier>,
+    /// The value of the marks clock when this entry last changed. This is local to the oplog, and
+    /// isn't encoded.
+    pub(crate) changed: usize,
+}
+
+/// The set of all marks known by an oplog.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Marks {
+    pub(crate) entries: BTreeMap<MarkId, MarkEntry>,
+    /// Bumped every time a mark is added or deleted.
+    pub(crate) clock: usize,
+}
+
+impl Marks {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn next_seq(&self, agent: AgentId) -> usize {
+        self.entries.range(MarkId { agent, seq: 0 }..=MarkId { agent, seq: usize::MAX })
+            .next_back()
+            .map_or(0, |(id, _)| id.seq + 1)
+    }
+
+    /// Add a mark (or merge in the deletions of a mark we already have).
+    pub(crate) fn merge_entry(&mut self, id: MarkId, mut entry: MarkEntry) {
+        if let Some(existing) = self.entries.get_mut(&id) {
+            for version in entry.deleted_at {
+                if !existing.deleted_at.contains(&version) {
+                    existing.deleted_at.push(version);
+                    self.clock += 1;
+                    existing.changed = self.clock;
+                }
+            }
+        } else {
+            self.clock += 1;
+            entry.changed = self.clock;
+            self.entries.insert(id, entry);
+        }
+    }
+}
+
+impl ListOpLog {
+    /// Merge all the marks in other into self. Used by
+    /// [`add_missing_operations_from`](ListOpLog::add_missing_operations_from), after all the
+    /// operations have been merged.
+    pub(crate) fn add_missing_marks_from(&mut self, other: &Self, agent_map: &[AgentId]) {
+        let map_lv = |lv: LV| {
+            let (agent, seq) = other.lv_to_agent_version(lv);
+            self.crdt_id_to_time((agent_map[agent as usize], seq))
+        };
+        let map_frontier = |f: &Frontier| {
+            let mut lvs: Vec<LV> = f.iter().map(|lv| map_lv(*lv)).collect();
+            lvs.sort_unstable();
+            Frontier::from_sorted(&lvs)
+        };
+
+        let entries: Vec<_> = other.marks.entries.iter().map(|(id, entry)| {
+            let id = MarkId { agent: agent_map[id.agent as usize], seq: id.seq };
+            let mut range = entry.range.clone();
+            range.start_anchor.lv = map_lv(range.start_anchor.lv);
+            range.end_anchor.lv = map_lv(range.end_anchor.lv);
+            (id, MarkEntry {
+                range,
+                created_at: map_frontier(&entry.created_at),
+                deleted_at: entry.deleted_at.iter().map(map_frontier).collect(),
+                changed: 0,
+            })
+        }).collect();
+
+        for (id, entry) in entries {
+            self.marks.merge_entry(id, entry);
+        }
+    }
+
+    /// Add a mark to the document. The mark is created at the specified version, which must
+    /// contain the characters named by the mark's anchors.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either anchor doesn't name a character inserted at (or before) `version`.
+    pub fn add_mark(&mut self, agent: AgentId, version: &[LV], range: MarkRange) -> MarkId {
+        for anchor in [range.start_anchor, range.end_anchor] {
+            assert!(self.resolve_anchor(version, anchor).is_some(), "Invalid mark anchor {:?}", anchor);
+        }
+
+        let id = MarkId { agent, seq: self.marks.next_seq(agent) };
+        self.marks.merge_entry(id, MarkEntry {
+            range,
+            created_at: Frontier::from_sorted(version),
+            deleted_at: vec![],
+            changed: 0,
+        });
+        id
+    }
+
+    /// Delete a mark as of the specified version. Returns false if the mark doesn't exist.
+    pub fn delete_mark(&mut self, id: MarkId, version: &[LV]) -> bool {
+        let Some(entry) = self.marks.entries.get_mut(&id) else { return false; };
+        let version = self.cg.graph.find_dominators_2(version, entry.created_at.as_ref());
+        if !entry.deleted_at.contains(&version) {
+            entry.deleted_at.push(version);
+            self.marks.clock += 1;
+            entry.changed = self.marks.clock;
+        }
+        true
+    }
+
+    /// Look up a mark by ID, whether or not it has been deleted.
+    pub fn get_mark(&self, id: MarkId) -> Option<&MarkRange> {
+        self.marks.entries.get(&id).map(|entry| &entry.range)
+    }
+
+    /// Returns true if the mark exists at the specified version.
+    pub fn mark_visible_at(&self, id: MarkId, version: &[LV]) -> bool {
+        self.marks.entries.get(&id).is_some_and(|entry| self.entry_visible_at(entry, version))
+    }
+
+    fn entry_visible_at(&self, entry: &MarkEntry, version: &[LV]) -> bool {
+        let graph = &self.cg.graph;
+        graph.frontier_contains_frontier(version, entry.created_at.as_ref())
+            && !entry.deleted_at.iter().any(|v| graph.frontier_contains_frontier(version, v.as_ref()))
+    }
+
+    /// Find the range of a mark in the document at the specified version. Returns None if the
+    /// mark isn't visible at that version.
+    ///
+    /// If all the marked content has been deleted, the returned range is empty.
+    pub fn resolve_mark(&self, id: MarkId, version: &[LV]) -> Option<Range<usize>> {
+        let entry = self.marks.entries.get(&id)?;
+        if !self.entry_visible_at(entry, version) { return None; }
+
+        let range = &entry.range;
+        let start = self.resolve_anchor_with(version, range.start_anchor, !range.expand.expands_before())?;
+        let end = self.resolve_anchor_with(version, range.end_anchor, range.expand.expands_after())?;
+        Some(start..end.max(start))
+    }
+
+    /// List the marks which overlap the specified range of the document at some version, along
+    /// with the range each mark covers. Marks are returned in ID order.
+    ///
+    /// A mark overlaps the range if they share at least one character. Marks whose content has
+    /// all been deleted are never returned.
+    ///
+    /// This resolves every mark in the document, so it takes time proportional to the number of
+    /// marks.
+    pub fn marks_overlapping(&self, version: &[LV], range: Range<usize>) -> Vec<(MarkId, Range<usize>, &MarkRange)> {
+        self.marks.entries.iter().filter_map(|(id, entry)| {
+            let mark_range = self.resolve_mark(*id, version)?;
+            if mark_range.start < range.end && range.start < mark_range.end {
+                Some((*id, mark_range, &entry.range))
+            } else { None }
+        }).collect()
+    }
+}
+
+impl ListCRDT {
+    /// Add a mark to the document at the current version. See [`ListOpLog::add_mark`].
+    pub fn add_mark(&mut self, agent: AgentId, range: MarkRange) -> MarkId {
+        self.oplog.add_mark(agent, self.branch.local_frontier_ref(), range)
+    }
+
+    /// Delete a mark at the current version. See [`ListOpLog::delete_mark`].
+    pub fn delete_mark(&mut self, id: MarkId) -> bool {
+        self.oplog.delete_mark(id, self.branch.local_frontier_ref())
+    }
+
+    /// List the marks which overlap the specified range of the document. See
+    /// [`ListOpLog::marks_overlapping`].
+    pub fn marks_overlapping(&self, range: Range<usize>) -> Vec<(MarkId, Range<usize>, &MarkRange)> {
+        self.oplog.marks_overlapping(self.branch.local_frontier_ref(), range)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::anchor::Anchor;
+    use crate::list::encoding::ENCODE_FULL;
+    use crate::list::{ListCRDT, ListOpLog};
+    use crate::list::marks::{MarkExpand, MarkId, MarkRange};
+
+    fn mark(start: usize, end: usize, expand: MarkExpand, payload: &str) -> MarkRange {
+        MarkRange {
+            start_anchor: Anchor::before(start),
+            end_anchor: Anchor::after(end),
+            expand,
+            payload: payload.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn marks_expand() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "hello world");
+        let link = doc.add_mark(seph, mark(0, 4, MarkExpand::None, "link"));
+        let bold = doc.add_mark(seph, mark(6, 10, MarkExpand::After, "bold"));
+        let both = doc.add_mark(seph, mark(6, 10, MarkExpand::Both, "both"));
+        assert_ne!(link, bold);
+
+        // Inserts at the boundaries of the marks.
+        doc.insert(seph, 11, "!");
+        doc.insert(seph, 6, "_");
+        doc.insert(seph, 5, "?");
+        doc.insert(seph, 0, "^");
+        assert_eq!(doc.branch.content().to_string(), "^hello? _world!");
+
+        let v = doc.branch.local_frontier_ref();
+        assert_eq!(doc.oplog.resolve_mark(link, v), Some(1..6));
+        assert_eq!(doc.oplog.resolve_mark(bold, v), Some(9..15));
+        assert_eq!(doc.oplog.resolve_mark(both, v), Some(8..15));
+
+        let found: Vec<_> = doc.marks_overlapping(5..9).into_iter()
+            .map(|(_, range, mark)| (range, mark.payload.clone()))
+            .collect();
+        assert_eq!(found, vec![(1..6, b"link".to_vec()), (8..15, b"both".to_vec())]);
+        assert!(doc.marks_overlapping(6..8).is_empty());
+
+        // Marks are empty once their content has been deleted.
+        doc.delete(seph, 8..15);
+        assert_eq!(doc.oplog.resolve_mark(bold, doc.branch.local_frontier_ref()), Some(8..8));
+        assert_eq!(doc.marks_overlapping(0..doc.len()).len(), 1);
+    }
+
+    #[test]
+    fn concurrent_marks_converge() {
+        let mut a = ListOpLog::new();
+        let seph = a.get_or_create_agent_id("seph");
+        let v1 = a.add_insert(seph, 0, "abcdef");
+        let m1 = a.add_mark(seph, &[v1], mark(1, 3, MarkExpand::After, "x"));
+
+        let mut b = ListOpLog::load_from(&a.encode(ENCODE_FULL)).unwrap();
+        assert_eq!(a, b);
+
+        // Concurrently, a deletes m1, and b creates a new mark (using an agent with no other
+        // changes) and deletes m1 as well.
+        let kath = b.get_or_create_agent_id("kath");
+        let mike = b.get_or_create_agent_id("mike");
+        let v2 = a.add_insert(seph, 6, "g");
+        assert!(a.delete_mark(m1, &[v2]));
+        let m2 = b.add_mark(mike, &[v1], mark(0, 5, MarkExpand::None, "y"));
+        let v3 = b.add_insert(kath, 0, "z");
+        assert!(b.delete_mark(m1, &[v3]));
+        assert!(!b.delete_mark(MarkId { agent: kath, seq: 0 }, &[v3]));
+
+        let mut a2 = a.clone();
+        a2.add_missing_operations_from(&b);
+        a.decode_and_add(&b.encode(ENCODE_FULL)).unwrap();
+        b.decode_and_add(&a2.encode(ENCODE_FULL)).unwrap();
+        assert_eq!(a, a2);
+        assert_eq!(a, b);
+
+        // m1 is visible at v1, but deleted in later versions.
+        assert!(a.mark_visible_at(m1, &[v1]));
+        assert!(!a.mark_visible_at(m1, &[v2]));
+        assert!(!a.mark_visible_at(m1, a.cg.version.as_ref()));
+        assert!(!a.mark_visible_at(m1, &[v3]));
+
+        // "zabcdefg"
+        let found: Vec<_> = a.marks_overlapping(a.cg.version.as_ref(), 0..8).into_iter()
+            .map(|(id, range, mark)| (a.get_agent_name(id.agent).to_string(), range, mark.payload.clone()))
+            .collect();
+        assert_eq!(found, vec![("mike".to_string(), 1..7, b"y".to_vec())]);
+        assert_eq!(b.get_mark(m2).unwrap().payload, b"y");
+    }
+
+    #[test]
+    fn marks_in_damaged_file_are_ignored() {
+        let mut a = ListOpLog::new();
+        let seph = a.get_or_create_agent_id("seph");
+        let v1 = a.add_insert(seph, 0, "abcdef");
+        let base = a.clone();
+        a.add_mark(seph, &[v1], mark(1, 3, MarkExpand::After, "x"));
+
+        let mut damaged = a.encode(ENCODE_FULL);
+        *damaged.last_mut().unwrap() ^= 1;
+        let mut b = base.clone();
+        assert!(b.decode_and_add(&damaged).is_err());
+        assert_eq!(b, base);
+    }
+}
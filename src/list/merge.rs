@@ -1,15 +1,290 @@
-use rle::HasLength;
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::Instant;
+use rle::{HasLength, SplitableSpan};
+use crate::causalgraph::agent_assignment::remote_ids::{RemoteVersion, RemoteVersionSpan, VersionConversionError};
 use crate::frontier::FrontierRef;
 use crate::list::{ListBranch, ListOpLog};
-use crate::list::operation::{ListOpKind, TextOperation};
+use crate::list::operation::{ListOpKind, TextOperation, TextOperationRef};
 use crate::listmerge::merge::{reverse_str, TransformedOpsIter2};
 use crate::listmerge::merge::TransformedResult::{BaseMoved, DeleteAlreadyHappened};
-use crate::{DTRange, LV};
+use crate::listmerge::merge::TransformedResult;
+use crate::listmerge::plan::{M1Plan, M1PlanAction};
+#[cfg(feature = "parallel")]
+use crate::listmerge::parallel::xf_operations_parallel;
+use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
+use crate::list::text_buffer::TextBuffer;
+use crate::{DTError, DTRange, Frontier, LV};
+use crate::rle::KVPair;
+use crate::unicount::count_chars;
+
+/// An estimate of how much work a merge will take. See
+/// [`ListOpLog::estimate_merge_cost`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct MergeCostEstimate {
+    /// The number of operations being merged in.
+    pub new_ops: usize,
+
+    /// The number of operations which are concurrent with other changes, and need to be replayed
+    /// through the merge tracker. This can include operations which are already in the `from`
+    /// version. Zero if the merge is a simple fast-forward.
+    pub conflict_ops: usize,
+
+    /// The number of operations the tracker retreats and advances past while replaying.
+    pub tracker_moves: usize,
+
+    /// A rough estimate of the peak number of items in the merge tracker.
+    pub tracker_size: usize,
+}
+
+/// Statistics about the work done by a merge. See [`ListBranch::merge_reporting`].
+///
+/// Documents whose merges regularly transform lots of operations or build big trackers have
+/// expensive histories. Compacting their history can make them much faster to work with.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct MergeStats {
+    /// The number of operations which were fast-forwarded. These are applied directly, without
+    /// being transformed.
+    pub ff_ops: usize,
+
+    /// The number of operations which were replayed through the merge tracker. This includes
+    /// operations which were already in the branch, which are replayed to build the tracker state.
+    pub transformed_ops: usize,
+
+    /// The peak number of entries in the merge tracker.
+    pub peak_tracker_entries: usize,
+
+    /// The number of operations the tracker retreated past.
+    pub retreated_ops: usize,
+
+    /// The number of operations the tracker advanced past.
+    pub advanced_ops: usize,
+
+    /// How long the merge took. This is always zero without std.
+    pub wall_time: Duration,
+}
+
+/// A description of what a merge would do to a document. See [`ListOpLog::simulate_merge`].
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct MergeSummary {
+    /// The number of characters the merge inserts.
+    pub inserted: usize,
+
+    /// The number of characters the merge deletes. Characters which have already been deleted at
+    /// the `from` version aren't counted.
+    pub deleted: usize,
+
+    /// The regions of the document which the merge changes, as ranges in the document at the
+    /// `from` version. The ranges are sorted and don't overlap. Content inserted between two
+    /// characters shows up as an empty range.
+    pub changed_regions: Vec<Range<usize>>,
+
+    /// True if the merged changes are concurrent with changes in the `from` version. Concurrent
+    /// changes are merged automatically, but the result might not be what either author expected.
+    pub has_conflicts: bool,
+}
+
+/// A chunk of a document being edited. Each chunk is either content which was in the document
+/// at the base version, or content inserted since.
+#[derive(Debug, Clone, Copy)]
+struct RegionChunk {
+    /// For original content, the position of the chunk at the base version. For inserted content,
+    /// the position at the base version where the content was inserted.
+    base_pos: usize,
+    len: usize,
+    original: bool,
+}
+
+impl RegionChunk {
+    fn base_pos_at(&self, offset: usize) -> usize {
+        if self.original { self.base_pos + offset } else { self.base_pos }
+    }
+}
+
+/// Tracks which regions of a document (at some base version) are changed by a sequence of
+/// operations. Each operation's position is in the document after the previous operations.
+#[derive(Debug, Clone)]
+pub(crate) struct ChangedRegions {
+    chunks: Vec<RegionChunk>,
+    regions: Vec<Range<usize>>,
+}
+
+impl ChangedRegions {
+    pub(crate) fn new() -> Self {
+        // We don't know how long the document is. The first chunk is big enough to cover any
+        // position in it.
+        Self {
+            chunks: vec![RegionChunk { base_pos: 0, len: usize::MAX / 2, original: true }],
+            regions: vec![],
+        }
+    }
+
+    /// Find the chunk containing pos, and the offset into that chunk. Positions at the end of a
+    /// chunk are matched to that chunk, rather than the start of the next one.
+    fn find(chunks: &[RegionChunk], mut pos: usize) -> (usize, usize) {
+        for (i, chunk) in chunks.iter().enumerate() {
+            if pos <= chunk.len { return (i, pos); }
+            pos -= chunk.len;
+        }
+        unreachable!("Position past the end of the document");
+    }
+
+    pub(crate) fn apply(&mut self, kind: ListOpKind, start: usize, len: usize) {
+        let chunks = &mut self.chunks;
+        let (idx, offset) = Self::find(chunks, start);
+        let start_pos = chunks[idx].base_pos_at(offset);
+
+        match kind {
+            ListOpKind::Ins => {
+                self.regions.push(start_pos..start_pos);
+
+                let mut chunk = chunks[idx];
+                let new_chunk = RegionChunk { base_pos: start_pos, len, original: false };
+                if offset == chunk.len {
+                    chunks.insert(idx + 1, new_chunk);
+                } else {
+                    chunks[idx].len = offset;
+                    chunk.len -= offset;
+                    chunk.base_pos = chunk.base_pos_at(offset);
+                    chunks.splice(idx + 1..idx + 1, [new_chunk, chunk]);
+                }
+            }
+            ListOpKind::Del => {
+                let (end_idx, end_offset) = Self::find(chunks, start + len);
+                let end_pos = chunks[end_idx].base_pos_at(end_offset);
+                self.regions.push(start_pos..end_pos);
+
+                // Cut the deleted content out of the chunks.
+                let mut tail = chunks[end_idx];
+                tail.len -= end_offset;
+                tail.base_pos = tail.base_pos_at(end_offset);
+                chunks[idx].len = offset;
+                chunks.splice(idx + 1..end_idx + 1, [tail]);
+            }
+        }
+        chunks.retain(|chunk| chunk.len > 0);
+    }
+
+    /// The position in the current document of the character at base_pos in the base version.
+    /// The character must not have been deleted.
+    pub(crate) fn current_pos(&self, base_pos: usize) -> usize {
+        let mut pos = 0;
+        for chunk in self.chunks.iter() {
+            if chunk.original && chunk.base_pos <= base_pos && base_pos < chunk.base_pos + chunk.len {
+                return pos + base_pos - chunk.base_pos;
+            }
+            pos += chunk.len;
+        }
+        unreachable!("Character has been deleted");
+    }
+
+    /// The changed regions of the document at the base version. The ranges are sorted and don't
+    /// overlap.
+    pub(crate) fn into_regions(mut self) -> Vec<Range<usize>> {
+        self.regions.sort_unstable_by_key(|r| r.start);
+        let mut result: Vec<Range<usize>> = Vec::with_capacity(self.regions.len());
+        for r in self.regions {
+            match result.last_mut() {
+                Some(last) if r.start <= last.end => { last.end = last.end.max(r.end); }
+                _ => result.push(r),
+            }
+        }
+        result
+    }
+}
+
+/// Limits on the resources a merge is allowed to use. See
+/// [`ListBranch::try_merge_with_limits`]. Limits which are `None` aren't enforced.
+///
+/// The limits are checked against the history being merged rather than the time or memory used,
+/// so a merge which fails on one peer will fail the same way on every other peer.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct MergeLimits {
+    /// The maximum number of entries in the merge tracker (like
+    /// [`MergeStats::peak_tracker_entries`]). Counting the tracker's entries is slow, so it's only
+    /// counted after every `max / 8` operations or so. Each operation adds at most a few entries,
+    /// so the tracker can grow up to about half past the limit before the merge fails.
+    pub max_tracker_entries: Option<usize>,
+
+    /// The maximum number of characters the merge can insert into the branch's content.
+    pub max_content_growth: Option<usize>,
+}
 
 impl ListOpLog {
+    /// Estimate the cost of merging the operations in `merging` into a document at version
+    /// `from`, without doing the merge. This is much cheaper than the merge itself, since it only
+    /// looks at the causal graph.
+    ///
+    /// Servers can use this to decide whether to merge changes immediately, defer the work to a
+    /// background worker, or send a snapshot instead.
+    pub fn estimate_merge_cost(&self, from: FrontierRef, merging: FrontierRef) -> MergeCostEstimate {
+        let (new_spans, _) = self.cg.graph.diff(merging, from);
+        let mut estimate = MergeCostEstimate {
+            new_ops: new_spans.iter().map(|span| span.len()).sum(),
+            ..Default::default()
+        };
+
+        let (plan, _) = self.cg.graph.make_m1_plan(Some(&self.operations), from, merging, true);
+        for action in plan.0.iter() {
+            match action {
+                M1PlanAction::Apply(span) => { estimate.conflict_ops += span.len(); }
+                M1PlanAction::Retreat(span) | M1PlanAction::Advance(span) => {
+                    estimate.tracker_moves += span.len();
+                }
+                M1PlanAction::Clear | M1PlanAction::FF(_) | M1PlanAction::BeginOutput => {}
+            }
+        }
+        estimate.tracker_size = self.peak_tracker_size(&plan);
+
+        estimate
+    }
+
+    /// Figure out what merging the operations in `merging` into a document at version `from`
+    /// would do, without modifying any branch. Editors can use this to warn users before a merge
+    /// changes the part of the document they're looking at.
+    pub fn simulate_merge(&self, from: FrontierRef, merging: FrontierRef) -> MergeSummary {
+        let (only_merging, only_from) = self.cg.graph.diff(merging, from);
+        let mut summary = MergeSummary {
+            has_conflicts: !only_merging.is_empty() && !only_from.is_empty(),
+            ..Default::default()
+        };
+
+        let mut regions = ChangedRegions::new();
+        for (_, op) in self.iter_xf_operations_ref_from(from, merging) {
+            let Some(op) = op else { continue; };
+            match op.kind {
+                ListOpKind::Ins => { summary.inserted += op.len(); }
+                ListOpKind::Del => { summary.deleted += op.len(); }
+            }
+            regions.apply(op.kind, op.start(), op.len());
+        }
+        summary.changed_regions = regions.into_regions();
+
+        summary
+    }
+
+    /// A rough estimate of the peak number of items in the merge tracker while running a plan.
+    fn peak_tracker_size(&self, plan: &M1Plan) -> usize {
+        let mut tracker_size = 0;
+        let mut peak = 0;
+        for action in plan.0.iter() {
+            match action {
+                M1PlanAction::Apply(span) => {
+                    tracker_size += self.estimate_cost(*span);
+                    peak = peak.max(tracker_size);
+                }
+                M1PlanAction::Clear => { tracker_size = 0; }
+                _ => {}
+            }
+        }
+        peak
+    }
+
     pub(crate) fn get_xf_operations_full(&self, from: FrontierRef, merging: FrontierRef) -> TransformedOpsIter2 {
         TransformedOpsIter2::new(&self.cg.graph, &self.cg.agent_assignment,
-                                &self.operation_ctx, &self.operations,
+                                &self.operation_ctx, &self.operations, self.tie_break,
                                 from, merging)
     }
 
@@ -21,10 +296,18 @@ impl ListOpLog {
     /// `get_xf_operations` returns an iterator over the *transformed changes*. That is, the set of
     /// changes that could be applied linearly to a document to bring it up to date.
     pub fn iter_xf_operations_from(&self, from: FrontierRef, merging: FrontierRef) -> impl Iterator<Item=(DTRange, Option<TextOperation>)> + '_ {
+        self.iter_xf_operations_ref_from(from, merging)
+            .map(|(range, op)| (range, op.map(TextOperation::from)))
+    }
+
+    /// Variant of [`iter_xf_operations_from`](ListOpLog::iter_xf_operations_from) which borrows
+    /// each operation's content from the oplog rather than copying it. Use this when relaying or
+    /// inspecting lots of operations, to avoid an allocation per operation.
+    pub fn iter_xf_operations_ref_from(&self, from: FrontierRef, merging: FrontierRef) -> impl Iterator<Item=(DTRange, Option<TextOperationRef<'_>>)> + '_ {
         self.get_xf_operations_full(from, merging)
             .map(|(lv, mut origin_op, xf)| {
                 let len = origin_op.len();
-                let op: Option<TextOperation> = match xf {
+                let op: Option<TextOperationRef> = match xf {
                     BaseMoved(base) => {
                         origin_op.loc.span = (base..base+len).into();
                         let content = origin_op.get_content(&self.operation_ctx);
@@ -36,6 +319,26 @@ impl ListOpLog {
             })
     }
 
+    /// Variant of [`iter_xf_operations_from`](ListOpLog::iter_xf_operations_from) which names
+    /// versions using remote IDs. The returned operations are split up so each one is named by a
+    /// single span of remote versions.
+    pub fn iter_xf_operations_from_remote(&self, from: &[RemoteVersion], merging: &[RemoteVersion]) -> Result<impl Iterator<Item=(RemoteVersionSpan<'_>, Option<TextOperation>)> + '_, VersionConversionError> {
+        let aa = &self.cg.agent_assignment;
+        let from = aa.try_remote_to_local_frontier(from.iter().copied())?;
+        let merging = aa.try_remote_to_local_frontier(merging.iter().copied())?;
+
+        Ok(self.iter_xf_operations_from(from.as_ref(), merging.as_ref())
+            .flat_map(move |(range, mut op)| {
+                aa.client_with_localtime.iter_range(range).map(move |KVPair(_, span)| {
+                    let op_here = match &mut op {
+                        Some(op) if op.len() > span.len() => Some(op.truncate_keeping_right(span.len())),
+                        op => op.take(),
+                    };
+                    (aa.agent_span_to_remote(span), op_here)
+                })
+            }))
+    }
+
     /// Get all transformed operations from the start of time.
     ///
     /// This is a shorthand for `oplog.get_xf_operations(&[], oplog.local_version)`, but
@@ -46,10 +349,16 @@ impl ListOpLog {
         self.iter_xf_operations_from(&[], self.cg.version.as_ref())
     }
 
+    /// Get all transformed operations from the start of time, borrowing their content. See
+    /// [`iter_xf_operations_ref_from`](ListOpLog::iter_xf_operations_ref_from).
+    pub fn iter_xf_operations_ref(&self) -> impl Iterator<Item=(DTRange, Option<TextOperationRef<'_>>)> + '_ {
+        self.iter_xf_operations_ref_from(&[], self.cg.version.as_ref())
+    }
+
     #[cfg(feature = "merge_conflict_checks")]
     pub fn has_conflicts_when_merging(&self) -> bool {
         let mut iter = TransformedOpsIter2::new(&self.cg.graph, &self.cg.agent_assignment,
-                                               &self.operation_ctx, &self.operations,
+                                               &self.operation_ctx, &self.operations, self.tie_break,
                                                &[], self.cg.version.as_ref());
         for _ in &mut iter {}
         iter.concurrent_inserts_collided()
@@ -57,7 +366,208 @@ impl ListOpLog {
 }
 
 
+/// How much work [`MergeTask::run_for`] should do before returning.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MergeBudget {
+    /// Process about this many operations.
+    Ops(usize),
+    /// Keep working until this much time has passed. (This needs std, and isn't supported in
+    /// wasm.)
+    #[cfg(feature = "std")]
+    Time(Duration),
+}
+
+/// How far along a [`MergeTask`] is.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MergeProgress {
+    /// There's more work to do. `ops_done` / `ops_total` can be used to show a progress bar.
+    Pending { ops_done: usize, ops_total: usize },
+    /// The merge is ready to be applied with [`MergeTask::finish`].
+    Complete,
+}
+
+/// A merge which can be run a little at a time. This is useful for big merges (which can take
+/// seconds on some histories) on threads which can't block for that long, like an event loop.
+///
+/// Create a task with [`MergeTask::new`], then call [`run_for`](MergeTask::run_for) on each tick
+/// until it returns [`MergeProgress::Complete`]. Then call [`finish`](MergeTask::finish) to apply
+/// the result to the branch. The branch is only modified by `finish`, so the merge can be aborted
+/// at any time by dropping the task.
+#[derive(Debug)]
+pub struct MergeTask<'a> {
+    oplog: &'a ListOpLog,
+    from: Frontier,
+    iter: TransformedOpsIter2<'a>,
+    output: Vec<(ListOpMetrics, TransformedResult)>,
+    ops_done: usize,
+    ops_total: usize,
+    complete: bool,
+}
+
+impl<'a> MergeTask<'a> {
+    /// Prepare to merge the operations in `merge_frontier` into a branch at version `from`.
+    pub fn new(oplog: &'a ListOpLog, from: &[LV], merge_frontier: &[LV]) -> Self {
+        let iter = oplog.get_xf_operations_full(from, merge_frontier);
+        Self {
+            oplog,
+            from: Frontier::from_sorted(from),
+            ops_total: iter.plan_ops(),
+            iter,
+            output: vec![],
+            ops_done: 0,
+            complete: false,
+        }
+    }
+
+    pub fn progress(&self) -> MergeProgress {
+        if self.complete {
+            MergeProgress::Complete
+        } else {
+            MergeProgress::Pending { ops_done: self.ops_done, ops_total: self.ops_total }
+        }
+    }
+
+    /// Do some of the work needed to merge, stopping once the budget has been used up.
+    pub fn run_for(&mut self, budget: MergeBudget) -> MergeProgress {
+        #[cfg(feature = "std")]
+        let start = Instant::now();
+        let mut work = 0;
+
+        while !self.complete {
+            let out_of_budget = match budget {
+                MergeBudget::Ops(max) => work >= max,
+                #[cfg(feature = "std")]
+                MergeBudget::Time(duration) => work > 0 && start.elapsed() >= duration,
+            };
+            if out_of_budget { break; }
+
+            // Work happens in small chunks so we can check the time regularly.
+            let max_ops = match budget {
+                MergeBudget::Ops(max) => (max - work).min(1000),
+                #[cfg(feature = "std")]
+                MergeBudget::Time(_) => 1000,
+            };
+
+            if let Some(applied) = self.iter.prepare_step(max_ops) {
+                self.ops_done += applied;
+                work += applied.max(1);
+            } else if let Some((_lv, origin_op, xf)) = self.iter.next() {
+                self.ops_done += origin_op.len();
+                work += origin_op.len();
+                self.output.push((origin_op, xf));
+            } else {
+                self.complete = true;
+            }
+        }
+
+        self.progress()
+    }
+
+    /// Apply the merge to a branch, first doing any work which remains.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the branch isn't at the version the task was created with.
+    pub fn finish(mut self, branch: &mut ListBranch) {
+        assert_eq!(branch.version, self.from, "Branch version changed during merge");
+        while self.run_for(MergeBudget::Ops(usize::MAX)) != MergeProgress::Complete {}
+
+        for (origin_op, xf) in self.output {
+            branch.apply_xf_op(self.oplog, origin_op, xf);
+        }
+        branch.version = self.iter.into_frontier();
+    }
+}
+
+/// Apply a transformed operation to a text buffer.
+pub(crate) fn apply_xf_op<B: TextBuffer>(into: &mut B, ctx: &ListOperationCtx, origin_op: ListOpMetrics, xf: TransformedResult) {
+    match (origin_op.kind, xf) {
+        (ListOpKind::Ins, BaseMoved(pos)) => {
+            // println!("Insert '{}' at {} (len {})", op.content, ins_pos, op.len());
+            debug_assert!(origin_op.content_pos.is_some()); // Ok if this is false - we'll just fill with junk.
+            let content = origin_op.get_content(ctx).unwrap();
+            assert!(pos <= into.len_chars());
+            if origin_op.loc.fwd {
+                into.insert(pos, content);
+            } else {
+                // We need to insert the content in reverse order.
+                let c = reverse_str(content);
+                into.insert(pos, &c);
+            }
+        }
+
+        (_, DeleteAlreadyHappened) => {}, // Discard.
+
+        (ListOpKind::Del, BaseMoved(pos)) => {
+            let del_end = pos + origin_op.len();
+            debug_assert!(into.len_chars() >= del_end);
+            // println!("Delete {}..{} (len {}) '{}'", del_start, del_end, mut_len, to.content.slice_chars(del_start..del_end).collect::<String>());
+            into.remove(pos..del_end);
+        }
+    }
+}
+
+/// Writes to a branch's content, keeping its snapshot base up to date.
+struct BranchWriter<'a>(&'a mut ListBranch);
+
+impl<'a> TextBuffer for BranchWriter<'a> {
+    fn len_chars(&self) -> usize { self.0.content.len_chars() }
+    fn insert(&mut self, pos: usize, content: &str) { self.0.insert_content(pos, content) }
+    fn remove(&mut self, range: Range<usize>) { self.0.remove_content(range) }
+}
+
+/// Wraps a text buffer, recording which ranges of the document have changed.
+struct DirtyTracker<B> {
+    inner: B,
+    /// Changed ranges, relative to the current content. The ranges are sorted, and don't touch.
+    dirty: Vec<Range<usize>>,
+}
+
+impl<B: TextBuffer> DirtyTracker<B> {
+    fn mark(&mut self, range: Range<usize>) {
+        let idx = self.dirty.partition_point(|r| r.end < range.start);
+        let mut merged = range;
+        while idx < self.dirty.len() && self.dirty[idx].start <= merged.end {
+            let r = self.dirty.remove(idx);
+            merged = merged.start.min(r.start)..merged.end.max(r.end);
+        }
+        self.dirty.insert(idx, merged);
+    }
+}
+
+impl<B: TextBuffer> TextBuffer for DirtyTracker<B> {
+    fn len_chars(&self) -> usize { self.inner.len_chars() }
+
+    fn insert(&mut self, pos: usize, content: &str) {
+        let len = count_chars(content);
+        for r in self.dirty.iter_mut() {
+            if r.start > pos {
+                r.start += len;
+                r.end += len;
+            } else if r.end >= pos {
+                r.end += len;
+            }
+        }
+        self.inner.insert(pos, content);
+        self.mark(pos..pos + len);
+    }
+
+    fn remove(&mut self, range: Range<usize>) {
+        let map = |x: usize| if x <= range.start { x } else { x.max(range.end) - range.len() };
+        for r in self.dirty.iter_mut() {
+            *r = map(r.start)..map(r.end);
+        }
+        self.inner.remove(range.clone());
+        // The deleted content leaves an empty range where it used to be.
+        self.mark(range.start..range.start);
+    }
+}
+
 impl ListBranch {
+    fn apply_xf_op(&mut self, oplog: &ListOpLog, origin_op: ListOpMetrics, xf: TransformedResult) {
+        apply_xf_op(&mut BranchWriter(self), &oplog.operation_ctx, origin_op, xf);
+    }
+
     /// Add everything in merge_frontier into the set..
     pub fn merge(&mut self, oplog: &ListOpLog, merge_frontier: &[LV]) {
         let mut iter = oplog.get_xf_operations_full(self.version.as_ref(), merge_frontier);
@@ -65,39 +575,403 @@ impl ListBranch {
 
         for (_lv, origin_op, xf) in &mut iter {
             // dbg!(_lv, &origin_op, &xf);
-            match (origin_op.kind, xf) {
-                (ListOpKind::Ins, BaseMoved(pos)) => {
-                    // println!("Insert '{}' at {} (len {})", op.content, ins_pos, op.len());
-                    debug_assert!(origin_op.content_pos.is_some()); // Ok if this is false - we'll just fill with junk.
-                    let content = origin_op.get_content(&oplog.operation_ctx).unwrap();
-                    assert!(pos <= self.content.len_chars());
-                    if origin_op.loc.fwd {
-                        self.content.insert(pos, content);
-                    } else {
-                        // We need to insert the content in reverse order.
-                        let c = reverse_str(content);
-                        self.content.insert(pos, &c);
+            self.apply_xf_op(oplog, origin_op, xf);
+        }
+
+
+        // dbg!(iter.count_range_tracker_size());
+
+        // let expect_v = oplog.cg.graph.find_dominators_2(self.version.as_ref(), merge_frontier);
+        self.version = iter.into_frontier();
+        // println!("-> '{}' v {:?}", self.content.to_string(), self.version);
+        // assert_eq!(self.version, expect_v);
+    }
+
+    /// Variant of [`merge`](ListBranch::merge) which also returns the ranges of the document which
+    /// changed. This is useful for incrementally reparsing the document (eg with tree-sitter), or
+    /// for invalidating parts of the screen.
+    ///
+    /// The ranges are relative to the branch's content after the merge. They're sorted and don't
+    /// overlap or touch. Places where content was deleted show up as empty ranges.
+    pub fn merge_into_with_dirty_ranges(&mut self, oplog: &ListOpLog, merge_frontier: &[LV]) -> Vec<Range<usize>> {
+        let mut iter = oplog.get_xf_operations_full(self.version.as_ref(), merge_frontier);
+        let mut tracker = DirtyTracker { inner: BranchWriter(self), dirty: Vec::new() };
+        for (_lv, origin_op, xf) in &mut iter {
+            apply_xf_op(&mut tracker, &oplog.operation_ctx, origin_op, xf);
+        }
+
+        let dirty = tracker.dirty;
+        self.version = iter.into_frontier();
+        dirty
+    }
+
+    /// Variant of [`merge`](ListBranch::merge) which also returns statistics about the work the
+    /// merge did.
+    pub fn merge_reporting(&mut self, oplog: &ListOpLog, merge_frontier: &[LV]) -> MergeStats {
+        #[cfg(feature = "std")]
+        let start = Instant::now();
+
+        let mut iter = oplog.get_xf_operations_full(self.version.as_ref(), merge_frontier);
+        for (_lv, origin_op, xf) in &mut iter {
+            self.apply_xf_op(oplog, origin_op, xf);
+        }
+
+        #[allow(unused_mut)]
+        let mut stats = iter.stats();
+        self.version = iter.into_frontier();
+
+        #[cfg(feature = "std")] {
+            stats.wall_time = start.elapsed();
+        }
+        stats
+    }
+
+    /// Variant of [`merge`](ListBranch::merge) which fails with
+    /// [`DTError::ResourceExhausted`] instead of using more resources than `limits` allows. This
+    /// is useful for servers which merge histories supplied by untrusted users.
+    ///
+    /// The limits are checked as the merge runs. The merge is applied to a copy of the branch,
+    /// which only replaces the branch if the merge succeeds.
+    pub fn try_merge_with_limits(&mut self, oplog: &ListOpLog, merge_frontier: &[LV], limits: &MergeLimits) -> Result<(), DTError> {
+        if merge_frontier.iter().any(|v| *v >= oplog.len()) {
+            return Err(DTError::UnknownVersion);
+        }
+
+        let check_every = limits.max_tracker_entries.map_or(usize::MAX, |max| (max / 8).max(1));
+        let mut since_check = 0;
+        let mut content_growth = 0;
+
+        let mut scratch = self.clone();
+        let mut iter = oplog.get_xf_operations_full(self.version.as_ref(), merge_frontier);
+        loop {
+            // Like MergeTask::run_for, the work before the first output is done in small steps so
+            // the tracker can be checked along the way.
+            let work = if let Some(applied) = iter.prepare_step(check_every.min(1000)) {
+                applied.max(1)
+            } else if let Some((_lv, origin_op, xf)) = iter.next() {
+                let len = origin_op.len();
+                if origin_op.kind == ListOpKind::Ins && xf != DeleteAlreadyHappened {
+                    content_growth += len;
+                    if limits.max_content_growth.is_some_and(|max| content_growth > max) {
+                        return Err(DTError::ResourceExhausted);
                     }
                 }
+                scratch.apply_xf_op(oplog, origin_op, xf);
+                len
+            } else { break; };
 
-                (_, DeleteAlreadyHappened) => {}, // Discard.
-
-                (ListOpKind::Del, BaseMoved(pos)) => {
-                    let del_end = pos + origin_op.len();
-                    debug_assert!(self.content.len_chars() >= del_end);
-                    // println!("Delete {}..{} (len {}) '{}'", del_start, del_end, mut_len, to.content.slice_chars(del_start..del_end).collect::<String>());
-                    self.content.remove(pos..del_end);
+            if let Some(max) = limits.max_tracker_entries {
+                since_check += work;
+                if since_check >= check_every {
+                    since_check = 0;
+                    if iter.tracker_entries() > max { return Err(DTError::ResourceExhausted); }
                 }
             }
         }
 
+        scratch.version = iter.into_frontier();
+        *self = scratch;
+        Ok(())
+    }
 
-        // dbg!(iter.count_range_tracker_size());
+    /// Variant of [`merge`](ListBranch::merge) which also returns the transformed operations
+    /// applied to the branch. This is used to send change events to subscribers.
+    pub(crate) fn merge_and_collect(&mut self, oplog: &ListOpLog, merge_frontier: &[LV]) -> Vec<TextOperation> {
+        let mut iter = oplog.get_xf_operations_full(self.version.as_ref(), merge_frontier);
+        let mut result = vec![];
+
+        for (_lv, origin_op, xf) in &mut iter {
+            if let BaseMoved(pos) = xf {
+                let mut op: TextOperation = (&origin_op, origin_op.get_content(&oplog.operation_ctx)).into();
+                op.loc.span = (pos..pos + origin_op.len()).into();
+                result.push(op);
+            }
+            self.apply_xf_op(oplog, origin_op, xf);
+        }
 
-        // let expect_v = oplog.cg.graph.find_dominators_2(self.version.as_ref(), merge_frontier);
         self.version = iter.into_frontier();
-        // println!("-> '{}' v {:?}", self.content.to_string(), self.version);
-        // assert_eq!(self.version, expect_v);
+        result
     }
 
-}
\ No newline at end of file
+    /// Variant of [`merge`](ListBranch::merge) which transforms independent regions of the
+    /// history on separate threads. This is only faster for large merges, where the history
+    /// regularly converges back to a single version.
+    #[cfg(feature = "parallel")]
+    pub fn merge_parallel(&mut self, oplog: &ListOpLog, merge_frontier: &[LV]) {
+        let (xf_ops, frontier) = xf_operations_parallel(&oplog.cg.graph, &oplog.cg.agent_assignment,
+                                                        &oplog.operation_ctx, &oplog.operations, oplog.tie_break,
+                                                        self.version.as_ref(), merge_frontier);
+
+        for (_lv, origin_op, xf) in xf_ops {
+            self.apply_xf_op(oplog, origin_op, xf);
+        }
+        self.version = frontier;
+    }
+
+    /// Variant of [`merge`](ListBranch::merge) which names the merged version using remote IDs.
+    ///
+    /// Returns an error (and leaves the branch untouched) if any of the named versions are not
+    /// known by the oplog.
+    pub fn merge_remote(&mut self, oplog: &ListOpLog, merge_frontier: &[RemoteVersion]) -> Result<(), VersionConversionError> {
+        let merge_frontier = oplog.cg.agent_assignment.try_remote_to_local_frontier(merge_frontier.iter().copied())?;
+        self.merge(oplog, merge_frontier.as_ref());
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+    use crate::causalgraph::agent_assignment::remote_ids::{RemoteVersion, RemoteVersionSpan, VersionConversionError};
+    use crate::list::{ListBranch, ListOpLog};
+    use crate::list::encoding::ENCODE_PATCH;
+    use crate::list::operation::TextOperation;
+    use crate::DTError;
+    use super::{MergeBudget, MergeLimits, MergeProgress, MergeSummary, MergeTask};
+    use alloc::vec;
+    use std::time::Duration;
+
+    #[test]
+    fn estimate_merge_cost() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "abc");
+        let b = oplog.add_insert(seph, 3, "def");
+
+        // Linear history fast-forwards.
+        let est = oplog.estimate_merge_cost(&[a], &[b]);
+        assert_eq!(est.new_ops, 3);
+        assert_eq!(est.conflict_ops, 0);
+        assert_eq!(oplog.estimate_merge_cost(&[b], &[a]).new_ops, 0);
+
+        // Concurrent changes need to be replayed.
+        let c = oplog.add_insert_at(mike, &[a], 0, "xx");
+        let est = oplog.estimate_merge_cost(&[b], &[c]);
+        assert_eq!(est.new_ops, 2);
+        assert!(est.conflict_ops >= 5);
+        assert!(est.tracker_size > 0);
+        assert!(est.tracker_moves > 0);
+    }
+
+    #[test]
+    fn dirty_ranges() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "hello world, how are you");
+        let mut branch = oplog.checkout(&[a]);
+
+        oplog.add_insert_at(mike, &[a], 0, "oh ");
+        oplog.add_delete_at(mike, &[a], 5..11);
+        oplog.add_insert_at(seph, &[a], 13, "bob ");
+        let b = oplog.add_insert_at(seph, &[a], 12, "!");
+        oplog.add_delete_at(seph, &[b], 17..21);
+
+        let dirty = branch.merge_into_with_dirty_ranges(&oplog, oplog.cg.version.as_ref());
+        assert_eq!(branch.content.to_string(), "oh hello,! bob how you");
+        assert_eq!(dirty, vec![0..3, 8..8, 9..10, 11..15, 18..18]);
+        assert_eq!(branch, oplog.checkout_tip());
+
+        // Nothing changed.
+        assert!(branch.merge_into_with_dirty_ranges(&oplog, &[a]).is_empty());
+    }
+
+    #[test]
+    fn merge_reporting() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "abc");
+        let b = oplog.add_insert_at(seph, &[a], 3, "def");
+        let c = oplog.add_insert_at(mike, &[a], 0, "xx");
+
+        // Merging linear history just fast-forwards.
+        let mut branch = ListBranch::new();
+        let stats = branch.merge_reporting(&oplog, &[b]);
+        assert_eq!((stats.ff_ops, stats.transformed_ops), (6, 0));
+
+        // Concurrent changes are transformed.
+        let stats = branch.merge_reporting(&oplog, &[c]);
+        assert_eq!(branch.content.to_string(), "xxabcdef");
+        assert!(stats.transformed_ops >= 2);
+        assert!(stats.peak_tracker_entries > 0);
+        assert!(stats.retreated_ops > 0);
+        assert_eq!(branch, oplog.checkout_tip());
+
+        // Nothing to merge.
+        assert_eq!(branch.merge_reporting(&oplog, &[c]).transformed_ops, 0);
+    }
+
+    #[test]
+    fn simulate_merge() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "hello world");
+        let b = oplog.add_insert_at(seph, &[a], 11, "!");
+        let c = oplog.add_delete_at(mike, &[a], 0..6);
+        let c = oplog.add_insert_at(mike, &[c], 0, "yo");
+        let c = oplog.add_insert_at(mike, &[c], 7, "?");
+
+        let summary = oplog.simulate_merge(&[a], &[b]);
+        assert_eq!((summary.inserted, summary.deleted), (1, 0));
+        assert_eq!(summary.changed_regions.len(), 1);
+        assert_eq!(summary.changed_regions[0], 11..11);
+        assert!(!summary.has_conflicts);
+
+        // At b, the document is "hello world!".
+        let summary = oplog.simulate_merge(&[b], &[c]);
+        assert_eq!(summary, MergeSummary {
+            inserted: 3,
+            deleted: 6,
+            changed_regions: vec![0..6, 11..11],
+            has_conflicts: true,
+        });
+
+        assert_eq!(oplog.simulate_merge(&[c], &[a]), MergeSummary::default());
+    }
+
+    #[test]
+    fn merge_task() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "hello world");
+        let b = oplog.add_insert_at(seph, &[a], 11, " and goodbye");
+        let c = oplog.add_delete_at(mike, &[a], 0..6);
+        let c = oplog.add_insert_at(mike, &[c], 0, "yo");
+
+        let expected = oplog.checkout_tip();
+        for budget in [1, 3, 100] {
+            let mut branch = oplog.checkout(&[b]);
+            let mut task = MergeTask::new(&oplog, &[b], &[c]);
+            let mut ticks = 0;
+            while let MergeProgress::Pending { ops_done, ops_total } = task.run_for(MergeBudget::Ops(budget)) {
+                assert!(ops_done <= ops_total);
+                ticks += 1;
+            }
+            if budget == 1 { assert!(ticks > 1); }
+            assert_eq!(task.ops_done, task.ops_total);
+
+            // Nothing changes until the task is finished.
+            assert_eq!(branch, oplog.checkout(&[b]));
+            task.finish(&mut branch);
+            assert_eq!(branch, expected);
+        }
+
+        let mut branch = oplog.checkout(&[b]);
+        MergeTask::new(&oplog, &[b], &[c]).finish(&mut branch);
+        assert_eq!(branch, expected);
+
+        let mut task = MergeTask::new(&oplog, &[b], &[c]);
+        assert_eq!(task.run_for(MergeBudget::Time(Duration::from_secs(10))), MergeProgress::Complete);
+    }
+
+    #[test]
+    fn merge_with_limits() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "hello");
+        let b = oplog.add_insert_at(seph, &[a], 5, " world");
+        let c = oplog.add_insert_at(mike, &[a], 0, "yo ");
+        let c = oplog.add_delete_at(mike, &[c], 3..5);
+
+        let expected = oplog.checkout_tip();
+        let peak = oplog.checkout(&[b]).merge_reporting(&oplog, &[c]).peak_tracker_entries;
+
+        let mut branch = oplog.checkout(&[b]);
+        let too_small = [
+            MergeLimits { max_tracker_entries: Some(peak - 1), ..Default::default() },
+            MergeLimits { max_content_growth: Some(2), ..Default::default() },
+        ];
+        for limits in too_small {
+            assert_eq!(branch.try_merge_with_limits(&oplog, &[c], &limits), Err(DTError::ResourceExhausted));
+            assert_eq!(branch, oplog.checkout(&[b]));
+        }
+        assert_eq!(branch.try_merge_with_limits(&oplog, &[1000], &MergeLimits::default()), Err(DTError::UnknownVersion));
+
+        let limits = MergeLimits {
+            max_tracker_entries: Some(peak),
+            max_content_growth: Some(3),
+        };
+        branch.try_merge_with_limits(&oplog, &[c], &limits).unwrap();
+        assert_eq!(branch, expected);
+    }
+
+    #[test]
+    fn tracker_limit_is_checked_during_merge() {
+        // Two agents type concurrently, so merging builds a big tracker.
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let base = oplog.add_insert(seph, 0, "xx");
+        let (mut a, mut b) = (base, base);
+        for i in 0..200 {
+            a = oplog.add_insert_at(seph, &[a], i % 2, "a");
+            b = oplog.add_insert_at(mike, &[b], 2 + i - i % 2, "b");
+        }
+
+        let peak = oplog.checkout(&[a]).merge_reporting(&oplog, &[b]).peak_tracker_entries;
+        assert!(peak > 100);
+
+        let mut branch = oplog.checkout(&[a]);
+        let limits = MergeLimits { max_tracker_entries: Some(peak / 3), ..Default::default() };
+        assert_eq!(branch.try_merge_with_limits(&oplog, &[b], &limits), Err(DTError::ResourceExhausted));
+        assert_eq!(branch, oplog.checkout(&[a]));
+
+        let limits = MergeLimits { max_tracker_entries: Some(peak), ..Default::default() };
+        branch.try_merge_with_limits(&oplog, &[b], &limits).unwrap();
+        assert_eq!(branch, oplog.checkout_tip());
+    }
+
+    #[test]
+    fn remote_version_siblings() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert(seph, 0, "abc");
+        oplog.add_insert(mike, 3, "def");
+
+        // The xf operations are merged into a single insert, but need to be split by agent.
+        let ops = oplog.iter_xf_operations_from_remote(&[], &[RemoteVersion("mike", 2)])
+            .unwrap().collect::<Vec<_>>();
+        assert_eq!(ops, vec![
+            (RemoteVersionSpan("seph", (0..3).into()), Some(TextOperation::new_insert(0, "abc"))),
+            (RemoteVersionSpan("mike", (0..3).into()), Some(TextOperation::new_insert(3, "def"))),
+        ]);
+
+        let from = [RemoteVersion("seph", 2)];
+        let ops = oplog.iter_range_since_remote(&from).unwrap().collect::<Vec<_>>();
+        assert_eq!(ops, vec![TextOperation::new_insert(3, "def")]);
+
+        let mut branch = ListBranch::new();
+        branch.merge_remote(&oplog, &from).unwrap();
+        assert_eq!(branch.content().to_string(), "abc");
+        assert_eq!(oplog.checkout_remote(&from).unwrap(), branch);
+
+        assert_eq!(branch.merge_remote(&oplog, &[RemoteVersion("fred", 0)]),
+                   Err(VersionConversionError::UnknownAgent));
+        assert_eq!(branch.content().to_string(), "abc");
+
+        let patch = oplog.encode_from_remote(ENCODE_PATCH, &from).unwrap();
+        assert_eq!(patch, oplog.encode_from(ENCODE_PATCH, &[2]));
+    }
+
+    #[test]
+    fn xf_operations_ref() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert(seph, 0, "hi there");
+        oplog.add_insert_at(mike, &[], 0, "yo ");
+        oplog.add_delete_without_content(seph, 0..3);
+
+        let borrowed = oplog.iter_xf_operations_ref()
+            .map(|(range, op)| (range, op.map(|op| op.to_owned())))
+            .collect::<Vec<_>>();
+        assert_eq!(borrowed, oplog.iter_xf_operations().collect::<Vec<_>>());
+        assert!(oplog.iter_xf_operations_ref().any(|(_, op)| op.and_then(|op| op.content) == Some("yo ")));
+    }
+}
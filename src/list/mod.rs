@@ -5,11 +5,13 @@
 //! Currently this code only supports lists of unicode characters (text documents). Support for
 //! more data types will be added over time.
 
+use alloc::vec::Vec;
 use smartstring::alias::String as SmartString;
 
 use crate::list::operation::ListOpKind;
+use crate::list::tie_break::TieBreak;
 use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
-use crate::{CausalGraph, Frontier};
+use crate::{CausalGraph, DTRange, Frontier};
 use crate::rle::{KVPair, RleVec};
 
 pub mod operation;
@@ -22,8 +24,53 @@ mod oplog;
 mod branch;
 pub mod encoding;
 pub mod op_metrics;
+mod content_buf;
+mod content_intern;
 mod eq;
 mod oplog_merge;
+pub mod presence;
+pub mod transaction;
+mod checkout_cache;
+mod time_travel;
+pub mod subscribe;
+pub mod repo;
+pub mod tie_break;
+pub mod protocol;
+pub mod broadcast;
+pub mod pending;
+pub mod edit_buffer;
+pub mod text_buffer;
+pub mod lsp;
+pub mod op_index;
+pub mod anchor;
+pub mod marks;
+pub mod suggestions;
+pub mod crdt_items;
+pub mod timeline;
+pub mod replay;
+pub mod semantic_diff;
+pub mod append_log;
+pub mod value_list;
+pub mod snapshot;
+pub mod read_txn;
+pub mod retention;
+pub mod redaction;
+pub mod access;
+pub mod squash;
+pub mod converge;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+pub(crate) mod content_tags;
+pub mod agent_meta;
+pub mod agent_links;
+#[cfg(feature = "storage")]
+pub mod storage;
+#[cfg(feature = "history_json")]
+pub mod history_json;
+#[cfg(feature = "trace_export")]
+pub mod trace_export;
+#[cfg(feature = "graphemes")]
+pub mod graphemes;
 
 #[cfg(any(test, feature = "gen_test_data"))]
 mod old_fuzzer_tools;
@@ -38,6 +85,9 @@ mod merge;
 mod gen_random;
 #[cfg(feature = "gen_test_data")]
 pub use gen_random::gen_oplog;
+pub use content_buf::ContentStore;
+pub use branch::BranchContent;
+pub use merge::{MergeBudget, MergeCostEstimate, MergeLimits, MergeProgress, MergeStats, MergeSummary, MergeTask};
 
 // TODO!
 // trait InlineReplace<T> {
@@ -67,7 +117,10 @@ pub use gen_random::gen_oplog;
 /// Branches also provide a simple way to edit documents, via the [`insert`](Branch::insert) and
 /// [`delete`](Branch::delete) methods. These methods append new operations to the oplog, and modify
 /// the branch to contain the named changes.
-#[derive(Debug, Clone, Eq, PartialEq)]
+///
+/// Branches are `Send` and `Sync`, so a branch can be read from several threads at once. See
+/// [`BranchContent`].
+#[derive(Debug, Clone)]
 pub struct ListBranch {
     /// The version the branch is currently at. This is used to track which changes the branch has
     /// or has not locally merged.
@@ -77,9 +130,21 @@ pub struct ListBranch {
     version: Frontier,
 
     /// The document's content.
-    content: jumprope::JumpRopeBuf,
+    content: BranchContent,
+
+    /// Shared with snapshots of the branch. This is None until the first snapshot is taken. All
+    /// edits to content must go through insert_content and remove_content to keep it in sync.
+    snapshot_base: Option<snapshot::SnapshotBase>,
+}
+
+impl PartialEq for ListBranch {
+    fn eq(&self, other: &Self) -> bool {
+        self.version == other.version && self.content == other.content
+    }
 }
 
+impl Eq for ListBranch {}
+
 /// An OpLog is a collection of Diamond Types operations, stored in a super fancy compact way. Each
 /// operation has a number of fields:
 ///
@@ -105,6 +170,9 @@ pub struct ListBranch {
 ///
 /// Well, it should. The public API is still a work in progress. I'm going to be tweaking method
 /// names and things a fair bit before we hit 1.0.
+///
+/// OpLogs are `Send` and `Sync`. So a server can share a single oplog (eg in an `Arc<RwLock<_>>`)
+/// and check out, encode or transform changes from multiple threads at once.
 #[derive(Debug, Clone)]
 pub struct ListOpLog {
     /// The ID of the document (if any). This is useful if you want to give a document a GUID or
@@ -113,6 +181,10 @@ pub struct ListOpLog {
     /// Optional - only used if you set it.
     doc_id: Option<SmartString>,
 
+    /// How concurrent inserts at the same location are ordered. This is set when the oplog is
+    /// created, and stored in the oplog's encoding.
+    tie_break: TieBreak,
+
     pub cg: CausalGraph,
 
     /// This contains all content ever inserted into the document, in time order (not document
@@ -121,6 +193,43 @@ pub struct ListOpLog {
     // TODO: Replace me with a compact form of this data.
     pub(crate) operations: RleVec<KVPair<ListOpMetrics>>,
 
+    /// For each entry in operations, the total change in document length from all the operations
+    /// before it. This is used by [`len_at`](ListOpLog::len_at).
+    pub(crate) len_prefix: Vec<isize>,
+
+    /// Snapshots used by [`checkout_cached`](ListOpLog::checkout_cached). None unless the cache
+    /// has been enabled.
+    checkout_cache: Option<checkout_cache::CheckoutCache>,
+
+    /// The maximum length of each stored operation. See
+    /// [`set_max_op_len`](ListOpLog::set_max_op_len).
+    max_op_len: Option<usize>,
+
+    /// Range annotations. See [`marks`] for details.
+    pub(crate) marks: marks::Marks,
+
+    /// Operations flagged as suggestions. See [`suggestions`] for details.
+    pub(crate) suggestions: suggestions::Suggestions,
+
+    /// Metadata about each agent. See [`agent_meta`] for details.
+    pub(crate) agent_meta: agent_meta::AgentMeta,
+
+    /// Links between agents which belong to the same principal. See [`agent_links`].
+    pub(crate) agent_links: agent_links::AgentLinks,
+
+    /// Which deleted content is kept. See [`retention`] for details.
+    pub(crate) retention: retention::Retention,
+
+    /// Sorted ranges of operations whose content has been redacted. See [`redaction`].
+    pub(crate) redactions: Vec<DTRange>,
+
+    /// True if the oplog's content is encrypted. See `encryption` (behind the `encryption`
+    /// feature) for details.
+    pub(crate) content_encrypted: bool,
+
+    /// Authentication tags for encrypted content. See [`content_tags`].
+    pub(crate) content_tags: content_tags::ContentTags,
+
     // /// This is the LocalVersion for the entire oplog. So, if you merged every change we store into
     // /// a branch, this is the version of that branch.
     // ///
@@ -143,10 +252,15 @@ pub struct ListOpLog {
 ///   to figure out what the document looks like at any specified moment in time.
 /// - If you're interacting with a document with multiple branches, you'll probably want to
 ///   instantiate the oplog (and any visible branches) separately.
+///
+/// Like [`ListBranch`], a ListCRDT is `Send` and `Sync`.
 #[derive(Debug, Clone)]
 pub struct ListCRDT {
     pub branch: ListBranch,
     pub oplog: ListOpLog,
+
+    /// Callbacks registered with [`subscribe`](ListCRDT::subscribe).
+    subscribers: subscribe::Subscribers,
 }
 
 fn switch<T>(tag: ListOpKind, ins: T, del: T) -> T {
@@ -155,3 +269,43 @@ fn switch<T>(tag: ListOpKind, ins: T, del: T) -> T {
         ListOpKind::Del => del,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::list::{ListBranch, ListCRDT, ListOpLog};
+
+    #[test]
+    fn thread_safety() {
+        fn assert_send<T: Send>() {}
+        fn assert_sync<T: Sync>() {}
+        assert_send::<ListOpLog>();
+        assert_sync::<ListOpLog>();
+        assert_send::<ListBranch>();
+        assert_sync::<ListBranch>();
+        assert_send::<ListCRDT>();
+        assert_sync::<ListCRDT>();
+
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "hi there");
+
+        // Edit the document on another thread.
+        let mut doc = std::thread::spawn(move || {
+            doc.delete(seph, 2..8);
+            doc
+        }).join().unwrap();
+        doc.insert(seph, 2, "!");
+
+        // And read the oplog and branch from several threads at once.
+        let doc = &doc;
+        std::thread::scope(|s| {
+            let handles = (0..3).map(|i| s.spawn(move || match i {
+                0 => doc.oplog.checkout_tip().content().to_string(),
+                _ => doc.branch.content().to_string(),
+            })).collect::<Vec<_>>();
+            for h in handles {
+                assert_eq!(h.join().unwrap(), "hi!");
+            }
+        });
+    }
+}
@@ -0,0 +1,249 @@
+//! An index from positions in a document to the operations which inserted the content there.
+//!
+//! This is useful for blame views, anchoring comments to the edits which created some text, and
+//! figuring out which parts of a document need to be re-rendered. The index is updated
+//! incrementally as the oplog grows, so queries don't need to replay the document's history.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::fmt::{Debug, Formatter};
+use core::ops::Range;
+use core::pin::Pin;
+use content_tree::{ContentTreeRaw, RawPositionMetricsUsize};
+use rle::{AppendRle, HasLength, SplitableSpan};
+use crate::{DTRange, Frontier, LV};
+use crate::list::{ListBranch, ListCRDT, ListOpLog};
+use crate::list::operation::ListOpKind;
+use crate::listmerge::merge::TransformedResult::BaseMoved;
+use crate::rev_range::RangeRev;
+
+/// Maps each character in a document (at some version) to the local version of the insert
+/// operation which created it.
+///
+/// Only inserts are indexed. Deleted content has no position in the document, so delete
+/// operations never show up in query results.
+pub struct OpRangeIndex {
+    version: Frontier,
+
+    /// Each entry names the LVs of a run of adjacent characters in document order.
+    index: Pin<Box<ContentTreeRaw<RangeRev, RawPositionMetricsUsize>>>,
+}
+
+impl Debug for OpRangeIndex {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("OpRangeIndex")
+            .field("version", &self.version)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl Default for OpRangeIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OpRangeIndex {
+    /// Create a new (empty) index at the root version.
+    pub fn new() -> Self {
+        Self {
+            version: Frontier::root(),
+            index: ContentTreeRaw::new(),
+        }
+    }
+
+    /// Create an index of the document at the current version of the oplog.
+    pub fn new_at_tip(oplog: &ListOpLog) -> Self {
+        let mut index = Self::new();
+        index.update(oplog);
+        index
+    }
+
+    /// The version of the document the index describes.
+    pub fn local_frontier_ref(&self) -> &[LV] { self.version.as_ref() }
+
+    /// The length of the indexed document, in unicode characters.
+    pub fn len(&self) -> usize {
+        self.index.offset_len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bring the index up to date with all the operations in the oplog.
+    pub fn update(&mut self, oplog: &ListOpLog) {
+        self.merge(oplog, oplog.cg.version.as_ref());
+    }
+
+    /// Add the operations in merge_frontier to the index. This works the same way as
+    /// [`ListBranch::merge`](crate::list::ListBranch::merge).
+    pub fn merge(&mut self, oplog: &ListOpLog, merge_frontier: &[LV]) {
+        let mut iter = oplog.get_xf_operations_full(self.version.as_ref(), merge_frontier);
+
+        for (lv, origin_op, xf) in &mut iter {
+            let BaseMoved(pos) = xf else { continue; };
+            let len = origin_op.len();
+
+            match origin_op.kind {
+                ListOpKind::Ins => {
+                    self.index.insert_at_offset(pos, RangeRev {
+                        span: (lv..lv + len).into(),
+                        fwd: origin_op.loc.fwd,
+                    });
+                }
+                ListOpKind::Del => {
+                    self.index.delete_at_offset(pos, len);
+                }
+            }
+        }
+
+        self.version = iter.into_frontier();
+    }
+
+    /// Get the LV of the insert which created the character at pos.
+    ///
+    /// # Panics
+    ///
+    /// Panics if pos is past the end of the document.
+    pub fn lv_at(&self, pos: usize) -> LV {
+        assert!(pos < self.len(), "Position {} is past the end of the document", pos);
+        let cursor = self.index.cursor_at_offset_pos(pos, false);
+        let entry = cursor.get_raw_entry();
+        if entry.fwd {
+            entry.span.start + cursor.offset
+        } else {
+            entry.span.last() - cursor.offset
+        }
+    }
+
+    /// Get the (local) versions of the operations which inserted the characters in the specified
+    /// range of the document. The result is sorted and run-length encoded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range extends past the end of the document.
+    pub fn ops_affecting(&self, range: Range<usize>) -> Vec<DTRange> {
+        assert!(range.end <= self.len(), "Range {:?} extends past the end of the document", range);
+        let mut result: Vec<DTRange> = vec![];
+        if range.is_empty() { return result; }
+
+        let mut cursor = self.index.cursor_at_offset_pos(range.start, false);
+        let mut remaining = range.len();
+        let offset = cursor.offset;
+        let mut first = cursor.next().unwrap();
+        first.truncate_keeping_right(offset);
+
+        let mut entry = first;
+        loop {
+            if entry.len() > remaining { entry.truncate(remaining); }
+            remaining -= entry.len();
+            result.push(entry.span);
+            if remaining == 0 { break; }
+            entry = cursor.next().unwrap();
+        }
+
+        result.sort_unstable_by_key(|span| span.start);
+        let mut merged = Vec::with_capacity(result.len());
+        for span in result {
+            merged.push_rle(span);
+        }
+        merged
+    }
+}
+
+impl ListBranch {
+    /// Get the LV of the insert which created the character at pos in this branch. This is the
+    /// inverse of [`resolve_anchor`](ListBranch::resolve_anchor): the character can be found again
+    /// at any later version using [`Anchor::before`](crate::list::anchor::Anchor::before).
+    ///
+    /// This replays the branch's history each time it's called. Use an [`OpRangeIndex`] to look
+    /// up lots of positions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if pos is past the end of the branch.
+    pub fn lv_at_position(&self, oplog: &ListOpLog, pos: usize) -> LV {
+        let mut index = OpRangeIndex::new();
+        index.merge(oplog, self.version.as_ref());
+        index.lv_at(pos)
+    }
+}
+
+impl ListCRDT {
+    /// Get the LV of the insert which created the character at pos in the document. See
+    /// [`ListBranch::lv_at_position`].
+    pub fn lv_at_position(&self, pos: usize) -> LV {
+        self.branch.lv_at_position(&self.oplog, pos)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::{ListCRDT, ListOpLog};
+    use crate::list::anchor::Anchor;
+    use crate::list::op_index::OpRangeIndex;
+
+    fn check_index(oplog: &ListOpLog, index: &OpRangeIndex) {
+        let content = oplog.checkout(index.local_frontier_ref()).content().to_string();
+        assert_eq!(index.len(), content.chars().count());
+
+        for (pos, c) in content.chars().enumerate() {
+            let lv = index.lv_at(pos);
+            assert_eq!(index.ops_affecting(pos..pos + 1), vec![(lv..lv + 1).into()]);
+
+            let (op, op_content) = oplog.iter_range_simple((lv..lv + 1).into()).next().unwrap();
+            assert_eq!(op.0, lv);
+            assert_eq!(op_content.unwrap().chars().next(), Some(c));
+        }
+    }
+
+    #[test]
+    fn index_tracks_inserts() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let mut index = OpRangeIndex::new();
+
+        let a = oplog.add_insert(seph, 0, "hello world");
+        index.update(&oplog);
+        check_index(&oplog, &index);
+        assert_eq!(index.ops_affecting(2..5), vec![(2..5).into()]);
+
+        // Concurrent edits, merged incrementally.
+        oplog.add_delete_at(seph, &[a], 0..6);
+        let b = oplog.add_insert(seph, 0, "yo ");
+        let c = oplog.add_insert_at(mike, &[a], 5, "!!");
+        index.merge(&oplog, &[b]);
+        check_index(&oplog, &index);
+        index.merge(&oplog, &[c]);
+        check_index(&oplog, &index);
+
+        // Content inserted in reverse order.
+        for _ in 0..3 { oplog.add_insert(mike, 3, "x"); }
+        index.update(&oplog);
+        check_index(&oplog, &index);
+
+        // "yo xxx!!world"
+        assert_eq!(index.ops_affecting(0..index.len()), vec![(6..11).into(), (17..25).into()]);
+        assert_eq!(index.ops_affecting(4..9), vec![(6..7).into(), (20..24).into()]);
+        assert_eq!(index.ops_affecting(1..1), vec![]);
+    }
+
+    #[test]
+    fn lv_at_position() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "hello world");
+        doc.delete_without_content(seph, 0..6);
+        doc.insert(seph, 5, "!");
+
+        // "world!"
+        assert_eq!(doc.lv_at_position(0), 6);
+        assert_eq!(doc.lv_at_position(5), 17);
+        for pos in 0..doc.len() {
+            let anchor = Anchor::before(doc.lv_at_position(pos));
+            assert_eq!(doc.resolve_anchor(anchor), Some(pos));
+        }
+    }
+}
@@ -1,7 +1,9 @@
+use alloc::vec::Vec;
 use smallvec::SmallVec;
 use rle::{HasLength, SplitableSpan, SplitableSpanCtx};
 use rle::zip::{rle_zip, rle_zip3};
-use crate::causalgraph::agent_span::AgentSpan;
+use crate::causalgraph::agent_assignment::remote_ids::{RemoteVersion, VersionConversionError};
+use crate::causalgraph::agent_span::{AgentSpan, AgentVersion};
 use crate::causalgraph::entry::CGEntry;
 use crate::causalgraph::graph::GraphEntrySimple;
 use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
@@ -170,6 +172,13 @@ impl ListOpLog {
             .map(|pair| (pair.0.1, pair.1).into())
     }
 
+    /// Variant of [`iter_range_since`](ListOpLog::iter_range_since) which names the starting
+    /// version using remote IDs.
+    pub fn iter_range_since_remote(&self, remote_version: &[RemoteVersion]) -> Result<impl Iterator<Item=TextOperation> + '_, VersionConversionError> {
+        let local_version = self.cg.agent_assignment.try_remote_to_local_frontier(remote_version.iter().copied())?;
+        Ok(self.iter_range_since(local_version.as_ref()))
+    }
+
     pub(crate) fn iter_fast(&self) -> OpMetricsWithContent {
         OpMetricsWithContent::new(self, (0..self.len()).into())
     }
@@ -177,6 +186,23 @@ impl ListOpLog {
     pub fn iter(&self) -> impl Iterator<Item=TextOperation> + '_ {
         self.iter_fast().map(|pair| (pair.0.1, pair.1).into())
     }
+
+    /// Look up the original (untransformed) operation at a single local version. Returns the
+    /// operation (which always has a length of 1), the agent and sequence number which made it,
+    /// and its parents. Returns None if the version isn't in the oplog.
+    ///
+    /// This takes O(log n) time, so it's suitable for random access from debuggers or audit tools.
+    /// Use [`iter_full`](ListOpLog::iter_full) to read runs of operations.
+    pub fn op_at(&self, lv: LV) -> Option<(TextOperation, AgentVersion, Frontier)> {
+        if lv >= self.len() { return None; }
+
+        let (KVPair(_, metrics), content) = self.iter_range_simple((lv..lv + 1).into()).next()?;
+        Some((
+            (metrics, content).into(),
+            self.lv_to_agent_version(lv),
+            self.parents_at_version(lv),
+        ))
+    }
 }
 
 
@@ -198,7 +224,7 @@ impl ListOpLog {
 
             let op: TextOperation = (pair.1, content).into();
 
-            rle_zip3(simple_splits, aa, std::iter::once(op))
+            rle_zip3(simple_splits, aa, core::iter::once(op))
         })
     }
 
@@ -254,8 +280,9 @@ mod test {
         }));
 
         let ctx = ListOperationCtx {
-            ins_content: "0123456789".to_string().into_bytes(),
-            del_content: "".to_string().into_bytes()
+            ins_content: "0123456789".to_string().into_bytes().into(),
+            del_content: "".to_string().into_bytes().into(),
+            ..Default::default()
         };
 
         assert_eq!(OpMetricsIter::new(&ops, &ctx, (0..30).into()).collect::<Vec<_>>(), ops.0.as_slice());
@@ -280,6 +307,22 @@ mod test {
         ]);
     }
 
+    #[test]
+    fn op_at() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert(seph, 0, "hi there");
+        oplog.add_delete_at(mike, &[2], 0..1);
+        oplog.add_insert(seph, 7, "!");
+
+        assert_eq!(oplog.op_at(1), Some((TextOperation::new_insert(1, "i"), (seph, 1), Frontier::new_1(0))));
+        assert_eq!(oplog.op_at(0).unwrap().2, Frontier::root());
+        assert_eq!(oplog.op_at(8), Some((TextOperation::new_delete(0..1), (mike, 0), Frontier::new_1(2))));
+        assert_eq!(oplog.op_at(9), Some((TextOperation::new_insert(7, "!"), (seph, 8), Frontier::from_sorted(&[7, 8]))));
+        assert_eq!(oplog.op_at(10), None);
+    }
+
     // #[test]
     // #[ignore]
     // fn test_file() {
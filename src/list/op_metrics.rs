@@ -1,4 +1,4 @@
-use std::fmt::{Debug, Formatter};
+use core::fmt::{Debug, Formatter};
 use rle::{HasLength, MergableSpan, SplitableSpan, SplitableSpanCtx};
 use crate::list::operation::{ListOpKind, TextOperation};
 use crate::list::operation::ListOpKind::*;
@@ -6,6 +6,8 @@ use crate::list::switch;
 use crate::dtrange::DTRange;
 use crate::rev_range::RangeRev;
 use crate::unicount::chars_to_bytes;
+use crate::list::content_buf::ContentBuf;
+use crate::list::content_intern::{ContentIndex, MIN_INTERNED_LEN};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
@@ -58,6 +60,14 @@ impl ListOpMetrics {
         })
     }
 
+    /// How much the operation changes the length of the document. This is negative for deletes.
+    pub(crate) fn len_delta(&self) -> isize {
+        match self.kind {
+            ListOpKind::Ins => self.len() as isize,
+            ListOpKind::Del => -(self.len() as isize),
+        }
+    }
+
     pub(crate) fn to_operation(&self, ctx: &ListOperationCtx) -> TextOperation {
         let content = self.get_content(ctx);
         (self, content).into()
@@ -70,51 +80,82 @@ impl HasLength for ListOpMetrics {
     }
 }
 
-#[derive(Clone, Eq, PartialEq, Default)]
+#[derive(Clone, Default)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub(crate) struct ListOperationCtx {
-    pub(crate) ins_content: Vec<u8>,
-    pub(crate) del_content: Vec<u8>,
+    pub(crate) ins_content: ContentBuf,
+    pub(crate) del_content: ContentBuf,
+
+    /// Long inserted strings, so repeated inserts can share their content. See
+    /// [`content_intern`](crate::list::content_intern).
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) interned: ContentIndex,
 }
 
+// The interning index is a cache, so it isn't compared.
+impl PartialEq for ListOperationCtx {
+    fn eq(&self, other: &Self) -> bool {
+        self.ins_content == other.ins_content && self.del_content == other.del_content
+    }
+}
+
+impl Eq for ListOperationCtx {}
+
 // Not using the derived Debug so we can from_utf8 the internal content.
 impl Debug for ListOperationCtx {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("ListOperationCtx")
             // We should be able to use from_utf8_unchecked here but its Debug, and I'd rather be
             // safe than sorry.
-            .field("ins_content", &std::str::from_utf8(&self.ins_content).unwrap())
-            .field("del_content", &std::str::from_utf8(&self.del_content).unwrap())
+            .field("ins_content", &core::str::from_utf8(&self.ins_content).unwrap())
+            .field("del_content", &core::str::from_utf8(&self.del_content).unwrap())
             .finish()
     }
 }
 
 impl ListOperationCtx {
     pub fn new() -> Self {
-        Self {
-            ins_content: Vec::new(),
-            del_content: Vec::new()
-        }
+        Self::default()
     }
 
     pub(crate) fn get_str(&self, kind: ListOpKind, range: DTRange) -> &str {
-        unsafe { std::str::from_utf8_unchecked(&self.switch(kind)[range.start..range.end]) }
+        let content = switch(kind, &self.ins_content, &self.del_content);
+        let bytes = content.slice(range);
+        if content.is_owned() {
+            // Owned content is only ever appended from valid strings.
+            unsafe { core::str::from_utf8_unchecked(bytes) }
+        } else {
+            // But content stores are implemented outside this crate, so their reads are checked.
+            core::str::from_utf8(bytes).expect("Content store returned invalid UTF-8")
+        }
     }
 
     // pub(crate) fn switch_str(&self, kind: InsDelTag) -> &str {
-    //     unsafe { std::str::from_utf8_unchecked(self.switch_bytes(kind)) }
+    //     unsafe { core::str::from_utf8_unchecked(self.switch_bytes(kind)) }
     //     // switch(tag, self.ins_content.as_str(), self.del_content.as_str())
     // }
 
-    pub(crate) fn switch(&self, kind: ListOpKind) -> &[u8] {
-        switch(kind, &self.ins_content, &self.del_content)
+    pub(crate) fn switch_mut(&mut self, kind: ListOpKind) -> &mut ContentBuf {
+        switch(kind, &mut self.ins_content, &mut self.del_content)
     }
 
-    pub(crate) fn switch_mut(&mut self, kind: ListOpKind) -> &mut Vec<u8> {
-        switch(kind, &mut self.ins_content, &mut self.del_content)
+    /// Truncate the inserted and deleted content to the specified lengths (in bytes).
+    pub(crate) fn truncate(&mut self, ins_len: usize, del_len: usize) {
+        self.ins_content.truncate(ins_len);
+        self.del_content.truncate(del_len);
+        self.interned.truncate(ins_len);
     }
 
     pub(crate) fn push_str(&mut self, kind: ListOpKind, s: &str) -> DTRange {
+        // Content referenced from a content store is left alone, so it doesn't get copied into
+        // memory.
+        if kind == Ins && s.len() >= MIN_INTERNED_LEN && self.ins_content.is_owned() {
+            let len = self.ins_content.len();
+            if let Some(range) = self.interned.find_or_insert(&self.ins_content, s.as_bytes(), len) {
+                return range;
+            }
+        }
+
         let storage = self.switch_mut(kind);
         let start = storage.len();
         storage.extend_from_slice(s.as_bytes());
@@ -307,8 +348,9 @@ mod test {
             kind: ListOpKind::Ins,
             content_pos: Some((0..10).into()),
         }, &ListOperationCtx {
-            ins_content: "0123456789".as_bytes().to_owned(),
-            del_content: "".as_bytes().to_owned()
+            ins_content: "0123456789".as_bytes().to_owned().into(),
+            del_content: "".as_bytes().to_owned().into(),
+            ..Default::default()
         });
 
         let s2 = "↯1↯3↯5↯7↯9";
@@ -317,8 +359,9 @@ mod test {
             kind: ListOpKind::Ins,
             content_pos: Some((0..s2.len()).into()),
         }, &ListOperationCtx {
-            ins_content: s2.as_bytes().to_owned(), // too easy? Maybe..
-            del_content: "".as_bytes().to_owned()
+            ins_content: s2.as_bytes().to_owned().into(), // too easy? Maybe..
+            del_content: "".as_bytes().to_owned().into(),
+            ..Default::default()
         });
 
         // I can't test the other splitablespan variants like this because they don't support
@@ -336,8 +379,9 @@ mod test {
 
         // let rem = op.truncate(2, "abcde");
         let rem = op.truncate_ctx(2, &ListOperationCtx {
-            ins_content: "".as_bytes().to_owned(),
-            del_content: "abcde".as_bytes().to_owned()
+            ins_content: "".as_bytes().to_owned().into(),
+            del_content: "abcde".as_bytes().to_owned().into(),
+            ..Default::default()
         });
 
         assert_eq!(op, ListOpMetrics {
@@ -359,8 +403,9 @@ mod test {
     fn split_around_unicode() {
         // The ¥ symbol is a 2-byte encoding. And ↯ is 3 bytes.
         let ctx = ListOperationCtx {
-            ins_content: "¥123↯".as_bytes().to_owned(),
-            del_content: "¥123↯".as_bytes().to_owned()
+            ins_content: "¥123↯".as_bytes().to_owned().into(),
+            del_content: "¥123↯".as_bytes().to_owned().into(),
+            ..Default::default()
         };
 
         let op = ListOpMetrics {
@@ -4,8 +4,8 @@
 ///
 /// Updates are made up of a series of insert / delete components, each at some position.
 
-use std::fmt::{Display, Formatter};
-use std::ops::Range;
+use core::fmt::{Display, Formatter};
+use core::ops::Range;
 use smartstring::alias::{String as SmartString};
 use rle::{HasLength, MergableSpan, SplitableSpanHelpers};
 use ListOpKind::*;
@@ -31,7 +31,7 @@ impl Default for ListOpKind {
 }
 
 impl Display for ListOpKind {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match self {
             Ins => f.write_str("Ins"),
             Del => f.write_str("Del")
@@ -154,6 +154,65 @@ impl TextOperation {
     }
 }
 
+/// A borrowed version of [`TextOperation`], with the content pointing into the oplog instead of
+/// being copied out. This is emitted by
+/// [`iter_xf_operations_ref_from`](crate::list::ListOpLog::iter_xf_operations_ref_from), so callers
+/// which just forward or inspect operations don't need to allocate for each one.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TextOperationRef<'a> {
+    /// The range of items in the document being modified by this operation.
+    pub loc: RangeRev,
+
+    /// Is this operation an insert or a delete?
+    pub kind: ListOpKind,
+
+    /// What content is being inserted or deleted, if known.
+    pub content: Option<&'a str>,
+}
+
+impl<'a> TextOperationRef<'a> {
+    pub fn range(&self) -> DTRange {
+        self.loc.span
+    }
+
+    #[inline]
+    pub fn start(&self) -> usize {
+        self.loc.span.start
+    }
+
+    #[inline]
+    pub fn end(&self) -> usize {
+        self.loc.span.end
+    }
+
+    /// Copy the content out into an owned [`TextOperation`].
+    pub fn to_owned(&self) -> TextOperation {
+        (*self).into()
+    }
+}
+
+impl<'a> HasLength for TextOperationRef<'a> {
+    fn len(&self) -> usize {
+        self.loc.len()
+    }
+}
+
+impl<'a> From<TextOperationRef<'a>> for TextOperation {
+    fn from(op: TextOperationRef<'a>) -> Self {
+        TextOperation {
+            loc: op.loc,
+            kind: op.kind,
+            content: op.content.map(|str| str.into())
+        }
+    }
+}
+
+impl<'a> From<(ListOpMetrics, Option<&'a str>)> for TextOperationRef<'a> {
+    fn from((op, content): (ListOpMetrics, Option<&'a str>)) -> Self {
+        TextOperationRef { loc: op.loc, kind: op.kind, content }
+    }
+}
+
 impl SplitableSpanHelpers for TextOperation {
     fn truncate_h(&mut self, at: usize) -> Self {
         // let (self_span, other_span) = TimeSpanRev::split_op_span(self.span, self.tag, at);
@@ -1,16 +1,21 @@
-use std::ops::Range;
-use rle::{HasLength, SplitableSpan};
-use crate::{AgentId, Frontier, LV};
+use alloc::string::String;
+use core::ops::Range;
+use rle::{HasLength, MergableSpan, SplitableSpan, SplitableSpanCtx};
+use crate::{AgentId, DTError, Frontier, LV};
 use crate::list::{ListBranch, ListOpLog};
 use crate::causalgraph::graph::GraphEntrySimple;
 use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
 use crate::list::operation::{TextOperation, ListOpKind};
-use crate::causalgraph::agent_assignment::remote_ids::{RemoteFrontier, RemoteVersionSpan};
+use crate::list::tie_break::TieBreak;
+use crate::list::retention::DeletedContentPolicy;
+use crate::causalgraph::agent_assignment::remote_ids::{MissingOps, RemoteFrontier, RemoteVersion, RemoteVersionSpan, VersionConversionError};
 use crate::dtrange::DTRange;
 use crate::causalgraph::agent_span::*;
 use crate::rev_range::RangeRev;
-use crate::rle::KVPair;
+use crate::rle::{KVPair, RleVec};
 use crate::unicount::{chars_to_bytes, count_chars};
+#[cfg(feature = "version_hashes")]
+use crate::causalgraph::hash::VersionHashes;
 
 impl Default for ListOpLog {
     fn default() -> Self {
@@ -20,21 +25,81 @@ impl Default for ListOpLog {
 
 impl ListOpLog {
     pub fn new() -> Self {
+        Self::new_with_tie_break(TieBreak::default())
+    }
+
+    /// Create a new oplog which orders concurrent inserts using the specified strategy. See
+    /// [`TieBreak`] for details.
+    pub fn new_with_tie_break(tie_break: TieBreak) -> Self {
         Self {
             doc_id: None,
+            tie_break,
             cg: Default::default(),
             operation_ctx: ListOperationCtx::new(),
             operations: Default::default(),
+            len_prefix: vec![],
+            checkout_cache: None,
+            max_op_len: None,
+            marks: Default::default(),
+            suggestions: Default::default(),
+            agent_meta: Default::default(),
+            agent_links: Default::default(),
+            retention: Default::default(),
+            redactions: vec![],
+            content_encrypted: false,
+            content_tags: Default::default(),
             // inserted_content: "".to_string(),
         }
     }
 
+    /// The strategy this oplog uses to order concurrent inserts.
+    pub fn tie_break(&self) -> TieBreak {
+        self.tie_break
+    }
+
+    /// Limit the length of each operation the oplog stores. Long operations (eg, a big paste) are
+    /// split into chunks of at most `max_op_len` characters. The chunks are still contiguous, so
+    /// they're a single entry in the causal graph. Encoded files split operations the same way.
+    ///
+    /// Splitting huge operations up front makes them cheaper to split later, when they're merged
+    /// or partially synced. This only affects operations added after it's set. None (the default)
+    /// stores operations at any length.
+    ///
+    /// # Panics
+    ///
+    /// Panics if max_op_len is `Some(0)`.
+    pub fn set_max_op_len(&mut self, max_op_len: Option<usize>) {
+        assert_ne!(max_op_len, Some(0), "Operations must be at least 1 character long");
+        self.max_op_len = max_op_len;
+    }
+
+    /// The maximum length of stored operations. See [`set_max_op_len`](ListOpLog::set_max_op_len).
+    pub fn max_op_len(&self) -> Option<usize> {
+        self.max_op_len
+    }
+
     pub fn checkout(&self, local_version: &[LV]) -> ListBranch {
         let mut branch = ListBranch::new();
         branch.merge(self, local_version);
         branch
     }
 
+    /// Variant of [`checkout`](ListOpLog::checkout) which names the version using remote IDs.
+    ///
+    /// If the oplog doesn't contain the named version, this returns the ranges of changes from
+    /// each agent which are missing. A client can fetch exactly those changes, then try again. See
+    /// [`AgentAssignment::missing_remote_versions`](crate::causalgraph::agent_assignment::AgentAssignment::missing_remote_versions).
+    pub fn checkout_remote(&self, remote_version: &[RemoteVersion]) -> Result<ListBranch, MissingOps> {
+        let aa = &self.cg.agent_assignment;
+        match aa.try_remote_to_local_frontier(remote_version.iter().copied()) {
+            Ok(local_version) => Ok(self.checkout(local_version.as_ref())),
+            Err(_) => Err(aa.missing_remote_versions(remote_version)),
+        }
+    }
+
+    /// Check out the latest version of the document. This includes every operation, including
+    /// [suggestions](crate::list::suggestions). Use
+    /// [`checkout_mainline`](ListOpLog::checkout_mainline) to leave suggestions out.
     pub fn checkout_tip(&self) -> ListBranch {
         let mut branch = ListBranch::new();
         branch.merge(self, self.cg.version.as_ref());
@@ -45,6 +110,13 @@ impl ListOpLog {
         self.cg.agent_assignment.get_or_create_agent_id(name)
     }
 
+    /// Get the ID of the named agent, creating it if needed. Unlike
+    /// [`get_or_create_agent_id`](ListOpLog::get_or_create_agent_id), this returns an error
+    /// instead of panicking if the name is invalid.
+    pub fn try_get_or_create_agent_id(&mut self, name: &str) -> Result<AgentId, DTError> {
+        self.cg.agent_assignment.try_get_or_create_agent_id(name)
+    }
+
     pub(crate) fn get_agent_id(&self, name: &str) -> Option<AgentId> {
         self.cg.agent_assignment.get_agent_id(name)
     }
@@ -53,6 +125,12 @@ impl ListOpLog {
         self.cg.agent_assignment.get_agent_name(agent)
     }
 
+    /// Iterate over all the agents which have edited the document (or been created locally). The
+    /// iterator returns triples of (agent id, name, number of operations by that agent).
+    pub fn iter_agents(&self) -> impl Iterator<Item = (AgentId, &str, usize)> + '_ {
+        self.cg.agent_assignment.iter_agents()
+    }
+
     pub(crate) fn lv_to_agent_version(&self, lv: LV) -> AgentVersion {
         self.cg.agent_assignment.local_to_agent_version(lv)
     }
@@ -159,6 +237,9 @@ impl ListOpLog {
     pub(crate) fn push_op_internal(&mut self, next_time: LV, loc: RangeRev, kind: ListOpKind, content: Option<&str>) {
         // next_time should almost always be self.len - except when loading, or modifying the data
         // in some complex way.
+        let content = content.filter(|_| {
+            kind == ListOpKind::Ins || self.retention.policy != DeletedContentPolicy::KeepNone
+        });
         let content_pos = content.map(|c|
             self.operation_ctx.push_str(kind, c)
         );
@@ -167,11 +248,159 @@ impl ListOpLog {
         // } else { None };
 
         // self.operations.push(KVPair(next_time, c.clone()));
-        self.operations.push(KVPair(next_time, ListOpMetrics {
+        let mut entry = KVPair(next_time, ListOpMetrics {
             loc,
             kind,
             content_pos
-        }));
+        });
+
+        loop {
+            // Split the operation if it would make an entry longer than max_op_len.
+            let mut force_new_entry = false;
+            let mut rest = None;
+            if let Some(max) = self.max_op_len {
+                let appendable_len = self.operations.0.last()
+                    .filter(|last| last.can_append(&entry))
+                    .map_or(0, |last| last.len());
+                let room = if appendable_len < max { max - appendable_len } else {
+                    force_new_entry = true;
+                    max
+                };
+                if entry.len() > room {
+                    let rest_op = entry.1.truncate_ctx(room, &self.operation_ctx);
+                    rest = Some(KVPair(entry.0 + room, rest_op));
+                }
+            }
+
+            if force_new_entry {
+                self.operations.0.push(entry);
+            } else {
+                self.operations.push(entry);
+            }
+
+            let num_entries = self.operations.num_entries();
+            if num_entries > self.len_prefix.len() {
+                // The operation couldn't be appended to the previous entry.
+                let prefix = num_entries.checked_sub(2).map_or(0, |i| {
+                    self.len_prefix[i] + self.operations.0[i].1.len_delta()
+                });
+                self.len_prefix.push(prefix);
+            }
+
+            match rest {
+                Some(r) => entry = r,
+                None => break,
+            }
+        }
+    }
+
+    /// Rebuild the stored content of the `kind` operations in range, replacing each operation's
+    /// content with the result of `replace` (None to drop it). `replace` is passed the local
+    /// versions of the operations along with their content. Entries which overlap the edges of the
+    /// range are split. Returns false (and does nothing) if no operations in the range have
+    /// content.
+    pub(crate) fn rewrite_content(&mut self, range: DTRange, kind: ListOpKind, mut replace: impl FnMut(DTRange, &str) -> Option<String>) -> bool {
+        let has_content = |KVPair(_, op): &KVPair<ListOpMetrics>| {
+            op.kind == kind && op.content_pos.is_some()
+        };
+        let overlaps = |KVPair(start, op): &KVPair<ListOpMetrics>| {
+            *start < range.end && start + op.len() > range.start
+        };
+        if !self.operations.0.iter().any(|e| has_content(e) && overlaps(e)) { return false; }
+
+        // The content is copied into a new buffer. Content of the other kind is left alone.
+        let ctx = &self.operation_ctx;
+        let mut new_ctx = ListOperationCtx::new();
+        let mut operations = RleVec::new();
+        for entry in self.operations.0.iter() {
+            if !has_content(entry) {
+                operations.0.push(entry.clone());
+                continue;
+            }
+
+            let mut entry = entry.clone();
+            loop {
+                let rest = [range.start, range.end].into_iter()
+                    .find(|&v| v > entry.0 && v < entry.0 + entry.len())
+                    .map(|v| KVPair(v, entry.1.truncate_ctx(v - entry.0, ctx)));
+
+                let content = ctx.get_str(kind, entry.1.content_pos.unwrap());
+                entry.1.content_pos = if range.contains(entry.0) {
+                    replace((entry.0..entry.0 + entry.len()).into(), content)
+                        .map(|c| new_ctx.push_str(kind, &c))
+                } else {
+                    Some(new_ctx.push_str(kind, content))
+                };
+                operations.0.push(entry);
+
+                match rest {
+                    Some(r) => entry = r,
+                    None => break,
+                }
+            }
+        }
+
+        // Splitting adds entries, so the length prefix needs to be rebuilt.
+        let mut prefix = 0;
+        self.len_prefix = operations.0.iter().map(|KVPair(_, op)| {
+            let p = prefix;
+            prefix += op.len_delta();
+            p
+        }).collect();
+        self.operations = operations;
+        *self.operation_ctx.switch_mut(kind) = core::mem::take(new_ctx.switch_mut(kind));
+        if kind == ListOpKind::Ins {
+            self.operation_ctx.interned = new_ctx.interned;
+        }
+        true
+    }
+
+    /// The total change in document length from all the operations before lv.
+    fn len_delta_before(&self, lv: LV) -> isize {
+        if lv == 0 { return 0; }
+        let idx = self.operations.find_index(lv - 1).unwrap();
+        let KVPair(start, op) = &self.operations.0[idx];
+        let offset = (lv - start) as isize;
+        self.len_prefix[idx] + if op.kind == ListOpKind::Ins { offset } else { -offset }
+    }
+
+    /// Get the length of the document (in unicode characters) at some version, without checking
+    /// it out. This is fast, since the oplog keeps a running total of the length changes made by
+    /// its operations.
+    ///
+    /// If the version merges concurrent deletes of the same content, each delete is counted, so
+    /// the result will be smaller than the real length of the document.
+    pub fn len_at(&self, version: &[LV]) -> usize {
+        let (not_in_version, _) = self.cg.graph.diff(self.cg.version.as_ref(), version);
+        let len = not_in_version.iter().fold(self.len_delta_before(self.len()), |len, span| {
+            len - (self.len_delta_before(span.end) - self.len_delta_before(span.start))
+        });
+        len.max(0) as usize
+    }
+
+    /// Check that a run of operations made at version `parents` fits in the document. Each insert
+    /// must be within the document, and each delete must remove content which exists.
+    pub(crate) fn check_op_bounds<I>(&self, parents: &[LV], ops: impl Fn() -> I) -> Result<(), DTError>
+        where I: Iterator<Item=(ListOpKind, DTRange)>
+    {
+        fn fits(mut doc_len: usize, ops: impl Iterator<Item=(ListOpKind, DTRange)>) -> bool {
+            for (kind, span) in ops {
+                match kind {
+                    ListOpKind::Ins if span.start <= doc_len => doc_len += span.len(),
+                    ListOpKind::Del if span.end <= doc_len => doc_len -= span.len(),
+                    _ => return false,
+                }
+            }
+            true
+        }
+
+        // len_at can be too small when concurrent deletes overlap. So if an operation looks out
+        // of bounds, check again using the real length of the document.
+        if fits(self.len_at(parents), ops()) || fits(self.checkout(parents).len(), ops()) {
+            Ok(())
+        } else {
+            Err(DTError::OpOutOfBounds)
+        }
     }
 
     /// Push new operations to the opset. Operation parents specified by parents parameter.
@@ -241,6 +470,36 @@ impl ListOpLog {
         new_lv_range
     }
 
+    /// Variant of [`add_operations_remote`](ListOpLog::add_operations_remote) for changes from
+    /// untrusted peers. The change is checked before anything is added to the oplog, and an error
+    /// is returned if it's invalid.
+    pub fn try_add_operations_remote(&mut self, agent: AgentId, parents: &[LV], start_seq: usize, ops: &[TextOperation]) -> Result<DTRange, DTError> {
+        if agent as usize >= self.cg.agent_assignment.client_data.len() {
+            return Err(DTError::UnknownAgent);
+        }
+        if parents.iter().any(|&p| p >= self.len()) {
+            return Err(DTError::UnknownVersion);
+        }
+
+        let mut len: usize = 0;
+        for op in ops {
+            let valid = !op.is_empty() && match (op.kind, &op.content) {
+                (_, Some(content)) if count_chars(content) != op.len() => false,
+                (ListOpKind::Ins, None) => false,
+                (ListOpKind::Ins, _) => op.loc.fwd,
+                (ListOpKind::Del, _) => true,
+            };
+            if !valid { return Err(DTError::InvalidOperation); }
+            len += op.len();
+        }
+        start_seq.checked_add(len).ok_or(DTError::InvalidOperation)?;
+
+        // The parents might be unsorted, or name versions which dominate one another.
+        let parents = self.cg.graph.find_dominators(parents);
+        self.check_op_bounds(parents.as_ref(), || ops.iter().map(|op| (op.kind, op.loc.span)))?;
+        Ok(self.add_operations_remote(agent, parents.as_ref(), start_seq, ops))
+    }
+
     /// Push new operations to the opset. Operation parents specified by parents parameter.
     ///
     /// Returns the single item version after merging. (The resulting LocalVersion after calling
@@ -319,7 +578,7 @@ impl ListOpLog {
     /// Add a local delete operation to the oplog. This variant of the method allows a user to pass
     /// the content of the delete into the oplog. This can be useful for undos and things like that
     /// but it is NOT CHECKED. If you don't have access to the deleted content, use
-    /// [`add_delete_without_content`](OpLog::add_delete_without_content) instead.
+    /// [`add_delete_without_content`](ListOpLog::add_delete_without_content) instead.
     ///
     /// If you have a local branch, its easier, faster, and safer to just call
     /// [`branch.delete(agent, pos, len)`](Branch::delete).
@@ -338,6 +597,44 @@ impl ListOpLog {
         self.add_operations(agent, &[TextOperation::new_delete(loc)])
     }
 
+    /// Import a linear editing trace from a single agent. Each patch is a tuple of (position,
+    /// delete length, inserted content), applied in order. Either the delete length or the
+    /// inserted content may be empty.
+    ///
+    /// This is equivalent to calling [`add_delete_without_content`](ListOpLog::add_delete_without_content)
+    /// and [`add_insert`](ListOpLog::add_insert) for each patch, but its much faster because all the
+    /// imported changes are assigned to the agent (and added to the causal graph) in one step.
+    ///
+    /// Returns the span of local versions assigned to the imported changes.
+    pub fn import_linear_trace<I, S>(&mut self, agent: AgentId, patches: I) -> DTRange
+        where I: IntoIterator<Item = (usize, usize, S)>, S: AsRef<str>
+    {
+        let patches = patches.into_iter();
+        self.operations.0.reserve(patches.size_hint().0);
+
+        let start = self.len();
+        let mut next_time = start;
+
+        for (pos, del_len, ins_content) in patches {
+            if del_len > 0 {
+                self.push_op_internal(next_time, (pos..pos + del_len).into(), ListOpKind::Del, None);
+                next_time += del_len;
+            }
+
+            let ins_content = ins_content.as_ref();
+            if !ins_content.is_empty() {
+                let len = count_chars(ins_content);
+                self.push_op_internal(next_time, (pos..pos + len).into(), ListOpKind::Ins, Some(ins_content));
+                next_time += len;
+            }
+        }
+
+        if next_time > start {
+            self.cg.assign_local_op(agent, next_time - start);
+        }
+        (start..next_time).into()
+    }
+
     /// Iterate through history entries
     pub fn iter_history(&self) -> impl Iterator<Item =GraphEntrySimple> + '_ {
         self.cg.graph.iter()
@@ -395,6 +692,7 @@ impl ListOpLog {
             .map(|item| self.cg.agent_assignment.agent_span_to_remote(item.1))
     }
 
+    #[cfg(feature = "std")]
     pub fn print_stats(&self, detailed: bool) {
         self.operations.print_stats("Operations", detailed);
 
@@ -478,6 +776,35 @@ impl ListOpLog {
         self.cg.graph.parents_at_version(lv)
     }
 
+    /// Compute content-addressed hashes for every version in the oplog. Unlike
+    /// [`CausalGraph::version_hashes`](crate::CausalGraph::version_hashes), these hashes also
+    /// cover each operation's kind, position and (for inserts) content.
+    ///
+    /// Note inserts stored without their content will hash differently from the same insert
+    /// stored with content.
+    #[cfg(feature = "version_hashes")]
+    pub fn version_hashes(&self) -> VersionHashes {
+        let mut ops = self.iter_metrics();
+        let mut current = None;
+
+        self.cg.version_hashes_with(|_lv, out| {
+            // Operations are visited one version at a time, in LV order.
+            let mut op = current.take()
+                .unwrap_or_else(|| ops.next().unwrap().1);
+            if op.len() > 1 {
+                current = Some(op.truncate_ctx(1, &self.operation_ctx));
+            }
+
+            out.push(op.kind as u8);
+            out.extend_from_slice(&(op.start() as u64).to_le_bytes());
+            if op.kind == ListOpKind::Ins {
+                if let Some(content) = op.get_content(&self.operation_ctx) {
+                    out.extend_from_slice(content.as_bytes());
+                }
+            }
+        })
+    }
+
     pub(crate) fn estimate_cost(&self, op_range: DTRange) -> usize {
         if op_range.is_empty() { return 0; }
         else {
@@ -487,4 +814,155 @@ impl ListOpLog {
             end_idx - start_idx + 1
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use rle::HasLength;
+    use crate::DTError;
+    use crate::causalgraph::agent_assignment::remote_ids::{MissingOps, RemoteVersion, RemoteVersionSpanOwned};
+    use crate::list::ListOpLog;
+    use crate::list::encoding::ENCODE_FULL;
+    use crate::list::operation::TextOperation;
+
+    #[test]
+    fn max_op_len() {
+        let mut oplog = ListOpLog::new();
+        oplog.set_max_op_len(Some(4));
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "0123456789");
+        oplog.add_insert(seph, 10, "ab");
+        oplog.add_delete_without_content(seph, 0..6);
+        assert_eq!(oplog.operations.0.iter().map(|e| e.len()).collect::<Vec<_>>(), vec![4, 4, 4, 4, 2]);
+        assert_eq!(oplog.cg.graph.entries.num_entries(), 1);
+        assert_eq!(oplog.checkout_tip().content().to_string(), "6789ab");
+        assert_eq!(oplog.len_at(&[11]), 12);
+        oplog.dbg_check(true);
+
+        // Encoded files split operations the same way.
+        let data = oplog.encode(ENCODE_FULL);
+        let mut unlimited = ListOpLog::new();
+        unlimited.get_or_create_agent_id("seph");
+        unlimited.add_insert(seph, 0, "0123456789ab");
+        unlimited.add_delete_without_content(seph, 0..6);
+        assert!(data.len() > unlimited.encode(ENCODE_FULL).len());
+
+        let decoded = ListOpLog::load_from(&data).unwrap();
+        assert_eq!(decoded, oplog);
+        assert_eq!(decoded.checkout_tip().content().to_string(), "6789ab");
+    }
+
+    #[test]
+    fn len_at() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        assert_eq!(oplog.len_at(&[]), 0);
+        let a = oplog.add_insert(seph, 0, "hello world");
+        let b = oplog.add_delete_at(seph, &[a], 0..6);
+        let b = oplog.add_insert_at(seph, &[b], 5, "!!");
+        let c = oplog.add_insert_at(mike, &[a], 0, "yo ");
+        let c = oplog.add_delete_at(mike, &[c], 9..10);
+
+        for v in [vec![], vec![a], vec![a - 3], vec![b], vec![b - 1], vec![c], vec![b, c], vec![b - 1, c - 1]] {
+            assert_eq!(oplog.len_at(&v), oplog.checkout(&v).len(), "version {:?}", v);
+        }
+    }
+
+    #[test]
+    fn checkout_remote_reports_missing_ops() {
+        // This peer only has seph's change with seq 3, which is concurrent with seph's earlier
+        // changes.
+        let mut other = ListOpLog::new();
+        let seph = other.get_or_create_agent_id("seph");
+        other.add_operations_remote(seph, &[], 3, &[TextOperation::new_insert(0, "y")]);
+        assert_eq!(other.checkout_remote(&[RemoteVersion("seph", 3)]).unwrap().content().to_string(), "y");
+
+        let missing = other.checkout_remote(&[RemoteVersion("seph", 4), RemoteVersion("mike", 1), RemoteVersion("seph", 1)])
+            .unwrap_err();
+        assert_eq!(missing, MissingOps(vec![
+            RemoteVersionSpanOwned("mike".into(), (0..2).into()),
+            RemoteVersionSpanOwned("seph".into(), (0..3).into()),
+            RemoteVersionSpanOwned("seph".into(), (4..5).into()),
+        ]));
+    }
+
+    #[test]
+    fn import_linear_trace_matches_add_ops() {
+        let patches = [(0, 0, "hi there"), (2, 4, ""), (0, 1, "H"), (5, 0, "ö!")];
+
+        let mut expect = ListOpLog::new();
+        let seph = expect.get_or_create_agent_id("seph");
+        expect.add_insert(seph, 0, "x");
+        for (pos, del_len, ins) in patches {
+            if del_len > 0 { expect.add_delete_without_content(seph, pos..pos + del_len); }
+            if !ins.is_empty() { expect.add_insert(seph, pos, ins); }
+        }
+
+        let mut actual = ListOpLog::new();
+        let seph = actual.get_or_create_agent_id("seph");
+        actual.add_insert(seph, 0, "x");
+        let span = actual.import_linear_trace(seph, patches);
+
+        assert_eq!(span, (1..expect.len()).into());
+        assert_eq!(actual, expect);
+        assert_eq!(actual.checkout_tip().content().to_string(), "Hirexö!");
+
+        assert!(actual.import_linear_trace(seph, std::iter::empty::<(usize, usize, &str)>()).is_empty());
+    }
+    #[test]
+    fn remote_ops_are_validated() {
+        let mut oplog = ListOpLog::new();
+        assert_eq!(oplog.try_get_or_create_agent_id("ROOT"), Err(DTError::InvalidAgentName));
+        assert_eq!(oplog.try_get_or_create_agent_id(&"x".repeat(100)), Err(DTError::InvalidAgentName));
+        let seph = oplog.try_get_or_create_agent_id("seph").unwrap();
+        let a = oplog.add_insert(seph, 0, "hi");
+
+        let ins = [TextOperation::new_insert(0, "yo")];
+        let mut bad_content = TextOperation::new_insert(0, "yo");
+        bad_content.loc.span.end += 1;
+        let cases = [
+            (seph + 1, vec![a], ins.to_vec(), DTError::UnknownAgent),
+            (seph, vec![a + 1], ins.to_vec(), DTError::UnknownVersion),
+            (seph, vec![a], vec![bad_content], DTError::InvalidOperation),
+            (seph, vec![a], vec![TextOperation::new_insert(0, "")], DTError::InvalidOperation),
+            (seph, vec![a], vec![TextOperation::new_insert(3, "yo")], DTError::OpOutOfBounds),
+            (seph, vec![a - 1], vec![TextOperation::new_delete(0..2)], DTError::OpOutOfBounds),
+        ];
+        for (agent, parents, ops, err) in cases {
+            assert_eq!(oplog.try_add_operations_remote(agent, &parents, 10, &ops), Err(err));
+        }
+        assert_eq!(oplog.len(), 2);
+
+        // Redundant parents are fine.
+        let span = oplog.try_add_operations_remote(seph, &[a, 0], 2, &ins).unwrap();
+        assert_eq!(span, (2..4).into());
+        assert_eq!(oplog.checkout_tip().content().to_string(), "yohi");
+
+        // Concurrent deletes of the same character only remove it once.
+        let mike = oplog.get_or_create_agent_id("mike");
+        let b = oplog.add_delete_at(seph, &[a], 0..1);
+        let c = oplog.add_delete_at(mike, &[a], 0..1);
+        oplog.try_add_operations_remote(mike, &[b, c], 1, &[TextOperation::new_insert(1, "!")]).unwrap();
+        assert_eq!(oplog.checkout_tip().content().to_string(), "yoi!");
+    }
+
+    #[test]
+    fn iter_agents() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert(seph, 0, "hi");
+        oplog.add_insert(mike, 0, "yo ");
+        oplog.add_delete_without_content(seph, 0..1);
+        let agents = vec![(seph, "seph", 3), (mike, "mike", 3)];
+        assert_eq!(oplog.iter_agents().collect::<Vec<_>>(), agents);
+
+        // The agent name index is rebuilt when the document is loaded.
+        let loaded = ListOpLog::load_from(&oplog.encode(ENCODE_FULL)).unwrap();
+        loaded.dbg_check(true);
+        assert_eq!(loaded.iter_agents().collect::<Vec<_>>(), agents);
+        assert_eq!(loaded.get_agent_id("mike"), Some(mike));
+        assert_eq!(loaded.get_agent_id("fred"), None);
+    }
+}
@@ -1,4 +1,5 @@
-use std::collections::BinaryHeap;
+use alloc::vec::Vec;
+use alloc::collections::BinaryHeap;
 use smallvec::SmallVec;
 use rle::{AppendRle, HasLength};
 use crate::list::ListOpLog;
@@ -153,6 +154,22 @@ impl ListOpLog {
 
             time += s.len();
         }
+
+        self.add_missing_marks_from(other, &agent_map);
+        for (&(agent, seq), entry) in other.suggestions.entries.iter() {
+            self.suggestions.merge_entry((agent_map[agent as usize], seq), *entry);
+        }
+        for (&(agent, block), (len, tags)) in other.content_tags.entries.iter() {
+            for tag in tags.iter() {
+                self.content_tags.merge_entry((agent_map[agent as usize], block), *len, *tag);
+            }
+        }
+        for ((agent, key), entry) in other.agent_meta.entries.iter() {
+            self.agent_meta.merge_entry(agent_map[*agent as usize], key, entry.clone());
+        }
+        for (&agent, &target) in other.agent_links.links.iter() {
+            self.merge_agent_link(agent_map[agent as usize], agent_map[target as usize]);
+        }
     }
 }
 
@@ -0,0 +1,206 @@
+//! Buffering patches which arrive before the operations they depend on.
+//!
+//! A patch made by [`ListOpLog::encode_from`] can only be merged into an oplog which has the
+//! version it was encoded from. Transports which don't deliver messages in order (or gossip
+//! networks, where patches arrive from lots of peers) often deliver a patch before its
+//! dependencies. [`PendingOps`] holds onto these patches, and merges them as soon as the
+//! operations they depend on arrive.
+//!
+//! ```
+//! use diamond_types::list::ListOpLog;
+//! use diamond_types::list::encoding::ENCODE_PATCH;
+//! use diamond_types::list::pending::PendingOps;
+//!
+//! let mut a = ListOpLog::new();
+//! let seph = a.get_or_create_agent_id("seph");
+//! a.add_insert(seph, 0, "hi");
+//! let first = a.encode(ENCODE_PATCH);
+//! let v = a.local_frontier();
+//! a.add_insert(seph, 2, " there");
+//! let second = a.encode_from(ENCODE_PATCH, v.as_ref());
+//!
+//! let mut b = ListOpLog::new();
+//! let mut pending = PendingOps::new();
+//! assert_eq!(pending.push(&mut b, second).unwrap(), 0);
+//! assert_eq!(pending.push(&mut b, first).unwrap(), 2);
+//! assert_eq!(a, b);
+//! ```
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use smartstring::alias::String as SmartString;
+use crate::causalgraph::agent_assignment::remote_ids::{MissingOps, RemoteVersion, RemoteVersionOwned};
+use crate::DTRange;
+use crate::encoding::parseerror::ParseError;
+use crate::list::ListOpLog;
+
+#[derive(Debug, Clone)]
+struct PendingPatch {
+    data: Vec<u8>,
+    /// The versions the patch depends on which aren't in the oplog yet.
+    missing: Vec<RemoteVersionOwned>,
+}
+
+/// A buffer of patches waiting for their dependencies. See the [module documentation](self) for
+/// details.
+#[derive(Debug, Clone, Default)]
+pub struct PendingOps {
+    /// Buffered patches, keyed by the order they arrived in.
+    patches: BTreeMap<usize, PendingPatch>,
+
+    /// Index from each missing version to the patches waiting for it.
+    waiting_for: BTreeMap<(SmartString, usize), Vec<usize>>,
+
+    next_id: usize,
+}
+
+impl PendingOps {
+    pub fn new() -> Self { Self::default() }
+
+    /// The number of patches waiting for their dependencies.
+    pub fn len(&self) -> usize {
+        self.patches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patches.is_empty()
+    }
+
+    /// Merge a patch into the oplog, or buffer it if the oplog doesn't have the version it was
+    /// encoded from yet. Merging a patch also merges any buffered patches which were waiting for
+    /// it.
+    ///
+    /// Returns the number of patches merged into the oplog, which is 0 if the patch was buffered.
+    ///
+    /// Returns an error if the patch can't be read. Buffered patches are only fully checked when
+    /// they're merged. If a buffered patch turns out to be invalid, it's discarded and its error
+    /// is returned from the call which unblocked it. The other patches are still merged.
+    pub fn push(&mut self, oplog: &mut ListOpLog, data: Vec<u8>) -> Result<usize, ParseError> {
+        let start_version = ListOpLog::file_info(&data)?.start_version;
+        let missing: Vec<RemoteVersionOwned> = start_version.into_iter()
+            .filter(|rv| oplog.cg.agent_assignment.try_remote_to_local_version(rv.into()).is_err())
+            .collect();
+
+        if !missing.is_empty() {
+            let id = self.next_id;
+            self.next_id += 1;
+            for rv in &missing {
+                self.waiting_for.entry((rv.0.clone(), rv.1)).or_default().push(id);
+            }
+            self.patches.insert(id, PendingPatch { data, missing });
+            return Ok(0);
+        }
+
+        let start = oplog.len();
+        oplog.decode_and_add(&data)?;
+        let mut merged = 1;
+        let mut first_err = None;
+
+        // Merging can unblock buffered patches, which can unblock more patches in turn.
+        let mut new_ops: DTRange = (start..oplog.len()).into();
+        while !new_ops.is_empty() {
+            let start = oplog.len();
+            for id in self.unblock(oplog, new_ops) {
+                let patch = self.patches.remove(&id).unwrap();
+                match oplog.decode_and_add(&patch.data) {
+                    Ok(_) => merged += 1,
+                    Err(e) => { first_err.get_or_insert(e); }
+                }
+            }
+            new_ops = (start..oplog.len()).into();
+        }
+
+        match first_err {
+            Some(e) => Err(e),
+            None => Ok(merged),
+        }
+    }
+
+    /// Mark the versions in `new_ops` as available. Returns the patches which aren't waiting for
+    /// anything anymore, in the order they arrived.
+    fn unblock(&mut self, oplog: &ListOpLog, new_ops: DTRange) -> Vec<usize> {
+        let mut ready = Vec::new();
+        for span in oplog.cg.agent_assignment.iter_remote_mappings_range(new_ops) {
+            let name: SmartString = span.0.into();
+            let keys: Vec<_> = self.waiting_for
+                .range((name.clone(), span.1.start)..(name, span.1.end))
+                .map(|(key, _)| key.clone())
+                .collect();
+
+            for key in keys {
+                for id in self.waiting_for.remove(&key).unwrap() {
+                    let patch = self.patches.get_mut(&id).unwrap();
+                    patch.missing.retain(|rv| rv.0 != key.0 || rv.1 != key.1);
+                    if patch.missing.is_empty() { ready.push(id); }
+                }
+            }
+        }
+        ready.sort_unstable();
+        ready
+    }
+
+    /// List the operations which the buffered patches are waiting for. These could be requested
+    /// from a peer.
+    pub fn missing(&self, oplog: &ListOpLog) -> MissingOps {
+        let versions: Vec<RemoteVersion> = self.waiting_for.keys()
+            .map(|(name, seq)| RemoteVersion(name.as_str(), *seq))
+            .collect();
+        oplog.cg.agent_assignment.missing_remote_versions(&versions)
+    }
+
+    /// Remove all the buffered patches, returning them in the order they arrived. This is useful
+    /// when a connection is reset and the patches will be sent again.
+    pub fn clear(&mut self) -> Vec<Vec<u8>> {
+        self.waiting_for.clear();
+        core::mem::take(&mut self.patches).into_values()
+            .map(|p| p.data)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec;
+    use crate::causalgraph::agent_assignment::remote_ids::{MissingOps, RemoteVersionSpanOwned};
+    use crate::list::encoding::ENCODE_PATCH;
+    use crate::list::ListOpLog;
+    use crate::list::pending::PendingOps;
+
+    #[test]
+    fn out_of_order_delivery() {
+        let mut a = ListOpLog::new();
+        let seph = a.get_or_create_agent_id("seph");
+        let mike = a.get_or_create_agent_id("mike");
+        let v0 = a.add_insert(seph, 0, "hello");
+        let p0 = a.encode(ENCODE_PATCH);
+        let v1 = a.add_insert_at(mike, &[v0], 5, " world");
+        let p1 = a.encode_from(ENCODE_PATCH, &[v0]);
+        let v2 = a.add_insert_at(seph, &[v0], 0, "oh ");
+        let p2 = a.encode_from(ENCODE_PATCH, &[v0]);
+        a.add_delete_at(mike, &[v1, v2], 0..3);
+        let p3 = a.encode_from(ENCODE_PATCH, &[v1, v2]);
+
+        let mut b = ListOpLog::new();
+        let mut pending = PendingOps::new();
+        assert_eq!(pending.push(&mut b, p3.clone()).unwrap(), 0);
+        assert_eq!(pending.push(&mut b, p1).unwrap(), 0);
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending.missing(&b), MissingOps(vec![
+            RemoteVersionSpanOwned("mike".into(), (0..6).into()),
+            RemoteVersionSpanOwned("seph".into(), (0..8).into()),
+        ]));
+
+        // p0 unblocks p1. p3 is still waiting for p2.
+        assert_eq!(pending.push(&mut b, p0).unwrap(), 2);
+        assert_eq!(pending.missing(&b), MissingOps(vec![
+            RemoteVersionSpanOwned("seph".into(), (5..8).into()),
+        ]));
+        assert_eq!(pending.push(&mut b, p2).unwrap(), 2);
+        assert!(pending.is_empty());
+        assert_eq!(a, b);
+
+        // Patches we already have are merged straight away.
+        assert_eq!(pending.push(&mut b, p3).unwrap(), 1);
+        assert_eq!(a, b);
+    }
+}
@@ -0,0 +1,303 @@
+//! Presence (cursor awareness) for collaborative editors.
+//!
+//! Presence data is ephemeral. Its never stored in the oplog - instead each peer periodically
+//! broadcasts a small blob describing where its cursor is. Cursor positions are always named
+//! relative to some version of the document, so when new operations are merged the cursors need to
+//! be transformed to stay in the same logical place. This uses the same transform machinery used to
+//! merge changes into a branch.
+//!
+//! Presence doesn't depend on any wall clock. All timestamps are supplied by the caller (eg as
+//! milliseconds since the epoch). They're mostly only compared against other local timestamps, but
+//! a peer's local cursor updates are also numbered from its clock. So each peer's timestamps should
+//! keep increasing even if it restarts.
+
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
+use core::ops::Range;
+use smartstring::alias::String as SmartString;
+use rle::HasLength;
+use crate::{AgentId, Frontier, LV};
+use crate::causalgraph::agent_assignment::remote_ids::RemoteVersion;
+use crate::encoding::bufparser::BufParser;
+use crate::encoding::parseerror::ParseError;
+use crate::encoding::varint::{push_u64, push_usize};
+use crate::list::ListOpLog;
+use crate::list::operation::{ListOpKind, TextOperation};
+
+/// The known cursor (or selection) of a single agent.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CursorState {
+    /// The version of the document which `range` is relative to.
+    pub version: Frontier,
+
+    /// The selected range, in characters. A simple cursor has `range.start == range.end`. The range
+    /// may be "backwards" (start > end) if the user selected text from right to left.
+    pub range: Range<usize>,
+
+    /// Increased by the owning peer each time its cursor changes. This is used to discard stale
+    /// presence updates which arrive out of order.
+    ///
+    /// The first update from a peer is numbered with its current time, and later updates use the
+    /// larger of the time and the last seq + 1. So a peer which restarts (and forgets its old seq)
+    /// carries on past the updates it sent before.
+    pub seq: u64,
+
+    /// The (local) time when we last heard about this cursor.
+    pub last_seen: u64,
+}
+
+/// The set of known cursors for a document, keyed by agent name.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Presence {
+    cursors: BTreeMap<SmartString, CursorState>,
+}
+
+fn transform_pos(pos: usize, op: &TextOperation) -> usize {
+    let start = op.start();
+    match op.kind {
+        // Inserts at the cursor position don't move the cursor.
+        ListOpKind::Ins => if pos > start { pos + op.len() } else { pos },
+        ListOpKind::Del => {
+            if pos >= op.end() { pos - op.len() }
+            else if pos > start { start }
+            else { pos }
+        }
+    }
+}
+
+impl CursorState {
+    /// Transform this cursor so it includes all changes in `version`.
+    fn transform(&mut self, oplog: &ListOpLog, version: &[LV]) {
+        let mut range = self.range.clone();
+        for (_, op) in oplog.iter_xf_operations_from(self.version.as_ref(), version) {
+            if let Some(op) = op {
+                range = transform_pos(range.start, &op)..transform_pos(range.end, &op);
+            }
+        }
+        self.range = range;
+        self.version = oplog.version_union(self.version.as_ref(), version);
+    }
+}
+
+impl Presence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cursors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cursors.is_empty()
+    }
+
+    /// Set the cursor for a local agent. The range is relative to the passed version of the
+    /// document - which will usually be the local branch's version.
+    pub fn set_local_cursor(&mut self, oplog: &ListOpLog, agent: AgentId, version: &[LV], range: Range<usize>, now: u64) {
+        let name = oplog.get_agent_name(agent);
+        let seq = self.cursors.get(name).map_or(now, |c| now.max(c.seq + 1));
+        self.cursors.insert(name.into(), CursorState {
+            version: version.into(),
+            range,
+            seq,
+            last_seen: now,
+        });
+    }
+
+    /// Get the stored state for the named agent's cursor.
+    pub fn get(&self, agent_name: &str) -> Option<&CursorState> {
+        self.cursors.get(agent_name)
+    }
+
+    /// Get the named agent's cursor, transformed to the specified version of the document.
+    pub fn cursor_at(&self, oplog: &ListOpLog, agent_name: &str, version: &[LV]) -> Option<Range<usize>> {
+        self.cursors.get(agent_name).map(|c| {
+            let mut c = c.clone();
+            c.transform(oplog, version);
+            c.range
+        })
+    }
+
+    /// Iterate through all known cursors as (agent name, cursor) pairs.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &CursorState)> {
+        self.cursors.iter().map(|(name, c)| (name.as_str(), c))
+    }
+
+    pub fn remove(&mut self, agent_name: &str) -> Option<CursorState> {
+        self.cursors.remove(agent_name)
+    }
+
+    /// Transform all cursors so they include the changes in `version`. This should be called
+    /// after merging new operations into a branch, passing the branch's new version.
+    pub fn transform_to(&mut self, oplog: &ListOpLog, version: &[LV]) {
+        for c in self.cursors.values_mut() {
+            c.transform(oplog, version);
+        }
+    }
+
+    /// Remove all cursors which haven't been updated since `now - max_age`.
+    pub fn expire(&mut self, now: u64, max_age: u64) {
+        self.cursors.retain(|_, c| c.last_seen.saturating_add(max_age) >= now);
+    }
+
+    /// Encode the cursors for the named agents into a blob which can be sent to remote peers.
+    /// Versions are encoded using remote IDs.
+    pub fn encode(&self, oplog: &ListOpLog, agent_names: &[&str]) -> Vec<u8> {
+        let aa = &oplog.cg.agent_assignment;
+        let mut result = Vec::new();
+
+        let cursors = agent_names.iter()
+            .filter_map(|name| self.cursors.get_key_value(*name))
+            .collect::<Vec<_>>();
+
+        push_usize(&mut result, cursors.len());
+        for (name, c) in cursors {
+            push_str(&mut result, name);
+            push_u64(&mut result, c.seq);
+            push_usize(&mut result, c.version.len());
+            for v in c.version.iter() {
+                let RemoteVersion(v_name, v_seq) = aa.local_to_remote_version(*v);
+                push_str(&mut result, v_name);
+                push_usize(&mut result, v_seq);
+            }
+            push_usize(&mut result, c.range.start);
+            push_usize(&mut result, c.range.end);
+        }
+
+        result
+    }
+
+    /// Merge a presence blob (created by [`encode`](Presence::encode)) from a remote peer.
+    ///
+    /// Cursors which are older than the cursors we already know about are ignored. So are cursors
+    /// which name versions we don't have yet - the remote peer will send its cursor again later.
+    pub fn merge_remote(&mut self, oplog: &ListOpLog, blob: &[u8], now: u64) -> Result<(), ParseError> {
+        let aa = &oplog.cg.agent_assignment;
+        let mut reader = BufParser(blob);
+
+        let num = reader.next_usize()?;
+        for _ in 0..num {
+            let name = reader.next_str()?;
+            let seq = reader.next_u64()?;
+
+            let version_len = reader.next_usize()?;
+            let mut version = Ok(Frontier::root());
+            for _ in 0..version_len {
+                let rv = RemoteVersion(reader.next_str()?, reader.next_usize()?);
+                if let Ok(f) = &mut version {
+                    match aa.try_remote_to_local_version(rv) {
+                        Ok(v) => f.insert(v),
+                        Err(e) => version = Err(e),
+                    }
+                }
+            }
+
+            let range = reader.next_usize()?..reader.next_usize()?;
+
+            let Ok(version) = version else { continue; };
+            if self.cursors.get(name).is_some_and(|c| c.seq >= seq) { continue; }
+
+            self.cursors.insert(name.into(), CursorState {
+                version,
+                range,
+                seq,
+                last_seen: now,
+            });
+        }
+
+        reader.expect_empty()
+    }
+}
+
+fn push_str(into: &mut Vec<u8>, s: &str) {
+    push_usize(into, s.len());
+    into.extend_from_slice(s.as_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::ListOpLog;
+    use crate::list::presence::Presence;
+
+    #[test]
+    fn cursors_transform_across_merges() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let v = oplog.add_insert(seph, 0, "hello world");
+
+        let mut presence = Presence::new();
+        presence.set_local_cursor(&oplog, mike, &[v], 6..11, 100); // Selecting "world".
+
+        // Concurrent with the cursor, seph inserts at the start and deletes in the selection.
+        let v2 = oplog.add_insert_at(seph, &[v], 0, ">> ");
+        let v3 = oplog.add_delete_at(seph, &[v2], 9..12);
+
+        assert_eq!(presence.cursor_at(&oplog, "mike", &[v3]), Some(9..11));
+        presence.transform_to(&oplog, &[v3]);
+        assert_eq!(presence.get("mike").unwrap().range, 9..11);
+        assert_eq!(presence.get("mike").unwrap().version.as_ref(), &[v3]);
+    }
+
+    #[test]
+    fn merge_remote_blobs() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let v = oplog.add_insert(seph, 0, "abc");
+
+        let mut local = Presence::new();
+        local.set_local_cursor(&oplog, seph, &[v], 1..1, 10);
+        let blob_1 = local.encode(&oplog, &["seph"]);
+        local.set_local_cursor(&oplog, seph, &[v], 2..3, 20);
+        let blob_2 = local.encode(&oplog, &["seph", "nobody"]);
+
+        let mut remote = Presence::new();
+        remote.merge_remote(&oplog, &blob_2, 1000).unwrap();
+        // Stale updates are ignored.
+        remote.merge_remote(&oplog, &blob_1, 1001).unwrap();
+        let c = remote.get("seph").unwrap();
+        assert_eq!(c.range, 2..3);
+        assert_eq!(c.seq, 20);
+        assert_eq!(c.last_seen, 1000);
+
+        // Cursors referencing versions we don't know about are skipped.
+        let mut other = ListOpLog::new();
+        other.get_or_create_agent_id("fred");
+        let mut empty = Presence::new();
+        empty.merge_remote(&other, &blob_2, 0).unwrap();
+        assert!(empty.is_empty());
+
+        assert!(remote.merge_remote(&oplog, &blob_2[..blob_2.len() - 1], 0).is_err());
+
+        remote.expire(1500, 1000);
+        assert_eq!(remote.len(), 1);
+        remote.expire(2500, 1000);
+        assert!(remote.is_empty());
+    }
+
+    #[test]
+    fn restarted_peer_cursor_updates() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let v = oplog.add_insert(seph, 0, "abc");
+
+        let mut local = Presence::new();
+        local.set_local_cursor(&oplog, seph, &[v], 0..0, 100);
+        local.set_local_cursor(&oplog, seph, &[v], 1..1, 100);
+        local.set_local_cursor(&oplog, seph, &[v], 2..2, 100);
+        assert_eq!(local.get("seph").unwrap().seq, 102);
+
+        let mut remote = Presence::new();
+        remote.merge_remote(&oplog, &local.encode(&oplog, &["seph"]), 1000).unwrap();
+
+        // Seph's editor restarts, forgetting its presence state. Its next update still wins.
+        let mut local = Presence::new();
+        local.set_local_cursor(&oplog, seph, &[v], 3..3, 200);
+        remote.merge_remote(&oplog, &local.encode(&oplog, &["seph"]), 1100).unwrap();
+        let c = remote.get("seph").unwrap();
+        assert_eq!(c.range, 3..3);
+        assert_eq!(c.seq, 200);
+        assert_eq!(c.last_seen, 1100);
+    }
+}
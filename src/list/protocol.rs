@@ -0,0 +1,418 @@
+//! A network-agnostic protocol for keeping two oplogs in sync.
+//!
+//! This module doesn't do any IO. A [`Peer`] tracks the state of a single connection to a remote
+//! peer, and tells you which messages to send. Messages are sent over whatever transport you like
+//! (websockets, QUIC, etc), so long as messages arrive in order.
+//!
+//! The protocol works like this:
+//!
+//! 1. When a connection is opened (or reopened after a disconnect), both sides send
+//!    [`Peer::hello`]. This contains a summary of the versions the peer knows about.
+//! 2. When a peer receives hello, it works out which operations the remote peer is missing and
+//!    sends them in batches.
+//! 3. Each batch of operations is acknowledged by the receiver. Only a limited number of batches
+//!    are sent before waiting for an acknowledgement, so a slow receiver isn't flooded with data.
+//! 4. After making local changes to the oplog, call [`Peer::flush`] to send them.
+//! 5. Marks and suggestion states don't change the oplog's version, so they're sent separately
+//!    once the remote peer has every operation. Each is sent once per connection, and again
+//!    whenever it changes.
+//!
+//! Messages only ever contain data in the diamond types binary format, so merging data from a
+//! peer is always safe even if it has been sent before.
+//!
+//! ```
+//! use diamond_types::list::ListOpLog;
+//! use diamond_types::list::protocol::Peer;
+//!
+//! let mut a = ListOpLog::new();
+//! let seph = a.get_or_create_agent_id("seph");
+//! a.add_insert(seph, 0, "hi there");
+//! let mut b = ListOpLog::new();
+//!
+//! let (mut peer_a, mut peer_b) = (Peer::new(), Peer::new());
+//! let mut to_b = vec![peer_a.hello(&a).to_bytes()];
+//! let mut to_a = vec![peer_b.hello(&b).to_bytes()];
+//!
+//! // Pass messages back and forth until both sides are quiet.
+//! while !to_a.is_empty() || !to_b.is_empty() {
+//!     for msg in core::mem::take(&mut to_b) {
+//!         to_a.extend(peer_b.on_message(&mut b, &msg).unwrap().iter().map(|m| m.to_bytes()));
+//!     }
+//!     for msg in core::mem::take(&mut to_a) {
+//!         to_b.extend(peer_a.on_message(&mut a, &msg).unwrap().iter().map(|m| m.to_bytes()));
+//!     }
+//! }
+//!
+//! assert_eq!(a, b);
+//! ```
+
+use alloc::vec::Vec;
+use num_enum::TryFromPrimitive;
+use smallvec::SmallVec;
+use rle::HasLength;
+use crate::{DTRange, Frontier, LV};
+use crate::causalgraph::summary::VersionSummary;
+use crate::encoding::bufparser::BufParser;
+use crate::encoding::parseerror::ParseError;
+use crate::encoding::varint::push_usize;
+use crate::list::encoding::{ChangeClocks, ENCODE_PATCH, EncodeFilter, EncodeOptions};
+use crate::list::ListOpLog;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, TryFromPrimitive)]
+#[repr(u32)]
+enum MsgType {
+    Hello = 1,
+    Ops = 2,
+    Ack = 3,
+}
+
+/// A message to send to the remote peer.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Msg {
+    /// Sent when a connection is opened, naming every version the sender knows about.
+    Hello(VersionSummary),
+
+    /// A batch of operations, encoded in the diamond types binary format.
+    Ops(Vec<u8>),
+
+    /// Acknowledges that a batch of operations has been received and merged.
+    Ack,
+}
+
+impl Msg {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = vec![];
+        match self {
+            Msg::Hello(summary) => {
+                push_usize(&mut result, MsgType::Hello as usize);
+                summary.encode_into(&mut result);
+            }
+            Msg::Ops(data) => {
+                push_usize(&mut result, MsgType::Ops as usize);
+                result.extend_from_slice(data);
+            }
+            Msg::Ack => {
+                push_usize(&mut result, MsgType::Ack as usize);
+            }
+        }
+        result
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        let mut reader = BufParser(bytes);
+        let msg_type = MsgType::try_from(reader.next_u32()?)
+            .map_err(|_| ParseError::GenericInvalidData)?;
+
+        Ok(match msg_type {
+            MsgType::Hello => {
+                let summary = VersionSummary::decode(&mut reader)?;
+                reader.expect_empty()?;
+                Msg::Hello(summary)
+            }
+            MsgType::Ops => Msg::Ops(reader.0.to_vec()),
+            MsgType::Ack => {
+                reader.expect_empty()?;
+                Msg::Ack
+            }
+        })
+    }
+}
+
+/// The state of a connection to a single remote peer. See the [module documentation](self) for
+/// details.
+#[derive(Debug, Clone)]
+pub struct Peer {
+    /// The version we know the remote peer has, including everything we've sent to it. None until
+    /// we receive the remote peer's hello.
+    remote_version: Option<Frontier>,
+
+    /// The oplog's change clocks when we last sent the marks and suggestions which had changed.
+    changes_sent: ChangeClocks,
+
+    /// The number of batches sent which haven't been acknowledged yet.
+    in_flight: usize,
+
+    max_batch_ops: usize,
+    max_in_flight: usize,
+}
+
+impl Default for Peer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Peer {
+    pub fn new() -> Self {
+        Self {
+            remote_version: None,
+            changes_sent: ChangeClocks::default(),
+            in_flight: 0,
+            max_batch_ops: 10000,
+            max_in_flight: 4,
+        }
+    }
+
+    /// Set the maximum number of operations sent in each batch. Defaults to 10000.
+    pub fn set_max_batch_ops(&mut self, max_batch_ops: usize) {
+        assert!(max_batch_ops > 0);
+        self.max_batch_ops = max_batch_ops;
+    }
+
+    /// Set the maximum number of batches sent before waiting for the remote peer to acknowledge
+    /// them. Defaults to 4.
+    pub fn set_max_in_flight(&mut self, max_in_flight: usize) {
+        assert!(max_in_flight > 0);
+        self.max_in_flight = max_in_flight;
+    }
+
+    /// Returns true once we've heard from the remote peer, and we've sent it every operation,
+    /// mark and suggestion in the oplog.
+    pub fn is_synced(&self, oplog: &ListOpLog) -> bool {
+        self.changes_sent == oplog.change_clocks() && self.remote_version.as_ref()
+            .is_some_and(|v| oplog.cg.graph.frontier_contains_frontier(v.as_ref(), oplog.cg.version.as_ref()))
+    }
+
+    /// Start (or restart) the connection. The returned message should be the first message sent
+    /// to the remote peer on a new connection.
+    ///
+    /// Any state from a previous connection is discarded. Operations which were in flight when the
+    /// connection dropped will be resent if the remote peer didn't receive them.
+    pub fn hello(&mut self, oplog: &ListOpLog) -> Msg {
+        self.remote_version = None;
+        self.changes_sent = ChangeClocks::default();
+        self.in_flight = 0;
+        Msg::Hello(oplog.cg.agent_assignment.summarize_versions())
+    }
+
+    /// Process a message from the remote peer. Any operations received are merged into the oplog.
+    ///
+    /// Returns the messages which should be sent in reply.
+    pub fn on_message(&mut self, oplog: &mut ListOpLog, bytes: &[u8]) -> Result<Vec<Msg>, ParseError> {
+        let mut result = vec![];
+
+        match Msg::from_bytes(bytes)? {
+            Msg::Hello(summary) => {
+                let (common, _) = oplog.cg.intersect_with_summary(&summary, &[]);
+                self.remote_version = Some(common);
+                self.in_flight = 0;
+            }
+            Msg::Ops(data) => {
+                let changes_synced = self.changes_sent == oplog.change_clocks();
+                let v = oplog.decode_and_add(&data)?;
+                // Don't send the remote peer's marks and suggestions straight back to it.
+                if changes_synced { self.changes_sent = oplog.change_clocks(); }
+                // The remote peer obviously has the operations it sent us.
+                if let Some(remote_version) = self.remote_version.as_mut() {
+                    *remote_version = join(oplog, remote_version.as_ref(), v.as_ref());
+                }
+                result.push(Msg::Ack);
+            }
+            Msg::Ack => {
+                self.in_flight = self.in_flight.saturating_sub(1);
+            }
+        }
+
+        self.flush_into(oplog, &mut result);
+        Ok(result)
+    }
+
+    /// Get the messages needed to send any operations the remote peer doesn't have yet. Call this
+    /// after making local changes to the oplog.
+    ///
+    /// Nothing is sent before the remote peer's hello has been received, or while too many
+    /// batches are waiting to be acknowledged.
+    pub fn flush(&mut self, oplog: &ListOpLog) -> Vec<Msg> {
+        let mut result = vec![];
+        self.flush_into(oplog, &mut result);
+        result
+    }
+
+    fn flush_into(&mut self, oplog: &ListOpLog, result: &mut Vec<Msg>) {
+        let Some(remote_version) = self.remote_version.as_mut() else { return; };
+
+        while self.in_flight < self.max_in_flight {
+            let (missing, _) = oplog.cg.graph.diff(oplog.cg.version.as_ref(), remote_version.as_ref());
+            if missing.is_empty() {
+                // The remote peer has every operation, so it can merge any mark or suggestion.
+                let changes = oplog.change_clocks();
+                if self.changes_sent != changes {
+                    result.push(Msg::Ops(oplog.encode_from_changes_since(ENCODE_PATCH, remote_version.as_ref(), self.changes_sent)));
+                    self.changes_sent = changes;
+                    self.in_flight += 1;
+                }
+                break;
+            }
+
+            // Take the first max_batch_ops operations. Spans are in ascending order, so all of the
+            // history of the batch is either in the batch or already known by the remote peer.
+            let mut batch: SmallVec<[DTRange; 4]> = SmallVec::new();
+            let mut remaining = self.max_batch_ops;
+            for span in missing {
+                if remaining == 0 { break; }
+                let len = span.len().min(remaining);
+                batch.push((span.start..span.start + len).into());
+                remaining -= len;
+            }
+
+            let filter = EncodeFilter::Spans(&batch);
+            // Marks and suggestions are sent once all the operations have been sent.
+            result.push(Msg::Ops(oplog.encode_from_changes_since(EncodeOptions {
+                filter: Some(filter),
+                ..ENCODE_PATCH
+            }, remote_version.as_ref(), ChangeClocks::MAX)));
+
+            *remote_version = join(oplog, remote_version.as_ref(), oplog.filter_version(filter).as_ref());
+            self.in_flight += 1;
+        }
+    }
+}
+
+/// The smallest version containing both a and b.
+fn join(oplog: &ListOpLog, a: &[LV], b: &[LV]) -> Frontier {
+    let versions: SmallVec<[LV; 4]> = a.iter().chain(b.iter()).copied().collect();
+    oplog.cg.graph.find_dominators(&versions)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+    use crate::list::anchor::Anchor;
+    use crate::list::ListOpLog;
+    use crate::list::marks::{MarkExpand, MarkRange};
+    use crate::list::protocol::{Msg, Peer};
+
+    struct Side {
+        oplog: ListOpLog,
+        peer: Peer,
+        inbox: VecDeque<Vec<u8>>,
+    }
+
+    fn make_side(name: &str, content: &str) -> Side {
+        let mut oplog = ListOpLog::new();
+        let agent = oplog.get_or_create_agent_id(name);
+        // Lots of small operations.
+        for (i, c) in content.chars().enumerate() {
+            oplog.add_insert(agent, i, &c.to_string());
+        }
+        let mut peer = Peer::new();
+        peer.set_max_batch_ops(3);
+        peer.set_max_in_flight(2);
+        Side { oplog, peer, inbox: VecDeque::new() }
+    }
+
+    fn connect(a: &mut Side, b: &mut Side) {
+        a.inbox.clear();
+        b.inbox.clear();
+        b.inbox.push_back(a.peer.hello(&a.oplog).to_bytes());
+        a.inbox.push_back(b.peer.hello(&b.oplog).to_bytes());
+    }
+
+    /// Deliver the next message in to's inbox. Returns false if there was nothing to deliver.
+    fn deliver(from: &mut Side, to: &mut Side) -> bool {
+        let Some(msg) = to.inbox.pop_front() else { return false; };
+        for reply in to.peer.on_message(&mut to.oplog, &msg).unwrap() {
+            from.inbox.push_back(reply.to_bytes());
+        }
+        true
+    }
+
+    fn step(a: &mut Side, b: &mut Side) -> bool {
+        let delivered = deliver(a, b);
+        deliver(b, a) || delivered
+    }
+
+    #[test]
+    fn msg_round_trips() {
+        let side = make_side("seph", "abc");
+        for msg in [Msg::Hello(side.oplog.cg.agent_assignment.summarize_versions()), Msg::Ops(vec![1, 2, 3]), Msg::Ack] {
+            assert_eq!(Msg::from_bytes(&msg.to_bytes()).unwrap(), msg);
+        }
+        assert!(Msg::from_bytes(&[100]).is_err());
+        assert!(Msg::from_bytes(&[]).is_err());
+    }
+
+    #[test]
+    fn sync_with_backpressure_and_reconnect() {
+        let mut a = make_side("seph", "hello world");
+        let mut b = make_side("mike", "yooo");
+        connect(&mut a, &mut b);
+
+        // Nothing is sent until hello is received, and then only max_in_flight batches.
+        assert!(a.peer.flush(&a.oplog).is_empty());
+        step(&mut a, &mut b);
+        assert_eq!(a.inbox.iter().filter(|m| matches!(Msg::from_bytes(m), Ok(Msg::Ops(_)))).count(), 2);
+
+        // Disconnect part way through syncing, then reconnect.
+        step(&mut a, &mut b);
+        assert!(!a.peer.is_synced(&a.oplog));
+        connect(&mut a, &mut b);
+        while step(&mut a, &mut b) {}
+        assert!(a.peer.is_synced(&a.oplog) && b.peer.is_synced(&b.oplog));
+        assert_eq!(a.oplog, b.oplog);
+
+        // Local changes are sent by flush.
+        let seph = a.oplog.get_or_create_agent_id("seph");
+        a.oplog.add_insert(seph, 0, "!");
+        assert!(!a.peer.is_synced(&a.oplog));
+        for msg in a.peer.flush(&a.oplog) {
+            b.inbox.push_back(msg.to_bytes());
+        }
+        while step(&mut a, &mut b) {}
+        assert_eq!(a.oplog, b.oplog);
+        assert_eq!(b.oplog.checkout_tip().content().to_string(), a.oplog.checkout_tip().content().to_string());
+    }
+
+    #[test]
+    fn sync_marks() {
+        let mut a = make_side("seph", "hello world");
+        let mut b = make_side("mike", "");
+        connect(&mut a, &mut b);
+        while step(&mut a, &mut b) {}
+
+        // Adding or deleting a mark doesn't change the version, but flush still sends it.
+        let seph = a.oplog.get_or_create_agent_id("seph");
+        let v = a.oplog.cg.version.clone();
+        let bold = a.oplog.add_mark(seph, v.as_ref(), MarkRange {
+            start_anchor: Anchor::before(0),
+            end_anchor: Anchor::after(4),
+            expand: MarkExpand::After,
+            payload: b"bold".to_vec(),
+        });
+        assert!(!a.peer.is_synced(&a.oplog));
+        let msgs = a.peer.flush(&a.oplog);
+        assert_eq!(msgs.len(), 1);
+        b.inbox.extend(msgs.iter().map(Msg::to_bytes));
+        while step(&mut a, &mut b) {}
+        assert_eq!(a.oplog, b.oplog);
+
+        assert!(a.oplog.delete_mark(bold, v.as_ref()));
+        b.inbox.extend(a.peer.flush(&a.oplog).iter().map(Msg::to_bytes));
+        while step(&mut a, &mut b) {}
+        assert_eq!(a.oplog, b.oplog);
+        assert!(a.peer.is_synced(&a.oplog) && b.peer.is_synced(&b.oplog));
+
+        // The marks aren't sent back, or sent again.
+        assert!(b.peer.flush(&b.oplog).is_empty());
+        assert!(a.peer.flush(&a.oplog).is_empty());
+    }
+
+    #[test]
+    fn sync_suggestion_states() {
+        let mut a = make_side("seph", "hello");
+        let mut b = make_side("mike", "");
+        let v = a.oplog.cg.version[0];
+        a.oplog.flag_suggestion((0..v + 1).into());
+        connect(&mut a, &mut b);
+        while step(&mut a, &mut b) {}
+        assert_eq!(b.oplog.checkout_mainline(), "");
+
+        // Accepting the suggestion doesn't change the version, but flush still sends it.
+        assert!(a.oplog.accept_suggestion(v));
+        b.inbox.extend(a.peer.flush(&a.oplog).iter().map(Msg::to_bytes));
+        while step(&mut a, &mut b) {}
+        assert_eq!(a.oplog, b.oplog);
+        assert_eq!(b.oplog.checkout_mainline(), "hello");
+        assert!(a.peer.is_synced(&a.oplog) && b.peer.is_synced(&b.oplog));
+    }
+}
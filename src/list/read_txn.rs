@@ -0,0 +1,258 @@
+//! Read transactions, for running several queries against the same version of a document.
+//!
+//! A [`ReadTxn`] pins a version (frontier) of the document. Every query made through the
+//! transaction - reading content, blame lookups, resolving anchors - sees the document at that
+//! version, even if the branch has moved on or more operations have been added to the oplog since
+//! the version was pinned.
+//!
+//! Making a read transaction is cheap. Nothing is checked out until the transaction's content is
+//! read, and the blame index is only built the first time it's needed. Both are then cached for the
+//! lifetime of the transaction.
+//!
+//! A transaction made with [`ListOpLog::read_txn`] borrows the oplog, so nothing can be appended
+//! while it's open. To read while other threads append, share the oplog as an `Arc<ListOpLog>`
+//! (eg in a `RwLock<Arc<ListOpLog>>`) and open transactions with [`ListOpLog::read_txn_shared`].
+//! The transaction holds its own reference to the oplog, so the lock only needs to be held while
+//! the `Arc` is cloned. Writers append through [`Arc::make_mut`]. If a transaction still holds the
+//! old oplog, this copies it once, and later appends go to the copy. Open transactions keep
+//! reading the oplog they started with.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::OnceCell;
+use core::ops::{Deref, Range};
+use jumprope::JumpRope;
+use crate::{DTRange, Frontier, LV};
+use crate::list::{ListBranch, ListCRDT, ListOpLog};
+use crate::list::anchor::Anchor;
+use crate::list::op_index::OpRangeIndex;
+
+/// The oplog a transaction reads from.
+#[derive(Debug)]
+enum TxnOpLog<'a> {
+    Borrowed(&'a ListOpLog),
+    /// An oplog shared with writers. See [`ListOpLog::read_txn_shared`].
+    Shared(Arc<ListOpLog>),
+}
+
+impl<'a> Deref for TxnOpLog<'a> {
+    type Target = ListOpLog;
+
+    fn deref(&self) -> &ListOpLog {
+        match self {
+            TxnOpLog::Borrowed(oplog) => oplog,
+            TxnOpLog::Shared(oplog) => oplog,
+        }
+    }
+}
+
+/// A consistent, read-only view of a document at a pinned version. See the
+/// [module documentation](self) for details.
+#[derive(Debug)]
+pub struct ReadTxn<'a> {
+    oplog: TxnOpLog<'a>,
+    version: Frontier,
+
+    /// A branch already at the pinned version. If present, its content is copied instead of
+    /// checking out the document from the oplog.
+    branch: Option<&'a ListBranch>,
+
+    content: OnceCell<JumpRope>,
+    index: OnceCell<OpRangeIndex>,
+}
+
+impl<'a> ReadTxn<'a> {
+    fn new(oplog: TxnOpLog<'a>, version: Frontier, branch: Option<&'a ListBranch>) -> Self {
+        Self { oplog, version, branch, content: OnceCell::new(), index: OnceCell::new() }
+    }
+
+    /// The version pinned by this transaction.
+    pub fn local_frontier_ref(&self) -> &[LV] { self.version.as_ref() }
+
+    /// The oplog this transaction reads from.
+    pub fn oplog(&self) -> &ListOpLog { &self.oplog }
+
+    /// The document's content at the pinned version. The document is checked out the first time
+    /// this is called.
+    pub fn content(&self) -> &JumpRope {
+        self.content.get_or_init(|| match self.branch {
+            Some(branch) => branch.content.borrow().clone(),
+            None => self.oplog.checkout_into(self.version.as_ref()),
+        })
+    }
+
+    /// The length of the document at the pinned version, in unicode characters.
+    pub fn len_chars(&self) -> usize {
+        self.content().len_chars()
+    }
+
+    /// Read a range of the document, in unicode characters.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range extends past the end of the document.
+    pub fn slice(&self, range: Range<usize>) -> String {
+        self.content().slice_chars(range).collect()
+    }
+
+    fn index(&self) -> &OpRangeIndex {
+        self.index.get_or_init(|| {
+            let mut index = OpRangeIndex::new();
+            index.merge(&self.oplog, self.version.as_ref());
+            index
+        })
+    }
+
+    /// Get the LV of the insert which created the character at pos. See
+    /// [`OpRangeIndex::lv_at`].
+    pub fn lv_at_position(&self, pos: usize) -> LV {
+        self.index().lv_at(pos)
+    }
+
+    /// Get the (local) versions of the operations which inserted the characters in a range of the
+    /// document. See [`OpRangeIndex::ops_affecting`].
+    pub fn ops_affecting(&self, range: Range<usize>) -> Vec<DTRange> {
+        self.index().ops_affecting(range)
+    }
+
+    /// Find the position of an anchor at the pinned version. See
+    /// [`ListOpLog::resolve_anchor`].
+    pub fn resolve_anchor(&self, anchor: Anchor) -> Option<usize> {
+        self.oplog.resolve_anchor(self.version.as_ref(), anchor)
+    }
+
+    /// Check out a branch at the pinned version, for editing.
+    pub fn checkout(&self) -> ListBranch {
+        match self.branch {
+            Some(branch) => branch.clone(),
+            None => self.oplog.checkout(self.version.as_ref()),
+        }
+    }
+}
+
+impl ListOpLog {
+    /// Open a read transaction at the current version of the oplog.
+    pub fn read_txn(&self) -> ReadTxn<'_> {
+        ReadTxn::new(TxnOpLog::Borrowed(self), self.cg.version.clone(), None)
+    }
+
+    /// Open a read transaction at the specified version.
+    pub fn read_txn_at(&self, version: &[LV]) -> ReadTxn<'_> {
+        ReadTxn::new(TxnOpLog::Borrowed(self), version.into(), None)
+    }
+
+    /// Open a read transaction at the current version of a shared oplog. The transaction keeps its
+    /// own reference to the oplog, so other threads can append to theirs while it's open. See the
+    /// [module documentation](crate::list::read_txn) for details.
+    pub fn read_txn_shared(oplog: &Arc<ListOpLog>) -> ReadTxn<'static> {
+        ReadTxn::new(TxnOpLog::Shared(oplog.clone()), oplog.cg.version.clone(), None)
+    }
+
+    /// Open a read transaction at the specified version of a shared oplog. See
+    /// [`read_txn_shared`](ListOpLog::read_txn_shared).
+    pub fn read_txn_shared_at(oplog: &Arc<ListOpLog>, version: &[LV]) -> ReadTxn<'static> {
+        ReadTxn::new(TxnOpLog::Shared(oplog.clone()), version.into(), None)
+    }
+}
+
+impl ListCRDT {
+    /// Open a read transaction at the document's current version. Reading the transaction's content
+    /// copies it from the branch, rather than replaying the oplog.
+    pub fn read_txn(&self) -> ReadTxn<'_> {
+        ReadTxn::new(TxnOpLog::Borrowed(&self.oplog), self.branch.version.clone(), Some(&self.branch))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, RwLock};
+    use crate::list::{ListCRDT, ListOpLog};
+    use crate::list::anchor::Anchor;
+
+    #[test]
+    fn read_txn_is_lazy_and_consistent() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        let mike = doc.get_or_create_agent_id("mike");
+        doc.insert(seph, 0, "hello world");
+        // A change the branch hasn't merged yet.
+        let v = doc.branch.local_frontier();
+        doc.oplog.add_insert_at(mike, v.as_ref(), 0, ">> ");
+
+        let txn = doc.read_txn();
+        assert!(txn.content.get().is_none());
+        assert_eq!(txn.resolve_anchor(Anchor::before(6)), Some(6));
+        assert!(txn.content.get().is_none());
+
+        assert_eq!(txn.slice(0..5), "hello");
+        assert_eq!(txn.len_chars(), 11);
+        assert_eq!(txn.lv_at_position(6), 6);
+        assert_eq!(txn.ops_affecting(0..11), vec![(0..11).into()]);
+        assert_eq!(txn.checkout(), doc.branch);
+
+        let tip = doc.oplog.read_txn();
+        assert_eq!(tip.slice(0..5), ">> he");
+        assert_eq!(tip.resolve_anchor(Anchor::before(6)), Some(9));
+        assert_eq!(tip.checkout(), doc.oplog.checkout_tip());
+    }
+
+    #[test]
+    fn reopen_after_concurrent_appends() {
+        let oplog = Arc::new(RwLock::new(ListOpLog::new()));
+        let version = {
+            let mut oplog = oplog.write().unwrap();
+            let seph = oplog.get_or_create_agent_id("seph");
+            oplog.add_insert(seph, 0, "abc");
+            oplog.read_txn().local_frontier_ref().to_vec()
+        };
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let mut oplog = oplog.write().unwrap();
+                let mike = oplog.get_or_create_agent_id("mike");
+                oplog.add_insert(mike, 1, "xyz");
+            });
+        });
+
+        let oplog = oplog.read().unwrap();
+        let txn = oplog.read_txn_at(&version);
+        assert_eq!(txn.content().to_string(), "abc");
+        assert_eq!(txn.lv_at_position(2), 2);
+        assert_eq!(oplog.read_txn().content().to_string(), "axyzbc");
+    }
+
+    #[test]
+    fn read_while_appending() {
+        let shared = RwLock::new(Arc::new(ListOpLog::new()));
+        {
+            let mut oplog = shared.write().unwrap();
+            let oplog = Arc::make_mut(&mut oplog);
+            let seph = oplog.get_or_create_agent_id("seph");
+            oplog.add_insert(seph, 0, "abc");
+        }
+
+        // The lock is only held while the transaction is opened.
+        let txn = ListOpLog::read_txn_shared(&shared.read().unwrap());
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let mut oplog = shared.write().unwrap();
+                let oplog = Arc::make_mut(&mut oplog);
+                let mike = oplog.get_or_create_agent_id("mike");
+                oplog.add_insert(mike, 1, "xyz");
+            });
+        });
+
+        // Nothing was checked out before the append, but the transaction still sees its version.
+        assert!(txn.content.get().is_none());
+        assert_eq!(txn.content().to_string(), "abc");
+        assert_eq!(txn.lv_at_position(2), 2);
+        assert_eq!(txn.oplog().len(), 3);
+
+        let oplog = shared.read().unwrap();
+        assert_eq!(oplog.len(), 6);
+        let txn = ListOpLog::read_txn_shared_at(&oplog, &[2]);
+        assert_eq!(txn.content().to_string(), "abc");
+        assert_eq!(ListOpLog::read_txn_shared(&oplog).content().to_string(), "axyzbc");
+    }
+}
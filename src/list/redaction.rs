@@ -0,0 +1,129 @@
+//! Redaction of content from a document's history.
+//!
+//! Sometimes content has to be removed from a document permanently - eg when someone pastes a
+//! password, or when the law requires it. Deleting the text isn't enough, since the oplog still
+//! stores it. [`ListOpLog::redact`] replaces the stored content of a range of operations with
+//! [`REDACTED_CHAR`]. The placeholder has the same length (in characters) as the content it
+//! replaces, so the operations themselves are unchanged and the document still merges correctly
+//! with peers.
+//!
+//! The oplog remembers which operations have been redacted. Redactions are stored when the oplog
+//! is encoded, and they're applied to any oplog which loads or merges the file. So once a
+//! redaction has reached the other peers, they stop storing the content too.
+//!
+//! Redaction only changes the oplog. Branches which already contain the redacted content need to
+//! be checked out again.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::{DTRange, LV};
+use crate::list::ListOpLog;
+use crate::list::operation::ListOpKind;
+use crate::unicount::count_chars;
+
+/// The character which replaces redacted content.
+pub const REDACTED_CHAR: char = '\u{2588}';
+
+/// True if lv is in one of the sorted, non-overlapping ranges.
+pub(crate) fn ranges_contain(ranges: &[DTRange], lv: LV) -> bool {
+    let idx = ranges.partition_point(|r| r.end <= lv);
+    ranges.get(idx).is_some_and(|r| r.contains(lv))
+}
+
+fn placeholder(content: &str) -> Option<String> {
+    Some(core::iter::repeat_n(REDACTED_CHAR, count_chars(content)).collect())
+}
+
+impl ListOpLog {
+    /// Permanently replace the content inserted and deleted by the operations in the specified
+    /// range of local versions with [`REDACTED_CHAR`]. See the [module documentation](self) for
+    /// details.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range extends past the end of the oplog.
+    pub fn redact(&mut self, range: DTRange) {
+        assert!(range.end <= self.len(), "Cannot redact operations which aren't in the oplog");
+        if range.is_empty() { return; }
+
+        self.rewrite_content(range, ListOpKind::Ins, |_, content| placeholder(content));
+        self.rewrite_content(range, ListOpKind::Del, |_, content| placeholder(content));
+        // The cached snapshots might contain the redacted content.
+        self.clear_checkout_cache();
+
+        // Keep the redacted ranges sorted, with overlapping and adjacent ranges merged.
+        let redactions = &mut self.redactions;
+        let idx = redactions.partition_point(|r| r.end < range.start);
+        let end = idx + redactions[idx..].iter().take_while(|r| r.start <= range.end).count();
+        let merged = redactions[idx..end].iter().fold(range, |a, r| {
+            (a.start.min(r.start)..a.end.max(r.end)).into()
+        });
+        redactions.splice(idx..end, [merged]);
+    }
+
+    /// The ranges of local versions which have been redacted, in order.
+    pub fn redactions(&self) -> &[DTRange] {
+        &self.redactions
+    }
+
+    /// True if the operation at the specified local version has been redacted.
+    pub fn is_redacted(&self, lv: LV) -> bool {
+        ranges_contain(&self.redactions, lv)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::encoding::{EncodeOptions, FormatFeatures};
+    use crate::list::{ListCRDT, ListOpLog};
+    use crate::list::redaction::REDACTED_CHAR;
+
+    #[test]
+    fn redact_secret() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "my password is hunter2!");
+        doc.delete(seph, 15..22);
+        doc.insert(seph, 15, "****");
+        let mut oplog = doc.oplog;
+        let mut other = oplog.clone();
+
+        oplog.enable_checkout_cache(2);
+        oplog.checkout_cached(&[10]);
+        oplog.redact((15..22).into());
+        oplog.redact((23..30).into());
+        oplog.redact((22..23).into());
+        assert_eq!(oplog.redactions(), &[(15..30).into()]);
+        assert!(oplog.is_redacted(29));
+        assert!(!oplog.is_redacted(30));
+
+        let blank = REDACTED_CHAR.to_string().repeat(8);
+        assert_eq!(oplog.checkout(&[22]).content().to_string(), format!("my password is {blank}"));
+        assert_eq!(oplog.checkout_tip().content().to_string(), format!("my password is ****{REDACTED_CHAR}"));
+        assert!(!oplog.iter().any(|op| op.content.is_some_and(|c| c.contains("hunter"))));
+        assert_eq!(oplog.checkout_cache_len(), 0);
+        assert_eq!(oplog.checkout_cached(&[22]).content().to_string(), format!("my password is {blank}"));
+        assert_eq!(oplog.checkout_cache_len(), 1);
+
+        // The redaction is stored in the encoding, and applied when the file is merged.
+        let data = oplog.encode(EncodeOptions { store_deleted_content: true, ..EncodeOptions::default() });
+        assert!(!data.windows(6).any(|w| w == b"hunter"));
+        assert!(ListOpLog::file_info(&data).unwrap().features.contains(FormatFeatures::REDACTIONS));
+        assert_eq!(ListOpLog::load_from(&data).unwrap(), oplog);
+
+        let mike = other.get_or_create_agent_id("mike");
+        other.add_insert(mike, 0, "> ");
+
+        // Nothing is redacted if the file is damaged.
+        let mut damaged = data.clone();
+        *damaged.last_mut().unwrap() ^= 1;
+        assert!(other.decode_and_add(&damaged).is_err());
+        assert!(other.redactions().is_empty());
+        assert!(other.iter().any(|op| op.content.is_some_and(|c| c.contains("hunter"))));
+
+        other.decode_and_add(&data).unwrap();
+        assert_eq!(other.redactions(), &[(15..30).into()]);
+        assert_eq!(other.checkout_tip().content().to_string(), format!("> my password is ****{REDACTED_CHAR}"));
+        assert!(!other.iter().any(|op| op.content.is_some_and(|c| c.contains("hunter"))));
+    }
+}
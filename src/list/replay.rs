@@ -0,0 +1,143 @@
+//! Replay a document's history forwards, yielding the document at each step. This is useful for
+//! "playback" views which animate a document being written.
+//!
+//! Checking out each version separately replays the history from scratch every time, which is
+//! O(n^2) in the length of the history. [`ListOpLog::replay_states`] instead advances a single
+//! branch through the history, and only merges the new operations at each step.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use crate::{Frontier, LV};
+use crate::list::{ListBranch, ListOpLog};
+use crate::list::operation::TextOperation;
+
+/// How far to advance through the history between each state yielded by
+/// [`ListOpLog::replay_states`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ReplayStep {
+    /// Yield a state after every `n` operations (by local version).
+    Ops(usize),
+
+    /// Yield a state after each chunk of changes returned by
+    /// [`history_chunks`](ListOpLog::history_chunks).
+    Chunks,
+}
+
+/// The document at one step of a replay.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReplayState {
+    /// The version of the document. This contains every operation in the oplog up to the end of
+    /// the step.
+    pub version: Frontier,
+
+    /// The operations which moved the document from the previous state to this one, transformed
+    /// so they can be applied in order to the previous state's content.
+    pub ops: Vec<TextOperation>,
+
+    /// The content of the document at this version.
+    pub content: String,
+}
+
+/// Iterator returned by [`ListOpLog::replay_states`].
+#[derive(Debug)]
+pub struct ReplayStates<'a> {
+    oplog: &'a ListOpLog,
+    branch: ListBranch,
+    step: ReplayStep,
+    /// The end of each step, when stepping by chunks. Stored in reverse order.
+    chunk_ends: Vec<LV>,
+    /// All operations before this LV have been replayed.
+    next_lv: LV,
+}
+
+impl ListOpLog {
+    /// Replay the oplog's history from the start, walking forward through the operations in
+    /// local version order. The iterator yields the state of the document after each step.
+    ///
+    /// Each step only merges the operations in that step into a single internal branch, so
+    /// replaying the entire history is about as fast as checking out the document once (plus
+    /// copying the content at each step).
+    ///
+    /// # Panics
+    ///
+    /// Panics if step is `ReplayStep::Ops(0)`.
+    pub fn replay_states(&self, step: ReplayStep) -> ReplayStates<'_> {
+        assert_ne!(step, ReplayStep::Ops(0), "Cannot replay in steps of 0 operations");
+
+        let chunk_ends = if step == ReplayStep::Chunks {
+            self.history_chunks().iter().rev().map(|chunk| chunk.span.end).collect()
+        } else { vec![] };
+
+        ReplayStates {
+            oplog: self,
+            branch: ListBranch::new(),
+            step,
+            chunk_ends,
+            next_lv: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for ReplayStates<'a> {
+    type Item = ReplayState;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = self.oplog.len();
+        if self.next_lv >= len { return None; }
+
+        let end = match self.step {
+            ReplayStep::Ops(n) => (self.next_lv + n).min(len),
+            ReplayStep::Chunks => self.chunk_ends.pop()?,
+        };
+
+        let mut version = self.branch.local_frontier();
+        version.advance(&self.oplog.cg.graph, (self.next_lv..end).into());
+        self.next_lv = end;
+
+        let ops = self.branch.merge_and_collect(self.oplog, version.as_ref());
+        Some(ReplayState {
+            version,
+            ops,
+            content: self.branch.content().to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::{ListBranch, ListOpLog};
+    use crate::list::replay::ReplayStep;
+
+    #[test]
+    fn replay_states() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "hello");
+        let b = oplog.add_insert_at(seph, &[a], 5, " world");
+        let c = oplog.add_insert_at(mike, &[a], 0, "yo ");
+        oplog.add_delete_at(seph, &[b, c], 0..3);
+
+        for step in [ReplayStep::Ops(1), ReplayStep::Ops(4), ReplayStep::Chunks] {
+            let mut prev = ListBranch::new();
+            let mut last_end = 0;
+            for state in oplog.replay_states(step) {
+                assert_eq!(state.content, oplog.checkout(state.version.as_ref()).content().to_string());
+                // Every operation up to the end of the step is included.
+                let end = (last_end..oplog.len())
+                    .find(|lv| !oplog.cg.graph.frontier_contains_version(state.version.as_ref(), *lv))
+                    .unwrap_or(oplog.len());
+                assert!(end > last_end);
+                last_end = end;
+
+                prev.apply(&state.ops);
+                assert_eq!(prev.content().to_string(), state.content);
+            }
+            assert_eq!(last_end, oplog.len());
+            assert_eq!(prev.content().to_string(), "hello world");
+        }
+
+        assert_eq!(oplog.replay_states(ReplayStep::Ops(1)).count(), oplog.len());
+        assert_eq!(oplog.replay_states(ReplayStep::Chunks).count(), oplog.history_chunks().len());
+    }
+}
@@ -0,0 +1,262 @@
+//! A repository is a collection of documents, keyed by document ID.
+//!
+//! Applications often have hundreds of small documents, all edited by the same user. Syncing each
+//! document individually means a separate network round trip (and a separate file header) for
+//! every document. A [`ListRepo`] lets all the documents be synced and saved together:
+//!
+//! - All documents are edited using a single agent name
+//! - [`ListRepo::summarize_versions`] summarizes the versions of every document at once
+//! - [`ListRepo::encode_missing`] packs every change a remote peer is missing into one blob
+//! - [`ListRepo::merge_data`] merges that blob (or a whole saved repository) back in.
+//!
+//! So syncing the entire repository only needs a single round trip: send our summary to a peer,
+//! and merge in whatever they send back.
+
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
+use smartstring::alias::String as SmartString;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use crate::{AgentId, Frontier};
+use crate::causalgraph::summary::VersionSummary;
+use crate::encoding::bufparser::BufParser;
+use crate::encoding::parseerror::ParseError;
+use crate::encoding::tools::push_str;
+use crate::encoding::varint::push_usize;
+use crate::list::encoding::{ENCODE_PATCH, EncodeOptions};
+use crate::list::ListOpLog;
+
+const REPO_MAGIC_BYTES: [u8; 8] = *b"DMNDREPO";
+
+const REPO_PROTOCOL_VERSION: usize = 0;
+
+/// A set of documents which are edited and synchronized together.
+#[derive(Debug, Clone)]
+pub struct ListRepo {
+    /// The agent name used for all local edits.
+    agent_name: SmartString,
+    docs: BTreeMap<SmartString, ListOpLog>,
+}
+
+/// A version summary for every document in a repository. See
+/// [`ListRepo::summarize_versions`].
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RepoVersionSummary(BTreeMap<SmartString, VersionSummary>);
+
+impl RepoVersionSummary {
+    /// Get the version summary for a single document, if the repository contains it.
+    pub fn get(&self, doc_id: &str) -> Option<&VersionSummary> {
+        self.0.get(doc_id)
+    }
+}
+
+impl ListRepo {
+    /// Create a new, empty repository. Local edits to any document will use the named agent.
+    pub fn new(agent_name: &str) -> Self {
+        Self {
+            agent_name: agent_name.into(),
+            docs: BTreeMap::new(),
+        }
+    }
+
+    pub fn agent_name(&self) -> &str {
+        &self.agent_name
+    }
+
+    /// The number of documents in the repository.
+    pub fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.docs.is_empty()
+    }
+
+    pub fn contains(&self, doc_id: &str) -> bool {
+        self.docs.contains_key(doc_id)
+    }
+
+    /// Iterate through the IDs of all documents in the repository, in sorted order.
+    pub fn doc_ids(&self) -> impl Iterator<Item = &str> + '_ {
+        self.docs.keys().map(|id| id.as_str())
+    }
+
+    pub fn get(&self, doc_id: &str) -> Option<&ListOpLog> {
+        self.docs.get(doc_id)
+    }
+
+    fn get_or_create_doc(&mut self, doc_id: &str) -> &mut ListOpLog {
+        self.docs.entry(doc_id.into()).or_insert_with(|| {
+            let mut oplog = ListOpLog::new();
+            oplog.doc_id = Some(doc_id.into());
+            oplog
+        })
+    }
+
+    /// Get a document for editing, creating it if it doesn't exist yet. This also returns the
+    /// local agent's ID within that document.
+    pub fn get_or_create(&mut self, doc_id: &str) -> (&mut ListOpLog, AgentId) {
+        let agent_name = self.agent_name.clone();
+        let oplog = self.get_or_create_doc(doc_id);
+        let agent = oplog.get_or_create_agent_id(&agent_name);
+        (oplog, agent)
+    }
+
+    /// Remove a document from the repository, returning it.
+    pub fn remove(&mut self, doc_id: &str) -> Option<ListOpLog> {
+        self.docs.remove(doc_id)
+    }
+
+    /// Summarize the versions of every document in the repository. Send this to a remote peer,
+    /// and the peer can reply with [`encode_missing`](ListRepo::encode_missing).
+    pub fn summarize_versions(&self) -> RepoVersionSummary {
+        RepoVersionSummary(self.docs.iter().map(|(id, oplog)| {
+            (id.clone(), oplog.cg.agent_assignment.summarize_versions())
+        }).collect())
+    }
+
+    fn encode_docs<'a, I>(docs: I) -> Vec<u8> where I: Iterator<Item = (&'a str, Vec<u8>)> {
+        let mut result = Vec::new();
+        result.extend_from_slice(&REPO_MAGIC_BYTES);
+        push_usize(&mut result, REPO_PROTOCOL_VERSION);
+
+        let mut body = Vec::new();
+        let mut count = 0;
+        for (id, data) in docs {
+            push_str(&mut body, id);
+            push_usize(&mut body, data.len());
+            body.extend_from_slice(&data);
+            count += 1;
+        }
+
+        push_usize(&mut result, count);
+        result.extend_from_slice(&body);
+        result
+    }
+
+    /// Encode every document in the repository into a single file.
+    pub fn encode(&self, opts: EncodeOptions) -> Vec<u8> {
+        Self::encode_docs(self.docs.iter().map(|(id, oplog)| {
+            (id.as_str(), oplog.encode(opts.clone()))
+        }))
+    }
+
+    /// Encode all the changes which are missing from a remote peer, given the peer's version
+    /// summary. Documents the peer doesn't know about are sent in full, and documents where the
+    /// peer is already up to date are skipped entirely.
+    ///
+    /// The result can be merged into the remote repository using
+    /// [`merge_data`](ListRepo::merge_data).
+    pub fn encode_missing(&self, remote: &RepoVersionSummary) -> Vec<u8> {
+        Self::encode_docs(self.docs.iter().filter_map(|(id, oplog)| {
+            let common = match remote.get(id) {
+                Some(summary) => {
+                    let common = oplog.cg.intersect_with_summary(summary, &[]).0;
+                    if common == oplog.cg.version { return None; }
+                    common
+                }
+                None => Frontier::root(),
+            };
+
+            Some((id.as_str(), oplog.encode_from(ENCODE_PATCH, common.as_ref())))
+        }))
+    }
+
+    /// Merge data produced by [`encode`](ListRepo::encode) or
+    /// [`encode_missing`](ListRepo::encode_missing) into this repository. Any documents which
+    /// don't exist locally are created.
+    ///
+    /// If an error occurs, documents before the one which failed to parse will have already been
+    /// merged.
+    pub fn merge_data(&mut self, bytes: &[u8]) -> Result<(), ParseError> {
+        let mut reader = BufParser(bytes);
+        if reader.next_n_bytes(REPO_MAGIC_BYTES.len())? != REPO_MAGIC_BYTES {
+            return Err(ParseError::InvalidMagic);
+        }
+        if reader.next_usize()? != REPO_PROTOCOL_VERSION {
+            return Err(ParseError::UnsupportedProtocolVersion);
+        }
+
+        let count = reader.next_usize()?;
+        for _ in 0..count {
+            let id = reader.next_str()?;
+            let len = reader.next_usize()?;
+            let data = reader.next_n_bytes(len)?;
+            self.get_or_create_doc(id).decode_and_add(data)?;
+        }
+        reader.expect_empty()
+    }
+
+    /// Load a repository from data produced by [`encode`](ListRepo::encode).
+    pub fn load_from(agent_name: &str, bytes: &[u8]) -> Result<Self, ParseError> {
+        let mut repo = Self::new(agent_name);
+        repo.merge_data(bytes)?;
+        Ok(repo)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::encoding::ENCODE_FULL;
+    use crate::list::repo::ListRepo;
+    use crate::encoding::parseerror::ParseError;
+
+    #[test]
+    fn repo_round_trip() {
+        let mut repo = ListRepo::new("seph");
+        for i in 0..10 {
+            let (doc, seph) = repo.get_or_create(&format!("doc{i}"));
+            doc.add_insert(seph, 0, "hi there");
+        }
+        let (doc, seph) = repo.get_or_create("doc3");
+        doc.add_delete_without_content(seph, 0..3);
+
+        let bytes = repo.encode(ENCODE_FULL);
+        let loaded = ListRepo::load_from("mike", &bytes).unwrap();
+        assert_eq!(loaded.len(), 10);
+        for id in repo.doc_ids() {
+            assert_eq!(repo.get(id).unwrap(), loaded.get(id).unwrap());
+        }
+        assert_eq!(loaded.get("doc3").unwrap().checkout_tip().content().to_string(), "there");
+
+        assert_eq!(ListRepo::load_from("mike", &bytes[1..]).unwrap_err(), ParseError::InvalidMagic);
+    }
+
+    #[test]
+    fn repo_sync() {
+        let mut a = ListRepo::new("seph");
+        let mut b = ListRepo::new("mike");
+        for i in 0..5 {
+            let (doc, seph) = a.get_or_create(&format!("doc{i}"));
+            doc.add_insert(seph, 0, "aaa");
+        }
+        b.merge_data(&a.encode_missing(&b.summarize_versions())).unwrap();
+        assert_eq!(b.len(), 5);
+
+        // Concurrent edits to some documents, plus a new document on each side.
+        let (doc, seph) = a.get_or_create("doc1");
+        doc.add_insert(seph, 0, "x");
+        a.get_or_create("only_a");
+        let (doc, mike) = b.get_or_create("doc1");
+        doc.add_insert(mike, 3, "y");
+        let (doc, mike) = b.get_or_create("only_b");
+        doc.add_insert(mike, 0, "b");
+
+        // A single round trip in each direction.
+        let a_to_b = a.encode_missing(&b.summarize_versions());
+        let b_to_a = b.encode_missing(&a.summarize_versions());
+        a.merge_data(&b_to_a).unwrap();
+        b.merge_data(&a_to_b).unwrap();
+
+        assert_eq!(a.summarize_versions(), b.summarize_versions());
+        assert_eq!(a.doc_ids().collect::<Vec<_>>(), b.doc_ids().collect::<Vec<_>>());
+        for id in a.doc_ids() {
+            assert_eq!(a.get(id).unwrap().checkout_tip(), b.get(id).unwrap().checkout_tip());
+        }
+        assert_eq!(a.get("doc1").unwrap().checkout_tip().content().to_string(), "xaaay");
+
+        // Nothing left to send.
+        assert_eq!(a.encode_missing(&b.summarize_versions()), ListRepo::new("x").encode(ENCODE_FULL));
+    }
+}
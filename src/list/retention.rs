@@ -0,0 +1,201 @@
+//! Retention of deleted content.
+//!
+//! By default the oplog stores the content of every delete, so the text can be restored (eg by
+//! undo) or shown in history views. Deleted content is never needed to merge changes - deletes
+//! only name the characters they remove by position. So an application can choose to throw it
+//! away, either to save space or because deleted text shouldn't be kept around.
+//!
+//! A [`DeletedContentPolicy`] controls whether the content of new deletes is recorded. Content
+//! which is already stored can be removed with [`ListOpLog::purge_deleted_content`]. Encoded files
+//! note that content has been purged (see
+//! [`FormatFeatures::DELETED_CONTENT_PURGED`](crate::list::encoding::FormatFeatures::DELETED_CONTENT_PURGED)),
+//! so readers can tell purged content apart from content which simply wasn't saved.
+//!
+//! This API doesn't read the clock. Under [`DeletedContentPolicy::KeepForDuration`], the
+//! application calls [`ListOpLog::expire_deleted_content`] every so often with the current time.
+
+use alloc::vec::Vec;
+use crate::{DTRange, LV};
+use crate::list::ListOpLog;
+use crate::list::operation::ListOpKind;
+
+/// Which deleted content the oplog keeps.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum DeletedContentPolicy {
+    /// Store the content of all deletes. This is the default.
+    #[default]
+    KeepAll,
+    /// Never store the content of deletes.
+    KeepNone,
+    /// Store the content of deletes, but purge it once the delete has been in the oplog for the
+    /// specified duration. Durations are measured in the same units as the `now` passed to
+    /// [`ListOpLog::expire_deleted_content`].
+    KeepForDuration(u64),
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Retention {
+    pub(crate) policy: DeletedContentPolicy,
+
+    /// (oplog length, time) for each call to expire_deleted_content which saw new operations.
+    /// Operations before the length were in the oplog at that time.
+    seen: Vec<(LV, u64)>,
+
+    /// Deleted content before this LV has already been expired.
+    expired_upto: LV,
+
+    /// Set once any deleted content has been purged, from this oplog or a file it loaded.
+    pub(crate) purged: bool,
+}
+
+impl ListOpLog {
+    /// Set which deleted content the oplog keeps. This only changes how deletes added from now on
+    /// are stored - use [`purge_deleted_content`](ListOpLog::purge_deleted_content) to remove
+    /// content the oplog already has.
+    pub fn set_deleted_content_policy(&mut self, policy: DeletedContentPolicy) {
+        self.retention.policy = policy;
+    }
+
+    /// The oplog's deleted content policy. See
+    /// [`set_deleted_content_policy`](ListOpLog::set_deleted_content_policy).
+    pub fn deleted_content_policy(&self) -> DeletedContentPolicy {
+        self.retention.policy
+    }
+
+    /// True if deleted content has been purged from this oplog (or from a file merged into it).
+    pub fn has_purged_deleted_content(&self) -> bool {
+        self.retention.purged
+    }
+
+    /// Remove the content of all deletes from the oplog.
+    pub fn purge_deleted_content(&mut self) {
+        self.purge_deleted_content_in((0..self.len()).into());
+    }
+
+    /// Remove the content of the deletes in the specified range of local versions.
+    pub fn purge_deleted_content_in(&mut self, range: DTRange) {
+        if self.rewrite_content(range, ListOpKind::Del, |_, _| None) {
+            self.retention.purged = true;
+        }
+    }
+
+    /// Apply the [`KeepForDuration`](DeletedContentPolicy::KeepForDuration) policy. This purges the
+    /// content of deletes which have been in the oplog for longer than the policy's duration.
+    ///
+    /// The oplog doesn't know when operations were added, so each call also records that every
+    /// operation currently in the oplog was present at `now`. A delete's age is measured from the
+    /// first call after it was added, so this should be called regularly (eg after merging remote
+    /// changes). This does nothing under other policies.
+    pub fn expire_deleted_content(&mut self, now: u64) {
+        let DeletedContentPolicy::KeepForDuration(duration) = self.retention.policy else { return; };
+
+        let len = self.len();
+        if self.retention.seen.last().map_or(self.retention.expired_upto, |(v, _)| *v) < len {
+            self.retention.seen.push((len, now));
+        }
+
+        let expired = self.retention.seen.iter()
+            .take_while(|(_, time)| time.saturating_add(duration) <= now)
+            .count();
+        if expired == 0 { return; }
+
+        let end = self.retention.seen[expired - 1].0;
+        self.retention.seen.drain(..expired);
+        let start = self.retention.expired_upto;
+        self.retention.expired_upto = end;
+        self.purge_deleted_content_in((start..end).into());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::encoding::{EncodeOptions, FormatFeatures};
+    use crate::list::{ListCRDT, ListOpLog};
+    use crate::list::operation::ListOpKind;
+    use crate::list::retention::DeletedContentPolicy;
+
+    fn deleted_content(oplog: &ListOpLog) -> Vec<Option<String>> {
+        oplog.iter()
+            .filter(|op| op.kind == ListOpKind::Del)
+            .map(|op| op.content.map(|c| c.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn keep_none_drops_new_deletes() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "hello world");
+        doc.delete(seph, 0..1);
+        doc.oplog.set_deleted_content_policy(DeletedContentPolicy::KeepNone);
+        doc.delete(seph, 4..10);
+
+        assert_eq!(deleted_content(&doc.oplog), vec![Some("h".into()), None]);
+        assert_eq!(doc.oplog.checkout_tip().content().to_string(), "ello");
+        assert!(!doc.oplog.has_purged_deleted_content());
+    }
+
+    #[test]
+    fn purge_keeps_merges_correct() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "hello secret world");
+        doc.delete(seph, 6..13);
+        doc.insert(seph, 0, "ok ");
+        doc.delete(seph, 3..9);
+        let oplog = doc.oplog;
+
+        let mut purged = oplog.clone();
+        // Purge part of the first delete. This splits it.
+        purged.purge_deleted_content_in((18..22).into());
+        assert_eq!(deleted_content(&purged), vec![None, Some("et ".into()), Some("hello ".into())]);
+        purged.purge_deleted_content();
+        assert_eq!(deleted_content(&purged), vec![None, None, None]);
+        assert!(purged.has_purged_deleted_content());
+        assert!(purged.operation_ctx.del_content.is_empty());
+
+        assert_eq!(purged.checkout_tip().content().to_string(), "ok world");
+        for v in 0..oplog.len() {
+            assert_eq!(purged.len_at(&[v]), oplog.len_at(&[v]));
+        }
+
+        // Encoded files note the purge.
+        let data = purged.encode(EncodeOptions { store_deleted_content: true, ..EncodeOptions::default() });
+        let info = ListOpLog::file_info(&data).unwrap();
+        assert!(info.features.contains(FormatFeatures::DELETED_CONTENT_PURGED));
+        assert!(info.is_supported());
+        let loaded = ListOpLog::load_from(&data).unwrap();
+        assert!(loaded.has_purged_deleted_content());
+        assert_eq!(deleted_content(&loaded), vec![None, None]);
+        assert_eq!(loaded.checkout_tip().content().to_string(), "ok world");
+
+        // And the purged oplog still merges with peers which kept the content.
+        let mut other = oplog.clone();
+        let mike = other.get_or_create_agent_id("mike");
+        other.add_insert_at(mike, &[20], 6, "!");
+        let mut merged = purged.clone();
+        merged.decode_and_add(&other.encode(EncodeOptions::default())).unwrap();
+        assert_eq!(merged.checkout_tip().content().to_string(), other.checkout_tip().content().to_string());
+    }
+
+    #[test]
+    fn keep_for_duration() {
+        let mut doc = ListCRDT::new();
+        doc.oplog.set_deleted_content_policy(DeletedContentPolicy::KeepForDuration(100));
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "abcdef");
+        doc.delete(seph, 0..1);
+        doc.oplog.expire_deleted_content(1000);
+        doc.delete(seph, 0..1);
+        doc.oplog.expire_deleted_content(1050);
+        assert_eq!(deleted_content(&doc.oplog), vec![Some("ab".into())]);
+
+        doc.oplog.expire_deleted_content(1100);
+        assert_eq!(deleted_content(&doc.oplog), vec![None, Some("b".into())]);
+        doc.oplog.expire_deleted_content(1149);
+        assert_eq!(deleted_content(&doc.oplog), vec![None, Some("b".into())]);
+        doc.oplog.expire_deleted_content(1150);
+        assert_eq!(deleted_content(&doc.oplog), vec![None, None]);
+        assert_eq!(doc.oplog.checkout_tip().content().to_string(), "cdef");
+    }
+}
@@ -0,0 +1,229 @@
+//! Human friendly diffs between two versions of a document, for display.
+//!
+//! A generic text diff (like Myers' algorithm) has to compare the full content of both versions.
+//! But the oplog already knows exactly which parts of the document changed. So
+//! [`ListOpLog::semantic_diff`] finds the changed regions from the operations between the two
+//! versions, and only compares the content inside those regions. The resulting hunks are then
+//! widened to word or line boundaries, so they're easier to read.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+use rle::HasLength;
+use crate::LV;
+use crate::list::ListOpLog;
+use crate::list::merge::ChangedRegions;
+
+/// The unit of change in a [`semantic_diff`](ListOpLog::semantic_diff).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DiffGranularity {
+    /// Hunks contain exactly the changed characters.
+    Char,
+    /// Hunks are widened so they don't start or end partway through a word.
+    Word,
+    /// Hunks are widened to contain whole lines, including the trailing newline.
+    Line,
+}
+
+impl DiffGranularity {
+    /// Is a hunk allowed to start or end between before and after?
+    fn is_boundary(self, before: Option<char>, after: Option<char>) -> bool {
+        let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+        match self {
+            DiffGranularity::Char => true,
+            DiffGranularity::Word => !(is_word(before) && is_word(after)),
+            DiffGranularity::Line => !matches!(before, Some(c) if c != '\n'),
+        }
+    }
+
+    fn is_boundary_at(self, text: &[char], pos: usize) -> bool {
+        self.is_boundary(pos.checked_sub(1).map(|p| text[p]), text.get(pos).copied())
+    }
+}
+
+/// A region of the document which differs between two versions. Content outside the diff's
+/// hunks is the same in both versions.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DiffHunk {
+    /// The range of the hunk in the old document (at version a). This is empty for insertions.
+    pub old_range: Range<usize>,
+    /// The range of the hunk in the new document (at version b). This is empty for deletions.
+    pub new_range: Range<usize>,
+    pub old_content: String,
+    pub new_content: String,
+}
+
+impl ListOpLog {
+    /// Track the regions of the document at `from` changed by the operations needed to get to `to`.
+    fn changed_regions_between(&self, from: &[LV], to: &[LV]) -> ChangedRegions {
+        let mut regions = ChangedRegions::new();
+        for (_, op) in self.iter_xf_operations_ref_from(from, to) {
+            if let Some(op) = op {
+                regions.apply(op.kind, op.start(), op.len());
+            }
+        }
+        regions
+    }
+
+    /// Compare the document at versions `a` and `b`, and list the hunks which changed. Hunks are
+    /// sorted, and positions are in unicode characters.
+    ///
+    /// Adjacent inserts and deletes are coalesced into a single hunk, and content which was
+    /// deleted and reinserted unchanged is left out. Then the hunks are widened to the requested
+    /// granularity. Hunks which end up touching are merged.
+    ///
+    /// The versions don't need to be related. Changes are found using the operations since the
+    /// common ancestor of `a` and `b`, so this is fast when the versions are close together in
+    /// the history.
+    pub fn semantic_diff(&self, a: &[LV], b: &[LV], granularity: DiffGranularity) -> Vec<DiffHunk> {
+        let common = self.cg.graph.common_ancestor(a, b);
+        let base = self.checkout(common.as_ref());
+        let mut branch_a = base.clone();
+        branch_a.merge(self, a);
+        let mut branch_b = base.clone();
+        branch_b.merge(self, b);
+
+        let base_len = base.len();
+        let a_chars: Vec<char> = branch_a.content().chars().collect();
+        let b_chars: Vec<char> = branch_b.content().chars().collect();
+
+        let regions_a = self.changed_regions_between(common.as_ref(), a);
+        let regions_b = self.changed_regions_between(common.as_ref(), b);
+
+        // Content outside the regions changed on either side is the same in both documents.
+        let mut regions: Vec<Range<usize>> = regions_a.clone().into_regions();
+        regions.extend(regions_b.clone().into_regions());
+        regions.sort_unstable_by_key(|r| r.start);
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(regions.len());
+        for r in regions {
+            match merged.last_mut() {
+                Some(last) if r.start <= last.end => { last.end = last.end.max(r.end); }
+                _ => merged.push(r),
+            }
+        }
+
+        // Map each region to both documents using the unchanged characters on either side of it.
+        let map_range = |regions: &ChangedRegions, r: &Range<usize>, len: usize| -> Range<usize> {
+            let start = if r.start == 0 { 0 } else { regions.current_pos(r.start - 1) + 1 };
+            let end = if r.end == base_len { len } else { regions.current_pos(r.end) };
+            start..end
+        };
+
+        let mut hunks: Vec<(Range<usize>, Range<usize>)> = vec![];
+        for r in merged.iter() {
+            let mut old = map_range(&regions_a, r, a_chars.len());
+            let mut new = map_range(&regions_b, r, b_chars.len());
+
+            // Trim content which is the same in both versions.
+            let prefix = a_chars[old.clone()].iter().zip(b_chars[new.clone()].iter())
+                .take_while(|(x, y)| x == y).count();
+            old.start += prefix;
+            new.start += prefix;
+            let suffix = a_chars[old.clone()].iter().rev().zip(b_chars[new.clone()].iter().rev())
+                .take_while(|(x, y)| x == y).count();
+            old.end -= suffix;
+            new.end -= suffix;
+
+            if !old.is_empty() || !new.is_empty() { hunks.push((old, new)); }
+        }
+
+        // Widen the hunks. Between hunks, the two documents match character for character.
+        let mut result: Vec<(Range<usize>, Range<usize>)> = Vec::with_capacity(hunks.len());
+        for i in 0..hunks.len() {
+            let (mut old, mut new) = hunks[i].clone();
+            let lower = result.last().map_or(0, |(old, _)| old.end);
+            let upper = hunks.get(i + 1).map_or(a_chars.len(), |(old, _)| old.start);
+
+            while old.start > lower && !(granularity.is_boundary_at(&a_chars, old.start)
+                && granularity.is_boundary_at(&b_chars, new.start)) {
+                old.start -= 1;
+                new.start -= 1;
+            }
+            while old.end < upper && !(granularity.is_boundary_at(&a_chars, old.end)
+                && granularity.is_boundary_at(&b_chars, new.end)) {
+                old.end += 1;
+                new.end += 1;
+            }
+
+            match result.last_mut() {
+                Some((last_old, last_new)) if old.start <= last_old.end => {
+                    last_old.end = old.end;
+                    last_new.end = new.end;
+                }
+                _ => result.push((old, new)),
+            }
+        }
+
+        result.into_iter().map(|(old, new)| DiffHunk {
+            old_content: a_chars[old.clone()].iter().collect(),
+            new_content: b_chars[new.clone()].iter().collect(),
+            old_range: old,
+            new_range: new,
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::ListOpLog;
+    use crate::list::semantic_diff::{DiffGranularity, DiffHunk};
+
+    fn hunks(diff: &[DiffHunk]) -> Vec<(&str, &str)> {
+        diff.iter().map(|h| (h.old_content.as_str(), h.new_content.as_str())).collect()
+    }
+
+    fn check_diff(oplog: &ListOpLog, a: &[usize], b: &[usize], granularity: DiffGranularity) -> Vec<DiffHunk> {
+        let diff = oplog.semantic_diff(a, b, granularity);
+        let old = oplog.checkout(a).content().to_string();
+        let new = oplog.checkout(b).content().to_string();
+
+        // Applying the hunks to the old document gives the new document.
+        let mut result: Vec<char> = old.chars().collect();
+        for hunk in diff.iter().rev() {
+            assert_eq!(result[hunk.old_range.clone()].iter().collect::<String>(), hunk.old_content);
+            result.splice(hunk.old_range.clone(), hunk.new_content.chars());
+        }
+        assert_eq!(result.into_iter().collect::<String>(), new);
+
+        let new_chars: Vec<char> = new.chars().collect();
+        for hunk in diff.iter() {
+            assert_eq!(new_chars[hunk.new_range.clone()].iter().collect::<String>(), hunk.new_content);
+        }
+        diff
+    }
+
+    #[test]
+    fn semantic_diff() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "the quick fox\njumps over\nthe dog\n");
+        oplog.add_insert(seph, 4, "very ");
+        oplog.add_delete_without_content(seph, 9..14);
+        oplog.add_insert(seph, 9, "slow");
+        // Delete "jumps" and put it back.
+        oplog.add_delete_without_content(seph, 18..23);
+        oplog.add_insert(seph, 18, "jumps");
+        oplog.add_delete_without_content(seph, 24..25);
+        let b = oplog.add_insert(seph, 24, "e");
+        let c = oplog.add_insert_at(mike, &[a], 29, "lazy ");
+
+        use DiffGranularity::*;
+        assert_eq!(hunks(&check_diff(&oplog, &[a], &[b], Char)), vec![("quick", "very slow"), ("o", "e")]);
+        assert_eq!(hunks(&check_diff(&oplog, &[a], &[b], Word)), vec![("quick", "very slow"), ("over", "ever")]);
+        assert_eq!(hunks(&check_diff(&oplog, &[a], &[b], Line)), vec![
+            ("the quick fox\njumps over\n", "the very slow fox\njumps ever\n"),
+        ]);
+
+        // Concurrent versions.
+        assert_eq!(hunks(&check_diff(&oplog, &[b], &[c], Char)), vec![("very slow", "quick"), ("e", "o"), ("", "lazy ")]);
+        assert_eq!(hunks(&check_diff(&oplog, &[c], &[b], Line)), vec![
+            ("the quick fox\njumps over\nthe lazy dog\n", "the very slow fox\njumps ever\nthe dog\n"),
+        ]);
+        for g in [Char, Word, Line] {
+            check_diff(&oplog, &[], &[b, c], g);
+            check_diff(&oplog, &[b, c], &[a], g);
+            assert!(oplog.semantic_diff(&[b], &[b], g).is_empty());
+        }
+    }
+}
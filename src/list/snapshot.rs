@@ -0,0 +1,135 @@
+//! Cheap, read-only snapshots of a [`ListBranch`].
+//!
+//! Editors often want to hand the current document to another thread (eg to render it, or diff it)
+//! after every keystroke. Cloning the branch does that, but it copies the whole document each time.
+//!
+//! [`ListBranch::snapshot`] instead returns a [`ListBranchSnapshot`]. Snapshots share an immutable
+//! copy of the document text (the *base*) with the branch, and with each other. As the branch is
+//! edited, it keeps a list of the edits made since the base was taken. Each snapshot holds the base
+//! and the edits made before it was taken, so making a snapshot only copies those edits. Once
+//! enough edits pile up, the next snapshot makes a fresh base.
+//!
+//! Snapshots are `Send` and `Sync`, and the work of applying the edits to the base happens when
+//! the snapshot's content is read - on whichever thread is reading it.
+
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+use jumprope::JumpRope;
+use crate::{Frontier, LV};
+use crate::list::ListBranch;
+use crate::list::operation::ListOpKind;
+use crate::list::operation::TextOperation;
+
+/// The number of edits a branch keeps on top of its snapshot base. Past this, the next snapshot
+/// copies the document to make a new base.
+const MAX_SNAPSHOT_EDITS: usize = 256;
+
+/// The state a branch keeps to make snapshots cheap. See the [module documentation](self).
+#[derive(Debug, Clone)]
+pub(crate) struct SnapshotBase {
+    text: Arc<str>,
+    edits: Vec<TextOperation>,
+}
+
+impl SnapshotBase {
+    /// Record an edit made to the branch. Returns false if there are too many edits, and the base
+    /// should be discarded.
+    pub(crate) fn record(&mut self, edit: TextOperation) -> bool {
+        if self.edits.len() >= MAX_SNAPSHOT_EDITS { return false; }
+        self.edits.push(edit);
+        true
+    }
+}
+
+/// A read-only view of a [`ListBranch`] at some version. See the
+/// [module documentation](self) for details.
+#[derive(Debug, Clone)]
+pub struct ListBranchSnapshot {
+    version: Frontier,
+    base: Arc<str>,
+    edits: Arc<[TextOperation]>,
+}
+
+impl ListBranchSnapshot {
+    /// The version of the branch when the snapshot was taken.
+    pub fn local_frontier_ref(&self) -> &[LV] { self.version.as_ref() }
+
+    /// The document's content, as a rope.
+    pub fn content(&self) -> JumpRope {
+        let mut rope = JumpRope::from(&*self.base);
+        for edit in self.edits.iter() {
+            match edit.kind {
+                ListOpKind::Ins => rope.insert(edit.start(), edit.content.as_ref().unwrap()),
+                ListOpKind::Del => rope.remove(edit.start()..edit.end()),
+            }
+        }
+        rope
+    }
+}
+
+impl Display for ListBranchSnapshot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        if self.edits.is_empty() {
+            f.write_str(&self.base)
+        } else {
+            Display::fmt(&self.content(), f)
+        }
+    }
+}
+
+impl ListBranch {
+    /// Take a read-only snapshot of the branch, which can be sent to other threads. This is much
+    /// cheaper than cloning the branch. See the [`snapshot`](crate::list::snapshot) module for
+    /// details.
+    pub fn snapshot(&mut self) -> ListBranchSnapshot {
+        let base = self.snapshot_base.get_or_insert_with(|| SnapshotBase {
+            text: self.content.to_string().into(),
+            edits: Vec::new(),
+        });
+
+        ListBranchSnapshot {
+            version: self.version.clone(),
+            base: base.text.clone(),
+            edits: base.edits.as_slice().into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::{ListBranch, ListOpLog};
+    use crate::list::snapshot::MAX_SNAPSHOT_EDITS;
+
+    #[test]
+    fn snapshots() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mut branch = ListBranch::new();
+        branch.insert(&mut oplog, seph, 0, "hello");
+
+        let a = branch.snapshot();
+        branch.insert(&mut oplog, seph, 5, " world");
+        branch.delete_without_content(&mut oplog, seph, 0..1);
+        let b = branch.snapshot();
+
+        // Snapshots aren't changed by later edits, and share their base.
+        assert_eq!(a.to_string(), "hello");
+        assert_eq!(b.to_string(), "ello world");
+        assert_eq!(b.local_frontier_ref(), branch.local_frontier_ref());
+        assert!(alloc::sync::Arc::ptr_eq(&a.base, &b.base));
+
+        // Lots of edits make a new base.
+        for i in 0..MAX_SNAPSHOT_EDITS {
+            branch.insert(&mut oplog, seph, i, "x");
+        }
+        let c = branch.snapshot();
+        assert!(c.edits.is_empty());
+        assert_eq!(c.content(), branch.content().clone());
+
+        // Snapshots can be read from other threads.
+        let handle = std::thread::spawn(move || b.to_string());
+        assert_eq!(handle.join().unwrap(), "ello world");
+    }
+}
@@ -0,0 +1,237 @@
+//! Squashing runs of operations in a document's history.
+//!
+//! Long-lived documents collect lots of small operations - each burst of typing, backspacing and
+//! retyping is stored exactly as it happened. [`ListOpLog::squash`] rewrites the history (a bit
+//! like `git rebase -i --autosquash`) into a new oplog where each linear run of operations by a
+//! single agent is replaced by the few operations which have the same net effect. Edits which
+//! cancel out disappear entirely.
+//!
+//! A run is only squashed if nothing is concurrent with it: every earlier operation is an ancestor
+//! of the run, and every later operation descends from the run's last version. So the document is
+//! unchanged at every version which survives - the versions in the middle of a squashed run are
+//! gone. The returned [`SquashRemap`] maps versions of the original oplog to the squashed oplog.
+//!
+//! The squashed oplog is a different history. Operations get new sequence numbers, so it can't be
+//! merged with peers which have the original. Every peer needs to switch to the squashed oplog
+//! together. Suggestions, marks and redaction records refer to operations by version, so they
+//! aren't copied.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use rle::HasLength;
+use crate::{DTRange, Frontier, LV};
+use crate::causalgraph::entry::CGEntry;
+use crate::list::{ListBranch, ListOpLog};
+use crate::list::edit_buffer::EditBuffer;
+use crate::list::operation::{ListOpKind, TextOperation};
+use crate::rle::KVPair;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum RemapSpan {
+    /// The versions were copied, starting at this version of the squashed oplog.
+    Copied(LV),
+    /// The versions were squashed. Only the last version survives, as this frontier of the
+    /// squashed oplog.
+    Squashed(Frontier),
+}
+
+/// Maps versions of an oplog to versions of its squashed copy. Returned by
+/// [`ListOpLog::squash`].
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SquashRemap {
+    spans: Vec<(DTRange, RemapSpan)>,
+}
+
+impl SquashRemap {
+    fn push(&mut self, range: DTRange, span: RemapSpan) {
+        if let (Some((last, RemapSpan::Copied(a))), RemapSpan::Copied(b)) = (self.spans.last_mut(), &span) {
+            if last.end == range.start && *a + last.len() == *b {
+                last.end = range.end;
+                return;
+            }
+        }
+        self.spans.push((range, span));
+    }
+
+    /// The version of the squashed oplog with the same document state as version `lv` of the
+    /// original. Returns None if the version was in the middle of a squashed run.
+    pub fn map_version(&self, lv: LV) -> Option<Frontier> {
+        let idx = self.spans.partition_point(|(range, _)| range.end <= lv);
+        match self.spans.get(idx)? {
+            (range, RemapSpan::Copied(start)) => Some(Frontier::new_1(start + lv - range.start)),
+            (range, RemapSpan::Squashed(frontier)) if lv == range.last() => Some(frontier.clone()),
+            _ => None,
+        }
+    }
+
+    /// Map a frontier of the original oplog to the squashed oplog. Returns None if any of its
+    /// versions didn't survive.
+    pub fn map_frontier(&self, frontier: &[LV]) -> Option<Frontier> {
+        let mut result = Vec::new();
+        for &v in frontier {
+            result.extend(self.map_version(v)?.iter().copied());
+        }
+        Some(Frontier::from_unsorted(&result))
+    }
+}
+
+impl ListOpLog {
+    /// Make a copy of this oplog where the linear runs of operations in the specified range of
+    /// local versions are squashed together. See the [module documentation](self) for details.
+    ///
+    /// Returns the squashed oplog, and a map from versions of this oplog to the squashed oplog.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the oplog's content is encrypted. Encrypted content depends on the IDs of the
+    /// operations, which squashing changes.
+    pub fn squash(&self, range: DTRange) -> (ListOpLog, SquashRemap) {
+        assert!(!self.content_encrypted, "Cannot squash encrypted content");
+
+        let mut result = ListOpLog::new_with_tie_break(self.tie_break);
+        result.doc_id = self.doc_id.clone();
+        result.max_op_len = self.max_op_len;
+        result.retention.policy = self.retention.policy;
+        result.agent_meta = self.agent_meta.clone();
+        result.agent_links = self.agent_links.clone();
+        for client in self.cg.agent_assignment.client_data.iter() {
+            result.get_or_create_agent_id(&client.name);
+        }
+
+        let entries: Vec<CGEntry> = self.cg.iter().collect();
+        // Runs in each entry must end at or before this bound, so every later operation descends
+        // from the end of the run.
+        let mut end_bound = vec![usize::MAX; entries.len()];
+        for i in (1..entries.len()).rev() {
+            let latest_parent = entries[i].parents.iter().max().map_or(0, |p| p + 1);
+            end_bound[i - 1] = end_bound[i].min(latest_parent);
+        }
+
+        let mut remap = SquashRemap::default();
+        // The frontier of all the operations before the current entry.
+        let mut prefix = Frontier::root();
+        // Used to read the content deleted by squashed runs.
+        let mut base = ListBranch::new();
+
+        for (entry, end_bound) in entries.iter().zip(end_bound) {
+            let agent = entry.span.agent;
+            let entry_range: DTRange = (entry.start..entry.start + entry.span.len()).into();
+
+            let run: DTRange = if entry.parents == prefix {
+                let start = range.start.clamp(entry_range.start, entry_range.end);
+                (start..entry_range.end.min(end_bound).min(range.end).max(start)).into()
+            } else {
+                (entry_range.start..entry_range.start).into()
+            };
+            prefix.advance_by_known_run(entry.parents.as_ref(), entry_range);
+
+            // All the parents survive: the middle of a squashed run can't be a parent of anything.
+            let mut parents = remap.map_frontier(entry.parents.as_ref()).unwrap();
+            let segments = [
+                (entry_range.start..run.start, false),
+                (run.start..run.end, true),
+                (run.end..entry_range.end, false),
+            ];
+            for (segment, squash) in segments {
+                if segment.is_empty() { continue; }
+                let segment: DTRange = segment.into();
+
+                let ops: Vec<TextOperation> = self.iter_range_simple(segment)
+                    .map(|(KVPair(_, op), content)| (op, content).into())
+                    .collect();
+                let can_squash = squash && ops.len() > 1
+                    && ops.iter().all(|op| op.kind == ListOpKind::Del || op.content.is_some());
+
+                if !can_squash {
+                    let last = result.add_operations_at(agent, parents.as_ref(), &ops);
+                    remap.push(segment, RemapSpan::Copied(last + 1 - segment.len()));
+                    parents = Frontier::new_1(last);
+                    continue;
+                }
+
+                let mut buf = EditBuffer::new(agent, u64::MAX, usize::MAX);
+                for op in ops.iter() {
+                    match op.kind {
+                        ListOpKind::Ins => buf.insert(op.start(), op.content_as_str().unwrap(), 0),
+                        ListOpKind::Del => buf.delete(op.start()..op.end(), 0),
+                    }
+                }
+
+                let base_version = if segment.start == entry.start { entry.parents.clone() } else { Frontier::new_1(segment.start - 1) };
+                base.merge(self, base_version.as_ref());
+                let mut net_ops = buf.take_operations(&base);
+                if !ops.iter().any(|op| op.kind == ListOpKind::Del && op.content.is_some()) {
+                    // Don't bring back deleted content which was never stored (or was purged).
+                    for op in net_ops.iter_mut().filter(|op| op.kind == ListOpKind::Del) {
+                        op.content = None;
+                    }
+                }
+
+                if !net_ops.is_empty() {
+                    parents = Frontier::new_1(result.add_operations_at(agent, parents.as_ref(), &net_ops));
+                }
+                remap.push(segment, RemapSpan::Squashed(parents.clone()));
+            }
+        }
+
+        (result, remap)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::string::ToString;
+    use crate::Frontier;
+    use crate::list::ListCRDT;
+
+    #[test]
+    fn squash_typing() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        let mike = doc.get_or_create_agent_id("mike");
+        doc.insert(seph, 0, "hello");
+        for (i, c) in " wrold".chars().enumerate() {
+            doc.insert(seph, 5 + i, &c.to_string());
+        }
+        doc.delete(seph, 7..11);
+        doc.insert(seph, 7, "orld");
+        let run_end = doc.oplog.len() - 1;
+
+        // Concurrent changes aren't squashed.
+        doc.oplog.add_insert_at(mike, &[run_end], 0, "> ");
+        doc.oplog.add_insert_at(seph, &[run_end], 11, "!");
+        doc.branch.merge(&doc.oplog, doc.oplog.local_frontier_ref());
+        let copied_end = doc.oplog.len();
+        doc.insert(seph, 14, "ab");
+        doc.delete(seph, 15..16);
+        doc.insert(seph, 15, "c");
+        let oplog = doc.oplog;
+
+        let (squashed, remap) = oplog.squash((0..oplog.len()).into());
+        squashed.dbg_check(true);
+        assert_eq!(squashed.iter().count(), 4);
+        assert_eq!(squashed.checkout_tip().content().to_string(), "> hello world!ac");
+        assert_eq!(remap.map_version(5), None);
+        assert_eq!(remap.map_frontier(oplog.local_frontier_ref()), Some(squashed.local_frontier()));
+        for v in 0..oplog.len() {
+            if let Some(f) = remap.map_version(v) {
+                assert_eq!(squashed.checkout(f.as_ref()).content(), oplog.checkout(&[v]).content());
+            }
+        }
+
+        // Only runs in the range are squashed.
+        let (partial, remap) = oplog.squash((0..copied_end).into());
+        assert_eq!(partial.checkout_tip().content(), oplog.checkout_tip().content());
+        assert_eq!(partial.iter().count(), 6);
+        assert_eq!(remap.map_version(oplog.len() - 2), Some(Frontier::new_1(partial.len() - 2)));
+
+        // Edits which cancel out disappear.
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "abc");
+        doc.delete(seph, 0..3);
+        let (squashed, remap) = doc.oplog.squash((0..6).into());
+        assert_eq!(squashed.len(), 0);
+        assert_eq!(remap.map_version(5), Some(Frontier::root()));
+    }
+}
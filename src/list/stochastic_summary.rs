@@ -1,5 +1,6 @@
 // WIP.
 
+use alloc::vec::Vec;
 use crate::causalgraph::agent_span::AgentVersion;
 use crate::list::ListOpLog;
 use crate::LV;
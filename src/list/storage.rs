@@ -0,0 +1,441 @@
+//! Simple append-only persistence for a list oplog.
+//!
+//! A [`DocFile`] stores an oplog on disk as a log of records. Each record contains a batch of
+//! operations, encoded using the normal diamond types binary format. When changes are made to the
+//! oplog, only the new operations are appended to the file. The file is periodically compacted by
+//! rewriting it with a single record containing the whole oplog.
+//!
+//! The file starts with the magic bytes `"DMNDTDOC"` and a 4 byte LE file version. Then each
+//! record is stored as:
+//!
+//! - The length of the record's data (4 bytes LE)
+//! - A CRC32c checksum of the data (4 bytes LE)
+//! - The data itself
+//!
+//! If the process crashes while appending, the last record in the file may be incomplete. This is
+//! detected and the incomplete record is discarded when the file is next opened. Any other
+//! corruption results in an error, and the file is left alone. In particular, a record which runs
+//! past the end of the file is only discarded if no complete record follows it - otherwise its
+//! length must have been corrupted.
+
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use crate::encoding::parseerror::ParseError;
+use crate::encoding::tools::calc_checksum;
+use crate::Frontier;
+use crate::list::encoding::{ChangeClocks, ENCODE_FULL, ENCODE_PATCH, EncodeOptions};
+use crate::list::ListOpLog;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DocFileError {
+    InvalidHeader,
+    /// A record in the file is corrupt. The offset names the start of the record.
+    Corrupt { offset: u64 },
+    ParseError(ParseError),
+    IO(io::Error),
+}
+
+impl Display for DocFileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DocFileError {:?}", self)
+    }
+}
+
+impl Error for DocFileError {}
+
+impl From<io::Error> for DocFileError {
+    fn from(io_err: io::Error) -> Self {
+        DocFileError::IO(io_err)
+    }
+}
+
+impl From<ParseError> for DocFileError {
+    fn from(pe: ParseError) -> Self {
+        DocFileError::ParseError(pe)
+    }
+}
+
+const DOC_MAGIC_BYTES: [u8; 8] = *b"DMNDTDOC";
+const DOC_VERSION: [u8; 4] = 1u32.to_le_bytes();
+const DOC_HEADER_LENGTH: usize = DOC_MAGIC_BYTES.len() + DOC_VERSION.len();
+const RECORD_HEADER_LENGTH: usize = 8;
+
+/// Deleted content is stored so the oplog is identical when it's loaded back.
+const RECORD_OPTS: EncodeOptions = EncodeOptions {
+    store_deleted_content: true,
+    ..ENCODE_PATCH
+};
+const SNAPSHOT_OPTS: EncodeOptions = EncodeOptions {
+    store_deleted_content: true,
+    ..ENCODE_FULL
+};
+
+/// An oplog stored on disk. See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct DocFile {
+    file: File,
+    path: PathBuf,
+
+    /// The version of the oplog which has been written to the file.
+    version: Frontier,
+    /// The oplog's change clocks when its marks and suggestions were last written to the file.
+    changes: ChangeClocks,
+
+    /// The number of records appended since the file was last compacted.
+    records: usize,
+
+    sync: bool,
+    compact_after: Option<usize>,
+}
+
+fn push_record(into: &mut Vec<u8>, data: &[u8]) {
+    into.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    into.extend_from_slice(&calc_checksum(data).to_le_bytes());
+    into.extend_from_slice(data);
+}
+
+/// Read the (length, checksum) header of the record at the start of bytes.
+fn read_record_header(bytes: &[u8]) -> Option<(usize, u32)> {
+    if bytes.len() < RECORD_HEADER_LENGTH { return None; }
+    let len = u32::from_le_bytes(bytes[0..4].try_into().unwrap()) as usize;
+    let checksum = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    Some((len, checksum))
+}
+
+/// Check if a complete, non-empty record with a valid checksum starts anywhere in bytes.
+fn contains_record(bytes: &[u8]) -> bool {
+    (0..bytes.len()).any(|i| match read_record_header(&bytes[i..]) {
+        Some((len, checksum)) if len > 0 && len <= bytes.len() - i - RECORD_HEADER_LENGTH => {
+            let start = i + RECORD_HEADER_LENGTH;
+            calc_checksum(&bytes[start..start + len]) == checksum
+        }
+        _ => false,
+    })
+}
+
+/// Sync the directory containing a file, so a rename of the file is persisted.
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+impl DocFile {
+    /// Open (or create) the named file, and load the oplog stored in it.
+    ///
+    /// If the last record in the file was only partially written, it is discarded and the file
+    /// is truncated to remove it.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<(ListOpLog, DocFile), DocFileError> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        let mut data = vec![];
+        file.read_to_end(&mut data)?;
+
+        if data.is_empty() {
+            file.write_all(&DOC_MAGIC_BYTES)?;
+            file.write_all(&DOC_VERSION)?;
+            file.sync_all()?;
+        } else if data.len() < DOC_HEADER_LENGTH || data[..8] != DOC_MAGIC_BYTES || data[8..12] != DOC_VERSION {
+            return Err(DocFileError::InvalidHeader);
+        }
+
+        let mut oplog = ListOpLog::new();
+        let mut records: usize = 0;
+        let mut pos = DOC_HEADER_LENGTH;
+
+        while pos < data.len() {
+            let rest = &data[pos..];
+            match read_record_header(rest) {
+                Some((len, checksum)) if len <= rest.len() - RECORD_HEADER_LENGTH => {
+                    let end = RECORD_HEADER_LENGTH + len;
+                    let record_data = &rest[RECORD_HEADER_LENGTH..end];
+
+                    if calc_checksum(record_data) != checksum {
+                        if end == rest.len() {
+                            // The last record was only partially written before a crash.
+                            break;
+                        }
+                        return Err(DocFileError::Corrupt { offset: pos as u64 });
+                    }
+
+                    oplog.decode_and_add(record_data)?;
+                    records += 1;
+                    pos += end;
+                }
+                // The record header or data is cut off by the end of the file. This happens if the
+                // process crashed while appending the record. But if there's another record after
+                // it, the header is corrupt and the record isn't really cut off.
+                _ if !contains_record(&rest[1..]) => break,
+                _ => return Err(DocFileError::Corrupt { offset: pos as u64 }),
+            }
+        }
+
+        if pos < data.len() {
+            file.set_len(pos as u64)?;
+            file.sync_all()?;
+        }
+        file.seek(SeekFrom::End(0))?;
+
+        let doc_file = DocFile {
+            file,
+            path,
+            version: oplog.cg.version.clone(),
+            changes: oplog.change_clocks(),
+            // The first record (if any) is the snapshot written by the last compaction.
+            records: records.saturating_sub(1),
+            sync: true,
+            compact_after: None,
+        };
+        Ok((oplog, doc_file))
+    }
+
+    /// Set whether appended records are flushed to disk (with fsync) before
+    /// [`append`](DocFile::append) returns. Defaults to true.
+    ///
+    /// Without syncing, appends are much faster but recent changes may be lost in a crash. Call
+    /// [`sync`](DocFile::sync) to flush them explicitly.
+    pub fn set_sync(&mut self, sync: bool) {
+        self.sync = sync;
+    }
+
+    /// Automatically [`compact`](DocFile::compact) the file once this many records have been
+    /// appended to it. Defaults to `None` (never).
+    pub fn set_compact_after(&mut self, records: Option<usize>) {
+        self.compact_after = records;
+    }
+
+    /// The number of records appended to the file since it was last compacted.
+    pub fn records_since_compaction(&self) -> usize {
+        self.records
+    }
+
+    /// Flush all appended records to disk.
+    pub fn sync(&mut self) -> Result<(), DocFileError> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Append all operations, marks and suggestions in the oplog which haven't been written to the
+    /// file yet.
+    ///
+    /// The oplog must contain everything previously loaded from or written to this file. Does
+    /// nothing if there are no new changes.
+    ///
+    /// Marks and suggestion states don't change the oplog's version, so their changes are found
+    /// using clocks local to the oplog. Only pass the oplog returned by [`open`](DocFile::open) (or
+    /// a clone of it), or some of those changes may not be saved.
+    pub fn append(&mut self, oplog: &ListOpLog) -> Result<(), DocFileError> {
+        let changes = oplog.change_clocks();
+        if oplog.cg.version == self.version && changes == self.changes { return Ok(()); }
+
+        let data = oplog.encode_from_changes_since(RECORD_OPTS, self.version.as_ref(), self.changes);
+        let mut record = Vec::with_capacity(RECORD_HEADER_LENGTH + data.len());
+        push_record(&mut record, &data);
+
+        let start = self.file.stream_position()?;
+        let result = self.file.write_all(&record).and_then(|_| {
+            if self.sync { self.file.sync_data() } else { Ok(()) }
+        });
+        if let Err(e) = result {
+            // Don't leave part of the record in the file. The next record would be appended after
+            // it, and then both would be discarded when the file is opened.
+            drop(self.file.set_len(start));
+            drop(self.file.seek(SeekFrom::Start(start)));
+            return Err(e.into());
+        }
+
+        self.version = oplog.cg.version.clone();
+        self.changes = changes;
+        self.records += 1;
+
+        if self.compact_after.is_some_and(|n| self.records >= n) {
+            self.compact(oplog)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the file with a single record containing the entire oplog. The new file is written
+    /// alongside the old one, then atomically renamed over it.
+    ///
+    /// The oplog must contain everything previously loaded from or written to this file.
+    pub fn compact(&mut self, oplog: &ListOpLog) -> Result<(), DocFileError> {
+        let mut tmp_path = OsString::from(&self.path);
+        tmp_path.push(".compact");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let data = oplog.encode(SNAPSHOT_OPTS);
+        let mut bytes = Vec::with_capacity(DOC_HEADER_LENGTH + RECORD_HEADER_LENGTH + data.len());
+        bytes.extend_from_slice(&DOC_MAGIC_BYTES);
+        bytes.extend_from_slice(&DOC_VERSION);
+        push_record(&mut bytes, &data);
+
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+
+        if let Err(e) = std::fs::rename(&tmp_path, &self.path) {
+            drop(std::fs::remove_file(&tmp_path));
+            return Err(e.into());
+        }
+        sync_parent_dir(&self.path)?;
+
+        self.file = file;
+        self.version = oplog.cg.version.clone();
+        self.changes = oplog.change_clocks();
+        self.records = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use crate::list::anchor::Anchor;
+    use crate::list::ListOpLog;
+    use crate::list::marks::{MarkExpand, MarkRange};
+    use crate::list::storage::{DocFile, DocFileError};
+
+    fn test_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("dt-{}-{name}.dtdoc", std::process::id()));
+        drop(std::fs::remove_file(&path));
+        path
+    }
+
+    #[test]
+    fn append_and_compact() {
+        let path = test_path("append");
+        let (mut oplog, mut file) = DocFile::open(&path).unwrap();
+        assert_eq!(oplog, ListOpLog::new());
+
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "hello world");
+        file.append(&oplog).unwrap();
+        oplog.add_delete_without_content(seph, 0..6);
+        file.append(&oplog).unwrap();
+        file.append(&oplog).unwrap(); // Nothing to write.
+        assert_eq!(file.records_since_compaction(), 2);
+        drop(file);
+
+        let (mut result, mut file) = DocFile::open(&path).unwrap();
+        assert_eq!(result, oplog);
+        assert_eq!(file.records_since_compaction(), 1);
+
+        file.set_compact_after(Some(2));
+        let mike = result.get_or_create_agent_id("mike");
+        result.add_insert(mike, 0, "yo ");
+        file.append(&result).unwrap();
+        assert_eq!(file.records_since_compaction(), 0);
+        result.add_insert(mike, 3, "!");
+        file.append(&result).unwrap();
+        drop(file);
+
+        let (result2, file) = DocFile::open(&path).unwrap();
+        assert_eq!(result2, result);
+        assert_eq!(file.records_since_compaction(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn append_marks() {
+        let path = test_path("marks");
+        let (mut oplog, mut file) = DocFile::open(&path).unwrap();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let v = oplog.add_insert(seph, 0, "hello world");
+        file.append(&oplog).unwrap();
+
+        // Marks don't change the version, but they're still saved. Only changed marks are written.
+        let range = MarkRange {
+            start_anchor: Anchor::before(0),
+            end_anchor: Anchor::after(4),
+            expand: MarkExpand::After,
+            payload: b"bold".to_vec(),
+        };
+        let file_len = || std::fs::metadata(&path).unwrap().len();
+        let len_before = file_len();
+        let bold = oplog.add_mark(seph, &[v], range.clone());
+        file.append(&oplog).unwrap();
+        let len = file_len();
+        oplog.add_mark(seph, &[v], MarkRange { payload: b"italic".to_vec(), ..range });
+        file.append(&oplog).unwrap();
+        // The payload is 2 bytes longer.
+        assert_eq!(file_len() - len, len - len_before + 2);
+        assert!(oplog.delete_mark(bold, &[v]));
+        file.append(&oplog).unwrap();
+        file.append(&oplog).unwrap(); // Nothing to write.
+        assert_eq!(file.records_since_compaction(), 4);
+        drop(file);
+
+        let (result, _) = DocFile::open(&path).unwrap();
+        assert_eq!(result, oplog);
+        assert!(!result.mark_visible_at(bold, &[v]));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn recovers_from_torn_write() {
+        let path = test_path("torn");
+        let (mut oplog, mut file) = DocFile::open(&path).unwrap();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "abc");
+        file.append(&oplog).unwrap();
+        let good_len = std::fs::metadata(&path).unwrap().len();
+        oplog.add_insert(seph, 3, "def");
+        file.append(&oplog).unwrap();
+        drop(file);
+
+        // Cut the last record off part way through.
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 3]).unwrap();
+        let (result, _) = DocFile::open(&path).unwrap();
+        assert_eq!(result.checkout_tip().content().to_string(), "abc");
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), good_len);
+
+        // A corrupt record at the end of the file is discarded too.
+        let (_, mut file) = DocFile::open(&path).unwrap();
+        file.append(&oplog).unwrap();
+        drop(file);
+        let mut bytes = std::fs::read(&path).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+        let (result, mut file) = DocFile::open(&path).unwrap();
+        assert_eq!(result.checkout_tip().content().to_string(), "abc");
+        file.append(&oplog).unwrap();
+        drop(file);
+
+        // But corrupting any other record is an error.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[20] ^= 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(DocFile::open(&path), Err(DocFileError::Corrupt { offset: 12 })));
+
+        // Including the length of a record which isn't at the end. The file isn't truncated.
+        bytes[20] ^= 0xff;
+        bytes[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(DocFile::open(&path), Err(DocFileError::Corrupt { offset: 12 })));
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
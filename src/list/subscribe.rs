@@ -0,0 +1,191 @@
+//! Change notifications for [`ListCRDT`].
+//!
+//! Editors need to know how the document changed after every local edit and every merge, so they
+//! can update their own copy of the text (and move cursors and so on). Subscribers registered with
+//! [`ListCRDT::subscribe`] are called with the operations which were applied to the document's
+//! branch, in order. Positions in these operations are relative to the branch's content at the
+//! moment each operation was applied - so they can be applied directly to a local copy of the text.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::fmt::{Debug, Formatter};
+use crate::list::ListCRDT;
+use crate::list::operation::TextOperation;
+
+/// Names a subscription created by [`ListCRDT::subscribe`]. Pass this to
+/// [`ListCRDT::unsubscribe`] to stop receiving events.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct SubscriptionId(usize);
+
+type Callback = Box<dyn FnMut(&[TextOperation]) + Send + Sync>;
+
+/// The set of callbacks subscribed to a document.
+///
+/// Callbacks can't be cloned, so cloning a document does not copy its subscriptions. The clone
+/// starts with no subscribers.
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    next_id: usize,
+    callbacks: Vec<(SubscriptionId, Callback)>,
+}
+
+impl Subscribers {
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.callbacks.is_empty()
+    }
+
+    pub(crate) fn notify(&mut self, ops: &[TextOperation]) {
+        if ops.is_empty() { return; }
+        for (_, f) in self.callbacks.iter_mut() {
+            f(ops);
+        }
+    }
+}
+
+impl Clone for Subscribers {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl Debug for Subscribers {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Subscribers")
+            .field("count", &self.callbacks.len())
+            .finish()
+    }
+}
+
+impl ListCRDT {
+    /// Register a callback which is called whenever the document's content changes through this
+    /// ListCRDT - both for local edits (eg [`insert`](ListCRDT::insert)) and for merged remote
+    /// changes (eg [`merge_data_and_ff`](ListCRDT::merge_data_and_ff)).
+    ///
+    /// The callback is passed the list of operations applied to the document, transformed so they
+    /// can be applied in order to a copy of the document's previous content. Deletes include the
+    /// deleted content when its known.
+    ///
+    /// Changes made by modifying `doc.branch` directly are not reported.
+    ///
+    /// The callback must be `Send` and `Sync`, so the document can still be shared between threads.
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use diamond_types::list::ListCRDT;
+    /// let mut doc = ListCRDT::new();
+    /// let seph = doc.get_or_create_agent_id("seph");
+    ///
+    /// let events = Arc::new(Mutex::new(vec![]));
+    /// let events2 = events.clone();
+    /// doc.subscribe(move |ops| events2.lock().unwrap().extend_from_slice(ops));
+    ///
+    /// doc.insert(seph, 0, "hi there");
+    /// doc.delete(seph, 2..8);
+    /// assert_eq!(events.lock().unwrap().len(), 2);
+    /// ```
+    pub fn subscribe<F: FnMut(&[TextOperation]) + Send + Sync + 'static>(&mut self, f: F) -> SubscriptionId {
+        let subs = &mut self.subscribers;
+        let id = SubscriptionId(subs.next_id);
+        subs.next_id += 1;
+        subs.callbacks.push((id, Box::new(f)));
+        id
+    }
+
+    /// Remove a subscription. Returns false if the subscription was not found.
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let callbacks = &mut self.subscribers.callbacks;
+        let len = callbacks.len();
+        callbacks.retain(|(i, _)| *i != id);
+        callbacks.len() != len
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use crate::list::ListCRDT;
+    use crate::list::encoding::ENCODE_FULL;
+    use crate::list::operation::{ListOpKind, TextOperation};
+
+    fn apply(content: &mut Vec<char>, op: &TextOperation) {
+        let range = op.start()..op.end();
+        match op.kind {
+            ListOpKind::Ins => {
+                let chars = op.content.as_ref().unwrap().chars();
+                content.splice(range.start..range.start, chars);
+            }
+            ListOpKind::Del => {
+                content.drain(range);
+            }
+        }
+    }
+
+    /// Subscribe to doc, and mirror its content using the events.
+    fn mirror(doc: &mut ListCRDT) -> Arc<Mutex<Vec<char>>> {
+        let content: Arc<Mutex<Vec<char>>> = Arc::new(Mutex::new(doc.branch.content().to_string().chars().collect()));
+        let c2 = content.clone();
+        doc.subscribe(move |ops| {
+            let mut content = c2.lock().unwrap();
+            for op in ops {
+                apply(&mut content, op);
+            }
+        });
+        content
+    }
+
+    fn check_mirror(doc: &ListCRDT, mirror: &Mutex<Vec<char>>) {
+        let expect = doc.branch.content().to_string();
+        assert_eq!(mirror.lock().unwrap().iter().collect::<String>(), expect);
+    }
+
+    #[test]
+    fn local_events() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        let content = mirror(&mut doc);
+
+        doc.insert(seph, 0, "hello world");
+        doc.delete(seph, 0..6);
+        doc.delete_without_content(seph, 1..3);
+        doc.transaction(seph, |txn| {
+            txn.insert(0, "abc");
+            txn.delete(1..2);
+        });
+        check_mirror(&doc, &content);
+    }
+
+    #[test]
+    fn merge_events() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.insert(seph, 0, "aaa");
+
+        let mut remote = doc.clone();
+        let mike = remote.get_or_create_agent_id("mike");
+        remote.insert(mike, 1, "bbb");
+        remote.delete(mike, 3..5);
+
+        let content = mirror(&mut doc);
+        doc.insert(seph, 3, "ccc");
+        doc.merge_data_and_ff(&remote.oplog.encode(ENCODE_FULL)).unwrap();
+        check_mirror(&doc, &content);
+        assert_eq!(doc.branch.content().to_string(), "abbaccc");
+    }
+
+    #[test]
+    fn unsubscribe() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        let count = Arc::new(Mutex::new(0));
+        let c2 = count.clone();
+        let id = doc.subscribe(move |_ops| *c2.lock().unwrap() += 1);
+
+        doc.insert(seph, 0, "a");
+        // Clones don't inherit subscriptions.
+        doc.clone().insert(seph, 0, "b");
+        assert!(doc.unsubscribe(id));
+        assert!(!doc.unsubscribe(id));
+        doc.insert(seph, 0, "c");
+        assert_eq!(*count.lock().unwrap(), 1);
+    }
+}
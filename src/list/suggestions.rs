@@ -0,0 +1,441 @@
+//! Suggestions (tracked changes).
+//!
+//! Any run of operations made by one agent can be flagged as a suggestion. Suggestions live in
+//! the causal graph like any other operations, but pending suggestions are left out of the
+//! document's [mainline version](ListOpLog::mainline_version). Operations which depend on a pending
+//! suggestion (ie, which were made at a version containing the suggestion) are left out along with
+//! it.
+//!
+//! Accepting a suggestion merges it into the mainline version. Because this uses the normal merge
+//! logic, the suggestion is transformed correctly by any edits made concurrently on mainline.
+//!
+//! Rejecting a suggestion tombstones it: [`ListOpLog::checkout_mainline`] hides any text the
+//! suggestion inserted, and restores any text it deleted. Operations which depend on the suggestion
+//! stay in the mainline version. They're transformed by the CRDT like any other edit, so text typed
+//! in the middle of a rejected insert stays where it was typed.
+//!
+//! [`ListOpLog::checkout_tip`] (and [`ListCRDT`](crate::list::ListCRDT)) still contain every
+//! operation, including suggestions. This is the "show all changes" view of the document.
+//!
+//! The state of each suggestion is stored in the oplog, and it is included when the oplog is
+//! encoded. A suggestion's state can only move forward from pending to accepted to rejected. If a
+//! suggestion is concurrently accepted and rejected, it ends up rejected. Changing the state of a
+//! suggestion doesn't change the oplog's version, so suggestions have a local clock like
+//! [marks](crate::list::marks) do.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
+use rle::{AppendRle, HasLength};
+use smallvec::SmallVec;
+use crate::{AgentId, DTRange, Frontier, LV};
+use crate::causalgraph::agent_span::AgentVersion;
+use crate::list::ListOpLog;
+use crate::list::operation::ListOpKind;
+use crate::listmerge::merge::TransformedOpsIter2;
+use crate::rle::KVPair;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum SuggestionState {
+    Pending = 0,
+    Accepted = 1,
+    Rejected = 2,
+}
+
+impl TryFrom<usize> for SuggestionState {
+    type Error = ();
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(SuggestionState::Pending),
+            1 => Ok(SuggestionState::Accepted),
+            2 => Ok(SuggestionState::Rejected),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) struct SuggestionEntry {
+    /// The end of the suggestion's range of sequence numbers (exclusive).
+    pub(crate) seq_end: usize,
+    pub(crate) state: SuggestionState,
+    /// The value of the suggestions clock when this entry last changed. This is local to the
+    /// oplog, and isn't encoded.
+    pub(crate) changed: usize,
+}
+
+/// All the suggestions in an oplog, keyed by the (agent, seq) of their first operation.
+/// Suggestions are named by agent sequence numbers rather than local versions, because the
+/// operations in a suggestion might not be contiguous in another peer's oplog.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Suggestions {
+    pub(crate) entries: BTreeMap<AgentVersion, SuggestionEntry>,
+    /// Bumped every time a suggestion is added or changes state.
+    pub(crate) clock: usize,
+}
+
+impl Suggestions {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add a suggestion, or move an existing suggestion to a later state.
+    pub(crate) fn merge_entry(&mut self, start: AgentVersion, mut entry: SuggestionEntry) {
+        match self.entries.get_mut(&start) {
+            Some(existing) => {
+                if entry.state > existing.state {
+                    existing.state = entry.state;
+                    self.clock += 1;
+                    existing.changed = self.clock;
+                }
+            }
+            None => {
+                self.clock += 1;
+                entry.changed = self.clock;
+                self.entries.insert(start, entry);
+            }
+        }
+    }
+
+    /// Move the suggestion containing the named operation to a later state. Returns false if
+    /// there's no such suggestion, or it's already in a later state.
+    fn set_state(&mut self, (agent, seq): AgentVersion, state: SuggestionState) -> bool {
+        let Some((_, entry)) = self.entries.range_mut(..=(agent, seq))
+            .next_back()
+            .filter(|((a, _), entry)| *a == agent && seq < entry.seq_end) else { return false; };
+
+        if entry.state > state { return false; }
+        if entry.state < state {
+            entry.state = state;
+            self.clock += 1;
+            entry.changed = self.clock;
+        }
+        true
+    }
+}
+
+/// Sort spans and merge the ones which overlap or touch.
+fn union_spans(mut spans: Vec<DTRange>) -> Vec<DTRange> {
+    spans.sort_unstable_by_key(|span| span.start);
+    let mut result: Vec<DTRange> = Vec::with_capacity(spans.len());
+    for span in spans {
+        match result.last_mut() {
+            Some(last) if span.start <= last.end => last.end = last.end.max(span.end),
+            _ => result.push(span),
+        }
+    }
+    result
+}
+
+/// The parts of span which aren't in remove. Remove must be sorted and non-overlapping.
+fn subtract_spans(span: DTRange, remove: &[DTRange]) -> SmallVec<[DTRange; 2]> {
+    let mut result = SmallVec::new();
+    let mut start = span.start;
+    let idx = remove.partition_point(|r| r.end <= span.start);
+    for r in remove[idx..].iter().take_while(|r| r.start < span.end) {
+        if r.start > start { result.push((start..r.start).into()); }
+        start = r.end;
+    }
+    if start < span.end { result.push((start..span.end).into()); }
+    result
+}
+
+impl ListOpLog {
+    /// The local versions of the operations in a suggestion. This is usually a single range.
+    fn suggestion_lv_spans(&self, (agent, seq_start): AgentVersion, seq_end: usize) -> SmallVec<[DTRange; 1]> {
+        let client_data = &self.cg.agent_assignment.client_data[agent as usize];
+        let mut result = SmallVec::new();
+        let mut seq = seq_start;
+        while seq < seq_end {
+            let span = client_data.seq_to_time_span((seq..seq_end).into());
+            result.push(span);
+            seq += span.len();
+        }
+        result
+    }
+
+    /// Flag a range of operations as a suggestion. The operations must all have been made by
+    /// the same agent.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is empty, or contains operations from multiple agents.
+    pub fn flag_suggestion(&mut self, span: DTRange) {
+        assert!(!span.is_empty() && span.end <= self.len(), "Invalid suggestion range");
+        let agent_span = self.cg.agent_assignment.local_span_to_agent_span(span);
+        assert_eq!(agent_span.len(), span.len(), "Suggestions must contain changes from a single agent");
+
+        self.suggestions.merge_entry((agent_span.agent, agent_span.seq_range.start), SuggestionEntry {
+            seq_end: agent_span.seq_range.end,
+            state: SuggestionState::Pending,
+            changed: 0,
+        });
+    }
+
+    /// Get the state of the suggestion containing the operation at lv. Returns None if the
+    /// operation isn't part of a suggestion.
+    pub fn suggestion_state(&self, lv: LV) -> Option<SuggestionState> {
+        let (agent, seq) = self.lv_to_agent_version(lv);
+        self.suggestions.entries.range(..=(agent, seq))
+            .next_back()
+            .filter(|((a, _), entry)| *a == agent && seq < entry.seq_end)
+            .map(|(_, entry)| entry.state)
+    }
+
+    /// List the local versions of all operations in pending suggestions.
+    pub fn pending_suggestions(&self) -> Vec<DTRange> {
+        let mut result = vec![];
+        for (start, entry) in self.suggestions.entries.iter() {
+            if entry.state == SuggestionState::Pending {
+                result.extend(self.suggestion_lv_spans(*start, entry.seq_end));
+            }
+        }
+        result.sort_unstable_by_key(|span| span.start);
+        result
+    }
+
+    /// Accept the suggestion containing the operation at lv, adding it to the mainline version.
+    /// Returns false if there's no such suggestion, or if the suggestion has been rejected.
+    pub fn accept_suggestion(&mut self, lv: LV) -> bool {
+        let id = self.lv_to_agent_version(lv);
+        self.suggestions.set_state(id, SuggestionState::Accepted)
+    }
+
+    /// Reject the suggestion containing the operation at lv. The suggestion is tombstoned in
+    /// [`checkout_mainline`](ListOpLog::checkout_mainline). Returns false if there's no such
+    /// suggestion.
+    pub fn reject_suggestion(&mut self, lv: LV) -> bool {
+        let id = self.lv_to_agent_version(lv);
+        self.suggestions.set_state(id, SuggestionState::Rejected)
+    }
+
+    /// The local versions of the operations in suggestions with the specified state, in order.
+    fn suggestion_spans_with_state(&self, state: SuggestionState) -> Vec<DTRange> {
+        let mut result: Vec<DTRange> = self.suggestions.entries.iter()
+            .filter(|(_, entry)| entry.state == state)
+            .flat_map(|(start, entry)| self.suggestion_lv_spans(*start, entry.seq_end))
+            .collect();
+        result.sort_unstable_by_key(|span| span.start);
+        result
+    }
+
+    /// Get the version containing every operation except pending suggestions (and the operations
+    /// which depend on them). Rejected suggestions are part of this version, but they're
+    /// tombstoned by [`checkout_mainline`](ListOpLog::checkout_mainline).
+    pub fn mainline_version(&self) -> Frontier {
+        let suggested = self.suggestion_spans_with_state(SuggestionState::Pending);
+        if suggested.is_empty() { return self.cg.version.clone(); }
+
+        // Graph entries are in LV order, and each entry is a simple run of changes. So we can
+        // find everything which depends on a suggestion in a single pass.
+        let mut excluded: Vec<DTRange> = vec![];
+        let is_excluded = |excluded: &[DTRange], lv: LV| {
+            let idx = excluded.partition_point(|span| span.end <= lv);
+            excluded.get(idx).is_some_and(|span| span.start <= lv)
+        };
+        let mut included_ends: SmallVec<[LV; 4]> = SmallVec::new();
+
+        for entry in self.cg.graph.entries.iter() {
+            let span = entry.span;
+            let exclude_from = if entry.parents.iter().any(|p| is_excluded(&excluded, *p)) {
+                span.start
+            } else {
+                let idx = suggested.partition_point(|s| s.end <= span.start);
+                suggested.get(idx)
+                    .filter(|s| s.start < span.end)
+                    .map_or(span.end, |s| s.start.max(span.start))
+            };
+
+            if exclude_from > span.start { included_ends.push(exclude_from - 1); }
+            if exclude_from < span.end { excluded.push_rle((exclude_from..span.end).into()); }
+        }
+
+        self.cg.graph.find_dominators(&included_ends)
+    }
+
+    /// Get the content of the document at the [mainline version](ListOpLog::mainline_version),
+    /// with rejected suggestions tombstoned. Text inserted by a rejected suggestion is hidden, and
+    /// text it deleted is restored. See the [module documentation](self) for details.
+    ///
+    /// If any suggestions have been rejected, this replays the whole history, so it takes about as
+    /// long as checking out the document from scratch.
+    pub fn checkout_mainline(&self) -> String {
+        let version = self.mainline_version();
+        let rejected = self.suggestion_spans_with_state(SuggestionState::Rejected);
+        if rejected.is_empty() {
+            return self.checkout(version.as_ref()).content().to_string();
+        }
+
+        // Replay every operation, so we can find the items each delete removed.
+        let mut iter = TransformedOpsIter2::new_without_ff(&self.cg.graph, &self.cg.agent_assignment,
+                                                           &self.operation_ctx, &self.operations, self.tie_break,
+                                                           &[], version.as_ref());
+        for _ in &mut iter {}
+
+        // Items are hidden if a rejected suggestion inserted them, or if any other operation
+        // deleted them.
+        let mut hidden = rejected.clone();
+        let (history, _) = self.cg.graph.diff(version.as_ref(), &[]);
+        for range in history {
+            for (KVPair(lv, op), _) in self.iter_range_simple(range) {
+                if op.kind != ListOpKind::Del { continue; }
+                for span in subtract_spans((lv..lv + op.len()).into(), &rejected) {
+                    let mut v = span.start;
+                    while v < span.end {
+                        let target = iter.delete_target(v, span.end - v).unwrap();
+                        hidden.push(target.span);
+                        v += target.len();
+                    }
+                }
+            }
+        }
+        let hidden = union_spans(hidden);
+
+        let mut result = String::new();
+        for item in iter.tracker_items() {
+            for span in subtract_spans(item.id, &hidden) {
+                for (_, content) in self.iter_range_simple(span) {
+                    result.push_str(content.expect("Inserted content is missing"));
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::encoding::ENCODE_FULL;
+    use crate::ParseError;
+    use crate::list::ListOpLog;
+    use crate::list::operation::TextOperation;
+    use crate::list::suggestions::{SuggestionEntry, SuggestionState};
+
+    #[test]
+    fn suggestions() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "hello world");
+
+        // Mike suggests some changes.
+        let s1_start = oplog.len();
+        let s1 = oplog.add_insert_at(mike, &[a], 5, " there");
+        oplog.flag_suggestion((s1_start..s1 + 1).into());
+        let s2_start = oplog.len();
+        let s2 = oplog.add_delete_at(mike, &[a], 0..6);
+        oplog.flag_suggestion((s2_start..s2 + 1).into());
+
+        // Meanwhile seph keeps editing.
+        let b = oplog.add_insert_at(seph, &[a], 11, "!");
+        // This edit depends on s1, so it isn't on mainline until s1 is accepted.
+        let c = oplog.add_insert_at(seph, &[s1, b], 0, ">");
+
+        assert_eq!(oplog.pending_suggestions(), vec![(s1_start..s1 + 1).into(), (s2_start..s2 + 1).into()]);
+        assert_eq!(oplog.suggestion_state(s1 - 2), Some(SuggestionState::Pending));
+        assert_eq!(oplog.suggestion_state(b), None);
+        assert_eq!(oplog.mainline_version().as_ref(), &[b]);
+        assert_eq!(oplog.checkout_mainline(), "hello world!");
+
+        assert!(oplog.accept_suggestion(s1));
+        assert_eq!(oplog.mainline_version().as_ref(), &[c]);
+        assert_eq!(oplog.checkout_mainline(), ">hello there world!");
+
+        assert!(oplog.reject_suggestion(s2));
+        assert!(!oplog.accept_suggestion(s2));
+        assert!(!oplog.accept_suggestion(b));
+        assert!(oplog.pending_suggestions().is_empty());
+        assert_eq!(oplog.mainline_version().as_ref(), &[s2, c]);
+        assert_eq!(oplog.checkout_mainline(), ">hello there world!");
+
+        // Suggestions are encoded with the oplog.
+        let mut other = ListOpLog::load_from(&oplog.encode(ENCODE_FULL)).unwrap();
+        assert_eq!(other, oplog);
+        assert_eq!(other.checkout_mainline(), oplog.checkout_mainline());
+
+        // Concurrently accepting and rejecting a suggestion rejects it.
+        let d_start = oplog.len();
+        let d = oplog.add_insert_at(mike, &[c], 0, "??");
+        oplog.flag_suggestion((d_start..d + 1).into());
+        other.decode_and_add(&oplog.encode(ENCODE_FULL)).unwrap();
+        assert!(oplog.accept_suggestion(d));
+        assert!(other.reject_suggestion(d));
+        other.decode_and_add(&oplog.encode(ENCODE_FULL)).unwrap();
+        oplog.add_missing_operations_from(&other);
+        assert_eq!(oplog.suggestion_state(d), Some(SuggestionState::Rejected));
+        assert_eq!(other, oplog);
+    }
+
+    #[test]
+    fn rejected_suggestions_are_tombstoned() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert(seph, 0, "hello world");
+
+        // Mike suggests replacing "world" with "there".
+        let start = oplog.len();
+        oplog.add_delete_without_content(mike, 6..11);
+        let s = oplog.add_insert(mike, 6, "there");
+        oplog.flag_suggestion((start..s + 1).into());
+
+        // Then seph edits on top of the suggestion.
+        oplog.add_insert(seph, 11, "!");
+        oplog.add_insert(seph, 8, "X");
+        oplog.add_delete_without_content(seph, 0..1);
+        assert_eq!(oplog.checkout_tip().content().to_string(), "ello thXere!");
+        assert_eq!(oplog.checkout_mainline(), "hello world");
+
+        // Rejecting the suggestion restores "world" and hides "there". Seph's changes are kept, in
+        // the places they were typed - "!" was typed after "there", which is before "world".
+        assert!(oplog.reject_suggestion(s));
+        assert_eq!(oplog.checkout_mainline(), "ello X!world");
+        assert_eq!(oplog.checkout_tip().content().to_string(), "ello thXere!");
+    }
+
+    #[test]
+    fn suggestion_states_sync() {
+        let mut a = ListOpLog::new();
+        let mike = a.get_or_create_agent_id("mike");
+        let v = a.add_insert(mike, 0, "hi");
+        a.flag_suggestion((0..v + 1).into());
+        let mut b = ListOpLog::load_from(&a.encode(ENCODE_FULL)).unwrap();
+
+        // Accepting a suggestion doesn't change the version, but the change is still encoded.
+        let before = a.change_clocks();
+        assert!(a.accept_suggestion(v));
+        assert_eq!(a.cg.version.as_ref(), &[v]);
+        let patch = a.encode_from_changes_since(ENCODE_FULL, &[v], before);
+        b.decode_and_add(&patch).unwrap();
+        assert_eq!(b.suggestion_state(v), Some(SuggestionState::Accepted));
+        assert_eq!(b.checkout_mainline(), "hi");
+
+        // Unchanged suggestions aren't encoded again.
+        let patch = a.encode_from_changes_since(ENCODE_FULL, &[v], a.change_clocks());
+        let mut c = ListOpLog::load_from(&a.encode_from(ENCODE_FULL, &[])).unwrap();
+        c.suggestions = Default::default();
+        c.decode_and_add(&patch).unwrap();
+        assert_eq!(c.suggestion_state(v), None);
+    }
+
+    #[test]
+    fn invalid_suggestions_arent_merged() {
+        let mut oplog = ListOpLog::new();
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(mike, 0, "hi");
+        let base = oplog.clone();
+        oplog.flag_suggestion((0..a + 1).into());
+
+        let mut damaged = oplog.encode(ENCODE_FULL);
+        *damaged.last_mut().unwrap() ^= 1;
+        let mut other = base.clone();
+        assert!(other.decode_and_add(&damaged).is_err());
+        assert_eq!(other, base);
+
+        // A suggestion can't name sequence numbers we don't have, even if we have later ones.
+        oplog.add_operations_remote(mike, &[], 5, &[TextOperation::new_insert(0, "yo")]);
+        oplog.suggestions.entries.insert((mike, 0), SuggestionEntry { seq_end: 7, state: SuggestionState::Pending, changed: 1 });
+        assert_eq!(ListOpLog::load_from(&oplog.encode(ENCODE_FULL)), Err(ParseError::BaseVersionUnknown));
+    }
+}
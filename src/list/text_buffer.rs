@@ -0,0 +1,158 @@
+//! Merging changes directly into an editor's own text buffer.
+//!
+//! Editors usually store the document in their own data structure (a rope or piece table). The
+//! [`TextBuffer`] trait lets diamond types write merged changes straight into that buffer using
+//! [`ListOpLog::merge_into_buffer`] and [`ListOpLog::checkout_into`], so the editor doesn't need
+//! to keep a second copy of the document in a [`ListBranch`](crate::list::ListBranch).
+//!
+//! The trait is implemented for [`JumpRopeBuf`], [`JumpRope`], [`String`], and (with the `ropey`
+//! feature) `ropey::Rope`. The `ropey` feature also adds conversions between branches and ropey
+//! ropes.
+
+use alloc::string::String;
+use core::ops::Range;
+use jumprope::{JumpRope, JumpRopeBuf};
+use crate::{Frontier, LV};
+use crate::list::ListOpLog;
+#[cfg(feature = "ropey")]
+use crate::list::ListBranch;
+use crate::list::merge::apply_xf_op;
+use crate::unicount::chars_to_bytes;
+
+/// A mutable text document, addressed by unicode character (not byte) positions.
+pub trait TextBuffer {
+    /// The length of the document in characters.
+    fn len_chars(&self) -> usize;
+
+    /// Insert content at a character position.
+    fn insert(&mut self, pos: usize, content: &str);
+
+    /// Remove the characters in range.
+    fn remove(&mut self, range: Range<usize>);
+}
+
+impl TextBuffer for JumpRopeBuf {
+    fn len_chars(&self) -> usize { JumpRopeBuf::len_chars(self) }
+    fn insert(&mut self, pos: usize, content: &str) { JumpRopeBuf::insert(self, pos, content) }
+    fn remove(&mut self, range: Range<usize>) { JumpRopeBuf::remove(self, range) }
+}
+
+impl TextBuffer for JumpRope {
+    fn len_chars(&self) -> usize { JumpRope::len_chars(self) }
+    fn insert(&mut self, pos: usize, content: &str) { JumpRope::insert(self, pos, content) }
+    fn remove(&mut self, range: Range<usize>) { JumpRope::remove(self, range) }
+}
+
+impl TextBuffer for String {
+    fn len_chars(&self) -> usize { crate::unicount::count_chars(self) }
+
+    fn insert(&mut self, pos: usize, content: &str) {
+        self.insert_str(chars_to_bytes(self, pos), content);
+    }
+
+    fn remove(&mut self, range: Range<usize>) {
+        let start = chars_to_bytes(self, range.start);
+        let end = start + chars_to_bytes(&self[start..], range.len());
+        self.replace_range(start..end, "");
+    }
+}
+
+#[cfg(feature = "ropey")]
+impl TextBuffer for ropey::Rope {
+    fn len_chars(&self) -> usize { ropey::Rope::len_chars(self) }
+    fn insert(&mut self, pos: usize, content: &str) { ropey::Rope::insert(self, pos, content) }
+    fn remove(&mut self, range: Range<usize>) { ropey::Rope::remove(self, range) }
+}
+
+#[cfg(feature = "ropey")]
+impl ListBranch {
+    /// Copy the branch's content into a new ropey rope.
+    pub fn as_ropey(&self) -> ropey::Rope {
+        let mut builder = ropey::RopeBuilder::new();
+        for s in self.content.substrings() {
+            builder.append(s);
+        }
+        builder.finish()
+    }
+
+    /// Create a branch from a ropey rope containing the document at `version`. The rope's content
+    /// isn't checked, so it must match the oplog's content at that version (for example, a rope
+    /// made by [`checkout_into`](ListOpLog::checkout_into) or [`as_ropey`](ListBranch::as_ropey)).
+    pub fn from_ropey(version: Frontier, rope: &ropey::Rope) -> Self {
+        let mut content = JumpRope::new();
+        for s in rope.chunks() {
+            content.insert(content.len_chars(), s);
+        }
+        Self::new_with_content(version, content)
+    }
+}
+
+impl ListOpLog {
+    /// Merge the changes in `merge_frontier` into a text buffer which contains the document at
+    /// version `from`. Returns the version of the buffer after the merge.
+    ///
+    /// This does the same thing as [`ListBranch::merge`](crate::list::ListBranch::merge), but
+    /// writes into any [`TextBuffer`].
+    pub fn merge_into_buffer<B: TextBuffer>(&self, buffer: &mut B, from: &[LV], merge_frontier: &[LV]) -> Frontier {
+        let mut iter = self.get_xf_operations_full(from, merge_frontier);
+        for (_lv, origin_op, xf) in &mut iter {
+            apply_xf_op(buffer, &self.operation_ctx, origin_op, xf);
+        }
+        iter.into_frontier()
+    }
+
+    /// Check out the document at `version` into a new text buffer.
+    pub fn checkout_into<B: TextBuffer + Default>(&self, version: &[LV]) -> B {
+        let mut buffer = B::default();
+        self.merge_into_buffer(&mut buffer, &[], version);
+        buffer
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::string::{String, ToString};
+    use jumprope::JumpRopeBuf;
+    use crate::list::ListOpLog;
+    use crate::list::text_buffer::TextBuffer;
+
+    #[test]
+    fn merge_into_buffers() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "héllo");
+        let b = oplog.add_insert_at(mike, &[a], 5, " wörld");
+        let c = oplog.add_delete_at(seph, &[a], 0..2);
+
+        let mut s: String = oplog.checkout_into(&[a]);
+        assert_eq!(s, "héllo");
+        let v = oplog.merge_into_buffer(&mut s, &[a], &[b, c]);
+        assert_eq!(v.as_ref(), &[b, c]);
+        assert_eq!(s, oplog.checkout_tip().content().to_string());
+
+        let rope: JumpRopeBuf = oplog.checkout_into(&[b, c]);
+        assert_eq!(rope.to_string(), s);
+        assert_eq!(TextBuffer::len_chars(&s), 9);
+    }
+
+    #[cfg(feature = "ropey")]
+    #[test]
+    fn ropey_round_trip() {
+        use crate::list::ListBranch;
+
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let a = oplog.add_insert(seph, 0, &"hi there ".repeat(200));
+        let mut rope: ropey::Rope = oplog.checkout_into(&[a]);
+        let b = oplog.add_delete_at(seph, &[a], 0..3);
+        oplog.merge_into_buffer(&mut rope, &[a], &[b]);
+
+        let branch = oplog.checkout_tip();
+        assert_eq!(branch.as_ropey(), rope);
+        let mut branch = ListBranch::from_ropey(oplog.local_frontier(), &rope);
+        oplog.add_insert(seph, 0, "yo ");
+        branch.merge(&oplog, oplog.cg.version.as_ref());
+        assert_eq!(branch, oplog.checkout_tip());
+    }
+}
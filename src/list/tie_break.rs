@@ -0,0 +1,154 @@
+//! Strategies for ordering concurrent inserts at the same location in a document.
+//!
+//! When two peers concurrently insert at the same position, every peer needs to put the inserted
+//! items in the same order. By default, diamond types orders them by the name of the agent which
+//! made each insert. But that means the resulting document order leaks information about agent
+//! names, and a user with a "low" agent name always has their concurrent inserts placed first.
+//!
+//! The strategy is chosen when a document is created (see
+//! [`ListOpLog::new_with_tie_break`](crate::list::ListOpLog::new_with_tie_break)) and stored in
+//! the document's encoding. All peers editing a document must use the same strategy, or their
+//! documents will not converge. Merging data encoded with a different strategy into a non-empty
+//! oplog fails with [`ParseError::TieBreakMismatch`](crate::encoding::parseerror::ParseError).
+
+use core::cmp::Ordering;
+use num_enum::TryFromPrimitive;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use crate::LV;
+use crate::causalgraph::agent_assignment::AgentAssignment;
+
+/// How to order concurrent inserts at the same location.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default, TryFromPrimitive)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[repr(u32)]
+pub enum TieBreak {
+    /// Order by agent name, then by sequence number. This is the original ordering used by
+    /// diamond types.
+    #[default]
+    AgentName = 0,
+
+    /// Order by a (stable) hash of the agent name, then by sequence number. The order of
+    /// concurrent inserts no longer depends on how agent names sort.
+    AgentHash = 1,
+
+    /// Order by sequence number first, then by agent name. Inserts from agents which have made
+    /// fewer changes are placed first.
+    SeqFirst = 2,
+}
+
+/// 64 bit FNV-1a. This needs to be stable across platforms and versions, so we can't use the
+/// standard library's hasher.
+fn hash_name(name: &str) -> u64 {
+    name.bytes().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl TieBreak {
+    /// Compare the concurrent inserts at versions a and b. Returns [`Ordering::Less`] if a should
+    /// be placed before b in the document.
+    pub(crate) fn cmp(self, aa: &AgentAssignment, a: LV, b: LV) -> Ordering {
+        let (a_agent, a_seq) = aa.local_to_agent_version(a);
+        let (b_agent, b_seq) = aa.local_to_agent_version(b);
+        let a_name = aa.get_agent_name(a_agent);
+        let b_name = aa.get_agent_name(b_agent);
+
+        // Its possible for a user to conflict with themself if they commit to multiple branches.
+        // In this case, sort by seq number. We can't compare versions here because sequence
+        // numbers could be used out of order, and the relative version ordering isn't consistent
+        // in that case.
+        match self {
+            TieBreak::AgentName => a_name.cmp(b_name)
+                .then(a_seq.cmp(&b_seq)),
+            TieBreak::AgentHash => hash_name(a_name).cmp(&hash_name(b_name))
+                .then_with(|| a_name.cmp(b_name))
+                .then(a_seq.cmp(&b_seq)),
+            TieBreak::SeqFirst => a_seq.cmp(&b_seq)
+                .then_with(|| a_name.cmp(b_name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::prelude::*;
+    use crate::list::encoding::ENCODE_FULL;
+    use crate::list::{ListCRDT, ListOpLog};
+    use crate::list::old_fuzzer_tools::old_make_random_change;
+    use crate::list_fuzzer_tools::choose_2;
+    use crate::list::tie_break::TieBreak;
+    use crate::encoding::parseerror::ParseError;
+
+    fn concurrent_inserts(tie_break: TieBreak) -> ListOpLog {
+        let mut oplog = ListOpLog::new_with_tie_break(tie_break);
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let base = oplog.add_insert(seph, 0, "__"); // seph seq 0-1.
+        let v = oplog.add_insert_at(mike, &[base], 2, "mmmm"); // mike seq 0-3.
+        // Concurrent inserts between the underscores, from mike (seq 4) and seph (seq 2).
+        oplog.add_insert_at(mike, &[v], 1, "aaa");
+        oplog.add_insert_at(seph, &[base], 1, "b");
+        oplog
+    }
+
+    #[test]
+    fn orderings() {
+        // "mike" < "seph".
+        let oplog = concurrent_inserts(TieBreak::AgentName);
+        assert_eq!(oplog.checkout_tip().content().to_string(), "_aaab_mmmm");
+
+        let oplog = concurrent_inserts(TieBreak::SeqFirst);
+        assert_eq!(oplog.checkout_tip().content().to_string(), "_baaa_mmmm");
+
+        let oplog = concurrent_inserts(TieBreak::AgentHash);
+        let content = oplog.checkout_tip().content().to_string();
+        assert!(content == "_aaab_mmmm" || content == "_baaa_mmmm");
+    }
+
+    #[test]
+    fn tie_break_is_encoded() {
+        let oplog = concurrent_inserts(TieBreak::SeqFirst);
+        let bytes = oplog.encode(ENCODE_FULL);
+        let result = ListOpLog::load_from(&bytes).unwrap();
+        assert_eq!(result.tie_break(), TieBreak::SeqFirst);
+        assert_eq!(result.checkout_tip().content().to_string(), "_baaa_mmmm");
+
+        // An empty oplog adopts the strategy of the data merged into it.
+        let mut empty = ListOpLog::new_with_tie_break(TieBreak::AgentHash);
+        empty.decode_and_add(&bytes).unwrap();
+        assert_eq!(empty.tie_break(), TieBreak::SeqFirst);
+
+        // Merging into a document using a different strategy fails.
+        let mut other = concurrent_inserts(TieBreak::AgentName);
+        assert_eq!(other.decode_and_add(&bytes).unwrap_err(), ParseError::TieBreakMismatch);
+        assert_eq!(other.tie_break(), TieBreak::AgentName);
+    }
+
+    #[test]
+    fn fuzz_strategies_converge() {
+        for tie_break in [TieBreak::AgentName, TieBreak::AgentHash, TieBreak::SeqFirst] {
+            for seed in 0..5 {
+                let mut rng = SmallRng::seed_from_u64(seed);
+                let mut docs = [(); 3].map(|_| ListCRDT::new_with_tie_break(tie_break));
+                for doc in docs.iter_mut() {
+                    for a in 0..3 { doc.get_or_create_agent_id(&format!("agent {a}")); }
+                }
+
+                for _i in 0..30 {
+                    for _j in 0..2 {
+                        let idx = rng.gen_range(0..docs.len());
+                        old_make_random_change(&mut docs[idx], None, idx as _, &mut rng, false);
+                    }
+
+                    let (_, a, _, b) = choose_2(&mut docs, &mut rng);
+                    a.merge_data_and_ff(&b.oplog.encode(ENCODE_FULL)).unwrap();
+                    b.merge_data_and_ff(&a.oplog.encode(ENCODE_FULL)).unwrap();
+                    assert_eq!(a.branch.content(), b.branch.content());
+                    // Merging incrementally gives the same result as checking out from scratch.
+                    assert_eq!(a.branch, a.oplog.checkout_tip());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! Moving a branch backwards (and forwards) through time in-place.
+//!
+//! Moving a branch forwards is just a merge. Moving backwards is harder: we need to undo the
+//! transformed operations which aren't in the target version, in reverse order. Undoing an insert
+//! is easy. But to undo a delete we need the deleted content - which isn't in the branch any more.
+//! Deletes may store their content in the oplog. If not, we ask the merge tracker which items the
+//! delete removed, and look up the content of the inserts which created them.
+
+use alloc::{string::String, vec::Vec};
+use rle::HasLength;
+use crate::{DTRange, LV};
+use crate::dtrange::is_underwater;
+use crate::list::{ListBranch, ListOpLog};
+use crate::list::operation::ListOpKind;
+use crate::listmerge::merge::{reverse_str, TransformedOpsIter2};
+use crate::listmerge::merge::TransformedResult::BaseMoved;
+
+/// An operation which undoes part of a transformed operation.
+enum Inverse {
+    Remove(DTRange),
+    Insert(usize, String),
+}
+
+impl ListOpLog {
+    /// Get the deleted content for the delete at lv, using the insert operations which created
+    /// the deleted items. The content is in LV order, like content stored in the oplog. Returns
+    /// None if any of the content is unknown.
+    fn deleted_content(&self, iter: &TransformedOpsIter2, mut lv: LV, len: usize) -> Option<String> {
+        let mut result = String::new();
+        let end = lv + len;
+
+        while lv < end {
+            let target = iter.delete_target(lv, end - lv)?;
+            lv += target.len();
+
+            if is_underwater(target.span.start) { return None; }
+
+            let mut content = String::new();
+            let mut span = target.span;
+            while !span.is_empty() {
+                let pair = self.operations.find_packed_and_split_ctx(span, &self.operation_ctx);
+                debug_assert_eq!(pair.1.kind, ListOpKind::Ins);
+                content.push_str(pair.1.get_content(&self.operation_ctx)?);
+                span.start += pair.len();
+            }
+
+            if target.fwd {
+                result.push_str(&content);
+            } else {
+                result.push_str(&reverse_str(&content));
+            }
+        }
+
+        Some(result)
+    }
+
+    /// Figure out the operations needed to move a branch at `from` back to `to`, which must be a
+    /// subset of from. Returns None if some deleted content isn't available.
+    fn inverse_ops(&self, from: &[LV], to: &[LV]) -> Option<Vec<Inverse>> {
+        let mut iter = TransformedOpsIter2::new_without_ff(&self.cg.graph, &self.cg.agent_assignment,
+                                                           &self.operation_ctx, &self.operations, self.tie_break(),
+                                                           to, from);
+        let xf_ops = (&mut iter).collect::<Vec<_>>();
+
+        xf_ops.into_iter().rev().filter_map(|(lv, op, xf)| {
+            let BaseMoved(pos) = xf else { return None; }; // Deletes which already happened.
+            let len = op.len();
+
+            Some(match op.kind {
+                ListOpKind::Ins => Some(Inverse::Remove((pos..pos + len).into())),
+                ListOpKind::Del => {
+                    let content = match op.get_content(&self.operation_ctx) {
+                        Some(content) => Some(String::from(content)),
+                        None => self.deleted_content(&iter, lv, len),
+                    };
+                    // Content is in LV order. Backwards deletes need it reversed.
+                    content.map(|c| Inverse::Insert(pos, if op.loc.fwd { c } else { reverse_str(&c).into() }))
+                }
+            })
+        }).collect()
+    }
+}
+
+impl ListBranch {
+    /// Move the branch to the specified version in-place. Unlike [`merge`](ListBranch::merge), the
+    /// target version doesn't need to contain the branch's current version. Any operations in the
+    /// branch which aren't in the target version are undone.
+    ///
+    /// This is useful for scrubbing through history. Stepping a branch a short distance backwards
+    /// or forwards in time is much faster than checking out the target version from scratch.
+    ///
+    /// Undoing a delete needs the deleted content. If the content of a delete isn't stored in the
+    /// oplog, and the deleted text was inserted before the version the branch is reverting to, this
+    /// falls back to checking out the target version from scratch.
+    pub fn time_travel(&mut self, oplog: &ListOpLog, target: &[LV]) {
+        let graph = &oplog.cg.graph;
+        let (revert, _) = graph.diff(self.version.as_ref(), target);
+
+        if !revert.is_empty() {
+            let common = graph.frontier_without(self.version.as_ref(), &revert);
+
+            let Some(inverse) = oplog.inverse_ops(self.version.as_ref(), common.as_ref()) else {
+                *self = oplog.checkout(target);
+                return;
+            };
+
+            for op in inverse {
+                match op {
+                    Inverse::Remove(range) => self.remove_content(range.into()),
+                    Inverse::Insert(pos, content) => self.insert_content(pos, &content),
+                }
+            }
+            self.version = common;
+        }
+
+        self.merge(oplog, target);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::prelude::*;
+    use crate::{AgentId, Frontier};
+    use crate::list::{ListBranch, ListCRDT, ListOpLog};
+    use crate::list::encoding::ENCODE_FULL;
+    use crate::list::old_fuzzer_tools::old_make_random_change_raw;
+    use crate::list_fuzzer_tools::choose_2;
+
+    fn check_all_pairs(oplog: &ListOpLog, versions: &[&[usize]]) {
+        for from in versions {
+            for to in versions {
+                let mut branch = oplog.checkout(from);
+                branch.time_travel(oplog, to);
+                assert_eq!(branch, oplog.checkout(to), "from {:?} to {:?}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn time_travel_linear() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let v1 = oplog.add_insert(seph, 0, "hello world");
+        let v2 = oplog.add_delete_without_content(seph, 0..6);
+        let v3 = oplog.add_insert(seph, 5, "!!");
+        let v4 = oplog.add_delete_without_content(seph, 4..6);
+        let v5 = oplog.add_insert(seph, 0, "abc");
+
+        check_all_pairs(&oplog, &[&[], &[v1], &[v2], &[v3], &[v4], &[v5], &[v1 - 3]]);
+    }
+
+    #[test]
+    fn time_travel_in_place() {
+        // Deleted content can come from the delete itself, or from the insert which created the
+        // deleted item. Either way the branch can be reverted without a checkout.
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        let v1 = doc.insert(seph, 0, "hello world");
+        let v2 = doc.delete(seph, 0..6);
+        let oplog = &mut doc.oplog;
+        let v3 = oplog.add_insert(seph, 0, "abc");
+        let v4 = oplog.add_delete_without_content(seph, 1..3);
+
+        assert!(oplog.inverse_ops(&[v2], &[v1]).is_some());
+        assert!(oplog.inverse_ops(&[v4], &[v1]).is_some());
+        // But here the deleted text was inserted before v3, so we don't know what it was.
+        assert!(oplog.inverse_ops(&[v4], &[v3]).is_none());
+        check_all_pairs(oplog, &[&[], &[v1], &[v2], &[v3], &[v4]]);
+    }
+
+    #[test]
+    fn time_travel_concurrent() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let base = oplog.add_insert(seph, 0, "abcdef");
+        let a = oplog.add_delete_without_content(seph, 1..3);
+        let b = oplog.add_insert_at(mike, &[base], 2, "XYZ");
+        let b2 = oplog.add_delete_at(mike, &[b], 0..4);
+        let m = oplog.add_insert_at(seph, &[a, b2], 0, "__");
+
+        check_all_pairs(&oplog, &[&[], &[base], &[a], &[b], &[b2], &[a, b], &[a, b2], &[m]]);
+    }
+
+    fn time_travel_fuzz(seed: u64, verbose: bool) {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut oplog = ListOpLog::new();
+        let mut branches = [ListBranch::new(), ListBranch::new(), ListBranch::new()];
+        for a in ["a", "b", "c"] {
+            oplog.get_or_create_agent_id(a);
+        }
+
+        let mut versions = vec![Frontier::root()];
+        for _i in 0..50 {
+            let idx = rng.gen_range(0..branches.len());
+            let v = old_make_random_change_raw(&mut oplog, &branches[idx], None, idx as AgentId, &mut rng, true);
+            branches[idx].merge(&oplog, &[v]);
+            versions.push(branches[idx].version.clone());
+
+            if rng.gen_bool(0.2) {
+                let (_, a, _, b) = choose_2(&mut branches, &mut rng);
+                a.merge(&oplog, b.version.as_ref());
+                versions.push(a.version.clone());
+            }
+        }
+
+        let (oplog, versions) = if seed % 2 == 1 {
+            // Round-trip through an encoding which drops deleted content. Deleted content then
+            // needs to be recovered from the merge tracker.
+            let stripped = ListOpLog::load_from(&oplog.encode(ENCODE_FULL)).unwrap();
+            let versions = versions.iter().map(|v| {
+                let remote = oplog.cg.agent_assignment.local_to_remote_frontier(v.as_ref());
+                stripped.cg.agent_assignment.remote_to_local_frontier(remote.into_iter())
+            }).collect::<Vec<_>>();
+            (stripped, versions)
+        } else {
+            (oplog, versions)
+        };
+
+        let mut branch = ListBranch::new();
+        for _i in 0..100 {
+            let target = &versions[rng.gen_range(0..versions.len())];
+            if verbose { println!("{:?} -> {:?}", branch.version, target); }
+            branch.time_travel(&oplog, target.as_ref());
+            assert_eq!(branch, oplog.checkout(target.as_ref()));
+        }
+    }
+
+    #[test]
+    fn time_travel_fuzz_once() {
+        for seed in 0..20 {
+            time_travel_fuzz(seed, false);
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn time_travel_fuzz_forever() {
+        for seed in 0.. {
+            if seed % 100 == 0 { println!("seed {seed}"); }
+            time_travel_fuzz(seed, false);
+        }
+    }
+}
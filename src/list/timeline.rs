@@ -0,0 +1,143 @@
+//! Summarise a document's history as a list of chunks, to power history timeline views.
+//!
+//! Replaying every version of a document to draw a timeline is slow. Instead, the oplog can be
+//! split into chunks of related changes, and each chunk summarised using only the operations in
+//! it. Chunks are either causal runs of changes by a single agent (see
+//! [`ListOpLog::history_chunks`]), or those runs split further using timestamps supplied by the
+//! application (see [`ListOpLog::history_chunks_by_time`]).
+
+use alloc::vec::Vec;
+use core::ops::Range;
+use rle::HasLength;
+use crate::{AgentId, DTRange, Frontier, LV};
+use crate::list::ListOpLog;
+use crate::list::merge::ChangedRegions;
+use crate::list::operation::ListOpKind;
+use crate::rle::KVPair;
+
+/// A summary of a run of changes made by one agent.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HistoryChunk {
+    /// The changes in the chunk. Each change (after the first) directly follows the one before it.
+    pub span: DTRange,
+    pub agent: AgentId,
+
+    /// The version of the document just before the chunk.
+    pub parents: Frontier,
+
+    /// The number of characters inserted by the chunk.
+    pub inserted: usize,
+
+    /// The number of characters deleted by the chunk.
+    pub deleted: usize,
+
+    /// The regions of the document changed by the chunk, as ranges in the document at `parents`.
+    /// See [`MergeSummary::changed_regions`](crate::list::MergeSummary::changed_regions).
+    pub changed_regions: Vec<Range<usize>>,
+}
+
+impl ListOpLog {
+    /// Split the oplog into causal runs of changes by each agent, and summarise each run. The
+    /// chunks are returned in local version order.
+    pub fn history_chunks(&self) -> Vec<HistoryChunk> {
+        self.chunk_history(|_| false)
+    }
+
+    /// Variant of [`history_chunks`](ListOpLog::history_chunks) which also starts a new chunk
+    /// whenever there's a gap of more than `max_gap` between consecutive changes. Diamond types
+    /// doesn't store when changes were made, so the application supplies the timestamp of each
+    /// change (by local version) using `timestamp`.
+    pub fn history_chunks_by_time<F: FnMut(LV) -> u64>(&self, max_gap: u64, mut timestamp: F) -> Vec<HistoryChunk> {
+        self.chunk_history(|lv| timestamp(lv).saturating_sub(timestamp(lv - 1)) > max_gap)
+    }
+
+    /// Group the oplog's changes into chunks. split_at(lv) is called for changes which could
+    /// continue the current chunk, and returns true if a new chunk should start at lv instead.
+    fn chunk_history<F: FnMut(LV) -> bool>(&self, mut split_at: F) -> Vec<HistoryChunk> {
+        let aa = &self.cg.agent_assignment;
+        let mut chunks: Vec<HistoryChunk> = vec![];
+
+        for entry in self.cg.graph.entries.iter() {
+            let mut lv = entry.span.start;
+            while lv < entry.span.end {
+                let agent_span = aa.local_span_to_agent_span((lv..entry.span.end).into());
+                let agent = agent_span.agent;
+                let end = lv + agent_span.len();
+
+                for v in lv..end {
+                    let continues = chunks.last().is_some_and(|chunk| {
+                        chunk.agent == agent && chunk.span.end == v
+                            && (v > entry.span.start || entry.parents.as_ref() == [v - 1])
+                    }) && !split_at(v);
+
+                    if continues {
+                        chunks.last_mut().unwrap().span.end = v + 1;
+                    } else {
+                        let parents = if v == entry.span.start { entry.parents.clone() } else { Frontier::new_1(v - 1) };
+                        chunks.push(HistoryChunk {
+                            span: (v..v + 1).into(),
+                            agent,
+                            parents,
+                            inserted: 0,
+                            deleted: 0,
+                            changed_regions: vec![],
+                        });
+                    }
+                }
+                lv = end;
+            }
+        }
+
+        // The changes in each chunk are sequential, so their positions can be used directly.
+        for chunk in chunks.iter_mut() {
+            let mut regions = ChangedRegions::new();
+            for (KVPair(_, op), _) in self.iter_range_simple(chunk.span) {
+                match op.kind {
+                    ListOpKind::Ins => { chunk.inserted += op.len(); }
+                    ListOpKind::Del => { chunk.deleted += op.len(); }
+                }
+                regions.apply(op.kind, op.start(), op.len());
+            }
+            chunk.changed_regions = regions.into_regions();
+        }
+
+        chunks
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Frontier;
+    use crate::list::ListOpLog;
+
+    #[test]
+    fn history_chunks() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "hello");
+        let b = oplog.add_insert_at(seph, &[a], 5, " world");
+        let c = oplog.add_insert_at(mike, &[a], 0, "yo ");
+        oplog.add_delete_at(seph, &[b, c], 0..3);
+
+        let chunks = oplog.history_chunks();
+        let summary: Vec<_> = chunks.iter().map(|chunk| {
+            let regions: Vec<_> = chunk.changed_regions.iter().map(|r| (r.start, r.end)).collect();
+            (chunk.span, chunk.agent, chunk.parents.clone(), chunk.inserted, chunk.deleted, regions)
+        }).collect();
+        assert_eq!(summary, vec![
+            ((0..11).into(), seph, Frontier::root(), 11, 0, vec![(0, 0)]),
+            ((11..14).into(), mike, Frontier::new_1(a), 3, 0, vec![(0, 0)]),
+            ((14..17).into(), seph, Frontier::from_sorted(&[b, c]), 0, 3, vec![(0, 3)]),
+        ]);
+
+        // Seph took a break after typing "hel".
+        let chunks = oplog.history_chunks_by_time(10, |lv| if lv < 3 { 0 } else { 100 });
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks[0].span, (0..3).into());
+        assert_eq!(chunks[1].span, (3..11).into());
+        assert_eq!(chunks[1].parents.as_ref(), &[2]);
+        assert_eq!(chunks[1].changed_regions.len(), 1);
+        assert_eq!(chunks[1].changed_regions[0], 3..3);
+    }
+}
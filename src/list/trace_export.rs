@@ -0,0 +1,244 @@
+//! Export an oplog as an editing trace, in the JSON formats used by the
+//! [editing-traces](https://github.com/josephg/editing-traces) repository.
+//!
+//! Editing traces are how real documents get contributed as benchmarks and as test fixtures for
+//! other CRDT implementations. There are two formats:
+//!
+//! - [`ConcurrentTrace`] keeps the causal graph. Each transaction names the transactions it
+//!   depends on by index, and agents are numbered in the order of their names.
+//! - [`SequentialTrace`] flattens the history into a single list of changes, which is what
+//!   `crdt_testdata::load_testing_data` reads. Concurrent changes are transformed, so the result
+//!   depends on how diamond types merges them.
+//!
+//! In both formats, each change (a [`TracePatch`]) is a `[position, delete length, inserted
+//! content]` triple, with positions counted in unicode characters. The benchmark data is stored
+//! gzipped (`.json.gz`). Compressing the JSON is left to the caller.
+//!
+//! Traces don't store agent names or sequence numbers, so re-importing a trace produces a
+//! different (but equivalent) oplog.
+//!
+//! Exporting panics if the oplog doesn't contain the content of every insert.
+
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use smallvec::{SmallVec, smallvec};
+use smartstring::alias::String as SmartString;
+use rle::{HasLength, SplitableSpan};
+use crate::AgentId;
+use crate::list::ListOpLog;
+use crate::list::operation::{ListOpKind, TextOperation};
+
+/// A single change, as `(position, delete length, inserted content)`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TracePatch(pub usize, pub usize, pub SmartString);
+
+impl From<TextOperation> for TracePatch {
+    fn from(op: TextOperation) -> Self {
+        match op.kind {
+            // Reversed inserts (from typing backwards) are emitted as a single insert. The content
+            // is stored in document order, so the result is the same.
+            ListOpKind::Ins => TracePatch(op.start(), 0, op.content.expect("Trace export needs inserted content")),
+            ListOpKind::Del => TracePatch(op.start(), op.len(), SmartString::new()),
+        }
+    }
+}
+
+/// An editing trace which keeps the document's causal graph. See the
+/// [module documentation](self) for details.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename = "concurrent", rename_all = "camelCase")]
+pub struct ConcurrentTrace {
+    pub end_content: String,
+    pub num_agents: usize,
+    pub txns: Vec<ConcurrentTraceTxn>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConcurrentTraceTxn {
+    /// The indexes of the transactions this transaction directly depends on. Parents always come
+    /// before their children.
+    pub parents: SmallVec<[usize; 2]>,
+    pub num_children: usize,
+    /// The agent which made the change. Agents are numbered in the order of their names, so
+    /// concurrent inserts at the same location are ordered the same way as in diamond types.
+    pub agent: usize,
+    /// The changes in the transaction. Each patch is relative to the document after the previous
+    /// patch.
+    pub patches: SmallVec<[TracePatch; 2]>,
+}
+
+/// An editing trace with all changes in a single sequence. This is the format of the older
+/// benchmark traces (like `automerge-paper.json.gz`).
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SequentialTrace {
+    pub start_content: String,
+    pub end_content: String,
+    pub txns: Vec<SequentialTraceTxn>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SequentialTraceTxn {
+    /// ISO 8601 timestamp. Diamond types doesn't store when changes were made, so the same time is
+    /// used for every transaction.
+    pub time: SmartString,
+    pub patches: Vec<TracePatch>,
+}
+
+impl ListOpLog {
+    /// Export the oplog as a concurrent editing trace.
+    ///
+    /// If the oplog's history ends with multiple concurrent branches, an extra empty transaction
+    /// is added at the end to merge them together.
+    pub fn export_trace(&self) -> ConcurrentTrace {
+        // Concurrent inserts at the same location are ordered by agent name in diamond types, and
+        // by agent number in traces. Number agents in name order so the order matches.
+        let num_agents = self.cg.num_agents();
+        let mut sorted_agents: Vec<AgentId> = (0..num_agents as AgentId).collect();
+        sorted_agents.sort_by_key(|agent| self.cg.agent_assignment.get_agent_name(*agent));
+        let mut agent_map = vec![0; num_agents];
+        for (i, agent) in sorted_agents.into_iter().enumerate() {
+            agent_map[agent as usize] = i;
+        }
+
+        let mut txns: Vec<ConcurrentTraceTxn> = vec![];
+        // Map from the last version in each transaction to its index.
+        let mut idx_for_v = HashMap::new();
+
+        for entry in self.as_chunked_operation_vec() {
+            let parents: SmallVec<[usize; 2]> = entry.parents.iter().map(|v| idx_for_v[v]).collect();
+            for &p in &parents {
+                txns[p].num_children += 1;
+            }
+
+            idx_for_v.insert(entry.span.last(), txns.len());
+            txns.push(ConcurrentTraceTxn {
+                parents,
+                num_children: 0,
+                agent: agent_map[entry.agent_span.agent as usize],
+                patches: entry.ops.into_iter().map(|op| op.into()).collect(),
+            });
+        }
+
+        if let Some((_, rest)) = txns.split_last_mut() {
+            if rest.iter().any(|txn| txn.num_children == 0) {
+                // Merge all the transactions with no children.
+                let mut merge = ConcurrentTraceTxn {
+                    parents: smallvec![],
+                    num_children: 0,
+                    agent: 0,
+                    patches: smallvec![],
+                };
+                for (i, txn) in rest.iter_mut().enumerate() {
+                    if txn.num_children == 0 {
+                        txn.num_children = 1;
+                        merge.parents.push(i);
+                    }
+                }
+                txns.last_mut().unwrap().num_children = 1;
+                merge.parents.push(txns.len() - 1);
+                txns.push(merge);
+            }
+        }
+
+        ConcurrentTrace {
+            end_content: self.checkout_tip().content().to_string(),
+            num_agents,
+            txns,
+        }
+    }
+
+    /// Export the oplog as a sequential editing trace. Consecutive changes from the same agent are
+    /// grouped into a transaction, and every transaction is given the timestamp `time`.
+    pub fn export_sequential_trace(&self, time: &str) -> SequentialTrace {
+        let mut txns: Vec<SequentialTraceTxn> = vec![];
+        let mut last_agent = None;
+
+        for (range, op) in self.iter_xf_operations() {
+            let Some(mut op) = op else { continue; };
+
+            for span in self.cg.agent_assignment.iter_remote_mappings_range(range) {
+                let op_here = op.truncate_keeping_right(span.1.len());
+                if last_agent != Some(span.0) {
+                    txns.push(SequentialTraceTxn { time: time.into(), patches: vec![] });
+                    last_agent = Some(span.0);
+                }
+                txns.last_mut().unwrap().patches.push(op_here.into());
+            }
+        }
+
+        SequentialTrace {
+            start_content: String::new(),
+            end_content: self.checkout_tip().content().to_string(),
+            txns,
+        }
+    }
+
+    /// Export the oplog as a concurrent editing trace in JSON. See
+    /// [`export_trace`](ListOpLog::export_trace).
+    pub fn export_trace_json(&self) -> String {
+        serde_json::to_string(&self.export_trace()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use jumprope::JumpRope;
+    use crate::list::ListOpLog;
+    use crate::list::operation::TextOperation;
+    use crate::list::trace_export::{ConcurrentTrace, TracePatch};
+
+    fn patch_op(TracePatch(pos, del, ins): &TracePatch) -> TextOperation {
+        if *del > 0 { TextOperation::new_delete(*pos..*pos + *del) }
+        else { TextOperation::new_insert(*pos, ins) }
+    }
+
+    #[test]
+    fn export_traces() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert(seph, 0, "hello world");
+        let b = oplog.add_insert_at(mike, &[a], 5, " there");
+        let c = oplog.add_delete_at(seph, &[a], 0..6);
+        oplog.add_insert_at(seph, &[b, c], 0, "oh ");
+        // Leave a concurrent change at the end.
+        oplog.add_insert_at(mike, &[b, c], 0, "x");
+
+        let trace = oplog.export_trace();
+        assert_eq!(trace.num_agents, 2);
+        // mike sorts before seph.
+        assert_eq!(trace.txns[1].agent, 0);
+        assert_eq!(trace.txns.last().unwrap().parents.len(), 2);
+
+        let json = oplog.export_trace_json();
+        assert!(json.starts_with(r#"{"kind":"concurrent","endContent":"#));
+        let trace: ConcurrentTrace = serde_json::from_str(&json).unwrap();
+
+        // Replay the trace.
+        let mut result = ListOpLog::new();
+        let agents = [result.get_or_create_agent_id("mike"), result.get_or_create_agent_id("seph")];
+        let mut txn_versions = vec![];
+        for txn in &trace.txns {
+            // The final merge transaction has no changes.
+            if txn.patches.is_empty() { continue; }
+            let parents: Vec<_> = txn.parents.iter().map(|&p| txn_versions[p]).collect();
+            let ops: Vec<_> = txn.patches.iter().map(patch_op).collect();
+            txn_versions.push(result.add_operations_at(agents[txn.agent], &parents, &ops));
+        }
+        assert_eq!(result.checkout_tip().content().to_string(), trace.end_content);
+        assert_eq!(trace.end_content, oplog.checkout_tip().content().to_string());
+
+        // The sequential trace can be read by the benchmark loader.
+        let json = serde_json::to_string(&oplog.export_sequential_trace("2024-01-01T00:00:00Z")).unwrap();
+        let data: crdt_testdata::TestData = serde_json::from_str(&json).unwrap();
+        let mut doc = JumpRope::from(data.start_content.as_str());
+        for patch in data.txns.iter().flat_map(|txn| txn.patches.iter()) {
+            doc.remove(patch.0..patch.0 + patch.1);
+            doc.insert(patch.0, &patch.2);
+        }
+        assert_eq!(doc.to_string(), data.end_content);
+        assert_eq!(data.end_content, trace.end_content);
+    }
+}
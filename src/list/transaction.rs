@@ -0,0 +1,150 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+use rle::{HasLength, MergableSpan};
+use crate::{AgentId, DTRange};
+use crate::list::{ListCRDT, ListOpLog};
+use crate::list::operation::TextOperation;
+
+/// A transaction collects a set of local edits, which are committed to the oplog together.
+///
+/// Each edit is relative to the document state after the previous edits in the transaction have
+/// been applied. When the transaction is committed, all edits are assigned a single contiguous span
+/// of local versions and a single entry in the causal graph. Adjacent edits are also run-length
+/// merged before they're committed.
+///
+/// Create transactions using [`ListOpLog::transaction`] or [`ListCRDT::transaction`].
+#[derive(Debug, Clone, Default)]
+pub struct ListTransaction {
+    ops: Vec<TextOperation>,
+}
+
+impl ListTransaction {
+    /// Add an insert to the transaction.
+    pub fn insert(&mut self, pos: usize, content: &str) {
+        self.push(TextOperation::new_insert(pos, content));
+    }
+
+    /// Add a delete (without content) to the transaction.
+    pub fn delete(&mut self, loc: Range<usize>) {
+        self.push(TextOperation::new_delete(loc));
+    }
+
+    /// Add an arbitrary operation to the transaction.
+    pub fn push(&mut self, op: TextOperation) {
+        if op.is_empty() { return; }
+
+        if let Some(last) = self.ops.last_mut() {
+            if last.can_append(&op) {
+                last.append(op);
+                return;
+            }
+        }
+        self.ops.push(op);
+    }
+
+    /// The number of (merged) operations in the transaction.
+    pub fn num_ops(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// The operations in this transaction, after merging.
+    pub fn ops(&self) -> &[TextOperation] {
+        &self.ops
+    }
+}
+
+impl ListOpLog {
+    /// Make a set of local changes to the oplog, atomically. The passed function is called with a
+    /// [`ListTransaction`], which collects the changes to make. When the function returns, all
+    /// changes are appended to the oplog at the current version.
+    ///
+    /// Returns the span of local versions assigned to the changes. This will be empty if the
+    /// transaction was empty.
+    ///
+    /// ```
+    /// use diamond_types::list::ListOpLog;
+    /// let mut oplog = ListOpLog::new();
+    /// let seph = oplog.get_or_create_agent_id("seph");
+    /// let span = oplog.transaction(seph, |txn| {
+    ///     txn.insert(0, "hi");
+    ///     txn.insert(2, " there");
+    ///     txn.delete(0..1);
+    /// });
+    /// assert_eq!(span, (0..9).into());
+    /// assert_eq!(oplog.checkout_tip().content().to_string(), "i there");
+    /// ```
+    pub fn transaction<F: FnOnce(&mut ListTransaction)>(&mut self, agent: AgentId, f: F) -> DTRange {
+        let mut txn = ListTransaction::default();
+        f(&mut txn);
+
+        let start = self.len();
+        if txn.is_empty() { return (start..start).into(); }
+
+        let last = self.add_operations(agent, &txn.ops);
+        (start..last + 1).into()
+    }
+}
+
+impl ListCRDT {
+    /// Make a set of local changes to the document, atomically. See
+    /// [`ListOpLog::transaction`].
+    pub fn transaction<F: FnOnce(&mut ListTransaction)>(&mut self, agent: AgentId, f: F) -> DTRange {
+        let mut txn = ListTransaction::default();
+        f(&mut txn);
+
+        let start = self.oplog.len();
+        if txn.is_empty() { return (start..start).into(); }
+
+        let last = self.apply_local_operations(agent, &txn.ops);
+        (start..last + 1).into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::{ListCRDT, ListOpLog};
+
+    #[test]
+    fn transaction_uses_one_graph_entry() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        oplog.add_insert(mike, 0, "xxx");
+
+        let span = oplog.transaction(seph, |txn| {
+            txn.insert(0, "a");
+            txn.insert(1, "b");
+            txn.insert(2, "c");
+            txn.delete(5..6);
+            assert_eq!(txn.num_ops(), 2);
+        });
+        assert_eq!(span, (3..7).into());
+        // The graph entry merges with mike's insert, since it directly follows it.
+        assert_eq!(oplog.cg.graph.num_entries(), 1);
+        // xxx, abc, and the delete.
+        assert_eq!(oplog.operations.num_entries(), 3);
+        assert_eq!(oplog.checkout_tip().content().to_string(), "abcxx");
+
+        let empty = oplog.transaction(seph, |_txn| {});
+        assert!(empty.is_empty());
+        assert_eq!(oplog.len(), 7);
+    }
+
+    #[test]
+    fn crdt_transaction() {
+        let mut doc = ListCRDT::new();
+        let seph = doc.get_or_create_agent_id("seph");
+        doc.transaction(seph, |txn| {
+            txn.insert(0, "hello");
+            txn.delete(0..1);
+            txn.insert(0, "j");
+        });
+        assert_eq!(doc.branch.content().to_string(), "jello");
+        assert_eq!(doc.oplog.checkout_tip(), doc.branch);
+        assert_eq!(doc.oplog.cg.graph.num_entries(), 1);
+    }
+}
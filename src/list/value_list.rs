@@ -0,0 +1,595 @@
+//! An ordered list of arbitrary values (eg IDs or serialized JSON blobs), rather than characters.
+//!
+//! The list CRDT doesn't care what's in the list. It only needs to know where each item was
+//! inserted, and which items were deleted. So [`ValueListOpLog`] reuses the text oplog and its
+//! transform / merge machinery. Each value is stored in the text oplog as a single placeholder
+//! character (U+FFFC, the object replacement character), and the values themselves are kept in a
+//! side table keyed by the local version of the insert which created them.
+//!
+//! # Moves
+//!
+//! Items can be moved with [`ValueListBranch::move_item`]. Each item keeps its identity (the
+//! local version of the insert which created it) when it's moved. In the underlying oplog, a move
+//! deletes the item's current slot and inserts a new placeholder slot for the same item.
+//!
+//! If two peers concurrently move the same item, both new slots end up in the list. Only one of
+//! them is visible: the winner is picked using the oplog's [`TieBreak`](crate::list::tie_break::TieBreak)
+//! strategy, so every peer agrees on where the item ends up. The next move or delete of the item
+//! removes all of its slots. If an item is moved concurrently with being deleted, the move wins.
+//!
+//! Moves only exist at this layer. There's no move operation in the text oplog, its op metrics or
+//! the merge tracker, so text documents still move content using a delete and an insert, and
+//! concurrent edits to the moved text aren't carried along with it. Adding a move op to the text
+//! CRDT would change every part of it, as well as the file format.
+//!
+//! # Encoding
+//!
+//! [`ValueListOpLog::encode_from`] writes the encoded text oplog, followed by the values of the
+//! encoded operations. The values are named by the agent and sequence number of their insert,
+//! since local versions differ between peers. The list doesn't know how to serialize values, so
+//! the caller passes functions to convert each value to and from bytes. Slots inserted by moves
+//! are written with the name of the item they hold.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::ops::Range;
+use rle::HasLength;
+use crate::{AgentId, DTRange, Frontier, LV};
+use crate::encoding::bufparser::BufParser;
+use crate::encoding::parseerror::ParseError;
+use crate::encoding::tools::push_str;
+use crate::encoding::varint::push_usize;
+use crate::list::ListOpLog;
+use crate::list::encoding::EncodeOptions;
+use crate::list::operation::ListOpKind;
+use crate::listmerge::merge::TransformedResult::BaseMoved;
+use crate::rle::KVPair;
+
+const PLACEHOLDER: char = '\u{FFFC}';
+
+pub(crate) fn placeholders(len: usize) -> String {
+    core::iter::repeat_n(PLACEHOLDER, len).collect()
+}
+
+/// An entry in the encoded value table.
+enum SlotEntry<'a, V> {
+    Empty,
+    Value(V),
+    /// A slot inserted by a move, holding the item with this agent name and sequence number.
+    Moved(&'a str, usize),
+}
+
+/// An oplog for a list of values of type V. See the [module documentation](self) for details.
+///
+/// Positions passed to the `add_*` methods are positions in the list of slots, which includes
+/// the hidden slots of concurrently moved items. Use [`ValueListBranch`] to edit the list using
+/// visible positions.
+#[derive(Debug, Clone)]
+pub struct ValueListOpLog<V> {
+    oplog: ListOpLog,
+
+    /// The value inserted at each local version. Deletes and moves have no value.
+    values: Vec<Option<V>>,
+
+    /// For each slot inserted by a move, the item which was moved there.
+    moves: BTreeMap<LV, LV>,
+}
+
+/// A list of values at some version, checked out from a [`ValueListOpLog`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ValueListBranch<V> {
+    version: Frontier,
+
+    /// Every slot in the list at this version (including hidden slots), as (slot, item) pairs.
+    slots: Vec<(LV, LV)>,
+
+    /// The indexes in slots of the visible slots.
+    visible: Vec<usize>,
+
+    /// The value of each visible item.
+    values: Vec<V>,
+}
+
+impl<V: Clone> Default for ValueListOpLog<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: Clone> ValueListOpLog<V> {
+    pub fn new() -> Self {
+        Self {
+            oplog: ListOpLog::new(),
+            values: vec![],
+            moves: BTreeMap::new(),
+        }
+    }
+
+    /// The underlying oplog, which stores the list's operations and causal graph. Each slot shows
+    /// up in the oplog as a placeholder character.
+    pub fn oplog(&self) -> &ListOpLog { &self.oplog }
+
+    pub fn get_or_create_agent_id(&mut self, name: &str) -> AgentId {
+        self.oplog.get_or_create_agent_id(name)
+    }
+
+    /// The number of operations in the oplog.
+    pub fn len(&self) -> usize { self.oplog.len() }
+
+    pub fn is_empty(&self) -> bool { self.oplog.is_empty() }
+
+    pub fn local_frontier_ref(&self) -> &[LV] { self.oplog.local_frontier_ref() }
+
+    /// The item stored in a slot. This is the slot itself, unless the slot was inserted by a move.
+    pub fn item_in_slot(&self, slot: LV) -> LV {
+        self.moves.get(&slot).copied().unwrap_or(slot)
+    }
+
+    /// The value of an item, named by the local version of the insert which created it. Returns
+    /// None if that operation didn't insert a value.
+    pub fn value(&self, item: LV) -> Option<&V> {
+        self.values.get(item)?.as_ref()
+    }
+
+    /// Insert values at pos, in the list at the version named by parents.
+    pub fn add_insert_at(&mut self, agent: AgentId, parents: &[LV], pos: usize, values: &[V]) -> LV {
+        let v = self.oplog.add_insert_at(agent, parents, pos, &placeholders(values.len()));
+        self.values.extend(values.iter().cloned().map(Some));
+        v
+    }
+
+    /// Delete the slots in range, in the list at the version named by parents.
+    pub fn add_delete_at(&mut self, agent: AgentId, parents: &[LV], range: Range<usize>) -> LV {
+        let v = self.oplog.add_delete_at(agent, parents, range);
+        self.values.resize(self.oplog.len(), None);
+        v
+    }
+
+    /// Insert a new slot for an existing item at pos, in the list at the version named by parents.
+    fn add_move_at(&mut self, agent: AgentId, parents: &[LV], pos: usize, item: LV) -> LV {
+        let v = self.oplog.add_insert_at(agent, parents, pos, &placeholders(1));
+        self.values.push(None);
+        self.moves.insert(v, item);
+        v
+    }
+
+    /// Add all operations (and their values) from the other oplog which this oplog is missing.
+    pub fn add_missing_operations_from(&mut self, other: &Self) {
+        let start = self.oplog.len();
+        self.oplog.add_missing_operations_from(&other.oplog);
+
+        let aa = &self.oplog.cg.agent_assignment;
+        let other_aa = &other.oplog.cg.agent_assignment;
+        for lv in start..self.oplog.len() {
+            let (agent, seq) = aa.local_to_agent_version(lv);
+            let other_agent = other_aa.get_agent_id(aa.get_agent_name(agent)).unwrap();
+            let other_lv = other_aa.client_data[other_agent as usize].seq_to_lv(seq);
+            self.values.push(other.values[other_lv].clone());
+
+            if let Some(other_item) = other.moves.get(&other_lv) {
+                let (other_agent, seq) = other_aa.local_to_agent_version(*other_item);
+                let agent = aa.get_agent_id(other_aa.get_agent_name(other_agent)).unwrap();
+                self.moves.insert(lv, aa.client_data[agent as usize].seq_to_lv(seq));
+            }
+        }
+    }
+
+    /// Encode the operations since from_version, and their values. Each value is converted to
+    /// bytes using write_value.
+    pub fn encode_from<F: FnMut(&V) -> Vec<u8>>(&self, opts: EncodeOptions, from_version: &[LV], mut write_value: F) -> Vec<u8> {
+        let oplog_data = self.oplog.encode_from(opts, from_version);
+        let mut result = Vec::new();
+        push_usize(&mut result, oplog_data.len());
+        result.extend_from_slice(&oplog_data);
+
+        let aa = &self.oplog.cg.agent_assignment;
+        let (_, ranges) = self.oplog.cg.graph.diff(from_version, self.local_frontier_ref());
+        let mut spans = Vec::new();
+        for mut range in ranges {
+            while !range.is_empty() {
+                let span = aa.local_span_to_agent_span(range);
+                spans.push((range.start, span));
+                range.start += span.seq_range.len();
+            }
+        }
+
+        push_usize(&mut result, spans.len());
+        for (start, span) in spans {
+            push_str(&mut result, aa.get_agent_name(span.agent));
+            push_usize(&mut result, span.seq_range.start);
+            push_usize(&mut result, span.seq_range.len());
+            for lv in start..start + span.seq_range.len() {
+                match (&self.values[lv], self.moves.get(&lv)) {
+                    (Some(value), _) => {
+                        push_usize(&mut result, 1);
+                        let bytes = write_value(value);
+                        push_usize(&mut result, bytes.len());
+                        result.extend_from_slice(&bytes);
+                    }
+                    (None, Some(&item)) => {
+                        push_usize(&mut result, 2);
+                        let (agent, seq) = aa.local_to_agent_version(item);
+                        push_str(&mut result, aa.get_agent_name(agent));
+                        push_usize(&mut result, seq);
+                    }
+                    (None, None) => push_usize(&mut result, 0),
+                }
+            }
+        }
+        result
+    }
+
+    /// Encode the whole list. See [`encode_from`](ValueListOpLog::encode_from).
+    pub fn encode<F: FnMut(&V) -> Vec<u8>>(&self, opts: EncodeOptions, write_value: F) -> Vec<u8> {
+        self.encode_from(opts, &[], write_value)
+    }
+
+    /// Add the operations and values from data made by [`encode_from`](ValueListOpLog::encode_from)
+    /// into this list. Each value is read from bytes using read_value, which returns None if the
+    /// bytes aren't a valid value.
+    ///
+    /// If an error occurs, the list is left unchanged.
+    pub fn decode_and_add<F: FnMut(&[u8]) -> Option<V>>(&mut self, data: &[u8], mut read_value: F) -> Result<Frontier, ParseError> {
+        let mut reader = BufParser(data);
+        let len = reader.next_usize()?;
+        let oplog_data = reader.next_n_bytes(len)?;
+
+        // Read the values before touching the oplog.
+        let mut entries: Vec<(&str, usize, SlotEntry<V>)> = Vec::new();
+        for _ in 0..reader.next_usize()? {
+            let name = reader.next_str()?;
+            let seq_start = reader.next_usize()?;
+            let len = reader.next_usize()?;
+            for seq in seq_start..seq_start.checked_add(len).ok_or(ParseError::InvalidLength)? {
+                let value = match reader.next_usize()? {
+                    0 => SlotEntry::Empty,
+                    1 => {
+                        let len = reader.next_usize()?;
+                        SlotEntry::Value(read_value(reader.next_n_bytes(len)?).ok_or(ParseError::InvalidContent)?)
+                    }
+                    2 => SlotEntry::Moved(reader.next_str()?, reader.next_usize()?),
+                    _ => { return Err(ParseError::GenericInvalidData); }
+                };
+                entries.push((name, seq, value));
+            }
+        }
+        reader.expect_empty()?;
+
+        let mut oplog = self.oplog.clone();
+        let frontier = oplog.decode_and_add(oplog_data)?;
+        let start = self.oplog.len();
+        let mut values: Vec<Option<Option<V>>> = vec![None; oplog.len() - start];
+        let mut moves = BTreeMap::new();
+        let aa = &oplog.cg.agent_assignment;
+        let find = |name: &str, seq: usize| {
+            aa.get_agent_id(name)
+                .and_then(|agent| aa.client_data[agent as usize].try_seq_to_lv(seq))
+                .ok_or(ParseError::DataMissing)
+        };
+        for (name, seq, entry) in entries {
+            let lv = find(name, seq)?;
+            if lv < start { continue; }
+            values[lv - start] = Some(match entry {
+                SlotEntry::Empty => None,
+                SlotEntry::Value(value) => Some(value),
+                SlotEntry::Moved(name, seq) => {
+                    moves.insert(lv, find(name, seq)?);
+                    None
+                }
+            });
+        }
+
+        // Every new operation needs an entry, and inserts need a value or a moved item.
+        let new_ops: DTRange = (start..oplog.len()).into();
+        for (KVPair(op_start, metrics), _) in oplog.iter_range_simple(new_ops) {
+            for lv in op_start..op_start + metrics.len() {
+                let Some(value) = &values[lv - start] else { return Err(ParseError::DataMissing); };
+                let valid = match metrics.kind {
+                    ListOpKind::Ins => value.is_some() != moves.contains_key(&lv),
+                    ListOpKind::Del => value.is_none() && !moves.contains_key(&lv),
+                };
+                if !valid { return Err(ParseError::InvalidContent); }
+            }
+        }
+
+        // Moved items must be inserts which have a value.
+        for &item in moves.values() {
+            let has_value = if item < start { self.values[item].is_some() } else { matches!(values[item - start], Some(Some(_))) };
+            if !has_value { return Err(ParseError::InvalidContent); }
+        }
+
+        self.oplog = oplog;
+        self.values.extend(values.into_iter().map(Option::unwrap));
+        self.moves.extend(moves);
+        Ok(frontier)
+    }
+
+    /// Load a list from data made by [`encode`](ValueListOpLog::encode). See
+    /// [`decode_and_add`](ValueListOpLog::decode_and_add).
+    pub fn load_from<F: FnMut(&[u8]) -> Option<V>>(data: &[u8], read_value: F) -> Result<Self, ParseError> {
+        let mut list = Self::new();
+        list.decode_and_add(data, read_value)?;
+        Ok(list)
+    }
+
+    pub fn checkout(&self, version: &[LV]) -> ValueListBranch<V> {
+        let mut branch = ValueListBranch::new();
+        branch.merge(self, version);
+        branch
+    }
+
+    pub fn checkout_tip(&self) -> ValueListBranch<V> {
+        self.checkout(self.local_frontier_ref())
+    }
+}
+
+impl<V: Clone> Default for ValueListBranch<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: Clone> ValueListBranch<V> {
+    /// Create a new (empty) list at the start of history.
+    pub fn new() -> Self {
+        Self {
+            version: Frontier::root(),
+            slots: vec![],
+            visible: vec![],
+            values: vec![],
+        }
+    }
+
+    pub fn local_frontier_ref(&self) -> &[LV] { self.version.as_ref() }
+
+    pub fn values(&self) -> &[V] { &self.values }
+
+    /// The number of (visible) items in the list.
+    pub fn len(&self) -> usize { self.values.len() }
+
+    pub fn is_empty(&self) -> bool { self.values.is_empty() }
+
+    /// The item at each position in the list, named by the local version of the insert which
+    /// created it. Items keep their name when they're moved.
+    pub fn items(&self) -> impl Iterator<Item = LV> + '_ {
+        self.visible.iter().map(|i| self.slots[*i].1)
+    }
+
+    /// Figure out which slots are visible. When an item has multiple slots, the winning slot is
+    /// the last one in tie break order.
+    fn refresh(&mut self, oplog: &ValueListOpLog<V>) {
+        let aa = &oplog.oplog.cg.agent_assignment;
+        let tie_break = oplog.oplog.tie_break();
+        let mut winners: BTreeMap<LV, LV> = BTreeMap::new();
+        for &(slot, item) in self.slots.iter() {
+            winners.entry(item)
+                .and_modify(|w| if tie_break.cmp(aa, *w, slot) == Ordering::Less { *w = slot; })
+                .or_insert(slot);
+        }
+
+        self.visible = self.slots.iter().enumerate()
+            .filter(|(_, (slot, item))| winners[item] == *slot)
+            .map(|(i, _)| i)
+            .collect();
+        self.values = self.items().map(|item| oplog.values[item].clone().unwrap()).collect();
+    }
+
+    /// The index in slots of the visible position pos.
+    fn slot_pos(&self, pos: usize) -> usize {
+        self.visible.get(pos).copied().unwrap_or(self.slots.len())
+    }
+
+    /// Add everything in merge_frontier into the list. This works the same way as
+    /// [`ListBranch::merge`](crate::list::ListBranch::merge).
+    pub fn merge(&mut self, oplog: &ValueListOpLog<V>, merge_frontier: &[LV]) {
+        let mut iter = oplog.oplog.get_xf_operations_full(self.version.as_ref(), merge_frontier);
+
+        for (lv, origin_op, xf) in &mut iter {
+            let BaseMoved(pos) = xf else { continue; };
+            let len = origin_op.len();
+
+            match origin_op.kind {
+                ListOpKind::Ins => {
+                    let slots = (lv..lv + len).map(|slot| (slot, oplog.item_in_slot(slot)));
+                    if origin_op.loc.fwd {
+                        self.slots.splice(pos..pos, slots);
+                    } else {
+                        self.slots.splice(pos..pos, slots.rev());
+                    }
+                }
+                ListOpKind::Del => {
+                    self.slots.drain(pos..pos + len);
+                }
+            }
+        }
+
+        self.version = iter.into_frontier();
+        self.refresh(oplog);
+    }
+
+    /// Insert values at pos, and add the change to the oplog.
+    pub fn insert(&mut self, oplog: &mut ValueListOpLog<V>, agent: AgentId, pos: usize, values: &[V]) -> LV {
+        assert!(pos <= self.len(), "Insert position is past the end of the list");
+        assert!(!values.is_empty(), "Cannot insert an empty list of values");
+        let slot_pos = self.slot_pos(pos);
+        let v = oplog.add_insert_at(agent, self.version.as_ref(), slot_pos, values);
+        let start = v + 1 - values.len();
+        self.slots.splice(slot_pos..slot_pos, (start..=v).map(|slot| (slot, slot)));
+        self.version.replace_with_1(v);
+        self.refresh(oplog);
+        v
+    }
+
+    /// Delete every slot of the named items. Returns the version of the last delete.
+    fn remove_items(&mut self, oplog: &mut ValueListOpLog<V>, agent: AgentId, items: &[LV]) -> LV {
+        // Slots are deleted back to front, so the positions of the remaining slots don't change.
+        let mut end = self.slots.len();
+        let mut v = None;
+        while end > 0 {
+            if !items.contains(&self.slots[end - 1].1) {
+                end -= 1;
+                continue;
+            }
+            let mut start = end - 1;
+            while start > 0 && items.contains(&self.slots[start - 1].1) { start -= 1; }
+
+            let lv = oplog.add_delete_at(agent, self.version.as_ref(), start..end);
+            self.slots.drain(start..end);
+            self.version.replace_with_1(lv);
+            v = Some(lv);
+            end = start;
+        }
+        v.unwrap()
+    }
+
+    /// Delete the items in range, and add the change to the oplog. If any of the items have been
+    /// concurrently moved, every copy of them is deleted.
+    pub fn delete(&mut self, oplog: &mut ValueListOpLog<V>, agent: AgentId, range: Range<usize>) -> LV {
+        assert!(range.end <= self.len(), "Delete range is past the end of the list");
+        assert!(!range.is_empty(), "Cannot delete an empty range");
+        let items: Vec<LV> = self.items().skip(range.start).take(range.len()).collect();
+        let v = self.remove_items(oplog, agent, &items);
+        self.refresh(oplog);
+        v
+    }
+
+    /// Move the item at index `from` so it ends up at index `to`. See the
+    /// [module documentation](self) for how concurrent moves behave.
+    pub fn move_item(&mut self, oplog: &mut ValueListOpLog<V>, agent: AgentId, from: usize, to: usize) -> LV {
+        assert!(from < self.len() && to < self.len(), "Move position is past the end of the list");
+        let item = self.items().nth(from).unwrap();
+        self.remove_items(oplog, agent, &[item]);
+        self.refresh(oplog);
+
+        let slot_pos = self.slot_pos(to);
+        let v = oplog.add_move_at(agent, self.version.as_ref(), slot_pos, item);
+        self.slots.insert(slot_pos, (v, item));
+        self.version.replace_with_1(v);
+        self.refresh(oplog);
+        v
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::encoding::parseerror::ParseError;
+    use crate::list::encoding::EncodeOptions;
+    use crate::list::value_list::{ValueListBranch, ValueListOpLog};
+
+    #[test]
+    fn value_list() {
+        let mut a: ValueListOpLog<u32> = ValueListOpLog::new();
+        let seph = a.get_or_create_agent_id("seph");
+        let mut branch = ValueListBranch::new();
+        branch.insert(&mut a, seph, 0, &[10, 20, 30]);
+        branch.move_item(&mut a, seph, 0, 2);
+        assert_eq!(branch.values(), &[20, 30, 10]);
+        assert_eq!(a.checkout_tip(), branch);
+
+        // Concurrent changes on another peer.
+        let mut b = ValueListOpLog::new();
+        b.add_missing_operations_from(&a);
+        let mike = b.get_or_create_agent_id("mike");
+        let mut branch_b = b.checkout_tip();
+        branch_b.insert(&mut b, mike, 1, &[99]);
+        branch_b.delete(&mut b, mike, 0..1);
+
+        branch.insert(&mut a, seph, 3, &[40]);
+
+        a.add_missing_operations_from(&b);
+        b.add_missing_operations_from(&a);
+        branch.merge(&a, a.local_frontier_ref());
+        branch_b.merge(&b, b.local_frontier_ref());
+        assert_eq!(branch.values(), &[99, 30, 10, 40]);
+        assert_eq!(branch_b.values(), branch.values());
+        assert_eq!(a.checkout_tip().values(), branch.values());
+
+        // Values are stored as placeholders in the underlying oplog.
+        assert_eq!(a.oplog().checkout_tip().len(), 4);
+        assert_eq!(a.value(0), Some(&10));
+    }
+
+    #[test]
+    fn concurrent_moves() {
+        let mut a: ValueListOpLog<&str> = ValueListOpLog::new();
+        let seph = a.get_or_create_agent_id("seph");
+        let mut branch_a = ValueListBranch::new();
+        branch_a.insert(&mut a, seph, 0, &["milk", "eggs", "bread", "jam"]);
+        let mut b = a.clone();
+        let mike = b.get_or_create_agent_id("mike");
+        let mut branch_b = branch_a.clone();
+
+        // Both peers move eggs to different places. Mike also moves jam, and seph deletes it.
+        branch_a.move_item(&mut a, seph, 1, 3);
+        branch_b.move_item(&mut b, mike, 1, 0);
+        branch_b.move_item(&mut b, mike, 3, 1);
+        branch_a.delete(&mut a, seph, 2..3);
+        assert_eq!(branch_a.values(), &["milk", "bread", "eggs"]);
+        assert_eq!(branch_b.values(), &["eggs", "jam", "milk", "bread"]);
+
+        a.add_missing_operations_from(&b);
+        b.add_missing_operations_from(&a);
+        branch_a.merge(&a, a.local_frontier_ref());
+        branch_b.merge(&b, b.local_frontier_ref());
+
+        // Seph's move of eggs wins ("seph" > "mike"), and mike's move of jam beats seph's delete.
+        assert_eq!(branch_a.values(), &["jam", "milk", "bread", "eggs"]);
+        assert_eq!(branch_b.values(), branch_a.values());
+        assert_eq!(a.checkout_tip(), branch_a);
+        // Eggs is still the same item.
+        assert_eq!(branch_a.items().nth(3), Some(1));
+
+        // Moving eggs again removes the hidden copy too.
+        branch_b.move_item(&mut b, mike, 3, 0);
+        assert_eq!(branch_b.values(), &["eggs", "jam", "milk", "bread"]);
+        assert_eq!(b.oplog().checkout_tip().len(), 4);
+        assert_eq!(b.checkout_tip(), branch_b);
+    }
+
+    #[test]
+    fn encode_values() {
+        let write = |v: &u32| v.to_le_bytes().to_vec();
+        let read = |b: &[u8]| Some(u32::from_le_bytes(b.try_into().ok()?));
+
+        let mut a: ValueListOpLog<u32> = ValueListOpLog::new();
+        let seph = a.get_or_create_agent_id("seph");
+        let mut branch = ValueListBranch::new();
+        branch.insert(&mut a, seph, 0, &[10, 20, 30]);
+        branch.delete(&mut a, seph, 1..2);
+
+        let mut b = ValueListOpLog::load_from(&a.encode(EncodeOptions::default(), write), read).unwrap();
+        assert_eq!(b.checkout_tip(), branch);
+
+        // Sync changes made on both sides.
+        let mike = b.get_or_create_agent_id("mike");
+        let mut branch_b = b.checkout_tip();
+        branch_b.insert(&mut b, mike, 2, &[40, 50]);
+        let version_a = a.local_frontier_ref().to_vec();
+        branch.insert(&mut a, seph, 0, &[60]);
+
+        let data = b.encode_from(EncodeOptions::default(), &version_a, write);
+        a.decode_and_add(&data, read).unwrap();
+        b.decode_and_add(&a.encode(EncodeOptions::default(), write), read).unwrap();
+        assert_eq!(a.checkout_tip().values(), &[60, 10, 30, 40, 50]);
+        assert_eq!(b.checkout_tip().values(), a.checkout_tip().values());
+
+        // Moves are synced too.
+        let version_b = b.local_frontier_ref().to_vec();
+        branch_b.merge(&b, &version_b);
+        branch_b.move_item(&mut b, mike, 4, 0);
+        a.decode_and_add(&b.encode_from(EncodeOptions::default(), &version_b, write), read).unwrap();
+        assert_eq!(a.checkout_tip().values(), &[50, 60, 10, 30, 40]);
+        // The moved item is still the insert of 50.
+        let item = a.checkout_tip().items().next().unwrap();
+        assert_eq!((a.item_in_slot(item), a.value(item)), (item, Some(&50)));
+        let c = ValueListOpLog::load_from(&a.encode(EncodeOptions::default(), write), read).unwrap();
+        assert_eq!(c.checkout_tip().values(), a.checkout_tip().values());
+
+        // Bad values are rejected, and the list is left alone.
+        let mut c: ValueListOpLog<u32> = ValueListOpLog::new();
+        assert_eq!(c.decode_and_add(&a.encode(EncodeOptions::default(), write), |_| None).unwrap_err(), ParseError::InvalidContent);
+        assert!(c.is_empty());
+    }
+}
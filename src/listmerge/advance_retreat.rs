@@ -1,7 +1,6 @@
-use std::ptr::NonNull;
-use content_tree::NodeLeaf;
+use core::ptr::NonNull;
 use rle::{HasLength, SplitableSpan};
-use crate::listmerge::{DocRangeIndex, M2Tracker};
+use crate::listmerge::{M2Tracker, RangeTreeLeaf};
 use crate::listmerge::markers::Marker::{DelTarget, InsPtr};
 use crate::listmerge::merge::notify_for;
 use crate::rev_range::RangeRev;
@@ -9,13 +8,14 @@ use crate::listmerge::yjsspan::CRDTSpan;
 use crate::list::operation::ListOpKind;
 use crate::list::operation::ListOpKind::{Del, Ins};
 use crate::dtrange::DTRange;
+use crate::LV;
 
 #[derive(Debug)]
 pub(super) struct QueryResult {
     tag: ListOpKind,
     target: RangeRev,
     offset: usize,
-    ptr: Option<NonNull<NodeLeaf<CRDTSpan, DocRangeIndex>>>
+    ptr: Option<NonNull<RangeTreeLeaf>>
 }
 
 impl M2Tracker {
@@ -55,7 +55,26 @@ impl M2Tracker {
         }
     }
 
+    /// Get the items deleted by the delete operation at lv. Returns at most max_len items, or None
+    /// if lv is an insert. The returned range is reversed (fwd is false) if later versions in the
+    /// delete deleted items with lower IDs.
+    ///
+    /// Like index_query, this should only be used with times we have advanced through.
+    pub(super) fn delete_target(&self, lv: LV, max_len: usize) -> Option<RangeRev> {
+        let QueryResult { tag, target, offset, .. } = self.index_query(lv);
+        if tag != Del { return None; }
+
+        let len = usize::min(target.len() - offset, max_len);
+        Some(RangeRev {
+            span: target.range(offset, offset + len),
+            fwd: target.fwd,
+        })
+    }
+
     pub(crate) fn advance_by_range(&mut self, mut range: DTRange) {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("advance", len = range.len()).entered();
+
         while !range.is_empty() {
             // Note the delete could be reversed - but we don't really care here; we just mark the
             // whole range anyway.
@@ -70,10 +89,9 @@ impl M2Tracker {
             // let mut len_remaining = len;
             while !target_range.is_empty() {
 
-                // STATS.with(|s| {
-                //     let mut s = s.borrow_mut();
-                //     s.1 += 1;
-                // });
+                #[cfg(feature = "tracing")] {
+                    self.stats.advance_mutations += 1;
+                }
 
                 // We'll only get a pointer when we're inserting. Note we can't reuse the ptr
                 // across subsequent invocations because we mutate the range_tree.
@@ -98,6 +116,9 @@ impl M2Tracker {
 
 
     pub(crate) fn retreat_by_range(&mut self, mut range: DTRange) {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("retreat", len = range.len()).entered();
+
         // We need to go through the range in reverse order to make sure if we visit an insert then
         // delete of the same item, we un-delete before un-inserting.
         // TODO: Could probably relax this restriction when I feel more comfortable about overall
@@ -121,10 +142,9 @@ impl M2Tracker {
             let mut target_range = target.range(e_offset, e_offset + len);
 
             while !target_range.is_empty() {
-                // STATS.with(|s| {
-                //     let mut s = s.borrow_mut();
-                //     s.2 += 1;
-                // });
+                #[cfg(feature = "tracing")] {
+                    self.stats.retreat_mutations += 1;
+                }
 
                 // Because the tag is either entirely delete or entirely insert, its safe to move
                 // forwards in this child range. (Which I'm doing because that makes the code much
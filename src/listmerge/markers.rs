@@ -1,12 +1,12 @@
-use std::fmt::Debug;
-use std::ptr::NonNull;
+use core::fmt::Debug;
+use core::ptr::NonNull;
 
 use rle::{HasLength, MergableSpan, SplitableSpan, SplitableSpanHelpers};
 
 use content_tree::*;
 use rle::Searchable;
 use crate::rev_range::RangeRev;
-use crate::listmerge::DocRangeIndex;
+use crate::listmerge::RangeTreeLeaf;
 use crate::listmerge::markers::Marker::{DelTarget, InsPtr};
 use crate::listmerge::yjsspan::CRDTSpan;
 use crate::list::operation::ListOpKind;
@@ -19,7 +19,7 @@ pub enum Marker {
     /// For inserts, we store a pointer to the leaf node containing the inserted item. This is only
     /// used for inserts so we don't need to modify multiple entries when the inserted item is
     /// moved.
-    InsPtr(NonNull<NodeLeaf<CRDTSpan, DocRangeIndex>>),
+    InsPtr(NonNull<RangeTreeLeaf>),
 
     /// For deletes we name the delete's target. Note this contains redundant information - since
     /// we already have a length field.
@@ -152,7 +152,7 @@ impl Default for MarkerEntry {
     fn default() -> Self {
         MarkerEntry {
             len: 0,
-            inner: InsPtr(std::ptr::NonNull::dangling()),
+            inner: InsPtr(core::ptr::NonNull::dangling()),
         }
     }
 }
@@ -169,7 +169,7 @@ impl Default for MarkerEntry {
 // }
 
 impl Searchable for MarkerEntry {
-    type Item = Option<NonNull<NodeLeaf<CRDTSpan, DocRangeIndex>>>;
+    type Item = Option<NonNull<RangeTreeLeaf>>;
 
     fn get_offset(&self, _loc: Self::Item) -> Option<usize> {
         panic!("Should never be used")
@@ -2,17 +2,23 @@
 // checker.
 #![allow(clippy::needless_option_as_deref)]
 
-use std::cmp::Ordering;
-use std::ptr::NonNull;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::ops::Range;
+use core::pin::Pin;
+use core::ptr::NonNull;
 use jumprope::JumpRopeBuf;
 use smallvec::{SmallVec, smallvec};
 use smartstring::alias::String as SmartString;
 use content_tree::*;
-use rle::{AppendRle, HasLength, MergeableIterator, Searchable, SplitableSpanCtx, Trim, TrimCtx};
+use rle::{AppendRle, HasLength, MergeableIterator, Searchable, SplitableSpan, SplitableSpanCtx, Trim, TrimCtx};
 use rle::intersect::rle_intersect_rev;
-use crate::listmerge::{DocRangeIndex, M2Tracker, SpaceIndex};
+use crate::listmerge::{M2Tracker, RangeTreeCursor, RangeTreeLeaf, RangeTreeUnsafeCursor, SpaceIndex};
 use crate::listmerge::yjsspan::{INSERTED, NOT_INSERTED_YET, CRDTSpan};
 use crate::list::operation::{ListOpKind, TextOperation};
+use crate::list::text_buffer::TextBuffer;
 use crate::dtrange::{DTRange, UNDERWATER_START};
 use crate::rle::{KVPair, RleSpanHelpers, RleVec};
 use crate::{AgentId, CausalGraph, Frontier, LV};
@@ -35,7 +41,10 @@ use crate::list::op_iter::OpMetricsIter;
 use crate::causalgraph::graph::Graph;
 use crate::textinfo::TextInfo;
 use crate::frontier::local_frontier_eq;
+use crate::list::MergeStats;
+use crate::unicount::chars_to_bytes;
 use crate::list::ListOpLog;
+use crate::list::tie_break::TieBreak;
 use crate::listmerge::plan::{M1Plan, M1PlanAction};
 #[cfg(feature = "ops_to_old")]
 use crate::listmerge::to_old::OldCRDTOpInternal;
@@ -53,12 +62,12 @@ fn pad_index_to(index: &mut SpaceIndex, desired_len: usize) {
     if index_len < desired_len {
         index.push(MarkerEntry {
             len: desired_len - index_len,
-            inner: InsPtr(std::ptr::NonNull::dangling()),
+            inner: InsPtr(core::ptr::NonNull::dangling()),
         });
     }
 }
 
-pub(super) fn notify_for(index: &mut SpaceIndex) -> impl FnMut(CRDTSpan, NonNull<NodeLeaf<CRDTSpan, DocRangeIndex, DEFAULT_IE, DEFAULT_LE>>) + '_ {
+pub(super) fn notify_for(index: &mut SpaceIndex) -> impl FnMut(CRDTSpan, NonNull<RangeTreeLeaf>) + '_ {
     move |entry: CRDTSpan, leaf| {
         debug_assert!(leaf != NonNull::dangling());
         let start = entry.id.start;
@@ -67,15 +76,12 @@ pub(super) fn notify_for(index: &mut SpaceIndex) -> impl FnMut(CRDTSpan, NonNull
         // Note we can only mutate_entries when we have something to mutate. The list is started
         // with a big placeholder "underwater" entry which will be split up as needed.
 
-        let mut cursor = index.unsafe_cursor_at_offset_pos(start, false);
-        unsafe {
-            ContentTreeRaw::unsafe_mutate_entries_notify(|marker| {
-                // The item should already be an insert entry.
-                debug_assert_eq!(marker.inner.tag(), ListOpKind::Ins);
+        index.mutate_range(start..start + len, |marker| {
+            // The item should already be an insert entry.
+            debug_assert_eq!(marker.inner.tag(), ListOpKind::Ins);
 
-                marker.inner = InsPtr(leaf);
-            }, &mut cursor, len, null_notify);
-        }
+            marker.inner = InsPtr(leaf);
+        });
     }
 }
 
@@ -88,6 +94,10 @@ fn take_content<'a>(x: Option<&mut &'a str>, len: usize) -> Option<&'a str> {
 
 impl M2Tracker {
     pub(super) fn new() -> Self {
+        Self::new_with_tie_break(TieBreak::default())
+    }
+
+    pub(super) fn new_with_tie_break(tie_break: TieBreak) -> Self {
         let mut range_tree = ContentTreeRaw::new();
         let mut index = ContentTreeRaw::new();
         let underwater = CRDTSpan::new_underwater();
@@ -97,24 +107,26 @@ impl M2Tracker {
         Self {
             range_tree,
             index,
+            tie_break,
             #[cfg(feature = "merge_conflict_checks")]
             concurrent_inserts_collide: false,
             #[cfg(feature = "ops_to_old")]
-            dbg_ops: vec![]
+            dbg_ops: vec![],
+            #[cfg(feature = "tracing")]
+            stats: Default::default(),
         }
     }
 
     pub(super) fn clear(&mut self) {
-        // TODO: Could make this cleaner with a clear() function in ContentTree.
-        self.range_tree = ContentTreeRaw::new();
-        self.index = ContentTreeRaw::new();
+        self.range_tree.clear();
+        self.index.clear();
 
         let underwater = CRDTSpan::new_underwater();
         pad_index_to(&mut self.index, underwater.id.end);
         self.range_tree.push_notify(underwater, notify_for(&mut self.index));
     }
 
-    pub(super) fn marker_at(&self, lv: LV) -> NonNull<NodeLeaf<CRDTSpan, DocRangeIndex>> {
+    pub(super) fn marker_at(&self, lv: LV) -> NonNull<RangeTreeLeaf> {
         let cursor = self.index.cursor_at_offset_pos(lv, false);
         // Gross.
         cursor.get_item().unwrap().unwrap()
@@ -132,7 +144,7 @@ impl M2Tracker {
         }
     }
 
-    fn get_cursor_before(&self, lv: LV) -> Cursor<CRDTSpan, DocRangeIndex> {
+    fn get_cursor_before(&self, lv: LV) -> RangeTreeCursor {
         if lv == usize::MAX {
             // This case doesn't seem to ever get hit by the fuzzer. It might be equally correct to
             // just panic() here.
@@ -144,7 +156,7 @@ impl M2Tracker {
     }
 
     // pub(super) fn get_unsafe_cursor_after(&self, time: Time, stick_end: bool) -> UnsafeCursor<YjsSpan2, DocRangeIndex> {
-    fn get_cursor_after(&self, lv: LV, stick_end: bool) -> Cursor<CRDTSpan, DocRangeIndex> {
+    fn get_cursor_after(&self, lv: LV, stick_end: bool) -> RangeTreeCursor {
         if lv == usize::MAX {
             self.range_tree.cursor_at_start()
         } else {
@@ -161,7 +173,7 @@ impl M2Tracker {
     }
 
     // TODO: Rewrite this to take a MutCursor instead of UnsafeCursor argument.
-    pub(super) fn integrate(&mut self, aa: &AgentAssignment, agent: AgentId, item: CRDTSpan, mut cursor: UnsafeCursor<CRDTSpan, DocRangeIndex>) -> usize {
+    pub(super) fn integrate(&mut self, aa: &AgentAssignment, item: CRDTSpan, mut cursor: RangeTreeUnsafeCursor) -> usize {
         debug_assert!(item.len() > 0);
 
         // Ok now that's out of the way, lets integrate!
@@ -205,34 +217,18 @@ impl M2Tracker {
             let other_left_lv = other_entry.origin_left_at_offset(cursor.offset);
             let other_left_cursor = self.get_cursor_after(other_left_lv, false);
 
-            // YjsMod / Fugue semantics. (The code here is the same for both CRDTs).
+            // YjsMod / FugueMax semantics. (The code here is the same for both CRDTs). Ordering
+            // right siblings by their origin_right (below) is what makes concurrent runs of inserts
+            // maximally non-interleaving - including runs typed in reverse.
             match unsafe { other_left_cursor.unsafe_cmp(&left_cursor) } {
                 Ordering::Less => { break; } // Top row
                 Ordering::Greater => {} // Bottom row. Continue.
                 Ordering::Equal => {
                     if item.origin_right == other_entry.origin_right {
-                        // Origin_right matches. Items are concurrent. Order by agent names.
-                        let my_name = aa.get_agent_name(agent);
-
-                        let (other_agent, other_seq) = aa.local_to_agent_version(other_lv);
-                        let other_name = aa.get_agent_name(other_agent);
-                        // eprintln!("concurrent insert at the same place {} ({}) vs {} ({})", item.id.start, my_name, other_lv, other_name);
-
-                        // Its possible for a user to conflict with themself if they commit to
-                        // multiple branches. In this case, sort by seq number.
-                        let ins_here = match my_name.cmp(other_name) {
-                            Ordering::Less => true,
-                            Ordering::Equal => {
-                                // We can't compare versions here because sequence numbers could be
-                                // used out of order, and the relative version ordering isn't
-                                // consistent in that case.
-                                //
-                                // We could cache this but this code doesn't run often anyway.
-                                let item_seq = aa.local_to_agent_version(item.id.start).1;
-                                item_seq < other_seq
-                            }
-                            Ordering::Greater => false,
-                        };
+                        // Origin_right matches. Items are concurrent. Order them using the tie
+                        // break strategy (by default, agent names).
+                        // eprintln!("concurrent insert at the same place {} vs {}", item.id.start, other_lv);
+                        let ins_here = self.tie_break.cmp(aa, item.id.start, other_lv) == Ordering::Less;
 
                         if ins_here {
                             // Insert here.
@@ -303,16 +299,17 @@ impl M2Tracker {
 
         let mut iter = OpMetricsIter::new(ops, op_ctx, range);
         // let mut iter = OpMetricsIter::new(&text_info.ops, &text_info.ctx, range);
+        let mut aa_hint = 0;
         while let Some(mut pair) = iter.next() {
             loop {
-                let span = aa.local_span_to_agent_span(pair.span());
+                let span = aa.local_span_to_agent_span_hinted(pair.span(), &mut aa_hint);
 
                 let len = span.len();
                 let remainder = pair.trim_ctx(len, iter.ctx);
 
                 let content = iter.get_content(&pair);
 
-                self.apply_to(aa, op_ctx, span.agent, &pair, content, to.as_deref_mut());
+                self.apply_to(aa, op_ctx, &pair, content, to.as_deref_mut());
 
                 if let Some(r) = remainder {
                     pair = r;
@@ -321,18 +318,20 @@ impl M2Tracker {
         }
     }
 
-    fn apply_to(&mut self, aa: &AgentAssignment, ctx: &ListOperationCtx, agent: AgentId, op_pair: &KVPair<ListOpMetrics>, content: Option<&str>, mut to: Option<&mut JumpRopeBuf>) {
+    fn apply_to(&mut self, aa: &AgentAssignment, ctx: &ListOperationCtx, op_pair: &KVPair<ListOpMetrics>, mut content: Option<&str>, mut to: Option<&mut JumpRopeBuf>) {
         let mut op_pair = op_pair.clone();
 
         loop {
-            // STATS.with(|s| {
-            //     let mut s = s.borrow_mut();
-            //     s.0 += 1;
-            // });
-
-            let (len_here, transformed_pos) = self.apply(aa, ctx, &op_pair, usize::MAX, agent);
+            let (len_here, transformed_pos) = self.apply(aa, ctx, &op_pair, usize::MAX);
 
             let remainder = op_pair.trim_ctx(len_here, ctx);
+            let (content_here, content_rest) = match content {
+                Some(c) if remainder.is_some() && op_pair.1.kind == ListOpKind::Ins => {
+                    let (a, b) = c.split_at(chars_to_bytes(c, len_here));
+                    (Some(a), Some(b))
+                }
+                _ => (content, content),
+            };
 
             // dbg!((&op_pair, len_here, transformed_pos));
             if let BaseMoved(pos) = transformed_pos {
@@ -343,7 +342,7 @@ impl M2Tracker {
                             // dbg!(&self.range_tree);
                             // println!("Insert '{}' at {} (len {})", op.content, ins_pos, op.len());
                             debug_assert!(op_pair.1.content_pos.is_some()); // Ok if this is false - we'll just fill with junk.
-                            let content = content.unwrap();
+                            let content = content_here.unwrap();
                             assert!(pos <= to.len_chars());
                             to.insert(pos, content);
                         }
@@ -360,10 +359,10 @@ impl M2Tracker {
 
             if let Some(r) = remainder {
                 op_pair = r;
-                // Curiously, we don't need to update content because we only use content for
-                // inserts, and inserts are always processed in one go. (Ie, there's never a
-                // remainder to worry about).
-                debug_assert_ne!(op_pair.1.kind, ListOpKind::Ins);
+                // Forward inserts are always processed in one go, but inserts typed in reverse
+                // are processed one character at a time.
+                debug_assert!(op_pair.1.kind == ListOpKind::Del || !op_pair.1.loc.fwd);
+                content = content_rest;
             } else { break; }
         }
     }
@@ -389,8 +388,12 @@ impl M2Tracker {
     /// | NotInsYet | Before     | After       |
     /// | Inserted  | After      | Before      |
     /// | Deleted   | Before     | Before      |
-    fn apply(&mut self, aa: &AgentAssignment, _ctx: &ListOperationCtx, op_pair: &KVPair<ListOpMetrics>, max_len: usize, agent: AgentId) -> (usize, TransformedResult) {
+    fn apply(&mut self, aa: &AgentAssignment, _ctx: &ListOperationCtx, op_pair: &KVPair<ListOpMetrics>, max_len: usize) -> (usize, TransformedResult) {
         // self.check_index();
+        #[cfg(feature = "tracing")] {
+            self.stats.applies += 1;
+        }
+
         // The op must have been applied at the branch that the tracker is currently at.
         let len = max_len.min(op_pair.len());
         let op = &op_pair.1;
@@ -398,7 +401,12 @@ impl M2Tracker {
         // dbg!(op);
         match op.kind {
             ListOpKind::Ins => {
-                if !op.loc.fwd { unimplemented!("Implement me!") }
+                // Inserts typed in reverse (eg by typing then pressing the left arrow key) are a
+                // run of single character inserts at the same position. Each character's
+                // origin_right is the character inserted before it, so they can't share a
+                // CRDTSpan. Integrate them one at a time - the caller will call apply again with
+                // the rest of the operation.
+                let len = if op.loc.fwd { len } else { 1 };
 
                 // To implement this we need to:
                 // 1. Find the item directly before the requested position. This is our origin-left.
@@ -467,7 +475,7 @@ impl M2Tracker {
 
                 // This is dirty because the cursor's lifetime is not associated with self.
                 let cursor = cursor.inner;
-                let ins_pos = self.integrate(aa, agent, item, cursor);
+                let ins_pos = self.integrate(aa, item, cursor);
                 // self.range_tree.check();
                 // self.check_index();
 
@@ -511,58 +519,65 @@ impl M2Tracker {
                 // If we've never been deleted locally, we'll need to do that.
                 let ever_deleted = e.ever_deleted;
 
-                // TODO(perf): Reuse cursor. After mutate_single_entry we'll often be at another
-                // entry that we can delete in a run.
-
                 // The transformed position that this delete is at. Only actually needed if we're
                 // modifying
                 let del_start_xf = upstream_cursor_pos(&cursor);
+                let lv_start = op_pair.0;
 
-                let (len2, target) = unsafe {
-                    // It would be tempting - and *nearly* correct to just use local_delete inside the
-                    // range tree. Its hard to bake that logic in here though.
-
-                    // TODO(perf): Reuse cursor. After mutate_single_entry we'll often be at another
-                    // entry that we can delete in a run.
-                    ContentTreeRaw::unsafe_mutate_single_entry_notify(|e| {
-                        // println!("Delete {:?}", e.id);
-                        // This will set the state to deleted, and mark ever_deleted in the entry.
-                        e.delete();
-                        e.id
-                    }, &mut cursor.inner, len, notify_for(&mut self.index))
-                };
-
-                // ContentTree should come to the same length conclusion as us.
-                if !fwd { debug_assert_eq!(len2, len); }
-                let len = len2;
-
-                debug_assert_eq!(len, target.len());
-                debug_assert_eq!(del_start_xf, upstream_cursor_pos(&cursor));
+                // Large deletes often span many entries in the range tree. Rather than returning
+                // after each entry (and seeking from the root again for the rest of the delete),
+                // keep deleting with the same cursor while the next entry is part of the same run.
+                // Entries need the same ever_deleted flag, so they all share the result we return.
+                let mut deleted = 0;
+                loop {
+                    let (len_here, target) = unsafe {
+                        // It would be tempting - and *nearly* correct to just use local_delete inside the
+                        // range tree. Its hard to bake that logic in here though.
+                        ContentTreeRaw::unsafe_mutate_single_entry_notify(|e| {
+                            // println!("Delete {:?}", e.id);
+                            // This will set the state to deleted, and mark ever_deleted in the entry.
+                            e.delete();
+                            e.id
+                        }, &mut cursor.inner, len - deleted, notify_for(&mut self.index))
+                    };
+
+                    // ContentTree should come to the same length conclusion as us.
+                    if !fwd { debug_assert_eq!(len_here, len); }
+                    debug_assert_eq!(len_here, target.len());
+
+                    #[cfg(feature = "ops_to_old")] {
+                        self.dbg_ops.push_rle(OldCRDTOpInternal::Del {
+                            start_v: lv_start + deleted,
+                            target: RangeRev {
+                                span: target,
+                                fwd
+                            }
+                        });
+                    }
 
-                let lv_start = op_pair.0;
+                    // if !is_underwater(target.start) {
+                    //     // Deletes must always dominate the item they're deleting in the time dag.
+                    //     debug_assert!(cg.parents.version_contains_time(&[lv_start], target.start));
+                    // }
 
-                #[cfg(feature = "ops_to_old")] {
-                    self.dbg_ops.push_rle(OldCRDTOpInternal::Del {
-                        start_v: lv_start,
-                        target: RangeRev {
+                    self.index.replace_range_at_offset(lv_start + deleted, MarkerEntry {
+                        len: len_here,
+                        inner: DelTarget(RangeRev {
                             span: target,
                             fwd
-                        }
+                        })
                     });
-                }
 
-                // if !is_underwater(target.start) {
-                //     // Deletes must always dominate the item they're deleting in the time dag.
-                //     debug_assert!(cg.parents.version_contains_time(&[lv_start], target.start));
-                // }
+                    deleted += len_here;
 
-                self.index.replace_range_at_offset(lv_start, MarkerEntry {
-                    len,
-                    inner: DelTarget(RangeRev {
-                        span: target,
-                        fwd
-                    })
-                });
+                    // Reversed deletes always fit in a single entry.
+                    if !fwd || deleted == len || !cursor.inner.roll_to_next_entry() { break; }
+                    let next = cursor.inner.get_raw_entry();
+                    if next.state != INSERTED || next.ever_deleted != ever_deleted { break; }
+                }
+                let len = deleted;
+
+                debug_assert_eq!(del_start_xf, upstream_cursor_pos(&cursor));
 
                 // if cfg!(debug_assertions) {
                 //     self.check_index();
@@ -607,7 +622,7 @@ pub(crate) enum TransformedResult {
     DeleteAlreadyHappened,
 }
 
-type TransformedTriple = (LV, ListOpMetrics, TransformedResult);
+pub(crate) type TransformedTriple = (LV, ListOpMetrics, TransformedResult);
 
 impl TransformedResult {
     fn not_moved(op_pair: KVPair<ListOpMetrics>) -> TransformedTriple {
@@ -620,6 +635,8 @@ impl TransformedResult {
 pub(crate) struct TransformedOpsIter2<'a> {
     subgraph: &'a Graph,
     aa: &'a AgentAssignment,
+    /// Search hint for looking up agent spans in aa. Ops are mostly visited in order.
+    aa_hint: usize,
     op_ctx: &'a ListOperationCtx,
     ops: &'a RleVec<KVPair<ListOpMetrics>>,
     op_iter: Option<BufferedIter<OpMetricsIter<'a>>>,
@@ -637,49 +654,179 @@ pub(crate) struct TransformedOpsIter2<'a> {
     applying: bool,
 
     max_frontier: Frontier,
+
+    /// Counters for the work done so far. peak_tracker_entries is only updated when the tracker
+    /// is cleared - use stats() to read it.
+    stats: MergeStats,
 }
 
 impl<'a> TransformedOpsIter2<'a> {
     pub(crate) fn from_plan(subgraph: &'a Graph, aa: &'a AgentAssignment, op_ctx: &'a ListOperationCtx,
-                      ops: &'a RleVec<KVPair<ListOpMetrics>>,
+                      ops: &'a RleVec<KVPair<ListOpMetrics>>, tie_break: TieBreak,
                       plan: M1Plan, common: Frontier) -> Self {
         Self {
             subgraph,
             aa,
+            aa_hint: 0,
             op_ctx,
             ops,
             op_iter: None,
-            tracker: M2Tracker::new(), // NOTE: This allocates, even if we don't need it.
+            tracker: M2Tracker::new_with_tie_break(tie_break), // NOTE: This allocates, even if we don't need it.
             plan,
             plan_idx: 0,
             ff_current: false,
             applying: false,
             max_frontier: common,
+            stats: MergeStats::default(),
         }
     }
 
+    fn make_plan(subgraph: &Graph, ops: &RleVec<KVPair<ListOpMetrics>>, from_frontier: &[LV],
+                 merge_frontier: &[LV], allow_ff: bool) -> (M1Plan, Frontier) {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("make_m1_plan", allow_ff).entered();
+
+        let (plan, common) = subgraph.make_m1_plan(Some(ops), from_frontier, merge_frontier, allow_ff);
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(actions = plan.0.len(), "made merge plan");
+
+        (plan, common)
+    }
+
     pub(crate) fn new(subgraph: &'a Graph, aa: &'a AgentAssignment, op_ctx: &'a ListOperationCtx,
-                      ops: &'a RleVec<KVPair<ListOpMetrics>>,
+                      ops: &'a RleVec<KVPair<ListOpMetrics>>, tie_break: TieBreak,
                       from_frontier: &[LV], merge_frontier: &[LV]) -> Self {
-        let (plan, common) = subgraph.make_m1_plan(Some(ops), from_frontier, merge_frontier, true);
-        Self::from_plan(subgraph, aa, op_ctx, ops, plan, common)
+        let (plan, common) = Self::make_plan(subgraph, ops, from_frontier, merge_frontier, true);
+        Self::from_plan(subgraph, aa, op_ctx, ops, tie_break, plan, common)
+    }
+
+    /// Variant of [`new`](TransformedOpsIter2::new) which never fast-forwards. This is slower, but
+    /// every operation is applied to the tracker. So after the iterator has been consumed, you can
+    /// look up which items each delete operation deleted using
+    /// [`delete_target`](TransformedOpsIter2::delete_target).
+    pub(crate) fn new_without_ff(subgraph: &'a Graph, aa: &'a AgentAssignment, op_ctx: &'a ListOperationCtx,
+                                 ops: &'a RleVec<KVPair<ListOpMetrics>>, tie_break: TieBreak,
+                                 from_frontier: &[LV], merge_frontier: &[LV]) -> Self {
+        let (plan, common) = Self::make_plan(subgraph, ops, from_frontier, merge_frontier, false);
+        Self::from_plan(subgraph, aa, op_ctx, ops, tie_break, plan, common)
+    }
+
+    /// Get the items (named by the LV of the insert which created them) deleted by the delete
+    /// operation at lv. Returns at most max_len items. The range is reversed if later versions in
+    /// the delete deleted items with lower LVs.
+    ///
+    /// Items which existed before the from_frontier passed to the iterator are "underwater", and
+    /// named by their position in the document at from_frontier (+ UNDERWATER_START) instead.
+    ///
+    /// This is only valid for delete operations which have already been returned by an iterator
+    /// created with [`new_without_ff`](TransformedOpsIter2::new_without_ff).
+    pub(crate) fn delete_target(&self, lv: LV, max_len: usize) -> Option<RangeRev> {
+        self.tracker.delete_target(lv, max_len)
+    }
+
+    /// The items in the tracker, in document order. Items which existed before the from_frontier
+    /// passed to the iterator are skipped.
+    ///
+    /// This only contains every item if the iterator was created with
+    /// [`new_without_ff`](TransformedOpsIter2::new_without_ff) and has been consumed.
+    pub(crate) fn tracker_items(&self) -> impl Iterator<Item=CRDTSpan> + '_ {
+        self.tracker.range_tree.iter().filter(|item| !item.is_underwater())
     }
 
     #[cfg(feature = "ops_to_old")]
     pub(crate) fn get_crdt_items(subgraph: &'a Graph, aa: &'a AgentAssignment, op_ctx: &'a ListOperationCtx,
-                                 ops: &'a RleVec<KVPair<ListOpMetrics>>,
+                                 ops: &'a RleVec<KVPair<ListOpMetrics>>, tie_break: TieBreak,
                                  from_frontier: &[LV], merge_frontier: &[LV]) -> Vec<crate::listmerge::to_old::OldCRDTOpInternal> {
         // Importantly, we're passing allow_ff: false to make sure we get the actual output!
         let (plan, common) = subgraph.make_m1_plan(Some(ops), from_frontier, merge_frontier, false);
-        let mut iter = Self::from_plan(subgraph, aa, op_ctx, ops, plan, common);
+        let mut iter = Self::from_plan(subgraph, aa, op_ctx, ops, tie_break, plan, common);
         while let Some(_) = iter.next() {} // Consume all actions.
         iter.tracker.dbg_ops
     }
 
+    /// Statistics about the work done by the iterator so far. The wall_time isn't filled in.
+    pub(crate) fn stats(&self) -> MergeStats {
+        let mut stats = self.stats;
+        stats.peak_tracker_entries = stats.peak_tracker_entries.max(self.tracker.range_tree.count_entries());
+        stats
+    }
+
+    /// The number of entries in the merge tracker right now. This walks the whole tracker.
+    pub(crate) fn tracker_entries(&self) -> usize {
+        self.tracker.range_tree.count_entries()
+    }
+
+    fn clear_tracker(tracker: &mut M2Tracker, stats: &mut MergeStats) {
+        // Counting the entries is about as expensive as clearing the tracker.
+        stats.peak_tracker_entries = stats.peak_tracker_entries.max(tracker.range_tree.count_entries());
+        tracker.clear();
+    }
+
     pub(crate) fn into_frontier(self) -> Frontier {
         self.max_frontier
     }
 
+    /// The total number of operations the iterator will apply or fast-forward through.
+    pub(crate) fn plan_ops(&self) -> usize {
+        self.plan.0.iter().map(|action| match action {
+            M1PlanAction::Apply(span) | M1PlanAction::FF(span) => span.len(),
+            _ => 0,
+        }).sum()
+    }
+
+    /// Do a bounded amount of the work needed before the iterator can start returning
+    /// operations. This lets callers spread a large merge out over time.
+    ///
+    /// Returns the number of operations applied to the tracker (at most max_ops), or None once
+    /// the iterator is ready to emit output. (At that point, call next() instead.)
+    pub(crate) fn prepare_step(&mut self, max_ops: usize) -> Option<usize> {
+        if self.applying || self.plan_idx >= self.plan.0.len() { return None; }
+
+        let max_ops = max_ops.max(1);
+        let action = &mut self.plan.0[self.plan_idx];
+        let mut applied = 0;
+        match action {
+            M1PlanAction::Apply(span) => {
+                // Only apply up to max_ops, and leave the rest of the span for next time.
+                let here: DTRange = (span.start..span.end.min(span.start + max_ops)).into();
+                span.start = here.end;
+                if span.is_empty() { self.plan_idx += 1; }
+
+                self.max_frontier.advance(self.subgraph, here);
+                self.tracker.apply_range(self.aa, self.op_ctx, self.ops, here, None);
+                applied = here.len();
+                self.stats.transformed_ops += applied;
+            }
+            M1PlanAction::Retreat(span) => {
+                let span = *span;
+                self.plan_idx += 1;
+                self.tracker.retreat_by_range(span);
+                self.stats.retreated_ops += span.len();
+            }
+            M1PlanAction::Advance(span) => {
+                let span = *span;
+                self.plan_idx += 1;
+                self.tracker.advance_by_range(span);
+                self.stats.advanced_ops += span.len();
+            }
+            M1PlanAction::Clear => {
+                self.plan_idx += 1;
+                Self::clear_tracker(&mut self.tracker, &mut self.stats);
+            }
+            M1PlanAction::BeginOutput => {
+                self.plan_idx += 1;
+                self.applying = true;
+                return None;
+            }
+            M1PlanAction::FF(_) => {
+                // The plan only fast-forwards while outputting.
+                unreachable!();
+            }
+        }
+        Some(applied)
+    }
+
     /// Returns if concurrent inserts ever collided at the same location while traversing.
     #[cfg(feature = "merge_conflict_checks")]
     pub(crate) fn concurrent_inserts_collided(&self) -> bool {
@@ -712,9 +859,11 @@ impl<'a> Iterator for TransformedOpsIter2<'a> {
                 match action {
                     M1PlanAction::Retreat(span) => {
                         self.tracker.retreat_by_range(*span);
+                        self.stats.retreated_ops += span.len();
                     }
                     M1PlanAction::Advance(span) => {
                         self.tracker.advance_by_range(*span);
+                        self.stats.advanced_ops += span.len();
                     }
                     M1PlanAction::Apply(span) => {
                         // println!("frontier {:?} + span {:?}", self.max_frontier, *span);
@@ -725,12 +874,16 @@ impl<'a> Iterator for TransformedOpsIter2<'a> {
                         if !self.applying {
                             // Just apply it directly to the tracker.
                             self.tracker.apply_range(self.aa, self.op_ctx, self.ops, *span, None);
+                            self.stats.transformed_ops += span.len();
                         } else {
                             self.op_iter = Some(OpMetricsIter::new(self.ops, self.op_ctx, *span).into());
                             continue 'outer;
                         }
                     }
                     M1PlanAction::FF(span) => {
+                        #[cfg(feature = "tracing")]
+                        tracing::trace!(len = span.len(), "fast forward");
+
                         // println!("frontier {:?} FF span {:?} -> {}", self.max_frontier, *span, span.last());
                         self.max_frontier.replace_with_1(span.last());
                         self.ff_current = true;
@@ -744,7 +897,7 @@ impl<'a> Iterator for TransformedOpsIter2<'a> {
                         }
                     }
                     M1PlanAction::Clear => {
-                        self.tracker.clear();
+                        Self::clear_tracker(&mut self.tracker, &mut self.stats);
                     }
                     M1PlanAction::BeginOutput => {
                         self.applying = true;
@@ -755,6 +908,13 @@ impl<'a> Iterator for TransformedOpsIter2<'a> {
             // No more plan. Stop!
             // dbg!(&self.op_iter, self.plan_idx);
             debug_assert!(self.op_iter.is_none());
+
+            #[cfg(feature = "tracing")]
+            tracing::debug!(applies = self.tracker.stats.applies,
+                advance_mutations = self.tracker.stats.advance_mutations,
+                retreat_mutations = self.tracker.stats.retreat_mutations,
+                "merge finished");
+
             return None;
 
             // Only really advancing the frontier so we can consume into it. The resulting frontier
@@ -767,13 +927,15 @@ impl<'a> Iterator for TransformedOpsIter2<'a> {
         };
 
         if self.ff_current {
+            self.stats.ff_ops += pair.len();
             Some(TransformedResult::not_moved(pair))
         } else {
             // Ok, try to consume as much as we can from pair.
-            let span = self.aa.local_span_to_agent_span(pair.span());
+            let span = self.aa.local_span_to_agent_span_hinted(pair.span(), &mut self.aa_hint);
             let len = span.len().min(pair.len());
 
-            let (consumed_here, xf_result) = self.tracker.apply(self.aa, self.op_ctx, &pair, len, span.agent);
+            let (consumed_here, xf_result) = self.tracker.apply(self.aa, self.op_ctx, &pair, len);
+            self.stats.transformed_ops += consumed_here;
 
             let remainder = pair.trim_ctx(consumed_here, self.op_ctx);
 
@@ -797,7 +959,7 @@ pub fn reverse_str(s: &str) -> SmartString {
 
 impl TextInfo {
     pub(crate) fn get_xf_operations_full<'a>(&'a self, subgraph: &'a Graph, aa: &'a AgentAssignment, from: &[LV], merging: &[LV]) -> TransformedOpsIter2<'a> {
-        TransformedOpsIter2::new(subgraph, aa, &self.ctx, &self.ops, from, merging)
+        TransformedOpsIter2::new(subgraph, aa, &self.ctx, &self.ops, TieBreak::default(), from, merging)
     }
 
     pub(crate) fn with_xf_iter<F: FnOnce(TransformedOpsIter2, Frontier) -> R, R>(&self, cg: &CausalGraph, from: &[LV], merge_frontier: &[LV], f: F) -> R {
@@ -868,7 +1030,7 @@ impl TextInfo {
     }
 
     /// Add everything in merge_frontier into the set..
-    pub fn merge_into(&self, into: &mut JumpRopeBuf, cg: &CausalGraph, from: &[LV], merge_frontier: &[LV]) -> Frontier {
+    pub fn merge_into<B: TextBuffer>(&self, into: &mut B, cg: &CausalGraph, from: &[LV], merge_frontier: &[LV]) -> Frontier {
         // println!("merge from {:?} + {:?}", from, merge_frontier);
         self.with_xf_iter(cg, from, merge_frontier, |iter, final_frontier| {
             // iter.plan.dbg_print();
@@ -907,6 +1069,145 @@ impl TextInfo {
     }
 
 
+    /// Check out the text at frontier, along with the version of the insert which created each
+    /// character. The versions are returned as a run-length encoded list in document order: each
+    /// entry names the versions of the next `entry.len()` characters (in reverse order if the
+    /// entry isn't `fwd`).
+    ///
+    /// This lets other systems (like search indexes) build their own mappings from positions to
+    /// versions without running the merge again.
+    pub fn checkout_with_ids(&self, cg: &CausalGraph, frontier: &[LV]) -> (JumpRopeBuf, Vec<RangeRev>) {
+        let mut content = JumpRopeBuf::new();
+        let mut ids: Pin<Box<ContentTreeRaw<RangeRev, RawPositionMetricsUsize>>> = ContentTreeRaw::new();
+
+        self.with_xf_iter(cg, &[], frontier, |iter, _| {
+            for (lv, origin_op, xf) in iter {
+                let BaseMoved(pos) = xf else { continue; };
+                let len = origin_op.len();
+
+                match origin_op.kind {
+                    ListOpKind::Ins => {
+                        let text = origin_op.get_content(&self.ctx).unwrap();
+                        if origin_op.loc.fwd {
+                            content.insert(pos, text);
+                        } else {
+                            content.insert(pos, &reverse_str(text));
+                        }
+                        ids.insert_at_offset(pos, RangeRev {
+                            span: (lv..lv + len).into(),
+                            fwd: origin_op.loc.fwd,
+                        });
+                    }
+                    ListOpKind::Del => {
+                        content.remove(pos..pos + len);
+                        ids.delete_at_offset(pos, len);
+                    }
+                }
+            }
+        });
+
+        (content, ids.iter().collect())
+    }
+
+    /// Check out a window of the text at frontier, along with the version of the insert which
+    /// created each character in the window. The versions are in the same format as
+    /// [`checkout_with_ids`](TextInfo::checkout_with_ids).
+    ///
+    /// The merge still has to visit every operation to figure out where the window is. But only the
+    /// versions of each character are tracked while doing so, and text is only read from the
+    /// operation log for the characters inside the window. This is much cheaper than a full
+    /// checkout for large documents.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range extends past the end of the document.
+    pub fn checkout_range(&self, cg: &CausalGraph, frontier: &[LV], range: Range<usize>) -> (String, Vec<RangeRev>) {
+        let mut ids: Pin<Box<ContentTreeRaw<RangeRev, RawPositionMetricsUsize>>> = ContentTreeRaw::new();
+
+        self.with_xf_iter(cg, &[], frontier, |iter, _| {
+            for (lv, origin_op, xf) in iter {
+                let BaseMoved(pos) = xf else { continue; };
+                let len = origin_op.len();
+
+                match origin_op.kind {
+                    ListOpKind::Ins => {
+                        ids.insert_at_offset(pos, RangeRev {
+                            span: (lv..lv + len).into(),
+                            fwd: origin_op.loc.fwd,
+                        });
+                    }
+                    ListOpKind::Del => {
+                        ids.delete_at_offset(pos, len);
+                    }
+                }
+            }
+        });
+
+        assert!(range.end <= ids.offset_len(), "Range {:?} extends past the end of the document", range);
+
+        let mut window_ids: Vec<RangeRev> = vec![];
+        let mut content = String::new();
+        if range.is_empty() { return (content, window_ids); }
+
+        let mut cursor = ids.cursor_at_offset_pos(range.start, false);
+        let offset = cursor.offset;
+        let mut entry = cursor.next().unwrap();
+        entry.truncate_keeping_right(offset);
+        let mut remaining = range.len();
+        loop {
+            if entry.len() > remaining { entry.truncate(remaining); }
+            remaining -= entry.len();
+
+            // The inserted content is stored in LV order.
+            let mut text = String::new();
+            let mut span = entry.span;
+            while !span.is_empty() {
+                let pair = self.ops.find_packed_and_split_ctx(span, &self.ctx);
+                debug_assert_eq!(pair.1.kind, ListOpKind::Ins);
+                text.push_str(pair.1.get_content(&self.ctx).unwrap());
+                span.start += pair.len();
+            }
+            if entry.fwd {
+                content.push_str(&text);
+            } else {
+                content.push_str(&reverse_str(&text));
+            }
+
+            window_ids.push_rle(entry);
+            if remaining == 0 { break; }
+            entry = cursor.next().unwrap();
+        }
+
+        (content, window_ids)
+    }
+
+    /// Add everything in merge_frontier into a list of items. Each item is named by the version of
+    /// the insert which created it. This is used for list CRDTs, which store their operations in a
+    /// TextInfo with a placeholder character for each item.
+    pub fn merge_items_into(&self, into: &mut Vec<LV>, cg: &CausalGraph, from: &[LV], merge_frontier: &[LV]) -> Frontier {
+        self.with_xf_iter(cg, from, merge_frontier, |iter, final_frontier| {
+            for (lv, origin_op, xf) in iter {
+                let BaseMoved(pos) = xf else { continue; };
+                let len = origin_op.len();
+
+                match origin_op.kind {
+                    ListOpKind::Ins => {
+                        if origin_op.loc.fwd {
+                            into.splice(pos..pos, lv..lv + len);
+                        } else {
+                            into.splice(pos..pos, (lv..lv + len).rev());
+                        }
+                    }
+                    ListOpKind::Del => {
+                        into.drain(pos..pos + len);
+                    }
+                }
+            }
+
+            final_frontier
+        })
+    }
+
     // /// Add everything in merge_frontier into the set..
     // pub fn merge_into(&self, into: &mut JumpRopeBuf, cg: &CausalGraph, from: &[LV], merge_frontier: &[LV]) -> Frontier {
     //     let (graph, flat) = match FlattenedOps::new_from_subgraph(cg, from, merge_frontier, &self.ops) {
@@ -1177,6 +1478,72 @@ mod test {
         assert_eq!(list.to_string(), "abc");
     }
 
+    /// Type "123" and "xyz" concurrently between the brackets in "[]". Each run is either typed
+    /// forwards, or in reverse (by repeatedly inserting at the same position).
+    fn type_concurrently(tie_break: TieBreak, names: [&str; 2], fwd: [bool; 2]) -> String {
+        let mut oplog = ListOpLog::new_with_tie_break(tie_break);
+        let agents = names.map(|name| oplog.get_or_create_agent_id(name));
+        let base = oplog.add_insert(agents[0], 0, "[]");
+
+        for (i, content) in ["123", "xyz"].iter().enumerate() {
+            let mut v = base;
+            let chars: Vec<_> = content.chars().collect();
+            for j in 0..chars.len() {
+                let (pos, c) = if fwd[i] { (1 + j, chars[j]) } else { (1, chars[chars.len() - 1 - j]) };
+                v = oplog.add_insert_at(agents[i], &[v], pos, c.encode_utf8(&mut [0; 4]));
+            }
+        }
+
+        oplog.checkout_tip().content().to_string()
+    }
+
+    #[test]
+    fn concurrent_runs_never_interleave() {
+        // This is the maximal non-interleaving property from Fugue (FugueMax). In particular, runs
+        // typed in reverse (eg by typing and then pressing the left arrow key) stay together.
+        for tie_break in [TieBreak::AgentName, TieBreak::AgentHash, TieBreak::SeqFirst] {
+            for names in [["a", "b"], ["b", "a"]] {
+                for fwd in [[true, true], [false, false], [true, false], [false, true]] {
+                    let result = type_concurrently(tie_break, names, fwd);
+                    assert!(result == "[123xyz]" || result == "[xyz123]",
+                            "{result} interleaved ({tie_break:?} {names:?} {fwd:?})");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reversed_insert_op() {
+        // A run typed in reverse can also be stored as a single insert operation with fwd = false.
+        let rev_insert = |content: &str| TextOperation {
+            loc: RangeRev { span: (1..4).into(), fwd: false },
+            kind: ListOpKind::Ins,
+            content: Some(content.into()),
+        };
+
+        for tie_break in [TieBreak::AgentName, TieBreak::AgentHash, TieBreak::SeqFirst] {
+            for names in [["a", "b"], ["b", "a"]] {
+                let mut oplog = ListOpLog::new_with_tie_break(tie_break);
+                let agents = names.map(|name| oplog.get_or_create_agent_id(name));
+                let base = oplog.add_insert(agents[0], 0, "[]");
+                oplog.add_operations_at(agents[0], &[base], &[rev_insert("321")]);
+                oplog.add_operations_at(agents[1], &[base], &[rev_insert("zyx")]);
+
+                assert_eq!(oplog.checkout_tip().content().to_string(),
+                           type_concurrently(tie_break, names, [false, false]));
+            }
+        }
+
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        oplog.add_insert(seph, 0, "[]");
+        oplog.add_operations(seph, &[rev_insert("321")]);
+        let mut content = JumpRopeBuf::new();
+        let mut t = M2Tracker::new();
+        t.apply_range(&oplog.cg.agent_assignment, &oplog.operation_ctx, &oplog.operations, (0..5).into(), Some(&mut content));
+        assert_eq!(content, "[123]");
+    }
+
 
     #[test]
     #[ignore]
@@ -1,4 +1,5 @@
-use content_tree::{ContentLength, Cursor, FindContent, Pair, TreeMetrics};
+use content_tree::{ContentLength, FindContent, Pair, TreeMetrics};
+use crate::listmerge::RangeTreeCursor;
 use crate::listmerge::yjsspan::CRDTSpan;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -59,7 +60,7 @@ impl MarkerMetrics {
 
 /// Get the upstream position of a cursor into a MarkerMetrics object. I'm not sure if this is the
 /// best place for this method, but it'll do.
-pub(super) fn upstream_cursor_pos(cursor: &Cursor<CRDTSpan, MarkerMetrics>) -> usize {
+pub(super) fn upstream_cursor_pos(cursor: &RangeTreeCursor) -> usize {
     cursor.count_pos_raw(MarkerMetrics::upstream_len,
                          CRDTSpan::upstream_len,
                          CRDTSpan::upstream_len_at)
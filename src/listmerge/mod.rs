@@ -10,11 +10,13 @@
 //! entries as we go). Or we could figure it out by walking the txns forwards and backwards through
 //! time.
 
-use std::pin::Pin;
-use content_tree::{ContentTreeRaw, RawPositionMetricsUsize};
+use alloc::{boxed::Box, vec::Vec};
+use core::pin::Pin;
+use content_tree::{ContentTreeRaw, Cursor, DEFAULT_IE, DEFAULT_LE, NodeLeaf, RawPositionMetricsUsize, UnsafeCursor};
 use crate::listmerge::markers::MarkerEntry;
 use crate::listmerge::metrics::MarkerMetrics;
 use crate::listmerge::yjsspan::CRDTSpan;
+use crate::list::tie_break::TieBreak;
 
 mod yjsspan;
 pub(crate) mod merge;
@@ -29,14 +31,37 @@ mod dot;
 
 #[cfg(feature = "ops_to_old")]
 pub mod to_old;
-#[cfg(any(test, feature = "gen_test_data"))]
+#[cfg(any(test, feature = "gen_test_data", feature = "testkit"))]
 pub(crate) mod simple_oplog;
 pub(crate) mod plan;
+#[cfg(feature = "parallel")]
+pub(crate) mod parallel;
 
 type DocRangeIndex = MarkerMetrics;
-type CRDTList2 = Pin<Box<ContentTreeRaw<CRDTSpan, DocRangeIndex>>>;
 
-type SpaceIndex = Pin<Box<ContentTreeRaw<MarkerEntry, RawPositionMetricsUsize>>>;
+// Node sizes for the tracker's trees. The range tree is the hot structure during merges: most of
+// the time is spent scanning leaves of CRDTSpans, so it gets wider leaves than the default. The
+// index is mostly looked up by offset, and its entries are small, so the defaults suit it fine.
+//
+// Debug builds use the (small) defaults everywhere so tests exercise node splits and merges.
+#[cfg(debug_assertions)]
+const RANGE_TREE_IE: usize = DEFAULT_IE;
+#[cfg(debug_assertions)]
+const RANGE_TREE_LE: usize = DEFAULT_LE;
+#[cfg(not(debug_assertions))]
+const RANGE_TREE_IE: usize = 16;
+#[cfg(not(debug_assertions))]
+const RANGE_TREE_LE: usize = 64;
+
+const INDEX_IE: usize = DEFAULT_IE;
+const INDEX_LE: usize = DEFAULT_LE;
+
+type CRDTList2 = Pin<Box<ContentTreeRaw<CRDTSpan, DocRangeIndex, RANGE_TREE_IE, RANGE_TREE_LE>>>;
+type RangeTreeLeaf = NodeLeaf<CRDTSpan, DocRangeIndex, RANGE_TREE_IE, RANGE_TREE_LE>;
+type RangeTreeCursor<'a> = Cursor<'a, CRDTSpan, DocRangeIndex, RANGE_TREE_IE, RANGE_TREE_LE>;
+type RangeTreeUnsafeCursor = UnsafeCursor<CRDTSpan, DocRangeIndex, RANGE_TREE_IE, RANGE_TREE_LE>;
+
+type SpaceIndex = Pin<Box<ContentTreeRaw<MarkerEntry, RawPositionMetricsUsize, INDEX_IE, INDEX_LE>>>;
 
 #[derive(Debug)]
 struct M2Tracker {
@@ -48,9 +73,28 @@ struct M2Tracker {
     /// - For deletes, this names the time at which the delete happened.
     index: SpaceIndex,
 
+    /// How concurrent inserts at the same location are ordered.
+    tie_break: TieBreak,
+
     #[cfg(feature = "merge_conflict_checks")]
     concurrent_inserts_collide: bool,
 
     #[cfg(feature = "ops_to_old")]
     dbg_ops: Vec<to_old::OldCRDTOpInternal>,
+
+    #[cfg(feature = "tracing")]
+    stats: TrackerStats,
+}
+
+/// Counters for the work done by the tracker. These are reported in a tracing event when a merge
+/// finishes.
+#[cfg(feature = "tracing")]
+#[derive(Debug, Default, Clone, Copy)]
+struct TrackerStats {
+    /// Calls to M2Tracker::apply.
+    applies: usize,
+    /// Range tree entries modified while advancing.
+    advance_mutations: usize,
+    /// Range tree entries modified while retreating.
+    retreat_mutations: usize,
 }
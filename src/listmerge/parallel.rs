@@ -0,0 +1,121 @@
+//! Parallel execution of M1 merge plans.
+//!
+//! Whenever the plan passes through a point on the critical path (where every operation merged so
+//! far is an ancestor of everything which comes next), it emits a `Clear` action and throws away
+//! the tracker state. Nothing after a clear depends on the tracker state from before it, so the
+//! segments of the plan between clears can be run on separate trackers at the same time. The
+//! transformed operations from each segment are then concatenated in plan order.
+//!
+//! This helps a lot with long, mostly-linear histories with occasional concurrent edits (like
+//! git-makefile). It doesn't help at all when the whole history is one big tangle.
+
+use rayon::prelude::*;
+use crate::{Frontier, LV};
+use crate::causalgraph::agent_assignment::AgentAssignment;
+use crate::causalgraph::graph::Graph;
+use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
+use crate::list::tie_break::TieBreak;
+use crate::listmerge::merge::{TransformedOpsIter2, TransformedTriple};
+use crate::listmerge::plan::{M1Plan, M1PlanAction};
+use crate::rle::{KVPair, RleVec};
+
+impl M1Plan {
+    /// Split the plan into independent segments at each `Clear` action. Every segment after the
+    /// first starts in output mode.
+    pub(crate) fn split_independent(self) -> Vec<M1Plan> {
+        let mut result = vec![];
+        let mut current = vec![];
+
+        for action in self.0 {
+            if action == M1PlanAction::Clear {
+                result.push(M1Plan(current));
+                current = vec![M1PlanAction::BeginOutput];
+            } else {
+                current.push(action);
+            }
+        }
+        result.push(M1Plan(current));
+        result
+    }
+}
+
+/// Transform all the operations in merge_frontier relative to from_frontier, using a thread pool.
+/// This returns the same operations (in the same order) as iterating through
+/// [`TransformedOpsIter2`], along with the resulting frontier.
+pub(crate) fn xf_operations_parallel(subgraph: &Graph, aa: &AgentAssignment, op_ctx: &ListOperationCtx,
+                                     ops: &RleVec<KVPair<ListOpMetrics>>, tie_break: TieBreak,
+                                     from_frontier: &[LV], merge_frontier: &[LV]) -> (Vec<TransformedTriple>, Frontier) {
+    let (plan, common) = subgraph.make_m1_plan(Some(ops), from_frontier, merge_frontier, true);
+    let segments = plan.split_independent();
+    let num_segments = segments.len();
+
+    let results: Vec<(Vec<TransformedTriple>, Frontier)> = segments.into_par_iter()
+        .enumerate()
+        .map(|(i, segment)| {
+            // Segments after the first always start by fast-forwarding, which replaces the
+            // frontier. So only the first segment needs to know the common version.
+            let start = if i == 0 { common.clone() } else { Frontier::root() };
+            let mut iter = TransformedOpsIter2::from_plan(subgraph, aa, op_ctx, ops, tie_break, segment, start);
+            let xf_ops = (&mut iter).collect();
+            (xf_ops, iter.into_frontier())
+        })
+        .collect();
+
+    let mut frontier = common;
+    let mut xf_ops = Vec::with_capacity(results.iter().map(|(ops, _)| ops.len()).sum());
+    for (i, (ops, f)) in results.into_iter().enumerate() {
+        xf_ops.extend(ops);
+        if i == num_segments - 1 { frontier = f; }
+    }
+
+    (xf_ops, frontier)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::io::Read;
+    use crate::list::{ListBranch, ListOpLog};
+
+    fn check_parallel_merge(oplog: &ListOpLog) {
+        let mut expect = ListBranch::new();
+        expect.merge(oplog, oplog.cg.version.as_ref());
+
+        let mut actual = ListBranch::new();
+        actual.merge_parallel(oplog, oplog.cg.version.as_ref());
+        assert_eq!(expect, actual);
+    }
+
+    #[test]
+    fn parallel_merge_concurrent_inserts() {
+        let mut oplog = ListOpLog::new();
+        let seph = oplog.get_or_create_agent_id("seph");
+        let mike = oplog.get_or_create_agent_id("mike");
+        let a = oplog.add_insert_at(seph, &[], 0, "aaa");
+        let b = oplog.add_insert_at(mike, &[], 0, "bbb");
+        let c = oplog.add_insert_at(seph, &[a, b], 3, "ccc");
+        let d = oplog.add_delete_at(mike, &[c], 1..4);
+        oplog.add_insert_at(seph, &[c], 0, "x");
+        oplog.add_insert_at(mike, &[d], 2, "y");
+
+        check_parallel_merge(&oplog);
+
+        // And merging from a non-root version.
+        let mut expect = oplog.checkout(&[a]);
+        expect.merge(&oplog, oplog.cg.version.as_ref());
+        let mut actual = oplog.checkout(&[a]);
+        actual.merge_parallel(&oplog, oplog.cg.version.as_ref());
+        assert_eq!(expect, actual);
+    }
+
+    #[test]
+    #[ignore]
+    fn parallel_merge_benchmark_data() {
+        for name in ["benchmark_data/git-makefile.dt", "benchmark_data/node_nodecc.dt"] {
+            let mut bytes = vec![];
+            File::open(name).unwrap().read_to_end(&mut bytes).unwrap();
+            let oplog = ListOpLog::load_from(&bytes).unwrap();
+            check_parallel_merge(&oplog);
+        }
+    }
+}
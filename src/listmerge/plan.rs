@@ -1,8 +1,9 @@
 //! This is a POC for what an action plan would look like using the current list merging algorithm
 //! instead of the new one.
 
-use std::cmp::Reverse;
-use std::collections::BinaryHeap;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use alloc::collections::BinaryHeap;
 use bumpalo::collections::CollectIn;
 use smallvec::{SmallVec, smallvec};
 use rle::{AppendRle, HasLength, HasRleKey, MergableSpan};
@@ -433,7 +434,9 @@ impl ConflictSubgraph<M1EntryState> {
                 for i in e.state.next..c.len() {
                     let next_idx = c[i];
                     let e2 = &self.entries[next_idx];
-                    debug_assert_eq!(e2.state.visited, false);
+                    // Children postponed in b_children are visited from there, but they stay in
+                    // this list.
+                    if e2.state.visited { continue; }
 
                     // This is a merge, but we haven't covered all the merge's parents.
                     if e2.state.parents_satisfied != e2.parents.len() { continue; }
@@ -685,6 +688,25 @@ mod test {
         plan.dbg_check(base_version.as_ref(), &[], &[3], &graph);
     }
 
+    #[test]
+    fn postponed_child_visited_from_b_children() {
+        let graph = Graph::from_simple_items(&[
+            GraphEntrySimple { span: (0..13).into(), parents: Frontier::root() },
+            GraphEntrySimple { span: (13..22).into(), parents: Frontier::root() },
+            GraphEntrySimple { span: (22..26).into(), parents: Frontier::root() },
+            GraphEntrySimple { span: (26..31).into(), parents: Frontier::root() },
+            GraphEntrySimple { span: 31.into(), parents: Frontier::from_sorted(&[25, 28]) },
+            GraphEntrySimple { span: (32..38).into(), parents: Frontier::from_sorted(&[12, 19]) },
+            GraphEntrySimple { span: (38..42).into(), parents: Frontier::from_sorted(&[21, 25]) },
+        ]);
+
+        let (a, b) = (&[37], &[30, 31, 37, 41]);
+        let g = graph.make_conflict_graph_between(a, b);
+        g.dbg_check();
+        let (plan, base_version) = g.make_m1_plan(None, true);
+        plan.dbg_check(base_version.as_ref(), a, b, &graph);
+    }
+
     #[test]
     fn fuzz_m1_plans() {
         with_random_cgs(3232, (100, 10), |(_i, _k), cg, frontiers| {
@@ -1,4 +1,4 @@
-use std::ops::Range;
+use core::ops::Range;
 use jumprope::JumpRopeBuf;
 use smartstring::SmartString;
 use rle::HasLength;
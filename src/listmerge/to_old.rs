@@ -1,3 +1,4 @@
+use alloc::vec::Vec;
 use rle::{HasLength, MergableSpan, merge_items, MergeableIterator, SplitableSpan, SplitableSpanHelpers};
 use crate::{DTRange, SmartString, LV, Frontier};
 use crate::causalgraph::agent_assignment::remote_ids::RemoteVersionSpan;
@@ -172,7 +173,7 @@ impl ListOpLog {
     #[cfg(feature = "ops_to_old")]
     pub fn dbg_items(&self) -> Vec<OldCRDTOp> {
         let items = TransformedOpsIter2::get_crdt_items(&self.cg.graph, &self.cg.agent_assignment,
-                                            &self.operation_ctx, &self.operations,
+                                            &self.operation_ctx, &self.operations, self.tie_break(),
                                             &[], self.cg.version.as_ref());
 
         // dbg!(&items);
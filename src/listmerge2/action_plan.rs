@@ -1,6 +1,7 @@
-use std::cmp::Reverse;
-use std::collections::BinaryHeap;
-use std::mem::take;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use alloc::collections::BinaryHeap;
+use core::mem::take;
 use bumpalo::Bump;
 use smallvec::{SmallVec, smallvec};
 use rle::{HasLength, MergableSpan};
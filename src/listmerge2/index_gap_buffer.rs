@@ -1,5 +1,6 @@
-use std::cmp::Ordering;
-use std::ops::Range;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::ops::Range;
 use rle::{HasLength, MergableSpan, merge_items, MergeIter, SplitableSpan};
 use crate::frontier::{debug_assert_sorted, is_sorted_slice};
 use crate::list::op_iter::OpMetricsIter;
@@ -961,7 +962,7 @@ fn run_plan(plan: &MergePlan, op_metrics: &RleVec<KVPair<ListOpMetrics>>, ctx: &
                 assert!(buffer.index_info[*src].active);
                 buffer.index_info[*dest] = buffer.index_info[*src];
 
-                // TODO: Benchmark this with std::ptr::copy_nonoverlapping.
+                // TODO: Benchmark this with core::ptr::copy_nonoverlapping.
                 let src_start = buffer.start_state_idx(*src);
                 let dest_start = buffer.start_state_idx(*dest);
                 buffer.states.copy_within(
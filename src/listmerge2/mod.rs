@@ -2,12 +2,13 @@ mod action_plan;
 mod test_conversion;
 
 // #[cfg(feature = "dot_export")]
+#[cfg(feature = "std")]
 mod dot;
 mod index_gap_buffer;
 mod yjsspan;
 
-use std::cmp::Ordering;
-use std::collections::BinaryHeap;
+use core::cmp::Ordering;
+use alloc::collections::BinaryHeap;
 use smallvec::{SmallVec, smallvec};
 use rle::SplitableSpan;
 use crate::{DTRange, Frontier, LV};
@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use alloc::vec::Vec;
+use alloc::collections::BTreeMap;
 use smallvec::{SmallVec, smallvec};
 use rle::{MergableSpan, RleRun};
 use crate::{DTRange, Frontier, LV};
@@ -33,7 +34,7 @@ impl Graph {
         let mut result = vec![];
 
         // Map of (base version, result index) tuples
-        let mut version_map = HashMap::<Vec<usize>, usize>::new();
+        let mut version_map = BTreeMap::<Vec<usize>, usize>::new();
         version_map.insert(vec![], usize::MAX); // ROOT entry.
 
         for e in self.entries.iter() {
@@ -149,7 +150,7 @@ impl Graph {
         let mut childless_entries = vec![];
 
         // Map of (last version, result index) tuples
-        let mut version_map = HashMap::<LV, usize>::new();
+        let mut version_map = BTreeMap::<LV, usize>::new();
 
         let root_idx = if self.root_child_indexes.len() > 1 {
             result.push(ConflictGraphEntry {
@@ -0,0 +1,23 @@
+//! Without std there's nowhere to print debugging output. These stand-ins for the std printing
+//! macros discard it, so verbose debugging code doesn't need to be feature gated everywhere it's
+//! used.
+
+macro_rules! println {
+    () => {};
+    ($($arg:tt)*) => {{ let _ = format_args!($($arg)*); }};
+}
+
+macro_rules! print {
+    ($($arg:tt)*) => {{ let _ = format_args!($($arg)*); }};
+}
+
+macro_rules! eprintln {
+    () => {};
+    ($($arg:tt)*) => {{ let _ = format_args!($($arg)*); }};
+}
+
+macro_rules! dbg {
+    () => {};
+    ($val:expr $(,)?) => {{ let val = $val; val }};
+    ($($val:expr),+ $(,)?) => { ($($val),+,) };
+}
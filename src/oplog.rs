@@ -1,6 +1,8 @@
-use std::collections::{BTreeMap, BTreeSet};
+use alloc::{boxed::Box, string::{String, ToString}, vec::Vec};
+use alloc::collections::{BTreeMap, BTreeSet};
 use smallvec::smallvec;
-use std::cmp::Ordering;
+use core::cmp::Ordering;
+use core::ops::Range;
 use jumprope::JumpRopeBuf;
 use smartstring::alias::String as SmartString;
 
@@ -18,6 +20,8 @@ use crate::branch::btree_range_for_crdt;
 use crate::frontier::{is_sorted_iter_uniq, is_sorted_slice};
 use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
 use crate::list::operation::TextOperation;
+use crate::list::value_list::placeholders;
+use crate::rev_range::RangeRev;
 use crate::rle::{KVPair, RleSpanHelpers};
 
 #[cfg(feature = "serde")]
@@ -89,11 +93,21 @@ impl OpLog {
         }
         assert_eq!(self.map_index.len(), expected_idx_count);
 
-        // And now text operations
+        // List elements can create CRDTs too.
+        for (lv, value) in self.list_values.iter() {
+            assert!(*lv < cg_len);
+            if let CreateValue::NewCRDT(crdt_type) = value {
+                item_type.insert(*lv, *crdt_type);
+            }
+        }
+
+        // And now text (and list) operations
         let mut expected_idx_count = 0;
         for (crdt, info) in self.texts.iter() {
             assert_ne!(*crdt, ROOT_CRDT_ID);
-            assert_eq!(*item_type.get(crdt).unwrap(), CRDTKind::Text);
+            let kind = *item_type.get(crdt).unwrap();
+            assert!(kind == CRDTKind::Text || kind == CRDTKind::List);
+            assert_eq!(self.lists.contains(crdt), kind == CRDTKind::List);
 
             // Check the operations are sorted
             assert!(is_sorted_iter_uniq(info.ops.iter().map(|KVPair(v, _)| *v)));
@@ -204,6 +218,10 @@ impl OpLog {
             CRDTKind::Text => {
                 self.texts.entry(v).or_default();
             }
+            CRDTKind::List => {
+                self.texts.entry(v).or_default();
+                self.lists.insert(v);
+            }
         }
     }
 
@@ -357,6 +375,42 @@ impl OpLog {
         }
     }
 
+    /// Insert values into a list CRDT at pos. Each value gets its own version. Values which create
+    /// a new CRDT use that version as the new CRDT's ID.
+    pub fn local_list_insert(&mut self, agent: AgentId, crdt: LVKey, pos: usize, values: &[CreateValue]) -> DTRange {
+        assert!(self.lists.contains(&crdt), "CRDT is not a list");
+        let v_range = self.local_text_op(agent, crdt, TextOperation::new_insert(pos, &placeholders(values.len())));
+        for (v, value) in v_range.iter().zip(values) {
+            self.add_list_value(v, value.clone());
+        }
+        v_range
+    }
+
+    /// Delete the elements in range from a list CRDT.
+    pub fn local_list_delete(&mut self, agent: AgentId, crdt: LVKey, range: Range<usize>) -> DTRange {
+        assert!(self.lists.contains(&crdt), "CRDT is not a list");
+        self.local_text_op(agent, crdt, TextOperation::new_delete(range))
+    }
+
+    // Like remote_map_set, this requires that the lv has already been added to the causal graph.
+    fn add_list_value(&mut self, v: LV, value: CreateValue) {
+        if let CreateValue::NewCRDT(kind) = value {
+            self.create_child_crdt(v, kind);
+        }
+        self.list_values.insert(v, value);
+    }
+
+    /// Get the elements of a list CRDT at the current version.
+    pub fn checkout_list(&self, crdt: LVKey) -> Vec<RegisterValue> {
+        let info = self.texts.get(&crdt).unwrap();
+
+        let mut items = vec![];
+        info.merge_items_into(&mut items, &self.cg, &[], self.cg.version.as_ref());
+        items.into_iter()
+            .map(|v| create_to_snapshot(v, &self.list_values[&v]))
+            .collect()
+    }
+
     // Its quite annoying, but RegisterInfo objects store the supremum as an array of indexes. This
     // returns the active index and (if necessary) the set of indexes of conflicting values.
     pub(crate) fn tie_break_mv<'a>(&self, reg: &'a RegisterInfo) -> (usize, Option<impl Iterator<Item = usize> + 'a>) {
@@ -379,7 +433,7 @@ impl OpLog {
         }
     }
 
-    fn resolve_mv(&self, reg: &RegisterInfo) -> RegisterValue {
+    pub(crate) fn resolve_mv(&self, reg: &RegisterInfo) -> RegisterValue {
         let (active_idx, _) = self.tie_break_mv(reg);
 
         let (v, value) = &reg.ops[active_idx];
@@ -394,6 +448,26 @@ impl OpLog {
         result
     }
 
+    /// Check out a text CRDT, along with the version of the insert which created each character.
+    /// The versions are returned as a run-length encoded list in document order: each entry names
+    /// the versions of the next `entry.len()` characters (in reverse order if the entry isn't
+    /// `fwd`).
+    pub fn checkout_text_with_ids(&self, crdt: LVKey) -> (JumpRopeBuf, Vec<RangeRev>) {
+        let info = self.texts.get(&crdt).unwrap();
+        info.checkout_with_ids(&self.cg, self.cg.version.as_ref())
+    }
+
+    /// Check out a window of a text CRDT, without reading the rest of the document's content. See
+    /// [`checkout_text_with_ids`](OpLog::checkout_text_with_ids) for the format of the versions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range extends past the end of the document.
+    pub fn checkout_text_range(&self, crdt: LVKey, range: Range<usize>) -> (String, Vec<RangeRev>) {
+        let info = self.texts.get(&crdt).unwrap();
+        info.checkout_range(&self.cg, self.cg.version.as_ref(), range)
+    }
+
     pub fn checkout_map(&self, crdt: LVKey) -> BTreeMap<SmartString, Box<DTValue>> {
         let empty_str: SmartString = "".into();
         // dbg!((crdt, empty_str.clone())..(crdt, empty_str));
@@ -405,23 +479,28 @@ impl OpLog {
         };
 
         iter.map(|((_, key), info)| {
-            let inner = match self.resolve_mv(info) {
-                RegisterValue::Primitive(p) => DTValue::Primitive(p),
-                RegisterValue::OwnedCRDT(kind, child_crdt) => {
-                    match kind {
-                        CRDTKind::Map => DTValue::Map(self.checkout_map(child_crdt)),
-                        CRDTKind::Text => DTValue::Text(self.checkout_text(child_crdt).to_string()),
-                        _ => unimplemented!(),
-                        // CRDTKind::Register => {}
-                        // CRDTKind::Collection => {}
-                        // CRDTKind::Text => {}
-                    }
-                }
-            };
-            (key.clone(), Box::new(inner))
+            (key.clone(), Box::new(self.checkout_value(self.resolve_mv(info))))
         }).collect()
     }
 
+    pub(crate) fn checkout_value(&self, value: RegisterValue) -> DTValue {
+        match value {
+            RegisterValue::Primitive(p) => DTValue::Primitive(p),
+            RegisterValue::OwnedCRDT(kind, child_crdt) => {
+                match kind {
+                    CRDTKind::Map => DTValue::Map(self.checkout_map(child_crdt)),
+                    CRDTKind::Text => DTValue::Text(self.checkout_text(child_crdt).to_string()),
+                    CRDTKind::List => DTValue::List(self.checkout_list(child_crdt).into_iter()
+                        .map(|v| Box::new(self.checkout_value(v)))
+                        .collect()),
+                    _ => unimplemented!(),
+                    // CRDTKind::Register => {}
+                    // CRDTKind::Collection => {}
+                }
+            }
+        }
+    }
+
     pub fn checkout(&self) -> BTreeMap<SmartString, Box<DTValue>> {
         self.checkout_map(ROOT_CRDT_ID)
     }
@@ -557,11 +636,21 @@ impl OpLog {
             }
         }
 
+        // Serialize the values of new list elements
+        let mut list_values = Vec::new();
+        for r in diff_rev.iter() {
+            for (lv, value) in self.list_values.range(r.start..r.end) {
+                let rv = self.cg.agent_assignment.local_to_remote_version(*lv);
+                list_values.push((rv, value.clone()));
+            }
+        }
+
         SerializedOps {
             cg_changes,
             map_ops,
             text_ops,
             text_context,
+            list_values,
         }
     }
 
@@ -593,6 +682,15 @@ impl OpLog {
             }
         }
 
+        // This needs to happen before the text operations, because list elements can create texts
+        // (and lists) which are edited in the same batch.
+        for (rv, val) in changes.list_values {
+            let lv = self.cg.agent_assignment.remote_to_local_version(rv);
+            if new_range.contains(lv) {
+                self.add_list_value(lv, val);
+            }
+        }
+
         for (crdt_r_name, rv, mut op_metrics) in changes.text_ops {
             let lv = self.cg.agent_assignment.remote_to_local_version(rv);
             let mut v_range: DTRange = (lv..lv + op_metrics.len()).into();
@@ -624,10 +722,11 @@ impl OpLog {
 mod tests {
     #[cfg(feature = "serde")]
     use serde::{Deserialize, Serialize};
-    use crate::{CRDTKind, CreateValue, OpLog, Primitive, ROOT_CRDT_ID, SerializedOps};
+    use crate::{CRDTKind, CreateValue, LV, OpLog, Primitive, ROOT_CRDT_ID, SerializedOps};
     use crate::causalgraph::agent_assignment::remote_ids::RemoteVersion;
     use crate::list::op_metrics::{ListOperationCtx, ListOpMetrics};
     use crate::list::operation::TextOperation;
+    use crate::rev_range::RangeRev;
 
     #[test]
     fn smoke() {
@@ -658,6 +757,10 @@ mod tests {
         assert_eq!(oplog.checkout_text(text).to_string(), "hai!");
         oplog.dbg_check(true);
 
+        let (content, ids) = oplog.checkout_text_with_ids(text);
+        assert_eq!(content.to_string(), "hai!");
+        assert_eq!(ids, vec![RangeRev::from(4..8)]);
+
         // dbg!(oplog.checkout());
 
         // dbg!(oplog.changes_since(&[]));
@@ -675,6 +778,46 @@ mod tests {
         assert_eq!(oplog.checkout(), oplog_2.checkout());
     }
 
+    #[test]
+    fn text_range() {
+        let mut oplog = OpLog::new();
+        let seph = oplog.cg.get_or_create_agent_id("seph");
+        let text = oplog.local_map_set(seph, ROOT_CRDT_ID, "content", CreateValue::NewCRDT(CRDTKind::Text));
+        oplog.local_text_op(seph, text, TextOperation::new_insert(0, "hello world"));
+
+        // A concurrent edit from another peer.
+        let mut oplog2 = oplog.clone();
+        let mike = oplog2.cg.get_or_create_agent_id("mike");
+        oplog2.local_text_op(mike, text, TextOperation::new_insert(11, "!!"));
+
+        oplog.local_text_op(seph, text, TextOperation::new_delete(0..1));
+        // Typing backwards.
+        for c in ["c", "b", "a"] {
+            oplog.local_text_op(seph, text, TextOperation::new_insert(5, c));
+        }
+        oplog.merge_ops(oplog2.ops_since(&[])).unwrap();
+
+        let (content, ids) = oplog.checkout_text_with_ids(text);
+        let content = content.to_string();
+        // Expand the run-length encoded versions to one version per character.
+        fn expand(ids: &[RangeRev]) -> Vec<LV> {
+            ids.iter().flat_map(|r| {
+                let span = r.span.start..r.span.end;
+                if r.fwd { span.collect::<Vec<_>>() } else { span.rev().collect() }
+            }).collect()
+        }
+        let all_ids = expand(&ids);
+
+        let len = content.chars().count();
+        for start in 0..=len {
+            for end in start..=len {
+                let (window, window_ids) = oplog.checkout_text_range(text, start..end);
+                assert_eq!(window, content.chars().skip(start).take(end - start).collect::<String>());
+                assert_eq!(expand(&window_ids), all_ids[start..end]);
+            }
+        }
+    }
+
     #[test]
     fn concurrent_changes() {
         let mut oplog1 = OpLog::new();
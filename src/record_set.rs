@@ -0,0 +1,237 @@
+//! A table of records with typed columns.
+//!
+//! A [`RecordSet`] is an ordered list of rows. Each row is a record with a fixed set of columns,
+//! named by the set's schema. Each column has one of two types:
+//!
+//! - [`ColumnType::Lww`] columns hold a [`Primitive`] value. If a cell is set concurrently on two
+//!   peers, one value wins, and the same value wins on every peer.
+//! - [`ColumnType::Text`] columns hold a text CRDT, so concurrent edits to a cell are merged.
+//!
+//! Rows are kept in a list CRDT, so concurrently inserted rows are all kept, in a consistent order.
+//! Each row is identified by a [`RowId`]: the version of the operation which inserted it.
+//!
+//! Internally this is built from the map, list and text CRDTs in [`OpLog`], so the whole table
+//! shares one causal graph. Changes are exchanged with [`RecordSet::ops_since`] and
+//! [`RecordSet::merge_ops`], which only send the cells which changed.
+//!
+//! # Creating record sets
+//!
+//! Every record set starts with the same operation, made by the `SCHEMA` agent, which creates the
+//! list of rows. Because this operation is identical on every peer, record sets which were created
+//! independently can be merged. But all peers must use the same schema.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use smartstring::alias::String as SmartString;
+use crate::{AgentId, CRDTKind, CreateValue, DTError, DTRange, LV, LVKey, OpLog, ParseError, Primitive, RegisterValue, ROOT_CRDT_ID, SerializedOps};
+use crate::json::TextRef;
+
+/// The name of the agent which creates the list of rows in every record set.
+const SCHEMA_AGENT: &str = "SCHEMA";
+
+/// The type of a column in a [`RecordSet`]. See the [module documentation](self) for details.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ColumnType {
+    Lww,
+    Text,
+}
+
+/// Rows are named by the local version of the operation which inserted them. Use
+/// [`AgentAssignment::local_to_remote_version`](crate::causalgraph::agent_assignment::AgentAssignment::local_to_remote_version)
+/// to get an ID which can be shared with other peers.
+pub type RowId = LV;
+
+/// A table of records. See the [module documentation](self) for details.
+#[derive(Debug, Clone)]
+pub struct RecordSet {
+    oplog: OpLog,
+    columns: Vec<(SmartString, ColumnType)>,
+
+    /// The list CRDT containing the rows.
+    rows_crdt: LVKey,
+
+    /// The rows at the current version, in order. Each row is a map CRDT.
+    rows: Vec<RowId>,
+}
+
+impl RecordSet {
+    /// Create a new, empty record set with the named columns.
+    pub fn new(columns: &[(&str, ColumnType)]) -> Self {
+        let mut oplog = OpLog::new();
+        let schema = oplog.cg.get_or_create_agent_id(SCHEMA_AGENT);
+        let rows_crdt = oplog.local_map_set(schema, ROOT_CRDT_ID, "rows", CreateValue::NewCRDT(CRDTKind::List));
+
+        Self {
+            oplog,
+            columns: columns.iter().map(|(name, ty)| ((*name).into(), *ty)).collect(),
+            rows_crdt,
+            rows: vec![],
+        }
+    }
+
+    /// The underlying oplog.
+    pub fn oplog(&self) -> &OpLog { &self.oplog }
+
+    pub fn get_or_create_agent_id(&mut self, name: &str) -> AgentId {
+        self.oplog.cg.get_or_create_agent_id(name)
+    }
+
+    pub fn local_frontier_ref(&self) -> &[LV] { self.oplog.cg.version.as_ref() }
+
+    /// The number of rows.
+    pub fn len(&self) -> usize { self.rows.len() }
+
+    pub fn is_empty(&self) -> bool { self.rows.is_empty() }
+
+    /// The IDs of all the rows, in order.
+    pub fn rows(&self) -> &[RowId] { &self.rows }
+
+    fn column_type(&self, column: &str) -> Result<ColumnType, DTError> {
+        self.columns.iter()
+            .find(|(name, _)| name == column)
+            .map(|(_, ty)| *ty)
+            .ok_or(DTError::InvalidColumn)
+    }
+
+    fn check_row(&self, row: RowId) -> Result<(), DTError> {
+        if self.rows.contains(&row) { Ok(()) } else { Err(DTError::UnknownRow) }
+    }
+
+    /// Insert a new row at pos. Text cells start empty, and LWW cells start unset.
+    ///
+    /// # Panics
+    ///
+    /// Panics if pos is greater than the number of rows.
+    pub fn insert_row(&mut self, agent: AgentId, pos: usize) -> RowId {
+        assert!(pos <= self.rows.len(), "Row position is past the end of the record set");
+        let row = self.oplog.local_list_insert(agent, self.rows_crdt, pos, &[CreateValue::NewCRDT(CRDTKind::Map)]).start;
+        for (name, ty) in self.columns.iter() {
+            if *ty == ColumnType::Text {
+                self.oplog.local_map_set(agent, row, name, CreateValue::NewCRDT(CRDTKind::Text));
+            }
+        }
+        self.rows.insert(pos, row);
+        row
+    }
+
+    /// Delete a row. Concurrent changes to the row's cells are discarded.
+    pub fn delete_row(&mut self, agent: AgentId, row: RowId) -> Result<(), DTError> {
+        let pos = self.rows.iter().position(|r| *r == row).ok_or(DTError::UnknownRow)?;
+        self.oplog.local_list_delete(agent, self.rows_crdt, pos..pos + 1);
+        self.rows.remove(pos);
+        Ok(())
+    }
+
+    /// Set the value of an LWW cell.
+    pub fn set(&mut self, agent: AgentId, row: RowId, column: &str, value: Primitive) -> Result<(), DTError> {
+        self.check_row(row)?;
+        if self.column_type(column)? != ColumnType::Lww { return Err(DTError::InvalidColumn); }
+        self.oplog.local_map_set(agent, row, column, CreateValue::Primitive(value));
+        Ok(())
+    }
+
+    /// Get the value of an LWW cell. Returns None if the cell hasn't been set.
+    pub fn get(&self, row: RowId, column: &str) -> Result<Option<Primitive>, DTError> {
+        self.check_row(row)?;
+        if self.column_type(column)? != ColumnType::Lww { return Err(DTError::InvalidColumn); }
+        Ok(self.oplog.checkout_map_key_nc(row, column).map(|value| match value {
+            RegisterValue::Primitive(p) => p,
+            // Nothing in a record set stores a CRDT in an LWW cell.
+            RegisterValue::OwnedCRDT(..) => Primitive::Nil,
+        }))
+    }
+
+    fn text_crdt(&self, row: RowId, column: &str) -> Result<LVKey, DTError> {
+        self.check_row(row)?;
+        if self.column_type(column)? != ColumnType::Text { return Err(DTError::InvalidColumn); }
+        match self.oplog.checkout_map_key_nc(row, column) {
+            Some(RegisterValue::OwnedCRDT(CRDTKind::Text, crdt)) => Ok(crdt),
+            _ => Err(DTError::InvalidColumn),
+        }
+    }
+
+    /// Get the content of a text cell.
+    pub fn text(&self, row: RowId, column: &str) -> Result<String, DTError> {
+        let crdt = self.text_crdt(row, column)?;
+        Ok(self.oplog.checkout_text(crdt).to_string())
+    }
+
+    /// Get a text cell, for editing.
+    pub fn text_at(&mut self, row: RowId, column: &str) -> Result<TextRef<'_>, DTError> {
+        let crdt = self.text_crdt(row, column)?;
+        Ok(TextRef::new(&mut self.oplog, crdt))
+    }
+
+    /// Get all the changes since the named version, to send to another peer.
+    pub fn ops_since(&self, since_frontier: &[LV]) -> SerializedOps<'_> {
+        self.oplog.ops_since(since_frontier)
+    }
+
+    /// Add changes from another peer. Returns the range of new local versions.
+    pub fn merge_ops(&mut self, changes: SerializedOps) -> Result<DTRange, ParseError> {
+        let range = self.oplog.merge_ops(changes)?;
+        if !range.is_empty() {
+            self.rows = self.oplog.checkout_list(self.rows_crdt).into_iter()
+                .filter_map(|value| match value {
+                    RegisterValue::OwnedCRDT(CRDTKind::Map, row) => Some(row),
+                    _ => None,
+                })
+                .collect();
+        }
+        Ok(range)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{DTError, Primitive};
+    use crate::record_set::{ColumnType, RecordSet};
+
+    const COLUMNS: &[(&str, ColumnType)] = &[("done", ColumnType::Lww), ("title", ColumnType::Text)];
+
+    #[test]
+    fn record_set() {
+        let mut a = RecordSet::new(COLUMNS);
+        let mut b = RecordSet::new(COLUMNS);
+        let seph = a.get_or_create_agent_id("seph");
+        let mike = b.get_or_create_agent_id("mike");
+
+        let row = a.insert_row(seph, 0);
+        a.text_at(row, "title").unwrap().insert(seph, 0, "buy milk");
+        assert_eq!(a.get(row, "done"), Ok(None));
+        a.set(seph, row, "done", Primitive::Bool(false)).unwrap();
+        assert_eq!(a.set(seph, row, "title", Primitive::Nil), Err(DTError::InvalidColumn));
+        assert_eq!(a.text(row, "nope"), Err(DTError::InvalidColumn));
+
+        // Record sets which were created separately can be merged.
+        b.merge_ops(a.ops_since(&[])).unwrap();
+        let b_row = b.rows()[0];
+        assert_eq!(b.text(b_row, "title").unwrap(), "buy milk");
+
+        // Concurrent edits.
+        let since_a = a.local_frontier_ref().to_vec();
+        let since_b = b.local_frontier_ref().to_vec();
+        a.set(seph, row, "done", Primitive::Bool(true)).unwrap();
+        a.text_at(row, "title").unwrap().insert(seph, 8, " and eggs");
+        let row2 = a.insert_row(seph, 1);
+        b.text_at(b_row, "title").unwrap().insert(mike, 0, "Please ");
+        let b_row2 = b.insert_row(mike, 0);
+        b.delete_row(mike, b_row2).unwrap();
+        assert_eq!(b.set(mike, b_row2, "done", Primitive::Nil), Err(DTError::UnknownRow));
+        let b_row3 = b.insert_row(mike, 1);
+
+        a.merge_ops(b.ops_since(&since_b)).unwrap();
+        b.merge_ops(a.ops_since(&since_a)).unwrap();
+        a.oplog().dbg_check(true);
+        b.oplog().dbg_check(true);
+
+        assert_eq!(a.len(), 3);
+        assert_eq!(b.len(), 3);
+        assert_eq!(a.text(row, "title").unwrap(), "Please buy milk and eggs");
+        assert_eq!(a.get(row, "done"), Ok(Some(Primitive::Bool(true))));
+        assert_eq!(b.get(b_row, "done"), a.get(row, "done"));
+        assert!(a.rows().contains(&row2));
+        assert!(b.rows().contains(&b_row3));
+        assert_eq!(a.oplog().checkout(), b.oplog().checkout());
+    }
+}
@@ -1,4 +1,4 @@
-use std::ops::Range;
+use core::ops::Range;
 use rle::{HasLength, MergableSpan, SplitableSpan, SplitableSpanHelpers};
 use crate::dtrange::DTRange;
 
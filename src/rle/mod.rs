@@ -1,10 +1,12 @@
-use std::fmt::{Debug, Formatter};
+use core::fmt::{Debug, Formatter};
 
 use rle::{HasRleKey, HasLength, MergableSpan, Searchable, SplitableSpan, SplitableSpanCtx};
 pub use rle_vec::RleVec;
+pub use rle_map::RleMap;
 use crate::dtrange::{debug_time_raw, DTRange};
 
 pub mod rle_vec;
+pub mod rle_map;
 
 pub trait RleSpanHelpers: HasRleKey + HasLength {
     fn end(&self) -> usize {
@@ -51,7 +53,7 @@ impl<V: HasRleKey + SplitableSpanCtx> RleKeyedAndSplitable for V {}
 pub struct KVPair<V>(pub usize, pub V);
 
 impl<V: Debug> Debug for KVPair<V> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         let mut s = f.debug_tuple("KVPair");
         debug_time_raw(self.0, |v| { s.field(v); });
         s.field(&self.1);
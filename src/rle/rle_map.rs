@@ -0,0 +1,239 @@
+use alloc::collections::BTreeMap;
+use alloc::collections::btree_map::Values;
+use core::ops::Bound;
+use rle::{HasLength, MergableSpan, SplitableSpan, SplitableSpanCtx};
+use crate::dtrange::DTRange;
+use crate::rle::{HasRleKey, RleKeyedAndSplitable, RleSpanHelpers};
+
+/// A sparse, run-length encoded map from keys to values. This has the same API as
+/// [`RleVec`](crate::rle::RleVec) for keyed entries, but the entries are stored in a B-tree. So
+/// inserting and removing entries in the middle is O(log n) rather than O(n).
+///
+/// RleVec is faster (and smaller) when entries are almost always appended. Use this for maps which
+/// might have lots of discontiguous entries.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct RleMap<V: HasRleKey + HasLength + MergableSpan>(BTreeMap<usize, V>);
+
+impl<V: HasRleKey + HasLength + MergableSpan> Default for RleMap<V> {
+    fn default() -> Self {
+        Self(BTreeMap::new())
+    }
+}
+
+impl<V: HasRleKey + HasLength + MergableSpan + Clone> RleMap<V> {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn num_entries(&self) -> usize { self.0.len() }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    /// The key after the last entry in the map, or 0 if the map is empty.
+    pub fn end(&self) -> usize {
+        self.last_entry().map_or(0, |e| e.end())
+    }
+
+    pub fn last_entry(&self) -> Option<&V> {
+        self.0.values().next_back()
+    }
+
+    /// Iterate through the entries in key order.
+    pub fn iter(&self) -> Values<'_, usize, V> { self.0.values() }
+
+    /// Append an entry to the end of the map, merging it with the last entry if possible. Returns
+    /// true if the entry was merged.
+    pub fn push(&mut self, val: V) -> bool {
+        debug_assert!(val.rle_key() >= self.end());
+        if let Some(mut last) = self.0.last_entry() {
+            if last.get().can_append(&val) {
+                last.get_mut().append(val);
+                return true;
+            }
+        }
+        self.0.insert(val.rle_key(), val);
+        false
+    }
+
+    /// The last entry which starts at or before needle. This entry might end before needle.
+    pub fn entry_at_or_before(&self, needle: usize) -> Option<&V> {
+        self.0.range(..=needle).next_back().map(|(_, e)| e)
+    }
+
+    /// Find the entry containing needle.
+    pub fn find(&self, needle: usize) -> Option<&V> {
+        self.entry_at_or_before(needle).filter(|e| needle < e.end())
+    }
+
+    /// Find the entry containing needle, and the offset of needle within it.
+    pub fn find_with_offset(&self, needle: usize) -> Option<(&V, usize)> {
+        self.find(needle).map(|e| (e, needle - e.rle_key()))
+    }
+
+    pub fn contains_needle(&self, needle: usize) -> bool {
+        self.find(needle).is_some()
+    }
+
+    /// Find the item at range, cloning and trimming it down to size. The returned value might be
+    /// smaller than the passed range.
+    ///
+    /// # Panics
+    ///
+    /// Panics if range.start isn't in the map.
+    pub fn find_packed_and_split(&self, range: DTRange) -> V where V: SplitableSpan {
+        let (item, offset) = self.find_with_offset(range.start).unwrap();
+        let mut item = item.clone();
+        item.truncate_keeping_right(offset);
+        if item.len() > range.len() {
+            item.truncate(range.len());
+        }
+        item
+    }
+
+    /// Like find, except instead of returning None when the needle isn't in the map, we return the
+    /// empty range around it. See [`RleVec::find_sparse`](crate::rle::RleVec::find_sparse).
+    ///
+    /// Returns (Ok(elem), offset) if item is found, otherwise (Err(void range), offset into void)
+    pub fn find_sparse(&self, needle: usize) -> (Result<&V, DTRange>, usize) {
+        match self.entry_at_or_before(needle) {
+            Some(e) if needle < e.end() => (Ok(e), needle - e.rle_key()),
+            prev => {
+                let start = prev.map_or(0, |e| e.end());
+                let end = self.0.range(needle..).next().map_or(usize::MAX, |(key, _)| *key);
+                (Err((start..end).into()), needle - start)
+            }
+        }
+    }
+
+    /// Insert an entry. The entry must not overlap any existing entries. The entry is merged with
+    /// its neighbours if possible.
+    pub fn insert(&mut self, val: V) {
+        let key = val.rle_key();
+        debug_assert!(!self.contains_needle(key), "Item already exists");
+
+        if let Some((_, prev)) = self.0.range_mut(..key).next_back() {
+            if prev.can_append(&val) {
+                prev.append(val);
+                return;
+            }
+        }
+
+        if let Some(next) = self.0.range(key..).next().map(|(k, _)| *k) {
+            debug_assert!(key + val.len() <= next, "Items overlap");
+            if val.can_append(&self.0[&next]) {
+                let mut next_entry = self.0.remove(&next).unwrap();
+                next_entry.prepend(val);
+                self.0.insert(key, next_entry);
+                return;
+            }
+        }
+
+        self.0.insert(key, val);
+    }
+
+    /// Remove the items in range, splitting any entries which straddle the edges of the range.
+    /// Parts of the range which aren't in the map are ignored.
+    pub fn remove_ctx(&mut self, range: DTRange, ctx: &V::Ctx) where V: SplitableSpanCtx {
+        if range.is_empty() { return; }
+
+        // Split an entry which straddles the start of the range.
+        let first_key = self.0.range(..range.start).next_back()
+            .filter(|(_, e)| e.end() > range.start)
+            .map(|(key, _)| *key);
+        if let Some(key) = first_key {
+            let e = self.0.get_mut(&key).unwrap();
+            let mut remainder = e.truncate_from_ctx(range.start, ctx);
+            if remainder.end() > range.end {
+                // The range is in the middle of this entry.
+                remainder.truncate_keeping_right_from_ctx(range.end, ctx);
+                self.0.insert(remainder.rle_key(), remainder);
+                return;
+            }
+        }
+
+        // Remove everything which starts inside the range. The last entry might need to be put
+        // back, trimmed.
+        let mut last = None;
+        while let Some((&key, _)) = self.0.range((Bound::Included(range.start), Bound::Excluded(range.end))).next() {
+            last = self.0.remove(&key);
+        }
+        if let Some(mut e) = last {
+            if e.end() > range.end {
+                e.truncate_keeping_right_from_ctx(range.end, ctx);
+                self.0.insert(e.rle_key(), e);
+            }
+        }
+    }
+
+    /// Iterate through the entries which intersect range, trimmed to fit inside it.
+    pub fn iter_range(&self, range: DTRange) -> impl Iterator<Item = V> + '_ where V: SplitableSpan {
+        let start_key = self.entry_at_or_before(range.start)
+            .filter(|e| e.end() > range.start)
+            .map_or(range.start, |e| e.rle_key());
+
+        self.0.range(start_key..range.end.max(start_key)).map(move |(_, e)| {
+            let mut item = e.clone();
+            if item.end() > range.end {
+                item.truncate(range.end - item.rle_key());
+            }
+            if item.rle_key() < range.start {
+                item.truncate_keeping_right(range.start - item.rle_key());
+            }
+            item
+        })
+    }
+}
+
+impl<V: HasRleKey + HasLength + MergableSpan + Clone> FromIterator<V> for RleMap<V> {
+    fn from_iter<T: IntoIterator<Item=V>>(iter: T) -> Self {
+        let mut map = Self::new();
+        for val in iter {
+            map.insert(val);
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use crate::dtrange::DTRange;
+    use crate::rle::KVPair;
+    use crate::rle::rle_map::RleMap;
+
+    #[test]
+    fn sparse_map() {
+        let mut map: RleMap<KVPair<DTRange>> = RleMap::new();
+        map.push(KVPair(0, (100..110).into()));
+        map.insert(KVPair(30, (200..210).into()));
+        map.insert(KVPair(15, (300..305).into()));
+        // Merges with the entry before it, and the entry after it.
+        map.insert(KVPair(10, (110..112).into()));
+        map.insert(KVPair(25, (195..200).into()));
+        assert_eq!(map.iter().cloned().collect::<Vec<_>>(), vec![
+            KVPair(0, (100..112).into()),
+            KVPair(15, (300..305).into()),
+            KVPair(25, (195..210).into()),
+        ]);
+        assert_eq!(map.end(), 40);
+
+        assert_eq!(map.find_with_offset(11), Some((&KVPair(0, (100..112).into()), 11)));
+        assert_eq!(map.find(12), None);
+        assert_eq!(map.find_sparse(13), (Err((12..15).into()), 1));
+        assert_eq!(map.find_sparse(50), (Err((40..usize::MAX).into()), 10));
+        assert_eq!(map.find_packed_and_split((2..5).into()), KVPair(2, (102..105).into()));
+
+        assert_eq!(map.iter_range((5..27).into()).collect::<Vec<_>>(), vec![
+            KVPair(5, (105..112).into()),
+            KVPair(15, (300..305).into()),
+            KVPair(25, (195..197).into()),
+        ]);
+
+        map.remove_ctx((3..5).into(), &());
+        map.remove_ctx((16..30).into(), &());
+        assert_eq!(map.iter().cloned().collect::<Vec<_>>(), vec![
+            KVPair(0, (100..103).into()),
+            KVPair(5, (105..112).into()),
+            KVPair(15, (300..301).into()),
+            KVPair(30, (200..210).into()),
+        ]);
+    }
+}
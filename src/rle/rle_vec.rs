@@ -1,8 +1,10 @@
-use std::cmp::Ordering::*;
-use std::fmt::Debug;
-use std::iter::{FromIterator, Cloned};
-use std::ops::{Index, Range};
-use std::slice::SliceIndex;
+use alloc::vec::Vec;
+use core::cmp::Ordering::*;
+use core::fmt::Debug;
+use core::iter::{FromIterator, Cloned};
+use core::ops::{Index, Range};
+use core::slice::SliceIndex;
+#[cfg(feature = "std")]
 use humansize::{DECIMAL, format_size};
 
 use rle::{AppendRle, HasLength, MergableSpan, MergeableIterator, MergeIter, SplitableSpan, SplitableSpanCtx};
@@ -58,14 +60,15 @@ impl<V: HasLength + MergableSpan + Sized> RleVec<V> {
     pub fn is_empty(&self) -> bool { self.0.is_empty() }
 
     #[inline]
-    pub fn iter(&self) -> std::slice::Iter<V> { self.0.iter() }
+    pub fn iter(&self) -> core::slice::Iter<V> { self.0.iter() }
 
-    pub fn iter_from_idx(&self, idx: usize) -> std::slice::Iter<V> { self.0[idx..].iter() }
+    pub fn iter_from_idx(&self, idx: usize) -> core::slice::Iter<V> { self.0[idx..].iter() }
 
-    pub fn iter_merged(&self) -> MergeIter<Cloned<std::slice::Iter<V>>> { self.0.iter().cloned().merge_spans() }
+    pub fn iter_merged(&self) -> MergeIter<Cloned<core::slice::Iter<V>>> { self.0.iter().cloned().merge_spans() }
 
+    #[cfg(feature = "std")]
     pub fn print_stats(&self, name: &str, _detailed: bool) {
-        let size = std::mem::size_of::<V>();
+        let size = core::mem::size_of::<V>();
         println!("-------- {} RLE --------", name);
         println!("number of {} byte entries: {}", size, self.0.len());
         println!("allocated size: {}", format_size(
@@ -101,28 +104,33 @@ impl<V: HasLength + MergableSpan + HasRleKey + Clone + Sized> RleVec<V> {
         self.find_index(needle).unwrap_or_else(|i| i)
     }
 
-    // /// This is a variant of find_index for data sets where we normally know the index (via
-    // /// iteration).
-    // pub(crate) fn find_hinted(&self, needle: usize, hint: &mut usize) -> Result<usize, usize> {
-    //     if self.is_empty() { return Err(0); }
-    //
-    //     if *hint < self.0.len() {
-    //         let e = &self.0[*hint];
-    //         if needle >= e.rle_key() && needle < e.end() {
-    //             return Ok(*hint);
-    //         } else if needle < e.rle_key() {
-    //             if hint > 0 {
-    //                 todo!()
-    //             } else {
-    //                 *hint = 0;
-    //                 return Err()
-    //             }
-    //         } else {
-    //             debug_assert!(needle >= e.end());
-    //         }
-    //     }
-    //     todo!()
-    // }
+    /// This is a variant of find_index for data sets where we normally know the index (via
+    /// iteration). The hint is the index of the last item found. If the needle is in that item or
+    /// the item after it, we skip the binary search.
+    ///
+    /// On success, the hint is updated to the index of the found item.
+    pub fn find_with_hint(&self, needle: usize, hint: &mut usize) -> Result<usize, usize> {
+        for idx in [*hint, *hint + 1] {
+            if let Some(e) = self.0.get(idx) {
+                if needle < e.rle_key() { break; }
+                if needle < e.end() {
+                    *hint = idx;
+                    return Ok(idx);
+                }
+            }
+        }
+
+        let result = self.find_index(needle);
+        if let Ok(idx) = result { *hint = idx; }
+        result
+    }
+
+    /// Same as [`find_packed_with_offset`](Self::find_packed_with_offset), but using a search
+    /// hint. See [`find_with_hint`](Self::find_with_hint).
+    pub fn find_packed_with_offset_hinted(&self, needle: usize, hint: &mut usize) -> (&V, usize) {
+        let entry = &self.0[self.find_with_hint(needle, hint).unwrap()];
+        (entry, needle - entry.rle_key())
+    }
 
     /// Find an entry in the list with the specified key using binary search.
     ///
@@ -299,21 +307,15 @@ impl<V: HasLength + MergableSpan + HasRleKey + Clone + Sized> RleVec<V> {
 
         // Slow case - the requested range is in the middle of the list somewhere. We need to carve
         // it out.
-        let mut idx = match self.find_index(deleted_range.start) {
-            Ok(idx) => idx,
-            Err(idx) => {
-                if let Some(entry) = self.0.get(idx) {
-                    deleted_range.truncate_keeping_right_from(entry.rle_key());
-                } else { return; }
-                idx
-            }
-        };
+        let mut idx = self.find_next_index(deleted_range.start);
 
         loop {
             if idx >= self.0.len() { break; }
             let e = &mut self.0[idx];
 
-            debug_assert!(e.rle_key() <= deleted_range.start);
+            // The list might be sparse. Skip over any gap before this entry.
+            if e.rle_key() >= deleted_range.end { break; }
+            deleted_range.start = deleted_range.start.max(e.rle_key());
 
             // There's 4 cases here.
 
@@ -328,8 +330,8 @@ impl<V: HasLength + MergableSpan + HasRleKey + Clone + Sized> RleVec<V> {
                 },
 
                 (false, true) => {
-                    // Trim the start, trim the end.
-                    e.truncate_keeping_right_from_ctx(deleted_range.start, ctx);
+                    // Trim the start.
+                    e.truncate_keeping_right_from_ctx(deleted_range.end, ctx);
                     break;
                 },
 
@@ -352,6 +354,56 @@ impl<V: HasLength + MergableSpan + HasRleKey + Clone + Sized> RleVec<V> {
         }
     }
 
+    /// Remove the items in range, splitting any entries which straddle the edges of the range.
+    /// This method is O(n) with the number of entries after the range.
+    ///
+    /// Like [`remove_ctx`](Self::remove_ctx), parts of the range which aren't in the list are
+    /// ignored.
+    pub fn remove_range(&mut self, range: DTRange) where V: SplitableSpan {
+        self.remove_ctx(range, &());
+    }
+
+    /// Split the entry containing key (if any) so an entry starts at key. Returns the index of the
+    /// first entry at or after key.
+    fn split_entry_at_ctx(&mut self, key: usize, ctx: &V::Ctx) -> usize where V: SplitableSpanCtx {
+        match self.find_index(key) {
+            Ok(idx) if self.0[idx].rle_key() < key => {
+                let remainder = self.0[idx].truncate_from_ctx(key, ctx);
+                self.0.insert(idx + 1, remainder);
+                idx + 1
+            }
+            Ok(idx) | Err(idx) => idx,
+        }
+    }
+
+    /// Split the list in two at key. Items from key onwards are removed from this list and
+    /// returned. An entry which contains key is split in half.
+    pub fn split_off_ctx(&mut self, key: usize, ctx: &V::Ctx) -> Self where V: SplitableSpanCtx {
+        let idx = self.split_entry_at_ctx(key, ctx);
+        Self(self.0.split_off(idx))
+    }
+
+    /// Split the list in two at key. See [`split_off_ctx`](Self::split_off_ctx).
+    pub fn split_off(&mut self, key: usize) -> Self where V: SplitableSpan {
+        self.split_off_ctx(key, &())
+    }
+
+    /// Remove all the items from key onwards. An entry which contains key is truncated.
+    pub fn truncate_ctx(&mut self, key: usize, ctx: &V::Ctx) where V: SplitableSpanCtx {
+        match self.find_index(key) {
+            Ok(idx) if self.0[idx].rle_key() < key => {
+                self.0[idx].truncate_from_ctx(key, ctx);
+                self.0.truncate(idx + 1);
+            }
+            Ok(idx) | Err(idx) => self.0.truncate(idx),
+        }
+    }
+
+    /// Remove all the items from key onwards. See [`truncate_ctx`](Self::truncate_ctx).
+    pub fn truncate(&mut self, key: usize) where V: SplitableSpan {
+        self.truncate_ctx(key, &());
+    }
+
     /// Search forward from idx until we find needle. idx is modified. Returns either the item if
     /// successful, or the key of the subsequent item.
     #[allow(unused)]
@@ -517,7 +569,7 @@ fn id_clone<V: Clone>(v: &V) -> V {
 // which wraps that, and clones and splits.
 #[derive(Debug, Clone)]
 pub struct RleVecRangeIter<'a, V: HasRleKey + HasLength, I: SplitableSpanCtx, F: Fn(&V) -> I> {
-    inner_iter: std::slice::Iter<'a, V>,
+    inner_iter: core::slice::Iter<'a, V>,
     range: DTRange,
     ctx: &'a I::Ctx, // This could have a different lifetime specifier.
     map_fn: F,
@@ -580,6 +632,7 @@ impl<'a, V: HasRleKey + HasLength, I: HasLength + SplitableSpanCtx, F: Fn(&V) ->
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rle::KVPair;
 
     #[test]
     fn rle_iter_range() {
@@ -602,6 +655,62 @@ mod tests {
         assert!(entries_c.is_empty());
     }
 
+    #[test]
+    fn remove_and_split() {
+        let mut rle: RleVec<KVPair<DTRange>> = RleVec::new();
+        rle.push(KVPair(0, (100..110).into()));
+        rle.push(KVPair(12, (200..206).into()));
+        rle.push(KVPair(20, (300..310).into()));
+
+        let mut removed = rle.clone();
+        removed.remove_range((5..22).into());
+        assert_eq!(removed.0, vec![KVPair(0, (100..105).into()), KVPair(22, (302..310).into())]);
+
+        let mut removed = rle.clone();
+        removed.remove_range((2..4).into());
+        assert_eq!(removed.0.len(), 4);
+        assert_eq!(removed.0[0], KVPair(0, (100..102).into()));
+        assert_eq!(removed.0[1], KVPair(4, (104..110).into()));
+
+        let mut left = rle.clone();
+        let right = left.split_off(15);
+        assert_eq!(left.0, vec![KVPair(0, (100..110).into()), KVPair(12, (200..203).into())]);
+        assert_eq!(right.0, vec![KVPair(15, (203..206).into()), KVPair(20, (300..310).into())]);
+
+        // Splitting on an entry boundary or in a gap doesn't split anything.
+        let mut left = rle.clone();
+        assert_eq!(left.split_off(11).0.len(), 2);
+        assert_eq!(left.0.len(), 1);
+        let mut left = rle.clone();
+        assert_eq!(left.split_off(20).0.len(), 1);
+        assert_eq!(left.0.len(), 2);
+
+        let mut truncated = rle.clone();
+        truncated.truncate(25);
+        assert_eq!(truncated.end(), 25);
+        assert_eq!(truncated.0.last(), Some(&KVPair(20, (300..305).into())));
+        truncated.truncate(0);
+        assert!(truncated.is_empty());
+    }
+
+    #[test]
+    fn find_with_hint() {
+        let mut rle: RleVec<KVPair<DTRange>> = RleVec::new();
+        rle.push(KVPair(0, (100..110).into()));
+        rle.push(KVPair(12, (200..206).into()));
+        rle.push(KVPair(20, (300..310).into()));
+
+        let mut hint = 0;
+        for (needle, expected) in [(3, Ok(0)), (13, Ok(1)), (25, Ok(2)), (1, Ok(0)), (11, Err(1)), (40, Err(3))] {
+            assert_eq!(rle.find_with_hint(needle, &mut hint), expected);
+            assert_eq!(rle.find_index(needle), expected);
+        }
+        // Failed lookups don't move the hint.
+        assert_eq!(hint, 0);
+        assert_eq!(rle.find_packed_with_offset_hinted(21, &mut hint), (&KVPair(20, (300..310).into()), 1));
+        assert_eq!(hint, 2);
+    }
+
     #[test]
     fn iter_range_sparse() {
         let mut rle: RleVec<DTRange> = RleVec::new();
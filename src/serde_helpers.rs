@@ -1,5 +1,5 @@
-use std::fmt;
-use std::fmt::Formatter;
+use core::fmt;
+use core::fmt::Formatter;
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use serde::de::{MapAccess, SeqAccess, Visitor};
 // use serde::de::{EnumAccess, Error, MapAccess, SeqAccess};
@@ -141,7 +141,7 @@ impl From<DTRange> for DTRangeTuple {
 //         impl Visitor for V {
 //             type Value = TimeSpanRev;
 //
-//             fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+//             fn expecting(&self, formatter: &mut Formatter) -> core::fmt::Result {
 //                 formatter.write_str("struct TimeSpanRev")
 //             }
 //
@@ -1,4 +1,5 @@
-use std::collections::BTreeMap;
+use alloc::{boxed::Box, string::{String, ToString}, vec::Vec};
+use alloc::collections::BTreeMap;
 use crate::{Branch, CRDTKind, LV, Primitive, RegisterValue, ROOT_CRDT_ID};
 use smartstring::alias::String as SmartString;
 
@@ -6,6 +7,7 @@ use smartstring::alias::String as SmartString;
 pub enum SimpleVal {
     Text(String),
     Map(BTreeMap<SmartString, Box<SimpleVal>>),
+    List(Vec<SimpleVal>),
     Primitive(Primitive),
 }
 
@@ -16,14 +18,7 @@ impl Branch {
                 let mut map = BTreeMap::new();
                 for (key, state) in self.maps.get(&key).unwrap() {
                     // TODO: Rewrite this as an iterator map then collect().
-                    map.insert(key.clone(), Box::new(match &state.value {
-                        RegisterValue::Primitive(primitive) => {
-                            SimpleVal::Primitive(primitive.clone())
-                        }
-                        RegisterValue::OwnedCRDT(inner_kind, inner_key) => {
-                            self.simple_val_at(*inner_key, *inner_kind)
-                        }
-                    }));
+                    map.insert(key.clone(), Box::new(self.simple_val_of(&state.value)));
                 }
                 SimpleVal::Map(map)
             }
@@ -38,6 +33,22 @@ impl Branch {
             CRDTKind::Text => {
                 SimpleVal::Text(self.texts.get(&key).unwrap().to_string())
             }
+            CRDTKind::List => {
+                SimpleVal::List(self.lists.get(&key).unwrap().iter()
+                    .map(|value| self.simple_val_of(value))
+                    .collect())
+            }
+        }
+    }
+
+    fn simple_val_of(&self, value: &RegisterValue) -> SimpleVal {
+        match value {
+            RegisterValue::Primitive(primitive) => {
+                SimpleVal::Primitive(primitive.clone())
+            }
+            RegisterValue::OwnedCRDT(inner_kind, inner_key) => {
+                self.simple_val_at(*inner_key, *inner_kind)
+            }
         }
     }
 
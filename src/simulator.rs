@@ -0,0 +1,376 @@
+//! A deterministic simulation of peers editing a document and syncing over an unreliable network.
+//!
+//! The [`Simulator`] runs a set of virtual peers in a full mesh, with a simulated network between
+//! each pair of peers. Each peer keeps its own oplog and syncs with the other peers using the
+//! [sync protocol](crate::list::protocol). Time advances in discrete ticks. In each tick, peers
+//! make random edits and messages which have arrived are delivered.
+//!
+//! The network is modelled as a stream transport (like TCP or websockets). Messages arrive in
+//! order after a random delay. When a message is lost, the connection breaks and both sides
+//! reconnect after a delay, which restarts the protocol with a new hello.
+//!
+//! Everything is driven by a single seeded RNG, so a failing run can be reproduced from its
+//! seed.
+//!
+//! ```
+//! use diamond_types::simulator::{NetworkModel, SimConfig, Simulator};
+//!
+//! let mut sim = Simulator::new(123, SimConfig {
+//!     num_peers: 3,
+//!     network: NetworkModel { loss: 0.05, min_latency: 1, max_latency: 5, reconnect_delay: 3 },
+//!     ..Default::default()
+//! });
+//! sim.run(50, 0.3);
+//! sim.settle();
+//! sim.assert_converged();
+//! ```
+
+use std::collections::VecDeque;
+use rand::prelude::*;
+use crate::AgentId;
+use crate::causalgraph::agent_assignment::remote_ids::RemoteFrontier;
+use crate::list::{ListBranch, ListOpLog};
+use crate::list::protocol::{Msg, Peer};
+use crate::list_fuzzer_tools::random_str;
+
+/// How messages travel between a pair of peers. Times are in ticks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NetworkModel {
+    /// The probability that each message is lost. Losing a message breaks the connection.
+    pub loss: f64,
+    pub min_latency: u64,
+    pub max_latency: u64,
+    /// How long a broken connection takes to be reopened.
+    pub reconnect_delay: u64,
+}
+
+impl Default for NetworkModel {
+    fn default() -> Self {
+        Self {
+            loss: 0.0,
+            min_latency: 1,
+            max_latency: 1,
+            reconnect_delay: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimConfig {
+    pub num_peers: usize,
+    pub network: NetworkModel,
+    /// Protocol settings used by every connection. Small values exercise batching and
+    /// backpressure.
+    pub max_batch_ops: usize,
+    pub max_in_flight: usize,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            num_peers: 3,
+            network: NetworkModel::default(),
+            max_batch_ops: 10000,
+            max_in_flight: 4,
+        }
+    }
+}
+
+/// Counters collected while the simulation runs.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct SimStats {
+    pub edits: usize,
+    pub messages_sent: usize,
+    pub bytes_sent: usize,
+    pub messages_lost: usize,
+    /// The number of times a connection was opened (including reconnects).
+    pub connections: usize,
+    /// The largest number of operation batches queued on a single link at once. The protocol's
+    /// backpressure should keep this at or below `max_in_flight`.
+    pub max_queued_ops: usize,
+}
+
+struct SimPeer {
+    oplog: ListOpLog,
+    branch: ListBranch,
+    agent: AgentId,
+    /// The connection to each other peer, indexed by peer. The entry for this peer is unused.
+    conns: Vec<Peer>,
+}
+
+struct InFlight {
+    /// The tick the message arrives.
+    arrives: u64,
+    is_ops: bool,
+    data: Vec<u8>,
+}
+
+/// The messages travelling in one direction between a pair of peers.
+#[derive(Default)]
+struct Link {
+    /// Arrival times never decrease, so messages arrive in order.
+    queue: VecDeque<InFlight>,
+    /// The number of queued messages which contain operations.
+    queued_ops: usize,
+    /// While the connection is broken, the tick it will be reopened.
+    down_until: Option<u64>,
+}
+
+/// A simulated network of peers. See the [module documentation](self) for details.
+pub struct Simulator {
+    config: SimConfig,
+    rng: SmallRng,
+    now: u64,
+    peers: Vec<SimPeer>,
+    /// The link from peer i to peer j is at `i * num_peers + j`.
+    links: Vec<Link>,
+    stats: SimStats,
+}
+
+impl Simulator {
+    /// Create a simulation with `config.num_peers` peers, all connected to each other. The same
+    /// seed and config always produce the same simulation.
+    pub fn new(seed: u64, config: SimConfig) -> Self {
+        let n = config.num_peers;
+        let peers = (0..n).map(|i| {
+            let mut oplog = ListOpLog::new();
+            let agent = oplog.get_or_create_agent_id(&format!("peer{i}"));
+            SimPeer { oplog, branch: ListBranch::new(), agent, conns: vec![Peer::new(); n] }
+        }).collect();
+
+        let mut sim = Self {
+            config,
+            rng: SmallRng::seed_from_u64(seed),
+            now: 0,
+            peers,
+            links: (0..n * n).map(|_| Link::default()).collect(),
+            stats: SimStats::default(),
+        };
+        for i in 0..n {
+            for j in i + 1..n {
+                sim.connect(i, j);
+            }
+        }
+        sim
+    }
+
+    pub fn stats(&self) -> &SimStats {
+        &self.stats
+    }
+
+    /// The current time, in ticks.
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn oplog(&self, peer: usize) -> &ListOpLog {
+        &self.peers[peer].oplog
+    }
+
+    fn link_mut(&mut self, from: usize, to: usize) -> &mut Link {
+        &mut self.links[from * self.config.num_peers + to]
+    }
+
+    fn connect(&mut self, a: usize, b: usize) {
+        self.stats.connections += 1;
+        for (from, to) in [(a, b), (b, a)] {
+            let link = self.link_mut(from, to);
+            *link = Link::default();
+
+            let peer = &mut self.peers[from];
+            peer.conns[to].set_max_batch_ops(self.config.max_batch_ops);
+            peer.conns[to].set_max_in_flight(self.config.max_in_flight);
+            let hello = peer.conns[to].hello(&peer.oplog);
+            self.send(from, to, vec![hello]);
+        }
+    }
+
+    fn disconnect(&mut self, a: usize, b: usize) {
+        let down_until = self.now + self.config.network.reconnect_delay;
+        for (from, to) in [(a, b), (b, a)] {
+            *self.link_mut(from, to) = Link { down_until: Some(down_until), ..Link::default() };
+        }
+    }
+
+    fn send(&mut self, from: usize, to: usize, msgs: Vec<Msg>) {
+        let network = self.config.network;
+        for msg in msgs {
+            let is_ops = matches!(msg, Msg::Ops(_));
+            let data = msg.to_bytes();
+            self.stats.messages_sent += 1;
+            self.stats.bytes_sent += data.len();
+
+            let latency = self.rng.gen_range(network.min_latency..=network.max_latency);
+            let now = self.now;
+            let link = self.link_mut(from, to);
+            let arrives = link.queue.back().map_or(0, |m| m.arrives).max(now + latency);
+            link.queue.push_back(InFlight { arrives, is_ops, data });
+            if is_ops { link.queued_ops += 1; }
+            let queued_ops = link.queued_ops;
+            self.stats.max_queued_ops = self.stats.max_queued_ops.max(queued_ops);
+        }
+    }
+
+    /// Send any operations the peer's connections haven't sent yet.
+    fn flush(&mut self, peer: usize) {
+        for to in 0..self.config.num_peers {
+            if to == peer || self.link_mut(peer, to).down_until.is_some() { continue; }
+            let p = &mut self.peers[peer];
+            let msgs = p.conns[to].flush(&p.oplog);
+            self.send(peer, to, msgs);
+        }
+    }
+
+    fn random_edit(&mut self, peer: usize) {
+        let p = &mut self.peers[peer];
+        let rng = &mut self.rng;
+        p.branch.merge(&p.oplog, p.oplog.cg.version.as_ref());
+
+        let len = p.branch.len();
+        let insert_weight = if len < 100 { 0.6 } else { 0.4 };
+        if len == 0 || rng.gen_bool(insert_weight) {
+            let pos = rng.gen_range(0..=len);
+            let content = random_str(rng.gen_range(1..5), rng, true);
+            p.branch.insert(&mut p.oplog, p.agent, pos, &content);
+        } else {
+            let pos = rng.gen_range(0..len);
+            let del_len = rng.gen_range(1..=usize::min(10, len - pos));
+            p.branch.delete(&mut p.oplog, p.agent, pos..pos + del_len);
+        }
+        self.stats.edits += 1;
+    }
+
+    /// Deliver the messages on a link which have arrived.
+    fn deliver(&mut self, from: usize, to: usize) {
+        let now = self.now;
+        loop {
+            let link = self.link_mut(from, to);
+            if link.queue.front().is_none_or(|m| m.arrives > now) { break; }
+            let msg = link.queue.pop_front().unwrap();
+            if msg.is_ops { link.queued_ops -= 1; }
+
+            if self.rng.gen_bool(self.config.network.loss) {
+                self.stats.messages_lost += 1;
+                self.disconnect(from, to);
+                break;
+            }
+
+            let p = &mut self.peers[to];
+            let replies = p.conns[from].on_message(&mut p.oplog, &msg.data)
+                .expect("Peer sent an invalid message");
+            self.send(to, from, replies);
+            // Any new operations need to be forwarded to the other peers.
+            self.flush(to);
+        }
+    }
+
+    /// Advance the simulation by one tick. Each peer makes a random edit with probability
+    /// `edit_probability`.
+    pub fn tick(&mut self, edit_probability: f64) {
+        self.now += 1;
+        let n = self.config.num_peers;
+
+        for a in 0..n {
+            for b in a + 1..n {
+                if self.link_mut(a, b).down_until.is_some_and(|t| t <= self.now) {
+                    self.connect(a, b);
+                }
+            }
+        }
+
+        for peer in 0..n {
+            if self.rng.gen_bool(edit_probability) {
+                self.random_edit(peer);
+                self.flush(peer);
+            }
+        }
+
+        for from in 0..n {
+            for to in 0..n {
+                if from != to { self.deliver(from, to); }
+            }
+        }
+    }
+
+    /// Run the simulation for a number of ticks.
+    pub fn run(&mut self, ticks: usize, edit_probability: f64) {
+        for _ in 0..ticks {
+            self.tick(edit_probability);
+        }
+    }
+
+    /// Returns true if every connection is open, no messages are in flight, and every peer has
+    /// sent all its operations to every other peer.
+    pub fn is_quiet(&self) -> bool {
+        self.links.iter().all(|link| link.down_until.is_none() && link.queue.is_empty())
+            && self.peers.iter().enumerate().all(|(i, p)| {
+                p.conns.iter().enumerate().all(|(j, conn)| i == j || conn.is_synced(&p.oplog))
+            })
+    }
+
+    /// Stop editing and run the simulation on a reliable network until all the peers have synced.
+    /// Returns the number of ticks this took.
+    ///
+    /// Panics if the peers haven't synced after 100000 ticks.
+    pub fn settle(&mut self) -> u64 {
+        let start = self.now;
+        let loss = core::mem::replace(&mut self.config.network.loss, 0.0);
+        while !self.is_quiet() {
+            assert!(self.now - start < 100000, "Simulation did not settle");
+            self.tick(0.0);
+        }
+        self.config.network.loss = loss;
+        self.now - start
+    }
+
+    /// Check that every peer has the same version and document content. This should be true after
+    /// [`settle`](Simulator::settle).
+    ///
+    /// The oplogs themselves can differ, since patches sent by the protocol don't include deleted
+    /// content.
+    pub fn assert_converged(&self) {
+        fn version(oplog: &ListOpLog) -> RemoteFrontier<'_> {
+            let mut v = oplog.remote_frontier();
+            v.sort_unstable_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+            v
+        }
+        let expected_version = version(&self.peers[0].oplog);
+        let expected = self.peers[0].oplog.checkout_tip().content().to_string();
+        for p in &self.peers[1..] {
+            assert_eq!(version(&p.oplog), expected_version);
+            assert_eq!(p.oplog.checkout_tip().content().to_string(), expected);
+        }
+        for p in &self.peers {
+            p.oplog.dbg_check(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::simulator::{NetworkModel, SimConfig, Simulator};
+
+    #[test]
+    fn lossy_network_converges() {
+        let config = SimConfig {
+            num_peers: 4,
+            network: NetworkModel { loss: 0.03, min_latency: 1, max_latency: 10, reconnect_delay: 5 },
+            max_batch_ops: 5,
+            max_in_flight: 2,
+        };
+
+        let mut lost = 0;
+        for seed in 0..5 {
+            let mut sim = Simulator::new(seed, config);
+            sim.run(100, 0.3);
+            sim.settle();
+            sim.assert_converged();
+
+            let stats = sim.stats();
+            assert!(stats.edits > 0);
+            assert!(stats.max_queued_ops <= config.max_in_flight);
+            lost += stats.messages_lost;
+        }
+        assert!(lost > 0);
+    }
+}
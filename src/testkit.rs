@@ -0,0 +1,384 @@
+//! Tools for testing other CRDT implementations (or network layers built on diamond-types)
+//! against diamond-types itself.
+//!
+//! [`Trace::generate`] deterministically generates a random editing trace from a seed. The trace
+//! is a list of events: local edits made by a set of peers, and syncs between pairs of peers. A
+//! trace can then be replayed against any implementation of [`TracePeer`], and
+//! [`Trace::replay`] checks that after every event the content of each peer matches the content
+//! diamond-types produces for the same events.
+//!
+//! Concurrent inserts at the same position are ordered by the names of the agents which made them.
+//! So the trace is generated using the agent names of the peers being tested, and peer `i` must
+//! make its edits as `trace.agents[i]`.
+//!
+//! ```
+//! use diamond_types::list::operation::{ListOpKind, TextOperation};
+//! use diamond_types::testkit::{Trace, TracePeer};
+//!
+//! // A "CRDT" which only works when there's a single peer.
+//! #[derive(Default)]
+//! struct Naive(Vec<char>);
+//!
+//! impl TracePeer for Naive {
+//!     fn apply_local(&mut self, op: &TextOperation) {
+//!         match op.kind {
+//!             ListOpKind::Ins => drop(self.0.splice(op.start()..op.start(), op.content_as_str().unwrap().chars())),
+//!             ListOpKind::Del => drop(self.0.drain(op.start()..op.end())),
+//!         }
+//!     }
+//!     fn receive_from(&mut self, from: &Self) { self.0 = from.0.clone(); }
+//!     fn content(&self) -> String { self.0.iter().collect() }
+//! }
+//!
+//! let trace = Trace::generate(123, &["seph"], 100, true);
+//! assert!(trace.replay(&mut [Naive::default()]).is_ok());
+//!
+//! let trace = Trace::generate(123, &["seph", "mike", "kaarina"], 100, true);
+//! assert!(trace.replay(&mut [Naive::default(), Naive::default(), Naive::default()]).is_err());
+//! ```
+
+use std::ops::Range;
+use rand::prelude::*;
+use crate::{AgentId, Frontier, LV};
+use crate::list::{ListBranch, ListOpLog};
+use crate::list::operation::TextOperation;
+use crate::list_fuzzer_tools::random_str;
+
+pub use crate::causalgraph::graph::random_graphs::with_random_cgs;
+
+/// A single event in a [`Trace`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum TraceEvent {
+    /// A local edit made by a peer. The operation's position is relative to the peer's current
+    /// content. Deletes include the deleted content.
+    Edit { peer: usize, op: TextOperation },
+
+    /// Peer `to` receives every change known by peer `from`, and merges them into its content.
+    Sync { from: usize, to: usize },
+}
+
+/// A deterministic, randomly generated multi-peer editing trace.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Trace {
+    /// The name of each peer's agent.
+    pub agents: Vec<String>,
+    pub events: Vec<TraceEvent>,
+}
+
+/// An implementation of a collaborative text document which can be tested using a [`Trace`].
+pub trait TracePeer {
+    /// Apply a local edit to this peer.
+    fn apply_local(&mut self, op: &TextOperation);
+
+    /// Merge all the changes known by another peer into this peer.
+    fn receive_from(&mut self, from: &Self);
+
+    /// The current content of this peer's document.
+    fn content(&self) -> String;
+}
+
+/// Returned by [`Trace::replay`] when a peer's content doesn't match the expected content.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Divergence {
+    /// The index of the event after which the peer diverged.
+    pub event: usize,
+    pub peer: usize,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// The trace replayed in diamond-types. All peers share an oplog, and each peer has its own
+/// branch.
+struct Reference {
+    oplog: ListOpLog,
+    branches: Vec<ListBranch>,
+    agents: Vec<AgentId>,
+}
+
+impl Reference {
+    fn new<S: AsRef<str>>(agent_names: &[S]) -> Self {
+        let mut oplog = ListOpLog::new();
+        let agents = agent_names.iter()
+            .map(|name| oplog.get_or_create_agent_id(name.as_ref()))
+            .collect();
+
+        Self {
+            oplog,
+            branches: vec![ListBranch::new(); agent_names.len()],
+            agents,
+        }
+    }
+
+    fn apply(&mut self, event: &TraceEvent) -> usize {
+        match event {
+            TraceEvent::Edit { peer, op } => {
+                self.branches[*peer].apply_local_operations(&mut self.oplog, self.agents[*peer], std::slice::from_ref(op));
+                *peer
+            }
+            TraceEvent::Sync { from, to } => {
+                let v = self.branches[*from].local_frontier();
+                self.branches[*to].merge(&self.oplog, v.as_ref());
+                *to
+            }
+        }
+    }
+
+    fn make_random_edit(&self, peer: usize, rng: &mut SmallRng, use_unicode: bool) -> TextOperation {
+        let branch = &self.branches[peer];
+        let doc_len = branch.len();
+        let insert_weight = if doc_len < 100 { 0.55 } else { 0.45 };
+
+        if doc_len == 0 || rng.gen_bool(insert_weight) {
+            let pos = rng.gen_range(0..=doc_len);
+            let len = rng.gen_range(1..3);
+            TextOperation::new_insert(pos, &random_str(len, rng, use_unicode))
+        } else {
+            let pos = rng.gen_range(0..doc_len);
+            let len = rng.gen_range(1..=usize::min(10, doc_len - pos));
+            branch.make_delete_op(pos..pos + len)
+        }
+    }
+}
+
+impl Trace {
+    /// Generate a random trace with a peer for each of the named agents. The same arguments always
+    /// generate the same trace.
+    ///
+    /// Each step, a random peer makes some local edits and then two random peers may sync. At the
+    /// end of the trace all the peers sync with each other, so all peers end up with the same
+    /// content.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no agents, or if an agent name is repeated.
+    pub fn generate(seed: u64, agents: &[&str], steps: usize, use_unicode: bool) -> Self {
+        let num_peers = agents.len();
+        assert!(num_peers >= 1);
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut reference = Reference::new(agents);
+        assert_eq!(reference.oplog.cg.agent_assignment.client_data.len(), num_peers, "Agent names must be unique");
+        let mut events = vec![];
+
+        let mut push = |reference: &mut Reference, event: TraceEvent| {
+            reference.apply(&event);
+            events.push(event);
+        };
+
+        for _i in 0..steps {
+            let peer = rng.gen_range(0..num_peers);
+            for _j in 0..rng.gen_range(1..=3) {
+                let op = reference.make_random_edit(peer, &mut rng, use_unicode);
+                push(&mut reference, TraceEvent::Edit { peer, op });
+            }
+
+            if num_peers > 1 && rng.gen_bool(0.5) {
+                let from = rng.gen_range(0..num_peers);
+                let to = (from + rng.gen_range(1..num_peers)) % num_peers;
+                push(&mut reference, TraceEvent::Sync { from, to });
+            }
+        }
+
+        // Sync everything into the last peer, then back out to everyone else.
+        for i in 1..num_peers {
+            push(&mut reference, TraceEvent::Sync { from: i - 1, to: i });
+        }
+        for i in 0..num_peers - 1 {
+            push(&mut reference, TraceEvent::Sync { from: num_peers - 1, to: i });
+        }
+
+        Self {
+            agents: agents.iter().map(|name| name.to_string()).collect(),
+            events,
+        }
+    }
+
+    pub fn num_peers(&self) -> usize {
+        self.agents.len()
+    }
+
+    /// The expected content of every peer at the end of the trace.
+    pub fn expected_content(&self) -> Vec<String> {
+        let mut reference = Reference::new(&self.agents);
+        for e in &self.events {
+            reference.apply(e);
+        }
+        reference.branches.iter().map(|b| b.content().to_string()).collect()
+    }
+
+    /// Replay the trace against a set of peers, which should all start out empty. After each
+    /// event, the content of the modified peer is compared with the content diamond-types expects.
+    ///
+    /// Returns the first divergence found, if any.
+    pub fn replay<P: TracePeer>(&self, peers: &mut [P]) -> Result<(), Divergence> {
+        assert_eq!(peers.len(), self.num_peers(), "Wrong number of peers");
+        let mut reference = Reference::new(&self.agents);
+
+        for (i, e) in self.events.iter().enumerate() {
+            match e {
+                TraceEvent::Edit { peer, op } => peers[*peer].apply_local(op),
+                TraceEvent::Sync { from, to } => {
+                    assert_ne!(from, to);
+                    // Borrow both peers at once.
+                    let (from, to) = if from < to {
+                        let (a, b) = peers.split_at_mut(*to);
+                        (&a[*from], &mut b[0])
+                    } else {
+                        let (a, b) = peers.split_at_mut(*from);
+                        (&b[0], &mut a[*to])
+                    };
+                    to.receive_from(from);
+                }
+            }
+
+            let peer = reference.apply(e);
+            let expected = reference.branches[peer].content().to_string();
+            let actual = peers[peer].content();
+            if expected != actual {
+                return Err(Divergence { event: i, peer, expected, actual });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An oplog with a convenient API for scripting editing histories, for integration tests and
+/// simulators. Agents are named by strings (and created as needed), and changes can name their
+/// parents explicitly.
+///
+/// ```
+/// use diamond_types::testkit::ScriptedOpLog;
+///
+/// let mut oplog = ScriptedOpLog::new();
+/// let base = oplog.add_insert("seph", 0, "hi");
+/// oplog.add_insert_at("seph", &[base], 2, " there");
+/// oplog.add_delete_at("mike", &[base], 0..1);
+/// assert_eq!(oplog.content(), "i there");
+/// assert_eq!(oplog.content_at(&[base]), "hi");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedOpLog {
+    pub oplog: ListOpLog,
+}
+
+impl ScriptedOpLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert content at the specified position in the document at version `parents`.
+    pub fn add_insert_at(&mut self, agent: &str, parents: &[LV], pos: usize, content: &str) -> LV {
+        let agent = self.oplog.get_or_create_agent_id(agent);
+        self.oplog.add_insert_at(agent, parents, pos, content)
+    }
+
+    /// Delete a range in the document at version `parents`. The deleted content isn't stored.
+    pub fn add_delete_at(&mut self, agent: &str, parents: &[LV], del_range: Range<usize>) -> LV {
+        let agent = self.oplog.get_or_create_agent_id(agent);
+        self.oplog.add_delete_at(agent, parents, del_range)
+    }
+
+    /// Insert content at the current version of the oplog.
+    pub fn add_insert(&mut self, agent: &str, pos: usize, content: &str) -> LV {
+        let version = self.version();
+        self.add_insert_at(agent, version.as_ref(), pos, content)
+    }
+
+    /// Delete a range at the current version of the oplog.
+    pub fn add_delete(&mut self, agent: &str, del_range: Range<usize>) -> LV {
+        let version = self.version();
+        self.add_delete_at(agent, version.as_ref(), del_range)
+    }
+
+    /// The current version of the oplog, which contains every change.
+    pub fn version(&self) -> Frontier {
+        self.oplog.local_frontier()
+    }
+
+    /// The content of the document with every change merged.
+    pub fn content(&self) -> String {
+        self.oplog.checkout_tip().content().to_string()
+    }
+
+    /// The content of the document at the specified version.
+    pub fn content_at(&self, version: &[LV]) -> String {
+        self.oplog.checkout(version).content().to_string()
+    }
+
+    pub fn into_oplog(self) -> ListOpLog {
+        self.oplog
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::list::encoding::ENCODE_FULL;
+    use crate::list::ListCRDT;
+    use crate::list::operation::TextOperation;
+    use crate::testkit::{Trace, TraceEvent, TracePeer};
+
+    /// A peer which syncs by sending the full encoded document over the "network".
+    struct EncodingPeer {
+        doc: ListCRDT,
+        /// Simulate a broken network layer by ignoring all incoming changes.
+        drop_syncs: bool,
+    }
+
+    impl TracePeer for EncodingPeer {
+        fn apply_local(&mut self, op: &TextOperation) {
+            // Agent 0 is created when the peer is made.
+            self.doc.apply_local_operations(0, std::slice::from_ref(op));
+        }
+
+        fn receive_from(&mut self, from: &Self) {
+            if !self.drop_syncs {
+                self.doc.merge_data_and_ff(&from.doc.oplog.encode(ENCODE_FULL)).unwrap();
+            }
+        }
+
+        fn content(&self) -> String {
+            self.doc.branch.content().to_string()
+        }
+    }
+
+    const AGENTS: [&str; 3] = ["seph", "mike", "kaarina"];
+
+    fn make_peers(trace: &Trace, drop_syncs: bool) -> Vec<EncodingPeer> {
+        trace.agents.iter().map(|name| {
+            let mut doc = ListCRDT::new();
+            doc.get_or_create_agent_id(name);
+            EncodingPeer { doc, drop_syncs }
+        }).collect()
+    }
+
+    #[test]
+    fn traces_are_deterministic() {
+        let a = Trace::generate(10, &AGENTS, 50, true);
+        assert_eq!(a, Trace::generate(10, &AGENTS, 50, true));
+        assert_ne!(a, Trace::generate(11, &AGENTS, 50, true));
+
+        let content = a.expected_content();
+        assert_eq!(content.len(), 3);
+        assert!(content.windows(2).all(|w| w[0] == w[1]));
+    }
+
+    #[test]
+    fn replay_converges() {
+        for seed in 0..10 {
+            let trace = Trace::generate(seed, &AGENTS, 100, true);
+            trace.replay(&mut make_peers(&trace, false)).unwrap();
+        }
+    }
+
+    #[test]
+    fn replay_finds_divergence() {
+        let trace = Trace::generate(3, &AGENTS[..2], 20, false);
+        let err = trace.replay(&mut make_peers(&trace, true)).unwrap_err();
+
+        let TraceEvent::Sync { to, .. } = trace.events[err.event] else {
+            panic!("Expected divergence on sync");
+        };
+        assert_eq!(err.peer, to);
+        assert_ne!(err.expected, err.actual);
+    }
+}